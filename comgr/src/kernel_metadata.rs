@@ -0,0 +1,345 @@
+use crate::Error;
+use goblin::elf::Elf;
+
+// Code objects (v3 and newer) describe their kernels in a NT_AMDGPU_METADATA note holding a
+// MessagePack map, see https://llvm.org/docs/AMDGPUUsage.html#code-object-v3-metadata
+const NT_AMDGPU_METADATA: u32 = 32;
+// Every kernel also has a 64 byte descriptor, the `<name>.kd` symbol, which is what the hardware
+// reads at dispatch, see https://llvm.org/docs/AMDGPUUsage.html#kernel-descriptor
+const KERNEL_DESCRIPTOR_SIZE: usize = 64;
+
+/// Resource usage of a single kernel, as reported by the compiler in the code object.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KernelResources {
+    /// Name of the kernel, without the `.kd` suffix of its descriptor
+    pub name: String,
+    pub sgpr_count: u32,
+    pub vgpr_count: u32,
+    /// Bytes of statically allocated LDS (PTX `.shared`)
+    pub group_segment_fixed_size: u32,
+    /// Bytes of scratch memory per work-item
+    pub private_segment_fixed_size: u32,
+    pub kernarg_segment_size: u32,
+    pub max_flat_workgroup_size: u32,
+    pub wavefront_size: u32,
+}
+
+pub fn get_kernel_resources(elf: &[u8]) -> Result<Vec<KernelResources>, Error> {
+    let parsed = Elf::parse(elf).map_err(|_| Error::INVALID_ARGUMENT)?;
+    let notes = parsed
+        .iter_note_sections(elf, None)
+        .ok_or(Error::INVALID_ARGUMENT)?;
+    for note in notes {
+        let note = note.map_err(|_| Error::INVALID_ARGUMENT)?;
+        if note.n_type != NT_AMDGPU_METADATA || note.name != "AMDGPU" {
+            continue;
+        }
+        let mut desc = note.desc;
+        let metadata = msgpack::read(&mut desc).ok_or(Error::INVALID_ARGUMENT)?;
+        return metadata_kernel_resources(&parsed, elf, &metadata);
+    }
+    Err(Error::INVALID_ARGUMENT)
+}
+
+fn metadata_kernel_resources(
+    parsed: &Elf,
+    elf: &[u8],
+    metadata: &msgpack::Value,
+) -> Result<Vec<KernelResources>, Error> {
+    let kernels = match metadata.get("amdhsa.kernels") {
+        Some(kernels) => kernels.as_array().ok_or(Error::INVALID_ARGUMENT)?,
+        // Code object of a library module, one with only functions and globals
+        None => return Ok(Vec::new()),
+    };
+    kernels
+        .iter()
+        .map(|kernel| {
+            let mut resources = kernel_resources(kernel)?;
+            // Segment sizes from the descriptor take precedence, the note is informational
+            if let Some(descriptor) = kernel_descriptor(parsed, elf, &resources.name) {
+                resources.group_segment_fixed_size = descriptor.group_segment_fixed_size;
+                resources.private_segment_fixed_size = descriptor.private_segment_fixed_size;
+                resources.kernarg_segment_size = descriptor.kernarg_size;
+            }
+            Ok(resources)
+        })
+        .collect()
+}
+
+struct KernelDescriptor {
+    group_segment_fixed_size: u32,
+    private_segment_fixed_size: u32,
+    kernarg_size: u32,
+}
+
+fn kernel_descriptor(parsed: &Elf, elf: &[u8], kernel: &str) -> Option<KernelDescriptor> {
+    let symbol_name = format!("{kernel}.kd");
+    let symbol = parsed
+        .syms
+        .iter()
+        .find(|sym| parsed.strtab.get_at(sym.st_name) == Some(symbol_name.as_str()))
+        .or_else(|| {
+            parsed
+                .dynsyms
+                .iter()
+                .find(|sym| parsed.dynstrtab.get_at(sym.st_name) == Some(symbol_name.as_str()))
+        })?;
+    let section = parsed.section_headers.get(symbol.st_shndx)?;
+    if section.sh_type == goblin::elf::section_header::SHT_NOBITS {
+        return None;
+    }
+    let offset = section.sh_offset + symbol.st_value.checked_sub(section.sh_addr)?;
+    let descriptor = elf.get(offset as usize..)?.get(..KERNEL_DESCRIPTOR_SIZE)?;
+    let u32_at =
+        |offset: usize| u32::from_le_bytes(descriptor[offset..offset + 4].try_into().unwrap());
+    Some(KernelDescriptor {
+        group_segment_fixed_size: u32_at(0),
+        private_segment_fixed_size: u32_at(4),
+        kernarg_size: u32_at(8),
+    })
+}
+
+fn kernel_resources(kernel: &msgpack::Value) -> Result<KernelResources, Error> {
+    let u32_field = |key| {
+        kernel
+            .get(key)
+            .and_then(msgpack::Value::as_u64)
+            .map_or(0, |value| value as u32)
+    };
+    Ok(KernelResources {
+        name: kernel
+            .get(".name")
+            .and_then(msgpack::Value::as_str)
+            .ok_or(Error::INVALID_ARGUMENT)?
+            .to_string(),
+        sgpr_count: u32_field(".sgpr_count"),
+        vgpr_count: u32_field(".vgpr_count"),
+        group_segment_fixed_size: u32_field(".group_segment_fixed_size"),
+        private_segment_fixed_size: u32_field(".private_segment_fixed_size"),
+        kernarg_segment_size: u32_field(".kernarg_segment_size"),
+        max_flat_workgroup_size: u32_field(".max_flat_workgroup_size"),
+        wavefront_size: u32_field(".wavefront_size"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hand-assembled gfx1030 code object with just the metadata note, the kernel descriptors and
+    // the symbols, no code. `scale` has stale segment sizes in its note, which must be ignored
+    static CODE_OBJECT: &[u8] = include_bytes!("test/kernels.hsaco");
+
+    #[test]
+    fn reads_resources_of_every_kernel() {
+        let kernels = get_kernel_resources(CODE_OBJECT).unwrap();
+        assert_eq!(
+            kernels,
+            vec![
+                KernelResources {
+                    name: "add".to_string(),
+                    sgpr_count: 10,
+                    vgpr_count: 3,
+                    group_segment_fixed_size: 0,
+                    private_segment_fixed_size: 0,
+                    kernarg_segment_size: 16,
+                    max_flat_workgroup_size: 1024,
+                    wavefront_size: 32,
+                },
+                KernelResources {
+                    name: "scale".to_string(),
+                    sgpr_count: 18,
+                    vgpr_count: 41,
+                    group_segment_fixed_size: 256,
+                    private_segment_fixed_size: 24,
+                    kernarg_segment_size: 24,
+                    max_flat_workgroup_size: 256,
+                    wavefront_size: 32,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata_without_kernels_is_empty() {
+        let parsed = Elf::parse(CODE_OBJECT).unwrap();
+        // {"amdhsa.version": [1, 2]}
+        let mut data: &[u8] = &[
+            0x81, 0xae, b'a', b'm', b'd', b'h', b's', b'a', b'.', b'v', b'e', b'r', b's', b'i',
+            b'o', b'n', 0x92, 0x01, 0x02,
+        ];
+        let metadata = msgpack::read(&mut data).unwrap();
+        let kernels = metadata_kernel_resources(&parsed, CODE_OBJECT, &metadata).unwrap();
+        assert!(kernels.is_empty());
+    }
+
+    #[test]
+    fn rejects_code_object_without_metadata() {
+        assert!(get_kernel_resources(&CODE_OBJECT[..64]).is_err());
+        assert!(get_kernel_resources(b"not an elf").is_err());
+    }
+}
+
+// Just enough of MessagePack to read the metadata note, extension types are skipped
+mod msgpack {
+    pub(super) enum Value<'a> {
+        Int(i64),
+        UInt(u64),
+        Str(&'a str),
+        Array(Vec<Value<'a>>),
+        Map(Vec<(Value<'a>, Value<'a>)>),
+        // Nil, booleans, floats, binary and extension types, not used by the metadata we read
+        Other,
+    }
+
+    impl<'a> Value<'a> {
+        pub(super) fn get(&self, key: &str) -> Option<&Value<'a>> {
+            match self {
+                Value::Map(entries) => entries
+                    .iter()
+                    .find(|(k, _)| k.as_str() == Some(key))
+                    .map(|(_, v)| v),
+                _ => None,
+            }
+        }
+
+        pub(super) fn as_str(&self) -> Option<&'a str> {
+            match self {
+                Value::Str(s) => Some(s),
+                _ => None,
+            }
+        }
+
+        pub(super) fn as_u64(&self) -> Option<u64> {
+            match *self {
+                Value::UInt(x) => Some(x),
+                Value::Int(x) => u64::try_from(x).ok(),
+                _ => None,
+            }
+        }
+
+        pub(super) fn as_array(&self) -> Option<&[Value<'a>]> {
+            match self {
+                Value::Array(values) => Some(values),
+                _ => None,
+            }
+        }
+    }
+
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+        if data.len() < len {
+            return None;
+        }
+        let (head, tail) = data.split_at(len);
+        *data = tail;
+        Some(head)
+    }
+
+    fn be<const N: usize>(data: &mut &[u8]) -> Option<[u8; N]> {
+        take(data, N)?.try_into().ok()
+    }
+
+    fn len(data: &mut &[u8], width: usize) -> Option<usize> {
+        Some(match width {
+            1 => u8::from_be_bytes(be(data)?) as usize,
+            2 => u16::from_be_bytes(be(data)?) as usize,
+            _ => u32::from_be_bytes(be(data)?) as usize,
+        })
+    }
+
+    fn str<'a>(data: &mut &'a [u8], len: usize) -> Option<Value<'a>> {
+        std::str::from_utf8(take(data, len)?).ok().map(Value::Str)
+    }
+
+    fn array<'a>(data: &mut &'a [u8], len: usize) -> Option<Value<'a>> {
+        (0..len)
+            .map(|_| read(data))
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array)
+    }
+
+    fn map<'a>(data: &mut &'a [u8], len: usize) -> Option<Value<'a>> {
+        (0..len)
+            .map(|_| Some((read(data)?, read(data)?)))
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Map)
+    }
+
+    fn skip<'a>(data: &mut &'a [u8], len: usize) -> Option<Value<'a>> {
+        take(data, len)?;
+        Some(Value::Other)
+    }
+
+    pub(super) fn read<'a>(data: &mut &'a [u8]) -> Option<Value<'a>> {
+        let marker = take(data, 1)?[0];
+        match marker {
+            0x00..=0x7f => Some(Value::UInt(marker as u64)),
+            0x80..=0x8f => map(data, (marker & 0x0f) as usize),
+            0x90..=0x9f => array(data, (marker & 0x0f) as usize),
+            0xa0..=0xbf => str(data, (marker & 0x1f) as usize),
+            0xc0 | 0xc2 | 0xc3 => Some(Value::Other),
+            0xc4..=0xc6 => {
+                let len = len(data, 1 << (marker - 0xc4))?;
+                skip(data, len)
+            }
+            // Extension types carry a type byte before the payload
+            0xc7..=0xc9 => {
+                let len = len(data, 1 << (marker - 0xc7))?;
+                skip(data, len + 1)
+            }
+            0xca => skip(data, 4),
+            0xcb => skip(data, 8),
+            0xcc => Some(Value::UInt(u8::from_be_bytes(be(data)?) as u64)),
+            0xcd => Some(Value::UInt(u16::from_be_bytes(be(data)?) as u64)),
+            0xce => Some(Value::UInt(u32::from_be_bytes(be(data)?) as u64)),
+            0xcf => Some(Value::UInt(u64::from_be_bytes(be(data)?))),
+            0xd0 => Some(Value::Int(i8::from_be_bytes(be(data)?) as i64)),
+            0xd1 => Some(Value::Int(i16::from_be_bytes(be(data)?) as i64)),
+            0xd2 => Some(Value::Int(i32::from_be_bytes(be(data)?) as i64)),
+            0xd3 => Some(Value::Int(i64::from_be_bytes(be(data)?))),
+            0xd4..=0xd8 => skip(data, (1 << (marker - 0xd4)) + 1),
+            0xd9..=0xdb => {
+                let len = len(data, 1 << (marker - 0xd9))?;
+                str(data, len)
+            }
+            0xdc | 0xdd => {
+                let len = len(data, 2 << (marker - 0xdc))?;
+                array(data, len)
+            }
+            0xde | 0xdf => {
+                let len = len(data, 2 << (marker - 0xde))?;
+                map(data, len)
+            }
+            0xe0..=0xff => Some(Value::Int(marker as i8 as i64)),
+            0xc1 => None,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn reads_nested_map() {
+            // {"amdhsa.kernels": [{".name": "add", ".sgpr_count": 300, ".vgpr_count": -1}]}
+            let mut data: &[u8] = &[
+                0x81, 0xae, b'a', b'm', b'd', b'h', b's', b'a', b'.', b'k', b'e', b'r', b'n', b'e',
+                b'l', b's', 0x91, 0x83, 0xa5, b'.', b'n', b'a', b'm', b'e', 0xa3, b'a', b'd', b'd',
+                0xab, b'.', b's', b'g', b'p', b'r', b'_', b'c', b'o', b'u', b'n', b't', 0xcd, 0x01,
+                0x2c, 0xab, b'.', b'v', b'g', b'p', b'r', b'_', b'c', b'o', b'u', b'n', b't', 0xff,
+            ];
+            let value = read(&mut data).unwrap();
+            assert!(data.is_empty());
+            let kernel = &value.get("amdhsa.kernels").unwrap().as_array().unwrap()[0];
+            assert_eq!(kernel.get(".name").unwrap().as_str(), Some("add"));
+            assert_eq!(kernel.get(".sgpr_count").unwrap().as_u64(), Some(300));
+            assert_eq!(kernel.get(".vgpr_count").unwrap().as_u64(), None);
+        }
+
+        #[test]
+        fn truncated_input_is_rejected() {
+            let mut data: &[u8] = &[0x92, 0x01];
+            assert!(read(&mut data).is_none());
+        }
+    }
+}
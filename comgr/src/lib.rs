@@ -1,5 +1,12 @@
 use amd_comgr_sys::*;
-use std::{ffi::CStr, iter, mem, ptr};
+use std::{
+    ffi::{CStr, CString},
+    iter, mem, ptr,
+};
+
+mod kernel_metadata;
+
+pub use kernel_metadata::{get_kernel_resources, KernelResources};
 
 macro_rules! call_dispatch_arg {
     (2, $arg:ident) => {
@@ -194,6 +201,7 @@ pub fn compile_bitcode(
     main_buffer: &[u8],
     ptx_impl: &[u8],
     attributes_buffer: &[u8],
+    correctly_rounded_div_sqrt: bool,
     compiler_hook: Option<&dyn Fn(&Vec<u8>, String)>,
 ) -> Result<Vec<u8>, Error> {
     let bitcode_data_set = DataSet::new(comgr)?;
@@ -205,6 +213,7 @@ pub fn compile_bitcode(
         Data::new(comgr, DataKind::Bc, c"attributes.bc", attributes_buffer)?;
     bitcode_data_set.add(&attributes_bitcode_data)?;
     let linking_info = ActionInfo::new(comgr)?;
+    log::info!("comgr link: zluda.bc ptx_impl.bc attributes.bc");
     let linked_data_set =
         comgr.do_action(ActionKind::LinkBcToBc, &linking_info, &bitcode_data_set)?;
     if let Some(hook) = compiler_hook {
@@ -249,7 +258,39 @@ pub fn compile_bitcode(
             c"-inlinehint-threshold=3250",
         ]
     };
-    compile_to_exec.set_options(common_options.chain(opt_options))?;
+    // Selects the oclc control libraries linked with the device libraries. By default we get
+    // oclc_correctly_rounded_sqrt_off and oclc_daz_opt_on, which are faster, but the results of
+    // ocml division and square root can be off by a few ULPs. PTX .rn variants must be exact
+    let rounding_options = if correctly_rounded_div_sqrt {
+        [
+            c"-cl-fp32-correctly-rounded-divide-sqrt",
+            c"-fno-gpu-flush-denormals-to-zero",
+        ]
+    } else {
+        [c"", c""]
+    };
+    // Must match the amdhsa_code_object_version module flag set by ptx, it selects the
+    // oclc_abi_version library used by the device libraries to find the hidden kernel arguments
+    let code_object_version = CString::new(format!(
+        "-mcode-object-version={}",
+        ptx::AMDHSA_CODE_OBJECT_VERSION
+    ))
+    .unwrap();
+    let options = common_options
+        .chain(iter::once(code_object_version.as_c_str()))
+        .chain(rounding_options)
+        .chain(opt_options)
+        .collect::<Vec<_>>();
+    log::info!(
+        "comgr compile --isa {gcn_arch} {}",
+        options
+            .iter()
+            .filter(|option| !option.is_empty())
+            .map(|option| option.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    compile_to_exec.set_options(options.into_iter())?;
     let exec_data_set = comgr.do_action(
         ActionKind::CompileSourceToExecutable,
         &compile_to_exec,
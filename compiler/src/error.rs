@@ -32,8 +32,8 @@ impl From<hipErrorCode_t> for CompilerError {
     }
 }
 
-impl From<Vec<PtxError<'_>>> for CompilerError {
-    fn from(causes: Vec<PtxError>) -> Self {
+impl CompilerError {
+    pub fn from_ptx_errors<'input>(text: &'input str, causes: Vec<PtxError<'input>>) -> Self {
         let errors: Vec<String> = causes
             .iter()
             .map(|e| {
@@ -42,7 +42,11 @@ impl From<Vec<PtxError<'_>>> for CompilerError {
                     | PtxError::UnrecognizedDirective(value) => value.to_string(),
                     other => other.to_string(),
                 };
-                format!("PtxError::{}: {}", e.as_ref(), msg)
+                let msg = format!("PtxError::{}: {}", e.as_ref(), msg);
+                match e.location(text) {
+                    Some(location) => location.annotate(&msg),
+                    None => msg,
+                }
             })
             .collect();
         let message = errors.join("\n");
@@ -55,7 +59,7 @@ impl From<Vec<PtxError<'_>>> for CompilerError {
 
 impl From<TranslateError> for CompilerError {
     fn from(cause: TranslateError) -> Self {
-        let message = format!("PTX TranslateError::{}", cause.as_ref());
+        let message = format!("PTX TranslateError::{}: {}", cause.inner().as_ref(), cause);
         let cause = Some(Box::new(cause) as Box<dyn std::error::Error>);
         CompilerError::GenericError { cause, message }
     }
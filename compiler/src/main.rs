@@ -65,7 +65,7 @@ fn main_core() -> Result<(), CompilerError> {
 
     let ptx = fs::read(&ptx_path).map_err(CompilerError::from)?;
     let ptx = str::from_utf8(&ptx).map_err(CompilerError::from)?;
-    let llvm = ptx_to_llvm(ptx).map_err(CompilerError::from)?;
+    let llvm = ptx_to_llvm(ptx, &arch).map_err(CompilerError::from)?;
 
     write_to_file(&llvm.llvm_ir, output_path.with_extension("ll").as_path())?;
 
@@ -80,6 +80,7 @@ fn main_core() -> Result<(), CompilerError> {
         &llvm.bitcode,
         &llvm.attributes_bitcode,
         &llvm.linked_bitcode,
+        llvm.correctly_rounded_div_sqrt,
         Some(&comgr_hook),
     )
     .map_err(CompilerError::from)?;
@@ -87,13 +88,18 @@ fn main_core() -> Result<(), CompilerError> {
     Ok(())
 }
 
-fn ptx_to_llvm(ptx: &str) -> Result<LLVMArtifacts, CompilerError> {
-    let ast = ptx_parser::parse_module_checked(ptx).map_err(CompilerError::from)?;
-    let module = ptx::to_llvm_module(
+fn ptx_to_llvm(ptx: &str, arch: &str) -> Result<LLVMArtifacts, CompilerError> {
+    let ast = ptx_parser::parse_module_checked(ptx)
+        .map_err(|errors| CompilerError::from_ptx_errors(ptx, errors))?;
+    let module = ptx::to_llvm_module_with_options(
         ast,
         ptx::Attributes {
             clock_rate: 2124000,
         },
+        ptx::TranslateOptions {
+            volatile_coherence: ptx::VolatileCoherence::for_arch(arch),
+            ..ptx::TranslateOptions::default()
+        },
     )
     .map_err(CompilerError::from)?;
     let bitcode = module.llvm_ir.write_bitcode_to_memory().to_vec();
@@ -105,6 +111,7 @@ fn ptx_to_llvm(ptx: &str) -> Result<LLVMArtifacts, CompilerError> {
         linked_bitcode,
         attributes_bitcode,
         llvm_ir,
+        correctly_rounded_div_sqrt: module.correctly_rounded_div_sqrt,
     })
 }
 
@@ -114,6 +121,7 @@ struct LLVMArtifacts {
     linked_bitcode: Vec<u8>,
     attributes_bitcode: Vec<u8>,
     llvm_ir: Vec<u8>,
+    correctly_rounded_div_sqrt: bool,
 }
 
 fn get_gpu_arch() -> Result<&'static str, CompilerError> {
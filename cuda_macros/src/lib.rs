@@ -10,7 +10,7 @@ use syn::punctuated::Punctuated;
 use syn::visit_mut::VisitMut;
 use syn::{
     bracketed, parse_macro_input, File, ForeignItem, ForeignItemFn, Ident, Item, Path, Signature,
-    Token,
+    Token, Visibility,
 };
 
 const CUDA_RS: &'static str = include_str! {"cuda.rs"};
@@ -21,6 +21,9 @@ const CUBLASLT_INTERNAL_RS: &'static str = include_str! {"cublaslt_internal.rs"}
 const CUFFT_RS: &'static str = include_str! {"cufft.rs"};
 const CUSPARSE_RS: &'static str = include_str! {"cusparse.rs"};
 const CUDNN9_RS: &'static str = include_str! {"cudnn9.rs"};
+// HIP functions come straight from the bindings, so that code loading them at runtime uses the
+// same signatures as code linking to them
+const HIP_RS: &'static str = include_str! {"../../ext/hip_runtime-sys/src/lib.rs"};
 
 // This macro accepts following arguments:
 // * `normal_macro`: ident for a normal macro
@@ -71,9 +74,32 @@ pub fn cudnn9_function_declarations(tokens: TokenStream) -> TokenStream {
     function_declarations(tokens, CUDNN9_RS)
 }
 
+#[proc_macro]
+pub fn hip_function_declarations(tokens: TokenStream) -> TokenStream {
+    let mut hip_module = syn::parse_str::<File>(HIP_RS).unwrap();
+    // Unlike our CUDA modules, HIP bindings also contain types and constants, and their functions
+    // are public
+    hip_module
+        .items
+        .retain(|item| matches!(item, Item::ForeignMod(..)));
+    for item in hip_module.items.iter_mut() {
+        if let Item::ForeignMod(extern_) = item {
+            for item in extern_.items.iter_mut() {
+                if let ForeignItem::Fn(fn_) = item {
+                    fn_.vis = Visibility::Inherited;
+                }
+            }
+        }
+    }
+    function_declarations_in(tokens, hip_module)
+}
+
 fn function_declarations(tokens: TokenStream, module: &str) -> TokenStream {
+    function_declarations_in(tokens, syn::parse_str::<File>(module).unwrap())
+}
+
+fn function_declarations_in(tokens: TokenStream, mut cuda_module: File) -> TokenStream {
     let input = parse_macro_input!(tokens as FnDeclInput);
-    let mut cuda_module = syn::parse_str::<File>(module).unwrap();
     let mut choose_macro = ChooseMacro::new(input);
     syn::visit_mut::visit_file_mut(&mut FixFnSignatures, &mut cuda_module);
     for item in cuda_module.items {
@@ -2,6 +2,9 @@ use std::env::VarError;
 use std::{env, path::PathBuf};
 
 fn main() -> Result<(), VarError> {
+    if env::var_os("CARGO_FEATURE_DYNAMIC").is_some() {
+        return Ok(());
+    }
     if cfg!(windows) {
         println!("cargo:rustc-link-lib=dylib=amdhip64_6");
         let env = env::var("CARGO_CFG_TARGET_ENV")?;
@@ -10,8 +10,24 @@ const COMPONENTS: &[&'static str] = &[
     "LLVMAnalysis", // for module verify
     #[cfg(debug_assertions)]
     "LLVMBitReader",
+    #[cfg(debug_assertions)]
+    "LLVMPasses", // for tests of the optimized output
+    #[cfg(debug_assertions)]
+    "LLVMOrcJIT", // for running kernels on the CPU in tests
+    #[cfg(all(debug_assertions, target_arch = "x86_64"))]
+    "LLVMX86CodeGen",
+    #[cfg(all(debug_assertions, target_arch = "aarch64"))]
+    "LLVMAArch64CodeGen",
+    #[cfg(debug_assertions)]
+    "LLVMLinker", // for tests linking several translated modules
 ];
 
+// ORC needs the host target, release builds don't run kernels on the CPU
+#[cfg(debug_assertions)]
+const TARGETS_TO_BUILD: &str = "host";
+#[cfg(not(debug_assertions))]
+const TARGETS_TO_BUILD: &str = "";
+
 fn main() {
     let mut cmake = Config::new(r"../ext/llvm-project/llvm");
     try_use_sccache(&mut cmake);
@@ -31,7 +47,7 @@ fn main() {
         .define("LLVM_INCLUDE_EXAMPLES", "OFF")
         .define("LLVM_INCLUDE_TESTS", "OFF")
         .define("LLVM_BUILD_TOOLS", "OFF")
-        .define("LLVM_TARGETS_TO_BUILD", "")
+        .define("LLVM_TARGETS_TO_BUILD", TARGETS_TO_BUILD)
         .define("LLVM_ENABLE_PROJECTS", "");
     cmake.build_target("llvm-config");
     let llvm_dir = cmake.build();
@@ -4,6 +4,8 @@
 #include <llvm/IR/IRBuilder.h>
 #include <llvm/IR/Type.h>
 #include <llvm/IR/Instructions.h>
+#include <llvm/IR/Metadata.h>
+#include <llvm/Support/TargetSelect.h>
 #pragma GCC diagnostic pop
 
 using namespace llvm;
@@ -196,4 +198,22 @@ void LLVMZludaBuildFence(LLVMBuilderRef B, LLVMAtomicOrdering Ordering,
                          Name);
 }
 
+LLVMMetadataRef LLVMZludaLoopID(LLVMContextRef C, LLVMMetadataRef *Properties,
+                                size_t Count)
+{
+    SmallVector<Metadata *, 4> Operands;
+    Operands.push_back(nullptr);
+    for (size_t i = 0; i < Count; i++)
+        Operands.push_back(unwrap(Properties[i]));
+    MDNode *LoopID = MDNode::getDistinct(*unwrap(C), Operands);
+    LoopID->replaceOperandWith(0, LoopID);
+    return wrap(LoopID);
+}
+
+// Fails when LLVM is built without the host target (release builds)
+LLVMBool LLVMZludaInitializeNativeTarget()
+{
+    return InitializeNativeTarget() || InitializeNativeTargetAsmPrinter();
+}
+
 LLVM_C_EXTERN_C_END
\ No newline at end of file
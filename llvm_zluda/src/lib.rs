@@ -72,10 +72,20 @@ extern "C" {
 
     pub fn LLVMZludaSetFastMathFlags(FPMathInst: LLVMValueRef, FMF: LLVMZludaFastMathFlags);
 
+    // Distinct node referring to itself as the first operand, followed by the loop properties
+    pub fn LLVMZludaLoopID(
+        C: LLVMContextRef,
+        Properties: *mut LLVMMetadataRef,
+        Count: usize,
+    ) -> LLVMMetadataRef;
+
     pub fn LLVMZludaBuildFence(
         B: LLVMBuilderRef,
         ordering: LLVMAtomicOrdering,
         scope: *const i8,
         Name: *const i8,
     ) -> LLVMValueRef;
+
+    // Native target and its asm printer, needed by ORC
+    pub fn LLVMZludaInitializeNativeTarget() -> LLVMBool;
 }
@@ -0,0 +1,521 @@
+// Compares runtimes of representative kernels compiled by ZLUDA for HIP against the same PTX
+// running on CUDA, and measures how long translation and compilation take. Either backend may
+// be missing, the other one is still benchmarked.
+//
+//     cargo bench -p ptx --bench kernels -- [--warmup N] [--iterations N] [--json PATH]
+//         [--max-translate-ms MS] [--max-compile-ms MS]
+
+use cuda_types::cuda::{CUcontext, CUdevice, CUdeviceptr_v2, CUevent, CUstream};
+use hip_runtime_sys::*;
+use ptx::{Artifact, Compiler, Target};
+use serde::Serialize;
+use std::ffi::{c_void, CStr, CString};
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{env, mem, ptr};
+
+const BLOCK_SIZE: u32 = 256;
+
+struct Kernel {
+    name: &'static str,
+    ptx: &'static str,
+    // Number of threads, one per element
+    elements: u32,
+    input_bytes: usize,
+    output_bytes: usize,
+}
+
+const KERNELS: &[Kernel] = &[
+    Kernel {
+        name: "copy",
+        ptx: include_str!("kernels/copy.ptx"),
+        elements: 1 << 22,
+        input_bytes: 16 << 22,
+        output_bytes: 16 << 22,
+    },
+    Kernel {
+        name: "reduce",
+        ptx: include_str!("kernels/reduce.ptx"),
+        elements: 1 << 24,
+        input_bytes: 4 << 24,
+        // One sum per block
+        output_bytes: 4 << 16,
+    },
+    Kernel {
+        name: "fma",
+        ptx: include_str!("kernels/fma.ptx"),
+        elements: 1 << 20,
+        input_bytes: 4 << 20,
+        output_bytes: 4 << 20,
+    },
+];
+
+// Compiler output of nvcc for a single kernel, much larger than the kernels above
+const LARGE_KERNEL: &str = include_str!("../src/test/_Z9vectorAddPKfS0_Pfi.ptx");
+
+struct Options {
+    warmup: u32,
+    iterations: u32,
+    json: Option<PathBuf>,
+    max_translate: Duration,
+    max_compile: Duration,
+}
+
+impl Options {
+    fn from_args() -> Self {
+        let mut options = Options {
+            warmup: 3,
+            iterations: 20,
+            json: None,
+            max_translate: Duration::from_secs(2),
+            max_compile: Duration::from_secs(10),
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .unwrap_or_else(|| panic!("Missing value for {arg}"))
+            };
+            match arg.as_str() {
+                // Passed by `cargo bench`
+                "--bench" => {}
+                "--warmup" => options.warmup = value().parse().unwrap(),
+                "--iterations" => options.iterations = value().parse().unwrap(),
+                "--json" => options.json = Some(value().into()),
+                "--max-translate-ms" => {
+                    options.max_translate = Duration::from_millis(value().parse().unwrap())
+                }
+                "--max-compile-ms" => {
+                    options.max_compile = Duration::from_millis(value().parse().unwrap())
+                }
+                _ => panic!("Unknown argument {arg}"),
+            }
+        }
+        assert!(options.iterations > 0, "--iterations must be at least 1");
+        options
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    hip_device: Option<String>,
+    cuda_device: Option<String>,
+    kernels: Vec<KernelReport>,
+    large_kernel: LargeKernelReport,
+}
+
+#[derive(Serialize)]
+struct KernelReport {
+    name: &'static str,
+    translate_ms: f64,
+    hip: Option<BackendReport>,
+    cuda: Option<BackendReport>,
+}
+
+#[derive(Serialize)]
+struct BackendReport {
+    compile_ms: f64,
+    runtime: Stats,
+}
+
+#[derive(Serialize)]
+struct LargeKernelReport {
+    translate_ms: f64,
+    hip_compile_ms: Option<f64>,
+    cuda_compile_ms: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    min_ms: f64,
+    median_ms: f64,
+    mean_ms: f64,
+}
+
+impl Stats {
+    fn new(mut samples: Vec<f64>) -> Self {
+        samples.sort_by(f64::total_cmp);
+        Stats {
+            min_ms: samples[0],
+            median_ms: samples[samples.len() / 2],
+            mean_ms: samples.iter().sum::<f64>() / samples.len() as f64,
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn translate(ptx: &str) -> (Duration, Artifact) {
+    let start = Instant::now();
+    let artifact = Compiler::new(Target::LlvmBitcode).compile(ptx).unwrap();
+    (start.elapsed(), artifact)
+}
+
+// Warmup launches are timed too, but their samples are dropped
+fn measure(options: &Options, mut timed_launch: impl FnMut() -> f32) -> Stats {
+    for _ in 0..options.warmup {
+        timed_launch();
+    }
+    Stats::new(
+        (0..options.iterations)
+            .map(|_| timed_launch() as f64)
+            .collect(),
+    )
+}
+
+fn main() {
+    let options = Options::from_args();
+    let hip = Hip::new()
+        .inspect_err(|err| eprintln!("HIP unavailable, skipping: {err}"))
+        .ok();
+    let cuda = Cuda::new()
+        .inspect_err(|err| eprintln!("CUDA unavailable, skipping: {err}"))
+        .ok();
+    let kernels = KERNELS
+        .iter()
+        .map(|kernel| {
+            let (translate_time, artifact) = translate(kernel.ptx);
+            KernelReport {
+                name: kernel.name,
+                translate_ms: millis(translate_time),
+                hip: hip
+                    .as_ref()
+                    .map(|hip| hip.bench(kernel, &artifact, &options)),
+                cuda: cuda.as_ref().map(|cuda| cuda.bench(kernel, &options)),
+            }
+        })
+        .collect();
+    let (translate_time, artifact) = (0..options.iterations.min(5))
+        .map(|_| translate(LARGE_KERNEL))
+        .min_by_key(|(time, _)| *time)
+        .unwrap();
+    let hip_compile = hip.as_ref().map(|hip| hip.compile(&artifact).0);
+    let cuda_compile = cuda.as_ref().map(|cuda| {
+        let (time, module) = cuda.compile(LARGE_KERNEL);
+        unsafe { cuda.lib.cuModuleUnload(module) }.unwrap().unwrap();
+        time
+    });
+    let report = Report {
+        hip_device: hip.as_ref().map(|hip| hip.device.clone()),
+        cuda_device: cuda.as_ref().map(|cuda| cuda.device.clone()),
+        kernels,
+        large_kernel: LargeKernelReport {
+            translate_ms: millis(translate_time),
+            hip_compile_ms: hip_compile.map(millis),
+            cuda_compile_ms: cuda_compile.map(millis),
+        },
+    };
+    print_table(&report);
+    if let Some(path) = &options.json {
+        serde_json::to_writer_pretty(File::create(path).unwrap(), &report).unwrap();
+    }
+    assert!(
+        translate_time <= options.max_translate,
+        "Translating the large kernel took {translate_time:?}, more than {:?}",
+        options.max_translate
+    );
+    if let Some(hip_compile) = hip_compile {
+        let total = translate_time + hip_compile;
+        assert!(
+            total <= options.max_compile,
+            "Translating and compiling the large kernel for HIP took {total:?}, more than {:?}",
+            options.max_compile
+        );
+    }
+}
+
+fn print_table(report: &Report) {
+    let unavailable = || "unavailable".to_string();
+    println!(
+        "HIP:  {}",
+        report.hip_device.clone().unwrap_or_else(unavailable)
+    );
+    println!(
+        "CUDA: {}",
+        report.cuda_device.clone().unwrap_or_else(unavailable)
+    );
+    println!();
+    println!(
+        "{:<10} {:>14} {:>14} {:>14} {:>14} {:>14}",
+        "kernel", "translate ms", "hip compile", "hip runtime", "cuda compile", "cuda runtime"
+    );
+    let cell = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{value:.3}"));
+    for kernel in report.kernels.iter() {
+        println!(
+            "{:<10} {:>14.3} {:>14} {:>14} {:>14} {:>14}",
+            kernel.name,
+            kernel.translate_ms,
+            cell(kernel.hip.as_ref().map(|hip| hip.compile_ms)),
+            cell(kernel.hip.as_ref().map(|hip| hip.runtime.median_ms)),
+            cell(kernel.cuda.as_ref().map(|cuda| cuda.compile_ms)),
+            cell(kernel.cuda.as_ref().map(|cuda| cuda.runtime.median_ms)),
+        );
+    }
+    let large = &report.large_kernel;
+    println!(
+        "{:<10} {:>14.3} {:>14} {:>14} {:>14} {:>14}",
+        "large",
+        large.translate_ms,
+        cell(large.hip_compile_ms),
+        "-",
+        cell(large.cuda_compile_ms),
+        "-",
+    );
+}
+
+struct Hip {
+    comgr: comgr::Comgr,
+    stream: hipStream_t,
+    device: String,
+    arch: String,
+}
+
+impl Hip {
+    fn new() -> Result<Self, String> {
+        let comgr = comgr::Comgr::new().map_err(|err| format!("failed to load comgr: {err}"))?;
+        unsafe { hipInit(0) }.map_err(|err| format!("{err:?}"))?;
+        let mut props = unsafe { mem::zeroed::<hipDeviceProp_tR0600>() };
+        unsafe { hipGetDevicePropertiesR0600(&mut props, 0) }.map_err(|err| format!("{err:?}"))?;
+        let mut stream = unsafe { mem::zeroed() };
+        unsafe { hipStreamCreate(&mut stream) }.map_err(|err| format!("{err:?}"))?;
+        let arch = unsafe { CStr::from_ptr(props.gcnArchName.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+        let name = unsafe { CStr::from_ptr(props.name.as_ptr()) }.to_string_lossy();
+        Ok(Hip {
+            comgr,
+            stream,
+            device: format!("{name} ({arch})"),
+            arch,
+        })
+    }
+
+    fn compile(&self, artifact: &Artifact) -> (Duration, Vec<u8>) {
+        let start = Instant::now();
+        let elf = comgr::compile_bitcode(
+            &self.comgr,
+            &self.arch,
+            artifact.bytes(),
+            artifact.linked_bitcode(),
+            artifact.attributes_bitcode(),
+            artifact.correctly_rounded_div_sqrt(),
+            None,
+        )
+        .unwrap();
+        (start.elapsed(), elf)
+    }
+
+    fn bench(&self, kernel: &Kernel, artifact: &Artifact, options: &Options) -> BackendReport {
+        let (compile_time, elf) = self.compile(artifact);
+        let name = CString::new(kernel.name).unwrap();
+        let mut module = unsafe { mem::zeroed() };
+        unsafe { hipModuleLoadData(&mut module, elf.as_ptr().cast()) }.unwrap();
+        let mut function = unsafe { mem::zeroed() };
+        unsafe { hipModuleGetFunction(&mut function, module, name.as_ptr()) }.unwrap();
+        let mut src = ptr::null_mut();
+        let mut dst = ptr::null_mut();
+        unsafe { hipMalloc(&mut src, kernel.input_bytes) }.unwrap();
+        unsafe { hipMalloc(&mut dst, kernel.output_bytes) }.unwrap();
+        unsafe { hipMemset(src, 0, kernel.input_bytes) }.unwrap();
+        let mut n = kernel.elements;
+        let mut params = [
+            ptr::from_mut(&mut src).cast::<c_void>(),
+            ptr::from_mut(&mut dst).cast(),
+            ptr::from_mut(&mut n).cast(),
+        ];
+        let mut start = unsafe { mem::zeroed() };
+        let mut end = unsafe { mem::zeroed() };
+        unsafe { hipEventCreate(&mut start) }.unwrap();
+        unsafe { hipEventCreate(&mut end) }.unwrap();
+        let runtime = measure(options, || {
+            unsafe { hipEventRecord(start, self.stream) }.unwrap();
+            unsafe {
+                hipModuleLaunchKernel(
+                    function,
+                    kernel.elements.div_ceil(BLOCK_SIZE),
+                    1,
+                    1,
+                    BLOCK_SIZE,
+                    1,
+                    1,
+                    0,
+                    self.stream,
+                    params.as_mut_ptr(),
+                    ptr::null_mut(),
+                )
+            }
+            .unwrap();
+            unsafe { hipEventRecord(end, self.stream) }.unwrap();
+            unsafe { hipEventSynchronize(end) }.unwrap();
+            let mut elapsed = 0.0;
+            unsafe { hipEventElapsedTime(&mut elapsed, start, end) }.unwrap();
+            elapsed
+        });
+        unsafe { hipEventDestroy(start) }.unwrap();
+        unsafe { hipEventDestroy(end) }.unwrap();
+        unsafe { hipFree(src) }.unwrap();
+        unsafe { hipFree(dst) }.unwrap();
+        unsafe { hipModuleUnload(module) }.unwrap();
+        BackendReport {
+            compile_ms: millis(compile_time),
+            runtime,
+        }
+    }
+}
+
+struct Cuda {
+    lib: DynamicCuda,
+    context: CUcontext,
+    device: String,
+}
+
+impl Cuda {
+    fn new() -> Result<Self, String> {
+        let lib = DynamicCuda::new()
+            .map_err(|err| format!("failed to load {}: {err}", DynamicCuda::CUDA_PATH))?;
+        let check = |result: Result<_, libloading::Error>| {
+            result
+                .map_err(|err| err.to_string())?
+                .map_err(|err| format!("{err:?}"))
+        };
+        check(unsafe { lib.cuInit(0) })?;
+        let mut device = unsafe { mem::zeroed::<CUdevice>() };
+        check(unsafe { lib.cuDeviceGet(&mut device, 0) })?;
+        let mut name = [0; 256];
+        check(unsafe { lib.cuDeviceGetName(name.as_mut_ptr(), name.len() as i32, device) })?;
+        let mut context = unsafe { mem::zeroed() };
+        check(unsafe { lib.cuCtxCreate_v2(&mut context, 0, device) })?;
+        let device = unsafe { CStr::from_ptr(name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+        Ok(Cuda {
+            lib,
+            context,
+            device,
+        })
+    }
+
+    // The driver JIT-compiles PTX when loading the module
+    fn compile(&self, ptx: &str) -> (Duration, cuda_types::cuda::CUmodule) {
+        let ptx = CString::new(ptx).unwrap();
+        let start = Instant::now();
+        let mut module = unsafe { mem::zeroed() };
+        unsafe { self.lib.cuModuleLoadData(&mut module, ptx.as_ptr().cast()) }
+            .unwrap()
+            .unwrap();
+        (start.elapsed(), module)
+    }
+
+    fn bench(&self, kernel: &Kernel, options: &Options) -> BackendReport {
+        let cuda = &self.lib;
+        let (compile_time, module) = self.compile(kernel.ptx);
+        let name = CString::new(kernel.name).unwrap();
+        let mut function = unsafe { mem::zeroed() };
+        unsafe { cuda.cuModuleGetFunction(&mut function, module, name.as_ptr()) }
+            .unwrap()
+            .unwrap();
+        let mut src = CUdeviceptr_v2(ptr::null_mut());
+        let mut dst = CUdeviceptr_v2(ptr::null_mut());
+        unsafe { cuda.cuMemAlloc_v2(&mut src, kernel.input_bytes) }
+            .unwrap()
+            .unwrap();
+        unsafe { cuda.cuMemAlloc_v2(&mut dst, kernel.output_bytes) }
+            .unwrap()
+            .unwrap();
+        unsafe { cuda.cuMemsetD8_v2(src, 0, kernel.input_bytes) }
+            .unwrap()
+            .unwrap();
+        let mut n = kernel.elements;
+        let mut params = [
+            ptr::from_mut(&mut src).cast::<c_void>(),
+            ptr::from_mut(&mut dst).cast(),
+            ptr::from_mut(&mut n).cast(),
+        ];
+        let stream = CUstream(ptr::null_mut());
+        let mut start: CUevent = ptr::null_mut();
+        let mut end: CUevent = ptr::null_mut();
+        unsafe { cuda.cuEventCreate(&mut start, 0) }
+            .unwrap()
+            .unwrap();
+        unsafe { cuda.cuEventCreate(&mut end, 0) }.unwrap().unwrap();
+        let runtime = measure(options, || {
+            unsafe { cuda.cuEventRecord(start, stream) }
+                .unwrap()
+                .unwrap();
+            unsafe {
+                cuda.cuLaunchKernel(
+                    function,
+                    kernel.elements.div_ceil(BLOCK_SIZE),
+                    1,
+                    1,
+                    BLOCK_SIZE,
+                    1,
+                    1,
+                    0,
+                    stream,
+                    params.as_mut_ptr(),
+                    ptr::null_mut(),
+                )
+            }
+            .unwrap()
+            .unwrap();
+            unsafe { cuda.cuEventRecord(end, stream) }.unwrap().unwrap();
+            unsafe { cuda.cuEventSynchronize(end) }.unwrap().unwrap();
+            let mut elapsed = 0.0;
+            unsafe { cuda.cuEventElapsedTime(&mut elapsed, start, end) }
+                .unwrap()
+                .unwrap();
+            elapsed
+        });
+        unsafe { cuda.cuEventDestroy_v2(start) }.unwrap().unwrap();
+        unsafe { cuda.cuEventDestroy_v2(end) }.unwrap().unwrap();
+        unsafe { cuda.cuMemFree_v2(src) }.unwrap().unwrap();
+        unsafe { cuda.cuMemFree_v2(dst) }.unwrap().unwrap();
+        unsafe { cuda.cuModuleUnload(module) }.unwrap().unwrap();
+        BackendReport {
+            compile_ms: millis(compile_time),
+            runtime,
+        }
+    }
+}
+
+impl Drop for Cuda {
+    fn drop(&mut self) {
+        unsafe { self.lib.cuCtxDestroy_v2(self.context) }.ok();
+    }
+}
+
+struct DynamicCuda {
+    lib: libloading::Library,
+}
+
+impl DynamicCuda {
+    #[cfg(not(windows))]
+    const CUDA_PATH: &'static str = "/usr/lib/x86_64-linux-gnu/libcuda.so.1";
+    #[cfg(windows)]
+    const CUDA_PATH: &'static str = "C:\\Windows\\System32\\nvcuda.dll";
+
+    fn new() -> Result<Self, libloading::Error> {
+        let lib = unsafe { libloading::Library::new(Self::CUDA_PATH) }?;
+        Ok(Self { lib })
+    }
+}
+
+macro_rules! dynamic_fns {
+    ($($abi:literal fn $fn_name:ident( $($arg_id:ident : $arg_type:ty),* ) -> $ret_type:ty;)*) => {
+        impl DynamicCuda {
+        $(
+            #[allow(dead_code)]
+            unsafe fn $fn_name(&self, $($arg_id : $arg_type),*) -> Result<$ret_type, libloading::Error> {
+                let func = unsafe { self.lib.get::<unsafe extern $abi fn ($($arg_type),*) -> $ret_type>(concat!(stringify!($fn_name), "\0").as_bytes()) };
+                func.map(|f| f($($arg_id),*) )
+            }
+        )*
+        }
+    };
+}
+
+cuda_macros::cuda_function_declarations!(dynamic_fns);
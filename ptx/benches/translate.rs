@@ -0,0 +1,148 @@
+// Measures how much running the passes in parallel speeds up translation of a large module. The
+// module is synthetic: a single nvcc kernel copied under different names. It is translated on a
+// single thread and then on every core, both outputs must be the same. Peak memory of the process
+// is reported too (Linux only). The run fails if the speedup is below MIN_SPEEDUP (on machines with
+// at least MIN_SPEEDUP_THREADS cores) or the peak memory is above MAX_PEAK_RSS, `--min-speedup`
+// and `--max-peak-rss` override them.
+//
+//     cargo bench -p ptx --bench translate -- [--kernels N] [--iterations N] [--min-speedup X]
+//         [--max-peak-rss MIB]
+
+use ptx::{Compiler, Target};
+use std::env;
+use std::time::{Duration, Instant};
+
+const KERNEL: &str = include_str!("../src/test/vectorAdd_kernel64.ptx");
+const ENTRY: &str = ".visible .entry ";
+// Limits for the default of 200 kernels, with headroom over the typical results so that only
+// a regression fails the run
+const MIN_SPEEDUP: f64 = 2.0;
+const MIN_SPEEDUP_THREADS: usize = 4;
+const MAX_PEAK_RSS: f64 = 1024.0;
+
+struct Options {
+    kernels: usize,
+    iterations: u32,
+    min_speedup: Option<f64>,
+    max_peak_rss: Option<f64>,
+}
+
+impl Options {
+    fn from_args() -> Self {
+        let mut options = Options {
+            kernels: 200,
+            iterations: 5,
+            min_speedup: None,
+            max_peak_rss: None,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .unwrap_or_else(|| panic!("Missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "--kernels" => options.kernels = value().parse().unwrap(),
+                "--iterations" => options.iterations = value().parse().unwrap(),
+                "--min-speedup" => options.min_speedup = Some(value().parse().unwrap()),
+                "--max-peak-rss" => options.max_peak_rss = Some(value().parse().unwrap()),
+                // Passed by `cargo bench`
+                "--bench" => {}
+                _ => panic!("Unknown argument {arg}"),
+            }
+        }
+        options
+    }
+}
+
+// Module header of KERNEL followed by `count` copies of its kernel, the copy `i` has `_i` appended
+// to every name derived from the kernel name
+fn synthetic_module(count: usize) -> String {
+    let entry = KERNEL.find(ENTRY).unwrap();
+    let (header, kernel) = KERNEL.split_at(entry);
+    let name = kernel[ENTRY.len()..].split('(').next().unwrap().trim();
+    let mut module = header.to_string();
+    for i in 0..count {
+        module.push_str(&kernel.replace(name, &format!("{name}_{i}")));
+        module.push('\n');
+    }
+    module
+}
+
+// Fastest of `iterations` translations with `threads` threads, and the last output
+fn translate(ptx: &str, threads: usize, iterations: u32) -> (Duration, Vec<u8>) {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap();
+    pool.install(|| {
+        let mut best = Duration::MAX;
+        let mut output = Vec::new();
+        for _ in 0..iterations {
+            let start = Instant::now();
+            let artifact = Compiler::new(Target::LlvmBitcode).compile(ptx).unwrap();
+            best = best.min(start.elapsed());
+            output = artifact.bytes().to_vec();
+        }
+        (best, output)
+    })
+}
+
+// Peak resident set size of the process in MiB, VmHWM of /proc/self/status
+fn peak_rss() -> Option<f64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+    Some(kib / 1024.0)
+}
+
+fn main() {
+    let options = Options::from_args();
+    let module = synthetic_module(options.kernels);
+    let threads = rayon::current_num_threads();
+    let min_speedup = options
+        .min_speedup
+        .or((threads >= MIN_SPEEDUP_THREADS).then_some(MIN_SPEEDUP));
+    let (sequential, sequential_output) = translate(&module, 1, options.iterations);
+    let (parallel, parallel_output) = translate(&module, threads, options.iterations);
+    assert!(
+        sequential_output == parallel_output,
+        "Translation on {threads} threads gives different output than on a single thread"
+    );
+    let speedup = sequential.as_secs_f64() / parallel.as_secs_f64();
+    println!(
+        "{} kernels: {:.1} ms on 1 thread, {:.1} ms on {threads} threads, {speedup:.2}x",
+        options.kernels,
+        sequential.as_secs_f64() * 1000.0,
+        parallel.as_secs_f64() * 1000.0
+    );
+    if let Some(min_speedup) = min_speedup {
+        assert!(
+            speedup >= min_speedup,
+            "Speedup {speedup:.2}x is below the required {min_speedup:.2}x"
+        );
+    }
+    let peak_rss = peak_rss();
+    match peak_rss {
+        Some(peak_rss) => println!("Peak RSS: {peak_rss:.1} MiB"),
+        None => println!("Peak RSS: unavailable"),
+    }
+    // The default limit is skipped where peak memory is not available, an explicit one is not
+    match (peak_rss, options.max_peak_rss) {
+        (Some(peak_rss), max_peak_rss) => {
+            let max_peak_rss = max_peak_rss.unwrap_or(MAX_PEAK_RSS);
+            assert!(
+                peak_rss <= max_peak_rss,
+                "Peak RSS {peak_rss:.1} MiB is above the allowed {max_peak_rss:.1} MiB"
+            );
+        }
+        (None, Some(_)) => panic!("Peak RSS is not available on this platform"),
+        (None, None) => {}
+    }
+}
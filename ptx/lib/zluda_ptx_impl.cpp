@@ -47,6 +47,19 @@ extern "C"
         return (uint32_t)__ockl_get_num_groups(member);
     }
 
+    // group_segment_size of the HSA dispatch packet is the LDS of the dispatch: the static
+    // allocation of the kernel and the dynamic one requested at launch
+    uint32_t FUNC(sreg_total_smem_size)()
+    {
+        const CONSTANT_SPACE uint32_t *packet = (const CONSTANT_SPACE uint32_t *)__builtin_amdgcn_dispatch_ptr();
+        return packet[7];
+    }
+
+    uint32_t FUNC(sreg_dynamic_smem_size)()
+    {
+        return __zluda_ptx_impl_sreg_total_smem_size() - __builtin_amdgcn_groupstaticsize();
+    }
+
     uint32_t __ockl_bfe_u32(uint32_t, uint32_t, uint32_t) __device__;
     uint32_t FUNC(bfe_u32)(uint32_t base, uint32_t pos_32, uint32_t len_32)
     {
@@ -152,6 +165,74 @@ extern "C"
         __builtin_amdgcn_s_barrier();
     }
 
+    // A CTA has 16 barriers, but AMD GPUs have a single workgroup barrier. bar.sync and bar.arrive
+    // with a thread count only involve the warps which reach them, so they count arrivals in
+    // shared memory instead, one counter per barrier. The counter is never reset, generation n of
+    // a barrier with a thread count of c is complete once the counter reaches (n + 1) * c, so a wave
+    // arriving for the next generation before the waiting waves have left is still counted.
+    // Caveats of the emulation:
+    // * the waiting warps busy-wait with s_sleep, they take issue slots from the warps they are
+    //   waiting for
+    // * it relies on every wave of the workgroup being resident and scheduled, which holds as long
+    //   as the workgroup fits on a single compute unit
+    // * the same barrier should not be used with and without a thread count, a workgroup barrier
+    //   doesn't see the warps spinning on the counter
+    // * every use of a barrier must have the same thread count, the generations are counted in
+    //   multiples of it
+    static __shared__ uint64_t bar_arrived[16];
+
+    size_t __ockl_get_local_linear_id() __device__;
+    // Shared memory is not initialized, called at the start of every kernel which can reach the
+    // counters, when every thread of the workgroup is still running
+    void FUNC(bar_sync_init)()
+    {
+        size_t id = __ockl_get_local_linear_id();
+        if (id < 16)
+            bar_arrived[id] = 0;
+        __zluda_ptx_impl_bar_sync(0);
+    }
+
+    uint32_t __ockl_activelane_u32() __device__;
+    static __device__ void bar_count(uint32_t barrier, uint32_t count, bool wait)
+    {
+        uint32_t block_size = (uint32_t)(__ockl_get_local_size(0) * __ockl_get_local_size(1) * __ockl_get_local_size(2));
+        // Every thread of the block takes part, a thread count larger than the block is undefined
+        if (count >= block_size)
+        {
+            __zluda_ptx_impl_bar_sync(barrier);
+            return;
+        }
+        barrier &= 15U;
+        // Like on NVIDIA GPUs, a warp counts as warp size threads, even if some of its threads
+        // have exited. A wave counts as all of its lanes, the thread count is rounded up to whole
+        // waves, so 32 threads on wave64 are a single wave
+        uint64_t wave_size = (uint64_t)__builtin_amdgcn_wavefrontsize();
+        uint64_t generation_size = ((uint64_t)count + wave_size - 1) / wave_size * wave_size;
+        __builtin_amdgcn_fence(__ATOMIC_RELEASE, "workgroup");
+        if (__ockl_activelane_u32() == 0)
+        {
+            uint64_t arrived = __hip_atomic_fetch_add(&bar_arrived[barrier], wave_size, __ATOMIC_ACQ_REL, __HIP_MEMORY_SCOPE_WORKGROUP);
+            uint64_t complete = (arrived / generation_size + 1) * generation_size;
+            if (wait)
+            {
+                while (__hip_atomic_load(&bar_arrived[barrier], __ATOMIC_ACQUIRE, __HIP_MEMORY_SCOPE_WORKGROUP) < complete)
+                    __builtin_amdgcn_s_sleep(1);
+            }
+        }
+        __builtin_amdgcn_wave_barrier();
+        __builtin_amdgcn_fence(__ATOMIC_ACQUIRE, "workgroup");
+    }
+
+    void FUNC(bar_sync_count)(uint32_t barrier, uint32_t count)
+    {
+        bar_count(barrier, count, true);
+    }
+
+    void FUNC(bar_arrive)(uint32_t barrier, uint32_t count)
+    {
+        bar_count(barrier, count, false);
+    }
+
     int32_t __ockl_wgred_and_i32(int32_t) __device__;
     int32_t __ockl_wgred_or_i32(int32_t) __device__;
 
@@ -165,6 +246,17 @@ extern "C"
     BAR_RED_IMPL(and);
     BAR_RED_IMPL(or);
 
+    int32_t __ockl_wgred_add_i32(int32_t) __device__;
+
+    // __ockl_wgred_add_i32 sums through a shared memory slot: every wave writes its partial sum
+    // after a barrier, and the total is read back after a second one, so the slot can be reused
+    // by the next reduction without a race
+    uint32_t FUNC(bar_red_popc_u32)(uint32_t barrier __attribute__((unused)), bool predicate, bool invert_predicate)
+    {
+        /* TODO: handle barrier */
+        return __ockl_wgred_add_i32(predicate ^ invert_predicate);
+    }
+
 
 typedef uint32_t ShflSyncResult __attribute__((ext_vector_type(2)));
 
@@ -0,0 +1,332 @@
+//! Stable entry point for compiling PTX text.
+//!
+//! ```no_run
+//! let ptx = std::fs::read_to_string("add.ptx").unwrap();
+//! let artifact = ptx::Compiler::new(ptx::Target::LlvmBitcode)
+//!     .opt_level(ptx::OptLevel::Default)
+//!     .compile(&ptx)
+//!     .unwrap();
+//! for kernel in artifact.kernels() {
+//!     println!("{}: {:?}", kernel.name, kernel.parameters);
+//! }
+//! ```
+
+use crate::pass;
+use ptx_parser as ast;
+use std::{fmt, path::PathBuf};
+
+/// Version of the translation pipeline, the git hash of the build. The same PTX text compiled
+/// with the same [`Compiler`] settings gives byte-identical output within one version, so it is
+/// the only part of a cache key which describes the compiler.
+pub const PIPELINE_VERSION: &str = env!("VERGEN_GIT_SHA");
+
+/// Format of [`Artifact::bytes`].
+///
+/// Compiling to a code object (HSACO) needs comgr, which itself depends on this crate for
+/// disassembly, so that last step is done by `comgr::compile_bitcode` on the
+/// [`Target::LlvmBitcode`] output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Target {
+    /// LLVM bitcode of the translated module, not yet linked with [`Artifact::linked_bitcode`]
+    LlvmBitcode,
+    /// Textual LLVM IR of the translated module
+    LlvmIr,
+    /// SPIR-V words (little-endian) of an OpenCL kernel module, for consumers other than AMD GPUs,
+    /// e.g. Intel Level Zero. Covers only a subset of PTX so far, the rest fails with
+    /// [`crate::TranslateError::UnsupportedSpirv`]
+    Spirv,
+}
+
+/// How much work the translation does beyond a literal lowering of every PTX instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum OptLevel {
+    /// Run only the passes required for correctness
+    None,
+    /// Run all the optional passes of [`crate::TranslateOptions`]
+    #[default]
+    Default,
+}
+
+/// Builder for a single PTX compilation.
+#[derive(Clone, Debug)]
+pub struct Compiler {
+    target: Target,
+    opt_level: OptLevel,
+    attributes: pass::Attributes,
+    unique_symbols: bool,
+    fp_contract: bool,
+    volatile_coherence: pass::VolatileCoherence,
+    link_overrides: pass::LinkOverrides,
+    entry_points: Option<Vec<String>>,
+    emulate_mma: bool,
+    dump_dir: Option<PathBuf>,
+}
+
+impl Compiler {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            opt_level: OptLevel::default(),
+            attributes: pass::Attributes { clock_rate: 0 },
+            unique_symbols: false,
+            fp_contract: true,
+            volatile_coherence: pass::VolatileCoherence::default(),
+            link_overrides: pass::LinkOverrides::default(),
+            entry_points: None,
+            emulate_mma: pass::TranslateOptions::default().emulate_mma,
+            dump_dir: pass::TranslateOptions::default().dump_dir,
+        }
+    }
+
+    pub fn opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Clock frequency in kHz of the target device
+    pub fn clock_rate(mut self, clock_rate: u32) -> Self {
+        self.attributes.clock_rate = clock_rate;
+        self
+    }
+
+    /// Append a hash of the module text to the names of functions and variables that are not
+    /// kernels, `.visible`, `.extern` or `.weak`, so that the bitcode of several modules
+    /// defining the same internal symbols can be linked together
+    pub fn unique_symbols(mut self, unique_symbols: bool) -> Self {
+        self.unique_symbols = unique_symbols;
+        self
+    }
+
+    /// Fuse float mul and add without rounding modifiers into fma, as ptxas does unless it is run
+    /// with `--fmad=false`. On by default, needs [`OptLevel::Default`]
+    pub fn fp_contract(mut self, fp_contract: bool) -> Self {
+        self.fp_contract = fp_contract;
+        self
+    }
+
+    /// How volatile loads and stores reach host memory, pick it with
+    /// [`crate::VolatileCoherence::for_arch`] for the device the module will run on
+    pub fn volatile_coherence(mut self, volatile_coherence: pass::VolatileCoherence) -> Self {
+        self.volatile_coherence = volatile_coherence;
+        self
+    }
+
+    /// Replace the module's declarations and definitions of function `name` with ZLUDA's
+    /// `__zluda_ptx_impl_<name>` from [`Artifact::linked_bitcode`]
+    pub fn link_override(mut self, name: impl Into<String>) -> Self {
+        self.link_overrides.add(name);
+        self
+    }
+
+    /// Translate only kernel `name`, the functions it calls and the variables it uses, instead of
+    /// every kernel of the module. Can be called for several kernels, [`Artifact::kernels`] still
+    /// lists all of them
+    pub fn entry_point(mut self, name: impl Into<String>) -> Self {
+        self.entry_points
+            .get_or_insert_with(Vec::new)
+            .push(name.into());
+        self
+    }
+
+    /// Expand `mma.sync.aligned.m16n8k8` with .f16 inputs into slow scalar code instead of
+    /// failing, defaults to ZLUDA_EMULATE_MMA=1. Other shapes and the wmma instructions are
+    /// rejected either way
+    pub fn emulate_mma(mut self, emulate_mma: bool) -> Self {
+        self.emulate_mma = emulate_mma;
+        self
+    }
+
+    /// Write the module after every pass to this directory, defaults to ZLUDA_DUMP_DIR
+    pub fn dump_dir(mut self, dump_dir: impl Into<PathBuf>) -> Self {
+        self.dump_dir = Some(dump_dir.into());
+        self
+    }
+
+    pub fn compile(&self, text: &str) -> Result<Artifact, CompileError> {
+        self.compile_with_names(text, &pass::NameInterner::default())
+    }
+
+    fn compile_with_names(
+        &self,
+        text: &str,
+        names: &pass::NameInterner,
+    ) -> Result<Artifact, CompileError> {
+        let ast = ast::parse_module_checked(text)
+            .map_err(|errors| CompileError::from_ptx_errors(text, errors))?;
+        let enabled = self.opt_level != OptLevel::None;
+        let options = pass::TranslateOptions {
+            if_convert_predicates: enabled,
+            infer_global_state_space: enabled,
+            promote_local_variables: enabled,
+            fuse_mul_add: self.fp_contract,
+            fold_constants: enabled,
+            deduplicate_values: enabled,
+            hoist_special_registers: enabled,
+            eliminate_dead_code: enabled,
+            scalarize_uniform_loads: enabled,
+            internalize_functions: enabled,
+            unique_symbols: self.unique_symbols,
+            volatile_coherence: self.volatile_coherence,
+            link_overrides: self.link_overrides.clone(),
+            entry_points: self.entry_points.clone(),
+            emulate_mma: self.emulate_mma,
+            dump_dir: self.dump_dir.clone(),
+        };
+        if self.target == Target::Spirv {
+            return self.compile_spirv(ast, options, names);
+        }
+        let mut module =
+            pass::to_llvm_module_with_names(ast, self.attributes.clone(), options, names)?;
+        let bytes = match self.target {
+            Target::LlvmBitcode => module.llvm_ir.write_bitcode_to_memory().to_vec(),
+            Target::LlvmIr => module.llvm_ir.print_module_to_string().to_bytes().to_vec(),
+            Target::Spirv => unreachable!(),
+        };
+        Ok(Artifact {
+            target: self.target,
+            bytes,
+            attributes_bitcode: module.attributes_ir.write_bitcode_to_memory().to_vec(),
+            correctly_rounded_div_sqrt: module.correctly_rounded_div_sqrt,
+            kernels: std::mem::take(&mut module.kernels),
+        })
+    }
+
+    fn compile_spirv<'input: 'a, 'a>(
+        &self,
+        ast: ast::Module<'input>,
+        options: pass::TranslateOptions,
+        names: &'a pass::NameInterner,
+    ) -> Result<Artifact, CompileError> {
+        let module = pass::to_spirv_module_with_names(ast, options, names)?;
+        Ok(Artifact {
+            target: self.target,
+            bytes: module
+                .words
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect(),
+            attributes_bitcode: Vec::new(),
+            correctly_rounded_div_sqrt: false,
+            kernels: module.kernels,
+        })
+    }
+
+    /// Compile several modules, one at a time, so at most one of them is being translated. Names
+    /// made up during translation (`%r0`, `%r1`... of parameterized registers) are kept once and
+    /// shared between the modules, they are freed when the batch is done. Results are in the
+    /// order of `texts`
+    pub fn compile_batch<'a>(
+        &self,
+        texts: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<Result<Artifact, CompileError>> {
+        let names = pass::NameInterner::default();
+        texts
+            .into_iter()
+            .map(|text| self.compile_with_names(text, &names))
+            .collect()
+    }
+}
+
+/// Result of [`Compiler::compile`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Artifact {
+    pub(crate) target: Target,
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) attributes_bitcode: Vec<u8>,
+    pub(crate) correctly_rounded_div_sqrt: bool,
+    pub(crate) kernels: Vec<pass::KernelInfo>,
+}
+
+impl Artifact {
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// The translated module in the format of [`Artifact::target`]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Bitcode of the module holding `__zluda_ptx_impl_attribute_*` globals, empty for
+    /// [`Target::Spirv`]
+    pub fn attributes_bitcode(&self) -> &[u8] {
+        &self.attributes_bitcode
+    }
+
+    /// Bitcode of the PTX runtime library, LLVM modules must be linked with it
+    pub fn linked_bitcode(&self) -> &'static [u8] {
+        pass::ZLUDA_PTX_IMPL
+    }
+
+    /// Module must be compiled with correctly rounded and denormal-preserving device library
+    /// controls
+    pub fn correctly_rounded_div_sqrt(&self) -> bool {
+        self.correctly_rounded_div_sqrt
+    }
+
+    /// Kernels (`.entry` functions) in declaration order, empty for a library module of only
+    /// functions and globals
+    pub fn kernels(&self) -> &[pass::KernelInfo] {
+        &self.kernels
+    }
+}
+
+/// Error returned by [`Compiler::compile`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CompileError {
+    /// The text is not valid PTX, one annotated message per error
+    Parse(Vec<String>),
+    /// The module is valid PTX, but uses something the translation does not support
+    Translate(pass::TranslateError),
+}
+
+impl CompileError {
+    fn from_ptx_errors<'input>(text: &'input str, errors: Vec<ast::PtxError<'input>>) -> Self {
+        CompileError::Parse(
+            errors
+                .iter()
+                .map(|err| {
+                    let msg = match err {
+                        ast::PtxError::UnrecognizedStatement(value)
+                        | ast::PtxError::UnrecognizedDirective(value) => value.to_string(),
+                        other => other.to_string(),
+                    };
+                    let msg = format!("PtxError::{}: {}", err.as_ref(), msg);
+                    match err.location(text) {
+                        Some(location) => location.annotate(&msg),
+                        None => msg,
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Parse(errors) => write!(f, "{}", errors.join("\n")),
+            CompileError::Translate(err) => {
+                write!(f, "TranslateError::{}: {}", err.inner().as_ref(), err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompileError::Parse(_) => None,
+            CompileError::Translate(err) => Some(err),
+        }
+    }
+}
+
+impl From<pass::TranslateError> for CompileError {
+    fn from(err: pass::TranslateError) -> Self {
+        CompileError::Translate(err)
+    }
+}
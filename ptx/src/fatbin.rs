@@ -0,0 +1,302 @@
+//! Extraction of PTX from CUDA fatbins.
+//!
+//! `nvcc -gencode` output is a fatbin container (a header followed by one file entry per PTX
+//! or SASS compilation target), optionally stored in the `.nv_fatbin` section of an ELF file.
+//! Unlike `dark_api::fatbin`, which walks `__cudaFatBinaryWrapper` structures in process memory,
+//! this module works on plain byte slices, so it can be used on files and on images passed to
+//! cuModuleLoadData.
+
+use goblin::elf::Elf;
+use std::borrow::Cow;
+
+const FATBIN_MAGIC: u32 = 0xBA55ED50;
+const FATBIN_VERSION: u16 = 1;
+const FATBIN_HEADER_SIZE: usize = 16;
+const FILE_HEADER_SIZE: usize = 64;
+const FILE_HEADER_VERSION: u16 = 0x101;
+const FILE_KIND_PTX: u16 = 1;
+const FILE_KIND_ELF: u16 = 2;
+const FLAG_COMPRESSED_LZ4: u64 = 0x2000;
+const FLAG_COMPRESSED_ZSTD: u64 = 0x8000;
+const MAX_DECOMPRESSED_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FatbinError {
+    #[error("not a fatbin or an ELF file")]
+    UnknownFormat,
+    #[error("fatbin is truncated")]
+    Truncated,
+    #[error("unsupported {what} version {version:#x}")]
+    UnsupportedVersion { what: &'static str, version: u32 },
+    #[error("failed to decompress fatbin entry")]
+    Decompression,
+    #[error("fatbin contains no PTX, only SASS")]
+    NoPtxAvailable,
+    #[error("PTX in fatbin is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    Ptx,
+    /// SASS cubin
+    Elf,
+    Unknown(u16),
+}
+
+/// Single file stored in a fatbin.
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<'a> {
+    pub kind: EntryKind,
+    /// Compute capability the entry was compiled for, e.g. 80 for sm_80
+    pub sm_version: u32,
+    flags: u64,
+    uncompressed_size: u64,
+    payload: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    /// Decompressed contents of the entry, without the trailing NUL padding
+    pub fn contents(&self) -> Result<Cow<'a, [u8]>, FatbinError> {
+        let mut contents = if self.flags & FLAG_COMPRESSED_LZ4 != 0 {
+            Cow::Owned(decompress_lz4(self.payload, self.uncompressed_size)?)
+        } else if self.flags & FLAG_COMPRESSED_ZSTD != 0 {
+            Cow::Owned(decompress_zstd(self.payload, self.uncompressed_size)?)
+        } else {
+            Cow::Borrowed(self.payload)
+        };
+        let len = contents
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map_or(0, |last| last + 1);
+        match &mut contents {
+            Cow::Borrowed(slice) => {
+                let full: &'a [u8] = *slice;
+                *slice = &full[..len]
+            }
+            Cow::Owned(vec) => vec.truncate(len),
+        }
+        Ok(contents)
+    }
+}
+
+/// All entries of all fatbins in `data`, which is either a fatbin or an ELF file with a
+/// `.nv_fatbin` section.
+pub fn entries(data: &[u8]) -> Result<Vec<Entry<'_>>, FatbinError> {
+    if data.starts_with(goblin::elf::header::ELFMAG) {
+        let elf = Elf::parse(data).map_err(|_| FatbinError::Truncated)?;
+        let section = elf
+            .section_headers
+            .iter()
+            .find(|section| elf.shdr_strtab.get_at(section.sh_name) == Some(".nv_fatbin"));
+        return match section {
+            Some(section) => {
+                let range = section.file_range().ok_or(FatbinError::Truncated)?;
+                fatbin_entries(data.get(range).ok_or(FatbinError::Truncated)?)
+            }
+            // Plain cubin
+            None => Err(FatbinError::NoPtxAvailable),
+        };
+    }
+    fatbin_entries(data)
+}
+
+/// Text of the PTX entry compiled for the newest compute capability.
+pub fn extract_ptx(data: &[u8]) -> Result<String, FatbinError> {
+    let entry = entries(data)?
+        .into_iter()
+        .filter(|entry| entry.kind == EntryKind::Ptx)
+        .max_by_key(|entry| entry.sm_version)
+        .ok_or(FatbinError::NoPtxAvailable)?;
+    String::from_utf8(entry.contents()?.into_owned()).map_err(|_| FatbinError::InvalidUtf8)
+}
+
+// A .nv_fatbin section holds several fatbins back to back, each one aligned to 8 bytes
+fn fatbin_entries(mut data: &[u8]) -> Result<Vec<Entry<'_>>, FatbinError> {
+    if read_u32(data, 0)? != FATBIN_MAGIC {
+        return Err(FatbinError::UnknownFormat);
+    }
+    let mut entries = Vec::new();
+    loop {
+        let version = read_u16(data, 4)?;
+        if version != FATBIN_VERSION {
+            return Err(FatbinError::UnsupportedVersion {
+                what: "fatbin",
+                version: version as u32,
+            });
+        }
+        let header_size = read_u16(data, 6)? as usize;
+        if header_size < FATBIN_HEADER_SIZE {
+            return Err(FatbinError::Truncated);
+        }
+        let files_size = to_usize(read_u64(data, 8)?)?;
+        let end = header_size
+            .checked_add(files_size)
+            .ok_or(FatbinError::Truncated)?;
+        let mut files = data.get(header_size..end).ok_or(FatbinError::Truncated)?;
+        while !files.is_empty() {
+            let (entry, rest) = file_entry(files)?;
+            entries.push(entry);
+            files = rest;
+        }
+        data = data.get(end.next_multiple_of(8)..).unwrap_or_default();
+        if read_u32(data, 0).ok() != Some(FATBIN_MAGIC) {
+            return Ok(entries);
+        }
+    }
+}
+
+fn file_entry(data: &[u8]) -> Result<(Entry<'_>, &[u8]), FatbinError> {
+    let kind = match read_u16(data, 0)? {
+        FILE_KIND_PTX => EntryKind::Ptx,
+        FILE_KIND_ELF => EntryKind::Elf,
+        kind => EntryKind::Unknown(kind),
+    };
+    let version = read_u16(data, 2)?;
+    if version != FILE_HEADER_VERSION {
+        return Err(FatbinError::UnsupportedVersion {
+            what: "fatbin file header",
+            version: version as u32,
+        });
+    }
+    let header_size = read_u32(data, 4)? as usize;
+    if header_size < FILE_HEADER_SIZE {
+        return Err(FatbinError::Truncated);
+    }
+    let padded_payload_size = read_u32(data, 8)? as usize;
+    let payload_size = read_u32(data, 16)? as usize;
+    let payload_end = header_size
+        .checked_add(payload_size)
+        .ok_or(FatbinError::Truncated)?;
+    let entry = Entry {
+        kind,
+        sm_version: read_u32(data, 28)?,
+        flags: read_u64(data, 40)?,
+        uncompressed_size: read_u64(data, 56)?,
+        payload: data
+            .get(header_size..payload_end)
+            .ok_or(FatbinError::Truncated)?,
+    };
+    let rest = data
+        .get(header_size + padded_payload_size.max(payload_size)..)
+        .ok_or(FatbinError::Truncated)?;
+    Ok((entry, rest))
+}
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], FatbinError> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(FatbinError::Truncated)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, FatbinError> {
+    read_bytes(data, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, FatbinError> {
+    read_bytes(data, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, FatbinError> {
+    read_bytes(data, offset).map(u64::from_le_bytes)
+}
+
+fn to_usize(value: u64) -> Result<usize, FatbinError> {
+    usize::try_from(value).map_err(|_| FatbinError::Truncated)
+}
+
+fn decompress_lz4(payload: &[u8], uncompressed_size: u64) -> Result<Vec<u8>, FatbinError> {
+    if uncompressed_size > MAX_DECOMPRESSED_SIZE {
+        return Err(FatbinError::Decompression);
+    }
+    let mut result = vec![0u8; uncompressed_size as usize];
+    let written = unsafe {
+        lz4_sys::LZ4_decompress_safe(
+            payload.as_ptr().cast(),
+            result.as_mut_ptr().cast(),
+            payload.len() as _,
+            result.len() as _,
+        )
+    };
+    if written < 0 {
+        return Err(FatbinError::Decompression);
+    }
+    result.truncate(written as usize);
+    Ok(result)
+}
+
+fn decompress_zstd(payload: &[u8], uncompressed_size: u64) -> Result<Vec<u8>, FatbinError> {
+    if uncompressed_size > MAX_DECOMPRESSED_SIZE {
+        return Err(FatbinError::Decompression);
+    }
+    let mut result = Vec::with_capacity(uncompressed_size as usize);
+    zstd_safe::decompress(&mut result, payload).map_err(|_| FatbinError::Decompression)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADD: &str = include_str!("test/spirv_run/add.ptx");
+
+    #[test]
+    fn uncompressed_ptx() {
+        let fatbin = include_bytes!("test/fatbin/add.fatbin");
+        assert_eq!(extract_ptx(fatbin).unwrap(), ADD);
+    }
+
+    #[test]
+    fn lz4_compressed_ptx() {
+        let fatbin = include_bytes!("test/fatbin/add_lz4.fatbin");
+        assert_eq!(extract_ptx(fatbin).unwrap(), ADD);
+    }
+
+    #[test]
+    fn zstd_compressed_ptx() {
+        let fatbin = include_bytes!("test/fatbin/add_zstd.fatbin");
+        assert_eq!(extract_ptx(fatbin).unwrap(), ADD);
+    }
+
+    #[test]
+    fn newest_ptx_is_selected() {
+        let fatbin = include_bytes!("test/fatbin/multi_arch.fatbin");
+        let entries = entries(fatbin).unwrap();
+        let kinds = entries
+            .iter()
+            .map(|entry| (entry.kind, entry.sm_version))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (EntryKind::Elf, 52),
+                (EntryKind::Ptx, 52),
+                (EntryKind::Ptx, 80),
+                (EntryKind::Elf, 80)
+            ]
+        );
+        assert!(extract_ptx(fatbin).unwrap().contains(".target sm_80"));
+    }
+
+    #[test]
+    fn elf_wrapped_fatbin() {
+        let elf = include_bytes!("test/fatbin/add_nv_fatbin.o");
+        assert_eq!(extract_ptx(elf).unwrap(), ADD);
+    }
+
+    #[test]
+    fn sass_only_fatbin() {
+        let fatbin = include_bytes!("test/fatbin/sass_only.fatbin");
+        assert_eq!(extract_ptx(fatbin), Err(FatbinError::NoPtxAvailable));
+    }
+
+    #[test]
+    fn truncated_fatbin() {
+        let fatbin = include_bytes!("test/fatbin/add.fatbin");
+        for len in [0, 8, FATBIN_HEADER_SIZE + 10, fatbin.len() - 1] {
+            assert_eq!(extract_ptx(&fatbin[..len]), Err(FatbinError::Truncated));
+        }
+        assert_eq!(extract_ptx(ADD.as_bytes()), Err(FatbinError::UnknownFormat));
+    }
+}
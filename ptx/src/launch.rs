@@ -0,0 +1,161 @@
+use crate::pass::KernelInfo;
+use std::fmt;
+
+/// Limits of the device a kernel is launched on. The defaults are those of current NVIDIA GPUs
+/// without opt-in shared memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceLimits {
+    pub max_threads_per_block: u32,
+    pub max_block_dim: (u32, u32, u32),
+    pub max_grid_dim: (u32, u32, u32),
+    /// Bytes of shared memory, static and dynamic together, available to a block
+    pub max_shared_memory_per_block: usize,
+}
+
+impl Default for DeviceLimits {
+    fn default() -> Self {
+        Self {
+            max_threads_per_block: 1024,
+            max_block_dim: (1024, 1024, 64),
+            max_grid_dim: (i32::MAX as u32, 65535, 65535),
+            max_shared_memory_per_block: 48 * 1024,
+        }
+    }
+}
+
+/// Launch that CUDA rejects. CUDA reports every one of them as `CUDA_ERROR_INVALID_VALUE`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LaunchError {
+    /// A dimension of the grid or of the block is 0
+    EmptyLaunch {
+        grid: (u32, u32, u32),
+        block: (u32, u32, u32),
+    },
+    /// Grid is larger than the device allows in some dimension
+    GridTooLarge {
+        grid: (u32, u32, u32),
+        max_grid_dim: (u32, u32, u32),
+    },
+    /// Block is larger than the device allows in some dimension or in total
+    BlockTooLarge {
+        block: (u32, u32, u32),
+        max_block_dim: (u32, u32, u32),
+        max_threads_per_block: u32,
+    },
+    /// Block has more threads than the kernel's `.maxntid`. Like in CUDA only the total is
+    /// checked, the shape of the block does not matter
+    MaxNtidExceeded {
+        block: (u32, u32, u32),
+        maxntid: (u32, u32, u32),
+    },
+    /// Block is not exactly the kernel's `.reqntid`
+    ReqNtidMismatch {
+        block: (u32, u32, u32),
+        reqntid: (u32, u32, u32),
+    },
+    /// Static shared memory of the kernel and the dynamic shared memory of the launch don't fit
+    /// in the shared memory of the device
+    SharedMemoryTooLarge {
+        static_shared_memory: usize,
+        dynamic_shared_memory: usize,
+        max_shared_memory_per_block: usize,
+    },
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::EmptyLaunch { grid, block } => {
+                write!(f, "Grid {grid:?} or block {block:?} has no threads")
+            }
+            LaunchError::GridTooLarge { grid, max_grid_dim } => {
+                write!(f, "Grid {grid:?} is larger than the device limit {max_grid_dim:?}")
+            }
+            LaunchError::BlockTooLarge {
+                block,
+                max_block_dim,
+                max_threads_per_block,
+            } => write!(
+                f,
+                "Block {block:?} is larger than the device limit {max_block_dim:?} or {max_threads_per_block} threads"
+            ),
+            LaunchError::MaxNtidExceeded { block, maxntid } => write!(
+                f,
+                "Block {block:?} has {} threads, .maxntid {maxntid:?} allows {}",
+                threads(*block),
+                threads(*maxntid)
+            ),
+            LaunchError::ReqNtidMismatch { block, reqntid } => {
+                write!(f, "Block {block:?} is not the required .reqntid {reqntid:?}")
+            }
+            LaunchError::SharedMemoryTooLarge {
+                static_shared_memory,
+                dynamic_shared_memory,
+                max_shared_memory_per_block,
+            } => write!(
+                f,
+                "{static_shared_memory} bytes of static and {dynamic_shared_memory} bytes of dynamic shared memory are more than the device limit of {max_shared_memory_per_block} bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LaunchError {}
+
+/// Checks a launch of `kernel` the way `cuLaunchKernel` does before anything runs on the device:
+/// grid and block against the device limits, block against the kernel's `.maxntid` and
+/// `.reqntid`, and shared memory against the device limit
+pub fn validate_launch(
+    kernel: &KernelInfo,
+    grid: (u32, u32, u32),
+    block: (u32, u32, u32),
+    dynamic_shared_memory: usize,
+    limits: &DeviceLimits,
+) -> Result<(), LaunchError> {
+    if threads(grid) == 0 || threads(block) == 0 {
+        return Err(LaunchError::EmptyLaunch { grid, block });
+    }
+    if !fits(grid, limits.max_grid_dim) {
+        return Err(LaunchError::GridTooLarge {
+            grid,
+            max_grid_dim: limits.max_grid_dim,
+        });
+    }
+    if !fits(block, limits.max_block_dim) || threads(block) > limits.max_threads_per_block as u64 {
+        return Err(LaunchError::BlockTooLarge {
+            block,
+            max_block_dim: limits.max_block_dim,
+            max_threads_per_block: limits.max_threads_per_block,
+        });
+    }
+    if let Some(maxntid) = kernel.max_ntid() {
+        if threads(block) > threads(maxntid) {
+            return Err(LaunchError::MaxNtidExceeded { block, maxntid });
+        }
+    }
+    if let Some(reqntid) = kernel.req_ntid() {
+        if block != reqntid {
+            return Err(LaunchError::ReqNtidMismatch { block, reqntid });
+        }
+    }
+    if kernel
+        .static_shared_memory
+        .saturating_add(dynamic_shared_memory)
+        > limits.max_shared_memory_per_block
+    {
+        return Err(LaunchError::SharedMemoryTooLarge {
+            static_shared_memory: kernel.static_shared_memory,
+            dynamic_shared_memory,
+            max_shared_memory_per_block: limits.max_shared_memory_per_block,
+        });
+    }
+    Ok(())
+}
+
+fn threads((x, y, z): (u32, u32, u32)) -> u64 {
+    x as u64 * y as u64 * z as u64
+}
+
+fn fits(dim: (u32, u32, u32), max: (u32, u32, u32)) -> bool {
+    dim.0 <= max.0 && dim.1 <= max.1 && dim.2 <= max.2
+}
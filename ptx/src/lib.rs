@@ -1,8 +1,44 @@
+mod compiler;
+pub mod fatbin;
+mod launch;
+mod logging;
 pub(crate) mod pass;
+mod serialize;
 #[cfg(test)]
 mod test;
 
+pub use compiler::Artifact;
+pub use compiler::CompileError;
+pub use compiler::Compiler;
+pub use compiler::OptLevel;
+pub use compiler::Target;
+pub use compiler::PIPELINE_VERSION;
+pub use launch::validate_launch;
+pub use launch::DeviceLimits;
+pub use launch::LaunchError;
+pub use logging::init_logger;
+pub use serialize::DeserializeError;
+
+// Implementation details of `Compiler`, kept public for the runtime crates and not covered by
+// any stability guarantees
+#[doc(hidden)]
 pub use pass::llvm::bitcode_to_ir;
+#[doc(hidden)]
+pub use pass::llvm::AMDHSA_CODE_OBJECT_VERSION;
+#[doc(hidden)]
 pub use pass::to_llvm_module;
+#[doc(hidden)]
+pub use pass::to_llvm_module_with_options;
+#[doc(hidden)]
+pub use pass::to_spirv_module_with_options;
 pub use pass::Attributes;
+pub use pass::KernelInfo;
+pub use pass::LinkOverrides;
+pub use pass::ParameterInfo;
+pub use pass::PassStatistics;
+pub use pass::PassTrace;
+pub use pass::SpirvModule;
 pub use pass::TranslateError;
+pub use pass::TranslateOptions;
+pub use pass::VolatileCoherence;
+pub use ptx_parser::TuningDirective;
@@ -0,0 +1,166 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{env, sync::OnceLock};
+
+// Logging of the translation through the `log` crate, targets are module paths:
+// * ptx::pass - info, once when the debug info of a -G build is discarded
+// * ptx::pass::trace - debug, every pass with its statement counts
+// * ptx::pass::llvm::emit - trace, LLVM instruction emitted for every PTX instruction
+// * ptx::pass::replace_instructions_with_functions - trace for instructions lowered to ptx_impl
+//   calls, warn for emulated instructions
+// * comgr - info, every toolchain invocation with its options
+// Release builds compile trace messages away (log's release_max_level_debug feature)
+
+const LOG_VAR: &str = "ZLUDA_LOG";
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Logs to stderr as configured by the `ZLUDA_LOG` environment variable, which is a comma
+/// separated list of `level` and `target=level` directives, e.g. `warn,ptx::pass=trace`.
+/// Does nothing if `ZLUDA_LOG` is not set or another logger is already installed.
+pub fn init_logger() {
+    if let Ok(spec) = env::var(LOG_VAR) {
+        install(Filter::parse(&spec));
+    }
+}
+
+fn install(filter: Filter) -> &'static Logger {
+    let mut installed = false;
+    let logger = LOGGER.get_or_init(|| {
+        installed = true;
+        Logger { filter }
+    });
+    if installed && log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.filter.max_level());
+    }
+    logger
+}
+
+struct Logger {
+    filter: Filter,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        #[cfg(test)]
+        capture::push(record);
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "[ZLUDA {} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// The most specific matching directive wins, targets match whole path segments
+#[derive(Debug, PartialEq)]
+struct Filter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn parse(spec: &str) -> Self {
+        let mut filter = Filter {
+            default: LevelFilter::Off,
+            targets: Vec::new(),
+        };
+        for directive in spec.split(',').map(str::trim) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    if let Ok(level) = level.trim().parse() {
+                        filter.targets.push((target.trim().to_string(), level));
+                    }
+                }
+                // A bare target enables all levels, like in env_logger
+                None => match directive.parse() {
+                    Ok(level) => filter.default = level,
+                    Err(_) if !directive.is_empty() => filter
+                        .targets
+                        .push((directive.to_string(), LevelFilter::Trace)),
+                    Err(_) => {}
+                },
+            }
+        }
+        filter
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+// Tests capture the messages of their own thread regardless of ZLUDA_LOG
+#[cfg(test)]
+pub(crate) mod capture {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+    }
+
+    pub(super) fn push(record: &Record) {
+        CAPTURED.with_borrow_mut(|captured| {
+            if let Some(captured) = captured {
+                captured.push(format!(
+                    "{} {}: {}",
+                    record.level(),
+                    record.target(),
+                    record.args()
+                ));
+            }
+        });
+    }
+
+    pub(crate) fn run<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+        let logger = install(Filter::parse(&env::var(LOG_VAR).unwrap_or_default()));
+        if log::logger() as *const dyn Log as *const () != logger as *const Logger as *const () {
+            panic!("Another logger is installed");
+        }
+        log::set_max_level(LevelFilter::Trace);
+        CAPTURED.set(Some(Vec::new()));
+        let result = f();
+        (result, CAPTURED.take().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_picks_most_specific_target() {
+        let filter = Filter::parse("warn, ptx::pass=debug, ptx::pass::llvm=trace, comgr");
+        assert_eq!(filter.level("zluda::impl"), LevelFilter::Warn);
+        assert_eq!(filter.level("ptx::pass::trace"), LevelFilter::Debug);
+        assert_eq!(filter.level("ptx::pass::llvm::emit"), LevelFilter::Trace);
+        assert_eq!(filter.level("ptx::passes"), LevelFilter::Warn);
+        assert_eq!(filter.level("comgr"), LevelFilter::Trace);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        assert_eq!(Filter::parse("").max_level(), LevelFilter::Off);
+    }
+}
@@ -0,0 +1,229 @@
+use super::*;
+
+// Rejects register operands whose declared type doesn't agree with the type the instruction
+// expects, before the later passes insert conversions or emit LLVM IR for them. Expected types and
+// state spaces of the operands come from the instruction definitions in ptx_parser, declared types
+// from the resolver. Following the PTX type checking rules an operand is accepted if:
+//  * it has the same size as the expected type, and
+//  * predicates are used only as predicates, and
+//  * .b types match any type, otherwise float types match only float types and signed and
+//    unsigned types match each other.
+// ld, st and cvt relax the check for their data operand, we leave them alone. Vector members
+// (`%v.x`, `%v.r`) must name a component of a register declared with a vector type, their type is
+// checked like the type of a register, except for special registers, which legacy PTX reads with
+// 16-bit instructions. Other operands (variables used as addresses and vector
+// packs) are converted by insert_implicit_conversions2
+pub(super) fn run<'input>(
+    resolver: &GlobalStringIdentResolver2<'input>,
+    sreg_map: &SpecialRegistersMap2,
+    text: &str,
+    directives: &[NormalizedDirective2],
+) -> Result<(), TranslateError> {
+    for directive in directives {
+        let body = match directive {
+            Directive2::Method(Function2 {
+                body: Some(body), ..
+            }) => body,
+            _ => continue,
+        };
+        for statement in body {
+            if let Statement::Instruction((predicate, instruction, span)) = statement {
+                run_instruction(resolver, sreg_map, predicate, instruction)
+                    .map_err(|mismatch| mismatch.into_error(text, *span).at(*span))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Position 0 is the guard predicate, operands are numbered from 1 in the order of the instruction
+// arguments
+enum OperandError {
+    Mismatch {
+        operand: usize,
+        name: String,
+        expected: ast::Type,
+        actual: ast::Type,
+    },
+    NoComponent {
+        name: String,
+        type_: ast::Type,
+        member: u8,
+    },
+}
+
+impl OperandError {
+    fn into_error(self, text: &str, span: ast::Span) -> TranslateError {
+        match self {
+            OperandError::Mismatch {
+                operand,
+                name,
+                expected,
+                actual,
+            } => TranslateError::OperandTypeMismatch {
+                instruction: opcode(text, span),
+                operand,
+                name,
+                expected: expected.to_string(),
+                actual: actual.to_string(),
+            },
+            OperandError::NoComponent {
+                name,
+                type_,
+                member,
+            } => error_malformed_operand(
+                opcode(text, span),
+                format!(
+                    "\"{}\" has type {}, it has no component .{}",
+                    name,
+                    type_,
+                    component_name(member)
+                ),
+            ),
+        }
+    }
+}
+
+fn run_instruction<'input>(
+    resolver: &GlobalStringIdentResolver2<'input>,
+    sreg_map: &SpecialRegistersMap2,
+    predicate: &Option<ast::PredAt<SpirvWord>>,
+    instruction: &ast::Instruction<ast::ParsedOperand<SpirvWord>>,
+) -> Result<(), OperandError> {
+    if let Some(predicate) = predicate {
+        check_register(
+            resolver,
+            0,
+            predicate.label,
+            &ast::Type::Scalar(ast::ScalarType::Pred),
+        )?;
+    }
+    let mut operand = 0;
+    ast::visit(
+        instruction,
+        &mut |argument: &ast::ParsedOperand<SpirvWord>,
+              type_space: Option<(&ast::Type, ast::StateSpace)>,
+              _is_dst: bool,
+              relaxed_type_check: bool| {
+            operand += 1;
+            match (argument, type_space, relaxed_type_check) {
+                (ast::ParsedOperand::Reg(reg), Some((expected, ast::StateSpace::Reg)), false) => {
+                    check_register(resolver, operand, *reg, expected)
+                }
+                (ast::ParsedOperand::VecMember(reg, member), type_space, relaxed) => {
+                    let expected = match type_space {
+                        Some((expected, ast::StateSpace::Reg))
+                            if !relaxed && sreg_map.get(*reg).is_none() =>
+                        {
+                            Some(expected)
+                        }
+                        _ => None,
+                    };
+                    check_member(resolver, operand, *reg, *member, expected)
+                }
+                _ => Ok(()),
+            }
+        },
+    )
+}
+
+fn check_register<'input>(
+    resolver: &GlobalStringIdentResolver2<'input>,
+    operand: usize,
+    reg: SpirvWord,
+    expected: &ast::Type,
+) -> Result<(), OperandError> {
+    let entry = match resolver.ident_map.get(&reg) {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+    match &entry.type_space {
+        Some((actual, ast::StateSpace::Reg)) if !is_compatible(expected, actual) => {
+            Err(OperandError::Mismatch {
+                operand,
+                name: entry.name.as_deref().unwrap_or_default().to_string(),
+                expected: expected.clone(),
+                actual: actual.clone(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_member<'input>(
+    resolver: &GlobalStringIdentResolver2<'input>,
+    operand: usize,
+    reg: SpirvWord,
+    member: u8,
+    expected: Option<&ast::Type>,
+) -> Result<(), OperandError> {
+    let entry = match resolver.ident_map.get(&reg) {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+    let name = entry.name.as_deref().unwrap_or_default();
+    match &entry.type_space {
+        Some((ast::Type::Vector(width, scalar), ast::StateSpace::Reg)) if member < *width => {
+            let actual = ast::Type::Scalar(*scalar);
+            match expected {
+                Some(expected) if !is_compatible(expected, &actual) => {
+                    Err(OperandError::Mismatch {
+                        operand,
+                        name: format!("{}.{}", name, component_name(member)),
+                        expected: expected.clone(),
+                        actual,
+                    })
+                }
+                _ => Ok(()),
+            }
+        }
+        Some((type_, ast::StateSpace::Reg)) => Err(OperandError::NoComponent {
+            name: name.to_string(),
+            type_: type_.clone(),
+            member,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn is_compatible(expected: &ast::Type, actual: &ast::Type) -> bool {
+    if expected.layout().size() != actual.layout().size() {
+        return false;
+    }
+    let (expected, actual) = match (expected, actual) {
+        (ast::Type::Scalar(expected), ast::Type::Scalar(actual)) => (expected, actual),
+        _ => return true,
+    };
+    match (expected.kind(), actual.kind()) {
+        (ast::ScalarKind::Pred, ast::ScalarKind::Pred) => true,
+        (ast::ScalarKind::Pred, _) | (_, ast::ScalarKind::Pred) => false,
+        (ast::ScalarKind::Bit, _) | (_, ast::ScalarKind::Bit) => true,
+        (ast::ScalarKind::Float, actual) | (actual, ast::ScalarKind::Float) => {
+            actual == ast::ScalarKind::Float
+        }
+        (ast::ScalarKind::Signed | ast::ScalarKind::Unsigned, _) => true,
+    }
+}
+
+// Components are printed the way ptx_parser prints them, `.r`, `.g`, `.b` and `.a` are the same
+// components as `.x`, `.y`, `.z` and `.w`
+fn component_name(member: u8) -> &'static str {
+    match member {
+        0 => "x",
+        1 => "y",
+        2 => "z",
+        3 => "w",
+        _ => "?",
+    }
+}
+
+// Instruction with its modifiers as written in the source, e.g. `add.u32`. The instruction
+// definitions are not printable
+fn opcode(text: &str, span: ast::Span) -> String {
+    let mut tokens = text
+        .get(span.start..span.end)
+        .unwrap_or_default()
+        .split(|c: char| c.is_whitespace() || c == ';')
+        .filter(|token| !token.is_empty() && !token.starts_with('@'));
+    tokens.next().unwrap_or("instruction").to_string()
+}
@@ -0,0 +1,164 @@
+use super::*;
+use rustc_hash::FxHashSet;
+
+// Size of the parameter buffer of a kernel, CUDA 12.1 raised it from 4 KB on Volta and newer GPUs
+const MAX_PARAMETERS_SIZE: usize = 32764;
+
+// Collects launch-relevant information about every kernel. This runs on the parsed module
+// before identifiers are normalized, so that names and parameter order match the PTX text.
+// Module-scope .shared and .const variables are counted for every kernel, we don't track
+// which kernel actually references them.
+// A parameter is considered a pointer if the value loaded from it is used as an address of
+// ld, st, atom or cvta. This is what the runtime needs to build kernarg buffers and matches
+// what the infer_global_state_space pass assumes about kernel arguments. Parameters declared
+// with `.ptr` are pointers even if the kernel never dereferences them.
+// Kernels whose parameters don't fit in the parameter buffer are rejected
+pub(super) fn run(ast: &ast::Module) -> Result<Vec<KernelInfo>, TranslateError> {
+    let mut module_memory = MemoryUsage::default();
+    for directive in ast.directives.iter() {
+        if let ast::Directive::Variable(_, var, _) = directive {
+            module_memory.add(var, 1);
+        }
+    }
+    ast.directives
+        .iter()
+        .filter_map(|directive| match directive {
+            ast::Directive::Method(_, method) => match method.func_directive.name {
+                ast::MethodName::Kernel(name) => Some(
+                    run_kernel(&module_memory, name, method).map_err(|err| err.at(method.span)),
+                ),
+                ast::MethodName::Func(_) => None,
+            },
+            ast::Directive::Variable(..) => None,
+        })
+        .collect()
+}
+
+fn run_kernel<'input>(
+    module_memory: &MemoryUsage,
+    name: &'input str,
+    method: &ast::Function<'input, &'input str, ast::Statement<ast::ParsedOperand<&'input str>>>,
+) -> Result<KernelInfo, TranslateError> {
+    let mut memory = module_memory.clone();
+    let mut param_loads = FxHashMap::default();
+    let mut addresses = FxHashSet::default();
+    if let Some(body) = method.body.as_ref() {
+        collect_statements(&mut memory, &mut param_loads, &mut addresses, body);
+    }
+    let pointer_params = param_loads
+        .into_iter()
+        .filter_map(|(reg, param)| addresses.contains(&reg).then_some(param))
+        .collect::<FxHashSet<_>>();
+    let parameters = method
+        .func_directive
+        .input_arguments
+        .iter()
+        .map(|param| {
+            let layout = param.v_type.layout();
+            ParameterInfo {
+                size: layout.size(),
+                align: param.align.map_or(layout.align(), |align| align as usize),
+                is_pointer: param.pointer.is_some()
+                    || (layout.size() == 8 && pointer_params.contains(param.name)),
+                pointee_align: param
+                    .pointer
+                    .and_then(|pointer| pointer.align)
+                    .map(|align| align as usize),
+            }
+        })
+        .collect();
+    let info = KernelInfo {
+        name: name.to_string(),
+        parameters,
+        static_shared_memory: memory.static_shared,
+        dynamic_shared_memory: memory.dynamic_shared,
+        local_memory: memory.local,
+        constant_memory: memory.constant,
+        tuning: method.tuning.clone(),
+    };
+    let size = info.parameters_size();
+    if size > MAX_PARAMETERS_SIZE {
+        return Err(TranslateError::KernelParametersTooLarge {
+            kernel: info.name,
+            size,
+            limit: MAX_PARAMETERS_SIZE,
+        });
+    }
+    Ok(info)
+}
+
+fn collect_statements<'input>(
+    memory: &mut MemoryUsage,
+    param_loads: &mut FxHashMap<&'input str, &'input str>,
+    addresses: &mut FxHashSet<&'input str>,
+    statements: &[ast::Statement<ast::ParsedOperand<&'input str>>],
+) {
+    for statement in statements {
+        match statement {
+            ast::Statement::Variable(multi_var, _) => {
+                memory.add(&multi_var.var, multi_var.count.unwrap_or(1) as usize)
+            }
+            ast::Statement::Block(block) => {
+                collect_statements(memory, param_loads, addresses, block)
+            }
+            ast::Statement::Instruction(_, instruction, _) => match instruction {
+                ast::Instruction::Ld { data, arguments } => {
+                    if data.state_space == ast::StateSpace::Param {
+                        if let (ast::ParsedOperand::Reg(dst), Some(param)) =
+                            (&arguments.dst, address_base(&arguments.src))
+                        {
+                            param_loads.insert(*dst, param);
+                        }
+                    } else {
+                        addresses.extend(address_base(&arguments.src));
+                    }
+                }
+                ast::Instruction::St { arguments, .. } => {
+                    addresses.extend(address_base(&arguments.src1))
+                }
+                ast::Instruction::Atom { arguments, .. } => {
+                    addresses.extend(address_base(&arguments.src1))
+                }
+                ast::Instruction::AtomCas { arguments, .. } => {
+                    addresses.extend(address_base(&arguments.src1))
+                }
+                ast::Instruction::Cvta { arguments, .. } => {
+                    addresses.extend(address_base(&arguments.src))
+                }
+                _ => {}
+            },
+            ast::Statement::Label(..) | ast::Statement::Pragma(..) => {}
+        }
+    }
+}
+
+fn address_base<'input>(operand: &ast::ParsedOperand<&'input str>) -> Option<&'input str> {
+    match operand {
+        ast::ParsedOperand::Reg(reg) | ast::ParsedOperand::RegOffset(reg, _) => Some(*reg),
+        _ => None,
+    }
+}
+
+#[derive(Default, Clone)]
+struct MemoryUsage {
+    static_shared: usize,
+    dynamic_shared: bool,
+    local: usize,
+    constant: usize,
+}
+
+impl MemoryUsage {
+    fn add(&mut self, var: &ast::Variable<&str>, count: usize) {
+        let size = var.v_type.layout().size() * count;
+        match var.state_space {
+            // Unsized arrays (`.extern .shared .b8 x[]`) are sized at launch
+            ast::StateSpace::Shared if matches!(var.v_type, ast::Type::Array(..)) && size == 0 => {
+                self.dynamic_shared = true
+            }
+            ast::StateSpace::Shared => self.static_shared += size,
+            ast::StateSpace::Local => self.local += size,
+            ast::StateSpace::Const => self.constant += size,
+            _ => {}
+        }
+    }
+}
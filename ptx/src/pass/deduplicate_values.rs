@@ -0,0 +1,155 @@
+use super::*;
+
+// The expansion passes create a fresh constant for every immediate operand and a fresh implicit
+// conversion for every operand whose type doesn't match, so a loop body unrolled 16 times carries
+// 16 copies of the same constant and 16 copies of the same conversion.
+// This pass keeps the first occurrence and replaces uses of the later copies with it:
+// * Constants are deduplicated in the whole function. They are emitted as LLVM constants, so the
+//   first occurrence doesn't have to dominate the uses of the copies
+// * Implicit conversions are deduplicated within a basic block. They only read their source
+//   register, never memory, so the only thing that can invalidate a conversion is a redefinition
+//   of its source
+// A statement is only ever replaced if its destination is defined exactly once
+pub(super) fn run(
+    statistics: &mut PassStatistics,
+    directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
+) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
+    run_directives_with_statistics(statistics, directives, run_directive)
+}
+
+fn run_directive(
+    statistics: &mut PassStatistics,
+    directive: Directive2<ast::Instruction<SpirvWord>, SpirvWord>,
+) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
+    Ok(match directive {
+        var @ Directive2::Variable(..) => var,
+        Directive2::Method(mut method) => {
+            // Arguments have a value on entry, so for our purposes they are defined once more
+            let arguments = method
+                .input_arguments
+                .iter()
+                .chain(method.return_arguments.iter())
+                .map(|arg| arg.name)
+                .collect::<Vec<_>>();
+            method.body = method
+                .body
+                .map(|statements| run_statements(statistics, &arguments, statements))
+                .transpose()
+                .map_err(|err| err.at(method.span))?;
+            Directive2::Method(method)
+        }
+    })
+}
+
+fn run_statements(
+    statistics: &mut PassStatistics,
+    arguments: &[SpirvWord],
+    statements: Vec<ExpandedStatement>,
+) -> Result<Vec<ExpandedStatement>, TranslateError> {
+    let mut definitions = FxHashMap::<_, usize>::default();
+    for argument in arguments {
+        *definitions.entry(*argument).or_default() += 1;
+    }
+    let statements = statements
+        .into_iter()
+        .map(|statement| {
+            statement.visit_map(&mut |ident: SpirvWord,
+                                      _: Option<(&ast::Type, ast::StateSpace)>,
+                                      is_dst: bool,
+                                      _: bool| {
+                if is_dst {
+                    *definitions.entry(ident).or_default() += 1;
+                }
+                Ok::<_, TranslateError>(ident)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let is_single_definition = |ident: &SpirvWord| definitions.get(ident) == Some(&1);
+    let mut substitutions = FxHashMap::default();
+    let mut constants = FxHashMap::default();
+    // State of the current basic block
+    let mut conversions = FxHashMap::default();
+    let mut result = Vec::with_capacity(statements.len());
+    for statement in statements {
+        // Earlier copies might be inputs of this statement, e.g. a conversion of a constant
+        let statement =
+            statement.visit_map(&mut |ident: SpirvWord,
+                                       _: Option<(&ast::Type, ast::StateSpace)>,
+                                       is_dst: bool,
+                                       _: bool| {
+                Ok::<_, TranslateError>(if is_dst {
+                    ident
+                } else {
+                    substitutions.get(&ident).copied().unwrap_or(ident)
+                })
+            })?;
+        match &statement {
+            Statement::Label(_) => conversions.clear(),
+            Statement::Constant(ConstantDefinition { dst, typ, value })
+                if is_single_definition(dst) =>
+            {
+                match constants.entry((*typ, ConstantKey::new(*value))) {
+                    hash_map::Entry::Occupied(first) => {
+                        substitutions.insert(*dst, *first.get());
+                        statistics.deduplicated_values += 1;
+                        continue;
+                    }
+                    hash_map::Entry::Vacant(entry) => {
+                        entry.insert(*dst);
+                    }
+                }
+            }
+            Statement::Conversion(ImplicitConversion {
+                src,
+                dst,
+                from_type,
+                to_type,
+                from_space,
+                to_space,
+                kind,
+            }) if is_single_definition(dst) && definitions.get(src).copied().unwrap_or(0) <= 1 => {
+                let key = (
+                    *src,
+                    from_type.clone(),
+                    to_type.clone(),
+                    *from_space,
+                    *to_space,
+                    kind.clone(),
+                );
+                match conversions.entry(key) {
+                    hash_map::Entry::Occupied(first) => {
+                        substitutions.insert(*dst, *first.get());
+                        statistics.deduplicated_values += 1;
+                        continue;
+                    }
+                    hash_map::Entry::Vacant(entry) => {
+                        entry.insert(*dst);
+                    }
+                }
+            }
+            _ => {}
+        }
+        result.push(statement);
+    }
+    Ok(result)
+}
+
+// ImmediateValue can hold floats, so we compare the bits. Signed and unsigned integers with
+// the same bits are emitted as the same constant
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Integer(u64),
+    F32(u32),
+    F64(u64),
+}
+
+impl ConstantKey {
+    fn new(value: ast::ImmediateValue) -> Self {
+        match value {
+            ast::ImmediateValue::U64(x) => ConstantKey::Integer(x),
+            ast::ImmediateValue::S64(x) => ConstantKey::Integer(x as u64),
+            ast::ImmediateValue::F32(x) => ConstantKey::F32(x.to_bits()),
+            ast::ImmediateValue::F64(x) => ConstantKey::F64(x.to_bits()),
+        }
+    }
+}
@@ -1,5 +1,14 @@
 use super::*;
+use rustc_hash::FxHashSet;
 
+// Turns .param arguments of functions into .reg arguments, values of .param variables are passed
+// through local copies accessed with ld.param/st.param, except for return values:
+// * Scalar and vector .param return values are kept in registers. In the callee `st.param` to
+//   the return value becomes a `mov` to the LLVM return value and in the caller `ld.param` of the
+//   call result becomes a `mov` from the value returned by the call. They can only be accessed
+//   whole (`[retval]` or `[retval+0]`), anything else is an UnsupportedParamAccess error
+// * Byte array return values (structs returned by value) are accessed at offsets, so they still
+//   go through a local copy
 pub(super) fn run<'a, 'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
     directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
@@ -16,7 +25,10 @@ fn run_directive<'input>(
 ) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
     Ok(match directive {
         var @ Directive2::Variable(..) => var,
-        Directive2::Method(method) => Directive2::Method(run_method(resolver, method)?),
+        Directive2::Method(method) => {
+            let span = method.span;
+            Directive2::Method(run_method(resolver, method).map_err(|err| err.at(span))?)
+        }
     })
 }
 
@@ -27,6 +39,7 @@ fn run_method<'input>(
     let is_declaration = method.body.is_none();
     let mut body = Vec::new();
     let mut remap_returns = Vec::new();
+    let mut slots = ReturnSlots::new(method.body.as_deref().unwrap_or_default());
     if !method.is_kernel {
         for arg in method.return_arguments.iter_mut() {
             match arg.state_space {
@@ -38,13 +51,18 @@ fn run_method<'input>(
                     if is_declaration {
                         continue;
                     }
+                    if is_register_type(&arg.v_type) {
+                        slots.insert(old_name, arg.name, arg.v_type.clone());
+                        continue;
+                    }
                     remap_returns.push((old_name, arg.name, arg.v_type.clone()));
                     body.push(Statement::Variable(ast::Variable {
                         align: None,
                         name: old_name,
                         v_type: arg.v_type.clone(),
                         state_space: ptx_parser::StateSpace::Param,
-                        array_init: Vec::new(),
+                        array_init: ast::Initializer::new(),
+                        pointer: None,
                     }));
                 }
                 ptx_parser::StateSpace::Reg => {}
@@ -66,7 +84,8 @@ fn run_method<'input>(
                         name: old_name,
                         v_type: arg.v_type.clone(),
                         state_space: ptx_parser::StateSpace::Param,
-                        array_init: Vec::new(),
+                        array_init: ast::Initializer::new(),
+                        pointer: None,
                     }));
                     body.push(Statement::Instruction(ast::Instruction::St {
                         data: ast::StData {
@@ -90,7 +109,7 @@ fn run_method<'input>(
         .body
         .map(|statements| {
             for statement in statements {
-                run_statement(resolver, &remap_returns, &mut body, statement)?;
+                run_statement(resolver, &remap_returns, &mut slots, &mut body, statement)?;
             }
             Ok::<_, TranslateError>(body)
         })
@@ -101,10 +120,65 @@ fn run_method<'input>(
 fn run_statement<'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
     remap_returns: &Vec<(SpirvWord, SpirvWord, ast::Type)>,
+    slots: &mut ReturnSlots,
     result: &mut Vec<Statement<ast::Instruction<SpirvWord>, SpirvWord>>,
     statement: Statement<ast::Instruction<SpirvWord>, SpirvWord>,
 ) -> Result<(), TranslateError> {
     match statement {
+        Statement::Variable(var)
+            if var.state_space == ast::StateSpace::Param
+                && slots.call_results.contains(&var.name)
+                && is_register_type(&var.v_type) =>
+        {
+            let register =
+                resolver.register_unnamed(Some((var.v_type.clone(), ast::StateSpace::Reg)));
+            slots.insert(var.name, register, var.v_type.clone());
+            result.push(Statement::Variable(ast::Variable {
+                state_space: ast::StateSpace::Reg,
+                name: register,
+                ..var
+            }));
+        }
+        Statement::Constant(constant) => {
+            if let ast::ImmediateValue::S64(value) = constant.value {
+                slots.constants.insert(constant.dst, value);
+            }
+            result.push(Statement::Constant(constant));
+        }
+        Statement::PtrAccess(ptr_access) if slots.get(ptr_access.ptr_src).is_some() => {
+            let slot = slots.get(ptr_access.ptr_src).unwrap().clone();
+            if slots.constants.get(&ptr_access.offset_src) != Some(&0) {
+                return Err(slot.error(resolver, "access at an offset"));
+            }
+            // Zero offset is just another name for the return value
+            slots.slots.insert(ptr_access.dst, slot);
+        }
+        Statement::Instruction(ast::Instruction::Ld { data, arguments })
+            if slots.get(arguments.src).is_some() =>
+        {
+            let slot = slots.get(arguments.src).unwrap();
+            slot.check_access(resolver, data.state_space, &data.typ)?;
+            result.push(Statement::Instruction(ast::Instruction::Mov {
+                data: ast::MovDetails { typ: data.typ },
+                arguments: ast::MovArgs {
+                    dst: arguments.dst,
+                    src: slot.register,
+                },
+            }));
+        }
+        Statement::Instruction(ast::Instruction::St { data, arguments })
+            if slots.get(arguments.src1).is_some() =>
+        {
+            let slot = slots.get(arguments.src1).unwrap();
+            slot.check_access(resolver, data.state_space, &data.typ)?;
+            result.push(Statement::Instruction(ast::Instruction::Mov {
+                data: ast::MovDetails { typ: data.typ },
+                arguments: ast::MovArgs {
+                    dst: slot.register,
+                    src: arguments.src2,
+                },
+            }));
+        }
         Statement::Instruction(ast::Instruction::Call {
             mut data,
             mut arguments,
@@ -115,7 +189,10 @@ fn run_statement<'input>(
                 .iter_mut()
                 .zip(arguments.input_arguments.iter_mut())
             {
-                if *space == ptx_parser::StateSpace::Param {
+                if let Some(slot) = slots.get(*ident) {
+                    *space = ptx_parser::StateSpace::Reg;
+                    *ident = slot.register;
+                } else if *space == ptx_parser::StateSpace::Param {
                     *space = ptx_parser::StateSpace::Reg;
                     let old_name = *ident;
                     *ident = resolver
@@ -140,7 +217,10 @@ fn run_statement<'input>(
                 .iter_mut()
                 .zip(arguments.return_arguments.iter_mut())
             {
-                if *space == ptx_parser::StateSpace::Param {
+                if let Some(slot) = slots.get(*ident) {
+                    *space = ptx_parser::StateSpace::Reg;
+                    *ident = slot.register;
+                } else if *space == ptx_parser::StateSpace::Param {
                     *space = ptx_parser::StateSpace::Reg;
                     let old_name = *ident;
                     *ident = resolver
@@ -184,8 +264,109 @@ fn run_statement<'input>(
             result.push(Statement::Instruction(ast::Instruction::Ret { data }));
         }
         statement => {
+            // Any other use of a return value kept in a register would need its address
+            let statement =
+                statement.visit_map(&mut |ident: SpirvWord,
+                                           _: Option<(&ast::Type, ast::StateSpace)>,
+                                           _: bool,
+                                           _: bool| {
+                    match slots.get(ident) {
+                        Some(slot) => Err(slot.error(&*resolver, "use of its address")),
+                        None => Ok(ident),
+                    }
+                })?;
             result.push(statement);
         }
     }
     Ok(())
 }
+
+fn is_register_type(type_: &ast::Type) -> bool {
+    matches!(type_, ast::Type::Scalar(_) | ast::Type::Vector(..))
+}
+
+struct ReturnSlots {
+    // Return value (or a zero offset into it) -> register holding it
+    slots: FxHashMap<SpirvWord, ReturnSlot>,
+    // Local .param variables which receive a call result
+    call_results: FxHashSet<SpirvWord>,
+    // Values of S64 constants, offsets of ld.param/st.param are S64
+    constants: FxHashMap<SpirvWord, i64>,
+}
+
+impl ReturnSlots {
+    fn new(statements: &[Statement<ast::Instruction<SpirvWord>, SpirvWord>]) -> Self {
+        let call_results = statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Instruction(ast::Instruction::Call { data, arguments }) => Some(
+                    data.return_arguments
+                        .iter()
+                        .zip(arguments.return_arguments.iter())
+                        .filter(|((_, space), _)| *space == ast::StateSpace::Param)
+                        .map(|(_, ident)| *ident),
+                ),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        Self {
+            slots: FxHashMap::default(),
+            call_results,
+            constants: FxHashMap::default(),
+        }
+    }
+
+    fn insert(&mut self, variable: SpirvWord, register: SpirvWord, type_: ast::Type) {
+        self.slots.insert(
+            variable,
+            ReturnSlot {
+                variable,
+                register,
+                type_,
+            },
+        );
+    }
+
+    fn get(&self, ident: SpirvWord) -> Option<&ReturnSlot> {
+        self.slots.get(&ident)
+    }
+}
+
+#[derive(Clone)]
+struct ReturnSlot {
+    // Original .param variable, for error messages
+    variable: SpirvWord,
+    register: SpirvWord,
+    type_: ast::Type,
+}
+
+impl ReturnSlot {
+    fn check_access(
+        &self,
+        resolver: &GlobalStringIdentResolver2,
+        space: ast::StateSpace,
+        type_: &ast::Type,
+    ) -> Result<(), TranslateError> {
+        if space != ast::StateSpace::Param {
+            return Err(self.error(resolver, format!("access through {} space", space)));
+        }
+        if type_.layout().size() != self.type_.layout().size() {
+            return Err(self.error(resolver, "access of a part of the value"));
+        }
+        Ok(())
+    }
+
+    fn error<T: Into<String>>(
+        &self,
+        resolver: &GlobalStringIdentResolver2,
+        detail: T,
+    ) -> TranslateError {
+        let name = resolver
+            .ident_map
+            .get(&self.variable)
+            .and_then(|entry| entry.name.as_deref())
+            .unwrap_or("<unnamed>");
+        error_unsupported_param_access(name, detail)
+    }
+}
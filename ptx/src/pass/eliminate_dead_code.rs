@@ -0,0 +1,159 @@
+use super::*;
+use rustc_hash::FxHashSet;
+
+// Removes code whose results are never observed. Runs late, after insert_explicit_load_store
+// and promote_local_variables, which leave behind a lot of unused registers:
+// * .local variables which are only ever written by plain stores are removed together with those
+//   stores. Volatile and atomic accesses (and taking the address) keep the variable alive
+// * Plain .local loads, constants and conversions whose result is never used are removed
+// * Labels which are only targeted by an unconditional jump immediately before them are merged
+//   with the preceding basic block. normalize_basic_blocks inserts such jumps before every label,
+//   so these are exactly the labels which are not branch targets in the original code
+// Functions which are never called are already removed by remove_unreachable_basic_blocks
+pub(super) fn run(
+    statistics: &mut PassStatistics,
+    directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
+) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
+    run_directives_with_statistics(statistics, directives, run_directive)
+}
+
+fn run_directive(
+    statistics: &mut PassStatistics,
+    directive: Directive2<ast::Instruction<SpirvWord>, SpirvWord>,
+) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
+    Ok(match directive {
+        var @ Directive2::Variable(..) => var,
+        Directive2::Method(mut method) => {
+            method.body = method
+                .body
+                .map(|statements| run_statements(statistics, statements))
+                .transpose()
+                .map_err(|err| err.at(method.span))?;
+            Directive2::Method(method)
+        }
+    })
+}
+
+fn run_statements(
+    statistics: &mut PassStatistics,
+    mut statements: Vec<ExpandedStatement>,
+) -> Result<Vec<ExpandedStatement>, TranslateError> {
+    let statements_before = statements.len();
+    // Removing a store can make the value it stored dead, so we iterate until nothing changes
+    loop {
+        let uses = count_uses(&mut statements)?;
+        let is_dead = |ident: &SpirvWord| uses.occurrences.get(ident).copied() == Some(1);
+        let is_dead_variable = |variable: &SpirvWord| {
+            uses.local_variables.contains(variable)
+                && uses.occurrences.get(variable) == uses.plain_stores.get(variable)
+        };
+        let length = statements.len();
+        statements.retain(|statement| match statement {
+            Statement::Variable(ast::Variable { name, .. }) => !is_dead_variable(name),
+            Statement::Instruction(ast::Instruction::St {
+                data:
+                    ast::StData {
+                        state_space: ast::StateSpace::Local,
+                        qualifier: ast::LdStQualifier::Weak,
+                        ..
+                    },
+                arguments: ast::StArgs { src1, .. },
+            }) => !is_dead_variable(src1),
+            Statement::Instruction(ast::Instruction::Ld {
+                data:
+                    ast::LdDetails {
+                        state_space: ast::StateSpace::Local,
+                        qualifier: ast::LdStQualifier::Weak,
+                        ..
+                    },
+                arguments: ast::LdArgs { dst, .. },
+            })
+            | Statement::Constant(ConstantDefinition { dst, .. })
+            | Statement::Conversion(ImplicitConversion { dst, .. }) => !is_dead(dst),
+            _ => true,
+        });
+        if statements.len() == length {
+            break;
+        }
+    }
+    let statements = merge_fallthrough_labels(statements)?;
+    statistics.eliminated_statements += statements_before - statements.len();
+    Ok(statements)
+}
+
+struct Uses {
+    // Number of times every identifier appears in the function body, including its definition
+    occurrences: FxHashMap<SpirvWord, usize>,
+    // Number of plain (non-volatile, non-atomic) stores to every variable
+    plain_stores: FxHashMap<SpirvWord, usize>,
+    local_variables: FxHashSet<SpirvWord>,
+}
+
+fn count_uses(statements: &mut Vec<ExpandedStatement>) -> Result<Uses, TranslateError> {
+    let mut uses = Uses {
+        occurrences: FxHashMap::default(),
+        plain_stores: FxHashMap::default(),
+        local_variables: FxHashSet::default(),
+    };
+    *statements = std::mem::take(statements)
+        .into_iter()
+        .map(|statement| {
+            match statement {
+                Statement::Variable(ast::Variable {
+                    name,
+                    state_space: ast::StateSpace::Local,
+                    ..
+                }) => {
+                    uses.local_variables.insert(name);
+                    return Ok(statement);
+                }
+                Statement::Variable(..) => return Ok(statement),
+                Statement::Instruction(ast::Instruction::St {
+                    data:
+                        ast::StData {
+                            state_space: ast::StateSpace::Local,
+                            qualifier: ast::LdStQualifier::Weak,
+                            ..
+                        },
+                    arguments: ast::StArgs { src1, .. },
+                }) => {
+                    *uses.plain_stores.entry(src1).or_default() += 1;
+                }
+                _ => {}
+            }
+            statement.visit_map(&mut |ident: SpirvWord,
+                                      _: Option<(&ast::Type, ast::StateSpace)>,
+                                      _: bool,
+                                      _: bool| {
+                *uses.occurrences.entry(ident).or_default() += 1;
+                Ok::<_, TranslateError>(ident)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(uses)
+}
+
+fn merge_fallthrough_labels(
+    mut statements: Vec<ExpandedStatement>,
+) -> Result<Vec<ExpandedStatement>, TranslateError> {
+    let uses = count_uses(&mut statements)?;
+    let mut result = Vec::with_capacity(statements.len());
+    for statement in statements {
+        if let Statement::Label(label) = statement {
+            // The jump and the label itself
+            if uses.occurrences.get(&label) == Some(&2) {
+                if let Some(Statement::Instruction(ast::Instruction::Bra {
+                    arguments: ast::BraArgs { src },
+                })) = result.last()
+                {
+                    if *src == label {
+                        result.pop();
+                        continue;
+                    }
+                }
+            }
+        }
+        result.push(statement);
+    }
+    Ok(result)
+}
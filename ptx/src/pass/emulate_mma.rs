@@ -0,0 +1,452 @@
+use super::*;
+
+// Tensor core instructions have nothing to be emitted as, so they are rejected here with their
+// shape and types. With TranslateOptions::emulate_mma, mma.sync.aligned.m16n8k8.row.col with .f16
+// inputs and .f16 or .f32 accumulators is expanded instead. The emulation is for correctness, not
+// speed: every thread fetches the rows of A and the columns of B it needs from the fragments of
+// other threads with shfl.sync.idx and computes its four elements of D with a chain of fma.rn.f32
+// in the order of k. Fragments are laid out as described in the PTX documentation:
+//   lane = 4 * group + thread_in_group
+//   a0 = A[group][2 * thread_in_group + {0, 1}], a1 = A[group + 8][2 * thread_in_group + {0, 1}]
+//   b0 = B[2 * thread_in_group + {0, 1}][group]
+//   c0, c1 = C[group][2 * thread_in_group + {0, 1}], c2, c3 = C[group + 8][...]
+// with .f16 accumulators packed in pairs like the elements of A. Only the writes to d are guarded
+// by the predicate of the instruction, shfl.sync has to be executed by the whole warp
+pub(super) fn run<'input>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    sreg_map: &SpecialRegistersMap2,
+    emulate: bool,
+    directives: Vec<NormalizedDirective2>,
+) -> Result<Vec<NormalizedDirective2>, TranslateError> {
+    directives
+        .into_iter()
+        .map(|directive| run_directive(resolver, sreg_map, emulate, directive))
+        .collect::<Result<Vec<_>, _>>()
+}
+
+fn run_directive<'input>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    sreg_map: &SpecialRegistersMap2,
+    emulate: bool,
+    directive: NormalizedDirective2,
+) -> Result<NormalizedDirective2, TranslateError> {
+    Ok(match directive {
+        var @ Directive2::Variable(..) => var,
+        Directive2::Method(mut method) => {
+            method.body = method
+                .body
+                .map(|statements| {
+                    let mut result = Vec::with_capacity(statements.len());
+                    for statement in statements {
+                        run_statement(resolver, sreg_map, emulate, &mut result, statement)?;
+                    }
+                    Ok::<_, TranslateError>(result)
+                })
+                .transpose()?;
+            Directive2::Method(method)
+        }
+    })
+}
+
+fn run_statement<'input>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    sreg_map: &SpecialRegistersMap2,
+    emulate: bool,
+    result: &mut Vec<NormalizedStatement>,
+    statement: NormalizedStatement,
+) -> Result<(), TranslateError> {
+    match statement {
+        Statement::Instruction((predicate, ast::Instruction::Mma { data, arguments }, span)) => {
+            let opcode = data.to_string();
+            if !is_emulated(&data) {
+                return Err(error_unsupported_instruction(opcode).at(span));
+            }
+            if !emulate {
+                log::warn!("{opcode} can be emulated, enable it with ZLUDA_EMULATE_MMA=1");
+                return Err(error_unsupported_instruction(opcode).at(span));
+            }
+            log::warn!("{opcode} is emulated with shfl.sync and fma");
+            let mut emulation = Emulation {
+                resolver,
+                sreg_map,
+                result,
+                span,
+            };
+            emulation
+                .m16n8k8(predicate, &data, arguments)
+                .map_err(|err| err.at(span))
+        }
+        Statement::Instruction((
+            _,
+            instruction @ (ast::Instruction::WmmaLoad { .. }
+            | ast::Instruction::WmmaStore { .. }
+            | ast::Instruction::WmmaMma { .. }),
+            span,
+        )) => Err(error_unsupported_instruction(instruction.to_string()).at(span)),
+        statement => {
+            result.push(statement);
+            Ok(())
+        }
+    }
+}
+
+fn is_emulated(data: &ast::MmaDetails) -> bool {
+    matches!(
+        data,
+        ast::MmaDetails {
+            shape: ast::MmaShape::M16n8k8,
+            alayout: ast::MatrixLayout::Row,
+            blayout: ast::MatrixLayout::Col,
+            dtype: ast::MatrixType::F16 | ast::MatrixType::F32,
+            atype: ast::MatrixType::F16,
+            btype: ast::MatrixType::F16,
+            ctype: ast::MatrixType::F16 | ast::MatrixType::F32,
+        }
+    )
+}
+
+struct Emulation<'a, 'input> {
+    resolver: &'a mut GlobalStringIdentResolver2<'input>,
+    sreg_map: &'a SpecialRegistersMap2,
+    result: &'a mut Vec<NormalizedStatement>,
+    span: ast::Span,
+}
+
+impl<'a, 'input> Emulation<'a, 'input> {
+    fn m16n8k8(
+        &mut self,
+        predicate: Option<ast::PredAt<SpirvWord>>,
+        data: &ast::MmaDetails,
+        arguments: ast::MmaArgs<ast::ParsedOperand<SpirvWord>>,
+    ) -> Result<(), TranslateError> {
+        let d = fragment(&arguments.dst, "d", accumulator_registers(data.dtype))?;
+        let a = fragment(&arguments.src_a, "a", 2)?;
+        let b = fragment(&arguments.src_b, "b", 1)?;
+        let c = fragment(&arguments.src_c, "c", accumulator_registers(data.ctype))?;
+        let lane = self.lane()?;
+        let thread_in_group = self.and(lane, 3);
+        let first_in_group = self.and(lane, !3);
+        let b_lanes = self.shl(thread_in_group, 3);
+        // a_rows[row][k] is A[group + 8 * row][k], b_columns[column][k] is
+        // B[k][2 * thread_in_group + column]
+        let mut a_rows = [Vec::new(), Vec::new()];
+        let mut b_columns = [Vec::new(), Vec::new()];
+        for source in 0..4 {
+            let a_lane = self.add(first_in_group, source);
+            for (row, a_register) in a.iter().enumerate() {
+                let pair = self.shfl_idx(*a_register, a_lane);
+                a_rows[row].extend(self.unpack_f16x2(pair));
+            }
+            for (column, b_column) in b_columns.iter_mut().enumerate() {
+                let b_lane = self.add(b_lanes, 4 * column as u32 + source);
+                let pair = self.shfl_idx(b[0], b_lane);
+                b_column.extend(self.unpack_f16x2(pair));
+            }
+        }
+        let accumulators = match data.ctype {
+            ast::MatrixType::F16 => {
+                let mut values = Vec::with_capacity(4);
+                for register in c {
+                    values.extend(self.unpack_f16x2(register));
+                }
+                values
+            }
+            _ => c,
+        };
+        let mut results = Vec::with_capacity(4);
+        for (index, accumulator) in accumulators.into_iter().enumerate() {
+            let (row, column) = (index / 2, index % 2);
+            let mut sum = accumulator;
+            for (a, b) in a_rows[row].iter().zip(b_columns[column].iter()) {
+                sum = self.fma(*a, *b, sum);
+            }
+            results.push(sum);
+        }
+        match data.dtype {
+            ast::MatrixType::F16 => {
+                for (dst, pair) in d.into_iter().zip(results.chunks(2)) {
+                    let low = self.cvt_f16(pair[0]);
+                    let high = self.cvt_f16(pair[1]);
+                    self.push_guarded(
+                        &predicate,
+                        mov(
+                            ast::ScalarType::B32,
+                            ast::ParsedOperand::Reg(dst),
+                            ast::ParsedOperand::VecPack(vec![low, high]),
+                        ),
+                    );
+                }
+            }
+            _ => {
+                for (dst, value) in d.into_iter().zip(results) {
+                    self.push_guarded(
+                        &predicate,
+                        mov(
+                            ast::ScalarType::F32,
+                            ast::ParsedOperand::Reg(dst),
+                            ast::ParsedOperand::Reg(value),
+                        ),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Warps are made of consecutive threads of the block, x varying fastest
+    fn lane(&mut self) -> Result<SpirvWord, TranslateError> {
+        let tid = self.special_register(PtxSpecialRegister::Tid)?;
+        let ntid = self.special_register(PtxSpecialRegister::Ntid)?;
+        let tid_x = self.read_component(tid, 0);
+        let tid_y = self.read_component(tid, 1);
+        let tid_z = self.read_component(tid, 2);
+        let ntid_x = self.read_component(ntid, 0);
+        let ntid_y = self.read_component(ntid, 1);
+        let plane = self.mad(tid_z, ntid_y, tid_y);
+        let linear = self.mad(plane, ntid_x, tid_x);
+        Ok(self.and(linear, 31))
+    }
+
+    fn special_register(&self, sreg: PtxSpecialRegister) -> Result<SpirvWord, TranslateError> {
+        self.sreg_map
+            .reg_to_id
+            .get(&sreg)
+            .copied()
+            .ok_or_else(error_unreachable)
+    }
+
+    fn register(&mut self, type_: ast::ScalarType) -> SpirvWord {
+        self.resolver
+            .register_unnamed(Some((ast::Type::Scalar(type_), ast::StateSpace::Reg)))
+    }
+
+    fn push(&mut self, instruction: ast::Instruction<ast::ParsedOperand<SpirvWord>>) {
+        self.result
+            .push(Statement::Instruction((None, instruction, self.span)));
+    }
+
+    fn push_guarded(
+        &mut self,
+        predicate: &Option<ast::PredAt<SpirvWord>>,
+        instruction: ast::Instruction<ast::ParsedOperand<SpirvWord>>,
+    ) {
+        let predicate = predicate.as_ref().map(|predicate| ast::PredAt {
+            not: predicate.not,
+            label: predicate.label,
+        });
+        self.result
+            .push(Statement::Instruction((predicate, instruction, self.span)));
+    }
+
+    fn read_component(&mut self, sreg: SpirvWord, component: u8) -> SpirvWord {
+        let dst = self.register(ast::ScalarType::U32);
+        self.push(mov(
+            ast::ScalarType::U32,
+            ast::ParsedOperand::Reg(dst),
+            ast::ParsedOperand::VecMember(sreg, component),
+        ));
+        dst
+    }
+
+    fn mad(&mut self, src1: SpirvWord, src2: SpirvWord, src3: SpirvWord) -> SpirvWord {
+        let dst = self.register(ast::ScalarType::U32);
+        self.push(ast::Instruction::Mad {
+            data: ast::MadDetails::Integer {
+                control: ast::MulIntControl::Low,
+                saturate: false,
+                type_: ast::ScalarType::U32,
+            },
+            arguments: ast::MadArgs {
+                dst: ast::ParsedOperand::Reg(dst),
+                src1: ast::ParsedOperand::Reg(src1),
+                src2: ast::ParsedOperand::Reg(src2),
+                src3: ast::ParsedOperand::Reg(src3),
+            },
+        });
+        dst
+    }
+
+    fn and(&mut self, src: SpirvWord, mask: u32) -> SpirvWord {
+        let dst = self.register(ast::ScalarType::B32);
+        self.push(ast::Instruction::And {
+            data: ast::ScalarType::B32,
+            arguments: ast::AndArgs {
+                dst: ast::ParsedOperand::Reg(dst),
+                src1: ast::ParsedOperand::Reg(src),
+                src2: immediate(mask),
+            },
+        });
+        dst
+    }
+
+    fn shl(&mut self, src: SpirvWord, shift: u32) -> SpirvWord {
+        let dst = self.register(ast::ScalarType::B32);
+        self.push(ast::Instruction::Shl {
+            data: ast::ScalarType::B32,
+            arguments: ast::ShlArgs {
+                dst: ast::ParsedOperand::Reg(dst),
+                src1: ast::ParsedOperand::Reg(src),
+                src2: immediate(shift),
+            },
+        });
+        dst
+    }
+
+    fn add(&mut self, src: SpirvWord, value: u32) -> SpirvWord {
+        let dst = self.register(ast::ScalarType::U32);
+        self.push(ast::Instruction::Add {
+            data: ast::ArithDetails::Integer(ast::ArithInteger {
+                type_: ast::ScalarType::U32,
+                saturate: false,
+            }),
+            arguments: ast::AddArgs {
+                dst: ast::ParsedOperand::Reg(dst),
+                src1: ast::ParsedOperand::Reg(src),
+                src2: immediate(value),
+            },
+        });
+        dst
+    }
+
+    // Lane numbers are absolute: no subsections, the whole warp is in range
+    fn shfl_idx(&mut self, src: SpirvWord, lane: SpirvWord) -> SpirvWord {
+        let dst = self.register(ast::ScalarType::B32);
+        self.push(ast::Instruction::ShflSync {
+            data: ast::ShflSyncDetails {
+                mode: ast::ShuffleMode::Idx,
+            },
+            arguments: ast::ShflSyncArgs {
+                dst: ast::ParsedOperand::Reg(dst),
+                dst_pred: None,
+                src: ast::ParsedOperand::Reg(src),
+                src_lane: ast::ParsedOperand::Reg(lane),
+                src_opts: immediate(0x1f),
+                src_membermask: immediate(u32::MAX),
+            },
+        });
+        dst
+    }
+
+    // Both halves of a .f16x2 register converted to .f32, the lower half first
+    fn unpack_f16x2(&mut self, pair: SpirvWord) -> [SpirvWord; 2] {
+        let low = self.register(ast::ScalarType::B16);
+        let high = self.register(ast::ScalarType::B16);
+        self.push(mov(
+            ast::ScalarType::B32,
+            ast::ParsedOperand::VecPack(vec![low, high]),
+            ast::ParsedOperand::Reg(pair),
+        ));
+        [self.cvt_f32(low), self.cvt_f32(high)]
+    }
+
+    fn cvt_f32(&mut self, half: SpirvWord) -> SpirvWord {
+        let dst = self.register(ast::ScalarType::F32);
+        self.push(cvt(
+            ast::ScalarType::F32,
+            ast::ScalarType::F16,
+            ast::CvtMode::FPExtend {
+                flush_to_zero: Some(false),
+                saturate: false,
+            },
+            dst,
+            half,
+        ));
+        dst
+    }
+
+    fn cvt_f16(&mut self, single: SpirvWord) -> SpirvWord {
+        let dst = self.register(ast::ScalarType::B16);
+        self.push(cvt(
+            ast::ScalarType::F16,
+            ast::ScalarType::F32,
+            ast::CvtMode::FPTruncate {
+                rounding: ast::RoundingMode::NearestEven,
+                is_integer_rounding: false,
+                flush_to_zero: Some(false),
+                saturate: false,
+                satfinite: false,
+            },
+            dst,
+            single,
+        ));
+        dst
+    }
+
+    fn fma(&mut self, src1: SpirvWord, src2: SpirvWord, src3: SpirvWord) -> SpirvWord {
+        let dst = self.register(ast::ScalarType::F32);
+        self.push(ast::Instruction::Fma {
+            data: ast::ArithFloat {
+                type_: ast::ScalarType::F32,
+                rounding: ast::RoundingMode::NearestEven,
+                flush_to_zero: Some(false),
+                saturate: false,
+                is_fusable: false,
+            },
+            arguments: ast::FmaArgs {
+                dst: ast::ParsedOperand::Reg(dst),
+                src1: ast::ParsedOperand::Reg(src1),
+                src2: ast::ParsedOperand::Reg(src2),
+                src3: ast::ParsedOperand::Reg(src3),
+            },
+        });
+        dst
+    }
+}
+
+// .f16 accumulators are packed in pairs
+fn accumulator_registers(type_: ast::MatrixType) -> usize {
+    match type_ {
+        ast::MatrixType::F16 => 2,
+        _ => 4,
+    }
+}
+
+fn fragment(
+    operand: &ast::ParsedOperand<SpirvWord>,
+    name: &str,
+    registers: usize,
+) -> Result<Vec<SpirvWord>, TranslateError> {
+    match operand {
+        ast::ParsedOperand::VecPack(fragment) if fragment.len() == registers => {
+            Ok(fragment.clone())
+        }
+        ast::ParsedOperand::Reg(register) if registers == 1 => Ok(vec![*register]),
+        _ => Err(error_malformed_operand(
+            "mma",
+            format!("fragment {name} must be a vector of {registers} registers"),
+        )),
+    }
+}
+
+fn immediate(value: u32) -> ast::ParsedOperand<SpirvWord> {
+    ast::ParsedOperand::Imm(ast::ImmediateValue::U64(value as u64))
+}
+
+fn mov(
+    type_: ast::ScalarType,
+    dst: ast::ParsedOperand<SpirvWord>,
+    src: ast::ParsedOperand<SpirvWord>,
+) -> ast::Instruction<ast::ParsedOperand<SpirvWord>> {
+    ast::Instruction::Mov {
+        data: ast::MovDetails {
+            typ: ast::Type::Scalar(type_),
+        },
+        arguments: ast::MovArgs { dst, src },
+    }
+}
+
+fn cvt(
+    to: ast::ScalarType,
+    from: ast::ScalarType,
+    mode: ast::CvtMode,
+    dst: SpirvWord,
+    src: SpirvWord,
+) -> ast::Instruction<ast::ParsedOperand<SpirvWord>> {
+    ast::Instruction::Cvt {
+        data: ast::CvtDetails { from, to, mode },
+        arguments: ast::CvtArgs {
+            dst: ast::ParsedOperand::Reg(dst),
+            src: ast::ParsedOperand::Reg(src),
+        },
+    }
+}
@@ -4,10 +4,7 @@ pub(super) fn run<'a, 'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
     directives: Vec<UnconditionalDirective>,
 ) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
-    directives
-        .into_iter()
-        .map(|directive| run_directive(resolver, directive))
-        .collect::<Result<Vec<_>, _>>()
+    run_directives_with_resolver(resolver, directives, run_directive)
 }
 
 fn run_directive<'input>(
@@ -19,7 +16,10 @@ fn run_directive<'input>(
 ) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
     Ok(match directive {
         Directive2::Variable(linking, var) => Directive2::Variable(linking, var),
-        Directive2::Method(method) => Directive2::Method(run_method(resolver, method)?),
+        Directive2::Method(method) => {
+            let span = method.span;
+            Directive2::Method(run_method(resolver, method).map_err(|err| err.at(span))?)
+        }
     })
 }
 
@@ -45,8 +45,10 @@ fn run_method<'input>(
         return_arguments: method.return_arguments,
         name: method.name,
         input_arguments: method.input_arguments,
+        span: method.span,
         import_as: method.import_as,
         tuning: method.tuning,
+        loop_pragmas: method.loop_pragmas,
         linkage: method.linkage,
         is_kernel: method.is_kernel,
         flush_to_zero_f32: method.flush_to_zero_f32,
@@ -101,71 +103,126 @@ impl<'a, 'input> FlattenArguments<'a, 'input> {
         } else {
             return Err(TranslateError::UntypedSymbol);
         };
-        if state_space == ast::StateSpace::Reg {
-            let (reg_type, reg_space) = self.resolver.get_typed(reg)?;
-            if *reg_space != ast::StateSpace::Reg {
-                return Err(error_mismatched_type());
+        if state_space != ast::StateSpace::Reg {
+            // `[reg+imm]` and `[symbol+imm]` of ld, st and other memory instructions
+            return Ok(self.ptr_offset(reg, offset, type_.clone(), state_space));
+        }
+        let (reg_type, reg_space) = self.resolver.get_typed(reg)?;
+        let reg_scalar_type = match (reg_type, reg_space) {
+            (ast::Type::Scalar(underlying_type), ast::StateSpace::Reg) => *underlying_type,
+            // Address of a variable with an offset, e.g. `mov.u64 r, shared_array+8`. The offset
+            // is applied to the pointer and insert_implicit_conversions2 takes its address, same
+            // as for `mov.u64 r, shared_array`
+            (
+                _,
+                ast::StateSpace::Const
+                | ast::StateSpace::Global
+                | ast::StateSpace::Local
+                | ast::StateSpace::Shared,
+            ) => {
+                let (reg_type, reg_space) = (reg_type.clone(), *reg_space);
+                return Ok(self.ptr_offset(reg, offset, reg_type, reg_space));
             }
-            let reg_scalar_type = match reg_type {
-                ast::Type::Scalar(underlying_type) => *underlying_type,
-                _ => return Err(error_mismatched_type()),
-            };
-            let reg_type = reg_type.clone();
-            let id_constant_stmt = self
-                .resolver
-                .register_unnamed(Some((reg_type.clone(), ast::StateSpace::Reg)));
-            self.result.push(Statement::Constant(ConstantDefinition {
-                dst: id_constant_stmt,
-                typ: reg_scalar_type,
-                value: ast::ImmediateValue::S64(offset as i64),
-            }));
-            let arith_details = match reg_scalar_type.kind() {
-                ast::ScalarKind::Signed => ast::ArithDetails::Integer(ast::ArithInteger {
+            (_, space) if *space != ast::StateSpace::Reg => {
+                return Err(self.error_unsupported_address(
+                    reg,
+                    offset,
+                    format!("symbol in {} space can't be offset", space),
+                ))
+            }
+            (reg_type, _) => {
+                return Err(self.error_unsupported_address(
+                    reg,
+                    offset,
+                    format!("register of type {} can't be offset", reg_type),
+                ))
+            }
+        };
+        let reg_type = reg_type.clone();
+        let id_constant_stmt = self
+            .resolver
+            .register_unnamed(Some((reg_type.clone(), ast::StateSpace::Reg)));
+        self.result.push(Statement::Constant(ConstantDefinition {
+            dst: id_constant_stmt,
+            typ: reg_scalar_type,
+            value: ast::ImmediateValue::S64(offset as i64),
+        }));
+        let arith_details = match reg_scalar_type.kind() {
+            ast::ScalarKind::Signed | ast::ScalarKind::Unsigned | ast::ScalarKind::Bit => {
+                ast::ArithDetails::Integer(ast::ArithInteger {
                     type_: reg_scalar_type,
                     saturate: false,
-                }),
-                ast::ScalarKind::Unsigned | ast::ScalarKind::Bit => {
-                    ast::ArithDetails::Integer(ast::ArithInteger {
-                        type_: reg_scalar_type,
-                        saturate: false,
-                    })
-                }
-                _ => return Err(error_unreachable()),
-            };
-            let id_add_result = self
-                .resolver
-                .register_unnamed(Some((reg_type, state_space)));
-            self.result
-                .push(Statement::Instruction(ast::Instruction::Add {
-                    data: arith_details,
-                    arguments: ast::AddArgs {
-                        dst: id_add_result,
-                        src1: reg,
-                        src2: id_constant_stmt,
-                    },
-                }));
-            Ok(id_add_result)
-        } else {
-            let id_constant_stmt = self.resolver.register_unnamed(Some((
-                ast::Type::Scalar(ast::ScalarType::S64),
-                ast::StateSpace::Reg,
-            )));
-            self.result.push(Statement::Constant(ConstantDefinition {
-                dst: id_constant_stmt,
-                typ: ast::ScalarType::S64,
-                value: ast::ImmediateValue::S64(offset as i64),
-            }));
-            let dst = self
-                .resolver
-                .register_unnamed(Some((type_.clone(), state_space)));
-            self.result.push(Statement::PtrAccess(PtrAccess {
-                underlying_type: type_.clone(),
-                state_space: state_space,
-                dst,
-                ptr_src: reg,
-                offset_src: id_constant_stmt,
+                })
+            }
+            _ => {
+                return Err(self.error_unsupported_address(
+                    reg,
+                    offset,
+                    format!("register of type {} can't be offset", reg_type),
+                ))
+            }
+        };
+        let id_add_result = self
+            .resolver
+            .register_unnamed(Some((reg_type, state_space)));
+        self.result
+            .push(Statement::Instruction(ast::Instruction::Add {
+                data: arith_details,
+                arguments: ast::AddArgs {
+                    dst: id_add_result,
+                    src1: reg,
+                    src2: id_constant_stmt,
+                },
             }));
-            Ok(dst)
+        Ok(id_add_result)
+    }
+
+    // Byte offset from a pointer. When the pointer is a variable both operands of the GEP are
+    // constants and LLVM folds it into a constant expression
+    fn ptr_offset(
+        &mut self,
+        ptr: SpirvWord,
+        offset: i32,
+        underlying_type: ast::Type,
+        state_space: ast::StateSpace,
+    ) -> SpirvWord {
+        let id_constant_stmt = self.resolver.register_unnamed(Some((
+            ast::Type::Scalar(ast::ScalarType::S64),
+            ast::StateSpace::Reg,
+        )));
+        self.result.push(Statement::Constant(ConstantDefinition {
+            dst: id_constant_stmt,
+            typ: ast::ScalarType::S64,
+            value: ast::ImmediateValue::S64(offset as i64),
+        }));
+        let dst = self
+            .resolver
+            .register_unnamed(Some((underlying_type.clone(), state_space)));
+        self.result.push(Statement::PtrAccess(PtrAccess {
+            underlying_type,
+            state_space,
+            dst,
+            ptr_src: ptr,
+            offset_src: id_constant_stmt,
+        }));
+        dst
+    }
+
+    fn error_unsupported_address(
+        &self,
+        base: SpirvWord,
+        offset: i32,
+        detail: String,
+    ) -> TranslateError {
+        let base = match self.resolver.ident_map.get(&base) {
+            Some(IdentEntry {
+                name: Some(name), ..
+            }) => name.to_string(),
+            _ => format!("{:?}", base),
+        };
+        TranslateError::UnsupportedAddress {
+            operand: format!("{}+{}", base, offset),
+            detail,
         }
     }
 
@@ -235,16 +292,31 @@ impl<'a, 'input> FlattenArguments<'a, 'input> {
         is_dst: bool,
         relaxed_type_check: bool,
     ) -> Result<SpirvWord, TranslateError> {
-        let (width, scalar_t, state_space) = match type_space {
-            Some((ast::Type::Vector(width, scalar_t), space)) => (*width, *scalar_t, space),
+        let (packed_type, scalar_t, state_space) = match type_space {
+            Some((ast::Type::Vector(width, scalar_t), space))
+                if *width as usize == vector_elements.len() =>
+            {
+                (ast::Type::Vector(*width, *scalar_t), *scalar_t, space)
+            }
+            // Bit-level pack and unpack, e.g. `mov.b64 d, {lo, hi}`
+            Some((ast::Type::Scalar(scalar_t), space)) => {
+                let element_t = match (scalar_t.size_of(), vector_elements.len()) {
+                    (2, 2) | (4, 4) => ast::ScalarType::B8,
+                    (4, 2) | (8, 4) => ast::ScalarType::B16,
+                    (8, 2) => ast::ScalarType::B32,
+                    _ => return Err(error_mismatched_type()),
+                };
+                (ast::Type::Scalar(*scalar_t), element_t, space)
+            }
             _ => return Err(error_mismatched_type()),
         };
         let temporary_vector = self
             .resolver
-            .register_unnamed(Some((ast::Type::Vector(width, scalar_t), state_space)));
+            .register_unnamed(Some((packed_type.clone(), state_space)));
         let statement = Statement::RepackVector(RepackVectorDetails {
             is_extract: is_dst,
             typ: scalar_t,
+            packed_type,
             packed: temporary_vector,
             unpacked: vector_elements,
             relaxed_type_check,
@@ -1,8 +1,14 @@
 use super::*;
 
+// Every read of a special register becomes a call to a function from zluda_ptx_impl. With
+// `hoist` set, invariant registers (everything except %clock) are instead read once per
+// function, at its start, and every later read reuses that value. Reads of %envreg<N> become
+// the constant 0
 pub(super) fn run<'a, 'input>(
     resolver: &'a mut GlobalStringIdentResolver2<'input>,
     special_registers: &'a SpecialRegistersMap2,
+    hoist: bool,
+    statistics: &'a mut PassStatistics,
     directives: Vec<UnconditionalDirective>,
 ) -> Result<Vec<UnconditionalDirective>, TranslateError> {
     let mut result = Vec::with_capacity(SpecialRegistersMap2::len() + directives.len());
@@ -16,8 +22,10 @@ pub(super) fn run<'a, 'input>(
                 name,
                 input_arguments,
                 body: None,
+                span: ast::Span::default(),
                 import_as: None,
                 tuning: Vec::new(),
+                loop_pragmas: FxHashMap::default(),
                 linkage: ast::LinkingDirective::EXTERN,
                 is_kernel: false,
                 flush_to_zero_f32: false,
@@ -33,6 +41,10 @@ pub(super) fn run<'a, 'input>(
         special_registers,
         sreg_to_function,
         result: Vec::new(),
+        hoist,
+        statistics,
+        hoisted: FxHashMap::default(),
+        prologue: Vec::new(),
     };
     for directive in directives.into_iter() {
         result.push(run_directive(&mut visitor, directive)?);
@@ -46,7 +58,10 @@ fn run_directive<'a, 'input>(
 ) -> Result<UnconditionalDirective, TranslateError> {
     Ok(match directive {
         var @ Directive2::Variable(..) => var,
-        Directive2::Method(method) => Directive2::Method(run_method(visitor, method)?),
+        Directive2::Method(method) => {
+            let span = method.span;
+            Directive2::Method(run_method(visitor, method).map_err(|err| err.at(span))?)
+        }
     })
 }
 
@@ -54,6 +69,7 @@ fn run_method<'a, 'input>(
     visitor: &mut SpecialRegisterResolver<'a, 'input>,
     method: UnconditionalFunction,
 ) -> Result<UnconditionalFunction, TranslateError> {
+    visitor.hoisted.clear();
     let body = method
         .body
         .map(|statements| {
@@ -61,7 +77,9 @@ fn run_method<'a, 'input>(
             for statement in statements {
                 run_statement(visitor, &mut result, statement)?;
             }
-            Ok::<_, TranslateError>(result)
+            let mut prologue = std::mem::take(&mut visitor.prologue);
+            prologue.append(&mut result);
+            Ok::<_, TranslateError>(prologue)
         })
         .transpose()?;
     Ok(Function2 { body, ..method })
@@ -83,6 +101,11 @@ struct SpecialRegisterResolver<'a, 'input> {
     special_registers: &'a SpecialRegistersMap2,
     sreg_to_function: FxHashMap<PtxSpecialRegister, SpirvWord>,
     result: Vec<UnconditionalStatement>,
+    hoist: bool,
+    statistics: &'a mut PassStatistics,
+    // Values of the special registers already read at the start of the current function
+    hoisted: FxHashMap<(PtxSpecialRegister, Option<u8>), SpirvWord>,
+    prologue: Vec<UnconditionalStatement>,
 }
 
 impl<'a, 'b, 'input>
@@ -119,10 +142,37 @@ impl<'a, 'b, 'input> SpecialRegisterResolver<'a, 'input> {
         vector_index: Option<u8>,
         is_dst: bool,
     ) -> Result<Option<SpirvWord>, TranslateError> {
+        if let Some(index) = self.special_registers.get_envreg(name) {
+            if is_dst {
+                return Err(error_mismatched_type());
+            }
+            log::debug!("%envreg{index} has no AMD GPU equivalent, read as 0");
+            let constant = self
+                .resolver
+                .register_unnamed(Some((ast::ScalarType::B32.into(), ast::StateSpace::Reg)));
+            self.result.push(Statement::Constant(ConstantDefinition {
+                dst: constant,
+                typ: ast::ScalarType::B32,
+                value: ast::ImmediateValue::U64(0),
+            }));
+            return Ok(Some(constant));
+        }
         if let Some(sreg) = self.special_registers.get(name) {
             if is_dst {
                 return Err(error_mismatched_type());
             }
+            let hoist = self.hoist && sreg.is_invariant();
+            if hoist {
+                if let Some(value) = self.hoisted.get(&(sreg, vector_index)) {
+                    self.statistics.hoisted_special_registers += 1;
+                    return Ok(Some(*value));
+                }
+            }
+            let output = if hoist {
+                &mut self.prologue
+            } else {
+                &mut self.result
+            };
             let input_arguments = match (vector_index, sreg.get_function_input_type()) {
                 (Some(idx), Some(inp_type)) => {
                     if inp_type != ast::ScalarType::U8 {
@@ -132,7 +182,7 @@ impl<'a, 'b, 'input> SpecialRegisterResolver<'a, 'input> {
                         ast::Type::Scalar(inp_type),
                         ast::StateSpace::Reg,
                     )));
-                    self.result.push(Statement::Constant(ConstantDefinition {
+                    output.push(Statement::Constant(ConstantDefinition {
                         dst: constant,
                         typ: inp_type,
                         value: ast::ImmediateValue::U64(idx as u64),
@@ -170,11 +220,13 @@ impl<'a, 'b, 'input> SpecialRegisterResolver<'a, 'input> {
                     .map(|(name, _, _)| ast::ParsedOperand::Reg(*name))
                     .collect(),
             };
-            self.result
-                .push(Statement::Instruction(ast::Instruction::Call {
-                    data,
-                    arguments,
-                }));
+            output.push(Statement::Instruction(ast::Instruction::Call {
+                data,
+                arguments,
+            }));
+            if hoist {
+                self.hoisted.insert((sreg, vector_index), fn_result);
+            }
             Ok(Some(fn_result))
         } else {
             Ok(None)
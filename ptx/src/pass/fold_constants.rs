@@ -0,0 +1,280 @@
+use super::*;
+
+// Replaces integer instructions whose inputs are all constants with constants. PTX code does
+// a lot of address arithmetic on immediates (`mov.u32 r, imm; shl.b32 r, r, 2; add...`)
+// and without this pass every single step is emitted as an instruction.
+// Constants are tracked per identifier: an identifier is known if it's defined exactly once,
+// either by a constant or by an instruction we've folded. Folded instructions keep their
+// destination, so uses don't have to be rewritten and the now unused inputs are cleaned up by
+// eliminate_dead_code.
+// Results wrap around just like in PTX. We don't fold floating point arithmetic, whose results
+// depend on rounding and denormal modes, only moves of floating point constants
+pub(super) fn run(
+    statistics: &mut PassStatistics,
+    directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
+) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
+    run_directives_with_statistics(statistics, directives, run_directive)
+}
+
+fn run_directive(
+    statistics: &mut PassStatistics,
+    directive: Directive2<ast::Instruction<SpirvWord>, SpirvWord>,
+) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
+    Ok(match directive {
+        var @ Directive2::Variable(..) => var,
+        Directive2::Method(mut method) => {
+            method.body = method
+                .body
+                .map(|statements| run_statements(statistics, statements))
+                .transpose()
+                .map_err(|err| err.at(method.span))?;
+            Directive2::Method(method)
+        }
+    })
+}
+
+fn run_statements(
+    statistics: &mut PassStatistics,
+    statements: Vec<ExpandedStatement>,
+) -> Result<Vec<ExpandedStatement>, TranslateError> {
+    let mut definitions = FxHashMap::<_, usize>::default();
+    let statements = statements
+        .into_iter()
+        .map(|statement| {
+            // Declarations are visited as destinations, but they don't assign a value
+            if let Statement::Variable(..) = statement {
+                return Ok(statement);
+            }
+            statement.visit_map(&mut |ident: SpirvWord,
+                                      _: Option<(&ast::Type, ast::StateSpace)>,
+                                      is_dst: bool,
+                                      _: bool| {
+                if is_dst {
+                    *definitions.entry(ident).or_default() += 1;
+                }
+                Ok::<_, TranslateError>(ident)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut constants = FxHashMap::default();
+    Ok(statements
+        .into_iter()
+        .map(|statement| {
+            let statement = match fold(&constants, &statement) {
+                Some(constant) => {
+                    statistics.folded_instructions += 1;
+                    Statement::Constant(constant)
+                }
+                None => statement,
+            };
+            if let Statement::Constant(ConstantDefinition { dst, typ, value }) = &statement {
+                if definitions.get(dst) == Some(&1) {
+                    constants.insert(*dst, (*typ, *value));
+                }
+            }
+            statement
+        })
+        .collect())
+}
+
+fn fold(
+    constants: &FxHashMap<SpirvWord, (ast::ScalarType, ast::ImmediateValue)>,
+    statement: &ExpandedStatement,
+) -> Option<ConstantDefinition> {
+    let instruction = match statement {
+        Statement::Instruction(instruction) => instruction,
+        _ => return None,
+    };
+    let (dst, typ, value) = match instruction {
+        ast::Instruction::Mov {
+            data: ast::MovDetails {
+                typ: ast::Type::Scalar(typ),
+            },
+            arguments: ast::MovArgs { dst, src },
+        } => {
+            let (src_type, value) = constants.get(src)?;
+            if src_type.size_of() != typ.size_of() || is_integer(*src_type) != is_integer(*typ) {
+                return None;
+            }
+            return Some(ConstantDefinition {
+                dst: *dst,
+                typ: *typ,
+                value: *value,
+            });
+        }
+        ast::Instruction::Add {
+            data:
+                ast::ArithDetails::Integer(ast::ArithInteger {
+                    type_,
+                    saturate: false,
+                }),
+            arguments: ast::AddArgs { dst, src1, src2 },
+        } => (
+            dst,
+            *type_,
+            integer(constants, src1, *type_)?.wrapping_add(integer(constants, src2, *type_)?),
+        ),
+        ast::Instruction::Sub {
+            data:
+                ast::ArithDetails::Integer(ast::ArithInteger {
+                    type_,
+                    saturate: false,
+                }),
+            arguments: ast::SubArgs { dst, src1, src2 },
+        } => (
+            dst,
+            *type_,
+            integer(constants, src1, *type_)?.wrapping_sub(integer(constants, src2, *type_)?),
+        ),
+        ast::Instruction::Mul {
+            data: ast::MulDetails::Integer { type_, control },
+            arguments: ast::MulArgs { dst, src1, src2 },
+        } => {
+            let bits = bit_width(*type_);
+            let product = extend(integer(constants, src1, *type_)?, *type_)
+                .wrapping_mul(extend(integer(constants, src2, *type_)?, *type_));
+            match control {
+                ast::MulIntControl::Low => (dst, *type_, product as u64),
+                ast::MulIntControl::High => (dst, *type_, (product >> bits) as u64),
+                ast::MulIntControl::Wide => {
+                    let dst_type = wide_type(*type_)?;
+                    (dst, dst_type, product as u64)
+                }
+            }
+        }
+        ast::Instruction::And {
+            data: type_,
+            arguments: ast::AndArgs { dst, src1, src2 },
+        } => (
+            dst,
+            *type_,
+            integer(constants, src1, *type_)? & integer(constants, src2, *type_)?,
+        ),
+        ast::Instruction::Or {
+            data: type_,
+            arguments: ast::OrArgs { dst, src1, src2 },
+        } => (
+            dst,
+            *type_,
+            integer(constants, src1, *type_)? | integer(constants, src2, *type_)?,
+        ),
+        ast::Instruction::Xor {
+            data: type_,
+            arguments: ast::XorArgs { dst, src1, src2 },
+        } => (
+            dst,
+            *type_,
+            integer(constants, src1, *type_)? ^ integer(constants, src2, *type_)?,
+        ),
+        ast::Instruction::Not {
+            data: type_,
+            arguments: ast::NotArgs { dst, src },
+        } => (dst, *type_, !integer(constants, src, *type_)?),
+        // Shifting by the type width or more shifts out all the bits
+        ast::Instruction::Shl {
+            data: type_,
+            arguments: ast::ShlArgs { dst, src1, src2 },
+        } => {
+            let amount = integer(constants, src2, ast::ScalarType::U32)?;
+            let value = integer(constants, src1, *type_)?;
+            let result = if amount >= bit_width(*type_) as u64 {
+                0
+            } else {
+                value << amount
+            };
+            (dst, *type_, result)
+        }
+        ast::Instruction::Shr {
+            data: ast::ShrData { type_, kind },
+            arguments: ast::ShrArgs { dst, src1, src2 },
+        } => {
+            let amount = integer(constants, src2, ast::ScalarType::U32)?;
+            let value = integer(constants, src1, *type_)?;
+            let bits = bit_width(*type_) as u64;
+            let result = match kind {
+                ast::RightShiftKind::Logical if amount >= bits => 0,
+                ast::RightShiftKind::Logical => value >> amount,
+                ast::RightShiftKind::Arithmetic => {
+                    (extend_signed(value, *type_) >> amount.min(bits - 1)) as u64
+                }
+            };
+            (dst, *type_, result)
+        }
+        _ => return None,
+    };
+    Some(ConstantDefinition {
+        dst: *dst,
+        typ,
+        value: ast::ImmediateValue::U64(truncate(value, typ)),
+    })
+}
+
+// Returns the value of a known integer constant, truncated to the bit width of `type_`
+fn integer(
+    constants: &FxHashMap<SpirvWord, (ast::ScalarType, ast::ImmediateValue)>,
+    ident: &SpirvWord,
+    type_: ast::ScalarType,
+) -> Option<u64> {
+    if !is_integer(type_) {
+        return None;
+    }
+    let (constant_type, value) = constants.get(ident)?;
+    if !is_integer(*constant_type) {
+        return None;
+    }
+    Some(truncate(value.as_u64()?, type_))
+}
+
+fn is_integer(type_: ast::ScalarType) -> bool {
+    matches!(
+        type_,
+        ast::ScalarType::U8
+            | ast::ScalarType::U16
+            | ast::ScalarType::U32
+            | ast::ScalarType::U64
+            | ast::ScalarType::S8
+            | ast::ScalarType::S16
+            | ast::ScalarType::S32
+            | ast::ScalarType::S64
+            | ast::ScalarType::B8
+            | ast::ScalarType::B16
+            | ast::ScalarType::B32
+            | ast::ScalarType::B64
+    )
+}
+
+fn bit_width(type_: ast::ScalarType) -> u32 {
+    type_.size_of() as u32 * 8
+}
+
+fn truncate(value: u64, type_: ast::ScalarType) -> u64 {
+    match bit_width(type_) {
+        64 => value,
+        bits => value & ((1u64 << bits) - 1),
+    }
+}
+
+fn extend_signed(value: u64, type_: ast::ScalarType) -> i64 {
+    let shift = 64 - bit_width(type_);
+    ((value << shift) as i64) >> shift
+}
+
+// Extends to 128 bits according to the signedness of `type_`, so the product contains both
+// the low and the high half of the result
+fn extend(value: u64, type_: ast::ScalarType) -> u128 {
+    if type_.kind() == ast::ScalarKind::Signed {
+        extend_signed(value, type_) as i128 as u128
+    } else {
+        value as u128
+    }
+}
+
+fn wide_type(type_: ast::ScalarType) -> Option<ast::ScalarType> {
+    Some(match type_ {
+        ast::ScalarType::U16 => ast::ScalarType::U32,
+        ast::ScalarType::S16 => ast::ScalarType::S32,
+        ast::ScalarType::U32 => ast::ScalarType::U64,
+        ast::ScalarType::S32 => ast::ScalarType::S64,
+        _ => return None,
+    })
+}
@@ -0,0 +1,116 @@
+use super::*;
+
+// ptxas contracts a float mul without a rounding modifier followed by an add without a rounding
+// modifier into a single fma (see ast::ArithFloat::is_fusable), so that is what results on NVIDIA
+// GPUs are compared against. LLVM keeps them as two separately rounded operations, this pass does
+// the contraction itself: `add d, p, c` (or `add d, c, p`) where `p` is the result of
+// `mul p, a, b` becomes `fma.rn d, a, b, c`. The mul is left in place for any other uses of `p`.
+// Both instructions need the same type and flush-to-zero mode and the mul must not saturate.
+// Runs after promote_local_variables, before that the product reaches the add through a register
+// load, so with promotion disabled nothing is fused
+pub(super) fn run(
+    statistics: &mut PassStatistics,
+    directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
+) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
+    run_directives_with_statistics(statistics, directives, run_directive)
+}
+
+fn run_directive(
+    statistics: &mut PassStatistics,
+    directive: Directive2<ast::Instruction<SpirvWord>, SpirvWord>,
+) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
+    Ok(match directive {
+        var @ Directive2::Variable(..) => var,
+        Directive2::Method(mut method) => {
+            method.body = method
+                .body
+                .map(|statements| run_statements(statistics, statements))
+                .transpose()
+                .map_err(|err| err.at(method.span))?;
+            Directive2::Method(method)
+        }
+    })
+}
+
+fn run_statements(
+    statistics: &mut PassStatistics,
+    mut statements: Vec<ExpandedStatement>,
+) -> Result<Vec<ExpandedStatement>, TranslateError> {
+    let definitions = count_definitions(&mut statements)?;
+    // Operands of the value must be the same when the add runs as they were for the mul
+    let is_defined_once = |value: &SpirvWord| definitions.get(value).copied().unwrap_or(0) <= 1;
+    let mut products = FxHashMap::default();
+    for statement in statements.iter_mut() {
+        match statement {
+            Statement::Instruction(ast::Instruction::Mul {
+                data: ast::MulDetails::Float(mul),
+                arguments,
+            }) if mul.is_fusable
+                && !mul.saturate
+                && is_defined_once(&arguments.dst)
+                && is_defined_once(&arguments.src1)
+                && is_defined_once(&arguments.src2) =>
+            {
+                products.insert(arguments.dst, (*mul, arguments.src1, arguments.src2));
+            }
+            Statement::Instruction(ast::Instruction::Add {
+                data: ast::ArithDetails::Float(add),
+                arguments,
+            }) if add.is_fusable => {
+                let fusable_product = |value: &SpirvWord| {
+                    products.get(value).filter(|(mul, _, _)| {
+                        mul.type_ == add.type_ && mul.flush_to_zero == add.flush_to_zero
+                    })
+                };
+                let (factors, addend) = match (
+                    fusable_product(&arguments.src1),
+                    fusable_product(&arguments.src2),
+                ) {
+                    (Some((_, a, b)), _) => ((*a, *b), arguments.src2),
+                    (None, Some((_, a, b))) => ((*a, *b), arguments.src1),
+                    (None, None) => continue,
+                };
+                let fma = Statement::Instruction(ast::Instruction::Fma {
+                    data: ast::ArithFloat {
+                        type_: add.type_,
+                        rounding: ast::RoundingMode::NearestEven,
+                        flush_to_zero: add.flush_to_zero,
+                        saturate: add.saturate,
+                        is_fusable: false,
+                    },
+                    arguments: ast::FmaArgs {
+                        dst: arguments.dst,
+                        src1: factors.0,
+                        src2: factors.1,
+                        src3: addend,
+                    },
+                });
+                *statement = fma;
+                statistics.fused_mul_adds += 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(statements)
+}
+
+fn count_definitions(
+    statements: &mut Vec<ExpandedStatement>,
+) -> Result<FxHashMap<SpirvWord, usize>, TranslateError> {
+    let mut definitions = FxHashMap::default();
+    *statements = std::mem::take(statements)
+        .into_iter()
+        .map(|statement| {
+            statement.visit_map(&mut |ident: SpirvWord,
+                                      _: Option<(&ast::Type, ast::StateSpace)>,
+                                      is_dst: bool,
+                                      _: bool| {
+                if is_dst {
+                    *definitions.entry(ident).or_default() += 1;
+                }
+                Ok::<_, TranslateError>(ident)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(definitions)
+}
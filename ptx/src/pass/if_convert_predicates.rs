@@ -0,0 +1,149 @@
+use super::*;
+
+// normalize_predicates2 turns every predicated instruction into a small diamond: a conditional
+// jump around the instruction. This is the only correct lowering for instructions with side
+// effects (stores, atomics, calls, barriers), but nvcc predicates plain arithmetic very often,
+// and every such diamond splits the basic block in two.
+// This pass if-converts predicated side-effect free instructions with a single destination:
+//   @p add.s32 x, y, z;
+// becomes
+//   add.s32 temp, y, z;
+//   selp.s32 x, temp, x, p;
+// Everything else is left predicated for normalize_predicates2
+pub(crate) fn run<'input>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    statistics: &mut PassStatistics,
+    directives: Vec<NormalizedDirective2>,
+) -> Result<Vec<NormalizedDirective2>, TranslateError> {
+    directives
+        .into_iter()
+        .map(|directive| run_directive(resolver, statistics, directive))
+        .collect::<Result<Vec<_>, _>>()
+}
+
+fn run_directive<'input>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    statistics: &mut PassStatistics,
+    directive: NormalizedDirective2,
+) -> Result<NormalizedDirective2, TranslateError> {
+    Ok(match directive {
+        var @ Directive2::Variable(..) => var,
+        Directive2::Method(mut method) => {
+            method.body = method
+                .body
+                .map(|statements| {
+                    let mut result = Vec::with_capacity(statements.len());
+                    for statement in statements {
+                        run_statement(resolver, statistics, &mut result, statement)?;
+                    }
+                    Ok::<_, TranslateError>(result)
+                })
+                .transpose()
+                .map_err(|err| err.at(method.span))?;
+            Directive2::Method(method)
+        }
+    })
+}
+
+fn run_statement<'input>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    statistics: &mut PassStatistics,
+    result: &mut Vec<NormalizedStatement>,
+    statement: NormalizedStatement,
+) -> Result<(), TranslateError> {
+    let (predicate, instruction, span) = match statement {
+        Statement::Instruction((Some(predicate), instruction, span))
+            if is_side_effect_free(&instruction) =>
+        {
+            (predicate, instruction, span)
+        }
+        statement => {
+            result.push(statement);
+            return Ok(());
+        }
+    };
+    let (dst, type_) = match single_scalar_destination(&instruction)? {
+        Some(destination) => destination,
+        None => {
+            result.push(Statement::Instruction((Some(predicate), instruction, span)));
+            return Ok(());
+        }
+    };
+    let temp = resolver.register_unnamed(Some((ast::Type::Scalar(type_), ast::StateSpace::Reg)));
+    let instruction = ast::visit_map(
+        instruction,
+        &mut |ident: SpirvWord, _: Option<(&ast::Type, ast::StateSpace)>, is_dst: bool, _: bool| {
+            Ok::<_, TranslateError>(if is_dst { temp } else { ident })
+        },
+    )?;
+    let (if_true, if_false) = if predicate.not {
+        (dst, temp)
+    } else {
+        (temp, dst)
+    };
+    result.push(Statement::Instruction((None, instruction, span)));
+    result.push(Statement::Instruction((
+        None,
+        ast::Instruction::Selp {
+            data: type_,
+            arguments: ast::SelpArgs {
+                dst: ast::ParsedOperand::Reg(dst),
+                src1: ast::ParsedOperand::Reg(if_true),
+                src2: ast::ParsedOperand::Reg(if_false),
+                src3: ast::ParsedOperand::Reg(predicate.label),
+            },
+        },
+        span,
+    )));
+    statistics.if_converted_instructions += 1;
+    Ok(())
+}
+
+fn is_side_effect_free(instruction: &ast::Instruction<ast::ParsedOperand<SpirvWord>>) -> bool {
+    matches!(
+        instruction,
+        ast::Instruction::Abs { .. }
+            | ast::Instruction::Add { .. }
+            | ast::Instruction::And { .. }
+            | ast::Instruction::Mad { .. }
+            | ast::Instruction::Max { .. }
+            | ast::Instruction::Min { .. }
+            | ast::Instruction::Mov { .. }
+            | ast::Instruction::Mul { .. }
+            | ast::Instruction::Neg { .. }
+            | ast::Instruction::Not { .. }
+            | ast::Instruction::Or { .. }
+            | ast::Instruction::Selp { .. }
+            | ast::Instruction::Shl { .. }
+            | ast::Instruction::Shr { .. }
+            | ast::Instruction::Sub { .. }
+            | ast::Instruction::Xor { .. }
+    )
+}
+
+// selp has no predicate variant, so we skip predicate destinations
+fn single_scalar_destination(
+    instruction: &ast::Instruction<ast::ParsedOperand<SpirvWord>>,
+) -> Result<Option<(SpirvWord, ast::ScalarType)>, TranslateError> {
+    let mut destinations = Vec::new();
+    ast::visit(
+        instruction,
+        &mut |operand: &ast::ParsedOperand<SpirvWord>,
+              type_space: Option<(&ast::Type, ast::StateSpace)>,
+              is_dst: bool,
+              _: bool| {
+            if is_dst {
+                destinations.push((operand.clone(), type_space.map(|(type_, _)| type_.clone())));
+            }
+            Ok::<_, TranslateError>(())
+        },
+    )?;
+    Ok(match &*destinations {
+        [(ast::ParsedOperand::Reg(dst), Some(ast::Type::Scalar(type_)))]
+            if *type_ != ast::ScalarType::Pred =>
+        {
+            Some((*dst, *type_))
+        }
+        _ => None,
+    })
+}
@@ -0,0 +1,289 @@
+use super::*;
+use rustc_hash::FxHashSet;
+
+// Generic (flat) memory accesses are much slower than global memory accesses on AMD GPUs, but
+// a lot of PTX in the wild loads the kernel pointer arguments and uses them with plain `ld`/`st`.
+// This pass finds identifiers which provably hold pointers to global memory and rewrites
+// generic loads and stores through them to global loads and stores.
+// Every identifier is either a global pointer, provably not a pointer or unknown. Definitions are
+// evaluated as follows:
+// * 64 bit load of a kernel parameter is global. Kernel pointer arguments can only point to global
+//   memory, unless annotated with a different space (`.param .u64 .ptr .shared p`)
+// * cvta.to.global or cvta.global is global
+// * constants, integer mul and shl and conversions from narrower types are not pointers
+// * mov copies the value of its source
+// * add of a global pointer and a non-pointer is global, add of two non-pointers is not a pointer
+// * sub of a non-pointer from a global pointer is global, difference of two global pointers (see
+//   stateful_neg_offset test) or two non-pointers is not a pointer
+// * everything else is unknown, including function arguments and anything defined by statements
+//   other than instructions
+// An identifier with several definitions is global (or not a pointer) only if every definition is.
+// We start by assuming nothing about identifiers defined by instructions and lower them until
+// nothing changes. This way pointers incremented in a loop are still recognized
+pub(super) fn run(
+    statistics: &mut PassStatistics,
+    directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
+) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
+    run_directives_with_statistics(statistics, directives, run_directive)
+}
+
+fn run_directive(
+    statistics: &mut PassStatistics,
+    directive: Directive2<ast::Instruction<SpirvWord>, SpirvWord>,
+) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
+    Ok(match directive {
+        var @ Directive2::Variable(..) => var,
+        Directive2::Method(mut method) => {
+            let kernel_parameters = if method.is_kernel {
+                method
+                    .input_arguments
+                    .iter()
+                    // `.ptr .shared` and similar override the default of global memory
+                    .filter(|arg| {
+                        arg.pointer.is_none_or(|pointer| {
+                            matches!(
+                                pointer.space,
+                                ast::StateSpace::Global | ast::StateSpace::Generic
+                            )
+                        })
+                    })
+                    .map(|arg| arg.name)
+                    .collect::<FxHashSet<_>>()
+            } else {
+                FxHashSet::default()
+            };
+            let arguments = method
+                .input_arguments
+                .iter()
+                .map(|arg| arg.name)
+                .collect::<Vec<_>>();
+            method.body = method
+                .body
+                .map(|statements| {
+                    run_statements(statistics, &kernel_parameters, &arguments, statements)
+                })
+                .transpose()
+                .map_err(|err| err.at(method.span))?;
+            Directive2::Method(method)
+        }
+    })
+}
+
+fn run_statements(
+    statistics: &mut PassStatistics,
+    kernel_parameters: &FxHashSet<SpirvWord>,
+    arguments: &[SpirvWord],
+    statements: Vec<ExpandedStatement>,
+) -> Result<Vec<ExpandedStatement>, TranslateError> {
+    // Arguments are defined by the caller, we know nothing about them
+    let mut origins = arguments
+        .iter()
+        .map(|arg| (*arg, vec![Origin::Unknown]))
+        .collect::<FxHashMap<_, _>>();
+    let statements = statements
+        .into_iter()
+        .map(|statement| {
+            // Declarations are not definitions, registers are defined by their first write
+            if matches!(statement, Statement::Variable(..)) {
+                return Ok(statement);
+            }
+            let origin = origin(kernel_parameters, &statement);
+            statement.visit_map(&mut |ident: SpirvWord,
+                                      _: Option<(&ast::Type, ast::StateSpace)>,
+                                      is_dst: bool,
+                                      _: bool| {
+                if is_dst {
+                    let origin = match origin {
+                        Some((dst, origin)) if dst == ident => origin,
+                        _ => Origin::Unknown,
+                    };
+                    origins.entry(ident).or_default().push(origin);
+                }
+                Ok::<_, TranslateError>(ident)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let global_pointers = global_pointers(origins);
+    Ok(statements
+        .into_iter()
+        .map(|statement| match statement {
+            Statement::Instruction(ast::Instruction::Ld {
+                mut data,
+                arguments,
+            }) if data.state_space == ast::StateSpace::Generic
+                && global_pointers.contains(&arguments.src) =>
+            {
+                statistics.inferred_global_accesses += 1;
+                data.state_space = ast::StateSpace::Global;
+                Statement::Instruction(ast::Instruction::Ld { data, arguments })
+            }
+            Statement::Instruction(ast::Instruction::St {
+                mut data,
+                arguments,
+            }) if data.state_space == ast::StateSpace::Generic
+                && global_pointers.contains(&arguments.src1) =>
+            {
+                statistics.inferred_global_accesses += 1;
+                data.state_space = ast::StateSpace::Global;
+                Statement::Instruction(ast::Instruction::St { data, arguments })
+            }
+            statement => statement,
+        })
+        .collect())
+}
+
+#[derive(Clone, Copy)]
+enum Origin {
+    Global,
+    NotPointer,
+    Copy(SpirvWord),
+    Offset(SpirvWord, SpirvWord),
+    Difference(SpirvWord, SpirvWord),
+    Unknown,
+}
+
+fn origin(
+    kernel_parameters: &FxHashSet<SpirvWord>,
+    statement: &ExpandedStatement,
+) -> Option<(SpirvWord, Origin)> {
+    let instruction = match statement {
+        Statement::Constant(constant) => return Some((constant.dst, Origin::NotPointer)),
+        Statement::Instruction(instruction) => instruction,
+        _ => return None,
+    };
+    Some(match instruction {
+        ast::Instruction::Ld {
+            data:
+                ast::LdDetails {
+                    state_space: ast::StateSpace::Param,
+                    typ: ast::Type::Scalar(typ),
+                    ..
+                },
+            arguments: ast::LdArgs { dst, src },
+        } if typ.size_of() == 8 && kernel_parameters.contains(src) => (*dst, Origin::Global),
+        ast::Instruction::Cvta {
+            data:
+                ast::CvtaDetails {
+                    state_space: ast::StateSpace::Global,
+                    ..
+                },
+            arguments: ast::CvtaArgs { dst, .. },
+        } => (*dst, Origin::Global),
+        ast::Instruction::Mul {
+            data: ast::MulDetails::Integer { .. },
+            arguments: ast::MulArgs { dst, .. },
+        } => (*dst, Origin::NotPointer),
+        ast::Instruction::Shl {
+            arguments: ast::ShlArgs { dst, .. },
+            ..
+        } => (*dst, Origin::NotPointer),
+        // A 64 bit pointer does not fit in a narrower source
+        ast::Instruction::Cvt {
+            data,
+            arguments: ast::CvtArgs { dst, .. },
+        } if data.from.size_of() < 8 => (*dst, Origin::NotPointer),
+        ast::Instruction::Mov {
+            data: ast::MovDetails {
+                typ: ast::Type::Scalar(_),
+            },
+            arguments: ast::MovArgs { dst, src },
+        } => (*dst, Origin::Copy(*src)),
+        ast::Instruction::Add {
+            data:
+                ast::ArithDetails::Integer(ast::ArithInteger {
+                    saturate: false, ..
+                }),
+            arguments: ast::AddArgs { dst, src1, src2 },
+        } => (*dst, Origin::Offset(*src1, *src2)),
+        ast::Instruction::Sub {
+            data:
+                ast::ArithDetails::Integer(ast::ArithInteger {
+                    saturate: false, ..
+                }),
+            arguments: ast::SubArgs { dst, src1, src2 },
+        } => (*dst, Origin::Difference(*src1, *src2)),
+        _ => return None,
+    })
+}
+
+// Top is the optimistic starting point of identifiers defined only by instructions we understand,
+// it is lowered to one of the other values as the definitions are evaluated
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Provenance {
+    Top,
+    Global,
+    NotPointer,
+    Unknown,
+}
+
+impl Provenance {
+    fn meet(self, other: Provenance) -> Provenance {
+        match (self, other) {
+            (Provenance::Top, value) | (value, Provenance::Top) => value,
+            (a, b) if a == b => a,
+            _ => Provenance::Unknown,
+        }
+    }
+}
+
+fn global_pointers(origins: FxHashMap<SpirvWord, Vec<Origin>>) -> FxHashSet<SpirvWord> {
+    let mut values = origins
+        .iter()
+        .map(|(ident, origins)| {
+            let value = if origins
+                .iter()
+                .any(|origin| matches!(origin, Origin::Unknown))
+            {
+                Provenance::Unknown
+            } else {
+                Provenance::Top
+            };
+            (*ident, value)
+        })
+        .collect::<FxHashMap<_, _>>();
+    loop {
+        let mut changed = false;
+        for (ident, origins) in origins.iter() {
+            let value = origins.iter().fold(Provenance::Top, |value, origin| {
+                value.meet(evaluate(&values, *origin))
+            });
+            if values[ident] != value {
+                values.insert(*ident, value);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    values
+        .into_iter()
+        .filter(|(_, value)| *value == Provenance::Global)
+        .map(|(ident, _)| ident)
+        .collect()
+}
+
+fn evaluate(values: &FxHashMap<SpirvWord, Provenance>, origin: Origin) -> Provenance {
+    // Identifiers without a definition in the body (globals, variables) are unknown
+    let value = |ident| values.get(&ident).copied().unwrap_or(Provenance::Unknown);
+    match origin {
+        Origin::Global => Provenance::Global,
+        Origin::NotPointer => Provenance::NotPointer,
+        Origin::Copy(src) => value(src),
+        Origin::Offset(src1, src2) => match (value(src1), value(src2)) {
+            (Provenance::Unknown, _) | (_, Provenance::Unknown) => Provenance::Unknown,
+            (Provenance::Global, Provenance::Global) => Provenance::Unknown,
+            (Provenance::Top, _) | (_, Provenance::Top) => Provenance::Top,
+            (Provenance::NotPointer, Provenance::NotPointer) => Provenance::NotPointer,
+            _ => Provenance::Global,
+        },
+        Origin::Difference(src1, src2) => match (value(src1), value(src2)) {
+            (Provenance::Unknown, _) | (_, Provenance::Unknown) => Provenance::Unknown,
+            (Provenance::NotPointer, Provenance::Global) => Provenance::Unknown,
+            (Provenance::Top, _) | (_, Provenance::Top) => Provenance::Top,
+            (Provenance::Global, Provenance::NotPointer) => Provenance::Global,
+            _ => Provenance::NotPointer,
+        },
+        Origin::Unknown => Provenance::Unknown,
+    }
+}
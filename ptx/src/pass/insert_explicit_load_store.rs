@@ -13,10 +13,7 @@ pub(super) fn run<'a, 'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
     directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
 ) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
-    directives
-        .into_iter()
-        .map(|directive| run_directive(resolver, directive))
-        .collect::<Result<Vec<_>, _>>()
+    run_directives_with_resolver(resolver, directives, run_directive)
 }
 
 fn run_directive<'a, 'input>(
@@ -26,8 +23,9 @@ fn run_directive<'a, 'input>(
     Ok(match directive {
         var @ Directive2::Variable(..) => var,
         Directive2::Method(method) => {
+            let span = method.span;
             let visitor = InsertMemSSAVisitor::new(resolver);
-            Directive2::Method(run_method(visitor, method)?)
+            Directive2::Method(run_method(visitor, method).map_err(|err| err.at(span))?)
         }
     })
 }
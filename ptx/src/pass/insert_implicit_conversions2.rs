@@ -21,10 +21,7 @@ pub(super) fn run<'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
     directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
 ) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
-    directives
-        .into_iter()
-        .map(|directive| run_directive(resolver, directive))
-        .collect::<Result<Vec<_>, _>>()
+    run_directives_with_resolver(resolver, directives, run_directive)
 }
 
 fn run_directive<'a, 'input>(
@@ -37,7 +34,8 @@ fn run_directive<'a, 'input>(
             method.body = method
                 .body
                 .map(|statements| run_statements(resolver, statements))
-                .transpose()?;
+                .transpose()
+                .map_err(|err| err.at(method.span))?;
             Directive2::Method(method)
         }
     })
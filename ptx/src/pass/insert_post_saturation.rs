@@ -5,10 +5,7 @@ pub(super) fn run<'a, 'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
     directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
 ) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
-    directives
-        .into_iter()
-        .map(|directive| run_directive(resolver, directive))
-        .collect::<Result<Vec<_>, _>>()
+    run_directives_with_resolver(resolver, directives, run_directive)
 }
 
 fn run_directive<'input>(
@@ -17,7 +14,10 @@ fn run_directive<'input>(
 ) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
     Ok(match directive {
         var @ Directive2::Variable(..) => var,
-        Directive2::Method(method) => Directive2::Method(run_method(resolver, method)?),
+        Directive2::Method(method) => {
+            let span = method.span;
+            Directive2::Method(run_method(resolver, method).map_err(|err| err.at(span))?)
+        }
     })
 }
 
@@ -71,12 +71,20 @@ fn run_instruction<'input>(
             data: ast::ArithDetails::Integer(..),
             ..
         }
+        | ast::Instruction::AddExtended { .. }
         | ast::Instruction::And { .. }
         | ast::Instruction::Atom { .. }
         | ast::Instruction::AtomCas { .. }
         | ast::Instruction::BarWarp { .. }
         | ast::Instruction::Bar { .. }
+        | ast::Instruction::BarArrive { .. }
+        | ast::Instruction::BarrierCluster { .. }
+        | ast::Instruction::Mma { .. }
+        | ast::Instruction::WmmaLoad { .. }
+        | ast::Instruction::WmmaStore { .. }
+        | ast::Instruction::WmmaMma { .. }
         | ast::Instruction::BarRed { .. }
+        | ast::Instruction::BarRedPopc { .. }
         | ast::Instruction::Bfe { .. }
         | ast::Instruction::Bfi { .. }
         | ast::Instruction::Bra { .. }
@@ -142,6 +150,7 @@ fn run_instruction<'input>(
             data: ast::MadDetails::Integer { .. },
             ..
         }
+        | ast::Instruction::MadExtended { .. }
         | ast::Instruction::Max { .. }
         | ast::Instruction::Membar { .. }
         | ast::Instruction::Min { .. }
@@ -192,6 +201,7 @@ fn run_instruction<'input>(
             data: ast::ArithDetails::Integer(..),
             ..
         }
+        | ast::Instruction::SubExtended { .. }
         | ast::Instruction::Tanh { .. }
         | ast::Instruction::Trap {}
         | ast::Instruction::Xor { .. } => result.push(Statement::Instruction(instruction)),
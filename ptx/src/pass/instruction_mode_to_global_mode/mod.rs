@@ -1,6 +1,8 @@
 use super::BrachCondition;
 use super::Directive2;
 use super::Function2;
+use super::FxIndexMap;
+use super::FxIndexSet;
 use super::GlobalStringIdentResolver2;
 use super::ModeRegister;
 use super::SpirvWord;
@@ -304,7 +306,7 @@ struct ControlFlowGraph {
     entry_points: FxHashMap<SpirvWord, NodeIndex>,
     basic_blocks: FxHashMap<SpirvWord, NodeIndex>,
     // map function -> return label
-    call_returns: FxHashMap<SpirvWord, Vec<NodeIndex>>,
+    call_returns: FxIndexMap<SpirvWord, Vec<NodeIndex>>,
     // map function -> return basic block
     functions_rets: FxHashMap<SpirvWord, NodeIndex>,
     graph: Graph<Node, ()>,
@@ -315,7 +317,7 @@ impl ControlFlowGraph {
         Self {
             entry_points: FxHashMap::default(),
             basic_blocks: FxHashMap::default(),
-            call_returns: FxHashMap::default(),
+            call_returns: FxIndexMap::default(),
             functions_rets: FxHashMap::default(),
             graph: Graph::new(),
         }
@@ -420,7 +422,16 @@ impl ResolvedControlFlowGraph {
                 let node_data = &cfg.graph[node];
                 match (mode, exit_getter(node_data)) {
                     (_, None) => {
-                        for next in cfg.graph.neighbors_directed(node, Direction::Incoming) {
+                        let mut predecessors = cfg
+                            .graph
+                            .neighbors_directed(node, Direction::Incoming)
+                            .peekable();
+                        // Reached an entry of a function no kernel calls or an unreachable
+                        // basic block, the mode on this path is unknown
+                        if predecessors.peek().is_none() {
+                            return Ok(Resolved::Conflict);
+                        }
+                        for next in predecessors {
                             if !visited.contains(&next) {
                                 to_visit.push(next);
                             }
@@ -442,8 +453,10 @@ impl ResolvedControlFlowGraph {
                     }
                 }
             }
-            // This should happen only for orphaned basic blocks
-            mode.map(Resolved::Value).ok_or_else(error_unreachable)
+            // No predecessor sets the mode: the basic block is unreachable (e.g. a label after
+            // `ret`) or is the entry of a function no kernel of this module calls. Such functions
+            // can still be called from other modules, so the mode they are entered with is unknown
+            Ok(mode.map(Resolved::Value).unwrap_or(Resolved::Conflict))
         }
         fn resolve_mode<T: Eq + PartialEq + Copy + Default>(
             cfg: &ControlFlowGraph,
@@ -984,14 +997,12 @@ fn apply_global_mode_controls(
                         .ok_or_else(error_unreachable)?;
                     let denormal_mode = initial_mode.denormal.twin_mode;
                     let rounding_mode = initial_mode.rounding.twin_mode;
-                    method.flush_to_zero_f32 =
-                        denormal_mode.f32.ok_or_else(error_unreachable)?.to_ftz();
-                    method.flush_to_zero_f16f64 =
-                        denormal_mode.f16f64.ok_or_else(error_unreachable)?.to_ftz();
-                    method.rounding_mode_f32 =
-                        rounding_mode.f32.ok_or_else(error_unreachable)?.to_ast();
-                    method.rounding_mode_f16f64 =
-                        rounding_mode.f16f64.ok_or_else(error_unreachable)?.to_ast();
+                    // Kernels always have a known entry mode, a function that no kernel of
+                    // this module calls gets a conflict for modes its entry block doesn't use
+                    method.flush_to_zero_f32 = denormal_mode.f32.unwrap_or_default().to_ftz();
+                    method.flush_to_zero_f16f64 = denormal_mode.f16f64.unwrap_or_default().to_ftz();
+                    method.rounding_mode_f32 = rounding_mode.f32.unwrap_or_default().to_ast();
+                    method.rounding_mode_f16f64 = rounding_mode.f16f64.unwrap_or_default().to_ast();
                     (method, initial_mode)
                 }
             };
@@ -1370,16 +1381,6 @@ impl<T> Resolved<T> {
             Resolved::Conflict => Resolved::Conflict,
         }
     }
-
-    fn ok_or_else<E, F>(self, err: F) -> Result<T, E>
-    where
-        F: FnOnce() -> E,
-    {
-        match self {
-            Resolved::Value(v) => Ok(v),
-            Resolved::Conflict => Err(err()),
-        }
-    }
 }
 
 trait ModeView {
@@ -1615,7 +1616,7 @@ fn compute_single_mode_insertions<T: Copy + Eq>(
     mut getter: impl FnMut(&Node) -> Mode<T>,
 ) -> PartialModeInsertion<T> {
     let mut must_insert_mode = FxHashSet::<SpirvWord>::default();
-    let mut maybe_insert_mode = FxHashMap::default();
+    let mut maybe_insert_mode = FxIndexMap::default();
     let mut remaining = graph
         .graph
         .node_references()
@@ -1642,8 +1643,18 @@ fn compute_single_mode_insertions<T: Copy + Eq>(
             let exit_mode = getter(graph.graph.node_weight(current).unwrap()).exit;
             match exit_mode {
                 None => {
-                    for predecessor in graph.graph.neighbors_directed(current, Direction::Incoming)
-                    {
+                    let mut predecessors = graph
+                        .graph
+                        .neighbors_directed(current, Direction::Incoming)
+                        .peekable();
+                    // Entry of a function no kernel in this module calls or an unreachable
+                    // basic block: the incoming mode is unknown
+                    if predecessors.peek().is_none() {
+                        maybe_insert_mode.shift_remove(&node_id);
+                        must_insert_mode.insert(node_id);
+                        continue 'next_basic_block;
+                    }
+                    for predecessor in predecessors {
                         if !visited.contains(&predecessor) {
                             to_visit.push(predecessor);
                         }
@@ -1651,16 +1662,17 @@ fn compute_single_mode_insertions<T: Copy + Eq>(
                 }
                 Some(ExtendedMode::BasicBlock(mode)) => {
                     if mode != expected_mode {
-                        maybe_insert_mode.remove(&node_id);
+                        maybe_insert_mode.shift_remove(&node_id);
                         must_insert_mode.insert(node_id);
                         continue 'next_basic_block;
                     }
                 }
                 Some(ExtendedMode::Entry(kernel)) => match maybe_insert_mode.entry(node_id) {
-                    std::collections::hash_map::Entry::Vacant(entry) => {
-                        entry.insert((expected_mode, iter::once(kernel).collect::<FxHashSet<_>>()));
+                    indexmap::map::Entry::Vacant(entry) => {
+                        entry
+                            .insert((expected_mode, iter::once(kernel).collect::<FxIndexSet<_>>()));
                     }
-                    std::collections::hash_map::Entry::Occupied(mut entry) => {
+                    indexmap::map::Entry::Occupied(mut entry) => {
                         entry.get_mut().1.insert(kernel);
                     }
                 },
@@ -1676,7 +1688,7 @@ fn compute_single_mode_insertions<T: Copy + Eq>(
 #[derive(Debug)]
 struct PartialModeInsertion<T> {
     bb_must_insert_mode: FxHashSet<SpirvWord>,
-    bb_maybe_insert_mode: FxHashMap<SpirvWord, (T, FxHashSet<SpirvWord>)>,
+    bb_maybe_insert_mode: FxIndexMap<SpirvWord, (T, FxIndexSet<SpirvWord>)>,
 }
 
 // Only returns kernel mode insertions if a kernel is relevant to the optimization problem
@@ -1687,7 +1699,7 @@ fn optimize_mode_insertions<
     partial: PartialModeInsertion<T>,
 ) -> MandatoryModeInsertions<T> {
     let mut problem = Problem::new(OptimizationDirection::Maximize);
-    let mut kernel_modes = FxHashMap::default();
+    let mut kernel_modes = FxIndexMap::default();
     let basic_block_variables = partial
         .bb_maybe_insert_mode
         .into_iter()
@@ -1847,7 +1859,14 @@ fn get_modes<T: ast::Operand>(inst: &ast::Instruction<T>) -> InstructionModes {
         | ast::Instruction::Ret { .. }
         | ast::Instruction::BarWarp { .. }
         | ast::Instruction::Bar { .. }
+        | ast::Instruction::BarArrive { .. }
+        | ast::Instruction::BarrierCluster { .. }
+        | ast::Instruction::Mma { .. }
+        | ast::Instruction::WmmaLoad { .. }
+        | ast::Instruction::WmmaStore { .. }
+        | ast::Instruction::WmmaMma { .. }
         | ast::Instruction::BarRed { .. }
+        | ast::Instruction::BarRedPopc { .. }
         | ast::Instruction::Cvta { .. }
         | ast::Instruction::Atom { .. }
         | ast::Instruction::Mul24 { .. }
@@ -1869,6 +1888,9 @@ fn get_modes<T: ast::Operand>(inst: &ast::Instruction<T>) -> InstructionModes {
             data: ast::MadDetails::Integer { .. },
             ..
         }
+        | ast::Instruction::AddExtended { .. }
+        | ast::Instruction::SubExtended { .. }
+        | ast::Instruction::MadExtended { .. }
         | ast::Instruction::Min {
             data: ast::MinMaxDetails::Signed(..) | ast::MinMaxDetails::Unsigned(..),
             ..
@@ -1976,9 +1998,10 @@ fn get_modes<T: ast::Operand>(inst: &ast::Instruction<T>) -> InstructionModes {
                 ast::DivFloatKind::Approx => RoundingMode::NearestEven,
                 ast::DivFloatKind::ApproxFull => RoundingMode::NearestEven,
             };
+            // div.f64 has no .ftz and never flushes subnormals
             InstructionModes::new(
                 *type_,
-                flush_to_zero.map(DenormalMode::from_ftz),
+                Some(DenormalMode::from_ftz(flush_to_zero.unwrap_or(false))),
                 Some(rounding),
             )
         }
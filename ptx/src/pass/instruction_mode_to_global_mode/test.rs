@@ -193,7 +193,8 @@ fn compile_methods(ptx: &str) -> Vec<Function2<ast::Instruction<SpirvWord>, Spir
     use crate::pass::*;
 
     let module = ptx_parser::parse_module_checked(ptx).unwrap();
-    let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1));
+    let names = NameInterner::default();
+    let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1), &names);
     let mut scoped_resolver = ScopedResolver::new(&mut flat_resolver);
     let directives = normalize_identifiers2::run(&mut scoped_resolver, module.directives).unwrap();
     let directives = normalize_predicates2::run(&mut flat_resolver, directives).unwrap();
@@ -397,3 +398,46 @@ fn conditionals<const N: usize>(
         .try_into()
         .unwrap()
 }
+
+// Nothing in the module calls `library_add` and nothing jumps to the label after `ret`, so the
+// mode they are entered with is unknown and the function has to set the mode it needs itself
+#[test]
+fn uncalled_function_sets_its_mode() {
+    let methods = compile_methods(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .func (.reg .f32 result) library_add(
+            .reg .f32 a,
+            .reg .f32 b
+        )
+        {
+            add.rn.ftz.f32 result, a, b;
+            ret;
+        unreachable:
+            ret;
+        }",
+    );
+    let body = methods[0].body.as_ref().unwrap();
+    let set_denormal = body
+        .iter()
+        .position(|statement| {
+            matches!(
+                statement,
+                Statement::SetMode(ModeRegister::Denormal { f32: true, .. })
+            )
+        })
+        .unwrap();
+    let add = body
+        .iter()
+        .position(|statement| {
+            matches!(
+                statement,
+                Statement::Instruction(ast::Instruction::Add { .. })
+            )
+        })
+        .unwrap();
+    assert!(set_denormal < add);
+}
@@ -0,0 +1,1991 @@
+// Reference interpreter of the statements left after the last pass. It runs the threads of a
+// launch one at a time over a single flat address space and gives every statement the meaning
+// LLVM emit gives it, but without going through LLVM, comgr and the GPU. When a kernel gives
+// different results on CUDA and HIP, running it here tells a bug in the passes from a bug in the
+// backend.
+// Floating point statements follow the mode register (set by SetMode and the kernel attributes),
+// not the modifiers of the instruction, same as the code emitted for AMD GPUs does
+use super::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+// Statements a single thread can execute before it's considered stuck
+const STEP_LIMIT: u64 = 1 << 24;
+const CALL_DEPTH_LIMIT: usize = 256;
+// Addresses start above zero, so null pointers fault, and stay below 4GB, so 32 bit pointers to
+// .shared and .local memory are valid too
+const MEMORY_BASE: u64 = 0x1000;
+const MEMORY_LIMIT: u64 = 1 << 32;
+
+#[derive(Debug)]
+pub(crate) enum InterpretError {
+    // The kernel uses something the interpreter does not model (warp-level operations, barriers
+    // in blocks with more than one thread, approximate math), the run says nothing about it
+    Unsupported(String),
+    // The kernel did something undefined or something that would crash on the GPU
+    Fault(String),
+}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpretError::Unsupported(what) => write!(f, "unsupported: {what}"),
+            InterpretError::Fault(what) => write!(f, "fault: {what}"),
+        }
+    }
+}
+
+fn unsupported<T>(what: impl fmt::Display) -> Result<T, InterpretError> {
+    Err(InterpretError::Unsupported(what.to_string()))
+}
+
+fn fault<T>(what: impl fmt::Display) -> Result<T, InterpretError> {
+    Err(InterpretError::Fault(what.to_string()))
+}
+
+/// Memory of the simulated device. Allocations are bump-allocated and bounds checked, every
+/// state space lives in the same address space
+#[derive(Default)]
+pub(crate) struct Memory {
+    bytes: Vec<u8>,
+    // Address -> size of every live allocation
+    allocations: BTreeMap<u64, usize>,
+}
+
+impl Memory {
+    pub(crate) fn alloc(&mut self, size: usize, align: u64) -> Result<u64, InterpretError> {
+        let end = MEMORY_BASE + self.bytes.len() as u64;
+        let address = end.next_multiple_of(align.max(16));
+        // Every allocation gets a distinct address, even an empty one
+        let new_end = address + size.max(1) as u64;
+        if new_end > MEMORY_LIMIT {
+            return fault(format!("out of memory allocating {size} bytes"));
+        }
+        self.bytes.resize((new_end - MEMORY_BASE) as usize, 0);
+        self.allocations.insert(address, size);
+        Ok(address)
+    }
+
+    pub(crate) fn free(&mut self, address: u64) {
+        self.allocations.remove(&address);
+    }
+
+    pub(crate) fn read(&self, address: u64, len: usize) -> Result<&[u8], InterpretError> {
+        let range = self.range(address, len)?;
+        Ok(&self.bytes[range])
+    }
+
+    pub(crate) fn write(&mut self, address: u64, data: &[u8]) -> Result<(), InterpretError> {
+        let range = self.range(address, data.len())?;
+        self.bytes[range].copy_from_slice(data);
+        Ok(())
+    }
+
+    // Everything allocated after the mark is freed by release, used for variables of threads,
+    // blocks and calls
+    fn mark(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn release(&mut self, mark: usize) {
+        self.bytes.truncate(mark);
+        let end = MEMORY_BASE + mark as u64;
+        self.allocations.retain(|&address, _| address < end);
+    }
+
+    fn range(&self, address: u64, len: usize) -> Result<Range<usize>, InterpretError> {
+        let out_of_bounds = || {
+            InterpretError::Fault(format!(
+                "access of {len} bytes at {address:#x} is out of bounds"
+            ))
+        };
+        let (&start, &size) = self
+            .allocations
+            .range(..=address)
+            .next_back()
+            .ok_or_else(out_of_bounds)?;
+        let end = address.checked_add(len as u64).ok_or_else(out_of_bounds)?;
+        if end > start + size as u64 {
+            return Err(out_of_bounds());
+        }
+        let offset = (address - MEMORY_BASE) as usize;
+        Ok(offset..offset + len)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Launch {
+    pub(crate) grid: (u32, u32, u32),
+    pub(crate) block: (u32, u32, u32),
+    /// Size of the dynamic .shared memory
+    pub(crate) shared_memory: u32,
+}
+
+/// Module after all the passes, ready to run
+pub(crate) struct Program<'input> {
+    resolver: GlobalStringIdentResolver2<'input>,
+    functions: FxHashMap<SpirvWord, Function>,
+    variables: Vec<ast::Variable<SpirvWord>>,
+}
+
+struct Function {
+    method: Function2<ast::Instruction<SpirvWord>, SpirvWord>,
+    // Position of every label in the body
+    labels: FxHashMap<SpirvWord, usize>,
+}
+
+impl<'a> Program<'a> {
+    pub(crate) fn new<'input: 'a>(
+        ast: ast::Module<'input>,
+        options: &TranslateOptions,
+        names: &'a NameInterner,
+    ) -> Result<Self, TranslateError> {
+        let text = ast.text;
+        let Lowered {
+            resolver,
+            directives,
+            ..
+        } = lower(ast, options, names).map_err(|err| err.resolve_location(text))?;
+        let mut functions = FxHashMap::default();
+        let mut variables = Vec::new();
+        for directive in directives {
+            match directive {
+                Directive2::Variable(_, variable) => variables.push(variable),
+                Directive2::Method(method) => {
+                    // Keep the definition when a function is both declared and defined
+                    if method.body.is_none() && functions.contains_key(&method.name) {
+                        continue;
+                    }
+                    let labels = method
+                        .body
+                        .iter()
+                        .flatten()
+                        .enumerate()
+                        .filter_map(|(index, statement)| match statement {
+                            Statement::Label(label) => Some((*label, index)),
+                            _ => None,
+                        })
+                        .collect();
+                    functions.insert(method.name, Function { method, labels });
+                }
+            }
+        }
+        Ok(Self {
+            resolver,
+            functions,
+            variables,
+        })
+    }
+
+    /// Runs every thread of the launch in order, `params` are the bytes of the kernel arguments
+    pub(crate) fn run(
+        &self,
+        memory: &mut Memory,
+        kernel: &str,
+        params: &[Vec<u8>],
+        launch: Launch,
+    ) -> Result<(), InterpretError> {
+        let entry = self
+            .functions
+            .values()
+            .find(|function| {
+                function.method.is_kernel
+                    && function.method.body.is_some()
+                    && self.name(function.method.name) == Some(kernel)
+            })
+            .ok_or_else(|| InterpretError::Fault(format!("no kernel named {kernel}")))?;
+        if entry.method.input_arguments.len() != params.len() {
+            return fault(format!(
+                "{kernel} takes {} arguments, got {}",
+                entry.method.input_arguments.len(),
+                params.len()
+            ));
+        }
+        let mark = memory.mark();
+        let result = self.run_grid(memory, &entry.method, params, launch);
+        memory.release(mark);
+        result
+    }
+
+    fn run_grid(
+        &self,
+        memory: &mut Memory,
+        entry: &Function2<ast::Instruction<SpirvWord>, SpirvWord>,
+        params: &[Vec<u8>],
+        launch: Launch,
+    ) -> Result<(), InterpretError> {
+        // Kernel arguments are pointers to the parameter bytes, same as in emit
+        let arguments = params
+            .iter()
+            .map(|param| {
+                let address = memory.alloc(param.len(), 16)?;
+                memory.write(address, param)?;
+                Ok(Value::Scalar(address))
+            })
+            .collect::<Result<Vec<_>, InterpretError>>()?;
+        let mut globals = FxHashMap::default();
+        self.allocate_variables(memory, &mut globals, launch, |space| {
+            !matches!(space, ast::StateSpace::Shared | ast::StateSpace::Local)
+        })?;
+        for ctaid in indices(launch.grid) {
+            let block_mark = memory.mark();
+            let mut block_globals = globals.clone();
+            self.allocate_variables(memory, &mut block_globals, launch, |space| {
+                space == ast::StateSpace::Shared
+            })?;
+            for tid in indices(launch.block) {
+                let thread_mark = memory.mark();
+                let mut thread_globals = block_globals.clone();
+                self.allocate_variables(memory, &mut thread_globals, launch, |space| {
+                    space == ast::StateSpace::Local
+                })?;
+                let mut thread = Thread {
+                    program: self,
+                    memory: &mut *memory,
+                    globals: thread_globals,
+                    launch,
+                    ctaid,
+                    tid,
+                    mode: FpMode {
+                        ftz_f32: entry.flush_to_zero_f32,
+                        ftz_f16f64: entry.flush_to_zero_f16f64,
+                        rounding_f32: entry.rounding_mode_f32,
+                        rounding_f16f64: entry.rounding_mode_f16f64,
+                    },
+                    carry: false,
+                    steps: 0,
+                };
+                thread.call(entry.name, arguments.clone(), 0)?;
+                memory.release(thread_mark);
+            }
+            memory.release(block_mark);
+        }
+        Ok(())
+    }
+
+    // Module-level variables in the state spaces matching `filter`. All the dynamic .shared
+    // arrays (`.extern .shared .b8 x[]`) alias the same allocation
+    fn allocate_variables(
+        &self,
+        memory: &mut Memory,
+        globals: &mut FxHashMap<SpirvWord, Value>,
+        launch: Launch,
+        filter: impl Fn(ast::StateSpace) -> bool,
+    ) -> Result<(), InterpretError> {
+        let mut dynamic_shared = None;
+        for variable in self
+            .variables
+            .iter()
+            .filter(|variable| filter(variable.state_space))
+        {
+            let is_dynamic = is_dynamic_shared(variable);
+            let address = match dynamic_shared {
+                Some(address) if is_dynamic => address,
+                _ if is_dynamic => {
+                    let address = memory.alloc(launch.shared_memory as usize, 16)?;
+                    dynamic_shared = Some(address);
+                    address
+                }
+                _ => allocate_variable(memory, variable)?,
+            };
+            globals.insert(variable.name, Value::Scalar(address));
+        }
+        // Initializers can hold addresses of variables declared after them, they are written once
+        // every variable has been allocated. State spaces share addresses, so generic() is a no-op
+        for variable in self
+            .variables
+            .iter()
+            .filter(|variable| filter(variable.state_space))
+        {
+            let (scalar, _) = elements_of(&variable.v_type)?;
+            for relocation in variable.array_init.relocations.iter() {
+                let (base, target) =
+                    match (globals.get(&variable.name), globals.get(&relocation.symbol)) {
+                        (Some(Value::Scalar(base)), Some(Value::Scalar(target))) => {
+                            (*base, *target)
+                        }
+                        _ => {
+                            return unsupported(format!(
+                                "initializer with the address of {}",
+                                self.name(relocation.symbol).unwrap_or_default()
+                            ))
+                        }
+                    };
+                let address = target.wrapping_add_signed(relocation.addend);
+                let value = match relocation.byte {
+                    Some(byte) => (address >> (byte * 8)) & 0xff,
+                    None => address,
+                };
+                write_scalar(memory, base + relocation.offset as u64, scalar, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&self, id: SpirvWord) -> Option<&str> {
+        self.resolver.ident_map.get(&id)?.name.as_deref()
+    }
+
+    // Static .shared variables laid out one after another
+    fn static_shared_size(&self) -> u64 {
+        let size = self
+            .variables
+            .iter()
+            .filter(|variable| {
+                variable.state_space == ast::StateSpace::Shared && !is_dynamic_shared(variable)
+            })
+            .fold(0usize, |offset, variable| {
+                let layout = variable.v_type.layout();
+                let align = variable
+                    .align
+                    .map_or(layout.align(), |align| align as usize);
+                offset.next_multiple_of(align) + layout.size()
+            });
+        size as u64
+    }
+}
+
+fn is_dynamic_shared(variable: &ast::Variable<SpirvWord>) -> bool {
+    variable.state_space == ast::StateSpace::Shared
+        && matches!(&variable.v_type, ast::Type::Array(_, _, dimensions) if dimensions.first() == Some(&0))
+}
+
+fn allocate_variable(
+    memory: &mut Memory,
+    variable: &ast::Variable<SpirvWord>,
+) -> Result<u64, InterpretError> {
+    let address = memory.alloc(
+        variable.v_type.layout().size(),
+        variable.align.unwrap_or(0) as u64,
+    )?;
+    if !variable.array_init.is_empty() {
+        memory.write(address, &variable.array_init.bytes)?;
+    }
+    Ok(address)
+}
+
+// Same order as CUDA numbers threads: x changes fastest
+fn indices((x, y, z): (u32, u32, u32)) -> impl Iterator<Item = (u32, u32, u32)> {
+    (0..z).flat_map(move |k| (0..y).flat_map(move |j| (0..x).map(move |i| (i, j, k))))
+}
+
+// Registers hold scalars as bits of their type, zero-extended. Vectors and arrays hold one
+// entry per element
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Scalar(u64),
+    Vector(Vec<u64>),
+}
+
+#[derive(Default)]
+struct Frame(FxHashMap<SpirvWord, Value>);
+
+impl Frame {
+    fn get(&self, id: SpirvWord) -> Result<&Value, InterpretError> {
+        self.0.get(&id).ok_or_else(|| {
+            InterpretError::Fault(format!("read of %{} before it was written", id.0))
+        })
+    }
+
+    fn scalar(&self, id: SpirvWord) -> Result<u64, InterpretError> {
+        match self.get(id)? {
+            Value::Scalar(value) => Ok(*value),
+            Value::Vector(_) => fault(format!("%{} is a vector, expected a scalar", id.0)),
+        }
+    }
+
+    fn vector(&self, id: SpirvWord) -> Result<&[u64], InterpretError> {
+        match self.get(id)? {
+            Value::Vector(elements) => Ok(elements),
+            Value::Scalar(_) => fault(format!("%{} is a scalar, expected a vector", id.0)),
+        }
+    }
+
+    fn set(&mut self, id: SpirvWord, value: Value) {
+        self.0.insert(id, value);
+    }
+
+    fn set_scalar(&mut self, id: SpirvWord, type_: ast::ScalarType, value: u64) {
+        self.set(id, Value::Scalar(value & mask(type_)));
+    }
+}
+
+enum Flow {
+    Next,
+    Jump(SpirvWord),
+    Return(Vec<Value>),
+}
+
+// Floating point mode register. Like on the GPU it belongs to the thread, changes made by
+// a called function outlive the call
+#[derive(Clone, Copy)]
+struct FpMode {
+    ftz_f32: bool,
+    ftz_f16f64: bool,
+    rounding_f32: ast::RoundingMode,
+    rounding_f16f64: ast::RoundingMode,
+}
+
+impl FpMode {
+    fn ftz(&self, type_: ast::ScalarType) -> bool {
+        match type_ {
+            ast::ScalarType::F32 => self.ftz_f32,
+            _ => self.ftz_f16f64,
+        }
+    }
+
+    fn rounding(&self, type_: ast::ScalarType) -> ast::RoundingMode {
+        match type_ {
+            ast::ScalarType::F32 => self.rounding_f32,
+            _ => self.rounding_f16f64,
+        }
+    }
+}
+
+struct Thread<'a, 'input> {
+    program: &'a Program<'input>,
+    memory: &'a mut Memory,
+    // Addresses of the module-level variables this thread sees
+    globals: FxHashMap<SpirvWord, Value>,
+    launch: Launch,
+    ctaid: (u32, u32, u32),
+    tid: (u32, u32, u32),
+    mode: FpMode,
+    // CC.CF of the extended precision instructions
+    carry: bool,
+    steps: u64,
+}
+
+impl Thread<'_, '_> {
+    fn call(
+        &mut self,
+        id: SpirvWord,
+        arguments: Vec<Value>,
+        depth: usize,
+    ) -> Result<Vec<Value>, InterpretError> {
+        let program = self.program;
+        let function = match program.functions.get(&id) {
+            Some(function) if function.method.body.is_some() => function,
+            _ => return self.builtin(id, &arguments),
+        };
+        if depth == CALL_DEPTH_LIMIT {
+            return fault("call stack overflow");
+        }
+        let mark = self.memory.mark();
+        let result = self.run_function(function, arguments, depth);
+        self.memory.release(mark);
+        result
+    }
+
+    fn run_function(
+        &mut self,
+        function: &Function,
+        arguments: Vec<Value>,
+        depth: usize,
+    ) -> Result<Vec<Value>, InterpretError> {
+        let body = function.method.body.as_deref().unwrap_or_default();
+        let mut frame = Frame(self.globals.clone());
+        for (argument, value) in function.method.input_arguments.iter().zip(arguments) {
+            frame.set(argument.name, value);
+        }
+        // Like in emit, all the variables are allocated on entry
+        let variables = body.iter().filter_map(|statement| match statement {
+            Statement::Variable(variable) => Some(variable),
+            _ => None,
+        });
+        for variable in function.method.return_arguments.iter().chain(variables) {
+            let address = allocate_variable(self.memory, variable)?;
+            frame.set(variable.name, Value::Scalar(address));
+        }
+        // Constants are emitted as LLVM constants, so deduplicate_values lets a constant be used
+        // in basic blocks its definition doesn't dominate
+        for statement in body {
+            if let Statement::Constant(constant) = statement {
+                let value = constant_bits(constant.typ, constant.value)?;
+                frame.set_scalar(constant.dst, constant.typ, value);
+            }
+        }
+        let mut index = 0;
+        loop {
+            let statement = body
+                .get(index)
+                .ok_or_else(|| InterpretError::Fault("fell off the end of a function".into()))?;
+            index += 1;
+            self.steps += 1;
+            if self.steps > STEP_LIMIT {
+                return fault(format!(
+                    "thread {:?} did not finish in {STEP_LIMIT} steps",
+                    self.tid
+                ));
+            }
+            match self.statement(&mut frame, statement, depth)? {
+                Flow::Next => {}
+                Flow::Jump(label) => {
+                    index = *function.labels.get(&label).ok_or_else(|| {
+                        InterpretError::Fault(format!("jump to missing label %{}", label.0))
+                    })?
+                }
+                Flow::Return(values) => return Ok(values),
+            }
+        }
+    }
+
+    fn statement(
+        &mut self,
+        frame: &mut Frame,
+        statement: &Statement<ast::Instruction<SpirvWord>, SpirvWord>,
+        depth: usize,
+    ) -> Result<Flow, InterpretError> {
+        match statement {
+            Statement::Label(_)
+            | Statement::Variable(_)
+            | Statement::Constant(_)
+            | Statement::FpModeRequired { .. } => {}
+            Statement::Instruction(instruction) => {
+                return self.instruction(frame, instruction, depth)
+            }
+            Statement::Conditional(condition) => {
+                return Ok(Flow::Jump(if frame.scalar(condition.predicate)? != 0 {
+                    condition.if_true
+                } else {
+                    condition.if_false
+                }))
+            }
+            Statement::Conversion(conversion) => {
+                let value = convert(frame.get(conversion.src)?.clone(), conversion)?;
+                frame.set(conversion.dst, value);
+            }
+            Statement::RetValue(_, values) => {
+                let values = values
+                    .iter()
+                    .map(|(id, type_)| load(self.memory, frame.scalar(*id)?, type_))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(Flow::Return(values));
+            }
+            Statement::PtrAccess(access) => {
+                let offset = frame.scalar(access.offset_src)?;
+                // GEP sign-extends narrower offsets
+                let offset = match self.program.resolver.get_typed(access.offset_src) {
+                    Ok((ast::Type::Scalar(type_), _)) => sign_extend(offset, *type_) as u64,
+                    _ => offset,
+                };
+                let address = frame.scalar(access.ptr_src)?.wrapping_add(offset);
+                frame.set(access.dst, Value::Scalar(address));
+            }
+            Statement::RepackVector(repack) => repack_vector(frame, repack)?,
+            Statement::FunctionPointer(_) => return unsupported("function pointers"),
+            Statement::VectorRead(read) => {
+                let element = element(frame.vector(read.vector_src)?, read.member)?;
+                frame.set(read.scalar_dst, Value::Scalar(element));
+            }
+            Statement::VectorWrite(write) => {
+                let mut elements = frame.vector(write.vector_src)?.to_vec();
+                let scalar = frame.scalar(write.scalar_src)?;
+                match elements.get_mut(write.member as usize) {
+                    Some(element) => *element = scalar,
+                    None => return fault(format!("write of vector member {}", write.member)),
+                }
+                frame.set(write.vector_dst, Value::Vector(elements));
+            }
+            Statement::SetMode(ModeRegister::Denormal { f32, f16f64 }) => {
+                self.mode.ftz_f32 = *f32;
+                self.mode.ftz_f16f64 = *f16f64;
+            }
+            Statement::SetMode(ModeRegister::Rounding { f32, f16f64 }) => {
+                self.mode.rounding_f32 = *f32;
+                self.mode.rounding_f16f64 = *f16f64;
+            }
+            // maxnum(minnum(x, 1), 0), so NaN saturates to 0
+            Statement::FpSaturate { dst, src, type_ } => {
+                let value = self.float_input(*type_, frame.scalar(*src)?)?;
+                let value = if value > 0.0 { value.min(1.0) } else { 0.0 };
+                frame.set_scalar(*dst, *type_, exact_float(*type_, value)?);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn instruction(
+        &mut self,
+        frame: &mut Frame,
+        instruction: &ast::Instruction<SpirvWord>,
+        depth: usize,
+    ) -> Result<Flow, InterpretError> {
+        match instruction {
+            ast::Instruction::Mov { arguments, .. } => {
+                let value = frame.get(arguments.src)?.clone();
+                frame.set(arguments.dst, value);
+            }
+            ast::Instruction::Ld { data, arguments } => {
+                // Volatile only matters when another agent writes the memory
+                if !matches!(
+                    data.qualifier,
+                    ast::LdStQualifier::Weak | ast::LdStQualifier::Volatile
+                ) {
+                    return unsupported("ld with memory ordering");
+                }
+                let value = load(self.memory, frame.scalar(arguments.src)?, &data.typ)?;
+                frame.set(arguments.dst, value);
+            }
+            ast::Instruction::St { data, arguments } => {
+                if !matches!(
+                    data.qualifier,
+                    ast::LdStQualifier::Weak | ast::LdStQualifier::Volatile
+                ) {
+                    return unsupported("st with memory ordering");
+                }
+                let address = frame.scalar(arguments.src1)?;
+                store(self.memory, address, &data.typ, frame.get(arguments.src2)?)?;
+            }
+            ast::Instruction::Add { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let (type_, result) = match data {
+                    ast::ArithDetails::Integer(integer) => (
+                        integer.type_,
+                        add_integer(integer.type_, integer.saturate, a, b, false),
+                    ),
+                    ast::ArithDetails::Float(float) => {
+                        (float.type_, self.add_float(float.type_, a, b, false)?)
+                    }
+                };
+                frame.set_scalar(arguments.dst, type_, result);
+            }
+            ast::Instruction::Sub { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let (type_, result) = match data {
+                    ast::ArithDetails::Integer(integer) => (
+                        integer.type_,
+                        add_integer(integer.type_, integer.saturate, a, b, true),
+                    ),
+                    ast::ArithDetails::Float(float) => {
+                        (float.type_, self.add_float(float.type_, a, b, true)?)
+                    }
+                };
+                frame.set_scalar(arguments.dst, type_, result);
+            }
+            ast::Instruction::AddExtended { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let result = self.add_with_carry(data, a, b, false);
+                frame.set_scalar(arguments.dst, data.type_, result);
+            }
+            ast::Instruction::SubExtended { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let result = self.add_with_carry(data, a, b, true);
+                frame.set_scalar(arguments.dst, data.type_, result);
+            }
+            ast::Instruction::Mul { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let result = match data {
+                    ast::MulDetails::Integer { type_, control } => {
+                        multiply_integer(*type_, *control, a, b)
+                    }
+                    ast::MulDetails::Float(float) => {
+                        let (a, b) = (
+                            self.float_input(float.type_, a)?,
+                            self.float_input(float.type_, b)?,
+                        );
+                        self.float_result(float.type_, multiply_exact(a, b))?
+                    }
+                };
+                frame.set(arguments.dst, Value::Scalar(result));
+            }
+            ast::Instruction::Mad { data, arguments } => {
+                let (a, b, c) = (
+                    frame.scalar(arguments.src1)?,
+                    frame.scalar(arguments.src2)?,
+                    frame.scalar(arguments.src3)?,
+                );
+                let result = match data {
+                    ast::MadDetails::Integer {
+                        control,
+                        saturate,
+                        type_,
+                    } => {
+                        let product = multiply_integer(*type_, *control, a, b);
+                        match control {
+                            ast::MulIntControl::Wide => {
+                                let wide_bits = 2 * bit_width(*type_);
+                                product.wrapping_add(c) & mask_bits(wide_bits)
+                            }
+                            _ => add_integer(*type_, *saturate, product, c, false),
+                        }
+                    }
+                    ast::MadDetails::Float(float) => self.fma(float.type_, a, b, c)?,
+                };
+                frame.set(arguments.dst, Value::Scalar(result));
+            }
+            ast::Instruction::MadExtended { data, arguments } => {
+                let (a, b, c) = (
+                    frame.scalar(arguments.src1)?,
+                    frame.scalar(arguments.src2)?,
+                    frame.scalar(arguments.src3)?,
+                );
+                let control = if data.is_hi {
+                    ast::MulIntControl::High
+                } else {
+                    ast::MulIntControl::Low
+                };
+                let product = multiply_integer(data.carry.type_, control, a, b);
+                let result = self.add_with_carry(&data.carry, product, c, false);
+                frame.set_scalar(arguments.dst, data.carry.type_, result);
+            }
+            ast::Instruction::Fma { data, arguments } => {
+                let result = self.fma(
+                    data.type_,
+                    frame.scalar(arguments.src1)?,
+                    frame.scalar(arguments.src2)?,
+                    frame.scalar(arguments.src3)?,
+                )?;
+                frame.set_scalar(arguments.dst, data.type_, result);
+            }
+            ast::Instruction::Mul24 { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let operand = |value: u64| -> i64 {
+                    if data.type_.kind() == ast::ScalarKind::Signed {
+                        ((value << 40) as i64) >> 40
+                    } else {
+                        (value & 0xff_ffff) as i64
+                    }
+                };
+                let product = operand(a) * operand(b);
+                let result = match data.control {
+                    ast::Mul24Control::Lo => product as u64,
+                    ast::Mul24Control::Hi => (product >> 16) as u64,
+                };
+                frame.set_scalar(arguments.dst, data.type_, result);
+            }
+            ast::Instruction::Div { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let (type_, result) = match data {
+                    ast::DivDetails::Unsigned(type_) | ast::DivDetails::Signed(type_) => {
+                        (*type_, divide_integer(*type_, a, b, false)?)
+                    }
+                    ast::DivDetails::Float(ast::DivFloatDetails {
+                        type_,
+                        kind: ast::DivFloatKind::Rounding(_),
+                        ..
+                    }) => {
+                        let (a, b) = (self.float_input(*type_, a)?, self.float_input(*type_, b)?);
+                        (*type_, self.float_result(*type_, divide_exact(a, b))?)
+                    }
+                    ast::DivDetails::Float(_) => return unsupported("approximate division"),
+                };
+                frame.set_scalar(arguments.dst, type_, result);
+            }
+            ast::Instruction::Rem { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                frame.set_scalar(arguments.dst, *data, divide_integer(*data, a, b, true)?);
+            }
+            ast::Instruction::Min {
+                data,
+                arguments: ast::MinArgs { dst, src1, src2 },
+            }
+            | ast::Instruction::Max {
+                data,
+                arguments: ast::MaxArgs { dst, src1, src2 },
+            } => {
+                let is_max = matches!(instruction, ast::Instruction::Max { .. });
+                let (a, b) = (frame.scalar(*src1)?, frame.scalar(*src2)?);
+                let (type_, result) = match data {
+                    ast::MinMaxDetails::Signed(type_) | ast::MinMaxDetails::Unsigned(type_) => {
+                        let pick_a = (integer(*type_, a) < integer(*type_, b)) != is_max;
+                        (*type_, if pick_a { a } else { b })
+                    }
+                    ast::MinMaxDetails::Float(float) => {
+                        let a = self.flush_result(float.type_, a);
+                        let b = self.flush_result(float.type_, b);
+                        (
+                            float.type_,
+                            min_max_float(float.type_, a, b, float.nan, is_max)?,
+                        )
+                    }
+                };
+                frame.set_scalar(*dst, type_, result);
+            }
+            ast::Instruction::Abs {
+                data,
+                arguments: ast::AbsArgs { dst, src },
+            }
+            | ast::Instruction::Neg {
+                data,
+                arguments: ast::NegArgs { dst, src },
+            } => {
+                let value = frame.scalar(*src)?;
+                let is_abs = matches!(instruction, ast::Instruction::Abs { .. });
+                let result = if data.type_.kind() == ast::ScalarKind::Float {
+                    let sign = sign_bit(data.type_);
+                    let result = if is_abs { value & !sign } else { value ^ sign };
+                    // llvm.canonicalize flushes only when the mode register says so
+                    if data.flush_to_zero == Some(true) {
+                        self.flush_result(data.type_, result)
+                    } else {
+                        result
+                    }
+                } else if is_abs {
+                    integer(data.type_, value).abs() as u64
+                } else {
+                    value.wrapping_neg()
+                };
+                frame.set_scalar(*dst, data.type_, result);
+            }
+            ast::Instruction::Rcp { data, arguments } => match data.kind {
+                ast::RcpKind::Approx => return unsupported("rcp.approx"),
+                ast::RcpKind::Compliant(_) => {
+                    let value = self.float_input(data.type_, frame.scalar(arguments.src)?)?;
+                    let result = self.float_result(data.type_, divide_exact(1.0, value))?;
+                    frame.set_scalar(arguments.dst, data.type_, result);
+                }
+            },
+            ast::Instruction::Sqrt { data, arguments } => match data.kind {
+                ast::RcpKind::Approx => return unsupported("sqrt.approx"),
+                ast::RcpKind::Compliant(_) => {
+                    let value = self.float_input(data.type_, frame.scalar(arguments.src)?)?;
+                    let result = self.float_result(data.type_, sqrt_exact(value))?;
+                    frame.set_scalar(arguments.dst, data.type_, result);
+                }
+            },
+            ast::Instruction::Rsqrt { .. }
+            | ast::Instruction::Sin { .. }
+            | ast::Instruction::Cos { .. }
+            | ast::Instruction::Lg2 { .. }
+            | ast::Instruction::Ex2 { .. }
+            | ast::Instruction::Tanh { .. } => {
+                return unsupported(format!("approximate {instruction}"))
+            }
+            ast::Instruction::And { arguments, .. } => {
+                let value = frame.scalar(arguments.src1)? & frame.scalar(arguments.src2)?;
+                frame.set(arguments.dst, Value::Scalar(value));
+            }
+            ast::Instruction::Or { arguments, .. } => {
+                let value = frame.scalar(arguments.src1)? | frame.scalar(arguments.src2)?;
+                frame.set(arguments.dst, Value::Scalar(value));
+            }
+            ast::Instruction::Xor { arguments, .. } => {
+                let value = frame.scalar(arguments.src1)? ^ frame.scalar(arguments.src2)?;
+                frame.set(arguments.dst, Value::Scalar(value));
+            }
+            ast::Instruction::Not { data, arguments } => {
+                frame.set_scalar(arguments.dst, *data, !frame.scalar(arguments.src)?);
+            }
+            ast::Instruction::Shl { data, arguments } => {
+                let (value, shift) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let result = if shift >= bit_width(*data) as u64 {
+                    0
+                } else {
+                    value << shift
+                };
+                frame.set_scalar(arguments.dst, *data, result);
+            }
+            ast::Instruction::Shr { data, arguments } => {
+                let (value, shift) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                // Shifting by the width or more leaves only the sign or zero bits
+                let result = match data.kind {
+                    ast::RightShiftKind::Arithmetic => {
+                        (sign_extend(value, data.type_) >> shift.min(63)) as u64
+                    }
+                    ast::RightShiftKind::Logical if shift >= bit_width(data.type_) as u64 => 0,
+                    ast::RightShiftKind::Logical => value >> shift,
+                };
+                frame.set_scalar(arguments.dst, data.type_, result);
+            }
+            ast::Instruction::Shf { data, arguments } => {
+                let low = frame.scalar(arguments.src_a)?;
+                let high = frame.scalar(arguments.src_b)?;
+                let shift = frame.scalar(arguments.src_c)? as u32;
+                let shift = match data.mode {
+                    ast::FunnelShiftMode::Clamp => shift.min(32),
+                    ast::FunnelShiftMode::Wrap => shift & 31,
+                };
+                let wide = (high << 32) | low;
+                let result = match data.direction {
+                    ast::ShiftDirection::L => (wide << shift) >> 32,
+                    ast::ShiftDirection::R => wide >> shift,
+                };
+                frame.set_scalar(arguments.dst, ast::ScalarType::B32, result);
+            }
+            ast::Instruction::Brev { data, arguments } => {
+                let value = frame.scalar(arguments.src)?;
+                let result = value.reverse_bits() >> (64 - bit_width(*data));
+                frame.set_scalar(arguments.dst, *data, result);
+            }
+            ast::Instruction::Clz { data, arguments } => {
+                let value = frame.scalar(arguments.src)?;
+                let result = value.leading_zeros() - (64 - bit_width(*data));
+                frame.set_scalar(arguments.dst, ast::ScalarType::U32, result as u64);
+            }
+            ast::Instruction::Popc { arguments, .. } => {
+                let result = frame.scalar(arguments.src)?.count_ones();
+                frame.set_scalar(arguments.dst, ast::ScalarType::U32, result as u64);
+            }
+            ast::Instruction::Prmt { data, arguments } => {
+                let bytes = (frame.scalar(arguments.src2)? << 32) | frame.scalar(arguments.src1)?;
+                let result = (0..4).fold(0, |result, index| {
+                    let selector = (*data >> (4 * index)) & 0b111;
+                    let byte = (bytes >> (8 * selector)) & 0xff;
+                    result | (byte << (8 * index))
+                });
+                frame.set_scalar(arguments.dst, ast::ScalarType::B32, result);
+            }
+            ast::Instruction::Dp4a { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let c = frame.scalar(arguments.src3)?;
+                let byte_type = match (data.atype, data.btype) {
+                    (ast::ScalarType::U32, ast::ScalarType::U32) => ast::ScalarType::U8,
+                    (ast::ScalarType::S32, ast::ScalarType::S32) => ast::ScalarType::S8,
+                    _ => return unsupported("dp4a with mixed signedness"),
+                };
+                let result = (0..4).fold(c, |result, index| {
+                    let a = integer(byte_type, a >> (8 * index));
+                    let b = integer(byte_type, b >> (8 * index));
+                    result.wrapping_add((a * b) as u64)
+                });
+                frame.set_scalar(arguments.dst, data.ctype(), result);
+            }
+            ast::Instruction::Selp { data, arguments } => {
+                let value = if frame.scalar(arguments.src3)? != 0 {
+                    frame.scalar(arguments.src1)?
+                } else {
+                    frame.scalar(arguments.src2)?
+                };
+                frame.set_scalar(arguments.dst, *data, value);
+            }
+            ast::Instruction::Setp { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let result = self.compare(data, a, b)?;
+                frame.set(arguments.dst1, Value::Scalar(result as u64));
+                if let Some(dst2) = arguments.dst2 {
+                    frame.set(dst2, Value::Scalar(!result as u64));
+                }
+            }
+            ast::Instruction::SetpBool { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let c = frame.scalar(arguments.src3)? != 0;
+                let result = self.compare(&data.base, a, b)?;
+                let c = c != data.negate_src3;
+                frame.set(
+                    arguments.dst1,
+                    Value::Scalar(bool_op(data.bool_op, result, c) as u64),
+                );
+                if let Some(dst2) = arguments.dst2 {
+                    frame.set(
+                        dst2,
+                        Value::Scalar(bool_op(data.bool_op, !result, c) as u64),
+                    );
+                }
+            }
+            ast::Instruction::Set { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let result = self.compare(&data.base, a, b)?;
+                frame.set_scalar(arguments.dst, data.dtype, set_result(data.dtype, result)?);
+            }
+            ast::Instruction::SetBool { data, arguments } => {
+                let (a, b) = (frame.scalar(arguments.src1)?, frame.scalar(arguments.src2)?);
+                let c = (frame.scalar(arguments.src3)? != 0) != data.base.negate_src3;
+                let result = self.compare(&data.base.base, a, b)?;
+                let result = bool_op(data.base.bool_op, result, c);
+                frame.set_scalar(arguments.dst, data.dtype, set_result(data.dtype, result)?);
+            }
+            ast::Instruction::Cvt { data, arguments } => {
+                let result = self.cvt(data, frame.scalar(arguments.src)?)?;
+                frame.set_scalar(arguments.dst, data.to, result);
+            }
+            // Every state space is a window into the same flat memory
+            ast::Instruction::Cvta { arguments, .. } => {
+                let value = frame.scalar(arguments.src)?;
+                frame.set(arguments.dst, Value::Scalar(value));
+            }
+            ast::Instruction::Atom { data, arguments } => {
+                let type_ = match data.type_ {
+                    ast::Type::Scalar(type_) => type_,
+                    _ => return unsupported("vector atomics"),
+                };
+                let address = frame.scalar(arguments.src1)?;
+                let operand = frame.scalar(arguments.src2)?;
+                let old = read_scalar(self.memory, address, type_)?;
+                let new = atomic(type_, data.op, old, operand)?;
+                write_scalar(self.memory, address, type_, new)?;
+                frame.set_scalar(arguments.dst, type_, old);
+            }
+            ast::Instruction::AtomCas { data, arguments } => {
+                let address = frame.scalar(arguments.src1)?;
+                let old = read_scalar(self.memory, address, data.type_)?;
+                if old == frame.scalar(arguments.src2)? & mask(data.type_) {
+                    let new = frame.scalar(arguments.src3)?;
+                    write_scalar(self.memory, address, data.type_, new)?;
+                }
+                frame.set_scalar(arguments.dst, data.type_, old);
+            }
+            // Copies are synchronous, same as in emit
+            ast::Instruction::CpAsync { data, arguments } => {
+                let size = data.cp_size.as_u64() as usize;
+                let source_size = match arguments.src_size {
+                    Some(src_size) => frame.scalar(src_size)?,
+                    None => data.src_size.unwrap_or(data.cp_size.as_u64()),
+                } as usize;
+                let mut bytes = self
+                    .memory
+                    .read(frame.scalar(arguments.src_from)?, source_size.min(size))?
+                    .to_vec();
+                bytes.resize(size, 0);
+                self.memory.write(frame.scalar(arguments.src_to)?, &bytes)?;
+            }
+            ast::Instruction::CpAsyncCommitGroup {}
+            | ast::Instruction::CpAsyncWaitGroup { .. }
+            | ast::Instruction::CpAsyncWaitAll { .. }
+            | ast::Instruction::Membar { .. } => {}
+            ast::Instruction::Bra { arguments } => return Ok(Flow::Jump(arguments.src)),
+            ast::Instruction::Call { arguments, .. } => {
+                let inputs = arguments
+                    .input_arguments
+                    .iter()
+                    .map(|id| frame.get(*id).cloned())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let outputs = self.call(arguments.func, inputs, depth + 1)?;
+                if outputs.len() != arguments.return_arguments.len() {
+                    return fault(format!(
+                        "call returned {} values, expected {}",
+                        outputs.len(),
+                        arguments.return_arguments.len()
+                    ));
+                }
+                for (id, value) in arguments.return_arguments.iter().zip(outputs) {
+                    frame.set(*id, value);
+                }
+            }
+            ast::Instruction::Ret { .. } => return Ok(Flow::Return(Vec::new())),
+            ast::Instruction::Trap {} => return fault("trap"),
+            ast::Instruction::BarWarp { .. } => return unsupported("bar.warp.sync"),
+            ast::Instruction::PrmtSlow { .. } => {
+                return unsupported("prmt with a register selector")
+            }
+            // Replaced by calls to ptx_impl functions before the last pass
+            ast::Instruction::Bfe { .. }
+            | ast::Instruction::Bfi { .. }
+            | ast::Instruction::Bar { .. }
+            | ast::Instruction::BarArrive { .. }
+            | ast::Instruction::BarrierCluster { .. }
+            | ast::Instruction::BarRed { .. }
+            | ast::Instruction::BarRedPopc { .. }
+            | ast::Instruction::Activemask { .. }
+            | ast::Instruction::ShflSync { .. }
+            | ast::Instruction::Nanosleep { .. } => {
+                return fault(format!("{instruction} should have been replaced by a call"))
+            }
+            ast::Instruction::Mma { .. }
+            | ast::Instruction::WmmaLoad { .. }
+            | ast::Instruction::WmmaStore { .. }
+            | ast::Instruction::WmmaMma { .. } => {
+                return fault(format!("{instruction} should have been expanded"))
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    // Functions without a body are implemented by ptx_impl, the ones that don't depend on other
+    // threads are modelled here
+    fn builtin(
+        &mut self,
+        function: SpirvWord,
+        arguments: &[Value],
+    ) -> Result<Vec<Value>, InterpretError> {
+        let program = self.program;
+        let full_name = program.name(function).unwrap_or_default();
+        let name = match full_name.strip_prefix(ZLUDA_PTX_PREFIX) {
+            Some(name) => name,
+            None => return unsupported(format!("call to {full_name}, which has no body")),
+        };
+        let argument = |index: usize| match arguments.get(index) {
+            Some(Value::Scalar(value)) => Ok(*value),
+            _ => fault(format!("{full_name} is missing argument {index}")),
+        };
+        let scalar = |value: u64| Ok(vec![Value::Scalar(value)]);
+        let (x, y, z) = self.launch.block;
+        let block_size = x * y * z;
+        let lane = (self.tid.0 + x * (self.tid.1 + y * self.tid.2)) % 32;
+        match name {
+            "sreg_tid" => scalar(dimension(self.tid, argument(0)?)?),
+            "sreg_ntid" => scalar(dimension(self.launch.block, argument(0)?)?),
+            "sreg_ctaid" => scalar(dimension(self.ctaid, argument(0)?)?),
+            "sreg_nctaid" => scalar(dimension(self.launch.grid, argument(0)?)?),
+            "sreg_lanemask_lt" => scalar((1 << lane) - 1),
+            "sreg_dynamic_smem_size" => scalar(self.launch.shared_memory as u64),
+            "sreg_total_smem_size" => {
+                scalar(program.static_shared_size() + self.launch.shared_memory as u64)
+            }
+            "activemask" | "bar_sync" | "bar_sync_count" | "bar_arrive" | "bar_red_and_pred"
+            | "bar_red_or_pred" | "bar_red_popc_u32"
+                if block_size > 1 =>
+            {
+                unsupported(format!("{name} in a block of {block_size} threads"))
+            }
+            "activemask" => scalar(1),
+            "bar_sync" | "bar_sync_init" | "bar_sync_count" | "bar_arrive" | "nanosleep_u32" => {
+                Ok(Vec::new())
+            }
+            // Arguments are the barrier, the predicate and whether to negate it
+            "bar_red_and_pred" | "bar_red_or_pred" | "bar_red_popc_u32" => {
+                scalar((argument(1)? ^ argument(2)?) & 1)
+            }
+            "div_f32_part1" => Ok(vec![Value::Scalar(0); 4]),
+            "div_f32_part2" => {
+                let type_ = ast::ScalarType::F32;
+                let a = self.float_input(type_, argument(0)?)?;
+                let b = self.float_input(type_, argument(1)?)?;
+                scalar(self.float_result(type_, divide_exact(a, b))?)
+            }
+            "sqrt_rn_f32" | "sqrt_rn_ftz_f32" => {
+                let type_ = ast::ScalarType::F32;
+                let ftz = name == "sqrt_rn_ftz_f32";
+                let flush_if = |bits| if ftz { flush(type_, bits) } else { bits };
+                let value = to_f64(type_, flush_if(argument(0)?))?;
+                let (result, _) = sqrt_exact(value);
+                scalar(flush_if(round_float(
+                    type_,
+                    result,
+                    0.0,
+                    ast::RoundingMode::NearestEven,
+                )?))
+            }
+            _ => {
+                if let Some(type_) = name.strip_prefix("bfe_").and_then(bit_field_type) {
+                    scalar(bit_field_extract(
+                        type_,
+                        argument(0)?,
+                        argument(1)?,
+                        argument(2)?,
+                    ))
+                } else if let Some(type_) = name.strip_prefix("bfi_").and_then(bit_field_type) {
+                    scalar(bit_field_insert(
+                        type_,
+                        argument(0)?,
+                        argument(1)?,
+                        argument(2)?,
+                        argument(3)?,
+                    ))
+                } else {
+                    unsupported(full_name)
+                }
+            }
+        }
+    }
+
+    fn compare(&self, data: &ast::SetpData, a: u64, b: u64) -> Result<bool, InterpretError> {
+        Ok(match data.cmp_op {
+            ast::SetpCompareOp::Integer(op) => {
+                let (signed_a, signed_b) = (sign_extend(a, data.type_), sign_extend(b, data.type_));
+                match op {
+                    ast::SetpCompareInt::Eq => a == b,
+                    ast::SetpCompareInt::NotEq => a != b,
+                    ast::SetpCompareInt::UnsignedLess => a < b,
+                    ast::SetpCompareInt::UnsignedLessOrEq => a <= b,
+                    ast::SetpCompareInt::UnsignedGreater => a > b,
+                    ast::SetpCompareInt::UnsignedGreaterOrEq => a >= b,
+                    ast::SetpCompareInt::SignedLess => signed_a < signed_b,
+                    ast::SetpCompareInt::SignedLessOrEq => signed_a <= signed_b,
+                    ast::SetpCompareInt::SignedGreater => signed_a > signed_b,
+                    ast::SetpCompareInt::SignedGreaterOrEq => signed_a >= signed_b,
+                }
+            }
+            ast::SetpCompareOp::Float(op) => {
+                let a = self.float_input(data.type_, a)?;
+                let b = self.float_input(data.type_, b)?;
+                let unordered = a.is_nan() || b.is_nan();
+                match op {
+                    ast::SetpCompareFloat::Eq => a == b,
+                    ast::SetpCompareFloat::NotEq => !unordered && a != b,
+                    ast::SetpCompareFloat::Less => a < b,
+                    ast::SetpCompareFloat::LessOrEq => a <= b,
+                    ast::SetpCompareFloat::Greater => a > b,
+                    ast::SetpCompareFloat::GreaterOrEq => a >= b,
+                    ast::SetpCompareFloat::NanEq => unordered || a == b,
+                    ast::SetpCompareFloat::NanNotEq => a != b,
+                    ast::SetpCompareFloat::NanLess => unordered || a < b,
+                    ast::SetpCompareFloat::NanLessOrEq => unordered || a <= b,
+                    ast::SetpCompareFloat::NanGreater => unordered || a > b,
+                    ast::SetpCompareFloat::NanGreaterOrEq => unordered || a >= b,
+                    ast::SetpCompareFloat::IsNotNan => !unordered,
+                    ast::SetpCompareFloat::IsAnyNan => unordered,
+                }
+            }
+        })
+    }
+
+    fn cvt(&self, data: &ast::CvtDetails, value: u64) -> Result<u64, InterpretError> {
+        let (from, to) = (data.from, data.to);
+        Ok(match data.mode {
+            ast::CvtMode::ZeroExtend | ast::CvtMode::Truncate | ast::CvtMode::Bitcast => value,
+            ast::CvtMode::SignExtend => sign_extend(value, from) as u64,
+            ast::CvtMode::IntSaturateToSigned | ast::CvtMode::IntSaturateToUnsigned => {
+                saturate_integer(to, integer(from, value))
+            }
+            ast::CvtMode::FPExtend { .. } => exact_float(to, self.float_input(from, value)?)?,
+            ast::CvtMode::FPTruncate {
+                satfinite: true, ..
+            } => {
+                let value = self.float_input(from, value)?;
+                let max_finite = match to {
+                    ast::ScalarType::F16 => half::f16::MAX.to_f64(),
+                    ast::ScalarType::BF16 => half::bf16::MAX.to_f64(),
+                    ast::ScalarType::F32 => f32::MAX as f64,
+                    _ => return unsupported(format!("cvt.satfinite to {to}")),
+                };
+                // f64::clamp keeps NaN
+                self.float_result(to, (value.clamp(-max_finite, max_finite), 0.0))?
+            }
+            ast::CvtMode::FPTruncate { .. } => {
+                self.float_result(to, (self.float_input(from, value)?, 0.0))?
+            }
+            ast::CvtMode::FPRound {
+                integer_rounding: None,
+                flush_to_zero: Some(true),
+                ..
+            } => self.flush_result(to, value),
+            ast::CvtMode::FPRound {
+                integer_rounding: None,
+                ..
+            } => value,
+            ast::CvtMode::FPRound {
+                integer_rounding: Some(rounding),
+                ..
+            } => {
+                let value = round_to_integer(self.float_input(from, value)?, rounding);
+                self.float_result(to, (value, 0.0))?
+            }
+            ast::CvtMode::SignedFromFP { rounding, .. }
+            | ast::CvtMode::UnsignedFromFP { rounding, .. } => {
+                let value = round_to_integer(self.float_input(from, value)?, rounding);
+                float_to_integer(to, value)
+            }
+            ast::CvtMode::FPFromSigned { .. } | ast::CvtMode::FPFromUnsigned { .. } => {
+                self.integer_to_float(to, integer(from, value))?
+            }
+        })
+    }
+
+    // Sum (or difference) of the operands and the incoming carry, the carry out is the bit above
+    // the result. Borrow of a subtraction is kept in the carry, as in PTX
+    fn add_with_carry(&mut self, data: &ast::CarryDetails, a: u64, b: u64, subtract: bool) -> u64 {
+        let bits = bit_width(data.type_);
+        let (a, b) = (
+            (a & mask(data.type_)) as u128,
+            (b & mask(data.type_)) as u128,
+        );
+        let carry = (data.carry_in && self.carry) as u128;
+        let result = if subtract {
+            a.wrapping_sub(b).wrapping_sub(carry)
+        } else {
+            a + b + carry
+        };
+        if data.carry_out {
+            self.carry = (result >> bits) & 1 != 0;
+        }
+        result as u64 & mask(data.type_)
+    }
+
+    fn add_float(
+        &self,
+        type_: ast::ScalarType,
+        a: u64,
+        b: u64,
+        subtract: bool,
+    ) -> Result<u64, InterpretError> {
+        let a = self.float_input(type_, a)?;
+        let b = self.float_input(type_, b)?;
+        let b = if subtract { -b } else { b };
+        let (sum, error) = add_exact(a, b);
+        // x + (-x) is +0 in every rounding mode except towards -inf
+        let sum = if sum == 0.0
+            && self.mode.rounding(type_) == ast::RoundingMode::NegativeInf
+            && (a != 0.0 || a.is_sign_negative() || b.is_sign_negative())
+        {
+            -0.0
+        } else {
+            sum
+        };
+        self.float_result(type_, (sum, error))
+    }
+
+    fn fma(&self, type_: ast::ScalarType, a: u64, b: u64, c: u64) -> Result<u64, InterpretError> {
+        let a = self.float_input(type_, a)?;
+        let b = self.float_input(type_, b)?;
+        let c = self.float_input(type_, c)?;
+        match (type_, self.mode.rounding(type_)) {
+            (ast::ScalarType::F32, ast::RoundingMode::NearestEven) => {
+                let result = (a as f32).mul_add(b as f32, c as f32);
+                Ok(self.flush_result(type_, result.to_bits() as u64))
+            }
+            (ast::ScalarType::F64, ast::RoundingMode::NearestEven) => {
+                Ok(self.flush_result(type_, a.mul_add(b, c).to_bits()))
+            }
+            (ast::ScalarType::F64, rounding) => {
+                unsupported(format!("fma.f64 rounded with {rounding:?}"))
+            }
+            // Product of two values of a type narrower than f64 is exact in f64
+            _ => self.float_result(type_, add_exact(a * b, c)),
+        }
+    }
+
+    fn integer_to_float(&self, type_: ast::ScalarType, value: i128) -> Result<u64, InterpretError> {
+        let rounding = self.mode.rounding(type_);
+        Ok(match type_ {
+            ast::ScalarType::F32 => {
+                let nearest = value as f32;
+                let error = (value - nearest as i128) as f64;
+                direct_f32(nearest, error, rounding).to_bits() as u64
+            }
+            ast::ScalarType::F64 => {
+                let nearest = value as f64;
+                let error = (value - nearest as i128) as f64;
+                direct_f64(nearest, error, rounding).to_bits()
+            }
+            _ => round_float(type_, value as f64, 0.0, rounding)?,
+        })
+    }
+
+    fn float_input(&self, type_: ast::ScalarType, bits: u64) -> Result<f64, InterpretError> {
+        to_f64(type_, self.flush_result(type_, bits))
+    }
+
+    // Rounds the exact result `value + error` with the rounding mode of the mode register
+    fn float_result(
+        &self,
+        type_: ast::ScalarType,
+        (value, error): (f64, f64),
+    ) -> Result<u64, InterpretError> {
+        let bits = round_float(type_, value, error, self.mode.rounding(type_))?;
+        Ok(self.flush_result(type_, bits))
+    }
+
+    fn flush_result(&self, type_: ast::ScalarType, bits: u64) -> u64 {
+        if self.mode.ftz(type_) {
+            flush(type_, bits)
+        } else {
+            bits
+        }
+    }
+}
+
+fn dimension((x, y, z): (u32, u32, u32), index: u64) -> Result<u64, InterpretError> {
+    Ok(match index {
+        0 => x,
+        1 => y,
+        2 => z,
+        _ => return fault(format!("special register dimension {index}")),
+    } as u64)
+}
+
+fn element(elements: &[u64], index: u8) -> Result<u64, InterpretError> {
+    match elements.get(index as usize) {
+        Some(element) => Ok(*element),
+        None => fault(format!("read of vector member {index}")),
+    }
+}
+
+fn bit_width(type_: ast::ScalarType) -> u32 {
+    (type_.size_of() as u32 * 8).min(64)
+}
+
+fn mask_bits(bits: u32) -> u64 {
+    u64::MAX >> (64 - bits.min(64))
+}
+
+fn mask(type_: ast::ScalarType) -> u64 {
+    match type_ {
+        ast::ScalarType::Pred => 1,
+        _ => mask_bits(bit_width(type_)),
+    }
+}
+
+fn sign_bit(type_: ast::ScalarType) -> u64 {
+    1 << (bit_width(type_) - 1)
+}
+
+fn sign_extend(value: u64, type_: ast::ScalarType) -> i64 {
+    let shift = 64 - bit_width(type_);
+    ((value << shift) as i64) >> shift
+}
+
+// Value of the bits as an integer of the type, signed types are sign-extended
+fn integer(type_: ast::ScalarType, value: u64) -> i128 {
+    if type_.kind() == ast::ScalarKind::Signed {
+        sign_extend(value, type_) as i128
+    } else {
+        (value & mask(type_)) as i128
+    }
+}
+
+fn saturate_integer(type_: ast::ScalarType, value: i128) -> u64 {
+    let bits = bit_width(type_);
+    let (min, max) = if type_.kind() == ast::ScalarKind::Signed {
+        (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+    } else {
+        (0, (1i128 << bits) - 1)
+    };
+    value.clamp(min, max) as u64 & mask(type_)
+}
+
+fn add_integer(type_: ast::ScalarType, saturate: bool, a: u64, b: u64, subtract: bool) -> u64 {
+    if saturate {
+        let (a, b) = (integer(type_, a), integer(type_, b));
+        saturate_integer(type_, if subtract { a - b } else { a + b })
+    } else if subtract {
+        a.wrapping_sub(b)
+    } else {
+        a.wrapping_add(b)
+    }
+}
+
+// Result of .wide is twice as wide as the type, the other forms are as wide as the type
+fn multiply_integer(type_: ast::ScalarType, control: ast::MulIntControl, a: u64, b: u64) -> u64 {
+    let bits = bit_width(type_);
+    let product = if type_.kind() == ast::ScalarKind::Signed {
+        (integer(type_, a) * integer(type_, b)) as u128
+    } else {
+        (a & mask(type_)) as u128 * (b & mask(type_)) as u128
+    };
+    match control {
+        ast::MulIntControl::Low => product as u64 & mask(type_),
+        ast::MulIntControl::High => (product >> bits) as u64 & mask(type_),
+        ast::MulIntControl::Wide => product as u64 & mask_bits(2 * bits),
+    }
+}
+
+fn divide_integer(
+    type_: ast::ScalarType,
+    a: u64,
+    b: u64,
+    remainder: bool,
+) -> Result<u64, InterpretError> {
+    let (a, b) = (integer(type_, a), integer(type_, b));
+    if b == 0 {
+        // Result is unspecified in PTX and differs between GPUs
+        return unsupported("integer division by zero");
+    }
+    Ok(if remainder { a % b } else { a / b } as u64)
+}
+
+fn bool_op(op: ast::SetpBoolPostOp, a: bool, b: bool) -> bool {
+    match op {
+        ast::SetpBoolPostOp::And => a && b,
+        ast::SetpBoolPostOp::Or => a || b,
+        ast::SetpBoolPostOp::Xor => a != b,
+    }
+}
+
+// 1.0 for floats and all ones for integers
+fn set_result(type_: ast::ScalarType, result: bool) -> Result<u64, InterpretError> {
+    Ok(match (result, type_.kind()) {
+        (false, _) => 0,
+        (true, ast::ScalarKind::Float) => exact_float(type_, 1.0)?,
+        (true, _) => mask(type_),
+    })
+}
+
+fn bit_field_type(name: &str) -> Option<ast::ScalarType> {
+    Some(match name {
+        "u32" => ast::ScalarType::U32,
+        "s32" => ast::ScalarType::S32,
+        "b32" => ast::ScalarType::B32,
+        "u64" => ast::ScalarType::U64,
+        "s64" => ast::ScalarType::S64,
+        "b64" => ast::ScalarType::B64,
+        _ => return None,
+    })
+}
+
+// 32 bit bfe and bfi only use the low 8 bits of the position and the length, the 64 bit ones
+// use the whole 32 bit values
+fn bit_field_range(type_: ast::ScalarType, position: u64, length: u64) -> (u64, u64) {
+    let mask = if bit_width(type_) == 64 {
+        0xffff_ffff
+    } else {
+        0xff
+    };
+    (position & mask, length & mask)
+}
+
+fn bit_field_extract(type_: ast::ScalarType, value: u64, position: u64, length: u64) -> u64 {
+    let msb = bit_width(type_) as u64 - 1;
+    let (position, length) = bit_field_range(type_, position, length);
+    let sign = type_.kind() == ast::ScalarKind::Signed
+        && length != 0
+        && (value >> (position + length - 1).min(msb)) & 1 != 0;
+    (0..=msb).fold(0, |result, index| {
+        let bit = if index < length && position + index <= msb {
+            (value >> (position + index)) & 1
+        } else {
+            sign as u64
+        };
+        result | (bit << index)
+    })
+}
+
+fn bit_field_insert(
+    type_: ast::ScalarType,
+    insert: u64,
+    base: u64,
+    position: u64,
+    length: u64,
+) -> u64 {
+    let msb = bit_width(type_) as u64 - 1;
+    let (position, length) = bit_field_range(type_, position, length);
+    (0..length)
+        .take_while(|index| position + index <= msb)
+        .fold(base, |result, index| {
+            let bit = (insert >> index) & 1;
+            (result & !(1 << (position + index))) | (bit << (position + index))
+        })
+}
+
+fn atomic(
+    type_: ast::ScalarType,
+    op: ast::AtomicOp,
+    old: u64,
+    operand: u64,
+) -> Result<u64, InterpretError> {
+    let operand = operand & mask(type_);
+    Ok(match op {
+        ast::AtomicOp::And => old & operand,
+        ast::AtomicOp::Or => old | operand,
+        ast::AtomicOp::Xor => old ^ operand,
+        ast::AtomicOp::Exchange => operand,
+        ast::AtomicOp::Add => old.wrapping_add(operand),
+        ast::AtomicOp::IncrementWrap => {
+            if old >= operand {
+                0
+            } else {
+                old + 1
+            }
+        }
+        ast::AtomicOp::DecrementWrap => {
+            if old == 0 || old > operand {
+                operand
+            } else {
+                old - 1
+            }
+        }
+        ast::AtomicOp::SignedMin => sign_extend(old, type_).min(sign_extend(operand, type_)) as u64,
+        ast::AtomicOp::SignedMax => sign_extend(old, type_).max(sign_extend(operand, type_)) as u64,
+        ast::AtomicOp::UnsignedMin => old.min(operand),
+        ast::AtomicOp::UnsignedMax => old.max(operand),
+        ast::AtomicOp::FloatAdd => {
+            let (sum, error) = add_exact(to_f64(type_, old)?, to_f64(type_, operand)?);
+            round_float(type_, sum, error, ast::RoundingMode::NearestEven)?
+        }
+        ast::AtomicOp::FloatMin => min_max_float(type_, old, operand, false, false)?,
+        ast::AtomicOp::FloatMax => min_max_float(type_, old, operand, false, true)?,
+    } & mask(type_))
+}
+
+fn convert(value: Value, conversion: &ImplicitConversion) -> Result<Value, InterpretError> {
+    let (from_type, to_type) = (&conversion.from_type, &conversion.to_type);
+    Ok(match conversion.kind {
+        ConversionKind::Default => match (from_type, to_type, value) {
+            (ast::Type::Scalar(from), ast::Type::Scalar(to), Value::Scalar(value)) => {
+                // Same as in emit, sign extension only when both types are signed
+                let value = if from.size_of() < to.size_of()
+                    && from.kind() == ast::ScalarKind::Signed
+                    && to.kind() == ast::ScalarKind::Signed
+                {
+                    sign_extend(value, *from) as u64
+                } else {
+                    value
+                };
+                Value::Scalar(value & mask(*to))
+            }
+            (
+                ast::Type::Vector(..) | ast::Type::Array(..),
+                ast::Type::Scalar(to),
+                Value::Vector(elements),
+            ) => {
+                let (element_type, _) = elements_of(from_type)?;
+                let bits = bit_width(element_type);
+                let value = elements.iter().rev().fold(0u64, |packed, element| {
+                    packed.checked_shl(bits).unwrap_or(0) | element
+                });
+                Value::Scalar(value & mask(*to))
+            }
+            (ast::Type::Scalar(_), ast::Type::Array(..), Value::Scalar(value)) => {
+                let (element_type, count) = elements_of(to_type)?;
+                let bits = bit_width(element_type);
+                Value::Vector(
+                    (0..count)
+                        .map(|index| {
+                            value.checked_shr(bits * index as u32).unwrap_or(0) & mask(element_type)
+                        })
+                        .collect(),
+                )
+            }
+            _ => return fault("implicit conversion between incompatible types"),
+        },
+        ConversionKind::SignExtend => match (from_type, to_type, value) {
+            (ast::Type::Scalar(from), ast::Type::Scalar(to), Value::Scalar(value)) => {
+                Value::Scalar(sign_extend(value, *from) as u64 & mask(*to))
+            }
+            _ => return fault("sign extension of a vector"),
+        },
+        // Pointers are plain addresses in the flat address space. The target type of a pointer
+        // conversion is the pointee type, only the address of a variable goes into a register
+        ConversionKind::BitToPtr | ConversionKind::PtrToPtr => value,
+        ConversionKind::AddressOf => match (to_type, value) {
+            (ast::Type::Scalar(to), Value::Scalar(value)) => Value::Scalar(value & mask(*to)),
+            (_, value) => value,
+        },
+    })
+}
+
+fn repack_vector(frame: &mut Frame, repack: &RepackVectorDetails) -> Result<(), InterpretError> {
+    let bits = bit_width(repack.typ);
+    if repack.is_extract {
+        let elements = match frame.get(repack.packed)? {
+            Value::Vector(elements) => elements.clone(),
+            // Element 0 is the least significant one, same as in memory
+            Value::Scalar(value) => (0..repack.unpacked.len() as u32)
+                .map(|index| value.checked_shr(bits * index).unwrap_or(0))
+                .collect(),
+        };
+        for (index, dst) in repack.unpacked.iter().enumerate() {
+            frame.set_scalar(*dst, repack.typ, element(&elements, index as u8)?);
+        }
+    } else {
+        let elements = repack
+            .unpacked
+            .iter()
+            .map(|id| Ok(frame.scalar(*id)? & mask(repack.typ)))
+            .collect::<Result<Vec<_>, InterpretError>>()?;
+        let value = match repack.packed_type {
+            ast::Type::Scalar(_) => {
+                Value::Scalar(elements.iter().rev().fold(0u64, |packed, element| {
+                    packed.checked_shl(bits).unwrap_or(0) | element
+                }))
+            }
+            _ => Value::Vector(elements),
+        };
+        frame.set(repack.packed, value);
+    }
+    Ok(())
+}
+
+fn constant_bits(
+    type_: ast::ScalarType,
+    value: ast::ImmediateValue,
+) -> Result<u64, InterpretError> {
+    Ok(match value {
+        ast::ImmediateValue::U64(value) => value,
+        ast::ImmediateValue::S64(value) => value as u64,
+        ast::ImmediateValue::F32(value) => exact_float(type_, value as f64)?,
+        ast::ImmediateValue::F64(value) => exact_float(type_, value)?,
+    })
+}
+
+// Type of the elements and their number, a scalar is a single element
+fn elements_of(type_: &ast::Type) -> Result<(ast::ScalarType, u64), InterpretError> {
+    Ok(match type_ {
+        ast::Type::Scalar(scalar) => (*scalar, 1),
+        ast::Type::Vector(count, scalar) => (*scalar, *count as u64),
+        ast::Type::Array(vector, scalar, dimensions) => {
+            let vector = vector.map_or(1, |width| width.get() as u64);
+            let count = dimensions.iter().map(|dim| *dim as u64).product::<u64>();
+            (*scalar, vector * count)
+        }
+    })
+}
+
+fn read_scalar(
+    memory: &Memory,
+    address: u64,
+    type_: ast::ScalarType,
+) -> Result<u64, InterpretError> {
+    let size = scalar_size(type_)?;
+    let mut bytes = [0u8; 8];
+    bytes[..size].copy_from_slice(memory.read(address, size)?);
+    // Predicates are kept in memory as bytes
+    Ok(u64::from_le_bytes(bytes) & mask(type_))
+}
+
+fn write_scalar(
+    memory: &mut Memory,
+    address: u64,
+    type_: ast::ScalarType,
+    value: u64,
+) -> Result<(), InterpretError> {
+    let size = scalar_size(type_)?;
+    memory.write(address, &(value & mask(type_)).to_le_bytes()[..size])
+}
+
+fn scalar_size(type_: ast::ScalarType) -> Result<usize, InterpretError> {
+    match type_.size_of() as usize {
+        size @ 1..=8 => Ok(size),
+        size => unsupported(format!("{size} byte scalars")),
+    }
+}
+
+fn load(memory: &Memory, address: u64, type_: &ast::Type) -> Result<Value, InterpretError> {
+    Ok(match type_ {
+        ast::Type::Scalar(scalar) => Value::Scalar(read_scalar(memory, address, *scalar)?),
+        _ => {
+            let (scalar, count) = elements_of(type_)?;
+            let size = scalar_size(scalar)? as u64;
+            Value::Vector(
+                (0..count)
+                    .map(|index| read_scalar(memory, address + index * size, scalar))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+    })
+}
+
+fn store(
+    memory: &mut Memory,
+    address: u64,
+    type_: &ast::Type,
+    value: &Value,
+) -> Result<(), InterpretError> {
+    let (scalar, count) = elements_of(type_)?;
+    let size = scalar_size(scalar)? as u64;
+    match value {
+        Value::Scalar(value) if count == 1 => write_scalar(memory, address, scalar, *value),
+        Value::Vector(elements) if elements.len() as u64 == count => {
+            for (index, element) in elements.iter().enumerate() {
+                write_scalar(memory, address + index as u64 * size, scalar, *element)?;
+            }
+            Ok(())
+        }
+        _ => fault("store of a value that does not match the type"),
+    }
+}
+
+fn to_f64(type_: ast::ScalarType, bits: u64) -> Result<f64, InterpretError> {
+    Ok(match type_ {
+        ast::ScalarType::F16 => half::f16::from_bits(bits as u16).to_f64(),
+        ast::ScalarType::BF16 => half::bf16::from_bits(bits as u16).to_f64(),
+        ast::ScalarType::F32 => f32::from_bits(bits as u32) as f64,
+        ast::ScalarType::F64 => f64::from_bits(bits),
+        _ => return unsupported(format!("floating point arithmetic on {type_}")),
+    })
+}
+
+fn exact_float(type_: ast::ScalarType, value: f64) -> Result<u64, InterpretError> {
+    round_float(type_, value, 0.0, ast::RoundingMode::NearestEven)
+}
+
+fn is_subnormal(type_: ast::ScalarType, bits: u64) -> bool {
+    let (exponent, mantissa) = match type_ {
+        ast::ScalarType::F16 => (0x7c00, 0x3ff),
+        ast::ScalarType::BF16 => (0x7f80, 0x7f),
+        ast::ScalarType::F32 => (0x7f80_0000, 0x7f_ffff),
+        ast::ScalarType::F64 => (0x7ff0_0000_0000_0000, 0xf_ffff_ffff_ffff),
+        _ => return false,
+    };
+    bits & exponent == 0 && bits & mantissa != 0
+}
+
+// Subnormals become zeros of the same sign
+fn flush(type_: ast::ScalarType, bits: u64) -> u64 {
+    if is_subnormal(type_, bits) {
+        bits & sign_bit(type_)
+    } else {
+        bits
+    }
+}
+
+fn canonical_nan(type_: ast::ScalarType) -> u64 {
+    mask(type_) >> 1
+}
+
+// PTX min and max: -0 is less than +0. Without .NaN a NaN input is ignored, with it a NaN input
+// gives a NaN result
+fn min_max_float(
+    type_: ast::ScalarType,
+    a: u64,
+    b: u64,
+    propagate_nan: bool,
+    is_max: bool,
+) -> Result<u64, InterpretError> {
+    let (x, y) = (to_f64(type_, a)?, to_f64(type_, b)?);
+    Ok(match (x.is_nan(), y.is_nan()) {
+        (true, true) => canonical_nan(type_),
+        (true, false) | (false, true) if propagate_nan => canonical_nan(type_),
+        (true, false) => b,
+        (false, true) => a,
+        (false, false) => {
+            let a_is_less = x < y || (x == y && x.is_sign_negative());
+            if a_is_less != is_max {
+                a
+            } else {
+                b
+            }
+        }
+    })
+}
+
+fn round_to_integer(value: f64, rounding: ast::RoundingMode) -> f64 {
+    match rounding {
+        ast::RoundingMode::NearestEven => value.round_ties_even(),
+        ast::RoundingMode::Zero => value.trunc(),
+        ast::RoundingMode::NegativeInf => value.floor(),
+        ast::RoundingMode::PositiveInf => value.ceil(),
+    }
+}
+
+// Float to integer conversions saturate and turn NaN into 0
+fn float_to_integer(type_: ast::ScalarType, value: f64) -> u64 {
+    if value.is_nan() {
+        return 0;
+    }
+    let value = if value >= i128::MAX as f64 {
+        i128::MAX
+    } else if value <= i128::MIN as f64 {
+        i128::MIN
+    } else {
+        value as i128
+    };
+    saturate_integer(type_, value)
+}
+
+// The operations below return the result rounded to f64 and the error of that rounding, so that
+// the exact result can be rounded to the destination type in any rounding mode. When the result
+// overflows, the error has the sign of the way back to the finite values
+
+fn overflow_error(result: f64, inputs: &[f64]) -> f64 {
+    if inputs.iter().all(|input| input.is_finite()) {
+        -result
+    } else {
+        0.0
+    }
+}
+
+fn add_exact(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    if !sum.is_finite() {
+        return (sum, overflow_error(sum, &[a, b]));
+    }
+    // TwoSum
+    let b_virtual = sum - a;
+    let a_virtual = sum - b_virtual;
+    (sum, (a - a_virtual) + (b - b_virtual))
+}
+
+fn multiply_exact(a: f64, b: f64) -> (f64, f64) {
+    let product = a * b;
+    if !product.is_finite() {
+        return (product, overflow_error(product, &[a, b]));
+    }
+    (product, a.mul_add(b, -product))
+}
+
+fn divide_exact(a: f64, b: f64) -> (f64, f64) {
+    let quotient = a / b;
+    if !quotient.is_finite() {
+        let error = if b != 0.0 {
+            overflow_error(quotient, &[a, b])
+        } else {
+            0.0
+        };
+        return (quotient, error);
+    }
+    if !b.is_finite() || b == 0.0 {
+        return (quotient, 0.0);
+    }
+    // Sign of a - q * b is the sign of the error times the sign of b
+    let remainder = (-quotient).mul_add(b, a);
+    (quotient, remainder * b.signum())
+}
+
+fn sqrt_exact(value: f64) -> (f64, f64) {
+    let root = value.sqrt();
+    if !root.is_finite() || root == 0.0 {
+        return (root, 0.0);
+    }
+    (root, (-root).mul_add(root, value))
+}
+
+// Rounds `value + error`, where `value` is a rounded to nearest f64 and only the sign of `error`
+// matters. Double rounding through f64 gives correctly rounded f32, f16 and bf16 results of
+// additions, multiplications, divisions and square roots of the narrower type
+fn round_float(
+    type_: ast::ScalarType,
+    value: f64,
+    error: f64,
+    rounding: ast::RoundingMode,
+) -> Result<u64, InterpretError> {
+    Ok(match type_ {
+        ast::ScalarType::F64 => direct_f64(value, error, rounding).to_bits(),
+        ast::ScalarType::F32 => {
+            let nearest = value as f32;
+            let difference = value - nearest as f64;
+            let error = if difference != 0.0 && !difference.is_nan() {
+                difference
+            } else {
+                error
+            };
+            direct_f32(nearest, error, rounding).to_bits() as u64
+        }
+        ast::ScalarType::F16 if rounding == ast::RoundingMode::NearestEven => {
+            half::f16::from_f64(value).to_bits() as u64
+        }
+        ast::ScalarType::BF16 if rounding == ast::RoundingMode::NearestEven => {
+            half::bf16::from_f64(value).to_bits() as u64
+        }
+        _ => return unsupported(format!("{type_} arithmetic rounded with {rounding:?}")),
+    })
+}
+
+// Direction in which `nearest`, the exact result rounded to nearest, has to move to be rounded
+// with `rounding`. `error` is the exact result minus `nearest`
+fn rounding_step(nearest: f64, error: f64, rounding: ast::RoundingMode) -> Option<bool> {
+    if error == 0.0 || error.is_nan() {
+        return None;
+    }
+    let exact_is_above = error > 0.0;
+    match rounding {
+        ast::RoundingMode::NearestEven => None,
+        ast::RoundingMode::PositiveInf => exact_is_above.then_some(true),
+        ast::RoundingMode::NegativeInf => (!exact_is_above).then_some(false),
+        ast::RoundingMode::Zero => {
+            if exact_is_above && nearest < 0.0 {
+                Some(true)
+            } else if !exact_is_above && nearest > 0.0 {
+                Some(false)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn direct_f32(nearest: f32, error: f64, rounding: ast::RoundingMode) -> f32 {
+    match rounding_step(nearest as f64, error, rounding) {
+        None => nearest,
+        Some(up) => {
+            if nearest == 0.0 {
+                let smallest = f32::from_bits(1);
+                return if up { smallest } else { -smallest };
+            }
+            // Moving away from zero increments the magnitude
+            if (nearest > 0.0) == up {
+                f32::from_bits(nearest.to_bits() + 1)
+            } else {
+                f32::from_bits(nearest.to_bits() - 1)
+            }
+        }
+    }
+}
+
+fn direct_f64(nearest: f64, error: f64, rounding: ast::RoundingMode) -> f64 {
+    match rounding_step(nearest, error, rounding) {
+        None => nearest,
+        Some(up) => {
+            if nearest == 0.0 {
+                let smallest = f64::from_bits(1);
+                return if up { smallest } else { -smallest };
+            }
+            if (nearest > 0.0) == up {
+                f64::from_bits(nearest.to_bits() + 1)
+            } else {
+                f64::from_bits(nearest.to_bits() - 1)
+            }
+        }
+    }
+}
@@ -9,8 +9,10 @@ pub(crate) fn run(
     attributes: Attributes,
 ) -> Result<llvm::Module, TranslateError> {
     let module = llvm::Module::new(context, LLVM_UNNAMED);
+    module.set_amdgpu_target();
 
     emit_attribute(context, &module, "clock_rate", attributes.clock_rate)?;
+    emit_code_object_version(context, &module);
 
     if let Err(err) = module.verify() {
         panic!("{:?}", err);
@@ -42,3 +44,26 @@ fn emit_attribute(
     unsafe { LLVMSetGlobalConstant(global, 1) };
     Ok(())
 }
+
+// This module is linked into every module we compile, so the flag set here applies to all of
+// them. The value is the version times 100, same as what clang emits
+fn emit_code_object_version(context: &Context, module: &llvm::Module) {
+    let key = "amdhsa_code_object_version";
+    let version_type = get_scalar_type(context.get(), ast::ScalarType::U32);
+    let version = unsafe {
+        LLVMValueAsMetadata(LLVMConstInt(
+            version_type,
+            AMDHSA_CODE_OBJECT_VERSION as u64 * 100,
+            0,
+        ))
+    };
+    unsafe {
+        LLVMAddModuleFlag(
+            module.get(),
+            llvm_zluda::LLVMModuleFlagBehavior::LLVMModuleFlagBehaviorError,
+            key.as_ptr().cast(),
+            key.len(),
+            version,
+        )
+    };
+}
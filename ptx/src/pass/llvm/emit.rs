@@ -26,14 +26,15 @@
 
 use std::array::TryFromSliceError;
 use std::convert::TryInto;
-use std::ffi::{CStr, NulError};
+use std::ffi::CStr;
 use std::{i8, ptr, u64};
 
 use super::*;
 use crate::pass::*;
-use llvm_zluda::{core::*, *};
+use llvm_zluda::{core::*, target::*, *};
 use llvm_zluda::{prelude::*, LLVMZludaBuildAtomicRMW};
 use llvm_zluda::{LLVMCallConv, LLVMZludaBuildAlloca};
+use petgraph::{algo::dominators, Graph};
 use ptx_parser::{CpAsyncArgs, CpAsyncDetails, FunnelShiftMode, Mul24Control, ShfArgs};
 
 struct Builder(LLVMBuilderRef);
@@ -60,31 +61,52 @@ impl Drop for Builder {
     }
 }
 
+// Returns the module and whether it contains any correctly rounded (.rn) division or square root
 pub(crate) fn run<'input>(
     context: &Context,
     id_defs: GlobalStringIdentResolver2<'input>,
+    symbol_suffix: Option<&str>,
+    volatile_coherence: VolatileCoherence,
     directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
-) -> Result<llvm::Module, TranslateError> {
+) -> Result<(llvm::Module, bool), TranslateError> {
     let module = llvm::Module::new(context, LLVM_UNNAMED);
-    let mut emit_ctx = ModuleEmitContext::new(context, &module, &id_defs);
+    let mut emit_ctx = ModuleEmitContext::new(
+        context,
+        &module,
+        &id_defs,
+        symbol_suffix,
+        volatile_coherence,
+    );
     for directive in directives {
         match directive {
             Directive2::Variable(linking, variable) => emit_ctx.emit_global(linking, variable)?,
-            Directive2::Method(method) => emit_ctx.emit_method(method)?,
+            Directive2::Method(method) => {
+                let span = method.span;
+                emit_ctx.emit_method(method).map_err(|err| err.at(span))?
+            }
         }
     }
+    emit_ctx.emit_initializers()?;
+    let correctly_rounded_div_sqrt = emit_ctx.correctly_rounded_div_sqrt;
     if let Err(err) = module.verify() {
         panic!("{:?}", err);
     }
-    Ok(module)
+    Ok((module, correctly_rounded_div_sqrt))
 }
 
 struct ModuleEmitContext<'a, 'input> {
     context: LLVMContextRef,
     module: LLVMModuleRef,
+    target_data: LLVMTargetDataRef,
     builder: Builder,
     id_defs: &'a GlobalStringIdentResolver2<'input>,
     resolver: ResolveIdent,
+    correctly_rounded_div_sqrt: bool,
+    // Initializers of the globals, set once every global and function is declared
+    initializers: Vec<(LLVMValueRef, ast::Type, ast::Initializer<SpirvWord>)>,
+    // Appended to the names of symbols private to the module, see TranslateOptions::unique_symbols
+    symbol_suffix: Option<&'a str>,
+    volatile_coherence: VolatileCoherence,
 }
 
 impl<'a, 'input> ModuleEmitContext<'a, 'input> {
@@ -92,16 +114,44 @@ impl<'a, 'input> ModuleEmitContext<'a, 'input> {
         context: &Context,
         module: &llvm::Module,
         id_defs: &'a GlobalStringIdentResolver2<'input>,
+        symbol_suffix: Option<&'a str>,
+        volatile_coherence: VolatileCoherence,
     ) -> Self {
+        module.set_amdgpu_target();
         ModuleEmitContext {
             context: context.get(),
             module: module.get(),
+            target_data: unsafe { LLVMGetModuleDataLayout(module.get()) },
             builder: Builder::new(context),
             id_defs,
             resolver: ResolveIdent::new(&id_defs),
+            correctly_rounded_div_sqrt: false,
+            initializers: Vec::new(),
+            symbol_suffix,
+            volatile_coherence,
         }
     }
 
+    fn symbol_name(
+        &self,
+        name: &str,
+        linking: ast::LinkingDirective,
+    ) -> Result<CString, TranslateError> {
+        let name = match self.symbol_suffix {
+            Some(suffix)
+                if !linking.intersects(
+                    ast::LinkingDirective::VISIBLE
+                        | ast::LinkingDirective::EXTERN
+                        | ast::LinkingDirective::WEAK,
+                ) =>
+            {
+                Cow::Owned(format!("{name}{suffix}"))
+            }
+            _ => Cow::Borrowed(name),
+        };
+        CString::new(&*name).map_err(|_| error_unreachable())
+    }
+
     fn kernel_call_convention() -> u32 {
         LLVMCallConv::LLVMAMDGPUKERNELCallConv as u32
     }
@@ -114,12 +164,21 @@ impl<'a, 'input> ModuleEmitContext<'a, 'input> {
         &mut self,
         method: Function2<ast::Instruction<SpirvWord>, SpirvWord>,
     ) -> Result<(), TranslateError> {
-        let name = method
-            .import_as
-            .as_deref()
-            .or_else(|| self.id_defs.ident_map[&method.name].name.as_deref())
-            .ok_or_else(|| error_unreachable())?;
-        let name = CString::new(name).map_err(|_| error_unreachable())?;
+        let name = match method.import_as.as_deref() {
+            Some(name) => CString::new(name).map_err(|_| error_unreachable())?,
+            None => {
+                let name = self.id_defs.ident_map[&method.name]
+                    .name
+                    .as_deref()
+                    .ok_or_else(|| error_unreachable())?;
+                // Kernels are looked up by name at launch
+                if method.is_kernel {
+                    CString::new(name).map_err(|_| error_unreachable())?
+                } else {
+                    self.symbol_name(name, method.linkage)?
+                }
+            }
+        };
         let mut fn_ = unsafe { LLVMGetNamedFunction(self.module, name.as_ptr()) };
         if fn_ == ptr::null_mut() {
             let fn_type = get_function_type(
@@ -140,6 +199,14 @@ impl<'a, 'input> ModuleEmitContext<'a, 'input> {
             self.emit_fn_attribute(fn_, "denormal-fp-math-f32", "dynamic");
             self.emit_fn_attribute(fn_, "denormal-fp-math", "dynamic");
         } else {
+            // Functions of the device libraries we call (e.g. hostcall behind __assertfail) read
+            // the hidden arguments through llvm.amdgcn.implicitarg.ptr. The backend drops them
+            // from kernels which don't use them
+            self.emit_fn_attribute(
+                fn_,
+                "amdgpu-implicitarg-num-bytes",
+                &IMPLICIT_ARGUMENTS_SIZE.to_string(),
+            );
             self.emit_fn_attribute(
                 fn_,
                 "denormal-fp-math-f32",
@@ -168,6 +235,17 @@ impl<'a, 'input> ModuleEmitContext<'a, 'input> {
                     )
                 };
                 unsafe { LLVMAddAttributeAtIndex(fn_, i as u32 + 1, attr) };
+                // Kernel arguments are laid out in the kernarg segment according to the
+                // alignment of byref pointers, without it `.param .align 16 .b8 x[64]` would be
+                // aligned to 1
+                if let Some(align) = param.align {
+                    let attr_kind = unsafe {
+                        LLVMGetEnumAttributeKindForName(b"align".as_ptr().cast(), b"align".len())
+                    };
+                    let attr =
+                        unsafe { LLVMCreateEnumAttribute(self.context, attr_kind, align as u64) };
+                    unsafe { LLVMAddAttributeAtIndex(fn_, i as u32 + 1, attr) };
+                }
             }
         }
         if !method.is_kernel {
@@ -175,6 +253,9 @@ impl<'a, 'input> ModuleEmitContext<'a, 'input> {
                 LLVMSetVisibility(fn_, llvm_zluda::LLVMVisibility::LLVMHiddenVisibility);
             }
         }
+        if method.body.is_some() && method.linkage.contains(ast::LinkingDirective::WEAK) {
+            unsafe { LLVMSetLinkage(fn_, LLVMLinkage::LLVMWeakAnyLinkage) };
+        }
         let call_conv = if method.is_kernel {
             Self::kernel_call_convention()
         } else {
@@ -198,6 +279,7 @@ impl<'a, 'input> ModuleEmitContext<'a, 'input> {
                     method_emitter.emit_label_initial(*label);
                 }
             }
+            let latches = loop_latches(&statements)?;
             let mut statements = statements.into_iter();
             if let Some(Statement::Label(label)) = statements.next() {
                 method_emitter.emit_label_delayed(label)?;
@@ -212,29 +294,33 @@ impl<'a, 'input> ModuleEmitContext<'a, 'input> {
             for statement in statements {
                 method_emitter.emit_statement(statement)?;
             }
+            method_emitter.emit_loop_metadata(&latches, &method.loop_pragmas)?;
             unsafe { LLVMBuildBr(method_emitter.variables_builder.get(), real_bb) };
+            // normalize_basic_blocks ends every basic block with a terminator, a block without
+            // one would fail verification much later and far from its cause
+            let mut block = unsafe { LLVMGetFirstBasicBlock(fn_) };
+            while block != ptr::null_mut() {
+                if unsafe { LLVMGetBasicBlockTerminator(block) } == ptr::null_mut() {
+                    return Err(error_unreachable());
+                }
+                block = unsafe { LLVMGetNextBasicBlock(block) };
+            }
         }
         Ok(())
     }
 
     fn emit_global(
         &mut self,
-        _linking: ast::LinkingDirective,
+        linking: ast::LinkingDirective,
         var: ast::Variable<SpirvWord>,
     ) -> Result<(), TranslateError> {
         let name = self
             .id_defs
             .ident_map
             .get(&var.name)
-            .map(|entry| {
-                entry
-                    .name
-                    .as_ref()
-                    .map(|text| Ok::<_, NulError>(Cow::Owned(CString::new(&**text)?)))
-            })
-            .flatten()
-            .transpose()
-            .map_err(|_| error_unreachable())?
+            .and_then(|entry| entry.name.as_deref())
+            .map(|text| self.symbol_name(text, linking).map(Cow::Owned))
+            .transpose()?
             .unwrap_or(Cow::Borrowed(LLVM_UNNAMED));
         let global = unsafe {
             LLVMAddGlobalInAddressSpace(
@@ -249,41 +335,138 @@ impl<'a, 'input> ModuleEmitContext<'a, 'input> {
             unsafe { LLVMSetAlignment(global, align) };
         }
         if !var.array_init.is_empty() {
-            self.emit_array_init(&var.v_type, &*var.array_init, global)?;
+            self.initializers.push((global, var.v_type, var.array_init));
+        } else if matches!(
+            var.state_space,
+            ast::StateSpace::Global | ast::StateSpace::Const
+        ) && !linking.contains(ast::LinkingDirective::EXTERN)
+        {
+            // PTX zeroes them, a declaration would be an undefined symbol of a library module
+            let zero = unsafe { LLVMConstNull(get_type(self.context, &var.v_type)?) };
+            unsafe { LLVMSetInitializer(global, zero) };
+        }
+        Ok(())
+    }
+
+    // Initializers can hold addresses of globals and functions declared later in the module
+    fn emit_initializers(&mut self) -> Result<(), TranslateError> {
+        for (global, type_, initializer) in std::mem::take(&mut self.initializers) {
+            self.emit_initializer(&type_, &initializer, global)?;
         }
         Ok(())
     }
 
     // TODO: instead of Vec<u8> we should emit a typed initializer
-    fn emit_array_init(
+    fn emit_initializer(
         &mut self,
         type_: &ast::Type,
-        array_init: &[u8],
+        initializer: &ast::Initializer<SpirvWord>,
         global: *mut llvm_zluda::LLVMValue,
     ) -> Result<(), TranslateError> {
-        match type_ {
+        let (scalar, length) = match type_ {
+            ast::Type::Scalar(scalar) => (*scalar, None),
             ast::Type::Array(None, scalar, dimensions) => {
                 if dimensions.len() != 1 {
-                    todo!()
+                    return Err(error_todo_msg(
+                        "multi-dimensional array initializers are not supported yet",
+                    ));
                 }
-                if dimensions[0] as usize * scalar.size_of() as usize != array_init.len() {
-                    return Err(error_unreachable());
-                }
-                let type_ = get_scalar_type(self.context, *scalar);
-                let mut elements = array_init
-                    .chunks(scalar.size_of() as usize)
-                    .map(|chunk| self.constant_from_bytes(*scalar, chunk, type_))
-                    .collect::<Result<Vec<_>, _>>()
-                    .map_err(|_| error_unreachable())?;
-                let initializer =
-                    unsafe { LLVMConstArray2(type_, elements.as_mut_ptr(), elements.len() as u64) };
-                unsafe { LLVMSetInitializer(global, initializer) };
+                (*scalar, Some(dimensions[0]))
+            }
+            _ => {
+                return Err(error_todo_msg(
+                    "initializers are only supported for scalars and arrays of scalars",
+                ))
             }
-            _ => todo!(),
+        };
+        let element_size = scalar.size_of() as usize;
+        if length.unwrap_or(1) as usize * element_size != initializer.bytes.len() {
+            return Err(error_unreachable());
+        }
+        if !matches!(
+            scalar,
+            ast::ScalarType::Pred
+                | ast::ScalarType::S8
+                | ast::ScalarType::B8
+                | ast::ScalarType::U8
+                | ast::ScalarType::S16
+                | ast::ScalarType::B16
+                | ast::ScalarType::U16
+                | ast::ScalarType::S32
+                | ast::ScalarType::B32
+                | ast::ScalarType::U32
+                | ast::ScalarType::S64
+                | ast::ScalarType::B64
+                | ast::ScalarType::U64
+        ) {
+            return Err(error_todo_msg(
+                "initializers are only supported for integer types",
+            ));
         }
+        let llvm_type = get_scalar_type(self.context, scalar);
+        let mut elements = initializer
+            .bytes
+            .chunks(element_size)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let offset = (index * element_size) as u32;
+                match initializer
+                    .relocations
+                    .iter()
+                    .find(|relocation| relocation.offset == offset)
+                {
+                    Some(relocation) => self.constant_address(relocation, llvm_type),
+                    None => self
+                        .constant_from_bytes(scalar, chunk, llvm_type)
+                        .map_err(|_| error_unreachable()),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = match length {
+            Some(_) => unsafe {
+                LLVMConstArray2(llvm_type, elements.as_mut_ptr(), elements.len() as u64)
+            },
+            None => elements[0],
+        };
+        unsafe { LLVMSetInitializer(global, value) };
         Ok(())
     }
 
+    // `generic(symbol)+addend` as a constant integer of type `llvm_type`: addrspacecast and GEP of
+    // the symbol converted with ptrtoint
+    fn constant_address(
+        &self,
+        relocation: &ast::Relocation<SpirvWord>,
+        llvm_type: LLVMTypeRef,
+    ) -> Result<LLVMValueRef, TranslateError> {
+        let mut address = self.resolver.value(relocation.symbol)?;
+        let address_space = unsafe { LLVMGetPointerAddressSpace(LLVMTypeOf(address)) };
+        if relocation.generic && address_space != GENERIC_ADDRESS_SPACE {
+            let generic_type =
+                unsafe { LLVMPointerTypeInContext(self.context, GENERIC_ADDRESS_SPACE) };
+            address = unsafe { LLVMConstAddrSpaceCast(address, generic_type) };
+        }
+        let i64_type = unsafe { LLVMInt64TypeInContext(self.context) };
+        if relocation.addend != 0 {
+            let mut addend = unsafe { LLVMConstInt(i64_type, relocation.addend as u64, 1) };
+            address = unsafe {
+                LLVMConstGEP2(LLVMInt8TypeInContext(self.context), address, &mut addend, 1)
+            };
+        }
+        Ok(match relocation.byte {
+            None => unsafe { LLVMConstPtrToInt(address, llvm_type) },
+            Some(byte) => unsafe {
+                let address = LLVMConstPtrToInt(address, i64_type);
+                let shift = LLVMConstInt(i64_type, byte as u64 * 8, 0);
+                let byte = LLVMConstTrunc(
+                    LLVMConstLShr(address, shift),
+                    LLVMInt8TypeInContext(self.context),
+                );
+                LLVMConstZExtOrBitCast(byte, llvm_type)
+            },
+        })
+    }
+
     fn constant_from_bytes(
         &self,
         scalar: ast::ScalarType,
@@ -307,13 +490,15 @@ impl<'a, 'input> ModuleEmitContext<'a, 'input> {
             | ptx_parser::ScalarType::U32 => unsafe {
                 LLVMConstInt(llvm_type, u32::from_le_bytes(bytes.try_into()?) as u64, 0)
             },
+            ptx_parser::ScalarType::S64
+            | ptx_parser::ScalarType::B64
+            | ptx_parser::ScalarType::U64 => unsafe {
+                LLVMConstInt(llvm_type, u64::from_le_bytes(bytes.try_into()?), 0)
+            },
             ptx_parser::ScalarType::F16 => todo!(),
             ptx_parser::ScalarType::BF16 => todo!(),
-            ptx_parser::ScalarType::U64 => todo!(),
-            ptx_parser::ScalarType::S64 => todo!(),
             ptx_parser::ScalarType::S16x2 => todo!(),
             ptx_parser::ScalarType::F32 => todo!(),
-            ptx_parser::ScalarType::B64 => todo!(),
             ptx_parser::ScalarType::F64 => todo!(),
             ptx_parser::ScalarType::B128 => todo!(),
             ptx_parser::ScalarType::U16x2 => todo!(),
@@ -344,6 +529,75 @@ fn llvm_ftz(ftz: bool) -> &'static str {
     }
 }
 
+// Back-edges of the natural loops as (latch, header) pairs: the latch branches to the header and
+// is dominated by it. Basic blocks not ending with a branch are taken to fall through to the next
+// one, an extra edge can only hide a loop, never make one up
+fn loop_latches(
+    statements: &[Statement<ast::Instruction<SpirvWord>, SpirvWord>],
+) -> Result<Vec<(SpirvWord, SpirvWord)>, TranslateError> {
+    let mut graph = Graph::<SpirvWord, ()>::new();
+    let mut nodes = FxHashMap::default();
+    let mut node = |graph: &mut Graph<SpirvWord, ()>, label: SpirvWord| {
+        *nodes.entry(label).or_insert_with(|| graph.add_node(label))
+    };
+    let mut current = None;
+    let mut edges = Vec::new();
+    for statement in statements {
+        match statement {
+            Statement::Label(label) => {
+                let label = node(&mut graph, *label);
+                if let Some(previous) = current {
+                    graph.add_edge(previous, label, ());
+                }
+                current = Some(label);
+            }
+            Statement::Instruction(ast::Instruction::Bra { arguments }) => {
+                if let Some(from) = current.take() {
+                    edges.push((from, node(&mut graph, arguments.src)));
+                }
+            }
+            Statement::Conditional(BrachCondition {
+                if_true, if_false, ..
+            }) => {
+                if let Some(from) = current.take() {
+                    edges.push((from, node(&mut graph, *if_true)));
+                    edges.push((from, node(&mut graph, *if_false)));
+                }
+            }
+            _ => {}
+        }
+    }
+    for (from, to) in edges.iter() {
+        graph.add_edge(*from, *to, ());
+    }
+    let entry = match statements.first() {
+        Some(Statement::Label(label)) => node(&mut graph, *label),
+        _ => return Err(error_unreachable()),
+    };
+    let dominators = dominators::simple_fast(&graph, entry);
+    Ok(edges
+        .into_iter()
+        .filter(|(from, to)| {
+            dominators
+                .dominators(*from)
+                .is_some_and(|mut dominators| dominators.any(|header| header == *to))
+        })
+        .map(|(from, to)| (graph[from], graph[to]))
+        .collect())
+}
+
+fn get_memory_type(
+    context: LLVMContextRef,
+    type_: &ast::Type,
+) -> Result<LLVMTypeRef, TranslateError> {
+    match type_ {
+        ast::Type::Scalar(ast::ScalarType::Pred) => {
+            Ok(get_scalar_type(context, ast::ScalarType::B8))
+        }
+        _ => get_type(context, type_),
+    }
+}
+
 fn get_input_argument_type(
     context: LLVMContextRef,
     v_type: &ast::Type,
@@ -361,10 +615,15 @@ fn get_input_argument_type(
 struct MethodEmitContext<'a> {
     context: LLVMContextRef,
     module: LLVMModuleRef,
+    target_data: LLVMTargetDataRef,
     method: LLVMValueRef,
     builder: LLVMBuilderRef,
     variables_builder: Builder,
     resolver: &'a mut ResolveIdent,
+    correctly_rounded_div_sqrt: &'a mut bool,
+    // CC.CF of the extended precision instructions (add.cc, addc, ...), created on first use
+    carry_flag: Option<LLVMValueRef>,
+    volatile_coherence: VolatileCoherence,
 }
 
 impl<'a> MethodEmitContext<'a> {
@@ -376,13 +635,21 @@ impl<'a> MethodEmitContext<'a> {
         MethodEmitContext {
             context: parent.context,
             module: parent.module,
+            target_data: parent.target_data,
             builder: parent.builder.get(),
             variables_builder,
             resolver: &mut parent.resolver,
+            correctly_rounded_div_sqrt: &mut parent.correctly_rounded_div_sqrt,
             method,
+            carry_flag: None,
+            volatile_coherence: parent.volatile_coherence,
         }
     }
 
+    fn abi_alignment(&self, type_: LLVMTypeRef) -> u32 {
+        unsafe { LLVMABIAlignmentOfType(self.target_data, type_) }
+    }
+
     fn emit_statement(
         &mut self,
         statement: Statement<ast::Instruction<SpirvWord>, SpirvWord>,
@@ -390,14 +657,16 @@ impl<'a> MethodEmitContext<'a> {
         Ok(match statement {
             Statement::Variable(var) => self.emit_variable(var)?,
             Statement::Label(label) => self.emit_label_delayed(label)?,
-            Statement::Instruction(inst) => self.emit_instruction(inst)?,
+            Statement::Instruction(inst) => self.emit_instruction_traced(inst)?,
             Statement::Conditional(cond) => self.emit_conditional(cond)?,
             Statement::Conversion(conversion) => self.emit_conversion(conversion)?,
             Statement::Constant(constant) => self.emit_constant(constant)?,
             Statement::RetValue(_, values) => self.emit_ret_value(values)?,
             Statement::PtrAccess(ptr_access) => self.emit_ptr_access(ptr_access)?,
             Statement::RepackVector(repack) => self.emit_vector_repack(repack)?,
-            Statement::FunctionPointer(_) => todo!(),
+            Statement::FunctionPointer(_) => {
+                return Err(error_unsupported_instruction("mov of a function address"))
+            }
             Statement::VectorRead(vector_read) => self.emit_vector_read(vector_read)?,
             Statement::VectorWrite(vector_write) => self.emit_vector_write(vector_write)?,
             Statement::SetMode(mode_reg) => self.emit_set_mode(mode_reg)?,
@@ -433,7 +702,7 @@ impl<'a> MethodEmitContext<'a> {
         let alloca = unsafe {
             LLVMZludaBuildAlloca(
                 self.variables_builder.get(),
-                get_type(self.context, &var.v_type)?,
+                get_memory_type(self.context, &var.v_type)?,
                 get_state_space(var.state_space)?,
                 self.resolver.get_or_add_raw(var.name),
             )
@@ -443,7 +712,10 @@ impl<'a> MethodEmitContext<'a> {
             unsafe { LLVMSetAlignment(alloca, align) };
         }
         if !var.array_init.is_empty() {
-            todo!()
+            return Err(error_unsupported_state_space(
+                var.state_space,
+                "variable initializers",
+            ));
         }
         Ok(())
     }
@@ -471,6 +743,81 @@ impl<'a> MethodEmitContext<'a> {
         Ok(())
     }
 
+    // All latches of a loop share its loop ID, the ID holds the unrolling hints of the pragma
+    // given in the loop header. Loops without a pragma are left for LLVM to discover
+    fn emit_loop_metadata(
+        &mut self,
+        latches: &[(SpirvWord, SpirvWord)],
+        pragmas: &FxHashMap<SpirvWord, ast::LoopPragma>,
+    ) -> Result<(), TranslateError> {
+        let kind = unsafe {
+            LLVMGetMDKindIDInContext(
+                self.context,
+                "llvm.loop".as_ptr().cast(),
+                "llvm.loop".len() as u32,
+            )
+        };
+        let mut loop_ids = FxHashMap::default();
+        for (latch, header) in latches.iter().copied() {
+            let pragma = match pragmas.get(&header) {
+                Some(pragma) => *pragma,
+                None => continue,
+            };
+            let loop_id = *loop_ids
+                .entry(header)
+                .or_insert_with(|| self.loop_id(pragma));
+            let latch = unsafe { LLVMValueAsBasicBlock(self.resolver.value(latch)?) };
+            let branch = unsafe { LLVMGetBasicBlockTerminator(latch) };
+            if branch == ptr::null_mut() {
+                return Err(error_unreachable());
+            }
+            unsafe { LLVMSetMetadata(branch, kind, LLVMMetadataAsValue(self.context, loop_id)) };
+        }
+        Ok(())
+    }
+
+    // https://llvm.org/docs/LangRef.html#llvm-loop
+    fn loop_id(&self, pragma: ast::LoopPragma) -> LLVMMetadataRef {
+        let mut hint = match pragma {
+            ast::LoopPragma::NoUnroll => vec![self.md_string("llvm.loop.unroll.disable")],
+            ast::LoopPragma::Unroll(count) => {
+                let i32_type = unsafe { LLVMInt32TypeInContext(self.context) };
+                let count = unsafe { LLVMValueAsMetadata(LLVMConstInt(i32_type, count as u64, 0)) };
+                vec![self.md_string("llvm.loop.unroll.count"), count]
+            }
+        };
+        let hint = unsafe { LLVMMDNodeInContext2(self.context, hint.as_mut_ptr(), hint.len()) };
+        let mut properties = [hint];
+        unsafe { LLVMZludaLoopID(self.context, properties.as_mut_ptr(), properties.len()) }
+    }
+
+    fn md_string(&self, text: &str) -> LLVMMetadataRef {
+        unsafe { LLVMMDStringInContext2(self.context, text.as_ptr().cast(), text.len()) }
+    }
+
+    // Formatting the instruction is not free, so it's only done when trace messages are enabled.
+    // In release builds they never are and this is just emit_instruction
+    fn emit_instruction_traced(
+        &mut self,
+        inst: ast::Instruction<SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        if !log::log_enabled!(log::Level::Trace) {
+            return self.emit_instruction(inst);
+        }
+        let opcode = inst.to_string();
+        let before = unsafe { LLVMGetLastInstruction(LLVMGetInsertBlock(self.builder)) };
+        self.emit_instruction(inst)?;
+        let after = unsafe { LLVMGetLastInstruction(LLVMGetInsertBlock(self.builder)) };
+        if after.is_null() || after == before {
+            log::trace!("{opcode} -> no instruction");
+        } else {
+            log::trace!("{opcode} -> {:?}", unsafe {
+                LLVMGetInstructionOpcode(after)
+            });
+        }
+        Ok(())
+    }
+
     fn emit_instruction(
         &mut self,
         inst: ast::Instruction<SpirvWord>,
@@ -500,8 +847,21 @@ impl<'a> MethodEmitContext<'a> {
             ast::Instruction::Cvta { data, arguments } => self.emit_cvta(data, arguments),
             ast::Instruction::Abs { data, arguments } => self.emit_abs(data, arguments),
             ast::Instruction::Mad { data, arguments } => self.emit_mad(data, arguments),
+            ast::Instruction::MadExtended { data, arguments } => {
+                self.emit_mad_extended(data, arguments)
+            }
             ast::Instruction::Fma { data, arguments } => self.emit_fma(data, arguments),
             ast::Instruction::Sub { data, arguments } => self.emit_sub(data, arguments),
+            ast::Instruction::AddExtended { data, arguments } => {
+                let src1 = self.resolver.value(arguments.src1)?;
+                let src2 = self.resolver.value(arguments.src2)?;
+                self.emit_add_extended(data, false, arguments.dst, src1, src2)
+            }
+            ast::Instruction::SubExtended { data, arguments } => {
+                let src1 = self.resolver.value(arguments.src1)?;
+                let src2 = self.resolver.value(arguments.src2)?;
+                self.emit_add_extended(data, true, arguments.dst, src1, src2)
+            }
             ast::Instruction::Min { data, arguments } => self.emit_min(data, arguments),
             ast::Instruction::Max { data, arguments } => self.emit_max(data, arguments),
             ast::Instruction::Rcp { data, arguments } => self.emit_rcp(data, arguments),
@@ -522,25 +882,37 @@ impl<'a> MethodEmitContext<'a> {
             ast::Instruction::Xor { data, arguments } => self.emit_xor(data, arguments),
             ast::Instruction::Rem { data, arguments } => self.emit_rem(data, arguments),
             ast::Instruction::BarWarp { .. } => self.emit_bar_warp(),
-            ast::Instruction::PrmtSlow { .. } => {
-                Err(error_todo_msg("PrmtSlow is not implemented yet"))
-            }
+            ast::Instruction::PrmtSlow { .. } => Err(error_malformed_operand(
+                "prmt",
+                "selector must be an immediate value",
+            )),
             ast::Instruction::Prmt { data, arguments } => self.emit_prmt(data, arguments),
             ast::Instruction::Membar { data } => self.emit_membar(data),
             ast::Instruction::Trap {} => self.emit_trap(),
             ast::Instruction::Tanh { data, arguments } => self.emit_tanh(data, arguments),
             ast::Instruction::CpAsync { data, arguments } => self.emit_cp_async(data, arguments),
             ast::Instruction::CpAsyncCommitGroup {} => Ok(()), // nop
-            ast::Instruction::CpAsyncWaitGroup { .. } => Ok(()), // nop
-            ast::Instruction::CpAsyncWaitAll { .. } => Ok(()), // nop
+            // Copies are synchronous, so waiting only has to order them before the accesses
+            // that follow
+            ast::Instruction::CpAsyncWaitGroup { .. } | ast::Instruction::CpAsyncWaitAll {} => {
+                self.emit_membar(ast::MemScope::Cta)
+            }
             // replaced by a function call
             ast::Instruction::Bfe { .. }
             | ast::Instruction::Bar { .. }
+            | ast::Instruction::BarArrive { .. }
+            | ast::Instruction::BarrierCluster { .. }
             | ast::Instruction::BarRed { .. }
+            | ast::Instruction::BarRedPopc { .. }
             | ast::Instruction::Bfi { .. }
             | ast::Instruction::Activemask { .. }
             | ast::Instruction::ShflSync { .. }
             | ast::Instruction::Nanosleep { .. } => return Err(error_unreachable()),
+            // expanded or rejected by emulate_mma
+            ast::Instruction::Mma { .. }
+            | ast::Instruction::WmmaLoad { .. }
+            | ast::Instruction::WmmaStore { .. }
+            | ast::Instruction::WmmaMma { .. } => return Err(error_unreachable()),
         }
     }
 
@@ -549,20 +921,56 @@ impl<'a> MethodEmitContext<'a> {
         data: ast::LdDetails,
         arguments: ast::LdArgs<SpirvWord>,
     ) -> Result<(), TranslateError> {
-        if data.qualifier != ast::LdStQualifier::Weak {
-            todo!()
-        }
-        let builder = self.builder;
-        let type_ = get_type(self.context, &data.typ)?;
+        let volatile = match data.qualifier {
+            ast::LdStQualifier::Weak => false,
+            ast::LdStQualifier::Volatile => true,
+            qualifier => {
+                return Err(error_unsupported_instruction(format!(
+                    "ld{}",
+                    ld_st_qualifier_suffix(qualifier)
+                )))
+            }
+        };
         let ptr = self.resolver.value(arguments.src)?;
-        self.resolver.with_result(arguments.dst, |dst| {
-            let load = unsafe { LLVMBuildLoad2(builder, type_, ptr, dst) };
-            unsafe { LLVMSetAlignment(load, data.typ.layout().align() as u32) };
-            load
-        });
+        let value = self.emit_load(&data.typ, ptr, Some(arguments.dst))?;
+        if volatile {
+            // Predicates are loaded as i8 and truncated
+            let load = if data.typ == ast::Type::Scalar(ast::ScalarType::Pred) {
+                unsafe { LLVMGetOperand(value, 0) }
+            } else {
+                value
+            };
+            self.set_volatile(load, &data.typ, data.state_space);
+        }
         Ok(())
     }
 
+    // See VolatileCoherence
+    fn set_volatile(&self, access: LLVMValueRef, type_: &ast::Type, state_space: ast::StateSpace) {
+        unsafe { LLVMSetVolatile(access, 1) };
+        self.set_system_coherent(access, type_, state_space);
+    }
+
+    // Makes the access a system-scope monotonic atomic on APUs, see VolatileCoherence::System
+    fn set_system_coherent(
+        &self,
+        access: LLVMValueRef,
+        type_: &ast::Type,
+        state_space: ast::StateSpace,
+    ) {
+        let reaches_host = matches!(
+            state_space,
+            ast::StateSpace::Global | ast::StateSpace::Generic
+        );
+        // Atomic loads and stores can't be vectors, i1 is not used in memory anyway
+        let is_scalar =
+            matches!(type_, ast::Type::Scalar(type_) if *type_ != ast::ScalarType::Pred);
+        if self.volatile_coherence == VolatileCoherence::System && reaches_host && is_scalar {
+            // The default syncscope is the system scope
+            unsafe { LLVMSetOrdering(access, LLVMAtomicOrdering::LLVMAtomicOrderingMonotonic) };
+        }
+    }
+
     fn emit_conversion(&mut self, conversion: ImplicitConversion) -> Result<(), TranslateError> {
         let builder = self.builder;
         match conversion.kind {
@@ -714,7 +1122,7 @@ impl<'a> MethodEmitContext<'a> {
                 });
                 Ok(())
             }
-            _ => todo!(),
+            _ => Err(error_unreachable()),
         }
     }
 
@@ -767,6 +1175,80 @@ impl<'a> MethodEmitContext<'a> {
         Ok(())
     }
 
+    // The carry flag is kept in a function-local variable, so it survives any instructions between
+    // the one that sets it and the one that consumes it. LLVM promotes it to a register
+    fn carry_flag(&mut self) -> Result<LLVMValueRef, TranslateError> {
+        if let Some(flag) = self.carry_flag {
+            return Ok(flag);
+        }
+        let state_space = get_state_space(ast::StateSpace::Reg)?;
+        let flag = unsafe {
+            let i1 = LLVMInt1TypeInContext(self.context);
+            let flag = LLVMZludaBuildAlloca(
+                self.variables_builder.get(),
+                i1,
+                state_space,
+                c"carry_flag".as_ptr(),
+            );
+            LLVMBuildStore(self.variables_builder.get(), LLVMConstInt(i1, 0, 0), flag);
+            flag
+        };
+        self.carry_flag = Some(flag);
+        Ok(flag)
+    }
+
+    // Computes src1 + src2 (or src1 - src2) in twice the width of the type, so the bit above the
+    // result is the carry (or the borrow) of the operation
+    fn emit_add_extended(
+        &mut self,
+        data: ast::CarryDetails,
+        subtract: bool,
+        dst: SpirvWord,
+        src1: LLVMValueRef,
+        src2: LLVMValueRef,
+    ) -> Result<(), TranslateError> {
+        let width = (data.type_.layout().size() * 8) as u32;
+        let narrow_type = get_scalar_type(self.context, data.type_);
+        let wide_type = unsafe { LLVMIntTypeInContext(self.context, width * 2) };
+        let op = if subtract { LLVMBuildSub } else { LLVMBuildAdd };
+        let mut result = unsafe {
+            let src1 = LLVMBuildZExt(self.builder, src1, wide_type, LLVM_UNNAMED.as_ptr());
+            let src2 = LLVMBuildZExt(self.builder, src2, wide_type, LLVM_UNNAMED.as_ptr());
+            op(self.builder, src1, src2, LLVM_UNNAMED.as_ptr())
+        };
+        if data.carry_in {
+            let flag = self.carry_flag()?;
+            result = unsafe {
+                let i1 = LLVMInt1TypeInContext(self.context);
+                let carry = LLVMBuildLoad2(self.builder, i1, flag, LLVM_UNNAMED.as_ptr());
+                let carry = LLVMBuildZExt(self.builder, carry, wide_type, LLVM_UNNAMED.as_ptr());
+                op(self.builder, result, carry, LLVM_UNNAMED.as_ptr())
+            };
+        }
+        if data.carry_out {
+            let flag = self.carry_flag()?;
+            unsafe {
+                let shifted = LLVMBuildLShr(
+                    self.builder,
+                    result,
+                    LLVMConstInt(wide_type, width as u64, 0),
+                    LLVM_UNNAMED.as_ptr(),
+                );
+                let carry = LLVMBuildTrunc(
+                    self.builder,
+                    shifted,
+                    LLVMInt1TypeInContext(self.context),
+                    LLVM_UNNAMED.as_ptr(),
+                );
+                LLVMBuildStore(self.builder, carry, flag);
+            }
+        }
+        self.resolver.with_result(dst, |dst| unsafe {
+            LLVMBuildTrunc(self.builder, result, narrow_type, dst)
+        });
+        Ok(())
+    }
+
     fn emit_st(
         &self,
         data: ast::StData,
@@ -774,16 +1256,62 @@ impl<'a> MethodEmitContext<'a> {
     ) -> Result<(), TranslateError> {
         let ptr = self.resolver.value(arguments.src1)?;
         let value = self.resolver.value(arguments.src2)?;
-        if data.qualifier != ast::LdStQualifier::Weak {
-            todo!()
-        }
+        let volatile = match data.qualifier {
+            ast::LdStQualifier::Weak => false,
+            ast::LdStQualifier::Volatile => true,
+            qualifier => {
+                return Err(error_unsupported_instruction(format!(
+                    "st{}",
+                    ld_st_qualifier_suffix(qualifier)
+                )))
+            }
+        };
+        let memory_type = get_memory_type(self.context, &data.typ)?;
+        let value = if data.typ == ast::Type::Scalar(ast::ScalarType::Pred) {
+            unsafe { LLVMBuildZExt(self.builder, value, memory_type, LLVM_UNNAMED.as_ptr()) }
+        } else {
+            value
+        };
         let store = unsafe { LLVMBuildStore(self.builder, value, ptr) };
         unsafe {
-            LLVMSetAlignment(store, data.typ.layout().align() as u32);
+            LLVMSetAlignment(store, self.abi_alignment(memory_type));
+        }
+        if volatile {
+            self.set_volatile(store, &data.typ, data.state_space);
+        } else if data.caching == ast::StCacheOperator::Writethrough {
+            // st.wt writes through the caches to system memory, which on APUs takes the same
+            // system scope as a volatile store
+            self.set_system_coherent(store, &data.typ, data.state_space);
         }
         Ok(())
     }
 
+    // Predicates are kept in memory as i8, AMDGPU handles i1 loads and stores poorly
+    fn emit_load(
+        &mut self,
+        type_: &ast::Type,
+        ptr: LLVMValueRef,
+        dst: Option<SpirvWord>,
+    ) -> Result<LLVMValueRef, TranslateError> {
+        let builder = self.builder;
+        let memory_type = get_memory_type(self.context, type_)?;
+        let alignment = self.abi_alignment(memory_type);
+        Ok(if *type_ == ast::Type::Scalar(ast::ScalarType::Pred) {
+            let load = unsafe { LLVMBuildLoad2(builder, memory_type, ptr, LLVM_UNNAMED.as_ptr()) };
+            unsafe { LLVMSetAlignment(load, alignment) };
+            let pred_type = get_scalar_type(self.context, ast::ScalarType::Pred);
+            self.resolver.with_result_option(dst, |dst| unsafe {
+                LLVMBuildTrunc(builder, load, pred_type, dst)
+            })
+        } else {
+            self.resolver.with_result_option(dst, |dst| {
+                let load = unsafe { LLVMBuildLoad2(builder, memory_type, ptr, dst) };
+                unsafe { LLVMSetAlignment(load, alignment) };
+                load
+            })
+        })
+    }
+
     fn emit_ret(&self, _data: ast::RetData) {
         unsafe { LLVMBuildRetVoid(self.builder) };
     }
@@ -877,7 +1405,7 @@ impl<'a> MethodEmitContext<'a> {
         arguments: ast::AtomArgs<SpirvWord>,
     ) -> Result<(), TranslateError> {
         let builder = self.builder;
-        let src1 = self.resolver.value(arguments.src1)?;
+        let (src1, scope) = atomic_pointer(self.resolver.value(arguments.src1)?, data.scope);
         let src2 = self.resolver.value(arguments.src2)?;
         let op = match data.op {
             ast::AtomicOp::And => LLVMZludaAtomicRMWBinOp::LLVMZludaAtomicRMWBinOpAnd,
@@ -905,7 +1433,7 @@ impl<'a> MethodEmitContext<'a> {
                 op,
                 src1,
                 src2,
-                get_scope(data.scope)?,
+                get_scope(scope)?,
                 get_ordering(data.semantics),
             )
         });
@@ -917,7 +1445,7 @@ impl<'a> MethodEmitContext<'a> {
         data: ast::AtomCasDetails,
         arguments: ast::AtomCasArgs<SpirvWord>,
     ) -> Result<(), TranslateError> {
-        let src1 = self.resolver.value(arguments.src1)?;
+        let (src1, scope) = atomic_pointer(self.resolver.value(arguments.src1)?, data.scope);
         let src2 = self.resolver.value(arguments.src2)?;
         let src3 = self.resolver.value(arguments.src3)?;
         let success_ordering = get_ordering(data.semantics);
@@ -928,7 +1456,7 @@ impl<'a> MethodEmitContext<'a> {
                 src1,
                 src2,
                 src3,
-                get_scope(data.scope)?,
+                get_scope(scope)?,
                 success_ordering,
                 failure_ordering,
             )
@@ -981,13 +1509,7 @@ impl<'a> MethodEmitContext<'a> {
             .iter()
             .map(|(value, type_)| {
                 let value = self.resolver.value(*value)?;
-                let lowered_type = get_type(self.context, type_)?;
-                let load = unsafe {
-                    LLVMBuildLoad2(self.builder, lowered_type, value, LLVM_UNNAMED.as_ptr())
-                };
-                unsafe {
-                    LLVMSetAlignment(load, type_.layout().align() as u32);
-                }
+                let load = self.emit_load(type_, value, None)?;
                 Ok((load, type_))
             })
             .collect::<Result<Vec<_>, _>>()?;
@@ -1027,9 +1549,10 @@ impl<'a> MethodEmitContext<'a> {
         };
         let type_ = get_scalar_type(self.context, data.into());
         let pred = get_scalar_type(self.context, ast::ScalarType::Pred);
+        // llvm.ctlz returns the type of its operand
         let fn_type = get_function_type(
             self.context,
-            iter::once(&ast::ScalarType::U32.into()),
+            iter::once(&data.into()),
             [Ok(type_), Ok(pred)].into_iter(),
         )?;
         let mut fn_ = unsafe { LLVMGetNamedFunction(self.module, llvm_fn.as_ptr()) };
@@ -1039,7 +1562,7 @@ impl<'a> MethodEmitContext<'a> {
         let src = self.resolver.value(arguments.src)?;
         let false_ = unsafe { LLVMConstInt(pred, 0, 0) };
         let mut args = [src, false_];
-        self.resolver.with_result(arguments.dst, |dst| unsafe {
+        let mut call = |dst| unsafe {
             LLVMBuildCall2(
                 self.builder,
                 fn_type,
@@ -1048,10 +1571,24 @@ impl<'a> MethodEmitContext<'a> {
                 args.len() as u32,
                 dst,
             )
-        });
+        };
+        if data.size_of() == 4 {
+            self.resolver.with_result(arguments.dst, call);
+        } else {
+            let count = call(LLVM_UNNAMED.as_ptr());
+            self.emit_truncate_count(arguments.dst, count);
+        }
         Ok(())
     }
 
+    // clz.b64 and popc.b64 count the bits of a 64 bit value, but their destination is .u32
+    fn emit_truncate_count(&mut self, dst: SpirvWord, count: LLVMValueRef) {
+        let u32_type = get_scalar_type(self.context, ast::ScalarType::U32);
+        self.resolver.with_result(dst, |dst| unsafe {
+            LLVMBuildTrunc(self.builder, count, u32_type, dst)
+        });
+    }
+
     fn emit_mul(
         &mut self,
         data: ast::MulDetails,
@@ -1211,6 +1748,9 @@ impl<'a> MethodEmitContext<'a> {
     }
 
     fn emit_vector_repack(&mut self, repack: RepackVectorDetails) -> Result<(), TranslateError> {
+        if let ast::Type::Scalar(packed_type) = repack.packed_type {
+            return self.emit_scalar_repack(repack, packed_type);
+        }
         let i8_type = get_scalar_type(self.context, ast::ScalarType::B8);
         if repack.is_extract {
             let src = self.resolver.value(repack.packed)?;
@@ -1242,6 +1782,60 @@ impl<'a> MethodEmitContext<'a> {
         Ok(())
     }
 
+    // Element 0 is the least significant one, same as in memory
+    fn emit_scalar_repack(
+        &mut self,
+        repack: RepackVectorDetails,
+        packed_type: ast::ScalarType,
+    ) -> Result<(), TranslateError> {
+        let packed_llvm_type = get_scalar_type(self.context, packed_type);
+        let element_llvm_type = get_scalar_type(self.context, repack.typ);
+        let element_bits = repack.typ.size_of() as u64 * 8;
+        if repack.is_extract {
+            let src = self.resolver.value(repack.packed)?;
+            for (index, dst) in repack.unpacked.iter().enumerate() {
+                let shifted = if index == 0 {
+                    src
+                } else {
+                    let shift =
+                        unsafe { LLVMConstInt(packed_llvm_type, index as u64 * element_bits, 0) };
+                    unsafe { LLVMBuildLShr(self.builder, src, shift, LLVM_UNNAMED.as_ptr()) }
+                };
+                self.resolver.with_result(*dst, |dst| unsafe {
+                    LLVMBuildTrunc(self.builder, shifted, element_llvm_type, dst)
+                });
+            }
+        } else {
+            let mut packed = unsafe { LLVMConstNull(packed_llvm_type) };
+            for (index, src_id) in repack.unpacked.iter().enumerate() {
+                let dst = if index == repack.unpacked.len() - 1 {
+                    Some(repack.packed)
+                } else {
+                    None
+                };
+                let element = self.resolver.value(*src_id)?;
+                let mut wide = unsafe {
+                    LLVMBuildZExt(
+                        self.builder,
+                        element,
+                        packed_llvm_type,
+                        LLVM_UNNAMED.as_ptr(),
+                    )
+                };
+                if index != 0 {
+                    let shift =
+                        unsafe { LLVMConstInt(packed_llvm_type, index as u64 * element_bits, 0) };
+                    wide =
+                        unsafe { LLVMBuildShl(self.builder, wide, shift, LLVM_UNNAMED.as_ptr()) };
+                }
+                packed = self.resolver.with_result_option(dst, |dst| unsafe {
+                    LLVMBuildOr(self.builder, packed, wide, dst)
+                });
+            }
+        }
+        Ok(())
+    }
+
     fn emit_div(
         &mut self,
         data: ptx_parser::DivDetails,
@@ -1280,7 +1874,11 @@ impl<'a> MethodEmitContext<'a> {
                 LLVMZludaFastMathAllowReciprocal | LLVMZludaFastMathApproxFunc
             }
             ptx_parser::DivFloatKind::ApproxFull => LLVMZludaFastMathNone,
-            ptx_parser::DivFloatKind::Rounding(_) => LLVMZludaFastMathNone,
+            // No fast math flags and no fpmath metadata: fdiv is correctly rounded
+            ptx_parser::DivFloatKind::Rounding(_) => {
+                *self.correctly_rounded_div_sqrt = true;
+                LLVMZludaFastMathNone
+            }
         };
         let fdiv = self.resolver.with_result(arguments.dst, |dst| unsafe {
             LLVMBuildFDiv(builder, src1, src2, dst)
@@ -1335,8 +1933,14 @@ impl<'a> MethodEmitContext<'a> {
         let src = self.resolver.value(arguments.src)?;
         let temp_ptr =
             unsafe { LLVMBuildIntToPtr(self.builder, src, from_type, LLVM_UNNAMED.as_ptr()) };
+        let cast_ptr = unsafe {
+            LLVMBuildAddrSpaceCast(self.builder, temp_ptr, dest_type, LLVM_UNNAMED.as_ptr())
+        };
+        // Result is a 64 bit register even for .local and .shared pointers, which are 32 bit.
+        // Storing the pointer itself into the register would leave the upper half undefined
+        let int_type = get_scalar_type(self.context, ast::ScalarType::B64);
         self.resolver.with_result(arguments.dst, |dst| unsafe {
-            LLVMBuildAddrSpaceCast(self.builder, temp_ptr, dest_type, dst)
+            LLVMBuildPtrToInt(self.builder, cast_ptr, int_type, dst)
         });
         Ok(())
     }
@@ -1412,6 +2016,26 @@ impl<'a> MethodEmitContext<'a> {
         Ok(())
     }
 
+    // Calls a function from the ROCm device library, e.g. `__ocml_rsqrt_f64`. We only declare it,
+    // the definition comes from ocml.bc linked in by comgr. `type_` is both the type of the
+    // arguments and of the result
+    fn emit_ocml_call(
+        &mut self,
+        function: &str,
+        type_: ast::ScalarType,
+        dst: Option<SpirvWord>,
+        arguments: Vec<LLVMValueRef>,
+    ) -> Result<LLVMValueRef, TranslateError> {
+        let name = format!("__ocml_{}_{}\0", function, LLVMTypeDisplay(type_));
+        let llvm_type = get_scalar_type(self.context, type_);
+        self.emit_intrinsic(
+            unsafe { CStr::from_bytes_with_nul_unchecked(name.as_bytes()) },
+            dst,
+            Some(&type_.into()),
+            arguments.into_iter().map(|arg| (arg, llvm_type)).collect(),
+        )
+    }
+
     fn emit_intrinsic(
         &mut self,
         name: &CStr,
@@ -1489,23 +2113,27 @@ impl<'a> MethodEmitContext<'a> {
         data: ptx_parser::SetpData,
         arguments: ptx_parser::SetpArgs<SpirvWord>,
     ) -> Result<(), TranslateError> {
-        let dst = self.emit_setp_impl(data, arguments.dst2, arguments.src1, arguments.src2)?;
+        let dst = self.emit_setp_impl(data, arguments.src1, arguments.src2)?;
         self.resolver.register(arguments.dst1, dst);
+        // Second destination is the complement of the first
+        let true_ = self.pred_true();
+        self.resolver
+            .with_result_option(arguments.dst2, |dst2| unsafe {
+                LLVMBuildXor(self.builder, dst, true_, dst2)
+            });
         Ok(())
     }
 
+    fn pred_true(&self) -> LLVMValueRef {
+        unsafe { LLVMConstInt(LLVMIntTypeInContext(self.context, 1), 1, 0) }
+    }
+
     fn emit_setp_impl(
         &mut self,
         data: ptx_parser::SetpData,
-        dst2: Option<SpirvWord>,
         src1: SpirvWord,
         src2: SpirvWord,
     ) -> Result<LLVMValueRef, TranslateError> {
-        if dst2.is_some() {
-            return Err(error_todo_msg(
-                "setp with two dst arguments not yet supported",
-            ));
-        }
         match data.cmp_op {
             ptx_parser::SetpCompareOp::Integer(setp_compare_int) => {
                 self.emit_setp_int(setp_compare_int, src1, src2)
@@ -1599,6 +2227,9 @@ impl<'a> MethodEmitContext<'a> {
                 return self.emit_cvt_signed_to_unsigned_sat(data.from, data.to, arguments)
             }
             ptx_parser::CvtMode::FPExtend { .. } => LLVMBuildFPExt,
+            ptx_parser::CvtMode::FPTruncate {
+                satfinite: true, ..
+            } => return self.emit_cvt_satfinite(data.from, data.to, arguments),
             ptx_parser::CvtMode::FPTruncate { .. } => LLVMBuildFPTrunc,
             ptx_parser::CvtMode::FPRound {
                 integer_rounding: None,
@@ -1651,6 +2282,57 @@ impl<'a> MethodEmitContext<'a> {
         Ok(())
     }
 
+    // .satfinite: the source is clamped to the finite range of the destination type before it is
+    // rounded, so infinities and values that would round to an infinity become the largest finite
+    // value. minnum and maxnum return the bound for NaN, so NaN is selected back
+    fn emit_cvt_satfinite(
+        &mut self,
+        from: ptx_parser::ScalarType,
+        to: ptx_parser::ScalarType,
+        arguments: ptx_parser::CvtArgs<SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        let max_finite = match to {
+            ptx_parser::ScalarType::F16 => half::f16::MAX.to_f64(),
+            ptx_parser::ScalarType::BF16 => half::bf16::MAX.to_f64(),
+            ptx_parser::ScalarType::F32 => f32::MAX as f64,
+            _ => return Err(error_unreachable()),
+        };
+        let from_llvm = get_scalar_type(self.context, from);
+        let to_llvm = get_scalar_type(self.context, to);
+        let maxnum_intrinsic = format!("llvm.maxnum.{}\0", LLVMTypeDisplay(from));
+        let minnum_intrinsic = format!("llvm.minnum.{}\0", LLVMTypeDisplay(from));
+        let src = self.resolver.value(arguments.src)?;
+        let lowest = unsafe { LLVMConstReal(from_llvm, -max_finite) };
+        let highest = unsafe { LLVMConstReal(from_llvm, max_finite) };
+        let maxnum = self.emit_intrinsic(
+            unsafe { CStr::from_bytes_with_nul_unchecked(maxnum_intrinsic.as_bytes()) },
+            None,
+            Some(&from.into()),
+            vec![(src, from_llvm), (lowest, from_llvm)],
+        )?;
+        let clamped = self.emit_intrinsic(
+            unsafe { CStr::from_bytes_with_nul_unchecked(minnum_intrinsic.as_bytes()) },
+            None,
+            Some(&from.into()),
+            vec![(maxnum, from_llvm), (highest, from_llvm)],
+        )?;
+        let is_nan = unsafe {
+            LLVMBuildFCmp(
+                self.builder,
+                LLVMRealPredicate::LLVMRealUNO,
+                src,
+                src,
+                LLVM_UNNAMED.as_ptr(),
+            )
+        };
+        let value =
+            unsafe { LLVMBuildSelect(self.builder, is_nan, src, clamped, LLVM_UNNAMED.as_ptr()) };
+        self.resolver.with_result(arguments.dst, |dst| unsafe {
+            LLVMBuildFPTrunc(self.builder, value, to_llvm, dst)
+        });
+        Ok(())
+    }
+
     fn emit_cvt_unsigned_to_signed_sat(
         &mut self,
         from: ptx_parser::ScalarType,
@@ -1900,7 +2582,16 @@ impl<'a> MethodEmitContext<'a> {
         let type_ = get_scalar_type(self.context, data.type_);
         let intrinsic = match data.type_ {
             ast::ScalarType::F32 => c"llvm.amdgcn.rsq.f32",
-            ast::ScalarType::F64 => c"llvm.amdgcn.rsq.f64",
+            // v_rsq_f64 is not precise enough
+            ast::ScalarType::F64 => {
+                self.emit_ocml_call(
+                    "rsqrt",
+                    data.type_,
+                    Some(arguments.dst),
+                    vec![self.resolver.value(arguments.src)?],
+                )?;
+                return Ok(());
+            }
             _ => return Err(error_unreachable()),
         };
         self.emit_intrinsic(
@@ -1924,6 +2615,9 @@ impl<'a> MethodEmitContext<'a> {
             (ast::ScalarType::F64, ast::RcpKind::Compliant(..)) => c"llvm.sqrt.f64",
             _ => return Err(error_unreachable()),
         };
+        if let ast::RcpKind::Compliant(..) = data.kind {
+            *self.correctly_rounded_div_sqrt = true;
+        }
         self.emit_intrinsic(
             intrinsic,
             Some(arguments.dst),
@@ -1941,6 +2635,7 @@ impl<'a> MethodEmitContext<'a> {
         let type_ = get_scalar_type(self.context, data.type_);
         let intrinsic = match (data.type_, data.kind) {
             (ast::ScalarType::F32, ast::RcpKind::Approx) => c"llvm.amdgcn.rcp.f32",
+            (ast::ScalarType::F64, ast::RcpKind::Approx) => c"llvm.amdgcn.rcp.f64",
             (_, ast::RcpKind::Compliant(rnd)) => {
                 return self.emit_rcp_compliant(data, arguments, rnd)
             }
@@ -1964,10 +2659,11 @@ impl<'a> MethodEmitContext<'a> {
         let type_ = get_scalar_type(self.context, data.type_);
         let one = unsafe { LLVMConstReal(type_, 1.0) };
         let src = self.resolver.value(arguments.src)?;
-        let rcp = self.resolver.with_result(arguments.dst, |dst| unsafe {
+        // Without fast math flags, so LLVM can't replace it with an approximate v_rcp
+        self.resolver.with_result(arguments.dst, |dst| unsafe {
             LLVMBuildFDiv(self.builder, one, src, dst)
         });
-        unsafe { LLVMZludaSetFastMathFlags(rcp, LLVMZludaFastMathAllowReciprocal) };
+        *self.correctly_rounded_div_sqrt = true;
         Ok(())
     }
 
@@ -2221,12 +2917,16 @@ impl<'a> MethodEmitContext<'a> {
             _ => return Err(error_unreachable()),
         };
         let llvm_type = get_scalar_type(self.context, type_);
-        self.emit_intrinsic(
+        let dst = (type_.size_of() == 4).then_some(arguments.dst);
+        let count = self.emit_intrinsic(
             intrinsic,
-            Some(arguments.dst),
+            dst,
             Some(&type_.into()),
             vec![(self.resolver.value(arguments.src)?, llvm_type)],
         )?;
+        if dst.is_none() {
+            self.emit_truncate_count(arguments.dst, count);
+        }
         Ok(())
     }
 
@@ -2312,6 +3012,29 @@ impl<'a> MethodEmitContext<'a> {
         Ok(())
     }
 
+    fn emit_mad_extended(
+        &mut self,
+        data: ast::MadCarryDetails,
+        arguments: ast::MadExtendedArgs<SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        let control = if data.is_hi {
+            ast::MulIntControl::High
+        } else {
+            ast::MulIntControl::Low
+        };
+        let product = self.emit_mul_impl(
+            ast::MulDetails::Integer {
+                control,
+                type_: data.carry.type_,
+            },
+            None,
+            arguments.src1,
+            arguments.src2,
+        )?;
+        let src3 = self.resolver.value(arguments.src3)?;
+        self.emit_add_extended(data.carry, false, arguments.dst, product, src3)
+    }
+
     fn emit_mad(
         &mut self,
         data: ptx_parser::MadDetails,
@@ -2378,7 +3101,10 @@ impl<'a> MethodEmitContext<'a> {
             (control >> 12) & 0b1111,
         ];
         if components.iter().any(|&c| c > 7) {
-            return Err(TranslateError::Todo("".to_string()));
+            return Err(error_malformed_operand(
+                "prmt",
+                "sign-replicating selector values are not supported",
+            ));
         }
         let u32_type = get_scalar_type(self.context, ast::ScalarType::U32);
         let v4u8_type = get_type(self.context, &ast::Type::Vector(4, ast::ScalarType::U8))?;
@@ -2606,34 +3332,102 @@ impl<'a> MethodEmitContext<'a> {
         data: CpAsyncDetails,
         arguments: CpAsyncArgs<SpirvWord>,
     ) -> Result<(), TranslateError> {
-        // Asynchronous copies are not supported by all AMD hardware, so we just do a synchronous copy for now
+        // Asynchronous copies are not supported by all AMD hardware, so we just do a synchronous copy for now.
+        // Bytes past src-size are not read and are filled with zeros
         let to = self.resolver.value(arguments.src_to)?;
         let from = self.resolver.value(arguments.src_from)?;
-        let cp_size = data.cp_size;
-        let src_size = data.src_size.unwrap_or(cp_size.as_u64());
-
-        let from_type = unsafe { LLVMIntTypeInContext(self.context, (src_size as u32) * 8) };
-
-        let to_type = match cp_size {
-            ptx_parser::CpAsyncCpSize::Bytes4 => unsafe { LLVMInt32TypeInContext(self.context) },
-            ptx_parser::CpAsyncCpSize::Bytes8 => unsafe { LLVMInt64TypeInContext(self.context) },
-            ptx_parser::CpAsyncCpSize::Bytes16 => unsafe { LLVMInt128TypeInContext(self.context) },
+        let cp_size = data.cp_size.as_u64();
+        let align = cp_size as u32;
+        let to_type = unsafe { LLVMIntTypeInContext(self.context, (cp_size as u32) * 8) };
+        let value = match arguments.src_size {
+            Some(src_size) => {
+                let src_size = self.resolver.value(src_size)?;
+                self.emit_cp_async_masked_load(from, cp_size, src_size)?
+            }
+            None => {
+                let src_size = data.src_size.unwrap_or(cp_size).min(cp_size);
+                if src_size == 0 {
+                    unsafe { LLVMConstNull(to_type) }
+                } else {
+                    let from_type =
+                        unsafe { LLVMIntTypeInContext(self.context, (src_size as u32) * 8) };
+                    let load = unsafe {
+                        LLVMBuildLoad2(self.builder, from_type, from, LLVM_UNNAMED.as_ptr())
+                    };
+                    unsafe { LLVMSetAlignment(load, align) };
+                    unsafe { LLVMBuildZExt(self.builder, load, to_type, LLVM_UNNAMED.as_ptr()) }
+                }
+            }
         };
-
-        let load = unsafe { LLVMBuildLoad2(self.builder, from_type, from, LLVM_UNNAMED.as_ptr()) };
-        unsafe {
-            LLVMSetAlignment(load, (cp_size.as_u64() as u32) * 8);
-        }
-
-        let extended = unsafe { LLVMBuildZExt(self.builder, load, to_type, LLVM_UNNAMED.as_ptr()) };
-
-        unsafe { LLVMBuildStore(self.builder, extended, to) };
-        unsafe {
-            LLVMSetAlignment(load, (cp_size.as_u64() as u32) * 8);
-        }
+        let store = unsafe { LLVMBuildStore(self.builder, value, to) };
+        unsafe { LLVMSetAlignment(store, align) };
         Ok(())
     }
 
+    // src-size known only at runtime: bytes at and past src-size are masked off, so they are
+    // neither read nor faulted on
+    fn emit_cp_async_masked_load(
+        &mut self,
+        from: LLVMValueRef,
+        cp_size: u64,
+        src_size: LLVMValueRef,
+    ) -> Result<LLVMValueRef, TranslateError> {
+        let bytes_type = ast::Type::Vector(cp_size as u8, ast::ScalarType::B8);
+        let llvm_bytes_type = get_type(self.context, &bytes_type)?;
+        let mask = unsafe {
+            let i32_type = LLVMInt32TypeInContext(self.context);
+            let mut indices = (0..cp_size)
+                .map(|i| LLVMConstInt(i32_type, i, 0))
+                .collect::<Vec<_>>();
+            let indices = LLVMConstVector(indices.as_mut_ptr(), indices.len() as u32);
+            let src_size = LLVMBuildInsertElement(
+                self.builder,
+                LLVMGetUndef(LLVMTypeOf(indices)),
+                src_size,
+                LLVMConstInt(i32_type, 0, 0),
+                LLVM_UNNAMED.as_ptr(),
+            );
+            let src_size = LLVMBuildShuffleVector(
+                self.builder,
+                src_size,
+                LLVMGetUndef(LLVMTypeOf(indices)),
+                LLVMConstNull(LLVMTypeOf(indices)),
+                LLVM_UNNAMED.as_ptr(),
+            );
+            LLVMBuildICmp(
+                self.builder,
+                LLVMIntPredicate::LLVMIntULT,
+                indices,
+                src_size,
+                LLVM_UNNAMED.as_ptr(),
+            )
+        };
+        let address_space = unsafe { LLVMGetPointerAddressSpace(LLVMTypeOf(from)) };
+        let intrinsic = format!("llvm.masked.load.v{cp_size}i8.p{address_space}\0");
+        let bytes = self.emit_intrinsic(
+            unsafe { CStr::from_bytes_with_nul_unchecked(intrinsic.as_bytes()) },
+            None,
+            Some(&bytes_type),
+            vec![
+                (from, unsafe { LLVMTypeOf(from) }),
+                (
+                    unsafe { LLVMConstInt(LLVMInt32TypeInContext(self.context), cp_size, 0) },
+                    unsafe { LLVMInt32TypeInContext(self.context) },
+                ),
+                (mask, unsafe { LLVMTypeOf(mask) }),
+                (unsafe { LLVMConstNull(llvm_bytes_type) }, llvm_bytes_type),
+            ],
+        )?;
+        Ok(unsafe {
+            LLVMBuildBitCast(
+                self.builder,
+                bytes,
+                LLVMIntTypeInContext(self.context, (cp_size as u32) * 8),
+                LLVM_UNNAMED.as_ptr(),
+            )
+        })
+    }
+
     fn flush_denormals(
         &mut self,
         type_: ptx_parser::ScalarType,
@@ -2687,7 +3481,7 @@ impl<'a> MethodEmitContext<'a> {
         data: ptx_parser::SetData,
         arguments: ptx_parser::SetArgs<SpirvWord>,
     ) -> Result<(), TranslateError> {
-        let setp_result = self.emit_setp_impl(data.base, None, arguments.src1, arguments.src2)?;
+        let setp_result = self.emit_setp_impl(data.base, arguments.src1, arguments.src2)?;
         self.setp_to_set(arguments.dst, data.dtype, setp_result)?;
         Ok(())
     }
@@ -2697,8 +3491,13 @@ impl<'a> MethodEmitContext<'a> {
         data: ptx_parser::SetBoolData,
         arguments: ptx_parser::SetBoolArgs<SpirvWord>,
     ) -> Result<(), TranslateError> {
-        let result =
-            self.emit_setp_bool_impl(data.base, arguments.src1, arguments.src2, arguments.src3)?;
+        let result = self.emit_setp_bool_impl(
+            data.base,
+            None,
+            arguments.src1,
+            arguments.src2,
+            arguments.src3,
+        )?;
         self.setp_to_set(arguments.dst, data.dtype, result)?;
         Ok(())
     }
@@ -2708,7 +3507,7 @@ impl<'a> MethodEmitContext<'a> {
         data: ast::SetpBoolData,
         args: ast::SetpBoolArgs<SpirvWord>,
     ) -> Result<(), TranslateError> {
-        let dst = self.emit_setp_bool_impl(data, args.src1, args.src2, args.src3)?;
+        let dst = self.emit_setp_bool_impl(data, args.dst2, args.src1, args.src2, args.src3)?;
         self.resolver.register(args.dst1, dst);
         Ok(())
     }
@@ -2716,24 +3515,32 @@ impl<'a> MethodEmitContext<'a> {
     fn emit_setp_bool_impl(
         &mut self,
         data: ptx_parser::SetpBoolData,
+        dst2: Option<SpirvWord>,
         src1: SpirvWord,
         src2: SpirvWord,
         src3: SpirvWord,
     ) -> Result<LLVMValueRef, TranslateError> {
-        let bool_result = self.emit_setp_impl(data.base, None, src1, src2)?;
-        let bool_result = if data.negate_src3 {
-            let constant =
-                unsafe { LLVMConstInt(LLVMIntTypeInContext(self.context, 1), u64::MAX, 0) };
-            unsafe { LLVMBuildXor(self.builder, bool_result, constant, LLVM_UNNAMED.as_ptr()) }
+        let bool_result = self.emit_setp_impl(data.base, src1, src2)?;
+        let true_ = self.pred_true();
+        let src3 = self.resolver.value(src3)?;
+        let src3 = if data.negate_src3 {
+            unsafe { LLVMBuildXor(self.builder, src3, true_, LLVM_UNNAMED.as_ptr()) }
         } else {
-            bool_result
+            src3
         };
         let post_op = match data.bool_op {
             ptx_parser::SetpBoolPostOp::Xor => LLVMBuildXor,
             ptx_parser::SetpBoolPostOp::And => LLVMBuildAnd,
             ptx_parser::SetpBoolPostOp::Or => LLVMBuildOr,
         };
-        let src3 = self.resolver.value(src3)?;
+        // p = BoolOp(t, c), q = BoolOp(!t, c)
+        if let Some(dst2) = dst2 {
+            let negated_result =
+                unsafe { LLVMBuildXor(self.builder, bool_result, true_, LLVM_UNNAMED.as_ptr()) };
+            self.resolver.with_result(dst2, |dst2| unsafe {
+                post_op(self.builder, negated_result, src3, dst2)
+            });
+        }
         Ok(unsafe { post_op(self.builder, bool_result, src3, LLVM_UNNAMED.as_ptr()) })
     }
 
@@ -2763,14 +3570,7 @@ impl<'a> MethodEmitContext<'a> {
         arguments: ast::TanhArgs<SpirvWord>,
     ) -> Result<(), TranslateError> {
         let src = self.resolver.value(arguments.src)?;
-        let llvm_type = get_scalar_type(self.context, data);
-        let name = format!("__ocml_tanh_{}\0", LLVMTypeDisplay(data));
-        let tanh = self.emit_intrinsic(
-            unsafe { CStr::from_bytes_with_nul_unchecked(name.as_bytes()) },
-            Some(arguments.dst),
-            Some(&data.into()),
-            vec![(src, llvm_type)],
-        )?;
+        let tanh = self.emit_ocml_call("tanh", data, Some(arguments.dst), vec![src])?;
         // Not sure if it ultimately does anything
         unsafe { LLVMZludaSetFastMathFlags(tanh, LLVMZludaFastMathApproxFunc) }
         Ok(())
@@ -2784,9 +3584,11 @@ impl<'a> MethodEmitContext<'a> {
         let intrinsic = match (data.atype, data.btype) {
             (ast::ScalarType::U32, ast::ScalarType::U32) => c"llvm.amdgcn.udot4",
             (ast::ScalarType::S32, ast::ScalarType::S32) => c"llvm.amdgcn.sdot4",
-            (ast::ScalarType::U32, ast::ScalarType::S32)
-            | (ast::ScalarType::S32, ast::ScalarType::U32) => {
-                return Err(error_todo_msg("dp4a with mixed types is not yet supported"))
+            (ast::ScalarType::U32, ast::ScalarType::S32) => {
+                return Err(error_unsupported_instruction("dp4a.u32.s32"))
+            }
+            (ast::ScalarType::S32, ast::ScalarType::U32) => {
+                return Err(error_unsupported_instruction("dp4a.s32.u32"))
             }
             _ => return Err(error_unreachable()),
         };
@@ -2894,13 +3696,51 @@ fn get_pointer_type<'ctx>(
     Ok(unsafe { LLVMPointerTypeInContext(context, get_state_space(to_space)?) })
 }
 
+fn ld_st_qualifier_suffix(qualifier: ast::LdStQualifier) -> &'static str {
+    match qualifier {
+        ast::LdStQualifier::Weak => "",
+        ast::LdStQualifier::Volatile => ".volatile",
+        ast::LdStQualifier::Relaxed(_) => ".relaxed",
+        ast::LdStQualifier::Acquire(_) => ".acquire",
+        ast::LdStQualifier::Release(_) => ".release",
+    }
+}
+
 // https://llvm.org/docs/AMDGPUUsage.html#memory-scopes
+// Pointer and scope of an atomic operation. Shared memory is only visible to the threads of a
+// block, so atomics on it are at most workgroup-scoped, which lets the backend use plain LDS
+// atomics. Generic atomics on a shared variable (`atom.add.u32 d, [shmem], b`) get the pointer
+// before its conversion to generic, so they are also shared memory atomics
+fn atomic_pointer(pointer: LLVMValueRef, scope: ast::MemScope) -> (LLVMValueRef, ast::MemScope) {
+    let source = unsafe {
+        let is_cast = !LLVMIsAAddrSpaceCastInst(pointer).is_null()
+            || (!LLVMIsAConstantExpr(pointer).is_null()
+                && LLVMGetConstOpcode(pointer) == LLVMOpcode::LLVMAddrSpaceCast);
+        if is_cast {
+            LLVMGetOperand(pointer, 0)
+        } else {
+            pointer
+        }
+    };
+    if unsafe { LLVMGetPointerAddressSpace(LLVMTypeOf(source)) } != SHARED_ADDRESS_SPACE {
+        return (pointer, scope);
+    }
+    match scope {
+        ast::MemScope::Gpu | ast::MemScope::Sys => (source, ast::MemScope::Cta),
+        ast::MemScope::Cta | ast::MemScope::Cluster => (source, scope),
+    }
+}
+
 fn get_scope(scope: ast::MemScope) -> Result<*const i8, TranslateError> {
     Ok(match scope {
         ast::MemScope::Cta => c"workgroup-one-as",
         ast::MemScope::Gpu => c"agent-one-as",
         ast::MemScope::Sys => c"one-as",
-        ast::MemScope::Cluster => todo!(),
+        ast::MemScope::Cluster => {
+            return Err(error_unsupported_instruction(
+                "atomic operation with .cluster scope",
+            ))
+        }
     }
     .as_ptr())
 }
@@ -2910,7 +3750,7 @@ fn get_scope_membar(scope: ast::MemScope) -> Result<*const i8, TranslateError> {
         ast::MemScope::Cta => c"workgroup",
         ast::MemScope::Gpu => c"agent",
         ast::MemScope::Sys => c"",
-        ast::MemScope::Cluster => todo!(),
+        ast::MemScope::Cluster => return Err(error_unsupported_instruction("membar.cluster")),
     }
     .as_ptr())
 }
@@ -2966,10 +3806,15 @@ fn get_or_create_struct_type<'a>(
         ("struct".to_string(), Vec::new()),
         |(mut name, mut types), t| {
             name.push('.');
-            if let ast::Type::Scalar(scalar) = t {
-                write!(name, "{}", LLVMTypeDisplay(*scalar)).ok();
-            } else {
-                return Err(error_unreachable());
+            match t {
+                ast::Type::Scalar(scalar) => {
+                    write!(name, "{}", LLVMTypeDisplay(*scalar)).ok();
+                }
+                // Vector .param return values
+                ast::Type::Vector(len, scalar) => {
+                    write!(name, "v{}{}", len, LLVMTypeDisplay(*scalar)).ok();
+                }
+                ast::Type::Array(..) => return Err(error_unreachable()),
             }
             types.push(get_type(context, t)?);
             Ok((name, types))
@@ -3024,27 +3869,25 @@ fn get_function_type<'a>(
 }
 
 struct ResolveIdent {
-    words: HashMap<SpirvWord, String>,
-    values: HashMap<SpirvWord, LLVMValueRef>,
+    words: IdMap<String>,
+    values: IdMap<LLVMValueRef>,
 }
 
 impl ResolveIdent {
-    fn new<'input>(_id_defs: &GlobalStringIdentResolver2<'input>) -> Self {
+    fn new<'input>(id_defs: &GlobalStringIdentResolver2<'input>) -> Self {
+        let capacity = id_defs.current_id.0 as usize;
         ResolveIdent {
-            words: HashMap::new(),
-            values: HashMap::new(),
+            words: IdMap::with_capacity(capacity),
+            values: IdMap::with_capacity(capacity),
         }
     }
 
     fn get_or_ad_impl<'a, T>(&'a mut self, word: SpirvWord, fn_: impl FnOnce(&'a str) -> T) -> T {
-        let str = match self.words.entry(word) {
-            hash_map::Entry::Occupied(entry) => entry.into_mut(),
-            hash_map::Entry::Vacant(entry) => {
-                let mut text = word.0.to_string();
-                text.push('\0');
-                entry.insert(text)
-            }
-        };
+        let str = self.words.get_or_insert_with(word, || {
+            let mut text = word.0.to_string();
+            text.push('\0');
+            text
+        });
         fn_(&str[..str.len() - 1])
     }
 
@@ -0,0 +1,51 @@
+use super::super::*;
+use super::*;
+use llvm_zluda::core::*;
+use llvm_zluda::{LLVMAttributeFunctionIndex, LLVMCallConv, LLVMLinkage, LLVMUnnamedAddr};
+
+// Every function is emitted with external linkage, which stops LLVM from inlining small device
+// functions and forces a real call (with arguments spilled to the stack) on every invocation.
+// A module with kernels is compiled on its own and nothing outside of it calls into its device
+// code, so all functions other than kernels are private to the module, including .visible ones
+// (which is why emit marks them hidden). Here we make it explicit: every defined non-kernel
+// function which is only ever called directly gets internal linkage, unnamed_addr and an inline
+// hint. Modules without kernels don't get here, their functions are linked into other modules.
+// Functions whose address is taken are left alone, they might be called through a pointer. Weak
+// functions are left alone too, they are meant to be replaced when linking
+pub(crate) fn run(module: &llvm::Module) {
+    let context = unsafe { LLVMGetModuleContext(module.get()) };
+    let inline_hint = unsafe {
+        let kind =
+            LLVMGetEnumAttributeKindForName(b"inlinehint".as_ptr().cast(), b"inlinehint".len());
+        LLVMCreateEnumAttribute(context, kind, 0)
+    };
+    let mut fn_ = unsafe { LLVMGetFirstFunction(module.get()) };
+    while !fn_.is_null() {
+        if can_internalize(fn_) {
+            unsafe {
+                LLVMSetLinkage(fn_, LLVMLinkage::LLVMInternalLinkage);
+                LLVMSetUnnamedAddress(fn_, LLVMUnnamedAddr::LLVMGlobalUnnamedAddr);
+                LLVMAddAttributeAtIndex(fn_, LLVMAttributeFunctionIndex, inline_hint);
+            }
+        }
+        fn_ = unsafe { LLVMGetNextFunction(fn_) };
+    }
+}
+
+fn can_internalize(fn_: LLVMValueRef) -> bool {
+    if unsafe { LLVMIsDeclaration(fn_) } != 0
+        || unsafe { LLVMGetLinkage(fn_) } != LLVMLinkage::LLVMExternalLinkage
+        || unsafe { LLVMGetFunctionCallConv(fn_) } == LLVMCallConv::LLVMAMDGPUKERNELCallConv as u32
+    {
+        return false;
+    }
+    let mut use_ = unsafe { LLVMGetFirstUse(fn_) };
+    while !use_.is_null() {
+        let call = unsafe { LLVMIsACallInst(LLVMGetUser(use_)) };
+        if call.is_null() || unsafe { LLVMGetCalledValue(call) } != fn_ {
+            return false;
+        }
+        use_ = unsafe { LLVMGetNextUse(use_) };
+    }
+    true
+}
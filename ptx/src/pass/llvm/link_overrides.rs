@@ -0,0 +1,49 @@
+use super::super::*;
+use super::*;
+use llvm_zluda::bit_reader::LLVMGetBitcodeModuleInContext2;
+use std::ffi::CString;
+
+// Every overridden function the module refers to must be defined in ptx_impl. Otherwise
+// llvm-link quietly keeps the weak definition from the module, or leaves the declaration
+// unresolved until the final compilation. ptx_impl is loaded lazily, function bodies are never
+// read
+pub(crate) fn run(module: &llvm::Module, overrides: &LinkOverrides) -> Result<(), TranslateError> {
+    let mut referenced = Vec::new();
+    for name in overrides.iter() {
+        let name = format!("__zluda_ptx_impl_{name}");
+        if !named_function(module, &name)?.is_null() {
+            referenced.push(name);
+        }
+    }
+    if referenced.is_empty() {
+        return Ok(());
+    }
+    let context = unsafe { LLVMGetModuleContext(module.get()) };
+    let buffer = unsafe {
+        LLVMCreateMemoryBufferWithMemoryRange(
+            ZLUDA_PTX_IMPL.as_ptr().cast(),
+            ZLUDA_PTX_IMPL.len(),
+            c"ptx_impl.bc".as_ptr(),
+            0,
+        )
+    };
+    let mut ptx_impl = ptr::null_mut();
+    // The lazily loaded module takes ownership of the buffer
+    if unsafe { LLVMGetBitcodeModuleInContext2(context, buffer, &mut ptx_impl) } != 0 {
+        unsafe { LLVMDisposeMemoryBuffer(buffer) };
+        return Err(error_unreachable());
+    }
+    let ptx_impl = llvm::Module(ptx_impl);
+    for name in referenced {
+        let fn_ = named_function(&ptx_impl, &name)?;
+        if fn_.is_null() || unsafe { LLVMIsDeclaration(fn_) } != 0 {
+            return Err(TranslateError::UnresolvedLinkOverride(name));
+        }
+    }
+    Ok(())
+}
+
+fn named_function(module: &llvm::Module, name: &str) -> Result<LLVMValueRef, TranslateError> {
+    let name = CString::new(name).map_err(|_| error_unreachable())?;
+    Ok(unsafe { LLVMGetNamedFunction(module.get(), name.as_ptr()) })
+}
@@ -1,5 +1,7 @@
 pub(super) mod attributes;
 pub(super) mod emit;
+pub(super) mod internalize;
+pub(super) mod link_overrides;
 
 use std::ffi::CStr;
 use std::mem;
@@ -22,6 +24,21 @@ const SHARED_ADDRESS_SPACE: u32 = 3;
 const CONSTANT_ADDRESS_SPACE: u32 = 4;
 const PRIVATE_ADDRESS_SPACE: u32 = 5;
 
+// Same as what clang uses for amdgcn-amd-amdhsa. Pointers to private (5), region (2) and local (3)
+// memory are 32 bit wide
+const AMDGPU_TARGET_TRIPLE: &CStr = c"amdgcn-amd-amdhsa";
+const AMDGPU_DATA_LAYOUT: &CStr = c"e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8";
+
+/// Code object version of the kernels we emit. It decides the layout of the hidden kernel
+/// arguments (hostcall buffer, queue pointer, multigrid sync, ...) for both the backend and the
+/// device libraries, which pick `oclc_abi_version` from `-mcode-object-version`. The module flag
+/// set by us and the option passed to comgr must agree, otherwise hostcall (printf, assert)
+/// reads the wrong kernel argument
+pub const AMDHSA_CODE_OBJECT_VERSION: u32 = 5;
+// Size of the hidden kernel arguments of code object v5, appended by the runtime after the
+// user arguments, see https://llvm.org/docs/AMDGPUUsage.html#amdgpu-amdhsa-code-object-kernel-argument-metadata-map-table-v5
+const IMPLICIT_ARGUMENTS_SIZE: u32 = 256;
+
 pub(super) struct Context(LLVMContextRef);
 
 impl Context {
@@ -49,6 +66,11 @@ impl Module {
         Self(unsafe { LLVMModuleCreateWithNameInContext(name.as_ptr(), ctx.get()) })
     }
 
+    fn set_amdgpu_target(&self) {
+        unsafe { LLVMSetTarget(self.get(), AMDGPU_TARGET_TRIPLE.as_ptr()) };
+        unsafe { LLVMSetDataLayout(self.get(), AMDGPU_DATA_LAYOUT.as_ptr()) };
+    }
+
     fn get(&self) -> LLVMModuleRef {
         self.0
     }
@@ -78,6 +100,24 @@ impl Module {
         let asm = unsafe { LLVMPrintModuleToString(self.get()) };
         Message(unsafe { CStr::from_ptr(asm) })
     }
+
+    // Runs LLVM optimization passes given in the `opt -passes=...` syntax
+    #[cfg(test)]
+    pub(crate) fn run_passes(&self, passes: &CStr) {
+        use llvm_zluda::error::{LLVMDisposeErrorMessage, LLVMGetErrorMessage};
+        use llvm_zluda::transforms::pass_builder::*;
+        let options = unsafe { LLVMCreatePassBuilderOptions() };
+        let error = unsafe { LLVMRunPasses(self.get(), passes.as_ptr(), ptr::null_mut(), options) };
+        unsafe { LLVMDisposePassBuilderOptions(options) };
+        if !error.is_null() {
+            let message = unsafe { LLVMGetErrorMessage(error) };
+            let text = unsafe { CStr::from_ptr(message) }
+                .to_string_lossy()
+                .into_owned();
+            unsafe { LLVMDisposeErrorMessage(message) };
+            panic!("{}", text);
+        }
+    }
 }
 
 impl Drop for Module {
@@ -178,14 +218,16 @@ fn get_state_space(space: ast::StateSpace) -> Result<u32, TranslateError> {
     match space {
         ast::StateSpace::Reg => Ok(PRIVATE_ADDRESS_SPACE),
         ast::StateSpace::Generic => Ok(GENERIC_ADDRESS_SPACE),
-        ast::StateSpace::Param => Err(TranslateError::Todo("".to_string())),
+        ast::StateSpace::Param | ast::StateSpace::ParamFunc => {
+            Err(error_unsupported_state_space(space, "LLVM emission"))
+        }
         ast::StateSpace::ParamEntry => Ok(CONSTANT_ADDRESS_SPACE),
-        ast::StateSpace::ParamFunc => Err(TranslateError::Todo("".to_string())),
         ast::StateSpace::Local => Ok(PRIVATE_ADDRESS_SPACE),
         ast::StateSpace::Global => Ok(GLOBAL_ADDRESS_SPACE),
         ast::StateSpace::Const => Ok(CONSTANT_ADDRESS_SPACE),
         ast::StateSpace::Shared => Ok(SHARED_ADDRESS_SPACE),
-        ast::StateSpace::SharedCta => Err(TranslateError::Todo("".to_string())),
-        ast::StateSpace::SharedCluster => Err(TranslateError::Todo("".to_string())),
+        ast::StateSpace::SharedCta | ast::StateSpace::SharedCluster => {
+            Err(error_unsupported_state_space(space, "LLVM emission"))
+        }
     }
 }
@@ -1,38 +1,67 @@
 use ptx_parser as ast;
 use quick_error::quick_error;
-use rustc_hash::FxHashMap;
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::hash::Hash;
 use std::{
     borrow::Cow,
-    collections::{hash_map, HashMap},
+    collections::hash_map,
+    env,
     ffi::CString,
     iter,
+    ops::Index,
+    path::PathBuf,
+    sync::{Arc, Mutex, Once},
+    time::Instant,
 };
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
+// Maps and sets whose iteration order reaches the output keep the insertion order. Otherwise the
+// output would depend on the numbering of identifiers or on the hashes of names
+type FxIndexMap<K, V> = indexmap::IndexMap<K, V, rustc_hash::FxBuildHasher>;
+type FxIndexSet<T> = indexmap::IndexSet<T, rustc_hash::FxBuildHasher>;
+
+pub use trace::PassTrace;
+
+mod check_operand_types;
+mod collect_kernel_info;
+mod deduplicate_values;
 mod deparamize_functions;
+mod eliminate_dead_code;
+mod emulate_mma;
 mod expand_operands;
 mod fix_special_registers2;
+mod fold_constants;
+mod fuse_mul_add;
 mod hoist_globals;
+mod if_convert_predicates;
+mod infer_global_state_space;
 mod insert_explicit_load_store;
 mod insert_implicit_conversions2;
 mod insert_post_saturation;
 mod instruction_mode_to_global_mode;
+#[cfg(test)]
+pub(crate) mod interpret;
 pub mod llvm;
 mod normalize_basic_blocks;
 mod normalize_identifiers2;
 mod normalize_predicates2;
+mod promote_local_variables;
 mod remove_unreachable_basic_blocks;
 mod replace_instructions_with_functions;
 mod replace_instructions_with_functions_fp_required;
 mod replace_known_functions;
 mod resolve_function_pointers;
+mod scalarize_uniform_loads;
+mod select_entry_points;
+mod spirv;
+mod trace;
 
 #[cfg(test)]
 mod test;
 
-static ZLUDA_PTX_IMPL: &'static [u8] = include_bytes!("../../lib/zluda_ptx_impl.bc");
+pub(crate) static ZLUDA_PTX_IMPL: &'static [u8] = include_bytes!("../../lib/zluda_ptx_impl.bc");
 const ZLUDA_PTX_PREFIX: &'static str = "__zluda_ptx_impl_";
 
 quick_error! {
@@ -41,74 +70,746 @@ quick_error! {
         UnknownSymbol(symbol: String) {
             display("Unknown symbol: \"{}\"", symbol)
         }
+        UnknownIdentifier { name: String, similar: Vec<String> } {
+            display("Use of undeclared identifier \"{}\"{}", name, display_similar(similar))
+        }
+        // Span of the first definition is resolved to a location together with the span of the
+        // error. Special registers and function arguments have no span
+        DuplicateDefinition {
+            name: String,
+            first_definition: Option<ast::Span>,
+            first_location: Option<ast::SourceLocation>
+        } {
+            display("Duplicate definition of \"{}\"{}", name, display_first_location(first_location))
+        }
+        // Operand 0 is the guard predicate, the arguments are numbered from 1
+        OperandTypeMismatch {
+            instruction: String,
+            operand: usize,
+            name: String,
+            expected: String,
+            actual: String
+        } {
+            display("{} \"{}\" of `{}` has type {}, but the instruction expects {}", display_operand(*operand), name, instruction, actual, expected)
+        }
+        UnsupportedInstruction { opcode: String } {
+            display("Instruction `{}` is not supported yet", opcode)
+        }
+        UnsupportedClusterFeature { instruction: String } {
+            display("Instruction `{}` uses thread block clusters: cluster features unsupported (sm_90)", instruction)
+        }
+        UnsupportedStateSpace { space: String, context: String } {
+            display("State space {} is not supported in {}", space, context)
+        }
+        UnsupportedSpirv { feature: String } {
+            display("{} is not supported by the SPIR-V target yet", feature)
+        }
+        MalformedOperand { instruction: String, detail: String } {
+            display("Unsupported operand of `{}`: {}", instruction, detail)
+        }
+        UnsupportedAddress { operand: String, detail: String } {
+            display("Unsupported address operand `{}`: {}", operand, detail)
+        }
+        UnsupportedParamAccess { name: String, detail: String } {
+            display("Unsupported access to .param return value \"{}\": {}", name, detail)
+        }
+        KernelNotFound { name: String, available: Vec<String> } {
+            display("Kernel \"{}\" not found{}", name, display_available(available))
+        }
+        KernelParametersTooLarge { kernel: String, size: usize, limit: usize } {
+            display("Parameters of kernel \"{}\" take {} bytes, more than the limit of {} bytes", kernel, size, limit)
+        }
         UntypedSymbol {}
         MismatchedType {}
         Unreachable {}
+        UnresolvedLinkOverride(name: String) {
+            display("Overridden function \"{}\" has no definition in ptx_impl", name)
+        }
         Todo(msg: String) {
             display("TODO: {}", msg)
         }
+        // Location is resolved from the span only once the translation fails, since the passes
+        // don't have access to the module text
+        Located {
+            span: ast::Span,
+            location: Option<ast::SourceLocation>,
+            error: Box<TranslateError>
+        } {
+            display("{}", display_located(span, location, error))
+        }
+    }
+}
+
+fn display_similar(similar: &[String]) -> String {
+    if similar.is_empty() {
+        return String::new();
+    }
+    let similar = similar
+        .iter()
+        .map(|name| format!("\"{}\"", name))
+        .collect::<Vec<_>>();
+    format!(", did you mean {}?", similar.join(" or "))
+}
+
+fn display_available(available: &[String]) -> String {
+    if available.is_empty() {
+        return ", the module has no kernels".to_string();
+    }
+    let available = available
+        .iter()
+        .map(|name| format!("\"{}\"", name))
+        .collect::<Vec<_>>();
+    format!(", the module has {}", available.join(", "))
+}
+
+fn display_operand(operand: usize) -> String {
+    match operand {
+        0 => "Guard predicate".to_string(),
+        operand => format!("Operand {}", operand),
+    }
+}
+
+fn display_first_location(location: &Option<ast::SourceLocation>) -> String {
+    match location {
+        Some(location) => format!(", first defined on line {}", location.line),
+        None => String::new(),
+    }
+}
+
+fn display_located(
+    span: &ast::Span,
+    location: &Option<ast::SourceLocation>,
+    error: &TranslateError,
+) -> String {
+    match location {
+        Some(location) => location.annotate(error),
+        None => format!("byte {}: {}", span.start, error),
+    }
+}
+
+impl TranslateError {
+    // The most precise span wins: if the error already points to a statement we don't replace it
+    // with the span of the enclosing function
+    fn at(self, span: ast::Span) -> Self {
+        match self {
+            TranslateError::Located { .. } => self,
+            error => TranslateError::Located {
+                span,
+                location: None,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    fn resolve_location(self, text: &str) -> Self {
+        match self {
+            TranslateError::Located {
+                span,
+                location,
+                error,
+            } => TranslateError::Located {
+                span,
+                location: location.or_else(|| Some(span.location(text))),
+                error: Box::new(error.resolve_location(text)),
+            },
+            TranslateError::DuplicateDefinition {
+                name,
+                first_definition,
+                first_location,
+            } => TranslateError::DuplicateDefinition {
+                name,
+                first_definition,
+                first_location: first_location
+                    .or_else(|| first_definition.map(|span| span.location(text))),
+            },
+            error => error,
+        }
+    }
+
+    /// Error with the source location stripped
+    pub fn inner(&self) -> &TranslateError {
+        match self {
+            TranslateError::Located { error, .. } => error,
+            error => error,
+        }
+    }
+
+    pub fn location(&self) -> Option<&ast::SourceLocation> {
+        match self {
+            TranslateError::Located { location, .. } => location.as_ref(),
+            _ => None,
+        }
     }
 }
 
 /// GPU attributes needed at compile time.
-#[derive(serde::Serialize)]
+#[derive(Clone, Debug, serde::Serialize)]
 pub struct Attributes {
     /// Clock frequency in kHz.
     pub clock_rate: u32,
 }
 
+/// Optional passes of the translation pipeline, all of them except `unique_symbols` are enabled
+/// by default.
+#[derive(Clone, Debug)]
+pub struct TranslateOptions {
+    /// Replace jumps around predicated arithmetic with selp
+    pub if_convert_predicates: bool,
+    /// Turn generic loads and stores through pointers from kernel arguments into global ones
+    pub infer_global_state_space: bool,
+    /// Forward values of PTX registers within basic blocks instead of going through memory
+    pub promote_local_variables: bool,
+    /// Fuse float mul and add without rounding modifiers into fma, like ptxas does. Only values
+    /// forwarded by promote_local_variables are fused
+    pub fuse_mul_add: bool,
+    /// Replace integer instructions with constant inputs with constants
+    pub fold_constants: bool,
+    /// Reuse the first copy of identical constants and implicit conversions
+    pub deduplicate_values: bool,
+    /// Read special registers other than %clock once, at the start of the function
+    pub hoist_special_registers: bool,
+    /// Remove unused registers, loads, stores and fall-through labels
+    pub eliminate_dead_code: bool,
+    /// Load through the constant address space when every thread of an `ld.global.nc` uses the
+    /// same address, so the backend can use scalar loads
+    pub scalarize_uniform_loads: bool,
+    /// Give internal linkage to non-kernel functions, so LLVM can inline them. Skipped for
+    /// modules without kernels
+    pub internalize_functions: bool,
+    /// Append a hash of the module text to the names of functions and variables that are not
+    /// kernels, `.visible`, `.extern` or `.weak`, so that modules defining the same internal
+    /// symbols can be linked together
+    pub unique_symbols: bool,
+    /// How `ld.volatile` and `st.volatile` are kept coherent with the host, depends on the device
+    pub volatile_coherence: VolatileCoherence,
+    /// Functions replaced by their ZLUDA implementations when linking with ptx_impl
+    pub link_overrides: LinkOverrides,
+    /// Translate only these kernels, the functions they call and the variables they use. `None`
+    /// translates the whole module. [`Module::kernels`] lists every kernel either way
+    pub entry_points: Option<Vec<String>>,
+    /// Expand mma.sync.aligned.m16n8k8 with .f16 inputs into shuffles and scalar fma instead of
+    /// rejecting it. Slow, meant only to get programs using it running. Defaults to
+    /// ZLUDA_EMULATE_MMA=1, the other tensor core instructions are rejected either way
+    pub emulate_mma: bool,
+    /// Write the statements after every pass to this directory, defaults to ZLUDA_DUMP_DIR
+    pub dump_dir: Option<PathBuf>,
+}
+
+impl Default for TranslateOptions {
+    fn default() -> Self {
+        Self {
+            if_convert_predicates: true,
+            infer_global_state_space: true,
+            promote_local_variables: true,
+            fuse_mul_add: true,
+            fold_constants: true,
+            deduplicate_values: true,
+            hoist_special_registers: true,
+            eliminate_dead_code: true,
+            scalarize_uniform_loads: true,
+            internalize_functions: true,
+            unique_symbols: false,
+            volatile_coherence: VolatileCoherence::default(),
+            link_overrides: LinkOverrides::default(),
+            entry_points: None,
+            emulate_mma: env::var("ZLUDA_EMULATE_MMA").as_deref() == Ok("1"),
+            dump_dir: env::var_os("ZLUDA_DUMP_DIR").map(PathBuf::from),
+        }
+    }
+}
+
+/// Lowering of `ld.volatile` and `st.volatile`, and of `st.wt`. Kernels use them to talk to the
+/// host while they run, e.g. spin on a flag the host sets, so they have to see host writes and
+/// make their own writes visible to the host. How to get there depends on where host-visible
+/// memory lives.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VolatileCoherence {
+    /// Discrete GPUs. Host-visible memory is reached over PCIe and is not kept in the L2 cache, so
+    /// LLVM volatile accesses are enough: the backend sets GLC (SC0/SC1 on gfx94x) to bypass the
+    /// per-CU caches and waits for each access to complete
+    #[default]
+    Device,
+    /// Integrated GPUs (APUs). Host memory is the same DRAM and lines of it can stay in the L2
+    /// cache, which volatile alone does not bypass. Volatile scalar accesses to global or generic
+    /// memory are also monotonic atomics at system scope, for which the backend adds SLC/DLC (SC1,
+    /// NT) to go past the L2. `st.wt` stores get the same treatment without being volatile. PTX
+    /// volatile does not order other accesses, so no fences are added
+    System,
+}
+
+impl VolatileCoherence {
+    /// Strategy for a device by its `gcnArchName`, with or without target features (`gfx1103`,
+    /// `gfx90c:xnack-`)
+    pub fn for_arch(gcn_arch: &str) -> Self {
+        let arch = gcn_arch.split(':').next().unwrap_or(gcn_arch);
+        match arch {
+            // Carrizo, Stoney, Raven, Renoir/Cezanne, Van Gogh, Rembrandt, Raphael, Mendocino,
+            // Phoenix, Strix Point, Strix Halo, Krackan
+            "gfx801" | "gfx810" | "gfx902" | "gfx909" | "gfx90c" | "gfx1033" | "gfx1035"
+            | "gfx1036" | "gfx1037" | "gfx1103" | "gfx1150" | "gfx1151" | "gfx1152" => {
+                VolatileCoherence::System
+            }
+            _ => VolatileCoherence::Device,
+        }
+    }
+}
+
+/// Names of functions implemented by ZLUDA in ptx_impl as `__zluda_ptx_impl_<name>`. Calls to
+/// them always end up in the ZLUDA implementation: declarations are renamed and definitions in
+/// the module become weak, so the strong definition from ptx_impl wins when the two are linked.
+#[derive(Clone, Debug)]
+pub struct LinkOverrides(Vec<String>);
+
+impl LinkOverrides {
+    /// Overrides `name`, ptx_impl must define `__zluda_ptx_impl_<name>`
+    pub fn add(&mut self, name: impl Into<String>) {
+        self.0.push(name.into());
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|override_| override_ == name)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+impl Default for LinkOverrides {
+    fn default() -> Self {
+        Self(vec!["__assertfail".to_string()])
+    }
+}
+
 pub fn to_llvm_module<'input>(
     ast: ast::Module<'input>,
     attributes: Attributes,
 ) -> Result<Module, TranslateError> {
-    let mut flat_resolver = GlobalStringIdentResolver2::<'input>::new(SpirvWord(1));
-    let mut scoped_resolver = ScopedResolver::new(&mut flat_resolver);
-    let sreg_map = SpecialRegistersMap2::new(&mut scoped_resolver)?;
-    let directives = normalize_identifiers2::run(&mut scoped_resolver, ast.directives)?;
-    let directives = replace_known_functions::run(&mut flat_resolver, directives);
-    let directives = normalize_predicates2::run(&mut flat_resolver, directives)?;
-    let directives = resolve_function_pointers::run(directives)?;
-    let directives = fix_special_registers2::run(&mut flat_resolver, &sreg_map, directives)?;
-    let directives = expand_operands::run(&mut flat_resolver, directives)?;
-    let directives = insert_post_saturation::run(&mut flat_resolver, directives)?;
-    let directives = deparamize_functions::run(&mut flat_resolver, directives)?;
-    let directives =
-        replace_instructions_with_functions_fp_required::run(&mut flat_resolver, directives)?;
-    let directives = normalize_basic_blocks::run(&mut flat_resolver, directives)?;
-    let directives = remove_unreachable_basic_blocks::run(directives)?;
-    let directives = instruction_mode_to_global_mode::run(&mut flat_resolver, directives)?;
-    let directives = insert_explicit_load_store::run(&mut flat_resolver, directives)?;
-    let directives = insert_implicit_conversions2::run(&mut flat_resolver, directives)?;
-    let directives = replace_instructions_with_functions::run(&mut flat_resolver, directives)?;
-    let directives = hoist_globals::run(directives)?;
+    to_llvm_module_with_options(ast, attributes, TranslateOptions::default())
+}
 
+pub fn to_llvm_module_with_options<'input>(
+    ast: ast::Module<'input>,
+    attributes: Attributes,
+    options: TranslateOptions,
+) -> Result<Module, TranslateError> {
+    to_llvm_module_with_names(ast, attributes, options, &NameInterner::default())
+}
+
+pub(crate) fn to_llvm_module_with_names<'input: 'a, 'a>(
+    ast: ast::Module<'input>,
+    attributes: Attributes,
+    options: TranslateOptions,
+    names: &'a NameInterner,
+) -> Result<Module, TranslateError> {
+    let text = ast.text;
+    translate(ast, attributes, options, names).map_err(|err| err.resolve_location(text))
+}
+
+fn translate<'input: 'a, 'a>(
+    ast: ast::Module<'input>,
+    attributes: Attributes,
+    options: TranslateOptions,
+    names: &'a NameInterner,
+) -> Result<Module, TranslateError> {
+    discard_debug_sections(&ast.sections);
+    let Lowered {
+        resolver,
+        directives,
+        kernels,
+        statistics,
+        symbol_suffix,
+    } = lower(ast, &options, names)?;
     let context = llvm::Context::new();
-    let llvm_ir = llvm::emit::run(&context, flat_resolver, directives)?;
+    let (llvm_ir, correctly_rounded_div_sqrt) = llvm::emit::run(
+        &context,
+        resolver,
+        symbol_suffix.as_deref(),
+        options.volatile_coherence,
+        directives,
+    )?;
+    llvm::link_overrides::run(&llvm_ir, &options.link_overrides)?;
+    // Modules without kernels are libraries of functions and globals for other modules to link
+    // against, internal functions nobody calls would be dropped by the first optimization pass
+    if options.internalize_functions && !kernels.is_empty() {
+        llvm::internalize::run(&llvm_ir);
+    }
     let attributes_ir = llvm::attributes::run(&context, attributes)?;
     Ok(Module {
         llvm_ir,
         attributes_ir,
-        kernel_info: HashMap::new(),
+        kernels,
+        statistics,
+        correctly_rounded_div_sqrt,
         _context: context,
     })
 }
 
+/// Same passes as [`to_llvm_module_with_options`], but the module is emitted as SPIR-V for
+/// OpenCL consumers. Only a subset of PTX is supported so far, see
+/// [`TranslateError::UnsupportedSpirv`]
+pub fn to_spirv_module_with_options<'input>(
+    ast: ast::Module<'input>,
+    options: TranslateOptions,
+) -> Result<SpirvModule, TranslateError> {
+    to_spirv_module_with_names(ast, options, &NameInterner::default())
+}
+
+pub(crate) fn to_spirv_module_with_names<'input: 'a, 'a>(
+    ast: ast::Module<'input>,
+    options: TranslateOptions,
+    names: &'a NameInterner,
+) -> Result<SpirvModule, TranslateError> {
+    let text = ast.text;
+    translate_spirv(ast, options, names).map_err(|err| err.resolve_location(text))
+}
+
+fn translate_spirv<'input: 'a, 'a>(
+    ast: ast::Module<'input>,
+    mut options: TranslateOptions,
+    names: &'a NameInterner,
+) -> Result<SpirvModule, TranslateError> {
+    discard_debug_sections(&ast.sections);
+    // Loads through the constant address space are a trick for the AMDGPU backend
+    options.scalarize_uniform_loads = false;
+    let Lowered {
+        resolver,
+        directives,
+        kernels,
+        statistics,
+        ..
+    } = lower(ast, &options, names)?;
+    let module = spirv::run(&resolver, directives)?;
+    Ok(SpirvModule {
+        words: rspirv::binary::Assemble::assemble(&module),
+        kernels,
+        statistics,
+    })
+}
+
+// DWARF of debug builds (nvcc -G) describes the PTX, not the code we emit, so it is dropped.
+// Every module of such a build carries it, saying it once per process is enough
+fn discard_debug_sections(sections: &[ast::Section]) {
+    static NOTICE: Once = Once::new();
+    if !sections.is_empty() {
+        NOTICE.call_once(|| log::info!("Device debug info (.section directives) is discarded"));
+    }
+}
+
+// Module after every pass of the pipeline, ready to be emitted as LLVM IR
+struct Lowered<'input> {
+    resolver: GlobalStringIdentResolver2<'input>,
+    directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
+    kernels: Vec<KernelInfo>,
+    statistics: PassStatistics,
+    symbol_suffix: Option<String>,
+}
+
+// Identifiers of the module borrow from its text and from the interner of made-up names
+fn lower<'input: 'a, 'a>(
+    ast: ast::Module<'input>,
+    options: &TranslateOptions,
+    names: &'a NameInterner,
+) -> Result<Lowered<'a>, TranslateError> {
+    let mut statistics = PassStatistics::default();
+    let symbol_suffix = options
+        .unique_symbols
+        .then(|| format!(".{:016x}", module_hash(ast.text)));
+    let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1), names);
+    let mut tracer = trace::PassTracer::new(options.dump_dir.as_deref(), &flat_resolver);
+    let kernels = collect_kernel_info::run(&ast)?;
+    let start = Instant::now();
+    let mut scoped_resolver = ScopedResolver::new(&mut flat_resolver);
+    let sreg_map = SpecialRegistersMap2::new(&mut scoped_resolver)?;
+    let directives = normalize_identifiers2::run(&mut scoped_resolver, ast.directives)?;
+    tracer.finish(&flat_resolver, "normalize_identifiers2", start, &directives);
+    let resolver = &mut flat_resolver;
+    let directives = match &options.entry_points {
+        Some(entry_points) => tracer.run(resolver, "select_entry_points", |resolver| {
+            select_entry_points::run(resolver, entry_points, directives)
+        })?,
+        None => directives,
+    };
+    let directives = tracer.run(resolver, "check_operand_types", |resolver| {
+        check_operand_types::run(resolver, &sreg_map, ast.text, &directives)?;
+        Ok(directives)
+    })?;
+    let directives = tracer.run(resolver, "emulate_mma", |resolver| {
+        emulate_mma::run(resolver, &sreg_map, options.emulate_mma, directives)
+    })?;
+    let directives = tracer.run(resolver, "replace_known_functions", |resolver| {
+        Ok(replace_known_functions::run(
+            resolver,
+            &options.link_overrides,
+            directives,
+        ))
+    })?;
+    let directives = if options.if_convert_predicates {
+        tracer.run(resolver, "if_convert_predicates", |resolver| {
+            if_convert_predicates::run(resolver, &mut statistics, directives)
+        })?
+    } else {
+        directives
+    };
+    let directives = tracer.run(resolver, "normalize_predicates2", |resolver| {
+        normalize_predicates2::run(resolver, directives)
+    })?;
+    let directives = tracer.run(resolver, "resolve_function_pointers", |_| {
+        resolve_function_pointers::run(directives)
+    })?;
+    let directives = tracer.run(resolver, "fix_special_registers2", |resolver| {
+        fix_special_registers2::run(
+            resolver,
+            &sreg_map,
+            options.hoist_special_registers,
+            &mut statistics,
+            directives,
+        )
+    })?;
+    let directives = tracer.run(resolver, "expand_operands", |resolver| {
+        expand_operands::run(resolver, directives)
+    })?;
+    let directives = if options.infer_global_state_space {
+        tracer.run(resolver, "infer_global_state_space", |_| {
+            infer_global_state_space::run(&mut statistics, directives)
+        })?
+    } else {
+        directives
+    };
+    let directives = tracer.run(resolver, "insert_post_saturation", |resolver| {
+        insert_post_saturation::run(resolver, directives)
+    })?;
+    let directives = tracer.run(resolver, "deparamize_functions", |resolver| {
+        deparamize_functions::run(resolver, directives)
+    })?;
+    let directives = tracer.run(
+        resolver,
+        "replace_instructions_with_functions_fp_required",
+        |resolver| replace_instructions_with_functions_fp_required::run(resolver, directives),
+    )?;
+    let directives = tracer.run(resolver, "normalize_basic_blocks", |resolver| {
+        normalize_basic_blocks::run(resolver, directives)
+    })?;
+    let directives = tracer.run(resolver, "remove_unreachable_basic_blocks", |_| {
+        remove_unreachable_basic_blocks::run(directives)
+    })?;
+    let directives = tracer.run(resolver, "instruction_mode_to_global_mode", |resolver| {
+        instruction_mode_to_global_mode::run(resolver, directives)
+    })?;
+    let directives = tracer.run(resolver, "insert_explicit_load_store", |resolver| {
+        insert_explicit_load_store::run(resolver, directives)
+    })?;
+    let directives = tracer.run(resolver, "insert_implicit_conversions2", |resolver| {
+        insert_implicit_conversions2::run(resolver, directives)
+    })?;
+    let directives = tracer.run(
+        resolver,
+        "replace_instructions_with_functions",
+        |resolver| replace_instructions_with_functions::run(resolver, directives),
+    )?;
+    let directives = if options.promote_local_variables {
+        tracer.run(resolver, "promote_local_variables", |_| {
+            promote_local_variables::run(&mut statistics, directives)
+        })?
+    } else {
+        directives
+    };
+    let directives = if options.fuse_mul_add {
+        tracer.run(resolver, "fuse_mul_add", |_| {
+            fuse_mul_add::run(&mut statistics, directives)
+        })?
+    } else {
+        directives
+    };
+    let directives = if options.fold_constants {
+        tracer.run(resolver, "fold_constants", |_| {
+            fold_constants::run(&mut statistics, directives)
+        })?
+    } else {
+        directives
+    };
+    let directives = if options.deduplicate_values {
+        tracer.run(resolver, "deduplicate_values", |_| {
+            deduplicate_values::run(&mut statistics, directives)
+        })?
+    } else {
+        directives
+    };
+    let directives = if options.eliminate_dead_code {
+        tracer.run(resolver, "eliminate_dead_code", |_| {
+            eliminate_dead_code::run(&mut statistics, directives)
+        })?
+    } else {
+        directives
+    };
+    let directives = if options.scalarize_uniform_loads {
+        tracer.run(resolver, "scalarize_uniform_loads", |resolver| {
+            scalarize_uniform_loads::run(resolver, &mut statistics, directives)
+        })?
+    } else {
+        directives
+    };
+    let directives = tracer.run(resolver, "hoist_globals", |_| {
+        hoist_globals::run(directives)
+    })?;
+    statistics.passes = tracer.into_passes();
+    Ok(Lowered {
+        resolver: flat_resolver,
+        directives,
+        kernels,
+        statistics,
+        symbol_suffix,
+    })
+}
+
+// FNV-1a, unlike DefaultHasher it gives the same names with every Rust version
+fn module_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 pub struct Module {
     pub llvm_ir: llvm::Module,
     pub attributes_ir: llvm::Module,
-    pub kernel_info: HashMap<String, KernelInfo>,
+    /// Kernels (`.entry` functions) in declaration order
+    pub kernels: Vec<KernelInfo>,
+    pub statistics: PassStatistics,
+    /// Module contains `.rn` divisions or square roots, so it must be compiled with correctly
+    /// rounded and denormal-preserving device library controls
+    pub correctly_rounded_div_sqrt: bool,
     _context: llvm::Context,
 }
 
+pub struct SpirvModule {
+    /// Assembled SPIR-V words of an OpenCL kernel module
+    pub words: Vec<u32>,
+    /// Kernels (`.entry` functions) in declaration order
+    pub kernels: Vec<KernelInfo>,
+    pub statistics: PassStatistics,
+}
+
+/// Counters collected by the passes during translation, summed over all function bodies.
+#[derive(Default, Debug, Clone)]
+pub struct PassStatistics {
+    /// Number of predicated instructions turned into selp by if_convert_predicates
+    pub if_converted_instructions: usize,
+    /// Number of generic loads and stores turned into global ones by infer_global_state_space
+    pub inferred_global_accesses: usize,
+    /// Number of local variables (mostly PTX registers) removed by promote_local_variables
+    pub promoted_variables: usize,
+    /// Number of statements before promote_local_variables
+    pub statements_before_promotion: usize,
+    /// Number of statements after promote_local_variables
+    pub statements_after_promotion: usize,
+    /// Number of mul and add pairs turned into fma by fuse_mul_add
+    pub fused_mul_adds: usize,
+    /// Number of instructions replaced with constants by fold_constants
+    pub folded_instructions: usize,
+    /// Number of constants and implicit conversions removed by deduplicate_values
+    pub deduplicated_values: usize,
+    /// Number of special register reads replaced with a read at the function start by
+    /// fix_special_registers2
+    pub hoisted_special_registers: usize,
+    /// Number of statements removed by eliminate_dead_code
+    pub eliminated_statements: usize,
+    /// Number of `ld.global.nc` instructions turned into constant loads by
+    /// scalarize_uniform_loads
+    pub scalarized_loads: usize,
+    /// Every pass in the order it ran
+    pub passes: Vec<PassTrace>,
+}
+
+impl PassStatistics {
+    // Adds the counters of `other`, pass traces are not counters and are left as they are
+    fn add(&mut self, other: &PassStatistics) {
+        let PassStatistics {
+            if_converted_instructions,
+            inferred_global_accesses,
+            promoted_variables,
+            statements_before_promotion,
+            statements_after_promotion,
+            fused_mul_adds,
+            folded_instructions,
+            deduplicated_values,
+            hoisted_special_registers,
+            eliminated_statements,
+            scalarized_loads,
+            passes: _,
+        } = other;
+        self.if_converted_instructions += if_converted_instructions;
+        self.inferred_global_accesses += inferred_global_accesses;
+        self.promoted_variables += promoted_variables;
+        self.statements_before_promotion += statements_before_promotion;
+        self.statements_after_promotion += statements_after_promotion;
+        self.fused_mul_adds += fused_mul_adds;
+        self.folded_instructions += folded_instructions;
+        self.deduplicated_values += deduplicated_values;
+        self.hoisted_special_registers += hoisted_special_registers;
+        self.eliminated_statements += eliminated_statements;
+        self.scalarized_loads += scalarized_loads;
+    }
+}
+
 impl Module {
     pub fn linked_bitcode(&self) -> &[u8] {
         ZLUDA_PTX_IMPL
     }
 }
 
+/// Launch-relevant information about a single kernel.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct KernelInfo {
-    pub arguments_sizes: Vec<(usize, bool)>,
-    pub uses_shared_mem: bool,
+    pub name: String,
+    /// Kernel parameters in declaration order
+    pub parameters: Vec<ParameterInfo>,
+    /// Bytes of statically sized `.shared` variables declared in the kernel body and at module
+    /// scope
+    pub static_shared_memory: usize,
+    /// Kernel uses unsized `.extern .shared` arrays, sized at launch
+    pub dynamic_shared_memory: bool,
+    /// Bytes of `.local` variables declared in the kernel body
+    pub local_memory: usize,
+    /// Bytes of `.const` variables declared at module scope
+    pub constant_memory: usize,
+    /// `.maxntid`, `.reqntid`, `.minnctapersm` and `.maxnreg` directives of the kernel
+    pub tuning: Vec<ast::TuningDirective>,
+}
+
+impl KernelInfo {
+    /// Bytes taken by the parameters laid out in declaration order, every parameter at an offset
+    /// aligned to its alignment
+    pub fn parameters_size(&self) -> usize {
+        self.parameters.iter().fold(0, |offset, param| {
+            offset.next_multiple_of(param.align) + param.size
+        })
+    }
+
+    /// Block dimensions of `.maxntid`, the kernel can't be launched with more threads in total
+    pub fn max_ntid(&self) -> Option<(u32, u32, u32)> {
+        self.tuning.iter().find_map(|directive| match directive {
+            ast::TuningDirective::MaxNtid(x, y, z) => Some((*x, *y, *z)),
+            _ => None,
+        })
+    }
+
+    /// Block dimensions of `.reqntid`, the only block the kernel can be launched with
+    pub fn req_ntid(&self) -> Option<(u32, u32, u32)> {
+        self.tuning.iter().find_map(|directive| match directive {
+            ast::TuningDirective::ReqNtid(x, y, z) => Some((*x, *y, *z)),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParameterInfo {
+    pub size: usize,
+    pub align: usize,
+    /// Value of the parameter is used as an address or the parameter is declared with `.ptr`
+    pub is_pointer: bool,
+    /// Alignment of the memory the parameter points to, from `.ptr .align N`
+    pub pointee_align: Option<usize>,
 }
 
 #[derive(Ord, PartialOrd, Eq, PartialEq, Hash, Copy, Clone, EnumIter)]
@@ -119,6 +820,8 @@ enum PtxSpecialRegister {
     Nctaid,
     Clock,
     LanemaskLt,
+    DynamicSmemSize,
+    TotalSmemSize,
 }
 
 impl PtxSpecialRegister {
@@ -130,6 +833,8 @@ impl PtxSpecialRegister {
             Self::Nctaid => "%nctaid",
             Self::Clock => "%clock",
             Self::LanemaskLt => "%lanemask_lt",
+            Self::DynamicSmemSize => "%dynamic_smem_size",
+            Self::TotalSmemSize => "%total_smem_size",
         }
     }
 
@@ -151,6 +856,8 @@ impl PtxSpecialRegister {
             PtxSpecialRegister::Nctaid => ast::ScalarType::U32,
             PtxSpecialRegister::Clock => ast::ScalarType::U32,
             PtxSpecialRegister::LanemaskLt => ast::ScalarType::U32,
+            PtxSpecialRegister::DynamicSmemSize => ast::ScalarType::U32,
+            PtxSpecialRegister::TotalSmemSize => ast::ScalarType::U32,
         }
     }
 
@@ -160,7 +867,38 @@ impl PtxSpecialRegister {
             | PtxSpecialRegister::Ntid
             | PtxSpecialRegister::Ctaid
             | PtxSpecialRegister::Nctaid => Some(ast::ScalarType::U8),
-            PtxSpecialRegister::Clock | PtxSpecialRegister::LanemaskLt => None,
+            PtxSpecialRegister::Clock
+            | PtxSpecialRegister::LanemaskLt
+            | PtxSpecialRegister::DynamicSmemSize
+            | PtxSpecialRegister::TotalSmemSize => None,
+        }
+    }
+
+    // Values that can't change during the lifetime of a thread
+    fn is_invariant(self) -> bool {
+        match self {
+            PtxSpecialRegister::Tid
+            | PtxSpecialRegister::Ntid
+            | PtxSpecialRegister::Ctaid
+            | PtxSpecialRegister::Nctaid
+            | PtxSpecialRegister::LanemaskLt
+            | PtxSpecialRegister::DynamicSmemSize
+            | PtxSpecialRegister::TotalSmemSize => true,
+            PtxSpecialRegister::Clock => false,
+        }
+    }
+
+    // Same value in every thread of a block
+    fn is_uniform(self) -> bool {
+        match self {
+            PtxSpecialRegister::Ntid
+            | PtxSpecialRegister::Ctaid
+            | PtxSpecialRegister::Nctaid
+            | PtxSpecialRegister::DynamicSmemSize
+            | PtxSpecialRegister::TotalSmemSize => true,
+            PtxSpecialRegister::Tid
+            | PtxSpecialRegister::Clock
+            | PtxSpecialRegister::LanemaskLt => false,
         }
     }
 
@@ -172,6 +910,8 @@ impl PtxSpecialRegister {
             PtxSpecialRegister::Nctaid => "sreg_nctaid",
             PtxSpecialRegister::Clock => "sreg_clock",
             PtxSpecialRegister::LanemaskLt => "sreg_lanemask_lt",
+            PtxSpecialRegister::DynamicSmemSize => "sreg_dynamic_smem_size",
+            PtxSpecialRegister::TotalSmemSize => "sreg_total_smem_size",
         }
     }
 }
@@ -196,16 +936,6 @@ fn error_todo_msg<T: Into<String>>(msg: T) -> TranslateError {
     TranslateError::Todo(msg.into())
 }
 
-#[cfg(debug_assertions)]
-fn error_todo() -> TranslateError {
-    unreachable!()
-}
-
-#[cfg(not(debug_assertions))]
-fn error_todo() -> TranslateError {
-    TranslateError::Todo("".to_string())
-}
-
 #[cfg(debug_assertions)]
 fn error_unknown_symbol<T: Into<String>>(symbol: T) -> TranslateError {
     panic!("Unknown symbol: \"{}\"", symbol.into())
@@ -216,6 +946,55 @@ fn error_unknown_symbol<T: Into<String>>(symbol: T) -> TranslateError {
     TranslateError::UnknownSymbol(symbol.into())
 }
 
+// Errors below are caused by the input PTX using something we don't support (as opposed to a bug
+// in ZLUDA), so unlike the errors above they never panic, even in debug builds
+
+fn error_unsupported_instruction<T: Into<String>>(opcode: T) -> TranslateError {
+    TranslateError::UnsupportedInstruction {
+        opcode: opcode.into(),
+    }
+}
+
+fn error_unsupported_state_space<T: Into<String>>(
+    space: ast::StateSpace,
+    context: T,
+) -> TranslateError {
+    let space = match space {
+        ast::StateSpace::Generic => "generic".to_string(),
+        space => space.to_string(),
+    };
+    TranslateError::UnsupportedStateSpace {
+        space,
+        context: context.into(),
+    }
+}
+
+fn error_unsupported_spirv<T: Into<String>>(feature: T) -> TranslateError {
+    TranslateError::UnsupportedSpirv {
+        feature: feature.into(),
+    }
+}
+
+fn error_malformed_operand<T: Into<String>, U: Into<String>>(
+    instruction: T,
+    detail: U,
+) -> TranslateError {
+    TranslateError::MalformedOperand {
+        instruction: instruction.into(),
+        detail: detail.into(),
+    }
+}
+
+fn error_unsupported_param_access<T: Into<String>, U: Into<String>>(
+    name: T,
+    detail: U,
+) -> TranslateError {
+    TranslateError::UnsupportedParamAccess {
+        name: name.into(),
+        detail: detail.into(),
+    }
+}
+
 #[cfg(debug_assertions)]
 fn error_mismatched_type() -> TranslateError {
     panic!()
@@ -294,7 +1073,10 @@ impl<T: ast::Operand<Ident = SpirvWord>> Statement<ast::Instruction<T>, T> {
                     v_type: var.v_type,
                     state_space: var.state_space,
                     name,
-                    array_init: var.array_init,
+                    array_init: var
+                        .array_init
+                        .map(|symbol| visitor.visit_ident(symbol, None, false, false))?,
+                    pointer: var.pointer,
                 })
             }
             Statement::Conditional(conditional) => {
@@ -470,6 +1252,7 @@ impl<T: ast::Operand<Ident = SpirvWord>> Statement<ast::Instruction<T>, T> {
             Statement::RepackVector(RepackVectorDetails {
                 is_extract,
                 typ,
+                packed_type,
                 packed,
                 unpacked,
                 relaxed_type_check,
@@ -488,10 +1271,7 @@ impl<T: ast::Operand<Ident = SpirvWord>> Statement<ast::Instruction<T>, T> {
                         .collect::<Result<Vec<_>, _>>()?;
                     let packed = visitor.visit_ident(
                         packed,
-                        Some((
-                            &ast::Type::Vector(unpacked.len() as u8, typ),
-                            ast::StateSpace::Reg,
-                        )),
+                        Some((&packed_type, ast::StateSpace::Reg)),
                         false,
                         false,
                     )?;
@@ -499,10 +1279,7 @@ impl<T: ast::Operand<Ident = SpirvWord>> Statement<ast::Instruction<T>, T> {
                 } else {
                     let packed = visitor.visit_ident(
                         packed,
-                        Some((
-                            &ast::Type::Vector(unpacked.len() as u8, typ),
-                            ast::StateSpace::Reg,
-                        )),
+                        Some((&packed_type, ast::StateSpace::Reg)),
                         true,
                         false,
                     )?;
@@ -522,6 +1299,7 @@ impl<T: ast::Operand<Ident = SpirvWord>> Statement<ast::Instruction<T>, T> {
                 Statement::RepackVector(RepackVectorDetails {
                     is_extract,
                     typ,
+                    packed_type,
                     packed,
                     unpacked,
                     relaxed_type_check,
@@ -590,7 +1368,7 @@ impl std::fmt::Display for ImplicitConversion {
     }
 }
 
-#[derive(PartialEq, Clone, strum_macros::Display)]
+#[derive(PartialEq, Eq, Hash, Clone, strum_macros::Display)]
 #[strum(serialize_all = "snake_case", prefix = ".")]
 enum ConversionKind {
     Default,
@@ -618,6 +1396,9 @@ pub struct PtrAccess<T> {
 struct RepackVectorDetails {
     is_extract: bool,
     typ: ast::ScalarType,
+    // Either a vector of `typ` or, for forms like `mov.b64 d, {lo, hi}`, a bit scalar as wide as
+    // all the unpacked elements together
+    packed_type: ast::Type,
     packed: SpirvWord,
     unpacked: Vec<SpirvWord>,
     relaxed_type_check: bool,
@@ -662,6 +1443,7 @@ type NormalizedStatement = Statement<
     (
         Option<ast::PredAt<SpirvWord>>,
         ast::Instruction<ast::ParsedOperand<SpirvWord>>,
+        ast::Span,
     ),
     ast::ParsedOperand<SpirvWord>,
 >;
@@ -676,9 +1458,14 @@ struct Function2<Instruction, Operand: ast::Operand> {
     pub name: Operand::Ident,
     pub input_arguments: Vec<ast::Variable<Operand::Ident>>,
     pub body: Option<Vec<Statement<Instruction, Operand>>>,
+    // Span of the function declaration, errors from the passes that can't be attributed to a
+    // single statement point here
+    span: ast::Span,
     is_kernel: bool,
     import_as: Option<String>,
     tuning: Vec<ast::TuningDirective>,
+    // Unrolling pragmas of the loops, keyed by the label of the loop header
+    loop_pragmas: FxHashMap<SpirvWord, ast::LoopPragma>,
     linkage: ast::LinkingDirective,
     flush_to_zero_f32: bool,
     flush_to_zero_f16f64: bool,
@@ -690,6 +1477,7 @@ type NormalizedDirective2 = Directive2<
     (
         Option<ast::PredAt<SpirvWord>>,
         ast::Instruction<ast::ParsedOperand<SpirvWord>>,
+        ast::Span,
     ),
     ast::ParsedOperand<SpirvWord>,
 >;
@@ -698,6 +1486,7 @@ type NormalizedFunction2 = Function2<
     (
         Option<ast::PredAt<SpirvWord>>,
         ast::Instruction<ast::ParsedOperand<SpirvWord>>,
+        ast::Span,
     ),
     ast::ParsedOperand<SpirvWord>,
 >;
@@ -710,17 +1499,34 @@ type UnconditionalFunction =
 
 struct GlobalStringIdentResolver2<'input> {
     pub(crate) current_id: SpirvWord,
-    pub(crate) ident_map: FxHashMap<SpirvWord, IdentEntry<'input>>,
+    pub(crate) ident_map: IdentMap<'input>,
+    names: &'input NameInterner,
 }
 
 impl<'input> GlobalStringIdentResolver2<'input> {
-    fn new(spirv_word: SpirvWord) -> Self {
+    fn new(spirv_word: SpirvWord, names: &'input NameInterner) -> Self {
         Self {
             current_id: spirv_word,
-            ident_map: FxHashMap::default(),
+            ident_map: IdentMap::default(),
+            names,
         }
     }
 
+    // Resolver for a single function run in parallel with the others, see
+    // run_directives_with_resolver. Identifiers registered by the fork are numbered from the
+    // current id of this resolver, share() must be called before the first fork
+    fn fork(&self) -> Self {
+        Self {
+            current_id: self.current_id,
+            ident_map: self.ident_map.fork(),
+            names: self.names,
+        }
+    }
+
+    fn share(&mut self) {
+        self.ident_map.share(self.current_id.0 as usize);
+    }
+
     fn register_named(
         &mut self,
         name: Cow<'input, str>,
@@ -730,7 +1536,7 @@ impl<'input> GlobalStringIdentResolver2<'input> {
         self.ident_map.insert(
             new_id,
             IdentEntry {
-                name: Some(name),
+                name: Some(self.names.intern(name)),
                 type_space,
             },
         );
@@ -762,11 +1568,280 @@ impl<'input> GlobalStringIdentResolver2<'input> {
     }
 }
 
+// Names that are not a slice of the input are made up during translation: `%r0`..`%r99` of
+// `.reg .b32 %r<100>`, names of ptx_impl functions. The same ones come up in every function of
+// every module, so each is allocated once and borrowed by every resolver using the interner.
+// A module gets its own interner, Compiler::compile_batch shares one between the modules of the
+// batch. The names are freed with the interner
+#[derive(Default)]
+pub(crate) struct NameInterner {
+    names: Mutex<FxHashSet<Box<str>>>,
+}
+
+impl NameInterner {
+    fn intern<'a>(&'a self, name: Cow<'a, str>) -> Cow<'a, str> {
+        match name {
+            Cow::Borrowed(_) => name,
+            Cow::Owned(name) => {
+                let mut names = self.names.lock().unwrap();
+                let interned: *const str = match names.get(name.as_str()) {
+                    Some(interned) => &**interned,
+                    None => {
+                        let interned = name.into_boxed_str();
+                        let ptr: *const str = &*interned;
+                        names.insert(interned);
+                        ptr
+                    }
+                };
+                // Names are never removed and the heap allocation of a Box<str> does not move
+                // when the set grows, so the name lives as long as the interner
+                Cow::Borrowed(unsafe { &*interned })
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 struct IdentEntry<'input> {
     name: Option<Cow<'input, str>>,
     type_space: Option<(ast::Type, ast::StateSpace)>,
 }
 
+// Entries of GlobalStringIdentResolver2, indexed by the identifier. Entries registered before the
+// last share() are behind an Arc, so forks of the resolver get them without a copy and only own
+// the entries they register themselves
+#[derive(Default)]
+struct IdentMap<'input> {
+    shared: Arc<Vec<Option<IdentEntry<'input>>>>,
+    own: Vec<Option<IdentEntry<'input>>>,
+}
+
+impl<'input> IdentMap<'input> {
+    fn get(&self, id: &SpirvWord) -> Option<&IdentEntry<'input>> {
+        let index = id.0 as usize;
+        match index.checked_sub(self.shared.len()) {
+            None => self.shared[index].as_ref(),
+            Some(index) => self.own.get(index)?.as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self, id: &SpirvWord) -> Option<&mut IdentEntry<'input>> {
+        let index = id.0 as usize;
+        match index.checked_sub(self.shared.len()) {
+            None => Arc::make_mut(&mut self.shared)[index].as_mut(),
+            Some(index) => self.own.get_mut(index)?.as_mut(),
+        }
+    }
+
+    fn insert(&mut self, id: SpirvWord, entry: IdentEntry<'input>) {
+        let index = id.0 as usize;
+        match index.checked_sub(self.shared.len()) {
+            None => Arc::make_mut(&mut self.shared)[index] = Some(entry),
+            Some(index) => {
+                if index >= self.own.len() {
+                    self.own.resize_with(index + 1, || None);
+                }
+                self.own[index] = Some(entry);
+            }
+        }
+    }
+
+    fn extend(&mut self, entries: impl IntoIterator<Item = (SpirvWord, IdentEntry<'input>)>) {
+        for (id, entry) in entries {
+            self.insert(id, entry);
+        }
+    }
+
+    // Moves the owned entries behind the Arc, `len` is the first identifier not registered yet
+    fn share(&mut self, len: usize) {
+        let shared = Arc::make_mut(&mut self.shared);
+        shared.append(&mut self.own);
+        if shared.len() < len {
+            shared.resize_with(len, || None);
+        }
+    }
+
+    fn fork(&self) -> Self {
+        debug_assert!(self.own.is_empty());
+        Self {
+            shared: self.shared.clone(),
+            own: Vec::new(),
+        }
+    }
+}
+
+impl<'input> Index<&SpirvWord> for IdentMap<'input> {
+    type Output = IdentEntry<'input>;
+
+    fn index(&self, id: &SpirvWord) -> &Self::Output {
+        self.get(id).expect("unknown identifier")
+    }
+}
+
+// Dense map keyed by identifier. Identifiers are numbered from 1 without large gaps, so a vector
+// is both smaller and faster than a hash map
+struct IdMap<T>(Vec<Option<T>>);
+
+impl<T> IdMap<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self(Vec::with_capacity(capacity))
+    }
+
+    fn get(&self, id: &SpirvWord) -> Option<&T> {
+        self.0.get(id.0 as usize)?.as_ref()
+    }
+
+    fn insert(&mut self, id: SpirvWord, value: T) -> Option<T> {
+        self.slot(id).replace(value)
+    }
+
+    fn get_or_insert_with(&mut self, id: SpirvWord, value: impl FnOnce() -> T) -> &mut T {
+        self.slot(id).get_or_insert_with(value)
+    }
+
+    fn slot(&mut self, id: SpirvWord) -> &mut Option<T> {
+        let index = id.0 as usize;
+        if index >= self.0.len() {
+            self.0.resize_with(index + 1, || None);
+        }
+        &mut self.0[index]
+    }
+}
+
+impl<T> Default for IdMap<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+// Runs `run_directive` on every directive in parallel, each directive with its own fork of the
+// resolver. Identifiers registered by the forks are then renumbered in directive order, so the
+// numbering is the same as if the directives were run one after another. Passes run this way
+// must not look at the numeric values of the identifiers they register
+fn run_directives_with_resolver<'input, From, F>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    directives: Vec<From>,
+    run_directive: F,
+) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError>
+where
+    From: Send,
+    F: Fn(
+            &mut GlobalStringIdentResolver2<'input>,
+            From,
+        ) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError>
+        + Sync,
+{
+    resolver.share();
+    let base = resolver.current_id;
+    let forked = {
+        let resolver = &*resolver;
+        directives
+            .into_par_iter()
+            .map(|directive| {
+                let mut fork = resolver.fork();
+                let directive = run_directive(&mut fork, directive)?;
+                Ok((directive, fork))
+            })
+            .collect::<Vec<Result<_, TranslateError>>>()
+    };
+    let mut result = Vec::with_capacity(forked.len());
+    for item in forked {
+        let (directive, fork) = item?;
+        let offset = resolver.current_id.0 - base.0;
+        result.push(relocate_directive(directive, base, offset)?);
+        for (index, entry) in fork.ident_map.own.into_iter().enumerate() {
+            if let Some(entry) = entry {
+                let id = SpirvWord(resolver.current_id.0 + index as u32);
+                resolver.ident_map.insert(id, entry);
+            }
+        }
+        resolver.current_id.0 += fork.current_id.0 - base.0;
+    }
+    Ok(result)
+}
+
+// Adds `offset` to every identifier registered by a fork, that is every identifier from `base`
+fn relocate_directive(
+    directive: Directive2<ast::Instruction<SpirvWord>, SpirvWord>,
+    base: SpirvWord,
+    offset: u32,
+) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
+    if offset == 0 {
+        return Ok(directive);
+    }
+    let relocate = |id: SpirvWord| {
+        if id.0 >= base.0 {
+            SpirvWord(id.0 + offset)
+        } else {
+            id
+        }
+    };
+    Ok(match directive {
+        Directive2::Variable(linking, mut var) => {
+            var.name = relocate(var.name);
+            Directive2::Variable(linking, var)
+        }
+        Directive2::Method(mut method) => {
+            for arg in method
+                .input_arguments
+                .iter_mut()
+                .chain(method.return_arguments.iter_mut())
+            {
+                arg.name = relocate(arg.name);
+            }
+            method.name = relocate(method.name);
+            method.body = method
+                .body
+                .map(|statements| {
+                    statements
+                        .into_iter()
+                        .map(|statement| {
+                            statement.visit_map(
+                                &mut |id: SpirvWord,
+                                      _: Option<(&ast::Type, ast::StateSpace)>,
+                                      _: bool,
+                                      _: bool| {
+                                    Ok::<_, TranslateError>(relocate(id))
+                                },
+                            )
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .transpose()?;
+            Directive2::Method(method)
+        }
+    })
+}
+
+// Runs `run_directive` on every directive in parallel, each directive counts into its own
+// statistics, which are then summed in directive order
+fn run_directives_with_statistics<From, To, F>(
+    statistics: &mut PassStatistics,
+    directives: Vec<From>,
+    run_directive: F,
+) -> Result<Vec<To>, TranslateError>
+where
+    From: Send,
+    To: Send,
+    F: Fn(&mut PassStatistics, From) -> Result<To, TranslateError> + Sync,
+{
+    directives
+        .into_par_iter()
+        .map(|directive| {
+            let mut directive_statistics = PassStatistics::default();
+            let directive = run_directive(&mut directive_statistics, directive)?;
+            Ok((directive, directive_statistics))
+        })
+        .collect::<Vec<Result<_, TranslateError>>>()
+        .into_iter()
+        .map(|item| {
+            let (directive, directive_statistics) = item?;
+            statistics.add(&directive_statistics);
+            Ok(directive)
+        })
+        .collect()
+}
+
 struct ScopedResolver<'input, 'b> {
     flat_resolver: &'b mut GlobalStringIdentResolver2<'input>,
     scopes: Vec<ScopeMarker<'input>>,
@@ -803,7 +1878,11 @@ impl<'input, 'b> ScopedResolver<'input, 'b> {
                         .get(&ident)
                         .ok_or_else(|| error_unreachable())?;
                     if entry.type_space.is_some() {
-                        return Err(error_unknown_symbol(name));
+                        return Err(TranslateError::DuplicateDefinition {
+                            name: name.to_string(),
+                            first_definition: current_scope.definitions.get(&ident).copied(),
+                            first_location: None,
+                        });
                     }
                     ident
                 }
@@ -824,21 +1903,25 @@ impl<'input, 'b> ScopedResolver<'input, 'b> {
         )
     }
 
+    // `span` is the span of the declaration, used to point to the first definition if the name
+    // is defined again
     fn add(
         &mut self,
         name: Cow<'input, str>,
         type_space: Option<(ast::Type, ast::StateSpace)>,
+        span: Option<ast::Span>,
     ) -> Result<SpirvWord, TranslateError> {
-        let result = self.flat_resolver.current_id;
-        self.flat_resolver.current_id.0 += 1;
         let current_scope = self.scopes.last_mut().unwrap();
-        if current_scope
-            .name_to_ident
-            .insert(name.clone(), result)
-            .is_some()
-        {
-            return Err(error_unknown_symbol(name));
+        if let Some(first) = current_scope.name_to_ident.get(&name) {
+            return Err(TranslateError::DuplicateDefinition {
+                name: name.into_owned(),
+                first_definition: current_scope.definitions.get(first).copied(),
+                first_location: None,
+            });
         }
+        let result = self.flat_resolver.current_id;
+        self.flat_resolver.current_id.0 += 1;
+        current_scope.name_to_ident.insert(name.clone(), result);
         current_scope.ident_map.insert(
             result,
             IdentEntry {
@@ -846,22 +1929,54 @@ impl<'input, 'b> ScopedResolver<'input, 'b> {
                 type_space,
             },
         );
+        if let Some(span) = span {
+            current_scope.definitions.insert(result, span);
+        }
         Ok(result)
     }
 
     fn get(&mut self, name: &str) -> Result<SpirvWord, TranslateError> {
+        self.get_entry(name).map(|(ident, _)| ident)
+    }
+
+    fn get_entry(&self, name: &str) -> Result<(SpirvWord, &IdentEntry<'input>), TranslateError> {
         self.scopes
             .iter()
             .rev()
-            .find_map(|resolver| resolver.name_to_ident.get(name).copied())
-            .ok_or_else(|| error_unknown_symbol(name))
+            .find_map(|scope| {
+                let ident = *scope.name_to_ident.get(name)?;
+                Some((ident, scope.ident_map.get(&ident)?))
+            })
+            .ok_or_else(|| TranslateError::UnknownIdentifier {
+                name: name.to_string(),
+                similar: self.similar_names(name),
+            })
     }
 
-    fn get_in_current_scope(&self, label: &'input str) -> Result<SpirvWord, TranslateError> {
+    // Up to three visible names closest to `name`, for names which are likely typos
+    fn similar_names(&self, name: &str) -> Vec<String> {
+        let max_distance = (name.chars().count() / 3).max(1);
+        let mut candidates = self
+            .scopes
+            .iter()
+            .flat_map(|scope| scope.name_to_ident.keys())
+            .map(|candidate| (edit_distance(name, candidate), candidate.to_string()))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect::<Vec<_>>();
+        candidates.sort();
+        candidates.dedup_by(|(_, a), (_, b)| a == b);
+        candidates
+            .into_iter()
+            .take(3)
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+
+    fn get_in_current_scope(&self, name: &str) -> Result<SpirvWord, TranslateError> {
         let current_scope = self.scopes.last().unwrap();
         current_scope
             .name_to_ident
-            .get(label)
+            .get(name)
             .copied()
             .ok_or_else(|| error_unreachable())
     }
@@ -870,6 +1985,7 @@ impl<'input, 'b> ScopedResolver<'input, 'b> {
 struct ScopeMarker<'input> {
     ident_map: FxHashMap<SpirvWord, IdentEntry<'input>>,
     name_to_ident: FxHashMap<Cow<'input, str>, SpirvWord>,
+    definitions: FxHashMap<SpirvWord, ast::Span>,
 }
 
 impl<'input> ScopeMarker<'input> {
@@ -877,6 +1993,7 @@ impl<'input> ScopeMarker<'input> {
         Self {
             ident_map: FxHashMap::default(),
             name_to_ident: FxHashMap::default(),
+            definitions: FxHashMap::default(),
         }
     }
 
@@ -885,9 +2002,29 @@ impl<'input> ScopeMarker<'input> {
     }
 }
 
+// Levenshtein distance, counted in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + (a_char != *b_char) as usize;
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// %envreg0 to %envreg31 are set by the CUDA driver and are zero for user kernels. There's
+// nothing like them on AMD GPUs, fix_special_registers2 replaces their reads with zero
+const ENVIRONMENT_REGISTERS: u8 = 32;
+
 struct SpecialRegistersMap2 {
     reg_to_id: FxHashMap<PtxSpecialRegister, SpirvWord>,
     id_to_reg: FxHashMap<SpirvWord, PtxSpecialRegister>,
+    id_to_envreg: FxHashMap<SpirvWord, u8>,
 }
 
 impl SpecialRegistersMap2 {
@@ -895,16 +2032,26 @@ impl SpecialRegistersMap2 {
         let mut result = SpecialRegistersMap2 {
             reg_to_id: FxHashMap::default(),
             id_to_reg: FxHashMap::default(),
+            id_to_envreg: FxHashMap::default(),
         };
         for sreg in PtxSpecialRegister::iter() {
             let text = sreg.as_str();
             let id = resolver.add(
                 Cow::Borrowed(text),
                 Some((sreg.get_type(), ast::StateSpace::Reg)),
+                None,
             )?;
             result.reg_to_id.insert(sreg, id);
             result.id_to_reg.insert(id, sreg);
         }
+        for index in 0..ENVIRONMENT_REGISTERS {
+            let id = resolver.add(
+                Cow::Owned(format!("%envreg{index}")),
+                Some((ast::ScalarType::B32.into(), ast::StateSpace::Reg)),
+                None,
+            )?;
+            result.id_to_envreg.insert(id, index);
+        }
         Ok(result)
     }
 
@@ -912,6 +2059,10 @@ impl SpecialRegistersMap2 {
         self.id_to_reg.get(&id).copied()
     }
 
+    fn get_envreg(&self, id: SpirvWord) -> Option<u8> {
+        self.id_to_envreg.get(&id).copied()
+    }
+
     fn len() -> usize {
         PtxSpecialRegister::iter().len()
     }
@@ -937,7 +2088,8 @@ impl SpecialRegistersMap2 {
                 v_type: return_type.into(),
                 state_space: ast::StateSpace::Reg,
                 name: resolver.register_unnamed(Some((return_type.into(), ast::StateSpace::Reg))),
-                array_init: Vec::new(),
+                array_init: ast::Initializer::new(),
+                pointer: None,
             }];
             let input_arguments = input_type
                 .into_iter()
@@ -946,7 +2098,8 @@ impl SpecialRegistersMap2 {
                     v_type: type_.into(),
                     state_space: ast::StateSpace::Reg,
                     name: resolver.register_unnamed(Some((type_.into(), ast::StateSpace::Reg))),
-                    array_init: Vec::new(),
+                    array_init: ast::Initializer::new(),
+                    pointer: None,
                 })
                 .collect::<Vec<_>>();
             fn_(sreg, (return_arguments, name, input_arguments));
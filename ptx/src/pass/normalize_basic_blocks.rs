@@ -4,9 +4,14 @@ use super::*;
 // and code emissions passes much simpler:
 // * Inserts label at the start of every function
 //   This makes control flow graph simpler in mode computation block: we can
-//   represent kernels as separate nodes with its own separate entry/exit mode
+//   represent kernels as separate nodes with its own separate entry/exit mode.
+//   A label the function already starts with is reused, unless the function
+//   branches to it: the entry block runs once, emit puts the kernel rounding
+//   prelude there
 // * Inserts label at the start of every basic block
 // * Insert explicit jumps before labels
+// * Inserts `ret;` at the end of every function that falls off its end, e.g. when the body ends
+//   on a label or is empty
 // * Non-.entry methods get a single `ret;` exit point - this is because mode computation
 //   logic requires it. Control flow graph constructed by mode computation
 //   models function calls as jumps into and then from another function.
@@ -28,19 +33,25 @@ pub(crate) fn run(
             _ => continue,
         };
         let body = std::mem::replace(body_ref, Vec::new());
+        let entry_is_branch_target = match body.first() {
+            Some(Statement::Label(label)) => is_branch_target(&body, *label),
+            _ => false,
+        };
         let mut result = Vec::with_capacity(body.len());
         let mut previous_instruction_was_terminator = TerminatorKind::Not;
         let mut body_iterator = body.into_iter();
         let mut return_statements = Vec::new();
-        match body_iterator.next() {
-            Some(Statement::Label(_)) => {}
-            Some(statement) => {
+        let first_statement = match body_iterator.next() {
+            Some(Statement::Label(label)) if !entry_is_branch_target => {
+                result.push(Statement::Label(label));
+                None
+            }
+            statement => {
                 result.push(Statement::Label(flat_resolver.register_unnamed(None)));
-                result.push(statement);
+                statement
             }
-            None => {}
-        }
-        for statement in body_iterator {
+        };
+        for statement in first_statement.into_iter().chain(body_iterator) {
             match previous_instruction_was_terminator {
                 TerminatorKind::Not => match statement {
                     Statement::Label(label) => {
@@ -85,6 +96,22 @@ pub(crate) fn run(
             previous_instruction_was_terminator = is_block_terminator(&statement);
             result.push(statement);
         }
+        // Falling off the end of a function returns from it
+        if !matches!(previous_instruction_was_terminator, TerminatorKind::Real) {
+            if matches!(previous_instruction_was_terminator, TerminatorKind::Fake) {
+                let label = flat_resolver.register_unnamed(None);
+                result.push(Statement::Instruction(ast::Instruction::Bra {
+                    arguments: ast::BraArgs { src: label },
+                }));
+                result.push(Statement::Label(label));
+            }
+            if !is_kernel {
+                return_statements.push(result.len());
+            }
+            result.push(Statement::Instruction(ast::Instruction::Ret {
+                data: ast::RetData { uniform: false },
+            }));
+        }
         convert_from_multiple_returns_to_single_return(
             flat_resolver,
             &mut result,
@@ -121,6 +148,19 @@ fn convert_from_multiple_returns_to_single_return(
     })
 }
 
+fn is_branch_target(
+    body: &[Statement<ast::Instruction<SpirvWord>, SpirvWord>],
+    label: SpirvWord,
+) -> bool {
+    body.iter().any(|statement| match statement {
+        Statement::Instruction(ast::Instruction::Bra { arguments }) => arguments.src == label,
+        Statement::Conditional(BrachCondition {
+            if_true, if_false, ..
+        }) => *if_true == label || *if_false == label,
+        _ => false,
+    })
+}
+
 fn is_block_terminator(
     statement: &Statement<ast::Instruction<SpirvWord>, SpirvWord>,
 ) -> TerminatorKind {
@@ -1,11 +1,24 @@
 use super::*;
 use ptx_parser as ast;
 
-pub(crate) fn run<'input, 'b>(
-    resolver: &mut ScopedResolver<'input, 'b>,
+pub(crate) fn run<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
     directives: Vec<ast::Directive<'input, ast::ParsedOperand<&'input str>>>,
 ) -> Result<Vec<NormalizedDirective2>, TranslateError> {
     resolver.start_scope();
+    // Functions and module variables can be referenced before they are declared
+    for directive in directives.iter() {
+        match directive {
+            ast::Directive::Variable(_, var, span) => {
+                declare_variable(resolver, var, Some(*span)).map_err(|err| err.at(*span))?;
+            }
+            ast::Directive::Method(_, method) => {
+                resolver
+                    .add_or_get_in_current_scope_untyped(method.func_directive.name.text())
+                    .map_err(|err| err.at(method.span))?;
+            }
+        }
+    }
     let result = directives
         .into_iter()
         .map(|directive| run_directive(resolver, directive))
@@ -14,22 +27,26 @@ pub(crate) fn run<'input, 'b>(
     Ok(result)
 }
 
-fn run_directive<'input, 'b>(
-    resolver: &mut ScopedResolver<'input, 'b>,
+fn run_directive<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
     directive: ast::Directive<'input, ast::ParsedOperand<&'input str>>,
 ) -> Result<NormalizedDirective2, TranslateError> {
     Ok(match directive {
-        ast::Directive::Variable(linking, var) => {
-            NormalizedDirective2::Variable(linking, run_variable(resolver, var)?)
-        }
+        ast::Directive::Variable(linking, var, span) => NormalizedDirective2::Variable(
+            linking,
+            run_declared_variable(resolver, var).map_err(|err| err.at(span))?,
+        ),
         ast::Directive::Method(linking, directive) => {
-            NormalizedDirective2::Method(run_method(resolver, linking, directive)?)
+            let span = directive.span;
+            NormalizedDirective2::Method(
+                run_method(resolver, linking, directive).map_err(|err| err.at(span))?,
+            )
         }
     })
 }
 
-fn run_method<'input, 'b>(
-    resolver: &mut ScopedResolver<'input, 'b>,
+fn run_method<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
     linkage: ast::LinkingDirective,
     method: ast::Function<'input, &'input str, ast::Statement<ast::ParsedOperand<&'input str>>>,
 ) -> Result<NormalizedFunction2, TranslateError> {
@@ -37,11 +54,12 @@ fn run_method<'input, 'b>(
     let name = resolver.add_or_get_in_current_scope_untyped(method.func_directive.name.text())?;
     resolver.start_scope();
     let (return_arguments, input_arguments) = run_function_decl(resolver, method.func_directive)?;
+    let mut loop_pragmas = FxHashMap::default();
     let body = method
         .body
         .map(|statements| {
             let mut result = Vec::with_capacity(statements.len());
-            run_statements(resolver, &mut result, statements)?;
+            run_statements(resolver, &mut result, &mut loop_pragmas, statements)?;
             Ok::<_, TranslateError>(result)
         })
         .transpose()?;
@@ -51,10 +69,12 @@ fn run_method<'input, 'b>(
         name,
         input_arguments,
         body,
+        span: method.span,
         import_as: None,
         linkage,
         is_kernel,
         tuning: method.tuning,
+        loop_pragmas,
         flush_to_zero_f32: false,
         flush_to_zero_f16f64: false,
         rounding_mode_f32: ptx_parser::RoundingMode::NearestEven,
@@ -62,11 +82,16 @@ fn run_method<'input, 'b>(
     })
 }
 
-fn run_function_decl<'input, 'b>(
-    resolver: &mut ScopedResolver<'input, 'b>,
+fn run_function_decl<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
     func_directive: ast::MethodDeclaration<'input, &'input str>,
 ) -> Result<(Vec<ast::Variable<SpirvWord>>, Vec<ast::Variable<SpirvWord>>), TranslateError> {
-    assert!(func_directive.shared_mem.is_none());
+    if func_directive.shared_mem.is_some() {
+        return Err(error_unsupported_state_space(
+            ast::StateSpace::Shared,
+            "function declarations",
+        ));
+    }
     let return_arguments = func_directive
         .return_arguments
         .into_iter()
@@ -80,68 +105,132 @@ fn run_function_decl<'input, 'b>(
     Ok((return_arguments, input_arguments))
 }
 
-fn run_variable<'input, 'b>(
-    resolver: &mut ScopedResolver<'input, 'b>,
+fn run_variable<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
+    variable: ast::Variable<&'input str>,
+) -> Result<ast::Variable<SpirvWord>, TranslateError> {
+    Ok(ast::Variable {
+        name: declare_variable(resolver, &variable, None)?,
+        align: variable.align,
+        v_type: variable.v_type,
+        state_space: variable.state_space,
+        array_init: run_initializer(resolver, variable.array_init)?,
+        pointer: variable.pointer,
+    })
+}
+
+fn declare_variable<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
+    variable: &ast::Variable<&'input str>,
+    span: Option<ast::Span>,
+) -> Result<SpirvWord, TranslateError> {
+    resolver.add(
+        Cow::Borrowed(variable.name),
+        Some((variable.v_type.clone(), variable.state_space)),
+        span,
+    )
+}
+
+// Variable must have been already registered with `declare_variable(...)` in the current scope
+fn run_declared_variable<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
     variable: ast::Variable<&'input str>,
 ) -> Result<ast::Variable<SpirvWord>, TranslateError> {
     Ok(ast::Variable {
-        name: resolver.add(
-            Cow::Borrowed(variable.name),
-            Some((variable.v_type.clone(), variable.state_space)),
-        )?,
+        name: resolver.get_in_current_scope(variable.name)?,
         align: variable.align,
         v_type: variable.v_type,
         state_space: variable.state_space,
-        array_init: variable.array_init,
+        array_init: run_initializer(resolver, variable.array_init)?,
+        pointer: variable.pointer,
     })
 }
 
-fn run_statements<'input, 'b>(
-    resolver: &mut ScopedResolver<'input, 'b>,
+// Initializers can hold addresses of variables and functions declared later in the module
+fn run_initializer<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
+    initializer: ast::Initializer<&'input str>,
+) -> Result<ast::Initializer<SpirvWord>, TranslateError> {
+    initializer.map(|symbol| resolver.get(symbol))
+}
+
+fn run_statements<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
     result: &mut Vec<NormalizedStatement>,
+    loop_pragmas: &mut FxHashMap<SpirvWord, ast::LoopPragma>,
     statements: Vec<ast::Statement<ast::ParsedOperand<&'input str>>>,
 ) -> Result<(), TranslateError> {
+    // Labels and variables are visible in the whole scope, including statements preceding their
+    // declarations. Nested blocks are not visited, their declarations are registered only once we
+    // enter their scope, so they shadow the declarations from this scope
     for statement in statements.iter() {
         match statement {
-            ast::Statement::Label(label) => {
-                resolver.add(Cow::Borrowed(*label), None)?;
+            ast::Statement::Label(label, span) => {
+                resolver
+                    .add(Cow::Borrowed(*label), None, Some(*span))
+                    .map_err(|err| err.at(*span))?;
+            }
+            ast::Statement::Variable(variable, span) => {
+                declare_multivariable(resolver, variable, *span).map_err(|err| err.at(*span))?;
             }
             _ => {}
         }
     }
     for statement in statements {
         match statement {
-            ast::Statement::Label(label) => {
-                result.push(Statement::Label(resolver.get_in_current_scope(label)?))
+            ast::Statement::Label(label, span) => result.push(Statement::Label(
+                resolver
+                    .get_in_current_scope(label)
+                    .map_err(|err| err.at(span))?,
+            )),
+            ast::Statement::Variable(variable, span) => {
+                run_multivariable(resolver, result, variable).map_err(|err| err.at(span))?
             }
-            ast::Statement::Variable(variable) => run_multivariable(resolver, result, variable)?,
-            ast::Statement::Instruction(predicate, instruction) => {
-                result.push(Statement::Instruction((
-                    predicate
-                        .map(|pred| {
-                            Ok::<_, TranslateError>(ast::PredAt {
-                                not: pred.not,
-                                label: resolver.get(pred.label)?,
-                            })
+            ast::Statement::Instruction(predicate, instruction, span) => {
+                let predicate = predicate
+                    .map(|pred| {
+                        Ok::<_, TranslateError>(ast::PredAt {
+                            not: pred.not,
+                            label: resolver.get(pred.label)?,
                         })
-                        .transpose()?,
-                    run_instruction(resolver, instruction)?,
-                )))
+                    })
+                    .transpose()
+                    .map_err(|err| err.at(span))?;
+                let instruction =
+                    run_instruction(resolver, instruction).map_err(|err| err.at(span))?;
+                result.push(Statement::Instruction((predicate, instruction, span)))
             }
             ast::Statement::Block(block) => {
                 resolver.start_scope();
-                run_statements(resolver, result, block)?;
+                run_statements(resolver, result, loop_pragmas, block)?;
                 resolver.end_scope();
             }
+            // The pragma applies to the loop headed by the basic block it's in, a pragma before
+            // the first label is in the entry block, which can't be a loop header
+            ast::Statement::Pragma(pragma, _) => {
+                let header = result.iter().rev().find_map(|statement| match statement {
+                    Statement::Label(label) => Some(*label),
+                    _ => None,
+                });
+                if let Some(header) = header {
+                    loop_pragmas.insert(header, pragma);
+                }
+            }
         }
     }
     Ok(())
 }
 
-fn run_instruction<'input, 'b>(
-    resolver: &mut ScopedResolver<'input, 'b>,
+fn run_instruction<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
     instruction: ast::Instruction<ast::ParsedOperand<&'input str>>,
 ) -> Result<ast::Instruction<ast::ParsedOperand<SpirvWord>>, TranslateError> {
+    // Rejected here, while the instruction still has its span
+    if let ast::Instruction::BarrierCluster { .. } = instruction {
+        return Err(TranslateError::UnsupportedClusterFeature {
+            instruction: instruction.to_string(),
+        });
+    }
     ast::visit_map(instruction, &mut |name: &'input str,
                                       _: Option<(
         &ast::Type,
@@ -153,42 +242,48 @@ fn run_instruction<'input, 'b>(
     })
 }
 
-fn run_multivariable<'input, 'b>(
-    resolver: &mut ScopedResolver<'input, 'b>,
+// Parameterized variable `.reg .b32 %r<3>` declares `%r0`, `%r1` and `%r2`
+fn multivariable_names<'a, 'input: 'a>(
+    names: &'a NameInterner,
+    variable: &ast::MultiVariable<&'input str>,
+) -> Vec<Cow<'a, str>> {
+    match variable.count {
+        Some(count) => (0..count)
+            .map(|i| names.intern(Cow::Owned(format!("{}{}", variable.var.name, i))))
+            .collect(),
+        None => vec![Cow::Borrowed(variable.var.name)],
+    }
+}
+
+fn declare_multivariable<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
+    variable: &ast::MultiVariable<&'input str>,
+    span: ast::Span,
+) -> Result<(), TranslateError> {
+    for name in multivariable_names(resolver.flat_resolver.names, variable) {
+        resolver.add(
+            name,
+            Some((variable.var.v_type.clone(), variable.var.state_space)),
+            Some(span),
+        )?;
+    }
+    Ok(())
+}
+
+fn run_multivariable<'a, 'input: 'a, 'b>(
+    resolver: &mut ScopedResolver<'a, 'b>,
     result: &mut Vec<NormalizedStatement>,
     variable: ast::MultiVariable<&'input str>,
 ) -> Result<(), TranslateError> {
-    match variable.count {
-        Some(count) => {
-            for i in 0..count {
-                let name = Cow::Owned(format!("{}{}", variable.var.name, i));
-                let ident = resolver.add(
-                    name,
-                    Some((variable.var.v_type.clone(), variable.var.state_space)),
-                )?;
-                result.push(Statement::Variable(ast::Variable {
-                    align: variable.var.align,
-                    v_type: variable.var.v_type.clone(),
-                    state_space: variable.var.state_space,
-                    name: ident,
-                    array_init: variable.var.array_init.clone(),
-                }));
-            }
-        }
-        None => {
-            let name = Cow::Borrowed(variable.var.name);
-            let ident = resolver.add(
-                name,
-                Some((variable.var.v_type.clone(), variable.var.state_space)),
-            )?;
-            result.push(Statement::Variable(ast::Variable {
-                align: variable.var.align,
-                v_type: variable.var.v_type.clone(),
-                state_space: variable.var.state_space,
-                name: ident,
-                array_init: variable.var.array_init.clone(),
-            }));
-        }
+    for name in multivariable_names(resolver.flat_resolver.names, &variable) {
+        result.push(Statement::Variable(ast::Variable {
+            align: variable.var.align,
+            v_type: variable.var.v_type.clone(),
+            state_space: variable.var.state_space,
+            name: resolver.get_in_current_scope(&name)?,
+            array_init: run_initializer(resolver, variable.var.array_init.clone())?,
+            pointer: variable.var.pointer,
+        }));
     }
     Ok(())
 }
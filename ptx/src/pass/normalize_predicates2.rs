@@ -17,7 +17,10 @@ fn run_directive<'input>(
 ) -> Result<UnconditionalDirective, TranslateError> {
     Ok(match directive {
         Directive2::Variable(linking, var) => Directive2::Variable(linking, var),
-        Directive2::Method(method) => Directive2::Method(run_method(resolver, method)?),
+        Directive2::Method(method) => {
+            let span = method.span;
+            Directive2::Method(run_method(resolver, method).map_err(|err| err.at(span))?)
+        }
     })
 }
 
@@ -40,8 +43,10 @@ fn run_method<'input>(
         return_arguments: method.return_arguments,
         name: method.name,
         input_arguments: method.input_arguments,
+        span: method.span,
         import_as: method.import_as,
         tuning: method.tuning,
+        loop_pragmas: method.loop_pragmas,
         linkage: method.linkage,
         is_kernel: method.is_kernel,
         flush_to_zero_f32: method.flush_to_zero_f32,
@@ -59,7 +64,7 @@ fn run_statement<'input>(
     Ok(match statement {
         Statement::Label(label) => result.push(Statement::Label(label)),
         Statement::Variable(var) => result.push(Statement::Variable(var)),
-        Statement::Instruction((predicate, instruction)) => {
+        Statement::Instruction((predicate, instruction, _)) => {
             if let Some(pred) = predicate {
                 let if_true = resolver.register_unnamed(None);
                 let if_false = resolver.register_unnamed(None);
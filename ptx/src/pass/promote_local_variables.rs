@@ -0,0 +1,223 @@
+use super::*;
+
+// insert_explicit_load_store turns every PTX register into a .local variable with a load before
+// and a store after each use. For big kernels this makes the emitted LLVM IR huge, so this pass
+// removes most of that traffic before emission:
+// * A load of a variable whose value is known in the current basic block (because it was stored
+//   or loaded earlier in the same block) is removed and its uses are replaced with the known value
+// * A store overwritten in the same basic block without being read in between is removed
+// * Variables which are no longer loaded at all are removed together with all their stores
+// Only variables which are exclusively accessed by whole-value ld/st with their own type are
+// considered, anything else (taking the address, partial access) might alias.
+// We don't construct phis, values live across basic blocks keep going through memory and are left
+// to LLVM's mem2reg
+pub(super) fn run(
+    statistics: &mut PassStatistics,
+    directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
+) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
+    run_directives_with_statistics(statistics, directives, run_directive)
+}
+
+fn run_directive(
+    statistics: &mut PassStatistics,
+    directive: Directive2<ast::Instruction<SpirvWord>, SpirvWord>,
+) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
+    Ok(match directive {
+        var @ Directive2::Variable(..) => var,
+        Directive2::Method(mut method) => {
+            method.body = method
+                .body
+                .map(|statements| run_statements(statistics, statements))
+                .transpose()
+                .map_err(|err| err.at(method.span))?;
+            Directive2::Method(method)
+        }
+    })
+}
+
+fn run_statements(
+    statistics: &mut PassStatistics,
+    statements: Vec<ExpandedStatement>,
+) -> Result<Vec<ExpandedStatement>, TranslateError> {
+    statistics.statements_before_promotion += statements.len();
+    let (statements, candidates, definitions) = find_candidates(statements)?;
+    let mut result = Vec::with_capacity(statements.len());
+    let mut substitutions = FxHashMap::default();
+    let mut variables = FxHashMap::default();
+    let mut stores = FxHashMap::<_, Vec<_>>::default();
+    let mut live_loads = FxHashMap::<_, usize>::default();
+    // State of the current basic block
+    let mut known_values = FxHashMap::default();
+    let mut pending_stores = FxHashMap::default();
+    // Only values defined at most once can be forwarded, otherwise the value might change
+    // between the store and the load
+    let is_forwardable = |value: &SpirvWord| definitions.get(value).copied().unwrap_or(0) <= 1;
+    for statement in statements {
+        match local_access(&statement, &candidates) {
+            Some(LocalAccess::Load { variable, dst }) => {
+                if let Some(value) = known_values.get(&variable) {
+                    substitutions.insert(dst, *value);
+                    continue;
+                }
+                pending_stores.remove(&variable);
+                *live_loads.entry(variable).or_default() += 1;
+                if is_forwardable(&dst) {
+                    known_values.insert(variable, dst);
+                }
+            }
+            Some(LocalAccess::Store { variable, value }) => {
+                let value = substitutions.get(&value).copied().unwrap_or(value);
+                if let Some(dead_store) = pending_stores.insert(variable, result.len()) {
+                    result[dead_store] = None;
+                }
+                stores.entry(variable).or_default().push(result.len());
+                if is_forwardable(&value) {
+                    known_values.insert(variable, value);
+                } else {
+                    known_values.remove(&variable);
+                }
+            }
+            None => match statement {
+                Statement::Label(_) => {
+                    known_values.clear();
+                    pending_stores.clear();
+                }
+                Statement::Variable(ast::Variable { name, .. })
+                    if candidates.contains_key(&name) =>
+                {
+                    variables.insert(name, result.len());
+                }
+                _ => {}
+            },
+        }
+        result.push(Some(statement));
+    }
+    for (variable, index) in variables {
+        if live_loads.contains_key(&variable) {
+            continue;
+        }
+        result[index] = None;
+        for store in stores.remove(&variable).unwrap_or_default() {
+            result[store] = None;
+        }
+        statistics.promoted_variables += 1;
+    }
+    let result = result
+        .into_iter()
+        .flatten()
+        .map(|statement| {
+            statement.visit_map(&mut |ident: SpirvWord,
+                                      _: Option<(&ast::Type, ast::StateSpace)>,
+                                      _: bool,
+                                      _: bool| {
+                Ok::<_, TranslateError>(substitutions.get(&ident).copied().unwrap_or(ident))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    statistics.statements_after_promotion += result.len();
+    Ok(result)
+}
+
+// Returns candidate variables with their types and the number of definitions of every identifier
+fn find_candidates(
+    statements: Vec<ExpandedStatement>,
+) -> Result<
+    (
+        Vec<ExpandedStatement>,
+        FxHashMap<SpirvWord, ast::Type>,
+        FxHashMap<SpirvWord, usize>,
+    ),
+    TranslateError,
+> {
+    let mut candidates = statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Variable(ast::Variable {
+                name,
+                v_type: v_type @ (ast::Type::Scalar(_) | ast::Type::Vector(..)),
+                state_space: ast::StateSpace::Local,
+                array_init,
+                ..
+            }) if array_init.is_empty() => Some((*name, v_type.clone())),
+            _ => None,
+        })
+        .collect::<FxHashMap<_, _>>();
+    let mut accesses = FxHashMap::<_, usize>::default();
+    let mut uses = FxHashMap::<_, usize>::default();
+    let mut definitions = FxHashMap::<_, usize>::default();
+    let statements = statements
+        .into_iter()
+        .map(|statement| {
+            if let Statement::Variable(..) = statement {
+                return Ok(statement);
+            }
+            match local_access(&statement, &candidates) {
+                Some(LocalAccess::Load { variable, .. })
+                | Some(LocalAccess::Store { variable, .. }) => {
+                    *accesses.entry(variable).or_default() += 1;
+                }
+                None => {}
+            }
+            statement.visit_map(&mut |ident: SpirvWord,
+                                      _: Option<(&ast::Type, ast::StateSpace)>,
+                                      is_dst: bool,
+                                      _: bool| {
+                if is_dst {
+                    *definitions.entry(ident).or_default() += 1;
+                }
+                if candidates.contains_key(&ident) {
+                    *uses.entry(ident).or_default() += 1;
+                }
+                Ok::<_, TranslateError>(ident)
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    candidates.retain(|variable, _| uses.get(variable) == accesses.get(variable));
+    Ok((statements, candidates, definitions))
+}
+
+enum LocalAccess {
+    Load {
+        variable: SpirvWord,
+        dst: SpirvWord,
+    },
+    Store {
+        variable: SpirvWord,
+        value: SpirvWord,
+    },
+}
+
+fn local_access(
+    statement: &ExpandedStatement,
+    candidates: &FxHashMap<SpirvWord, ast::Type>,
+) -> Option<LocalAccess> {
+    match statement {
+        Statement::Instruction(ast::Instruction::Ld {
+            data:
+                ast::LdDetails {
+                    state_space: ast::StateSpace::Local,
+                    qualifier: ast::LdStQualifier::Weak,
+                    typ,
+                    ..
+                },
+            arguments: ast::LdArgs { dst, src },
+        }) if candidates.get(src) == Some(typ) => Some(LocalAccess::Load {
+            variable: *src,
+            dst: *dst,
+        }),
+        Statement::Instruction(ast::Instruction::St {
+            data:
+                ast::StData {
+                    state_space: ast::StateSpace::Local,
+                    qualifier: ast::LdStQualifier::Weak,
+                    typ,
+                    ..
+                },
+            arguments: ast::StArgs { src1, src2 },
+        }) if candidates.get(src1) == Some(typ) && src1 != src2 => Some(LocalAccess::Store {
+            variable: *src1,
+            value: *src2,
+        }),
+        _ => None,
+    }
+}
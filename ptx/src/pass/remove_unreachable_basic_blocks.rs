@@ -13,7 +13,9 @@ pub(crate) fn run(
     for directive in directives.iter_mut() {
         match directive {
             Directive2::Method(Function2 {
-                body: Some(body), ..
+                body: Some(body),
+                span,
+                ..
             }) => {
                 let old_body = std::mem::replace(body, Vec::new());
                 let mut cfg = ControlFlowGraph::new();
@@ -40,7 +42,8 @@ pub(crate) fn run(
                         Statement::FunctionPointer(FunctionPointerDetails {
                             src: _func, ..
                         }) => {
-                            return Err(error_todo());
+                            return Err(error_unsupported_instruction("mov of a function address")
+                                .at(*span));
                         }
                         Statement::Instruction(ast::Instruction::Call {
                             arguments: ast::CallArgs { func, .. },
@@ -66,6 +69,15 @@ pub(crate) fn run(
                     Ok(visited)
                 })?;
             }
+            // Functions whose address is stored in an initializer can be called through it
+            Directive2::Variable(_, var) => {
+                reachable_funcs.extend(
+                    var.array_init
+                        .relocations
+                        .iter()
+                        .map(|relocation| relocation.symbol),
+                );
+            }
             _ => {}
         }
     }
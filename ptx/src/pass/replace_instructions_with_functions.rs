@@ -1,15 +1,20 @@
 use super::*;
+use rustc_hash::FxHashSet;
 use smallvec::*;
 
 pub(super) fn run<'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
     directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
 ) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
-    let mut fn_declarations = FxHashMap::default();
-    let remapped_directives = directives
+    let mut fn_declarations = FxIndexMap::default();
+    let mut remapped_directives = directives
         .into_iter()
         .map(|directive| run_directive(resolver, &mut fn_declarations, directive))
         .collect::<Result<Vec<_>, _>>()?;
+    if fn_declarations.contains_key("bar_sync_count") || fn_declarations.contains_key("bar_arrive")
+    {
+        insert_barrier_init(resolver, &mut fn_declarations, &mut remapped_directives);
+    }
     let mut result = fn_declarations
         .into_iter()
         .map(|(_, (return_arguments, name, input_arguments))| {
@@ -18,8 +23,10 @@ pub(super) fn run<'input>(
                 name: name,
                 input_arguments,
                 body: None,
+                span: ast::Span::default(),
                 import_as: None,
                 tuning: Vec::new(),
+                loop_pragmas: FxHashMap::default(),
                 linkage: ast::LinkingDirective::EXTERN,
                 is_kernel: false,
                 flush_to_zero_f32: false,
@@ -33,9 +40,112 @@ pub(super) fn run<'input>(
     Ok(result)
 }
 
+fn warn_barrier_emulation() {
+    static NOTICE: Once = Once::new();
+    NOTICE.call_once(|| {
+        log::warn!("bar.sync and bar.arrive with a thread count are emulated with a spin-wait")
+    });
+}
+
+// Arrival counters live in shared memory, which starts uninitialized. Every kernel which can reach
+// bar_sync_count or bar_arrive, directly or through other functions, clears them first
+fn insert_barrier_init<'input>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    fn_declarations: &mut FxIndexMap<
+        Cow<'input, str>,
+        (
+            Vec<ast::Variable<SpirvWord>>,
+            SpirvWord,
+            Vec<ast::Variable<SpirvWord>>,
+        ),
+    >,
+    directives: &mut [Directive2<ast::Instruction<SpirvWord>, SpirvWord>],
+) {
+    let name = Cow::Borrowed("bar_sync_init");
+    let full_name = [ZLUDA_PTX_PREFIX, &*name].concat();
+    let init = fn_declarations
+        .entry(name)
+        .or_insert_with(|| {
+            (
+                Vec::new(),
+                resolver.register_named(Cow::Owned(full_name), None),
+                Vec::new(),
+            )
+        })
+        .1;
+    let mut reaching = ["bar_sync_count", "bar_arrive"]
+        .into_iter()
+        .filter_map(|name| fn_declarations.get(name))
+        .map(|(_, fn_name, _)| *fn_name)
+        .collect::<FxHashSet<_>>();
+    loop {
+        let callers = directives
+            .iter()
+            .filter_map(|directive| match directive {
+                Directive2::Method(Function2 {
+                    name,
+                    body: Some(body),
+                    ..
+                }) if !reaching.contains(name)
+                    && referenced_functions(body).any(|callee| reaching.contains(&callee)) =>
+                {
+                    Some(*name)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if callers.is_empty() {
+            break;
+        }
+        reaching.extend(callers);
+    }
+    for directive in directives {
+        let body = match directive {
+            Directive2::Method(Function2 {
+                is_kernel: true,
+                name,
+                body: Some(body),
+                ..
+            }) if reaching.contains(name) => body,
+            _ => continue,
+        };
+        // Right after the label of the entry block, normalize_basic_blocks makes sure there is one
+        let position = match body.first() {
+            Some(Statement::Label(_)) => 1,
+            _ => 0,
+        };
+        body.insert(
+            position,
+            Statement::Instruction(ast::Instruction::Call {
+                data: ptx_parser::CallDetails {
+                    uniform: false,
+                    return_arguments: Vec::new(),
+                    input_arguments: Vec::new(),
+                },
+                arguments: ptx_parser::CallArgs {
+                    return_arguments: Vec::new(),
+                    func: init,
+                    input_arguments: Vec::new(),
+                },
+            }),
+        );
+    }
+}
+
+// Functions called or with their address taken
+fn referenced_functions(
+    body: &[Statement<ast::Instruction<SpirvWord>, SpirvWord>],
+) -> impl Iterator<Item = SpirvWord> + '_ {
+    body.iter().filter_map(|statement| match statement {
+        Statement::Instruction(ast::Instruction::Call { arguments, .. }) => Some(arguments.func),
+        Statement::FunctionPointer(FunctionPointerDetails { src, .. }) => Some(*src),
+        _ => None,
+    })
+}
+
 fn run_directive<'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
-    fn_declarations: &mut FxHashMap<
+    fn_declarations: &mut FxIndexMap<
         Cow<'input, str>,
         (
             Vec<ast::Variable<SpirvWord>>,
@@ -51,7 +161,8 @@ fn run_directive<'input>(
             method.body = method
                 .body
                 .map(|statements| run_statements(resolver, fn_declarations, statements))
-                .transpose()?;
+                .transpose()
+                .map_err(|err| err.at(method.span))?;
             Directive2::Method(method)
         }
     })
@@ -59,7 +170,7 @@ fn run_directive<'input>(
 
 fn run_statements<'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
-    fn_declarations: &mut FxHashMap<
+    fn_declarations: &mut FxIndexMap<
         Cow<'input, str>,
         (
             Vec<ast::Variable<SpirvWord>>,
@@ -100,6 +211,7 @@ fn run_statements<'input>(
                         ptx_parser::StateSpace::Reg,
                     )));
                     let full_name = [ZLUDA_PTX_PREFIX, "shfl_sync_", mode, "_b32_pred"].concat();
+                    log::warn!("shfl.sync.{mode} with a predicate is emulated by {full_name}");
                     let return_arguments = vec![(
                         ast::Type::Vector(2, ast::ScalarType::U32),
                         ptx_parser::StateSpace::Reg,
@@ -123,8 +235,8 @@ fn run_statements<'input>(
                         ),
                     ];
                     let func = match fn_declarations.entry(full_name.into()) {
-                        hash_map::Entry::Occupied(occupied_entry) => occupied_entry.get().1,
-                        hash_map::Entry::Vacant(vacant_entry) => {
+                        indexmap::map::Entry::Occupied(occupied_entry) => occupied_entry.get().1,
+                        indexmap::map::Entry::Vacant(vacant_entry) => {
                             let name = vacant_entry.key().clone();
                             let name = resolver.register_named(name, None);
                             vacant_entry.insert((
@@ -171,6 +283,7 @@ fn run_statements<'input>(
                         Statement::RepackVector(RepackVectorDetails {
                             is_extract: true,
                             typ: ast::ScalarType::U32,
+                            packed_type: ast::Type::Vector(2, ast::ScalarType::U32),
                             packed: packed_var,
                             unpacked: vec![dst, dst_pred_wide],
                             relaxed_type_check: false,
@@ -207,7 +320,7 @@ fn run_statements<'input>(
 
 fn run_instruction<'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
-    fn_declarations: &mut FxHashMap<
+    fn_declarations: &mut FxIndexMap<
         Cow<'input, str>,
         (
             Vec<ast::Variable<SpirvWord>>,
@@ -294,12 +407,27 @@ fn run_instruction<'input>(
             let name = ["bfi_", scalar_to_ptx_name(data)].concat();
             to_call(resolver, fn_declarations, name.into(), i)?
         }
+        i @ ptx_parser::Instruction::Bar {
+            arguments: ast::BarArgs { src2: None, .. },
+            ..
+        } => to_call(resolver, fn_declarations, "bar_sync".into(), i)?,
+        // AMD GPUs have a single barrier per workgroup and no way to wait for only a part of it,
+        // barriers with a thread count are emulated by ptx_impl with arrival counters in shared
+        // memory. A thread count equal to the block size still uses the workgroup barrier
         i @ ptx_parser::Instruction::Bar { .. } => {
-            to_call(resolver, fn_declarations, "bar_sync".into(), i)?
+            warn_barrier_emulation();
+            to_call(resolver, fn_declarations, "bar_sync_count".into(), i)?
+        }
+        i @ ptx_parser::Instruction::BarArrive { .. } => {
+            warn_barrier_emulation();
+            to_call(resolver, fn_declarations, "bar_arrive".into(), i)?
         }
         ptx_parser::Instruction::BarRed { data, arguments } => {
             if arguments.src_threadcount.is_some() {
-                return Err(error_todo());
+                return Err(error_malformed_operand(
+                    "bar.red",
+                    "thread count operand is not supported",
+                ));
             }
             let name = match data.pred_reduction {
                 ptx_parser::Reduction::And => "bar_red_and_pred",
@@ -312,6 +440,20 @@ fn run_instruction<'input>(
                 ptx_parser::Instruction::BarRed { data, arguments },
             )?
         }
+        ptx_parser::Instruction::BarRedPopc { data, arguments } => {
+            if arguments.src_threadcount.is_some() {
+                return Err(error_malformed_operand(
+                    "bar.red.popc",
+                    "thread count operand is not supported",
+                ));
+            }
+            to_call(
+                resolver,
+                fn_declarations,
+                "bar_red_popc_u32".into(),
+                ptx_parser::Instruction::BarRedPopc { data, arguments },
+            )?
+        }
         ptx_parser::Instruction::ShflSync {
             data,
             arguments: orig_arguments @ ast::ShflSyncArgs { dst_pred: None, .. },
@@ -322,6 +464,7 @@ fn run_instruction<'input>(
                 ptx_parser::ShuffleMode::BFly => "bfly",
                 ptx_parser::ShuffleMode::Idx => "idx",
             };
+            log::warn!("shfl.sync.{mode} is emulated by {ZLUDA_PTX_PREFIX}shfl_sync_{mode}_b32");
             to_call(
                 resolver,
                 fn_declarations,
@@ -341,7 +484,7 @@ fn run_instruction<'input>(
 
 fn to_call<'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
-    fn_declarations: &mut FxHashMap<
+    fn_declarations: &mut FxIndexMap<
         Cow<'input, str>,
         (
             Vec<ast::Variable<SpirvWord>>,
@@ -352,6 +495,7 @@ fn to_call<'input>(
     name: Cow<'input, str>,
     i: ast::Instruction<SpirvWord>,
 ) -> Result<ptx_parser::Instruction<SpirvWord>, TranslateError> {
+    log::trace!("{i} -> call {ZLUDA_PTX_PREFIX}{name}");
     let mut data_return = Vec::new();
     let mut data_input = Vec::new();
     let mut arguments_return = Vec::new();
@@ -374,8 +518,8 @@ fn to_call<'input>(
         Ok::<_, TranslateError>(())
     })?;
     let fn_name = match fn_declarations.entry(name) {
-        hash_map::Entry::Occupied(occupied_entry) => occupied_entry.get().1,
-        hash_map::Entry::Vacant(vacant_entry) => {
+        indexmap::map::Entry::Occupied(occupied_entry) => occupied_entry.get().1,
+        indexmap::map::Entry::Vacant(vacant_entry) => {
             let name = vacant_entry.key().clone();
             let full_name = [ZLUDA_PTX_PREFIX, &*name].concat();
             let name = resolver.register_named(Cow::Owned(full_name.clone()), None);
@@ -412,7 +556,8 @@ fn to_variables<'input>(
             v_type: type_.clone(),
             state_space: *space,
             name: resolver.register_unnamed(Some((type_.clone(), *space))),
-            array_init: Vec::new(),
+            array_init: ast::Initializer::new(),
+            pointer: None,
         })
         .collect::<Vec<_>>()
 }
@@ -36,7 +36,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                         ast::Variable {
                             name: resolver.register_unnamed(Some((
@@ -46,7 +47,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                         ast::Variable {
                             name: resolver.register_unnamed(Some((
@@ -56,7 +58,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                         ast::Variable {
                             name: resolver.register_unnamed(Some((
@@ -66,7 +69,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::U8),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                     ],
                     name: imports.part1,
@@ -79,7 +83,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                         ast::Variable {
                             name: resolver.register_unnamed(Some((
@@ -89,12 +94,15 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                     ],
                     body: None,
+                    span: ast::Span::default(),
                     import_as: None,
                     tuning: Vec::new(),
+                    loop_pragmas: FxHashMap::default(),
                     linkage: ast::LinkingDirective::EXTERN,
                     is_kernel: false,
                     flush_to_zero_f32: false,
@@ -111,7 +119,8 @@ pub(crate) fn run<'input>(
                         align: None,
                         v_type: ast::Type::Scalar(ast::ScalarType::F32),
                         state_space: ast::StateSpace::Reg,
-                        array_init: Vec::new(),
+                        array_init: ast::Initializer::new(),
+                        pointer: None,
                     }],
                     name: imports.part2,
                     input_arguments: vec![
@@ -123,7 +132,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                         ast::Variable {
                             name: resolver.register_unnamed(Some((
@@ -133,7 +143,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                         ast::Variable {
                             name: resolver.register_unnamed(Some((
@@ -143,7 +154,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                         ast::Variable {
                             name: resolver.register_unnamed(Some((
@@ -153,7 +165,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                         ast::Variable {
                             name: resolver.register_unnamed(Some((
@@ -163,7 +176,8 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::F32),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                         ast::Variable {
                             name: resolver.register_unnamed(Some((
@@ -173,12 +187,15 @@ pub(crate) fn run<'input>(
                             align: None,
                             v_type: ast::Type::Scalar(ast::ScalarType::U8),
                             state_space: ast::StateSpace::Reg,
-                            array_init: Vec::new(),
+                            array_init: ast::Initializer::new(),
+                            pointer: None,
                         },
                     ],
                     body: None,
+                    span: ast::Span::default(),
                     import_as: None,
                     tuning: Vec::new(),
+                    loop_pragmas: FxHashMap::default(),
                     linkage: ast::LinkingDirective::EXTERN,
                     is_kernel: false,
                     flush_to_zero_f32: false,
@@ -201,7 +218,10 @@ fn run_directive<'input>(
 ) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
     Ok(match directive {
         Directive2::Variable(linking, var) => Directive2::Variable(linking, var),
-        Directive2::Method(method) => Directive2::Method(run_method(resolver, method, imports)?),
+        Directive2::Method(method) => {
+            let span = method.span;
+            Directive2::Method(run_method(resolver, method, imports).map_err(|err| err.at(span))?)
+        }
     })
 }
 
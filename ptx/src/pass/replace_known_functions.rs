@@ -1,15 +1,20 @@
 use std::borrow::Cow;
 
-use super::{GlobalStringIdentResolver2, NormalizedDirective2, SpirvWord};
+use super::{GlobalStringIdentResolver2, LinkOverrides, NormalizedDirective2, SpirvWord};
+use ptx_parser as ast;
 
 pub(crate) fn run<'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
+    overrides: &LinkOverrides,
     mut directives: Vec<NormalizedDirective2>,
 ) -> Vec<NormalizedDirective2> {
     for directive in directives.iter_mut() {
         match directive {
             NormalizedDirective2::Method(func) => {
-                replace_with_ptx_impl(resolver, func.name);
+                if replace_with_ptx_impl(resolver, overrides, func.name) && func.body.is_some() {
+                    // Our definition in ptx_impl takes precedence when linking
+                    func.linkage |= ast::LinkingDirective::WEAK;
+                }
             }
             _ => {}
         }
@@ -19,15 +24,18 @@ pub(crate) fn run<'input>(
 
 fn replace_with_ptx_impl<'input>(
     resolver: &mut GlobalStringIdentResolver2<'input>,
+    overrides: &LinkOverrides,
     fn_name: SpirvWord,
-) {
-    let known_names = ["__assertfail"];
+) -> bool {
+    let names = resolver.names;
     if let Some(super::IdentEntry {
         name: Some(name), ..
     }) = resolver.ident_map.get_mut(&fn_name)
     {
-        if known_names.contains(&&**name) {
-            *name = Cow::Owned(format!("__zluda_ptx_impl_{}", name));
+        if overrides.contains(name) {
+            *name = names.intern(Cow::Owned(format!("__zluda_ptx_impl_{}", name)));
+            return true;
         }
     }
+    false
 }
@@ -22,7 +22,8 @@ fn run_directive<'input>(
             if !method.is_kernel {
                 functions.insert(method.name);
             }
-            Directive2::Method(run_method(functions, method)?)
+            let span = method.span;
+            Directive2::Method(run_method(functions, method).map_err(|err| err.at(span))?)
         }
     })
 }
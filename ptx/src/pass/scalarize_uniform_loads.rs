@@ -0,0 +1,411 @@
+use super::*;
+use rustc_hash::FxHashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// `ld.global.nc` promises that the memory is not written for the duration of the kernel. When
+// every thread of a wave loads from the same address (a table indexed by a kernel parameter or by
+// %ctaid), AMD GPUs can do the load once per wave on the scalar unit. The backend only does that
+// for loads from the constant address space, so uniform non-coherent loads get their pointer cast
+// from global to constant. Everything else keeps the plain global load.
+// Uniformity is an optimistic fixpoint over the function body, a value is divergent if:
+// * It comes from something that is different in every thread: arguments of a non-kernel
+//   function, calls (except the special registers that are the same in the whole block),
+//   atomics, shuffles and loads from generic or local memory
+// * It is computed from a divergent value
+// * It is read from a register (.local variable) which has a divergent value stored to it, is
+//   stored to in a block that not every thread reaches or is accessed in any way other than plain
+//   loads and stores
+// * It is defined in a block that not every thread reaches and used in another block, this catches
+//   values computed in a loop that threads leave in different iterations
+// A block is not reached by every thread if it is reachable from a branch on a divergent predicate
+pub(super) fn run<'input>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    statistics: &mut PassStatistics,
+    directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
+) -> Result<Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>, TranslateError> {
+    let uniform_functions = uniform_special_registers(resolver, &directives);
+    let scalarized = AtomicUsize::new(0);
+    let directives = run_directives_with_resolver(resolver, directives, |resolver, directive| {
+        run_directive(resolver, &uniform_functions, &scalarized, directive)
+    })?;
+    statistics.scalarized_loads += scalarized.into_inner();
+    Ok(directives)
+}
+
+// Declarations of the ptx_impl functions reading special registers with the same value in every
+// thread of a block
+fn uniform_special_registers(
+    resolver: &GlobalStringIdentResolver2,
+    directives: &[Directive2<ast::Instruction<SpirvWord>, SpirvWord>],
+) -> FxHashSet<SpirvWord> {
+    let names = PtxSpecialRegister::iter()
+        .filter(|sreg| sreg.is_uniform())
+        .map(|sreg| [ZLUDA_PTX_PREFIX, sreg.get_unprefixed_function_name()].concat())
+        .collect::<FxHashSet<_>>();
+    directives
+        .iter()
+        .filter_map(|directive| match directive {
+            Directive2::Method(Function2 {
+                name, body: None, ..
+            }) => Some(*name),
+            _ => None,
+        })
+        .filter(|name| {
+            resolver
+                .ident_map
+                .get(name)
+                .and_then(|entry| entry.name.as_deref())
+                .is_some_and(|name| names.contains(name))
+        })
+        .collect()
+}
+
+fn run_directive<'input>(
+    resolver: &mut GlobalStringIdentResolver2<'input>,
+    uniform_functions: &FxHashSet<SpirvWord>,
+    scalarized: &AtomicUsize,
+    directive: Directive2<ast::Instruction<SpirvWord>, SpirvWord>,
+) -> Result<Directive2<ast::Instruction<SpirvWord>, SpirvWord>, TranslateError> {
+    Ok(match directive {
+        var @ Directive2::Variable(..) => var,
+        Directive2::Method(mut method) => {
+            if let Some(mut statements) = method.body.take() {
+                let divergent_arguments = if method.is_kernel {
+                    Vec::new()
+                } else {
+                    method
+                        .input_arguments
+                        .iter()
+                        .map(|argument| argument.name)
+                        .collect()
+                };
+                let uniformity =
+                    Uniformity::new(uniform_functions, divergent_arguments, &mut statements)
+                        .map_err(|err| err.at(method.span))?;
+                let (statements, count) = scalarize(resolver, &uniformity, statements);
+                scalarized.fetch_add(count, Ordering::Relaxed);
+                method.body = Some(statements);
+            }
+            Directive2::Method(method)
+        }
+    })
+}
+
+fn scalarize(
+    resolver: &mut GlobalStringIdentResolver2,
+    uniformity: &Uniformity,
+    statements: Vec<ExpandedStatement>,
+) -> (Vec<ExpandedStatement>, usize) {
+    let mut result = Vec::with_capacity(statements.len());
+    let mut count = 0;
+    let mut block = 0;
+    for statement in statements {
+        match statement {
+            Statement::Label(..) => block += 1,
+            Statement::Instruction(ast::Instruction::Ld {
+                mut data,
+                mut arguments,
+            }) if data.non_coherent
+                && data.state_space == ast::StateSpace::Global
+                && data.qualifier == ast::LdStQualifier::Weak
+                && uniformity.is_uniform(arguments.src, block) =>
+            {
+                let constant_ptr =
+                    resolver.register_unnamed(Some((data.typ.clone(), ast::StateSpace::Const)));
+                result.push(Statement::Conversion(ImplicitConversion {
+                    src: arguments.src,
+                    dst: constant_ptr,
+                    from_type: data.typ.clone(),
+                    to_type: data.typ.clone(),
+                    from_space: ast::StateSpace::Global,
+                    to_space: ast::StateSpace::Const,
+                    kind: ConversionKind::PtrToPtr,
+                }));
+                data.state_space = ast::StateSpace::Const;
+                arguments.src = constant_ptr;
+                result.push(Statement::Instruction(ast::Instruction::Ld {
+                    data,
+                    arguments,
+                }));
+                count += 1;
+                continue;
+            }
+            _ => {}
+        }
+        result.push(statement);
+    }
+    (result, count)
+}
+
+// What a statement does as far as uniformity is concerned. Blocks are numbered in order of
+// appearance, the function entry is block 0 and every label starts the next one
+enum Step {
+    Label(SpirvWord),
+    // Every destination is divergent if any source is
+    Compute {
+        dsts: Vec<SpirvWord>,
+        srcs: Vec<SpirvWord>,
+    },
+    // Every destination has its own value in every thread
+    Divergent(Vec<SpirvWord>),
+    // Plain store of `value` to the register `variable`
+    Store {
+        variable: SpirvWord,
+        value: SpirvWord,
+    },
+    Branch {
+        predicate: SpirvWord,
+        targets: [SpirvWord; 2],
+    },
+    Jump(SpirvWord),
+    Return,
+}
+
+struct Uniformity {
+    divergent: FxHashSet<SpirvWord>,
+    divergent_blocks: Vec<bool>,
+    // Block of the statement defining every value
+    definitions: FxHashMap<SpirvWord, usize>,
+}
+
+impl Uniformity {
+    fn new(
+        uniform_functions: &FxHashSet<SpirvWord>,
+        divergent_arguments: Vec<SpirvWord>,
+        statements: &mut Vec<ExpandedStatement>,
+    ) -> Result<Self, TranslateError> {
+        let (steps, escaped) = collect_steps(uniform_functions, statements)?;
+        let labels = steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::Label(label) => Some(*label),
+                _ => None,
+            })
+            .zip(1..)
+            .collect::<FxHashMap<_, _>>();
+        let successors = successors(&labels, &steps);
+        let mut uniformity = Uniformity {
+            divergent: divergent_arguments.into_iter().chain(escaped).collect(),
+            divergent_blocks: vec![false; successors.len()],
+            definitions: FxHashMap::default(),
+        };
+        let mut block = 0;
+        for step in steps.iter() {
+            match step {
+                Step::Label(..) => block += 1,
+                Step::Compute { dsts, .. } | Step::Divergent(dsts) => {
+                    for dst in dsts {
+                        uniformity.definitions.insert(*dst, block);
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Nothing ever becomes uniform again, so this terminates
+        loop {
+            let before = (
+                uniformity.divergent.len(),
+                uniformity.divergent_blocks.iter().filter(|b| **b).count(),
+            );
+            let mut block = 0;
+            for step in steps.iter() {
+                match step {
+                    Step::Label(..) => block += 1,
+                    Step::Compute { dsts, srcs } => {
+                        if srcs.iter().any(|src| !uniformity.is_uniform(*src, block)) {
+                            uniformity.divergent.extend(dsts.iter().copied());
+                        }
+                    }
+                    Step::Divergent(dsts) => uniformity.divergent.extend(dsts.iter().copied()),
+                    Step::Store { variable, value } => {
+                        if uniformity.divergent_blocks[block]
+                            || !uniformity.is_uniform(*value, block)
+                        {
+                            uniformity.divergent.insert(*variable);
+                        }
+                    }
+                    Step::Branch { predicate, targets } => {
+                        if !uniformity.is_uniform(*predicate, block) {
+                            for target in targets {
+                                uniformity.mark_reachable(&successors, labels[target]);
+                            }
+                        }
+                    }
+                    Step::Jump(..) | Step::Return => {}
+                }
+            }
+            let after = (
+                uniformity.divergent.len(),
+                uniformity.divergent_blocks.iter().filter(|b| **b).count(),
+            );
+            if before == after {
+                break;
+            }
+        }
+        Ok(uniformity)
+    }
+
+    fn is_uniform(&self, value: SpirvWord, block: usize) -> bool {
+        if self.divergent.contains(&value) {
+            return false;
+        }
+        match self.definitions.get(&value) {
+            Some(definition) => *definition == block || !self.divergent_blocks[*definition],
+            None => true,
+        }
+    }
+
+    fn mark_reachable(&mut self, successors: &[Vec<usize>], block: usize) {
+        let mut pending = vec![block];
+        while let Some(block) = pending.pop() {
+            if std::mem::replace(&mut self.divergent_blocks[block], true) {
+                continue;
+            }
+            pending.extend(successors[block].iter().copied());
+        }
+    }
+}
+
+// Steps of all statements and the registers accessed by anything other than plain loads and stores
+fn collect_steps(
+    uniform_functions: &FxHashSet<SpirvWord>,
+    statements: &mut Vec<ExpandedStatement>,
+) -> Result<(Vec<Step>, Vec<SpirvWord>), TranslateError> {
+    let mut steps = Vec::with_capacity(statements.len());
+    let mut variables = FxHashSet::default();
+    let mut other_uses = FxHashSet::default();
+    *statements = std::mem::take(statements)
+        .into_iter()
+        .map(|statement| {
+            let step = match &statement {
+                Statement::Label(label) => Some(Step::Label(*label)),
+                Statement::Variable(ast::Variable {
+                    name,
+                    state_space: ast::StateSpace::Local,
+                    ..
+                }) => {
+                    variables.insert(*name);
+                    return Ok(statement);
+                }
+                Statement::Variable(..) => return Ok(statement),
+                Statement::Instruction(ast::Instruction::Ld {
+                    data:
+                        ast::LdDetails {
+                            state_space: ast::StateSpace::Local,
+                            qualifier: ast::LdStQualifier::Weak,
+                            ..
+                        },
+                    arguments: ast::LdArgs { dst, src },
+                }) if variables.contains(src) => Some(Step::Compute {
+                    dsts: vec![*dst],
+                    srcs: vec![*src],
+                }),
+                Statement::Instruction(ast::Instruction::St {
+                    data:
+                        ast::StData {
+                            state_space: ast::StateSpace::Local,
+                            qualifier: ast::LdStQualifier::Weak,
+                            ..
+                        },
+                    arguments: ast::StArgs { src1, src2 },
+                }) if variables.contains(src1) => Some(Step::Store {
+                    variable: *src1,
+                    value: *src2,
+                }),
+                Statement::Conditional(BrachCondition {
+                    predicate,
+                    if_true,
+                    if_false,
+                }) => Some(Step::Branch {
+                    predicate: *predicate,
+                    targets: [*if_true, *if_false],
+                }),
+                Statement::Instruction(ast::Instruction::Bra {
+                    arguments: ast::BraArgs { src },
+                }) => Some(Step::Jump(*src)),
+                _ => None,
+            };
+            if let Some(step) = step {
+                steps.push(step);
+                return Ok(statement);
+            }
+            let mut dsts = Vec::new();
+            let mut srcs = Vec::new();
+            let statement =
+                statement.visit_map(&mut |ident: SpirvWord,
+                                           _: Option<(&ast::Type, ast::StateSpace)>,
+                                           is_dst: bool,
+                                           _: bool| {
+                    if is_dst {
+                        dsts.push(ident);
+                    } else {
+                        srcs.push(ident);
+                    }
+                    other_uses.insert(ident);
+                    Ok::<_, TranslateError>(ident)
+                })?;
+            steps.push(match &statement {
+                Statement::RetValue(..)
+                | Statement::Instruction(ast::Instruction::Ret { .. })
+                | Statement::Instruction(ast::Instruction::Trap { .. }) => Step::Return,
+                Statement::Instruction(ast::Instruction::Call { arguments, .. })
+                    if !uniform_functions.contains(&arguments.func) =>
+                {
+                    Step::Divergent(dsts)
+                }
+                Statement::Instruction(ast::Instruction::Ld { data, .. })
+                    if matches!(
+                        data.state_space,
+                        ast::StateSpace::Generic | ast::StateSpace::Local
+                    ) =>
+                {
+                    Step::Divergent(dsts)
+                }
+                Statement::Instruction(
+                    ast::Instruction::Atom { .. }
+                    | ast::Instruction::AtomCas { .. }
+                    | ast::Instruction::ShflSync { .. }
+                    | ast::Instruction::BarRed { .. }
+                    | ast::Instruction::BarRedPopc { .. },
+                ) => Step::Divergent(dsts),
+                _ => Step::Compute { dsts, srcs },
+            });
+            Ok(statement)
+        })
+        .collect::<Result<Vec<_>, TranslateError>>()?;
+    let escaped = variables
+        .into_iter()
+        .filter(|variable| other_uses.contains(variable))
+        .collect();
+    Ok((steps, escaped))
+}
+
+// Blocks every block can jump or fall through to
+fn successors(labels: &FxHashMap<SpirvWord, usize>, steps: &[Step]) -> Vec<Vec<usize>> {
+    let mut successors = vec![Vec::new()];
+    let mut falls_through = true;
+    for step in steps {
+        match step {
+            Step::Label(..) => {
+                let next = successors.len();
+                if falls_through {
+                    successors[next - 1].push(next);
+                }
+                successors.push(Vec::new());
+                falls_through = true;
+            }
+            Step::Jump(target) => {
+                successors.last_mut().unwrap().push(labels[target]);
+                falls_through = false;
+            }
+            Step::Branch { targets, .. } => {
+                let block = successors.last_mut().unwrap();
+                block.extend(targets.iter().map(|target| labels[target]));
+                falls_through = false;
+            }
+            Step::Return => falls_through = false,
+            _ => {}
+        }
+    }
+    successors
+}
@@ -0,0 +1,135 @@
+use super::*;
+use rustc_hash::FxHashSet;
+
+// Drops everything the selected kernels can't reach, so that the passes after it and LLVM only
+// work on the kernels which are going to be launched. A directive is reachable if it's one of
+// the selected kernels or its name appears in a reachable function body or in the initializer of
+// a reachable variable: called and declared functions, functions whose address is taken and
+// module variables. Kernel metadata is collected before this pass, so it still describes every
+// kernel of the module
+pub(super) fn run<'input>(
+    resolver: &GlobalStringIdentResolver2<'input>,
+    entry_points: &[String],
+    directives: Vec<NormalizedDirective2>,
+) -> Result<Vec<NormalizedDirective2>, TranslateError> {
+    // Functions can be declared several times before they are defined
+    let mut by_name = FxHashMap::<_, Vec<_>>::default();
+    for (index, directive) in directives.iter().enumerate() {
+        let ident = match directive {
+            Directive2::Variable(_, variable) => variable.name,
+            Directive2::Method(method) => method.name,
+        };
+        by_name.entry(ident).or_default().push(index);
+    }
+    let mut reachable = FxHashSet::default();
+    let mut pending = Vec::new();
+    for entry_point in entry_points {
+        let kernel = directives.iter().find_map(|directive| match directive {
+            Directive2::Method(method)
+                if method.is_kernel
+                    && name(resolver, method.name) == Some(entry_point.as_str()) =>
+            {
+                Some(method.name)
+            }
+            _ => None,
+        });
+        match kernel {
+            Some(kernel) => pending.push(kernel),
+            None => return Err(error_kernel_not_found(resolver, entry_point, &directives)),
+        }
+    }
+    while let Some(ident) = pending.pop() {
+        let indices = match by_name.get(&ident) {
+            Some(indices) => indices,
+            None => continue,
+        };
+        if !reachable.insert(ident) {
+            continue;
+        }
+        for index in indices {
+            match &directives[*index] {
+                Directive2::Variable(_, variable) => {
+                    collect_initializer(&variable.array_init, &mut pending);
+                }
+                Directive2::Method(method) => {
+                    for statement in method.body.iter().flatten() {
+                        collect_statement(statement, &mut pending)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(directives
+        .into_iter()
+        .filter(|directive| match directive {
+            Directive2::Variable(_, variable) => reachable.contains(&variable.name),
+            Directive2::Method(method) => reachable.contains(&method.name),
+        })
+        .collect())
+}
+
+fn name<'a>(resolver: &'a GlobalStringIdentResolver2, ident: SpirvWord) -> Option<&'a str> {
+    resolver
+        .ident_map
+        .get(&ident)
+        .and_then(|entry| entry.name.as_deref())
+}
+
+fn error_kernel_not_found(
+    resolver: &GlobalStringIdentResolver2,
+    entry_point: &str,
+    directives: &[NormalizedDirective2],
+) -> TranslateError {
+    let available = directives
+        .iter()
+        .filter_map(|directive| match directive {
+            Directive2::Method(method) if method.is_kernel => name(resolver, method.name),
+            _ => None,
+        })
+        .map(str::to_string)
+        .collect();
+    TranslateError::KernelNotFound {
+        name: entry_point.to_string(),
+        available,
+    }
+}
+
+// Collects every identifier, most of them are registers and labels of the function which don't
+// name any directive and are skipped by the caller
+fn collect_statement(
+    statement: &NormalizedStatement,
+    pending: &mut Vec<SpirvWord>,
+) -> Result<(), TranslateError> {
+    match statement {
+        Statement::Instruction((_, instruction, _)) => ast::visit(
+            instruction,
+            &mut |operand: &ast::ParsedOperand<SpirvWord>,
+                  _: Option<(&ast::Type, ast::StateSpace)>,
+                  _: bool,
+                  _: bool| {
+                match operand {
+                    ast::ParsedOperand::Reg(ident)
+                    | ast::ParsedOperand::RegOffset(ident, _)
+                    | ast::ParsedOperand::VecMember(ident, _) => pending.push(*ident),
+                    ast::ParsedOperand::VecPack(idents) => pending.extend(idents),
+                    ast::ParsedOperand::Imm(_) => {}
+                }
+                Ok(())
+            },
+        ),
+        Statement::Variable(variable) => {
+            collect_initializer(&variable.array_init, pending);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn collect_initializer(initializer: &ast::Initializer<SpirvWord>, pending: &mut Vec<SpirvWord>) {
+    pending.extend(
+        initializer
+            .relocations
+            .iter()
+            .map(|relocation| relocation.symbol),
+    )
+}
@@ -0,0 +1,630 @@
+// SPIR-V counterpart of llvm::emit for consumers other than AMD GPUs (Intel Level Zero, spirv-val).
+// It takes the statements left after the last pass and emits an OpenCL kernel module with rspirv.
+// Only a subset of the statements is covered so far: loads and stores, integer and float add, mov,
+// constants, implicit conversions and control flow in kernels. Everything else fails with
+// TranslateError::UnsupportedSpirv rather than producing a module the consumer would reject.
+// Unlike LLVM, SPIR-V pointers are typed, so a pointer is bitcast to the type of every access
+// that goes through it
+use super::*;
+use rspirv::dr;
+use rspirv::spirv;
+
+pub(super) fn run<'input>(
+    id_defs: &GlobalStringIdentResolver2<'input>,
+    directives: Vec<Directive2<ast::Instruction<SpirvWord>, SpirvWord>>,
+) -> Result<dr::Module, TranslateError> {
+    let mut emit_ctx = ModuleEmitContext::new(id_defs);
+    for directive in directives {
+        match directive {
+            Directive2::Variable(linking, variable) => emit_ctx.emit_global(linking, variable)?,
+            Directive2::Method(method) => {
+                let span = method.span;
+                emit_ctx.emit_method(method).map_err(|err| err.at(span))?
+            }
+        }
+    }
+    Ok(emit_ctx.builder.module())
+}
+
+// rspirv only fails when the builder is misused (no function or block selected)
+fn build_error(_: dr::Error) -> TranslateError {
+    error_unreachable()
+}
+
+struct ModuleEmitContext<'a, 'input> {
+    builder: dr::Builder,
+    id_defs: &'a GlobalStringIdentResolver2<'input>,
+    capabilities: Vec<spirv::Capability>,
+    ids: FxHashMap<SpirvWord, spirv::Word>,
+    // Type of every pointer value, pointers are cast to the type of the access through them
+    pointer_types: FxHashMap<spirv::Word, spirv::Word>,
+}
+
+impl<'a, 'input> ModuleEmitContext<'a, 'input> {
+    fn new(id_defs: &'a GlobalStringIdentResolver2<'input>) -> Self {
+        let mut builder = dr::Builder::new();
+        // Version 1.2 is the newest one every Level Zero driver accepts
+        builder.set_version(1, 2);
+        builder.memory_model(
+            spirv::AddressingModel::Physical64,
+            spirv::MemoryModel::OpenCL,
+        );
+        let mut emit_ctx = Self {
+            builder,
+            id_defs,
+            capabilities: Vec::new(),
+            ids: FxHashMap::default(),
+            pointer_types: FxHashMap::default(),
+        };
+        for capability in [
+            spirv::Capability::Addresses,
+            spirv::Capability::Kernel,
+            spirv::Capability::Int64,
+            spirv::Capability::GenericPointer,
+        ] {
+            emit_ctx.require(capability);
+        }
+        emit_ctx
+    }
+
+    fn require(&mut self, capability: spirv::Capability) {
+        if !self.capabilities.contains(&capability) {
+            self.capabilities.push(capability);
+            self.builder.capability(capability);
+        }
+    }
+
+    fn id(&mut self, word: SpirvWord) -> spirv::Word {
+        match self.ids.get(&word) {
+            Some(id) => *id,
+            None => {
+                let id = self.builder.id();
+                self.ids.insert(word, id);
+                id
+            }
+        }
+    }
+
+    fn value(&self, word: SpirvWord) -> Result<spirv::Word, TranslateError> {
+        self.ids.get(&word).copied().ok_or_else(error_unreachable)
+    }
+
+    fn name(&self, word: SpirvWord) -> Option<&'a str> {
+        self.id_defs
+            .ident_map
+            .get(&word)
+            .and_then(|entry| entry.name.as_deref())
+    }
+
+    fn emit_global(
+        &mut self,
+        linking: ast::LinkingDirective,
+        var: ast::Variable<SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        if linking.intersects(ast::LinkingDirective::EXTERN | ast::LinkingDirective::WEAK) {
+            return Err(error_unsupported_spirv("linking of variables"));
+        }
+        if !var.array_init.is_empty() {
+            return Err(error_unsupported_spirv("variable initializers"));
+        }
+        let storage_class = storage_class(var.state_space)?;
+        let pointer_type = self.pointer_type(storage_class, &var.v_type)?;
+        let id = self.id(var.name);
+        self.builder
+            .variable(pointer_type, Some(id), storage_class, None);
+        self.pointer_types.insert(id, pointer_type);
+        if let Some(name) = self.name(var.name) {
+            self.builder.name(id, name);
+        }
+        if let Some(align) = var.align {
+            self.builder.decorate(
+                id,
+                spirv::Decoration::Alignment,
+                [dr::Operand::LiteralInt32(align)],
+            );
+        }
+        Ok(())
+    }
+
+    fn emit_method(
+        &mut self,
+        method: Function2<ast::Instruction<SpirvWord>, SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        if !method.is_kernel {
+            return Err(error_unsupported_spirv("functions other than kernels"));
+        }
+        let statements = match method.body {
+            Some(statements) => statements,
+            None => return Err(error_unsupported_spirv("kernel declarations")),
+        };
+        let name = self.name(method.name).ok_or_else(error_unreachable)?;
+        let mut parameter_types = Vec::with_capacity(method.input_arguments.len());
+        for parameter in method.input_arguments.iter() {
+            if parameter.state_space != ast::StateSpace::ParamEntry {
+                return Err(error_unreachable());
+            }
+            parameter_types.push(self.type_(&parameter.v_type)?);
+        }
+        let void = self.builder.type_void();
+        let function_type = self.builder.type_function(void, parameter_types.clone());
+        let function = self.id(method.name);
+        self.builder
+            .begin_function(
+                void,
+                Some(function),
+                spirv::FunctionControl::NONE,
+                function_type,
+            )
+            .map_err(build_error)?;
+        let mut parameters = Vec::with_capacity(parameter_types.len());
+        for type_ in parameter_types {
+            parameters.push(
+                self.builder
+                    .function_parameter(type_)
+                    .map_err(build_error)?,
+            );
+        }
+        // OpVariable in Function storage must come first in the first block, so the variables of
+        // the whole body go into a block of their own which then branches to the body
+        self.builder.begin_block(None).map_err(build_error)?;
+        // Kernel parameters are passed by value, ld.param reads them from a copy
+        let mut copies = Vec::with_capacity(parameters.len());
+        for (parameter, value) in method.input_arguments.iter().zip(parameters) {
+            copies.push((
+                self.emit_variable(parameter.name, &parameter.v_type)?,
+                value,
+            ));
+        }
+        for statement in statements.iter() {
+            if let Statement::Variable(var) = statement {
+                match var.state_space {
+                    ast::StateSpace::Reg | ast::StateSpace::Local => {}
+                    space => return Err(error_unsupported_state_space(space, "SPIR-V emission")),
+                }
+                if !var.array_init.is_empty() {
+                    return Err(error_unsupported_spirv("variable initializers"));
+                }
+                self.emit_variable(var.name, &var.v_type)?;
+            }
+        }
+        for (variable, value) in copies {
+            self.builder
+                .store(variable, value, None, [])
+                .map_err(build_error)?;
+        }
+        for statement in statements {
+            self.emit_statement(statement)?;
+        }
+        // normalize_basic_blocks ends every basic block with a terminator
+        if self.builder.selected_block().is_some() {
+            return Err(error_unreachable());
+        }
+        self.builder.end_function().map_err(build_error)?;
+        self.builder
+            .entry_point(spirv::ExecutionModel::Kernel, function, name, []);
+        self.builder.name(function, name);
+        // The passes already fused what ptxas would, the consumer must not contract anything else
+        self.builder
+            .execution_mode(function, spirv::ExecutionMode::ContractionOff, []);
+        for tuning in method.tuning {
+            if let ast::TuningDirective::ReqNtid(x, y, z) = tuning {
+                self.builder
+                    .execution_mode(function, spirv::ExecutionMode::LocalSize, [x, y, z]);
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_variable(
+        &mut self,
+        name: SpirvWord,
+        type_: &ast::Type,
+    ) -> Result<spirv::Word, TranslateError> {
+        let pointer_type = self.pointer_type(spirv::StorageClass::Function, type_)?;
+        let id = self.id(name);
+        self.builder
+            .variable(pointer_type, Some(id), spirv::StorageClass::Function, None);
+        self.pointer_types.insert(id, pointer_type);
+        Ok(id)
+    }
+
+    fn emit_statement(
+        &mut self,
+        statement: Statement<ast::Instruction<SpirvWord>, SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        Ok(match statement {
+            // Already emitted at the start of the function
+            Statement::Variable(_) => {}
+            Statement::Label(label) => self.emit_label(label)?,
+            Statement::Instruction(inst) => self.emit_instruction(inst)?,
+            Statement::Conditional(cond) => self.emit_conditional(cond)?,
+            Statement::Conversion(conversion) => self.emit_conversion(conversion)?,
+            Statement::Constant(constant) => self.emit_constant(constant)?,
+            // No-op
+            Statement::FpModeRequired { .. } => {}
+            Statement::RetValue(..) => return Err(error_unsupported_spirv("return values")),
+            Statement::PtrAccess(_) => return Err(error_unsupported_spirv("pointer arithmetic")),
+            Statement::RepackVector(_) | Statement::VectorRead(_) | Statement::VectorWrite(_) => {
+                return Err(error_unsupported_spirv("vector operands"))
+            }
+            Statement::FunctionPointer(_) => {
+                return Err(error_unsupported_instruction("mov of a function address"))
+            }
+            Statement::SetMode(_) | Statement::FpSaturate { .. } => {
+                return Err(error_unsupported_spirv("floating point modes"))
+            }
+        })
+    }
+
+    fn emit_label(&mut self, label: SpirvWord) -> Result<(), TranslateError> {
+        let label = self.id(label);
+        if self.builder.selected_block().is_some() {
+            self.builder.branch(label).map_err(build_error)?;
+        }
+        self.builder.begin_block(Some(label)).map_err(build_error)?;
+        Ok(())
+    }
+
+    fn emit_instruction(
+        &mut self,
+        inst: ast::Instruction<SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        match inst {
+            ast::Instruction::Ld { data, arguments } => self.emit_ld(data, arguments),
+            ast::Instruction::St { data, arguments } => self.emit_st(data, arguments),
+            ast::Instruction::Add { data, arguments } => self.emit_add(data, arguments),
+            ast::Instruction::Mov { data, arguments } => self.emit_mov(data, arguments),
+            ast::Instruction::Bra { arguments } => {
+                let target = self.id(arguments.src);
+                self.builder.branch(target).map_err(build_error)
+            }
+            ast::Instruction::Ret { .. } => self.builder.ret().map_err(build_error),
+            inst => Err(error_unsupported_spirv(format!("instruction {inst}"))),
+        }
+    }
+
+    fn emit_ld(
+        &mut self,
+        data: ast::LdDetails,
+        arguments: ast::LdArgs<SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        let memory_access = memory_access(data.qualifier, &data.typ)?;
+        let type_ = self.memory_type(data.state_space, &data.typ)?;
+        let pointer = self.pointer(arguments.src, data.state_space, &data.typ)?;
+        let dst = self.id(arguments.dst);
+        self.builder
+            .load(
+                type_,
+                Some(dst),
+                pointer,
+                Some(memory_access.0),
+                memory_access.1,
+            )
+            .map_err(build_error)?;
+        Ok(())
+    }
+
+    fn emit_st(
+        &mut self,
+        data: ast::StData,
+        arguments: ast::StArgs<SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        let memory_access = memory_access(data.qualifier, &data.typ)?;
+        self.memory_type(data.state_space, &data.typ)?;
+        let pointer = self.pointer(arguments.src1, data.state_space, &data.typ)?;
+        let value = self.value(arguments.src2)?;
+        self.builder
+            .store(pointer, value, Some(memory_access.0), memory_access.1)
+            .map_err(build_error)
+    }
+
+    fn emit_add(
+        &mut self,
+        data: ast::ArithDetails,
+        arguments: ast::AddArgs<SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        let type_ = self.scalar_type(data.type_())?;
+        let src1 = self.value(arguments.src1)?;
+        let src2 = self.value(arguments.src2)?;
+        let dst = self.id(arguments.dst);
+        let result = match data {
+            ast::ArithDetails::Integer(ast::ArithInteger { saturate: true, .. }) => {
+                return Err(error_unsupported_spirv("add.sat"))
+            }
+            ast::ArithDetails::Integer(..) => self.builder.i_add(type_, Some(dst), src1, src2),
+            ast::ArithDetails::Float(..) => self.builder.f_add(type_, Some(dst), src1, src2),
+        };
+        result.map_err(build_error)?;
+        Ok(())
+    }
+
+    fn emit_mov(
+        &mut self,
+        data: ast::MovDetails,
+        arguments: ast::MovArgs<SpirvWord>,
+    ) -> Result<(), TranslateError> {
+        let type_ = self.type_(&data.typ)?;
+        let src = self.value(arguments.src)?;
+        let dst = self.id(arguments.dst);
+        self.builder
+            .copy_object(type_, Some(dst), src)
+            .map_err(build_error)?;
+        Ok(())
+    }
+
+    fn emit_conditional(&mut self, cond: BrachCondition) -> Result<(), TranslateError> {
+        let predicate = self.value(cond.predicate)?;
+        let if_true = self.id(cond.if_true);
+        let if_false = self.id(cond.if_false);
+        self.builder
+            .branch_conditional(predicate, if_true, if_false, [])
+            .map_err(build_error)
+    }
+
+    fn emit_constant(&mut self, constant: ConstantDefinition) -> Result<(), TranslateError> {
+        let type_ = self.scalar_type(constant.typ)?;
+        let dst = self.id(constant.dst);
+        let value = match (constant.typ.kind(), constant.value) {
+            (ast::ScalarKind::Pred, value) => {
+                let value = value.as_u64().ok_or_else(error_mismatched_type)?;
+                let opcode = if value != 0 {
+                    spirv::Op::ConstantTrue
+                } else {
+                    spirv::Op::ConstantFalse
+                };
+                dr::Instruction::new(opcode, Some(type_), Some(dst), Vec::new())
+            }
+            (ast::ScalarKind::Float, value) => {
+                let value = match value {
+                    ast::ImmediateValue::F32(x) => x as f64,
+                    ast::ImmediateValue::F64(x) => x,
+                    ast::ImmediateValue::U64(_) | ast::ImmediateValue::S64(_) => {
+                        return Err(error_mismatched_type())
+                    }
+                };
+                let literal = match constant.typ {
+                    ast::ScalarType::F32 => dr::Operand::LiteralFloat32(value as f32),
+                    ast::ScalarType::F64 => dr::Operand::LiteralFloat64(value),
+                    _ => {
+                        return Err(error_unsupported_spirv(format!(
+                            "{} constants",
+                            constant.typ
+                        )))
+                    }
+                };
+                dr::Instruction::new(spirv::Op::Constant, Some(type_), Some(dst), vec![literal])
+            }
+            (_, value) => {
+                let value = value.as_u64().ok_or_else(error_mismatched_type)?;
+                // Literals narrower than 32 bits take the high-order bits as zero
+                let literal = match constant.typ.size_of() {
+                    8 => dr::Operand::LiteralInt64(value),
+                    size => {
+                        dr::Operand::LiteralInt32((value & (u64::MAX >> (64 - size * 8))) as u32)
+                    }
+                };
+                dr::Instruction::new(spirv::Op::Constant, Some(type_), Some(dst), vec![literal])
+            }
+        };
+        self.builder.module_mut().types_global_values.push(value);
+        Ok(())
+    }
+
+    fn emit_conversion(&mut self, conversion: ImplicitConversion) -> Result<(), TranslateError> {
+        let dst = self.id(conversion.dst);
+        match conversion.kind {
+            ConversionKind::Default => self.emit_conversion_default(conversion, dst),
+            ConversionKind::SignExtend => {
+                let type_ = self.type_(&conversion.to_type)?;
+                let src = self.value(conversion.src)?;
+                self.builder
+                    .s_convert(type_, Some(dst), src)
+                    .map_err(build_error)?;
+                Ok(())
+            }
+            ConversionKind::BitToPtr => {
+                let type_ =
+                    self.pointer_type(storage_class(conversion.to_space)?, &conversion.to_type)?;
+                let src = self.value(conversion.src)?;
+                self.builder
+                    .convert_u_to_ptr(type_, Some(dst), src)
+                    .map_err(build_error)?;
+                self.pointer_types.insert(dst, type_);
+                Ok(())
+            }
+            ConversionKind::PtrToPtr => {
+                let type_ =
+                    self.pointer_type(storage_class(conversion.to_space)?, &conversion.to_type)?;
+                let src =
+                    self.pointer(conversion.src, conversion.from_space, &conversion.to_type)?;
+                let result = if conversion.to_space == ast::StateSpace::Generic {
+                    self.builder.ptr_cast_to_generic(type_, Some(dst), src)
+                } else if conversion.from_space == ast::StateSpace::Generic {
+                    self.builder.generic_cast_to_ptr(type_, Some(dst), src)
+                } else {
+                    return Err(error_unreachable());
+                };
+                result.map_err(build_error)?;
+                self.pointer_types.insert(dst, type_);
+                Ok(())
+            }
+            ConversionKind::AddressOf => {
+                let type_ = self.type_(&conversion.to_type)?;
+                let src = self.value(conversion.src)?;
+                self.builder
+                    .convert_ptr_to_u(type_, Some(dst), src)
+                    .map_err(build_error)?;
+                Ok(())
+            }
+        }
+    }
+
+    // Same size is a bitcast, different size of integers is a zero extension or truncation
+    fn emit_conversion_default(
+        &mut self,
+        conversion: ImplicitConversion,
+        dst: spirv::Word,
+    ) -> Result<(), TranslateError> {
+        let (from, to) = match (&conversion.from_type, &conversion.to_type) {
+            (ast::Type::Scalar(from), ast::Type::Scalar(to)) => (*from, *to),
+            _ => return Err(error_unsupported_spirv("conversions of vectors and arrays")),
+        };
+        if from.kind() == ast::ScalarKind::Pred || to.kind() == ast::ScalarKind::Pred {
+            return Err(error_unsupported_spirv("conversions of predicates"));
+        }
+        let type_ = self.scalar_type(to)?;
+        let src = self.value(conversion.src)?;
+        let from_type = self.scalar_type(from)?;
+        let result = if from.size_of() == to.size_of() {
+            if from_type == type_ {
+                self.builder.copy_object(type_, Some(dst), src)
+            } else {
+                self.builder.bitcast(type_, Some(dst), src)
+            }
+        } else if from.kind() != ast::ScalarKind::Float && to.kind() != ast::ScalarKind::Float {
+            self.builder.u_convert(type_, Some(dst), src)
+        } else {
+            return Err(error_mismatched_type());
+        };
+        result.map_err(build_error)?;
+        Ok(())
+    }
+
+    // Pointer to `type_` in `space`, cast from the type the pointer was created with
+    fn pointer(
+        &mut self,
+        pointer: SpirvWord,
+        space: ast::StateSpace,
+        type_: &ast::Type,
+    ) -> Result<spirv::Word, TranslateError> {
+        let pointer = self.value(pointer)?;
+        let actual_type = *self
+            .pointer_types
+            .get(&pointer)
+            .ok_or_else(error_unreachable)?;
+        let expected_type = self.pointer_type(storage_class(space)?, type_)?;
+        if actual_type == expected_type {
+            return Ok(pointer);
+        }
+        let cast = self
+            .builder
+            .bitcast(expected_type, None, pointer)
+            .map_err(build_error)?;
+        self.pointer_types.insert(cast, expected_type);
+        Ok(cast)
+    }
+
+    fn pointer_type(
+        &mut self,
+        storage_class: spirv::StorageClass,
+        type_: &ast::Type,
+    ) -> Result<spirv::Word, TranslateError> {
+        let pointee = self.type_(type_)?;
+        Ok(self.builder.type_pointer(None, storage_class, pointee))
+    }
+
+    // Predicates have no size in SPIR-V, they can only live in Function storage
+    fn memory_type(
+        &mut self,
+        space: ast::StateSpace,
+        type_: &ast::Type,
+    ) -> Result<spirv::Word, TranslateError> {
+        let is_private = matches!(
+            space,
+            ast::StateSpace::Reg | ast::StateSpace::Local | ast::StateSpace::ParamEntry
+        );
+        if !is_private && *type_ == ast::Type::Scalar(ast::ScalarType::Pred) {
+            return Err(error_unsupported_spirv("predicates in memory"));
+        }
+        self.type_(type_)
+    }
+
+    fn type_(&mut self, type_: &ast::Type) -> Result<spirv::Word, TranslateError> {
+        match type_ {
+            ast::Type::Scalar(scalar) => self.scalar_type(*scalar),
+            ast::Type::Vector(size, scalar) => {
+                let scalar = self.scalar_type(*scalar)?;
+                Ok(self.builder.type_vector(scalar, *size as u32))
+            }
+            ast::Type::Array(None, scalar, dimensions) => {
+                let mut type_ = self.scalar_type(*scalar)?;
+                let u32 = self.scalar_type(ast::ScalarType::U32)?;
+                for dimension in dimensions.iter().rev() {
+                    let length = self.builder.constant_u32(u32, *dimension);
+                    type_ = self.builder.type_array(type_, length);
+                }
+                Ok(type_)
+            }
+            ast::Type::Array(Some(_), ..) => Err(error_unsupported_spirv("arrays of vectors")),
+        }
+    }
+
+    fn scalar_type(&mut self, type_: ast::ScalarType) -> Result<spirv::Word, TranslateError> {
+        Ok(match type_ {
+            ast::ScalarType::Pred => self.builder.type_bool(),
+            ast::ScalarType::U8 | ast::ScalarType::S8 | ast::ScalarType::B8 => {
+                self.require(spirv::Capability::Int8);
+                self.builder.type_int(8, 0)
+            }
+            ast::ScalarType::U16 | ast::ScalarType::S16 | ast::ScalarType::B16 => {
+                self.require(spirv::Capability::Int16);
+                self.builder.type_int(16, 0)
+            }
+            // OpenCL kernels have no signed integer types, signedness is in the instructions
+            ast::ScalarType::U32 | ast::ScalarType::S32 | ast::ScalarType::B32 => {
+                self.builder.type_int(32, 0)
+            }
+            ast::ScalarType::U64 | ast::ScalarType::S64 | ast::ScalarType::B64 => {
+                self.builder.type_int(64, 0)
+            }
+            ast::ScalarType::F32 => self.builder.type_float(32),
+            ast::ScalarType::F64 => {
+                self.require(spirv::Capability::Float64);
+                self.builder.type_float(64)
+            }
+            ast::ScalarType::F16
+            | ast::ScalarType::BF16
+            | ast::ScalarType::F16x2
+            | ast::ScalarType::BF16x2
+            | ast::ScalarType::U16x2
+            | ast::ScalarType::S16x2
+            | ast::ScalarType::B128 => {
+                return Err(error_unsupported_spirv(format!("type {type_}")))
+            }
+        })
+    }
+}
+
+fn storage_class(space: ast::StateSpace) -> Result<spirv::StorageClass, TranslateError> {
+    match space {
+        // Kernel parameters are copied to Function variables, see emit_method
+        ast::StateSpace::Reg | ast::StateSpace::Local | ast::StateSpace::ParamEntry => {
+            Ok(spirv::StorageClass::Function)
+        }
+        ast::StateSpace::Generic => Ok(spirv::StorageClass::Generic),
+        ast::StateSpace::Global => Ok(spirv::StorageClass::CrossWorkgroup),
+        ast::StateSpace::Const => Ok(spirv::StorageClass::UniformConstant),
+        ast::StateSpace::Shared => Ok(spirv::StorageClass::Workgroup),
+        ast::StateSpace::Param
+        | ast::StateSpace::ParamFunc
+        | ast::StateSpace::SharedCta
+        | ast::StateSpace::SharedCluster => {
+            Err(error_unsupported_state_space(space, "SPIR-V emission"))
+        }
+    }
+}
+
+fn memory_access(
+    qualifier: ast::LdStQualifier,
+    type_: &ast::Type,
+) -> Result<(spirv::MemoryAccess, [dr::Operand; 1]), TranslateError> {
+    let access = match qualifier {
+        ast::LdStQualifier::Weak => spirv::MemoryAccess::ALIGNED,
+        ast::LdStQualifier::Volatile => {
+            spirv::MemoryAccess::ALIGNED | spirv::MemoryAccess::VOLATILE
+        }
+        _ => return Err(error_unsupported_spirv("ld and st with memory ordering")),
+    };
+    let align = type_.layout().align() as u32;
+    Ok((access, [dr::Operand::LiteralInt32(align)]))
+}
@@ -0,0 +1,281 @@
+use crate::pass::*;
+
+fn check(ptx: &str) -> Result<(), TranslateError> {
+    let module = ptx_parser::parse_module_checked(ptx).unwrap();
+    let names = NameInterner::default();
+    let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1), &names);
+    let mut scoped_resolver = ScopedResolver::new(&mut flat_resolver);
+    let sreg_map = SpecialRegistersMap2::new(&mut scoped_resolver)?;
+    let directives = normalize_identifiers2::run(&mut scoped_resolver, module.directives)?;
+    check_operand_types::run(&flat_resolver, &sreg_map, ptx, &directives)
+}
+
+fn check_error(ptx: &str) -> TranslateError {
+    match check(ptx) {
+        Ok(_) => panic!("expected an error"),
+        Err(error) => error.resolve_location(ptx),
+    }
+}
+
+fn assert_mismatch(
+    error: &TranslateError,
+    expected_instruction: &str,
+    expected_operand: usize,
+    expected_name: &str,
+    expected_type: &str,
+    actual_type: &str,
+) {
+    match error.inner() {
+        TranslateError::OperandTypeMismatch {
+            instruction,
+            operand,
+            name,
+            expected,
+            actual,
+        } => {
+            assert_eq!(instruction, expected_instruction);
+            assert_eq!(*operand, expected_operand);
+            assert_eq!(name, expected_name);
+            assert_eq!(expected, expected_type);
+            assert_eq!(actual, actual_type);
+        }
+        _ => panic!("{:?}", error),
+    }
+}
+
+#[test]
+fn register_of_different_size_is_mismatch() {
+    let error = check_error(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry mismatch()
+        {
+            .reg .u64 wide;
+            .reg .u32 narrow;
+            add.u32 narrow, narrow, wide;
+            ret;
+        }",
+    );
+    assert_mismatch(&error, "add.u32", 3, "wide", ".u32", ".u64");
+    assert_eq!(error.location().unwrap().line, 10);
+    assert!(
+        error.to_string().contains(
+            "Operand 3 \"wide\" of `add.u32` has type .u64, but the instruction expects .u32"
+        ),
+        "{error}"
+    );
+}
+
+#[test]
+fn predicate_used_as_bits_is_mismatch() {
+    let error = check_error(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry mismatch()
+        {
+            .reg .pred p;
+            .reg .b32 bits;
+            setp.eq.u32 p, bits, 0;
+            mov.b32 bits, p;
+            ret;
+        }",
+    );
+    assert_mismatch(&error, "mov.b32", 2, "p", ".b32", ".pred");
+}
+
+#[test]
+fn guard_which_is_not_a_predicate_is_mismatch() {
+    let error = check_error(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry mismatch()
+        {
+            .reg .u16 flag;
+            mov.u16 flag, 1;
+            @!flag bra end;
+        end:
+            ret;
+        }",
+    );
+    assert_mismatch(&error, "bra", 0, "flag", ".pred", ".u16");
+    assert!(
+        error
+            .to_string()
+            .contains("Guard predicate \"flag\" of `bra`"),
+        "{error}"
+    );
+}
+
+#[test]
+fn float_register_in_integer_instruction_is_mismatch() {
+    let error = check_error(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry mismatch()
+        {
+            .reg .u32 sum;
+            .reg .f32 value;
+            add.u32 sum, sum, value;
+            ret;
+        }",
+    );
+    assert_mismatch(&error, "add.u32", 3, "value", ".u32", ".f32");
+}
+
+#[test]
+fn integer_register_in_float_instruction_is_mismatch() {
+    let error = check_error(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry mismatch()
+        {
+            .reg .s64 value;
+            .reg .f64 result;
+            neg.f64 result, value;
+            ret;
+        }",
+    );
+    assert_mismatch(&error, "neg.f64", 2, "value", ".f64", ".s64");
+}
+
+#[test]
+fn compatible_registers_are_accepted() {
+    check(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry compatible(.param .u64 input)
+        {
+            .reg .u64 address;
+            .reg .u64 wide;
+            .reg .s32 signed;
+            .reg .u32 unsigned;
+            .reg .b32 bits;
+            .reg .f32 float;
+            .reg .f16 half;
+            .reg .pred p;
+
+            ld.param.u64 address, [input];
+            ld.global.u32 wide, [address];
+            add.u32 unsigned, signed, unsigned;
+            mov.b32 bits, float;
+            add.f32 float, bits, float;
+            cvt.rn.f16.f32 half, float;
+            ex2.approx.f16 half, half;
+            setp.lt.s32 p, signed, 0;
+            @p ret;
+            ret;
+        }",
+    )
+    .unwrap();
+}
+
+#[test]
+fn component_past_vector_width_is_malformed() {
+    let error = check_error(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry malformed()
+        {
+            .reg .v2 .f32 %v<2>;
+            mov.f32 %v1.b, %v0.x;
+            ret;
+        }",
+    );
+    assert!(
+        matches!(error.inner(), TranslateError::MalformedOperand { .. }),
+        "{error:?}"
+    );
+    assert!(
+        error.to_string().contains(
+            "Unsupported operand of `mov.f32`: \"%v1\" has type .v2.f32, it has no component .z"
+        ),
+        "{error}"
+    );
+}
+
+#[test]
+fn component_of_scalar_is_malformed() {
+    let error = check_error(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry malformed()
+        {
+            .reg .u32 scalar;
+            .reg .u32 result;
+            add.u32 result, scalar.x, 1;
+            ret;
+        }",
+    );
+    assert!(
+        error
+            .to_string()
+            .contains("\"scalar\" has type .u32, it has no component .x"),
+        "{error}"
+    );
+}
+
+#[test]
+fn component_of_different_type_is_mismatch() {
+    let error = check_error(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry mismatch()
+        {
+            .reg .v4 .f32 %v;
+            .reg .u32 result;
+            add.u32 result, result, %v.a;
+            ret;
+        }",
+    );
+    assert_mismatch(&error, "add.u32", 3, "%v.w", ".u32", ".f32");
+}
+
+#[test]
+fn components_of_vector_registers_are_accepted() {
+    check(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry compatible()
+        {
+            .reg .v4 .f32 %v<2>;
+            .reg .v2 .b32 bits;
+            .reg .u16 tid;
+            add.f32 %v0.x, %v1.r, %v1.g;
+            mul.f32 %v0.w, %v0.b, %v0.a;
+            mov.b32 bits.y, %v1.z;
+            mov.u16 tid, %tid.x;
+            ret;
+        }",
+    )
+    .unwrap();
+}
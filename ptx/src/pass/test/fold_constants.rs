@@ -0,0 +1,65 @@
+use crate::pass::*;
+
+fn fold_and_eliminate(ptx: &str) -> (Vec<ExpandedStatement>, PassStatistics) {
+    // We run the minimal number of passes required to produce the input expected by fold_constants
+    let module = ptx_parser::parse_module_checked(ptx).unwrap();
+    let names = NameInterner::default();
+    let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1), &names);
+    let mut scoped_resolver = ScopedResolver::new(&mut flat_resolver);
+    let directives = normalize_identifiers2::run(&mut scoped_resolver, module.directives).unwrap();
+    let directives = normalize_predicates2::run(&mut flat_resolver, directives).unwrap();
+    let directives = expand_operands::run(&mut flat_resolver, directives).unwrap();
+    let mut statistics = PassStatistics::default();
+    let directives = fold_constants::run(&mut statistics, directives).unwrap();
+    let mut directives = eliminate_dead_code::run(&mut statistics, directives).unwrap();
+    match directives.pop() {
+        Some(Directive2::Method(Function2 {
+            body: Some(body), ..
+        })) => (body, statistics),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn constant_chain_collapses() {
+    let (body, statistics) = fold_and_eliminate(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .func chain(
+            .reg .u64 out_addr
+        )
+        {
+            .reg .u32 a;
+            .reg .u32 b;
+            .reg .u32 c;
+            .reg .u32 d;
+            .reg .u32 e;
+            .reg .u32 f;
+
+            mov.u32 a, 3;
+            shl.b32 b, a, 2;
+            add.u32 c, b, 5;
+            mul.lo.u32 d, c, 3;
+            sub.u32 e, d, 1;
+            xor.b32 f, e, 7;
+            st.u32 [out_addr], f;
+            ret;
+        }",
+    );
+    let constants = body
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Constant(constant) => Some(constant),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(statistics.folded_instructions, 6);
+    assert_eq!(constants.len(), 1);
+    assert_eq!(
+        constants[0].value.as_u64(),
+        Some((((3 << 2) + 5) * 3 - 1) ^ 7)
+    );
+}
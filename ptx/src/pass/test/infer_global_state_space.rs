@@ -0,0 +1,99 @@
+use crate::pass::*;
+
+fn inferred_global_accesses(ptx: &str) -> usize {
+    // We run the minimal number of passes required to produce the input expected by
+    // infer_global_state_space
+    let module = ptx_parser::parse_module_checked(ptx).unwrap();
+    let names = NameInterner::default();
+    let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1), &names);
+    let mut scoped_resolver = ScopedResolver::new(&mut flat_resolver);
+    let directives = normalize_identifiers2::run(&mut scoped_resolver, module.directives).unwrap();
+    let directives = normalize_predicates2::run(&mut flat_resolver, directives).unwrap();
+    let directives = expand_operands::run(&mut flat_resolver, directives).unwrap();
+    let mut statistics = PassStatistics::default();
+    infer_global_state_space::run(&mut statistics, directives).unwrap();
+    statistics.inferred_global_accesses
+}
+
+#[test]
+fn kernel_argument_with_computed_offset_is_global() {
+    let accesses = inferred_global_accesses(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry offset(
+            .param .u64 input,
+            .param .u32 index_param
+        )
+        {
+            .reg .u64 in_addr;
+            .reg .u64 offset;
+            .reg .u32 index;
+            .reg .u32 temp;
+
+            ld.param.u64 in_addr, [input];
+            ld.param.u32 index, [index_param];
+            mul.wide.u32 offset, index, 4;
+            add.u64 in_addr, in_addr, offset;
+            ld.u32 temp, [in_addr];
+            add.u64 in_addr, in_addr, 4;
+            st.u32 [in_addr], temp;
+            ret;
+        }",
+    );
+    assert_eq!(accesses, 2);
+}
+
+#[test]
+fn shared_pointer_plus_kernel_argument_is_not_global() {
+    let accesses = inferred_global_accesses(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry shared_offset(
+            .param .u64 offset_param
+        )
+        {
+            .shared .align 4 .b8 smem[128];
+            .reg .u64 offset;
+            .reg .u64 shared_addr;
+            .reg .u64 addr;
+            .reg .u32 temp;
+
+            ld.param.u64 offset, [offset_param];
+            cvta.shared.u64 shared_addr, smem;
+            add.u64 addr, shared_addr, offset;
+            ld.u32 temp, [addr];
+            st.u32 [addr], temp;
+            ret;
+        }",
+    );
+    assert_eq!(accesses, 0);
+}
+
+#[test]
+fn incremented_function_argument_is_not_global() {
+    let accesses = inferred_global_accesses(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .func increment(
+            .reg .u64 addr
+        )
+        {
+            .reg .u32 temp;
+
+            add.u64 addr, addr, 4;
+            ld.u32 temp, [addr];
+            st.u32 [addr], temp;
+            ret;
+        }",
+    );
+    assert_eq!(accesses, 0);
+}
@@ -10,7 +10,8 @@ macro_rules! test_insert_implicit_conversions {
 
 fn run_insert_implicit_conversions(ptx: ptx_parser::Module) -> String {
     // We run the minimal number of passes required to produce the input expected by insert_implicit_conversions
-    let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1));
+    let names = NameInterner::default();
+    let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1), &names);
     let mut scoped_resolver = ScopedResolver::new(&mut flat_resolver);
     let directives = normalize_identifiers2::run(&mut scoped_resolver, ptx.directives).unwrap();
     let directives = normalize_predicates2::run(&mut flat_resolver, directives).unwrap();
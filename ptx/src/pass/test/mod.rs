@@ -6,7 +6,12 @@ use std::{
     path::Path,
 };
 
+mod check_operand_types;
+mod fold_constants;
+mod infer_global_state_space;
 mod insert_implicit_conversions;
+mod normalize_identifiers;
+mod replace_instructions_with_functions;
 
 #[macro_export]
 macro_rules! test_pass {
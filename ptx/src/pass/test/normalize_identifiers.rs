@@ -0,0 +1,264 @@
+use crate::pass::*;
+use ptx_parser as ast;
+
+fn normalize(ptx: &str) -> Result<Vec<NormalizedDirective2>, TranslateError> {
+    let module = ptx_parser::parse_module_checked(ptx).unwrap();
+    let names = NameInterner::default();
+    let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1), &names);
+    let mut scoped_resolver = ScopedResolver::new(&mut flat_resolver);
+    normalize_identifiers2::run(&mut scoped_resolver, module.directives)
+}
+
+fn normalize_error(ptx: &str) -> TranslateError {
+    match normalize(ptx) {
+        Ok(_) => panic!("expected an error"),
+        Err(error) => error,
+    }
+}
+
+fn method(directives: &[NormalizedDirective2], index: usize) -> &NormalizedFunction2 {
+    match &directives[index] {
+        Directive2::Method(method) => method,
+        Directive2::Variable(..) => panic!(),
+    }
+}
+
+fn movs(method: &NormalizedFunction2) -> Vec<(SpirvWord, SpirvWord)> {
+    method
+        .body
+        .as_ref()
+        .unwrap()
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Instruction((
+                _,
+                ast::Instruction::Mov {
+                    arguments:
+                        ast::MovArgs {
+                            dst: ast::ParsedOperand::Reg(dst),
+                            src: ast::ParsedOperand::Reg(src),
+                        },
+                    ..
+                },
+                _,
+            )) => Some((*dst, *src)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn variables(method: &NormalizedFunction2) -> Vec<SpirvWord> {
+    method
+        .body
+        .as_ref()
+        .unwrap()
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Variable(var) => Some(var.name),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn function_referenced_before_declaration() {
+    let directives = normalize(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry caller()
+        {
+            .reg .u64 fn_ptr;
+            mov.u64 fn_ptr, callee;
+            ret;
+        }
+
+        .func callee()
+        {
+            ret;
+        }",
+    )
+    .unwrap();
+    let callee = method(&directives, 1).name;
+    let fn_ptr = variables(method(&directives, 0))[0];
+    assert_eq!(movs(method(&directives, 0)), vec![(fn_ptr, callee)]);
+}
+
+#[test]
+fn global_referenced_before_declaration() {
+    let directives = normalize(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry reader()
+        {
+            .reg .u64 addr;
+            mov.u64 addr, foo;
+            ret;
+        }
+
+        .global .u32 foo;",
+    )
+    .unwrap();
+    let foo = match &directives[1] {
+        Directive2::Variable(_, var) => var.name,
+        Directive2::Method(..) => panic!(),
+    };
+    let addr = variables(method(&directives, 0))[0];
+    assert_eq!(movs(method(&directives, 0)), vec![(addr, foo)]);
+}
+
+#[test]
+fn register_used_before_declaration() {
+    let directives = normalize(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry late_register()
+        {
+            .reg .u32 a;
+            {
+                mov.u32 a, b;
+                .reg .u32 b;
+            }
+            ret;
+        }",
+    )
+    .unwrap();
+    let method = method(&directives, 0);
+    let variables = variables(method);
+    assert_eq!(movs(method), vec![(variables[0], variables[1])]);
+}
+
+#[test]
+fn block_declaration_shadows_outer_one() {
+    let directives = normalize(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry shadowing()
+        {
+            .reg .u32 a;
+            .reg .u32 b;
+            mov.u32 a, b;
+            {
+                mov.u32 b, a;
+                .reg .u32 a;
+            }
+            mov.u32 b, a;
+            ret;
+        }",
+    )
+    .unwrap();
+    let method = method(&directives, 0);
+    let variables = variables(method);
+    let (outer_a, outer_b, inner_a) = (variables[0], variables[1], variables[2]);
+    assert_eq!(
+        movs(method),
+        vec![(outer_a, outer_b), (outer_b, inner_a), (outer_b, outer_a)]
+    );
+}
+
+#[test]
+fn block_declaration_is_not_duplicate() {
+    let directives = normalize(include_str!("../../test/spirv_run/block.ptx")).unwrap();
+    let variables = variables(method(&directives, 0));
+    assert_eq!(variables.len(), 5);
+    assert_ne!(variables[3], variables[4]);
+}
+
+#[test]
+fn duplicate_declaration_points_to_first_one() {
+    let ptx = "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry duplicate()
+        {
+            .reg .u32 a;
+            .reg .u32 b;
+            .reg .u32 a;
+            ret;
+        }";
+    let error = normalize_error(ptx);
+    match error.inner() {
+        TranslateError::DuplicateDefinition {
+            name,
+            first_definition: Some(first_definition),
+            ..
+        } => {
+            assert_eq!(name, "a");
+            assert_eq!(first_definition.location(ptx).line, 8);
+        }
+        _ => panic!("{:?}", error),
+    }
+}
+
+#[test]
+fn misspelled_register_suggests_similar_names() {
+    let error = normalize_error(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry typo()
+        {
+            .reg .u32 counter;
+            .reg .u32 count_total;
+            add.u32 counter, countr, 1;
+            ret;
+        }",
+    );
+    match error.inner() {
+        TranslateError::UnknownIdentifier { name, similar } => {
+            assert_eq!(name, "countr");
+            assert_eq!(similar, &vec!["counter".to_string()]);
+        }
+        _ => panic!("{:?}", error),
+    }
+}
+
+#[test]
+fn parameterized_register_names_are_shared_by_modules_with_one_interner() {
+    let ptx = "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry registers()
+        {
+            .reg .b32 %r<2>;
+            mov.b32 %r1, %r0;
+            ret;
+        }";
+    let names = NameInterner::default();
+    let register_names = || {
+        let module = ptx_parser::parse_module_checked(ptx).unwrap();
+        let mut flat_resolver = GlobalStringIdentResolver2::new(SpirvWord(1), &names);
+        let mut scoped_resolver = ScopedResolver::new(&mut flat_resolver);
+        normalize_identifiers2::run(&mut scoped_resolver, module.directives).unwrap();
+        (1..flat_resolver.current_id.0)
+            .filter_map(
+                |id| match &flat_resolver.ident_map.get(&SpirvWord(id))?.name {
+                    Some(Cow::Borrowed(name)) if name.starts_with("%r") => {
+                        Some(*name as *const str)
+                    }
+                    _ => None,
+                },
+            )
+            .collect::<Vec<_>>()
+    };
+    let first = register_names();
+    assert_eq!(first.len(), 2);
+    assert_eq!(first, register_names());
+}
@@ -0,0 +1,79 @@
+use crate::pass::*;
+
+// Names of the kernels which clear the barrier arrival counters
+fn kernels_with_barrier_init(ptx: &str) -> Vec<String> {
+    let module = ptx_parser::parse_module_checked(ptx).unwrap();
+    let names = NameInterner::default();
+    let Lowered {
+        resolver,
+        directives,
+        ..
+    } = lower(module, &TranslateOptions::default(), &names).unwrap();
+    let name = |ident: SpirvWord| {
+        resolver.ident_map[&ident]
+            .name
+            .as_deref()
+            .map(str::to_string)
+    };
+    directives
+        .iter()
+        .filter_map(|directive| match directive {
+            Directive2::Method(Function2 {
+                is_kernel: true,
+                name: kernel,
+                body: Some(body),
+                ..
+            }) => body
+                .iter()
+                .any(|statement| match statement {
+                    Statement::Instruction(ast::Instruction::Call { arguments, .. }) => {
+                        name(arguments.func).as_deref() == Some("__zluda_ptx_impl_bar_sync_init")
+                    }
+                    _ => false,
+                })
+                .then(|| name(*kernel).unwrap()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn barrier_counters_are_cleared_only_by_kernels_reaching_them() {
+    let kernels = kernels_with_barrier_init(
+        "
+        .version 7.0
+        .target sm_70
+        .address_size 64
+
+        .func arrive()
+        {
+            bar.arrive 1, 64;
+            ret;
+        }
+
+        .func wrapper()
+        {
+            call arrive;
+            ret;
+        }
+
+        .visible .entry direct()
+        {
+            bar.sync 0, 32;
+            ret;
+        }
+
+        .visible .entry indirect()
+        {
+            call wrapper;
+            ret;
+        }
+
+        .visible .entry plain()
+        {
+            bar.sync 0;
+            ret;
+        }",
+    );
+    assert_eq!(kernels, ["direct", "indirect"]);
+}
@@ -0,0 +1,305 @@
+use super::*;
+use std::{
+    fmt::{self, Write as _},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+// Records what every pass of the pipeline did: how many statements it consumed and produced, how
+// many identifiers it created and how long it took. If a dump directory is set, the statements
+// after every pass are also written there as text, one file per pass:
+//   <dump_dir>/ptx_<pid>_<module>/<pass index>_<pass name>.txt
+// Dumps are a debugging aid, so failing to write one doesn't fail the translation
+
+/// Statements, identifiers and time of a single pass of the translation pipeline.
+#[derive(Debug, Clone)]
+pub struct PassTrace {
+    pub name: &'static str,
+    /// Number of statements in all function bodies before the pass
+    pub statements_before: usize,
+    /// Number of statements in all function bodies after the pass
+    pub statements_after: usize,
+    pub identifiers_created: usize,
+    pub duration: Duration,
+}
+
+static DUMPED_MODULES: AtomicUsize = AtomicUsize::new(0);
+
+pub(super) struct PassTracer {
+    dump_dir: Option<PathBuf>,
+    passes: Vec<PassTrace>,
+    statements: usize,
+    current_id: SpirvWord,
+}
+
+impl PassTracer {
+    pub(super) fn new(dump_dir: Option<&Path>, resolver: &GlobalStringIdentResolver2) -> Self {
+        let dump_dir = dump_dir.and_then(|dump_dir| {
+            let module = DUMPED_MODULES.fetch_add(1, Ordering::Relaxed);
+            let dump_dir = dump_dir.join(format!("ptx_{}_{}", std::process::id(), module));
+            fs::create_dir_all(&dump_dir).ok()?;
+            Some(dump_dir)
+        });
+        Self {
+            dump_dir,
+            passes: Vec::new(),
+            statements: 0,
+            current_id: resolver.current_id,
+        }
+    }
+
+    pub(super) fn run<
+        'input,
+        I: DisplayInstruction,
+        P: ast::Operand<Ident = SpirvWord> + fmt::Display,
+    >(
+        &mut self,
+        resolver: &mut GlobalStringIdentResolver2<'input>,
+        name: &'static str,
+        pass: impl FnOnce(
+            &mut GlobalStringIdentResolver2<'input>,
+        ) -> Result<Vec<Directive2<I, P>>, TranslateError>,
+    ) -> Result<Vec<Directive2<I, P>>, TranslateError> {
+        log::debug!("{name}: started with {} statements", self.statements);
+        let start = Instant::now();
+        let directives = pass(resolver)?;
+        self.finish(resolver, name, start, &directives);
+        Ok(directives)
+    }
+
+    // For passes which can't be run through `run`, because they don't use the flat resolver
+    pub(super) fn finish<
+        I: DisplayInstruction,
+        P: ast::Operand<Ident = SpirvWord> + fmt::Display,
+    >(
+        &mut self,
+        resolver: &GlobalStringIdentResolver2,
+        name: &'static str,
+        start: Instant,
+        directives: &[Directive2<I, P>],
+    ) {
+        let duration = start.elapsed();
+        let statements = directives
+            .iter()
+            .map(|directive| match directive {
+                Directive2::Variable(..) => 0,
+                Directive2::Method(method) => method.body.as_ref().map_or(0, Vec::len),
+            })
+            .sum();
+        self.passes.push(PassTrace {
+            name,
+            statements_before: self.statements,
+            statements_after: statements,
+            identifiers_created: (resolver.current_id.0 - self.current_id.0) as usize,
+            duration,
+        });
+        log::debug!(
+            "{name}: finished with {statements} statements, {} new identifiers, {duration:?}",
+            resolver.current_id.0 - self.current_id.0
+        );
+        self.statements = statements;
+        self.current_id = resolver.current_id;
+        if let Some(dump_dir) = &self.dump_dir {
+            let file = dump_dir.join(format!("{:02}_{}.txt", self.passes.len() - 1, name));
+            fs::write(file, directives_to_string(directives)).ok();
+        }
+    }
+
+    pub(super) fn into_passes(self) -> Vec<PassTrace> {
+        self.passes
+    }
+}
+
+fn directives_to_string<
+    I: DisplayInstruction,
+    P: ast::Operand<Ident = SpirvWord> + fmt::Display,
+>(
+    directives: &[Directive2<I, P>],
+) -> String {
+    let mut result = String::new();
+    for directive in directives {
+        match directive {
+            Directive2::Variable(linking, variable) => {
+                writeln!(result, "{} {};", linking, variable).ok();
+            }
+            Directive2::Method(method) => {
+                if !method.linkage.is_empty() {
+                    write!(result, "{} ", method.linkage).ok();
+                }
+                result.push_str(if method.is_kernel { ".entry" } else { ".func" });
+                if !method.return_arguments.is_empty() {
+                    write!(result, " ({})", join(&method.return_arguments)).ok();
+                }
+                write!(
+                    result,
+                    " {}({})",
+                    method.name,
+                    join(&method.input_arguments)
+                )
+                .ok();
+                match &method.body {
+                    Some(statements) => {
+                        result.push_str("\n{\n");
+                        for statement in statements {
+                            writeln!(result, "    {}", statement).ok();
+                        }
+                        result.push_str("}\n");
+                    }
+                    None => result.push_str(";\n"),
+                }
+            }
+        }
+    }
+    result
+}
+
+fn join(arguments: &[ast::Variable<SpirvWord>]) -> String {
+    arguments
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Instructions are stored with and without predicates, depending on the stage of the pipeline
+pub(super) trait DisplayInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+impl<T: ast::Operand<Ident = SpirvWord> + fmt::Display> DisplayInstruction for ast::Instruction<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)?;
+        let mut separator = " ";
+        ast::visit(self, &mut |operand: &T,
+                               _: Option<(&ast::Type, ast::StateSpace)>,
+                               _: bool,
+                               _: bool| {
+            write!(f, "{}{}", separator, operand)?;
+            separator = ", ";
+            Ok::<_, fmt::Error>(())
+        })?;
+        f.write_char(';')
+    }
+}
+
+impl DisplayInstruction
+    for (
+        Option<ast::PredAt<SpirvWord>>,
+        ast::Instruction<ast::ParsedOperand<SpirvWord>>,
+        ast::Span,
+    )
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (predicate, instruction, _) = self;
+        if let Some(predicate) = predicate {
+            let not = if predicate.not { "!" } else { "" };
+            write!(f, "@{}{} ", not, predicate.label)?;
+        }
+        DisplayInstruction::fmt(instruction, f)
+    }
+}
+
+impl<I: DisplayInstruction, P: ast::Operand<Ident = SpirvWord> + fmt::Display> fmt::Display
+    for Statement<I, P>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Label(label) => write!(f, "{}:", label),
+            Statement::Variable(variable) => write!(f, "{};", variable),
+            Statement::Instruction(instruction) => DisplayInstruction::fmt(instruction, f),
+            Statement::Conditional(BrachCondition {
+                predicate,
+                if_true,
+                if_false,
+            }) => write!(
+                f,
+                "zluda.conditional {}, {}, {};",
+                predicate, if_true, if_false
+            ),
+            Statement::Conversion(conversion) => {
+                write!(f, "{} {}, {};", conversion, conversion.dst, conversion.src)
+            }
+            Statement::Constant(ConstantDefinition { dst, typ, value }) => {
+                write!(f, "zluda.constant{} {}, {};", typ, dst, value)
+            }
+            Statement::RetValue(_, values) => {
+                f.write_str("zluda.ret_value")?;
+                let mut separator = " ";
+                for (value, _) in values {
+                    write!(f, "{}{}", separator, value)?;
+                    separator = ", ";
+                }
+                f.write_char(';')
+            }
+            Statement::PtrAccess(PtrAccess {
+                underlying_type,
+                state_space,
+                dst,
+                ptr_src,
+                offset_src,
+            }) => write!(
+                f,
+                "zluda.ptr_access{}{} {}, {}, {};",
+                state_space, underlying_type, dst, ptr_src, offset_src
+            ),
+            Statement::RepackVector(RepackVectorDetails {
+                is_extract,
+                typ,
+                packed,
+                unpacked,
+                ..
+            }) => {
+                let op = if *is_extract { "unpack" } else { "pack" };
+                write!(f, "zluda.{}{} {}", op, typ, packed)?;
+                for value in unpacked {
+                    write!(f, ", {}", value)?;
+                }
+                f.write_char(';')
+            }
+            Statement::FunctionPointer(FunctionPointerDetails { dst, src }) => {
+                write!(f, "zluda.function_pointer {}, {};", dst, src)
+            }
+            Statement::VectorRead(VectorRead {
+                scalar_type,
+                vector_width,
+                scalar_dst,
+                vector_src,
+                member,
+            }) => write!(
+                f,
+                "zluda.vector_read.v{}{} {}, {}, {};",
+                vector_width, scalar_type, scalar_dst, vector_src, member
+            ),
+            Statement::VectorWrite(VectorWrite {
+                scalar_type,
+                vector_width,
+                vector_dst,
+                vector_src,
+                scalar_src,
+                member,
+            }) => write!(
+                f,
+                "zluda.vector_write.v{}{} {}, {}, {}, {};",
+                vector_width, scalar_type, vector_dst, vector_src, scalar_src, member
+            ),
+            Statement::SetMode(ModeRegister::Denormal { f32, f16f64 }) => {
+                write!(f, "zluda.set_mode.denormal f32={}, f16f64={};", f32, f16f64)
+            }
+            Statement::SetMode(ModeRegister::Rounding { f32, f16f64 }) => write!(
+                f,
+                "zluda.set_mode.rounding f32={:?}, f16f64={:?};",
+                f32, f16f64
+            ),
+            Statement::FpModeRequired { ftz_f32, rnd_f32 } => write!(
+                f,
+                "zluda.fp_mode_required ftz_f32={:?}, rnd_f32={:?};",
+                ftz_f32, rnd_f32
+            ),
+            Statement::FpSaturate { dst, src, type_ } => {
+                write!(f, "zluda.fp_saturate{} {}, {};", type_, dst, src)
+            }
+        }
+    }
+}
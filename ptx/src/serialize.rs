@@ -0,0 +1,318 @@
+//! Binary format of [`Artifact`] for persistent caches.
+//!
+//! Layout, all integers little endian:
+//! * header: magic, format version (u32), pipeline version of the build (u64 length + bytes)
+//! * payload length (u64) and FNV-1a hash (u64) of the payload
+//! * payload: target, flags, module bytes, attributes bitcode and kernels
+//!
+//! Artifacts written by a different build are rejected, the translation may have changed.
+
+use crate::compiler::{Artifact, Target, PIPELINE_VERSION};
+use crate::pass::{KernelInfo, ParameterInfo};
+use ptx_parser::TuningDirective;
+
+const MAGIC: &[u8; 8] = b"ZLUDAPTX";
+// Bump on every change to the layout below
+const FORMAT_VERSION: u32 = 2;
+
+const FLAG_CORRECTLY_ROUNDED_DIV_SQRT: u8 = 1;
+
+/// Error returned by [`Artifact::from_bytes`].
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeserializeError {
+    #[error("not a serialized PTX artifact")]
+    BadMagic,
+    #[error("unsupported artifact format version {0}, expected {FORMAT_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("artifact was written by build {0}, this is build {PIPELINE_VERSION}")]
+    StaleBuild(String),
+    #[error("artifact is truncated")]
+    Truncated,
+    #[error("artifact is corrupted")]
+    Corrupted,
+}
+
+impl Artifact {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = Writer(Vec::new());
+        payload.u8(match self.target {
+            Target::LlvmBitcode => 0,
+            Target::LlvmIr => 1,
+            Target::Spirv => 2,
+        });
+        payload.u8(if self.correctly_rounded_div_sqrt {
+            FLAG_CORRECTLY_ROUNDED_DIV_SQRT
+        } else {
+            0
+        });
+        payload.bytes(&self.bytes);
+        payload.bytes(&self.attributes_bitcode);
+        payload.u32(self.kernels.len() as u32);
+        for kernel in self.kernels.iter() {
+            payload.bytes(kernel.name.as_bytes());
+            payload.u32(kernel.parameters.len() as u32);
+            for param in kernel.parameters.iter() {
+                payload.u64(param.size as u64);
+                payload.u64(param.align as u64);
+                payload.u8(param.is_pointer as u8);
+                // 0 is not a valid alignment
+                payload.u64(param.pointee_align.unwrap_or(0) as u64);
+            }
+            payload.u64(kernel.static_shared_memory as u64);
+            payload.u8(kernel.dynamic_shared_memory as u8);
+            payload.u64(kernel.local_memory as u64);
+            payload.u64(kernel.constant_memory as u64);
+            payload.u32(kernel.tuning.len() as u32);
+            for tuning in kernel.tuning.iter() {
+                let (tag, values) = match *tuning {
+                    TuningDirective::MaxNReg(x) => (0, [x, 0, 0]),
+                    TuningDirective::MaxNtid(x, y, z) => (1, [x, y, z]),
+                    TuningDirective::ReqNtid(x, y, z) => (2, [x, y, z]),
+                    TuningDirective::MinNCtaPerSm(x) => (3, [x, 0, 0]),
+                };
+                payload.u8(tag);
+                values.into_iter().for_each(|value| payload.u32(value));
+            }
+        }
+        let payload = payload.0;
+        let mut result = Writer(Vec::with_capacity(payload.len() + 64));
+        result.0.extend_from_slice(MAGIC);
+        result.u32(FORMAT_VERSION);
+        result.bytes(PIPELINE_VERSION.as_bytes());
+        result.u64(payload.len() as u64);
+        result.u64(fnv1a(&payload));
+        result.0.extend_from_slice(&payload);
+        result.0
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DeserializeError> {
+        let mut reader = Reader(data);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(DeserializeError::BadMagic);
+        }
+        let version = reader.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(DeserializeError::UnsupportedVersion(version));
+        }
+        let git_hash = reader.bytes()?;
+        if git_hash != PIPELINE_VERSION.as_bytes() {
+            return Err(DeserializeError::StaleBuild(
+                String::from_utf8_lossy(git_hash).into_owned(),
+            ));
+        }
+        let payload_len = reader.len64()?;
+        let hash = reader.u64()?;
+        let payload = reader.take(payload_len)?;
+        if !reader.0.is_empty() || fnv1a(payload) != hash {
+            return Err(DeserializeError::Corrupted);
+        }
+        let mut reader = Reader(payload);
+        let target = match reader.u8()? {
+            0 => Target::LlvmBitcode,
+            1 => Target::LlvmIr,
+            2 => Target::Spirv,
+            _ => return Err(DeserializeError::Corrupted),
+        };
+        let flags = reader.u8()?;
+        let bytes = reader.bytes()?.to_vec();
+        let attributes_bitcode = reader.bytes()?.to_vec();
+        let kernels = (0..reader.u32()?)
+            .map(|_| read_kernel(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !reader.0.is_empty() {
+            return Err(DeserializeError::Corrupted);
+        }
+        Ok(Artifact {
+            target,
+            bytes,
+            attributes_bitcode,
+            correctly_rounded_div_sqrt: flags & FLAG_CORRECTLY_ROUNDED_DIV_SQRT != 0,
+            kernels,
+        })
+    }
+}
+
+fn read_kernel(reader: &mut Reader) -> Result<KernelInfo, DeserializeError> {
+    let name =
+        String::from_utf8(reader.bytes()?.to_vec()).map_err(|_| DeserializeError::Corrupted)?;
+    let parameters = (0..reader.u32()?)
+        .map(|_| {
+            Ok(ParameterInfo {
+                size: reader.len64()?,
+                align: reader.len64()?,
+                is_pointer: reader.bool()?,
+                pointee_align: Some(reader.len64()?).filter(|align| *align != 0),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let static_shared_memory = reader.len64()?;
+    let dynamic_shared_memory = reader.bool()?;
+    let local_memory = reader.len64()?;
+    let constant_memory = reader.len64()?;
+    let tuning = (0..reader.u32()?)
+        .map(|_| {
+            let tag = reader.u8()?;
+            let (x, y, z) = (reader.u32()?, reader.u32()?, reader.u32()?);
+            Ok(match tag {
+                0 => TuningDirective::MaxNReg(x),
+                1 => TuningDirective::MaxNtid(x, y, z),
+                2 => TuningDirective::ReqNtid(x, y, z),
+                3 => TuningDirective::MinNCtaPerSm(x),
+                _ => return Err(DeserializeError::Corrupted),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(KernelInfo {
+        name,
+        parameters,
+        static_shared_memory,
+        dynamic_shared_memory,
+        local_memory,
+        constant_memory,
+        tuning,
+    })
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.u64(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DeserializeError> {
+        if self.0.len() < len {
+            return Err(DeserializeError::Truncated);
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, DeserializeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, DeserializeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DeserializeError::Corrupted),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32, DeserializeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, DeserializeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn len64(&mut self) -> Result<usize, DeserializeError> {
+        usize::try_from(self.u64()?).map_err(|_| DeserializeError::Corrupted)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], DeserializeError> {
+        let len = self.len64()?;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Compiler;
+
+    fn artifact() -> Artifact {
+        Compiler::new(Target::LlvmBitcode)
+            .compile(include_str!("test/spirv_run/add_tuning.ptx"))
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let artifact = artifact();
+        assert_eq!(Artifact::from_bytes(&artifact.to_bytes()), Ok(artifact));
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        let bytes = artifact().to_bytes();
+        // Every prefix, including ones cut inside the header
+        for len in 0..bytes.len() {
+            assert!(
+                Artifact::from_bytes(&bytes[..len]).is_err(),
+                "prefix of length {len} was accepted"
+            );
+        }
+        let header_len = MAGIC.len() + 4 + 8 + PIPELINE_VERSION.len() + 16;
+        assert_eq!(
+            Artifact::from_bytes(&bytes[..header_len + 1]),
+            Err(DeserializeError::Truncated)
+        );
+    }
+
+    #[test]
+    fn corrupted_payload_is_rejected() {
+        let mut bytes = artifact().to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        assert_eq!(
+            Artifact::from_bytes(&bytes),
+            Err(DeserializeError::Corrupted)
+        );
+        bytes.push(0);
+        assert_eq!(
+            Artifact::from_bytes(&bytes),
+            Err(DeserializeError::Corrupted)
+        );
+    }
+
+    #[test]
+    fn header_is_validated() {
+        let mut bytes = artifact().to_bytes();
+        bytes[0] = b'X';
+        assert_eq!(
+            Artifact::from_bytes(&bytes),
+            Err(DeserializeError::BadMagic)
+        );
+        bytes[0] = MAGIC[0];
+        bytes[MAGIC.len()] = 0xff;
+        assert!(matches!(
+            Artifact::from_bytes(&bytes),
+            Err(DeserializeError::UnsupportedVersion(_))
+        ));
+        bytes[MAGIC.len()] = FORMAT_VERSION as u8;
+        let hash_start = MAGIC.len() + 4 + 8;
+        bytes[hash_start] ^= 0x01;
+        assert!(matches!(
+            Artifact::from_bytes(&bytes),
+            Err(DeserializeError::StaleBuild(_))
+        ));
+    }
+}
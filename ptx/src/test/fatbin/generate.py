@@ -0,0 +1,125 @@
+# Generates the fatbin fixtures from spirv_run/add.ptx. Real fatbins come from
+# `nvcc -fatbin -gencode ...`, these follow the same layout, but are written by hand so that
+# they are small and do not need the CUDA toolkit. Compression uses literal-only LZ4 blocks
+# and raw zstd blocks, which are valid inputs for both decompressors.
+# Run from this directory: python3 generate.py
+import struct
+
+FATBIN_MAGIC = 0xBA55ED50
+KIND_PTX = 1
+KIND_ELF = 2
+FLAG_64BIT = 0x1
+FLAG_LINUX = 0x10
+FLAG_LZ4 = 0x2000
+FLAG_ZSTD = 0x8000
+
+with open("../spirv_run/add.ptx", "rb") as f:
+    ADD = f.read()
+# Stand-in for a SASS cubin, contents are never inspected
+SASS = b"\x7fELF" + bytes(28)
+
+
+def pad8(data):
+    return data + bytes(-len(data) % 8)
+
+
+def lz4(data):
+    # Single sequence made only of literals
+    length = len(data)
+    out = bytearray([min(length, 15) << 4])
+    if length >= 15:
+        rest = length - 15
+        while rest >= 255:
+            out.append(255)
+            rest -= 255
+        out.append(rest)
+    return bytes(out) + data
+
+
+def zstd(data):
+    assert 256 <= len(data) < 65536 + 256
+    # Single segment, 2 byte frame content size, one raw last block
+    header = struct.pack("<IBH", 0xFD2FB528, 0x60, len(data) - 256)
+    block = struct.pack("<I", (len(data) << 3) | 1)[:3]
+    return header + block + data
+
+
+def file_entry(kind, sm_version, contents, flags=0, compress=None):
+    flags |= FLAG_64BIT | FLAG_LINUX
+    payload = contents
+    if compress == "lz4":
+        payload, flags = lz4(contents), flags | FLAG_LZ4
+    elif compress == "zstd":
+        payload, flags = zstd(contents), flags | FLAG_ZSTD
+    # PTX headers carry 8 more bytes describing ptxas options
+    header_size = 72 if kind == KIND_PTX else 64
+    padded = pad8(payload)
+    header = struct.pack(
+        "<HHIIIIIIIIIQQQ",
+        kind,
+        0x101,
+        header_size,
+        len(padded),
+        0,
+        len(payload),
+        0,
+        0,
+        sm_version,
+        64,
+        0,
+        flags,
+        0,
+        len(contents),
+    )
+    header += bytes(header_size - len(header))
+    return header + padded
+
+
+def fatbin(*entries):
+    files = b"".join(entries)
+    return struct.pack("<IHHQ", FATBIN_MAGIC, 1, 16, len(files)) + files
+
+
+def elf_with_section(name, contents):
+    shstrtab = b"\0" + name + b"\0.shstrtab\0"
+    data_offset = 64
+    shstrtab_offset = data_offset + len(pad8(contents))
+    shoff = shstrtab_offset + len(pad8(shstrtab))
+    ident = b"\x7fELF" + bytes([2, 1, 1, 0]) + bytes(8)
+    header = ident + struct.pack(
+        "<HHIQQQIHHHHHH", 1, 62, 1, 0, 0, shoff, 0, 64, 0, 0, 64, 3, 2
+    )
+
+    def section(name_offset, type_, flags, offset, size, align):
+        return struct.pack(
+            "<IIQQQQIIQQ", name_offset, type_, flags, 0, offset, size, 0, 0, align, 0
+        )
+
+    sections = (
+        bytes(64)
+        + section(1, 1, 2, data_offset, len(contents), 8)
+        + section(len(name) + 2, 3, 0, shstrtab_offset, len(shstrtab), 1)
+    )
+    return header + pad8(contents) + pad8(shstrtab) + sections
+
+
+def write(name, data):
+    with open(name, "wb") as f:
+        f.write(data)
+
+
+add = fatbin(file_entry(KIND_ELF, 52, SASS), file_entry(KIND_PTX, 52, ADD))
+write("add.fatbin", add)
+write("add_lz4.fatbin", fatbin(file_entry(KIND_PTX, 52, ADD, compress="lz4")))
+write("add_zstd.fatbin", fatbin(file_entry(KIND_PTX, 52, ADD, compress="zstd")))
+write(
+    "multi_arch.fatbin",
+    fatbin(
+        file_entry(KIND_ELF, 52, SASS),
+        file_entry(KIND_PTX, 52, ADD.replace(b".target sm_30", b".target sm_52")),
+        file_entry(KIND_PTX, 80, ADD.replace(b".target sm_30", b".target sm_80")),
+        file_entry(KIND_ELF, 80, SASS),
+    ),
+)
+write("sass_only.fatbin", fatbin(file_entry(KIND_ELF, 80, SASS)))
+write("add_nv_fatbin.o", elf_with_section(b".nv_fatbin", add))
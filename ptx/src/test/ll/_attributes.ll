@@ -1 +1,8 @@
-@__ZLUDA_PTX_IMPL_ATTRIBUTE_CLOCK_RATE = hidden addrspace(4) constant i32 2124000
\ No newline at end of file
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
+@__ZLUDA_PTX_IMPL_ATTRIBUTE_CLOCK_RATE = hidden addrspace(4) constant i32 2124000
+
+!llvm.module.flags = !{!0}
+
+!0 = !{i32 1, !"amdhsa_code_object_version", i32 500}
\ No newline at end of file
@@ -1,3 +1,6 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 declare hidden i1 @__zluda_ptx_impl_bar_red_and_pred(i32, i1, i1) #0
 
 declare hidden i1 @__zluda_ptx_impl_bar_red_or_pred(i32, i1, i1) #0
@@ -9,8 +12,8 @@ define amdgpu_kernel void @bar_red_and_pred(ptr addrspace(4) byref(i64) %"73", p
   %"76" = alloca i64, align 8, addrspace(5)
   %"77" = alloca i32, align 4, addrspace(5)
   %"78" = alloca i32, align 4, addrspace(5)
-  %"79" = alloca i1, align 1, addrspace(5)
-  %"80" = alloca i1, align 1, addrspace(5)
+  %"79" = alloca i8, align 1, addrspace(5)
+  %"80" = alloca i8, align 1, addrspace(5)
   %"81" = alloca i32, align 4, addrspace(5)
   br label %1
 
@@ -30,12 +33,16 @@ define amdgpu_kernel void @bar_red_and_pred(ptr addrspace(4) byref(i64) %"73", p
   store i32 %"84", ptr addrspace(5) %"78", align 4
   %"87" = load i32, ptr addrspace(5) %"78", align 4
   %2 = icmp eq i32 %"87", 0
-  store i1 %2, ptr addrspace(5) %"80", align 1
+  %3 = zext i1 %2 to i8
+  store i8 %3, ptr addrspace(5) %"80", align 1
   store i32 0, ptr addrspace(5) %"81", align 4
-  %"90" = load i1, ptr addrspace(5) %"80", align 1
+  %4 = load i8, ptr addrspace(5) %"80", align 1
+  %"90" = trunc i8 %4 to i1
   %"89" = call i1 @__zluda_ptx_impl_bar_red_and_pred(i32 1, i1 %"90", i1 false)
-  store i1 %"89", ptr addrspace(5) %"79", align 1
-  %"91" = load i1, ptr addrspace(5) %"79", align 1
+  %5 = zext i1 %"89" to i8
+  store i8 %5, ptr addrspace(5) %"79", align 1
+  %6 = load i8, ptr addrspace(5) %"79", align 1
+  %"91" = trunc i8 %6 to i1
   br i1 %"91", label %"17", label %"18"
 
 "17":                                             ; preds = %"71"
@@ -45,10 +52,13 @@ define amdgpu_kernel void @bar_red_and_pred(ptr addrspace(4) byref(i64) %"73", p
   br label %"18"
 
 "18":                                             ; preds = %"17", %"71"
-  %"95" = load i1, ptr addrspace(5) %"80", align 1
+  %7 = load i8, ptr addrspace(5) %"80", align 1
+  %"95" = trunc i8 %7 to i1
   %"94" = call i1 @__zluda_ptx_impl_bar_red_or_pred(i32 1, i1 %"95", i1 false)
-  store i1 %"94", ptr addrspace(5) %"79", align 1
-  %"96" = load i1, ptr addrspace(5) %"79", align 1
+  %8 = zext i1 %"94" to i8
+  store i8 %8, ptr addrspace(5) %"79", align 1
+  %9 = load i8, ptr addrspace(5) %"79", align 1
+  %"96" = trunc i8 %9 to i1
   br i1 %"96", label %"19", label %"20"
 
 "19":                                             ; preds = %"18"
@@ -58,11 +68,14 @@ define amdgpu_kernel void @bar_red_and_pred(ptr addrspace(4) byref(i64) %"73", p
   br label %"20"
 
 "20":                                             ; preds = %"19", %"18"
-  store i1 true, ptr addrspace(5) %"80", align 1
-  %"101" = load i1, ptr addrspace(5) %"80", align 1
+  store i8 1, ptr addrspace(5) %"80", align 1
+  %10 = load i8, ptr addrspace(5) %"80", align 1
+  %"101" = trunc i8 %10 to i1
   %"100" = call i1 @__zluda_ptx_impl_bar_red_and_pred(i32 1, i1 %"101", i1 false)
-  store i1 %"100", ptr addrspace(5) %"79", align 1
-  %"102" = load i1, ptr addrspace(5) %"79", align 1
+  %11 = zext i1 %"100" to i8
+  store i8 %11, ptr addrspace(5) %"79", align 1
+  %12 = load i8, ptr addrspace(5) %"79", align 1
+  %"102" = trunc i8 %12 to i1
   br i1 %"102", label %"21", label %"22"
 
 "21":                                             ; preds = %"20"
@@ -72,11 +85,14 @@ define amdgpu_kernel void @bar_red_and_pred(ptr addrspace(4) byref(i64) %"73", p
   br label %"22"
 
 "22":                                             ; preds = %"21", %"20"
-  store i1 false, ptr addrspace(5) %"80", align 1
-  %"107" = load i1, ptr addrspace(5) %"80", align 1
+  store i8 0, ptr addrspace(5) %"80", align 1
+  %13 = load i8, ptr addrspace(5) %"80", align 1
+  %"107" = trunc i8 %13 to i1
   %"106" = call i1 @__zluda_ptx_impl_bar_red_or_pred(i32 1, i1 %"107", i1 false)
-  store i1 %"106", ptr addrspace(5) %"79", align 1
-  %"108" = load i1, ptr addrspace(5) %"79", align 1
+  %14 = zext i1 %"106" to i8
+  store i8 %14, ptr addrspace(5) %"79", align 1
+  %15 = load i8, ptr addrspace(5) %"79", align 1
+  %"108" = trunc i8 %15 to i1
   br i1 %"108", label %"23", label %"24"
 
 "23":                                             ; preds = %"22"
@@ -86,11 +102,14 @@ define amdgpu_kernel void @bar_red_and_pred(ptr addrspace(4) byref(i64) %"73", p
   br label %"24"
 
 "24":                                             ; preds = %"23", %"22"
-  store i1 true, ptr addrspace(5) %"80", align 1
-  %"113" = load i1, ptr addrspace(5) %"80", align 1
+  store i8 1, ptr addrspace(5) %"80", align 1
+  %16 = load i8, ptr addrspace(5) %"80", align 1
+  %"113" = trunc i8 %16 to i1
   %"112" = call i1 @__zluda_ptx_impl_bar_red_and_pred(i32 1, i1 %"113", i1 true)
-  store i1 %"112", ptr addrspace(5) %"79", align 1
-  %"114" = load i1, ptr addrspace(5) %"79", align 1
+  %17 = zext i1 %"112" to i8
+  store i8 %17, ptr addrspace(5) %"79", align 1
+  %18 = load i8, ptr addrspace(5) %"79", align 1
+  %"114" = trunc i8 %18 to i1
   br i1 %"114", label %"25", label %"26"
 
 "25":                                             ; preds = %"24"
@@ -118,4 +137,4 @@ define amdgpu_kernel void @bar_red_and_pred(ptr addrspace(4) byref(i64) %"73", p
 }
 
 attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="dynamic" "denormal-fp-math-f32"="dynamic" "no-trapping-math"="true" "uniform-work-group-size"="true" }
-attributes #1 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #1 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
@@ -1,3 +1,6 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 declare i32 @__zluda_ptx_impl_sreg_tid(i8) #0
 
 declare i32 @__zluda_ptx_impl_sreg_ntid(i8) #0
@@ -18,7 +21,7 @@ define amdgpu_kernel void @bench(ptr addrspace(4) byref(i64) %"55", ptr addrspac
   %"61" = alloca float, align 4, addrspace(5)
   %"62" = alloca float, align 4, addrspace(5)
   %"63" = alloca i32, align 4, addrspace(5)
-  %"64" = alloca i1, align 1, addrspace(5)
+  %"64" = alloca i8, align 1, addrspace(5)
   br label %1
 
 1:                                                ; preds = %0
@@ -67,8 +70,10 @@ define amdgpu_kernel void @bench(ptr addrspace(4) byref(i64) %"55", ptr addrspac
   store i32 %"82", ptr addrspace(5) %"63", align 4
   %"85" = load i32, ptr addrspace(5) %"63", align 4
   %"84" = icmp eq i32 %"85", 100000000
-  store i1 %"84", ptr addrspace(5) %"64", align 1
-  %"86" = load i1, ptr addrspace(5) %"64", align 1
+  %2 = zext i1 %"84" to i8
+  store i8 %2, ptr addrspace(5) %"64", align 1
+  %3 = load i8, ptr addrspace(5) %"64", align 1
+  %"86" = trunc i8 %3 to i1
   br i1 %"86", label %"11", label %"21"
 
 "21":                                             ; preds = %"10"
@@ -88,4 +93,4 @@ define amdgpu_kernel void @bench(ptr addrspace(4) byref(i64) %"55", ptr addrspac
 }
 
 attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="dynamic" "denormal-fp-math-f32"="dynamic" "no-trapping-math"="true" "uniform-work-group-size"="true" }
-attributes #1 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #1 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
@@ -1,6 +1,8 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 define hidden i64 @incr(i64 %"43") #0 {
   %"63" = alloca i64, align 8, addrspace(5)
-  %"64" = alloca i64, align 8, addrspace(5)
   %"65" = alloca i64, align 8, addrspace(5)
   %"66" = alloca i64, align 8, addrspace(5)
   br label %1
@@ -16,9 +18,7 @@ define hidden i64 @incr(i64 %"43") #0 {
   %"68" = add i64 %"69", 1
   store i64 %"68", ptr addrspace(5) %"66", align 8
   %"70" = load i64, ptr addrspace(5) %"66", align 8
-  store i64 %"70", ptr addrspace(5) %"64", align 8
-  %"71" = load i64, ptr addrspace(5) %"64", align 8
-  store i64 %"71", ptr addrspace(5) %"63", align 8
+  store i64 %"70", ptr addrspace(5) %"63", align 8
   %2 = load i64, ptr addrspace(5) %"63", align 8
   ret i64 %2
 }
@@ -47,10 +47,10 @@ define amdgpu_kernel void @call(ptr addrspace(4) byref(i64) %"48", ptr addrspace
   store i64 %"59", ptr addrspace(5) %"57", align 8
   %"40" = load i64, ptr addrspace(5) %"57", align 8
   %"41" = call i64 @incr(i64 %"40")
+  store i64 %"41", ptr addrspace(5) %"58", align 8
   br label %"45"
 
 "45":                                             ; preds = %"44"
-  store i64 %"41", ptr addrspace(5) %"58", align 8
   %"60" = load i64, ptr addrspace(5) %"58", align 8
   store i64 %"60", ptr addrspace(5) %"52", align 8
   %"61" = load i64, ptr addrspace(5) %"51", align 8
@@ -61,4 +61,4 @@ define amdgpu_kernel void @call(ptr addrspace(4) byref(i64) %"48", ptr addrspace
 }
 
 attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="dynamic" "denormal-fp-math-f32"="dynamic" "no-trapping-math"="true" "uniform-work-group-size"="true" }
-attributes #1 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #1 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
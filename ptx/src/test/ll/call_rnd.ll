@@ -1,6 +1,8 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 define hidden float @add_rm(float %"79", float %"80") #0 {
   %"128" = alloca float, align 4, addrspace(5)
-  %"129" = alloca float, align 4, addrspace(5)
   %"130" = alloca float, align 4, addrspace(5)
   %"131" = alloca float, align 4, addrspace(5)
   %"132" = alloca float, align 4, addrspace(5)
@@ -26,16 +28,13 @@ define hidden float @add_rm(float %"79", float %"80") #0 {
   %"136" = fadd float %"137", %"138"
   store float %"136", ptr addrspace(5) %"132", align 4
   %"139" = load float, ptr addrspace(5) %"132", align 4
-  store float %"139", ptr addrspace(5) %"129", align 4
-  %"140" = load float, ptr addrspace(5) %"129", align 4
-  store float %"140", ptr addrspace(5) %"128", align 4
+  store float %"139", ptr addrspace(5) %"128", align 4
   %2 = load float, ptr addrspace(5) %"128", align 4
   ret float %2
 }
 
 define hidden float @add_rp(float %"82", float %"83") #0 {
   %"141" = alloca float, align 4, addrspace(5)
-  %"142" = alloca float, align 4, addrspace(5)
   %"143" = alloca float, align 4, addrspace(5)
   %"144" = alloca float, align 4, addrspace(5)
   %"145" = alloca float, align 4, addrspace(5)
@@ -57,9 +56,7 @@ define hidden float @add_rp(float %"82", float %"83") #0 {
   %"149" = fadd float %"150", %"151"
   store float %"149", ptr addrspace(5) %"145", align 4
   %"152" = load float, ptr addrspace(5) %"145", align 4
-  store float %"152", ptr addrspace(5) %"142", align 4
-  %"153" = load float, ptr addrspace(5) %"142", align 4
-  store float %"153", ptr addrspace(5) %"141", align 4
+  store float %"152", ptr addrspace(5) %"141", align 4
   %2 = load float, ptr addrspace(5) %"141", align 4
   ret float %2
 }
@@ -116,10 +113,10 @@ define amdgpu_kernel void @call_rnd(ptr addrspace(4) byref(i64) %"92", ptr addrs
   %"72" = load float, ptr addrspace(5) %"102", align 4
   %"73" = load float, ptr addrspace(5) %"103", align 4
   %"74" = call float @add_rp(float %"72", float %"73")
+  store float %"74", ptr addrspace(5) %"104", align 4
   br label %"85"
 
 "85":                                             ; preds = %"84"
-  store float %"74", ptr addrspace(5) %"104", align 4
   %"120" = load float, ptr addrspace(5) %"104", align 4
   store float %"120", ptr addrspace(5) %"100", align 4
   %"121" = load i64, ptr addrspace(5) %"95", align 8
@@ -133,10 +130,10 @@ define amdgpu_kernel void @call_rnd(ptr addrspace(4) byref(i64) %"92", ptr addrs
   %"75" = load float, ptr addrspace(5) %"105", align 4
   %"76" = load float, ptr addrspace(5) %"106", align 4
   %"77" = call float @add_rm(float %"75", float %"76")
+  store float %"77", ptr addrspace(5) %"107", align 4
   br label %"86"
 
 "86":                                             ; preds = %"85"
-  store float %"77", ptr addrspace(5) %"107", align 4
   %"125" = load float, ptr addrspace(5) %"107", align 4
   store float %"125", ptr addrspace(5) %"101", align 4
   %"126" = load i64, ptr addrspace(5) %"95", align 8
@@ -151,5 +148,5 @@ define amdgpu_kernel void @call_rnd(ptr addrspace(4) byref(i64) %"92", ptr addrs
 declare void @llvm.amdgcn.s.setreg(i32 immarg, i32) #2
 
 attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="dynamic" "denormal-fp-math-f32"="dynamic" "no-trapping-math"="true" "uniform-work-group-size"="true" }
-attributes #1 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="ieee" "no-trapping-math"="true" "uniform-work-group-size"="true" }
+attributes #1 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="ieee" "no-trapping-math"="true" "uniform-work-group-size"="true" }
 attributes #2 = { nocallback nofree nosync nounwind willreturn }
\ No newline at end of file
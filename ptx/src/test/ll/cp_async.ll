@@ -1,5 +1,8 @@
-@from = addrspace(1) global [4 x i32] [i32 1, i32 2, i32 3, i32 4]
-@to = external addrspace(3) global [4 x i32]
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
+@from = addrspace(1) global [4 x i32] [i32 1, i32 2, i32 3, i32 4], align 16
+@to = external addrspace(3) global [4 x i32], align 16
 
 define amdgpu_kernel void @cp_async(ptr addrspace(4) byref(i64) %"48", ptr addrspace(4) byref(i64) %"49") #0 {
   %"50" = alloca i64, align 8, addrspace(5)
@@ -18,9 +21,10 @@ define amdgpu_kernel void @cp_async(ptr addrspace(4) byref(i64) %"48", ptr addrs
   store i64 %"56", ptr addrspace(5) %"50", align 8
   %"57" = load i64, ptr addrspace(4) %"49", align 8
   store i64 %"57", ptr addrspace(5) %"51", align 8
-  %2 = load i96, ptr addrspace(1) @from, align 128
+  %2 = load i96, ptr addrspace(1) @from, align 16
   %3 = zext i96 %2 to i128
-  store i128 %3, ptr addrspace(3) @to, align 4
+  store i128 %3, ptr addrspace(3) @to, align 16
+  fence syncscope("workgroup") seq_cst
   %"58" = load i32, ptr addrspacecast (ptr addrspace(3) @to to ptr), align 4
   store i32 %"58", ptr addrspace(5) %"52", align 4
   %"59" = load i32, ptr getelementptr inbounds (i8, ptr addrspacecast (ptr addrspace(3) @to to ptr), i64 4), align 4
@@ -51,4 +55,4 @@ define amdgpu_kernel void @cp_async(ptr addrspace(4) byref(i64) %"48", ptr addrs
   ret void
 }
 
-attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #0 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
@@ -1,3 +1,6 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 define amdgpu_kernel void @cvta(ptr addrspace(4) byref(i64) %"30", ptr addrspace(4) byref(i64) %"31") #0 {
   %"32" = alloca i64, align 8, addrspace(5)
   %"33" = alloca i64, align 8, addrspace(5)
@@ -14,12 +17,14 @@ define amdgpu_kernel void @cvta(ptr addrspace(4) byref(i64) %"30", ptr addrspace
   store i64 %"36", ptr addrspace(5) %"33", align 8
   %"38" = load i64, ptr addrspace(5) %"32", align 8
   %2 = inttoptr i64 %"38" to ptr
-  %"45" = addrspacecast ptr %2 to ptr addrspace(1)
-  store ptr addrspace(1) %"45", ptr addrspace(5) %"32", align 8
+  %3 = addrspacecast ptr %2 to ptr addrspace(1)
+  %"45" = ptrtoint ptr addrspace(1) %3 to i64
+  store i64 %"45", ptr addrspace(5) %"32", align 8
   %"40" = load i64, ptr addrspace(5) %"33", align 8
-  %3 = inttoptr i64 %"40" to ptr
-  %"47" = addrspacecast ptr %3 to ptr addrspace(1)
-  store ptr addrspace(1) %"47", ptr addrspace(5) %"33", align 8
+  %4 = inttoptr i64 %"40" to ptr
+  %5 = addrspacecast ptr %4 to ptr addrspace(1)
+  %"47" = ptrtoint ptr addrspace(1) %5 to i64
+  store i64 %"47", ptr addrspace(5) %"33", align 8
   %"42" = load i64, ptr addrspace(5) %"32", align 8
   %"49" = inttoptr i64 %"42" to ptr addrspace(1)
   %"41" = load float, ptr addrspace(1) %"49", align 4
@@ -31,4 +36,4 @@ define amdgpu_kernel void @cvta(ptr addrspace(4) byref(i64) %"30", ptr addrspace
   ret void
 }
 
-attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #0 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
@@ -1,8 +1,11 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 %struct.i32.i1 = type { i32, i1 }
 
 define hidden %struct.i32.i1 @do_something(i32 %"10") #0 {
   %"46" = alloca i32, align 4, addrspace(5)
-  %"47" = alloca i1, align 1, addrspace(5)
+  %"47" = alloca i8, align 1, addrspace(5)
   br label %1
 
 1:                                                ; preds = %0
@@ -11,12 +14,13 @@ define hidden %struct.i32.i1 @do_something(i32 %"10") #0 {
 "43":                                             ; preds = %1
   %"48" = add i32 %"10", 1
   store i32 %"48", ptr addrspace(5) %"46", align 4
-  store i1 true, ptr addrspace(5) %"47", align 1
+  store i8 1, ptr addrspace(5) %"47", align 1
   %2 = load i32, ptr addrspace(5) %"46", align 4
-  %3 = load i1, ptr addrspace(5) %"47", align 1
-  %4 = insertvalue %struct.i32.i1 undef, i32 %2, 0
-  %5 = insertvalue %struct.i32.i1 %4, i1 %3, 1
-  ret %struct.i32.i1 %5
+  %3 = load i8, ptr addrspace(5) %"47", align 1
+  %4 = trunc i8 %3 to i1
+  %5 = insertvalue %struct.i32.i1 undef, i32 %2, 0
+  %6 = insertvalue %struct.i32.i1 %5, i1 %4, 1
+  ret %struct.i32.i1 %6
 }
 
 define amdgpu_kernel void @multiple_return(ptr addrspace(4) byref(i64) %"50", ptr addrspace(4) byref(i64) %"51") #1 {
@@ -24,7 +28,7 @@ define amdgpu_kernel void @multiple_return(ptr addrspace(4) byref(i64) %"50", pt
   %"53" = alloca i64, align 8, addrspace(5)
   %"54" = alloca i32, align 4, addrspace(5)
   %"55" = alloca i32, align 4, addrspace(5)
-  %"56" = alloca i1, align 1, addrspace(5)
+  %"56" = alloca i8, align 1, addrspace(5)
   br label %1
 
 1:                                                ; preds = %0
@@ -44,7 +48,8 @@ define amdgpu_kernel void @multiple_return(ptr addrspace(4) byref(i64) %"50", pt
   %"61" = extractvalue %struct.i32.i1 %2, 0
   %"62" = extractvalue %struct.i32.i1 %2, 1
   store i32 %"61", ptr addrspace(5) %"55", align 4
-  store i1 %"62", ptr addrspace(5) %"56", align 1
+  %3 = zext i1 %"62" to i8
+  store i8 %3, ptr addrspace(5) %"56", align 1
   br label %"45"
 
 "45":                                             ; preds = %"44"
@@ -52,7 +57,8 @@ define amdgpu_kernel void @multiple_return(ptr addrspace(4) byref(i64) %"50", pt
   %"65" = load i32, ptr addrspace(5) %"55", align 4
   %"69" = inttoptr i64 %"64" to ptr
   store i32 %"65", ptr %"69", align 4
-  %"66" = load i1, ptr addrspace(5) %"56", align 1
+  %4 = load i8, ptr addrspace(5) %"56", align 1
+  %"66" = trunc i8 %4 to i1
   br i1 %"66", label %"19", label %"20"
 
 "19":                                             ; preds = %"45"
@@ -67,4 +73,4 @@ define amdgpu_kernel void @multiple_return(ptr addrspace(4) byref(i64) %"50", pt
 }
 
 attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="dynamic" "denormal-fp-math-f32"="dynamic" "no-trapping-math"="true" "uniform-work-group-size"="true" }
-attributes #1 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #1 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
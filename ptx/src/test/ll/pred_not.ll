@@ -1,10 +1,13 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 define amdgpu_kernel void @pred_not(ptr addrspace(4) byref(i64) %"41", ptr addrspace(4) byref(i64) %"42") #0 {
   %"43" = alloca i64, align 8, addrspace(5)
   %"44" = alloca i64, align 8, addrspace(5)
   %"45" = alloca i64, align 8, addrspace(5)
   %"46" = alloca i64, align 8, addrspace(5)
   %"47" = alloca i64, align 8, addrspace(5)
-  %"48" = alloca i1, align 1, addrspace(5)
+  %"48" = alloca i8, align 1, addrspace(5)
   br label %1
 
 1:                                                ; preds = %0
@@ -27,11 +30,15 @@ define amdgpu_kernel void @pred_not(ptr addrspace(4) byref(i64) %"41", ptr addrs
   %"56" = load i64, ptr addrspace(5) %"45", align 8
   %"57" = load i64, ptr addrspace(5) %"46", align 8
   %2 = icmp ult i64 %"56", %"57"
-  store i1 %2, ptr addrspace(5) %"48", align 1
-  %"59" = load i1, ptr addrspace(5) %"48", align 1
+  %3 = zext i1 %2 to i8
+  store i8 %3, ptr addrspace(5) %"48", align 1
+  %4 = load i8, ptr addrspace(5) %"48", align 1
+  %"59" = trunc i8 %4 to i1
   %"58" = xor i1 %"59", true
-  store i1 %"58", ptr addrspace(5) %"48", align 1
-  %"60" = load i1, ptr addrspace(5) %"48", align 1
+  %5 = zext i1 %"58" to i8
+  store i8 %5, ptr addrspace(5) %"48", align 1
+  %6 = load i8, ptr addrspace(5) %"48", align 1
+  %"60" = trunc i8 %6 to i1
   br i1 %"60", label %"16", label %"17"
 
 "16":                                             ; preds = %"40"
@@ -39,7 +46,8 @@ define amdgpu_kernel void @pred_not(ptr addrspace(4) byref(i64) %"41", ptr addrs
   br label %"17"
 
 "17":                                             ; preds = %"16", %"40"
-  %"62" = load i1, ptr addrspace(5) %"48", align 1
+  %7 = load i8, ptr addrspace(5) %"48", align 1
+  %"62" = trunc i8 %7 to i1
   br i1 %"62", label %"19", label %"18"
 
 "18":                                             ; preds = %"17"
@@ -54,4 +62,4 @@ define amdgpu_kernel void @pred_not(ptr addrspace(4) byref(i64) %"41", ptr addrs
   ret void
 }
 
-attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #0 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
@@ -1,10 +1,13 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 define amdgpu_kernel void @setp_gt(ptr addrspace(4) byref(i64) %"39", ptr addrspace(4) byref(i64) %"40") #0 {
   %"41" = alloca i64, align 8, addrspace(5)
   %"42" = alloca i64, align 8, addrspace(5)
   %"43" = alloca float, align 4, addrspace(5)
   %"44" = alloca float, align 4, addrspace(5)
   %"45" = alloca float, align 4, addrspace(5)
-  %"46" = alloca i1, align 1, addrspace(5)
+  %"46" = alloca i8, align 1, addrspace(5)
   br label %1
 
 1:                                                ; preds = %0
@@ -27,8 +30,10 @@ define amdgpu_kernel void @setp_gt(ptr addrspace(4) byref(i64) %"39", ptr addrsp
   %"54" = load float, ptr addrspace(5) %"43", align 4
   %"55" = load float, ptr addrspace(5) %"44", align 4
   %2 = fcmp ogt float %"54", %"55"
-  store i1 %2, ptr addrspace(5) %"46", align 1
-  %"56" = load i1, ptr addrspace(5) %"46", align 1
+  %3 = zext i1 %2 to i8
+  store i8 %3, ptr addrspace(5) %"46", align 1
+  %4 = load i8, ptr addrspace(5) %"46", align 1
+  %"56" = trunc i8 %4 to i1
   br i1 %"56", label %"16", label %"17"
 
 "16":                                             ; preds = %"38"
@@ -37,7 +42,8 @@ define amdgpu_kernel void @setp_gt(ptr addrspace(4) byref(i64) %"39", ptr addrsp
   br label %"17"
 
 "17":                                             ; preds = %"16", %"38"
-  %"59" = load i1, ptr addrspace(5) %"46", align 1
+  %5 = load i8, ptr addrspace(5) %"46", align 1
+  %"59" = trunc i8 %5 to i1
   br i1 %"59", label %"19", label %"18"
 
 "18":                                             ; preds = %"17"
@@ -53,4 +59,4 @@ define amdgpu_kernel void @setp_gt(ptr addrspace(4) byref(i64) %"39", ptr addrsp
   ret void
 }
 
-attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #0 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
@@ -1,3 +1,6 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrspace(4) byref(i64) %"84") #0 {
   %"85" = alloca i64, align 8, addrspace(5)
   %"86" = alloca i64, align 8, addrspace(5)
@@ -10,7 +13,7 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   %"93" = alloca float, align 4, addrspace(5)
   %"94" = alloca float, align 4, addrspace(5)
   %"95" = alloca i32, align 4, addrspace(5)
-  %"96" = alloca i1, align 1, addrspace(5)
+  %"96" = alloca i8, align 1, addrspace(5)
   br label %1
 
 1:                                                ; preds = %0
@@ -63,8 +66,10 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   %"116" = load float, ptr addrspace(5) %"87", align 4
   %"117" = load float, ptr addrspace(5) %"88", align 4
   %2 = fcmp ord float %"116", %"117"
-  store i1 %2, ptr addrspace(5) %"96", align 1
-  %"118" = load i1, ptr addrspace(5) %"96", align 1
+  %3 = zext i1 %2 to i8
+  store i8 %3, ptr addrspace(5) %"96", align 1
+  %4 = load i8, ptr addrspace(5) %"96", align 1
+  %"118" = trunc i8 %4 to i1
   br i1 %"118", label %"22", label %"23"
 
 "22":                                             ; preds = %"82"
@@ -72,7 +77,8 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   br label %"23"
 
 "23":                                             ; preds = %"22", %"82"
-  %"120" = load i1, ptr addrspace(5) %"96", align 1
+  %5 = load i8, ptr addrspace(5) %"96", align 1
+  %"120" = trunc i8 %5 to i1
   br i1 %"120", label %"25", label %"24"
 
 "24":                                             ; preds = %"23"
@@ -86,9 +92,11 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   store i32 %"123", ptr %"159", align 4
   %"125" = load float, ptr addrspace(5) %"89", align 4
   %"126" = load float, ptr addrspace(5) %"90", align 4
-  %3 = fcmp ord float %"125", %"126"
-  store i1 %3, ptr addrspace(5) %"96", align 1
-  %"127" = load i1, ptr addrspace(5) %"96", align 1
+  %6 = fcmp ord float %"125", %"126"
+  %7 = zext i1 %6 to i8
+  store i8 %7, ptr addrspace(5) %"96", align 1
+  %8 = load i8, ptr addrspace(5) %"96", align 1
+  %"127" = trunc i8 %8 to i1
   br i1 %"127", label %"26", label %"27"
 
 "26":                                             ; preds = %"25"
@@ -96,7 +104,8 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   br label %"27"
 
 "27":                                             ; preds = %"26", %"25"
-  %"129" = load i1, ptr addrspace(5) %"96", align 1
+  %9 = load i8, ptr addrspace(5) %"96", align 1
+  %"129" = trunc i8 %9 to i1
   br i1 %"129", label %"29", label %"28"
 
 "28":                                             ; preds = %"27"
@@ -111,9 +120,11 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   store i32 %"132", ptr %"73", align 4
   %"134" = load float, ptr addrspace(5) %"91", align 4
   %"135" = load float, ptr addrspace(5) %"92", align 4
-  %4 = fcmp ord float %"134", %"135"
-  store i1 %4, ptr addrspace(5) %"96", align 1
-  %"136" = load i1, ptr addrspace(5) %"96", align 1
+  %10 = fcmp ord float %"134", %"135"
+  %11 = zext i1 %10 to i8
+  store i8 %11, ptr addrspace(5) %"96", align 1
+  %12 = load i8, ptr addrspace(5) %"96", align 1
+  %"136" = trunc i8 %12 to i1
   br i1 %"136", label %"30", label %"31"
 
 "30":                                             ; preds = %"29"
@@ -121,7 +132,8 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   br label %"31"
 
 "31":                                             ; preds = %"30", %"29"
-  %"138" = load i1, ptr addrspace(5) %"96", align 1
+  %13 = load i8, ptr addrspace(5) %"96", align 1
+  %"138" = trunc i8 %13 to i1
   br i1 %"138", label %"33", label %"32"
 
 "32":                                             ; preds = %"31"
@@ -136,9 +148,11 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   store i32 %"141", ptr %"77", align 4
   %"143" = load float, ptr addrspace(5) %"93", align 4
   %"144" = load float, ptr addrspace(5) %"94", align 4
-  %5 = fcmp ord float %"143", %"144"
-  store i1 %5, ptr addrspace(5) %"96", align 1
-  %"145" = load i1, ptr addrspace(5) %"96", align 1
+  %14 = fcmp ord float %"143", %"144"
+  %15 = zext i1 %14 to i8
+  store i8 %15, ptr addrspace(5) %"96", align 1
+  %16 = load i8, ptr addrspace(5) %"96", align 1
+  %"145" = trunc i8 %16 to i1
   br i1 %"145", label %"34", label %"35"
 
 "34":                                             ; preds = %"33"
@@ -146,7 +160,8 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   br label %"35"
 
 "35":                                             ; preds = %"34", %"33"
-  %"147" = load i1, ptr addrspace(5) %"96", align 1
+  %17 = load i8, ptr addrspace(5) %"96", align 1
+  %"147" = trunc i8 %17 to i1
   br i1 %"147", label %"37", label %"36"
 
 "36":                                             ; preds = %"35"
@@ -162,4 +177,4 @@ define amdgpu_kernel void @setp_num(ptr addrspace(4) byref(i64) %"83", ptr addrs
   ret void
 }
 
-attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="ieee" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #0 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="ieee" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
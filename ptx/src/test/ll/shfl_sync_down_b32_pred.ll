@@ -1,3 +1,6 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 declare hidden <2 x i32> @__zluda_ptx_impl_shfl_sync_down_b32_pred(i32, i32, i32, i32) #0
 
 declare hidden i32 @__zluda_ptx_impl_sreg_tid(i8) #0
@@ -7,7 +10,7 @@ define amdgpu_kernel void @shfl_sync_down_b32_pred(ptr addrspace(4) byref(i64) %
   %"44" = alloca i64, align 8, addrspace(5)
   %"45" = alloca i32, align 4, addrspace(5)
   %"46" = alloca i32, align 4, addrspace(5)
-  %"47" = alloca i1, align 1, addrspace(5)
+  %"47" = alloca i8, align 1, addrspace(5)
   br label %1
 
 1:                                                ; preds = %0
@@ -27,8 +30,10 @@ define amdgpu_kernel void @shfl_sync_down_b32_pred(ptr addrspace(4) byref(i64) %
   %"69" = extractelement <2 x i32> %"68", i8 1
   %"51" = trunc i32 %"69" to i1
   store i32 %"65", ptr addrspace(5) %"46", align 4
-  store i1 %"51", ptr addrspace(5) %"47", align 1
-  %"53" = load i1, ptr addrspace(5) %"47", align 1
+  %2 = zext i1 %"51" to i8
+  store i8 %2, ptr addrspace(5) %"47", align 1
+  %3 = load i8, ptr addrspace(5) %"47", align 1
+  %"53" = trunc i8 %3 to i1
   br i1 %"53", label %"15", label %"14"
 
 "14":                                             ; preds = %"40"
@@ -56,4 +61,4 @@ define amdgpu_kernel void @shfl_sync_down_b32_pred(ptr addrspace(4) byref(i64) %
 }
 
 attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="dynamic" "denormal-fp-math-f32"="dynamic" "no-trapping-math"="true" "uniform-work-group-size"="true" }
-attributes #1 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #1 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
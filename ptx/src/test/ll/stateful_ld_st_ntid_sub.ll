@@ -1,3 +1,6 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 declare hidden i32 @__zluda_ptx_impl_sreg_tid(i8) #0
 
 define amdgpu_kernel void @stateful_ld_st_ntid_sub(ptr addrspace(4) byref(i64) %"44", ptr addrspace(4) byref(i64) %"45") #1 {
@@ -22,12 +25,14 @@ define amdgpu_kernel void @stateful_ld_st_ntid_sub(ptr addrspace(4) byref(i64) %
   store i64 %"75", ptr addrspace(5) %"49", align 8
   %"58" = load i64, ptr addrspace(5) %"46", align 8
   %2 = inttoptr i64 %"58" to ptr
-  %"57" = addrspacecast ptr %2 to ptr addrspace(1)
-  store ptr addrspace(1) %"57", ptr addrspace(5) %"47", align 8
+  %3 = addrspacecast ptr %2 to ptr addrspace(1)
+  %"57" = ptrtoint ptr addrspace(1) %3 to i64
+  store i64 %"57", ptr addrspace(5) %"47", align 8
   %"60" = load i64, ptr addrspace(5) %"49", align 8
-  %3 = inttoptr i64 %"60" to ptr
-  %"59" = addrspacecast ptr %3 to ptr addrspace(1)
-  store ptr addrspace(1) %"59", ptr addrspace(5) %"50", align 8
+  %4 = inttoptr i64 %"60" to ptr
+  %5 = addrspacecast ptr %4 to ptr addrspace(1)
+  %"59" = ptrtoint ptr addrspace(1) %5 to i64
+  store i64 %"59", ptr addrspace(5) %"50", align 8
   %"36" = call i32 @__zluda_ptx_impl_sreg_tid(i8 0)
   br label %"42"
 
@@ -58,4 +63,4 @@ define amdgpu_kernel void @stateful_ld_st_ntid_sub(ptr addrspace(4) byref(i64) %
 }
 
 attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="dynamic" "denormal-fp-math-f32"="dynamic" "no-trapping-math"="true" "uniform-work-group-size"="true" }
-attributes #1 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #1 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
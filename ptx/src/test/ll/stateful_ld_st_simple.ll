@@ -1,3 +1,6 @@
+target datalayout = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8"
+target triple = "amdgcn-amd-amdhsa"
+
 define amdgpu_kernel void @stateful_ld_st_simple(ptr addrspace(4) byref(i64) %"32", ptr addrspace(4) byref(i64) %"33") #0 {
   %"34" = alloca i64, align 8, addrspace(5)
   %"35" = alloca i64, align 8, addrspace(5)
@@ -16,12 +19,14 @@ define amdgpu_kernel void @stateful_ld_st_simple(ptr addrspace(4) byref(i64) %"3
   store i64 %"40", ptr addrspace(5) %"35", align 8
   %"42" = load i64, ptr addrspace(5) %"34", align 8
   %2 = inttoptr i64 %"42" to ptr
-  %"49" = addrspacecast ptr %2 to ptr addrspace(1)
-  store ptr addrspace(1) %"49", ptr addrspace(5) %"36", align 8
+  %3 = addrspacecast ptr %2 to ptr addrspace(1)
+  %"49" = ptrtoint ptr addrspace(1) %3 to i64
+  store i64 %"49", ptr addrspace(5) %"36", align 8
   %"44" = load i64, ptr addrspace(5) %"35", align 8
-  %3 = inttoptr i64 %"44" to ptr
-  %"51" = addrspacecast ptr %3 to ptr addrspace(1)
-  store ptr addrspace(1) %"51", ptr addrspace(5) %"37", align 8
+  %4 = inttoptr i64 %"44" to ptr
+  %5 = addrspacecast ptr %4 to ptr addrspace(1)
+  %"51" = ptrtoint ptr addrspace(1) %5 to i64
+  store i64 %"51", ptr addrspace(5) %"37", align 8
   %"46" = load i64, ptr addrspace(5) %"36", align 8
   %"53" = inttoptr i64 %"46" to ptr addrspace(1)
   %"45" = load i64, ptr addrspace(1) %"53", align 8
@@ -33,4 +38,4 @@ define amdgpu_kernel void @stateful_ld_st_simple(ptr addrspace(4) byref(i64) %"3
   ret void
 }
 
-attributes #0 = { "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
+attributes #0 = { "amdgpu-implicitarg-num-bytes"="256" "amdgpu-unsafe-fp-atomics"="true" "denormal-fp-math"="preserve-sign" "denormal-fp-math-f32"="preserve-sign" "no-trapping-math"="true" "uniform-work-group-size"="true" }
\ No newline at end of file
@@ -60,6 +60,727 @@ fn vectorAdd_kernel64_ptx() -> Result<(), TranslateError> {
     compile_and_assert(vector_add)
 }
 
+const LD_ST: &str = include_str!("spirv_run/ld_st.ptx");
+
+// PTX saved by Windows applications
+#[test]
+fn ld_st_with_dos_line_endings() -> Result<(), TranslateError> {
+    compile_and_assert(&LD_ST.replace('\n', "\r\n"))
+}
+
+#[test]
+fn ld_st_with_byte_order_mark() -> Result<(), TranslateError> {
+    compile_and_assert(&format!("\u{FEFF}{LD_ST}"))
+}
+
+// Buffers passed to cuModuleLoadData are NUL-terminated, sometimes with extra padding
+#[test]
+fn ld_st_with_trailing_nuls() -> Result<(), TranslateError> {
+    compile_and_assert(&format!("{LD_ST}\0\0\0"))
+}
+
+#[test]
+fn ld_st_with_all_windows_artifacts() -> Result<(), TranslateError> {
+    compile_and_assert(&format!("\u{FEFF}{}\0", LD_ST.replace('\n', "\r\n")))
+}
+
+#[test]
+fn promote_local_variables_reduces_statements() -> Result<(), TranslateError> {
+    let vector_add = include_str!("vectorAdd_kernel64.ptx");
+    let ast = ast::parse_module_checked(vector_add).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let statistics = crate::to_llvm_module(ast, attributes)?.statistics;
+    assert!(statistics.promoted_variables > 0);
+    assert!(statistics.statements_after_promotion < statistics.statements_before_promotion);
+    Ok(())
+}
+
+#[test]
+fn pass_statistics_are_collected() -> Result<(), TranslateError> {
+    let vector_add = include_str!("vectorAdd_kernel64.ptx");
+    let ast = ast::parse_module_checked(vector_add).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let statistics = crate::to_llvm_module(ast, attributes)?.statistics;
+    assert_eq!(statistics.passes[0].name, "normalize_identifiers2");
+    let load_store = statistics
+        .passes
+        .iter()
+        .find(|pass| pass.name == "insert_explicit_load_store")
+        .unwrap();
+    assert!(load_store.statements_after > load_store.statements_before);
+    assert!(load_store.identifiers_created > 0);
+    // Every pass starts with what the previous one produced
+    for passes in statistics.passes.windows(2) {
+        assert_eq!(passes[0].statements_after, passes[1].statements_before);
+    }
+    Ok(())
+}
+
+#[test]
+fn parallel_passes_give_same_output_as_single_thread() -> Result<(), TranslateError> {
+    let vector_add = include_str!("vectorAdd_kernel64.ptx");
+    let entry = vector_add.find(".visible .entry").unwrap();
+    let (header, kernel) = vector_add.split_at(entry);
+    let mut module = header.to_string();
+    for i in 0..16 {
+        module.push_str(&kernel.replace("VecAdd_kernel", &format!("VecAdd_kernel_{i}")));
+    }
+    let single_thread = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap()
+        .install(|| compile_to_ll(&module))?;
+    let parallel = compile_to_ll(&module)?;
+    assert_eq!(single_thread, parallel);
+    Ok(())
+}
+
+#[test]
+fn statements_are_dumped_after_every_pass() -> Result<(), TranslateError> {
+    let dump_dir = tempfile::tempdir().unwrap();
+    let ast = ast::parse_module_checked(include_str!("spirv_run/ld_st.ptx")).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let options = pass::TranslateOptions {
+        dump_dir: Some(dump_dir.path().to_path_buf()),
+        ..pass::TranslateOptions::default()
+    };
+    let statistics = crate::to_llvm_module_with_options(ast, attributes, options)?.statistics;
+    let module_dirs = std::fs::read_dir(dump_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(module_dirs.len(), 1);
+    let mut dumps = std::fs::read_dir(&module_dirs[0])
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    dumps.sort();
+    assert_eq!(dumps.len(), statistics.passes.len());
+    assert_eq!(dumps[0], "00_normalize_identifiers2.txt");
+    let last_dump = std::fs::read_to_string(module_dirs[0].join(dumps.last().unwrap())).unwrap();
+    assert!(last_dump.contains(".entry"));
+    Ok(())
+}
+
+#[test]
+fn eliminate_dead_code_removes_unused_registers() -> Result<(), TranslateError> {
+    let dead_registers = "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry dead_registers(
+            .param .u64 output
+        )
+        {
+            .reg .u64 out_addr;
+            .reg .u32 unused<16>;
+            .reg .u32 copied;
+            .reg .u32 value;
+
+            ld.param.u64 out_addr, [output];
+            mov.u32 copied, 1;
+            mov.u32 unused1, copied;
+            mov.u32 value, 2;
+            st.u32 [out_addr], value;
+            ret;
+        }";
+    let ast = ast::parse_module_checked(dead_registers).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    // Promotion would already remove most of the unused registers
+    let options = pass::TranslateOptions {
+        if_convert_predicates: false,
+        infer_global_state_space: false,
+        promote_local_variables: false,
+        fold_constants: false,
+        deduplicate_values: false,
+        hoist_special_registers: false,
+        eliminate_dead_code: true,
+        scalarize_uniform_loads: false,
+        internalize_functions: true,
+        ..pass::TranslateOptions::default()
+    };
+    let statistics = crate::to_llvm_module_with_options(ast, attributes, options)?.statistics;
+    assert!(statistics.eliminated_statements >= 16);
+    Ok(())
+}
+
+#[test]
+fn predicated_moves_are_if_converted() -> Result<(), TranslateError> {
+    let pred_not = include_str!("spirv_run/pred_not.ptx");
+    let ast = ast::parse_module_checked(pred_not).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let statistics = crate::to_llvm_module(ast, attributes)?.statistics;
+    assert_eq!(statistics.if_converted_instructions, 2);
+    Ok(())
+}
+
+fn compile_to_ll(ptx_text: &str) -> Result<String, TranslateError> {
+    let ast = ast::parse_module_checked(ptx_text).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let module = crate::to_llvm_module(ast, attributes)?;
+    let ll = module.llvm_ir.print_module_to_string();
+    Ok(ll.to_str().to_string())
+}
+
+// Must match `clang -target amdgcn-amd-amdhsa -S -emit-llvm`, otherwise linking with the
+// device libraries warns about mismatched data layouts
+const CLANG_AMDGPU_DATA_LAYOUT: &str = "e-p:64:64-p1:64:64-p2:32:32-p3:32:32-p4:64:64-p5:32:32-p6:32:32-p7:160:256:256:32-p8:128:128-i64:64-v16:16-v24:32-v32:32-v48:64-v96:128-v192:256-v256:256-v512:512-v1024:1024-v2048:2048-n32:64-S32-A5-G1-ni:7:8";
+
+#[test]
+fn bitcode_has_amdgpu_data_layout_and_triple() -> Result<(), TranslateError> {
+    let ast = ast::parse_module_checked(include_str!("spirv_run/reg_local.ptx")).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let module = crate::to_llvm_module(ast, attributes)?;
+    for bitcode in [
+        module.llvm_ir.write_bitcode_to_memory(),
+        module.attributes_ir.write_bitcode_to_memory(),
+    ] {
+        let ll = crate::bitcode_to_ir(bitcode.to_vec());
+        let ll = String::from_utf8(ll).unwrap();
+        assert!(ll.contains(&format!(
+            "target datalayout = \"{}\"",
+            CLANG_AMDGPU_DATA_LAYOUT
+        )));
+        assert!(ll.contains("target triple = \"amdgcn-amd-amdhsa\""));
+    }
+    Ok(())
+}
+
+// Attribute group of the function defined on the line starting with `define_prefix`
+fn function_attributes<'a>(ll: &'a str, define_prefix: &str) -> &'a str {
+    let define = ll
+        .lines()
+        .find(|line| line.starts_with(define_prefix))
+        .unwrap();
+    let group = define.rsplit(' ').nth(1).unwrap();
+    ll.lines()
+        .find_map(|line| line.strip_prefix(&format!("attributes {group} = ")))
+        .unwrap()
+}
+
+#[test]
+fn kernels_reserve_hidden_arguments() -> Result<(), TranslateError> {
+    let ast = ast::parse_module_checked(include_str!("spirv_run/call.ptx")).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let module = crate::to_llvm_module(ast, attributes)?;
+    let ll = module.llvm_ir.print_module_to_string();
+    let ll = ll.to_str();
+    assert!(
+        function_attributes(ll, "define amdgpu_kernel void @call(")
+            .contains("\"amdgpu-implicitarg-num-bytes\"=\"256\""),
+        "{ll}"
+    );
+    assert!(
+        !function_attributes(ll, "define hidden i64 @incr(").contains("amdgpu-implicitarg"),
+        "{ll}"
+    );
+    // The size of the hidden arguments above is the one of code object v5
+    assert_eq!(crate::AMDHSA_CODE_OBJECT_VERSION, 5);
+    let attributes_ll = module.attributes_ir.print_module_to_string();
+    assert!(attributes_ll
+        .to_str()
+        .contains("!{i32 1, !\"amdhsa_code_object_version\", i32 500}"));
+    Ok(())
+}
+
+#[test]
+fn only_rn_division_requires_correct_rounding() -> Result<(), TranslateError> {
+    let attributes = || pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let rn = ast::parse_module_checked(include_str!("spirv_run/div_rn_f64.ptx")).unwrap();
+    assert!(crate::to_llvm_module(rn, attributes())?.correctly_rounded_div_sqrt);
+    let approx = ast::parse_module_checked(include_str!("spirv_run/div_approx.ptx")).unwrap();
+    assert!(!crate::to_llvm_module(approx, attributes())?.correctly_rounded_div_sqrt);
+    Ok(())
+}
+
+#[test]
+fn aligned_kernel_parameter_keeps_alignment() -> Result<(), TranslateError> {
+    let ll = compile_to_ll(include_str!("spirv_run/param_align.ptx"))?;
+    let signature = ll
+        .lines()
+        .find(|line| line.starts_with("define amdgpu_kernel void @param_align"))
+        .unwrap();
+    assert!(signature.contains("align 16"));
+    assert!(signature.contains("byref([64 x i8])"));
+    assert!(ll.contains("load <4 x i32>, ptr addrspace(4)"));
+    assert!(ll
+        .lines()
+        .any(|line| line.contains("load <4 x i32>") && line.ends_with("align 16")));
+    Ok(())
+}
+
+#[test]
+fn zero_sized_kernel_parameter_is_passed_as_empty_array() -> Result<(), TranslateError> {
+    let ll = compile_to_ll(include_str!("spirv_run/param_zero_sized.ptx"))?;
+    let signature = ll
+        .lines()
+        .find(|line| line.starts_with("define amdgpu_kernel void @param_zero_sized"))
+        .unwrap();
+    assert!(signature.contains("byref([0 x i8])"));
+    Ok(())
+}
+
+const OVERSIZED_PARAMETERS: &str = "
+.version 8.1
+.target sm_70
+.address_size 64
+
+.visible .entry oversized(
+    .param .align 8 .b8 value[32768],
+    .param .u64 output
+)
+{
+    ret;
+}
+";
+
+#[test]
+fn oversized_kernel_parameters_are_rejected() {
+    let error = compile_and_assert(OVERSIZED_PARAMETERS).unwrap_err();
+    assert!(
+        matches!(error.inner(), TranslateError::KernelParametersTooLarge { kernel, size: 32776, .. } if kernel == "oversized"),
+        "{error:?}"
+    );
+    assert_eq!(error.location().unwrap().line, 6);
+}
+
+#[test]
+fn annotated_pointer_parameter_is_global() -> Result<(), TranslateError> {
+    let ll = compile_to_ll(include_str!("spirv_run/param_ptr.ptx"))?;
+    assert!(ll.contains("load i64, ptr addrspace(1)"));
+    assert!(ll
+        .lines()
+        .any(|line| line.contains("store i64") && line.contains("ptr addrspace(1)")));
+    let info = kernel_info(include_str!("spirv_run/param_ptr.ptx"))?;
+    let pointer = pass::ParameterInfo {
+        size: 8,
+        align: 8,
+        is_pointer: true,
+        pointee_align: Some(8),
+    };
+    assert_eq!(info.parameters, [pointer, pointer]);
+    Ok(())
+}
+
+#[test]
+fn cvta_local_keeps_registers_64_bit() -> Result<(), TranslateError> {
+    let ll = compile_to_ll(include_str!("spirv_run/local_ptr_call.ptx"))?;
+    assert!(ll.contains("alloca [16 x i8], align 8, addrspace(5)"));
+    // .local pointers are 32 bit, they are converted to and from the 64 bit registers
+    assert!(ll.contains("addrspacecast ptr addrspace(5)"));
+    assert!(!ll.contains("store ptr"));
+    Ok(())
+}
+
+#[test]
+fn functions_falling_off_their_end_return() -> Result<(), TranslateError> {
+    let ll = compile_to_ll(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .func empty()
+        {
+        }
+
+        .visible .entry call_empty()
+        {
+            call empty;
+        }",
+    )?;
+    assert_eq!(ll.matches("ret void").count(), 2);
+    Ok(())
+}
+
+#[test]
+fn debug_sections_are_discarded() -> Result<(), TranslateError> {
+    let ptx = include_str!("spirv_run/add_debug.ptx");
+    assert_eq!(ast::parse_module_checked(ptx).unwrap().sections.len(), 4);
+    let ll = compile_to_ll(ptx)?;
+    assert!(!ll.contains("!dbg"));
+    assert!(!ll.contains("debug_info"));
+    Ok(())
+}
+
+#[test]
+fn predicates_are_kept_in_memory_as_bytes() -> Result<(), TranslateError> {
+    let ll = compile_to_ll(include_str!("spirv_run/pred_local.ptx"))?;
+    assert!(!ll.contains("alloca i1,"));
+    assert!(!ll.contains("load i1,"));
+    assert!(!ll.contains("store i1 "));
+    Ok(())
+}
+
+#[test]
+fn envreg_reads_are_constant_zero() -> Result<(), TranslateError> {
+    let ptx = include_str!("spirv_run/envreg.ptx");
+    let ll = compile_to_ll(ptx)?;
+    assert!(!ll.contains("call "), "{ll}");
+    assert!(
+        ll.lines()
+            .any(|line| line.contains(" = add i32 ") && line.ends_with(", 0")),
+        "{ll}"
+    );
+    let write = ptx.replace(
+        "add.u32             temp, temp, %envreg3;",
+        "mov.u32 %envreg3, temp;",
+    );
+    assert!(compile_to_ll(&write).is_err());
+    Ok(())
+}
+
+fn kernel_info(ptx_text: &str) -> Result<pass::KernelInfo, TranslateError> {
+    let ast = ast::parse_module_checked(ptx_text).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let mut module = crate::to_llvm_module(ast, attributes)?;
+    assert_eq!(module.kernels.len(), 1);
+    Ok(module.kernels.pop().unwrap())
+}
+
+#[test]
+fn kernel_info_describes_pointer_parameters() -> Result<(), TranslateError> {
+    let info = kernel_info(include_str!("spirv_run/add.ptx"))?;
+    assert_eq!(info.name, "add");
+    let pointer = pass::ParameterInfo {
+        size: 8,
+        align: 8,
+        is_pointer: true,
+        pointee_align: None,
+    };
+    assert_eq!(info.parameters, [pointer, pointer]);
+    assert_eq!(info.static_shared_memory, 0);
+    assert!(!info.dynamic_shared_memory);
+    assert_eq!(info.local_memory, 0);
+    assert_eq!(info.constant_memory, 0);
+    assert!(info.tuning.is_empty());
+    Ok(())
+}
+
+#[test]
+fn kernel_info_reports_memory_usage() -> Result<(), TranslateError> {
+    let extern_shared = kernel_info(include_str!("spirv_run/extern_shared.ptx"))?;
+    assert!(extern_shared.dynamic_shared_memory);
+    assert_eq!(extern_shared.static_shared_memory, 0);
+    let shared = kernel_info(include_str!("spirv_run/shared_variable.ptx"))?;
+    assert!(!shared.dynamic_shared_memory);
+    assert_eq!(shared.static_shared_memory, 128);
+    let const_ = kernel_info(include_str!("spirv_run/const.ptx"))?;
+    assert_eq!(const_.constant_memory, 8);
+    let local = kernel_info(include_str!("spirv_run/reg_local.ptx"))?;
+    assert_eq!(local.local_memory, 8);
+    Ok(())
+}
+
+#[test]
+fn kernel_info_keeps_tuning_directives() -> Result<(), TranslateError> {
+    let info = kernel_info(include_str!("spirv_run/add_tuning.ptx"))?;
+    assert_eq!(
+        info.tuning,
+        [
+            ast::TuningDirective::MaxNtid(256, 1, 1),
+            ast::TuningDirective::MinNCtaPerSm(4)
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn kernel_info_lays_out_large_and_zero_sized_parameters() -> Result<(), TranslateError> {
+    let large = kernel_info(include_str!("spirv_run/param_large.ptx"))?;
+    assert_eq!(large.parameters[0].size, 4096);
+    assert_eq!(large.parameters_size(), 4104);
+    let zero_sized = kernel_info(include_str!("spirv_run/param_zero_sized.ptx"))?;
+    let sizes = zero_sized
+        .parameters
+        .iter()
+        .map(|param| param.size)
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [8, 0, 8]);
+    assert_eq!(zero_sized.parameters_size(), 16);
+    Ok(())
+}
+
+#[test]
+fn code_object_reports_kernel_resources() -> Result<(), Box<dyn std::error::Error>> {
+    let ast = ast::parse_module_checked(include_str!("spirv_run/shared_variable.ptx")).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let module = crate::to_llvm_module(ast, attributes)?;
+    let comgr = comgr::Comgr::new()?;
+    let elf = comgr::compile_bitcode(
+        &comgr,
+        "gfx1030",
+        &*module.llvm_ir.write_bitcode_to_memory(),
+        module.linked_bitcode(),
+        &*module.attributes_ir.write_bitcode_to_memory(),
+        module.correctly_rounded_div_sqrt,
+        None,
+    )?;
+    let resources = comgr::get_kernel_resources(&elf)?;
+    assert_eq!(resources.len(), 1);
+    assert_eq!(resources[0].name, "shared_variable");
+    assert_eq!(resources[0].group_segment_fixed_size, 128);
+    assert!(resources[0].kernarg_segment_size >= 16);
+    assert!(resources[0].sgpr_count > 0);
+    assert!(resources[0].vgpr_count > 0);
+    Ok(())
+}
+
+#[test]
+fn stateful_ld_st_simple_accesses_global_memory() -> Result<(), TranslateError> {
+    let ll = compile_to_ll(include_str!("spirv_run/stateful_ld_st_simple.ptx"))?;
+    assert!(ll.contains("load i64, ptr addrspace(1)"));
+    assert!(!ll.contains("load i64, ptr %"));
+    Ok(())
+}
+
+#[test]
+fn generic_access_through_kernel_argument_is_global() -> Result<(), TranslateError> {
+    let ll = compile_to_ll(include_str!("spirv_run/ld_st.ptx"))?;
+    assert!(ll.contains("load i64, ptr addrspace(1)"));
+    assert!(ll
+        .lines()
+        .any(|line| line.contains("store i64") && line.contains("ptr addrspace(1)")));
+    Ok(())
+}
+
+const UNROLLED_STORES: &str = "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry unrolled_stores(
+            .param .u64 output
+        )
+        {
+            .reg .u64 out_addr;
+            .reg .u32 acc;
+
+            ld.param.u64 out_addr, [output];
+            ld.global.u32 acc, [out_addr];
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            add.u32 acc, acc, 1;
+            st.global.u32 [out_addr], acc;
+            ret;
+        }";
+
+#[test]
+fn unrolled_constants_and_conversions_are_deduplicated() -> Result<(), TranslateError> {
+    let translate = |deduplicate_values| {
+        let ast = ast::parse_module_checked(UNROLLED_STORES).unwrap();
+        let attributes = pass::Attributes {
+            clock_rate: 2124000,
+        };
+        let options = pass::TranslateOptions {
+            deduplicate_values,
+            ..pass::TranslateOptions::default()
+        };
+        crate::to_llvm_module_with_options(ast, attributes, options)
+    };
+    let count_inttoptr = |module: &pass::Module| {
+        let ll = module.llvm_ir.print_module_to_string();
+        ll.to_str()
+            .lines()
+            .filter(|line| line.contains("inttoptr"))
+            .count()
+    };
+    let duplicated = translate(false)?;
+    let deduplicated = translate(true)?;
+    // 15 copies of the constant 1 and 16 copies of the conversion of out_addr to a pointer
+    assert!(deduplicated.statistics.deduplicated_values >= 31);
+    assert_eq!(count_inttoptr(&duplicated), 17);
+    assert_eq!(count_inttoptr(&deduplicated), 1);
+    Ok(())
+}
+
+#[test]
+fn internal_function_is_inlined() -> Result<(), TranslateError> {
+    let call = include_str!("spirv_run/call.ptx");
+    let ast = ast::parse_module_checked(call).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let module = crate::to_llvm_module(ast, attributes)?;
+    module.llvm_ir.run_passes(c"inline");
+    let ll = module.llvm_ir.print_module_to_string();
+    assert!(!ll
+        .to_str()
+        .lines()
+        .any(|line| line.contains("call") && line.contains("@incr(")));
+    Ok(())
+}
+
+#[test]
+fn loop_pragmas_become_loop_metadata() -> Result<(), TranslateError> {
+    let ll = compile_to_ll(include_str!("spirv_run/loop_pragmas.ptx"))?;
+    let latches = ll
+        .lines()
+        .filter(|line| line.trim_start().starts_with("br ") && line.contains("!llvm.loop !"))
+        .count();
+    assert_eq!(latches, 2);
+    assert!(ll.contains("!{!\"llvm.loop.unroll.count\", i32 4}"));
+    assert!(ll.contains("!{!\"llvm.loop.unroll.disable\"}"));
+    // Loops without a pragma get no metadata
+    let ll = compile_to_ll(include_str!("spirv_run/shared_reverse.ptx"))?;
+    assert!(!ll.contains("llvm.loop"));
+    Ok(())
+}
+
+#[test]
+fn uniform_non_coherent_loads_are_scalar() -> Result<(), TranslateError> {
+    let translate = |text, scalarize_uniform_loads| {
+        let ast = ast::parse_module_checked(text).unwrap();
+        let attributes = pass::Attributes {
+            clock_rate: 2124000,
+        };
+        let options = pass::TranslateOptions {
+            scalarize_uniform_loads,
+            ..pass::TranslateOptions::default()
+        };
+        crate::to_llvm_module_with_options(ast, attributes, options)
+    };
+    let constant_casts = |module: &pass::Module| {
+        let ll = module.llvm_ir.print_module_to_string();
+        ll.to_str()
+            .lines()
+            .filter(|line| line.contains("addrspacecast") && line.contains("to ptr addrspace(4)"))
+            .count()
+    };
+    let uniform_loads = include_str!("spirv_run/uniform_loads.ptx");
+    let scalarized = translate(uniform_loads, true)?;
+    // Only the load from table[index + %ctaid.x], table[%tid.x] is different in every thread
+    assert_eq!(scalarized.statistics.scalarized_loads, 1);
+    assert_eq!(constant_casts(&scalarized), 1);
+    let ll = scalarized.llvm_ir.print_module_to_string();
+    assert_eq!(ll.to_str().matches("load i32, ptr addrspace(1)").count(), 1);
+    let plain = translate(uniform_loads, false)?;
+    assert_eq!(plain.statistics.scalarized_loads, 0);
+    assert_eq!(constant_casts(&plain), 0);
+    // The index is only uniform until some of the threads skip the block storing to it
+    let divergent_index = "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry divergent_index(
+            .param .u64 input,
+            .param .u64 output
+        )
+        {
+            .reg .u64 table;
+            .reg .u64 out_addr;
+            .reg .u64 offset;
+            .reg .u32 thread_id;
+            .reg .u32 index;
+            .reg .u32 value;
+            .reg .pred low;
+
+            ld.param.u64 table, [input];
+            ld.param.u64 out_addr, [output];
+            mov.u32 thread_id, %tid.x;
+            mov.u32 index, 0;
+            setp.lt.u32 low, thread_id, 16;
+            @low bra skip;
+            st.global.u32 [out_addr+4], thread_id;
+            mov.u32 index, 1;
+        skip:
+            mul.wide.u32 offset, index, 4;
+            add.u64 table, table, offset;
+            ld.global.nc.u32 value, [table];
+            st.global.u32 [out_addr], value;
+            ret;
+        }";
+    let divergent = translate(divergent_index, true)?;
+    assert_eq!(divergent.statistics.scalarized_loads, 0);
+    assert_eq!(constant_casts(&divergent), 0);
+    Ok(())
+}
+
+#[test]
+fn volatile_accesses_are_system_atomics_on_apus() -> Result<(), TranslateError> {
+    let polling = include_str!("spirv_run/volatile_flag_polling.ptx");
+    let translate = |gcn_arch| {
+        let ast = ast::parse_module_checked(polling).unwrap();
+        let attributes = pass::Attributes {
+            clock_rate: 2124000,
+        };
+        let options = pass::TranslateOptions {
+            volatile_coherence: pass::VolatileCoherence::for_arch(gcn_arch),
+            ..pass::TranslateOptions::default()
+        };
+        let module = crate::to_llvm_module_with_options(ast, attributes, options)?;
+        Ok::<_, TranslateError>(module.llvm_ir.print_module_to_string().to_str().to_string())
+    };
+    let discrete = translate("gfx1100")?;
+    assert_eq!(discrete.matches("load volatile i32").count(), 2);
+    assert_eq!(discrete.matches("store volatile i32").count(), 1);
+    assert!(!discrete.contains("atomic"));
+    let integrated = translate("gfx1103:xnack-")?;
+    assert_eq!(
+        integrated
+            .matches("load atomic volatile i32, ptr addrspace(1)")
+            .count(),
+        2
+    );
+    assert_eq!(integrated.matches("store atomic volatile i32").count(), 1);
+    assert_eq!(integrated.matches(" monotonic, align 4").count(), 3);
+    // The store to the output is not volatile
+    assert_eq!(integrated.matches("store i32").count(), 1);
+    Ok(())
+}
+
 #[test]
 #[allow(non_snake_case)]
 fn _Z9vectorAddPKfS0_Pfi_ptx() -> Result<(), TranslateError> {
@@ -73,3 +794,353 @@ fn vectorAdd_11_ptx() -> Result<(), TranslateError> {
     let vector_add = include_str!("vectorAdd_11.ptx");
     compile_and_assert(vector_add)
 }
+
+const UNSUPPORTED_DP4A: &str = "
+.version 6.5
+.target sm_70
+.address_size 64
+
+.visible .entry dp4a_mixed(
+    .param .u64 output
+)
+{
+    .reg .u64       out_addr;
+    .reg .b32       src;
+    .reg .b32       dst;
+
+    ld.param.u64    out_addr, [output];
+    ld.b32          src, [out_addr];
+    dp4a.u32.s32    dst, src, src, src;
+    st.b32          [out_addr], dst;
+    ret;
+}
+";
+
+#[test]
+fn unsupported_instruction_names_opcode() {
+    let error = compile_and_assert(UNSUPPORTED_DP4A).unwrap_err();
+    assert!(
+        matches!(error.inner(), TranslateError::UnsupportedInstruction { opcode } if opcode == "dp4a.u32.s32"),
+        "{error:?}"
+    );
+    assert!(error.to_string().contains("dp4a.u32.s32"));
+    // Errors from the LLVM emission are attributed to the enclosing function
+    assert_eq!(error.location().unwrap().line, 6);
+}
+
+const CLUSTER_BARRIER: &str = "
+.version 7.8
+.target sm_90
+.address_size 64
+
+.visible .entry cluster_barrier()
+{
+    barrier.cluster.arrive.release.aligned;
+    barrier.cluster.wait.acquire.aligned;
+    ret;
+}
+";
+
+#[test]
+fn cluster_barrier_is_parsed_but_unsupported() {
+    let module = ptx_parser::parse_module_checked(CLUSTER_BARRIER).unwrap();
+    assert!(module
+        .to_string()
+        .contains("barrier.cluster.wait.acquire.aligned;"));
+    let error = compile_and_assert(CLUSTER_BARRIER).unwrap_err();
+    assert!(
+        matches!(error.inner(), TranslateError::UnsupportedClusterFeature { instruction } if instruction == "barrier.cluster.arrive"),
+        "{error:?}"
+    );
+    assert!(error
+        .to_string()
+        .contains("cluster features unsupported (sm_90)"));
+    assert_eq!(error.location().unwrap().line, 8);
+}
+
+const PARTIAL_RETURN_VALUE: &str = "
+.version 6.5
+.target sm_70
+.address_size 64
+
+.func (.param .b64 ret) high_word(
+    .param .b32 value
+)
+{
+    .reg .b32       x;
+
+    ld.param.b32    x, [value];
+    st.param.b32    [ret+4], x;
+    ret;
+}
+
+.visible .entry partial_return(
+    .param .u64 output
+)
+{
+    .reg .u64       out_addr;
+    .reg .b64       result;
+
+    ld.param.u64    out_addr, [output];
+    {
+        .param .b32 param0;
+        st.param.b32    [param0], 1;
+        .param .b64 retval0;
+        call (retval0), high_word, (param0);
+        ld.param.b64    result, [retval0];
+    }
+    st.b64          [out_addr], result;
+    ret;
+}
+";
+
+#[test]
+fn partial_access_of_return_value_is_an_error() {
+    let error = compile_and_assert(PARTIAL_RETURN_VALUE).unwrap_err();
+    assert!(
+        matches!(error.inner(), TranslateError::UnsupportedParamAccess { name, .. } if name == "ret"),
+        "{error:?}"
+    );
+    assert_eq!(error.location().unwrap().line, 6);
+}
+
+const UNDECLARED_IDENTIFIER: &str = "
+.version 6.5
+.target sm_70
+.address_size 64
+
+.visible .entry undeclared(
+    .param .u64 output
+)
+{
+    .reg .u64       out_addr;
+    .reg .b32       dst;
+
+    ld.param.u64    out_addr, [output];
+    add.u32         dst, dst, not_declared;
+    st.b32          [out_addr], dst;
+    ret;
+}
+";
+
+#[test]
+fn undeclared_identifier_is_named() {
+    let error = compile_and_assert(UNDECLARED_IDENTIFIER).unwrap_err();
+    assert!(
+        matches!(error.inner(), TranslateError::UnknownIdentifier { name, .. } if name == "not_declared"),
+        "{error:?}"
+    );
+    let location = error.location().unwrap();
+    assert_eq!(location.line, 14);
+    assert_eq!(location.column, 5);
+    assert_eq!(
+        location.snippet,
+        "    add.u32         dst, dst, not_declared;"
+    );
+    assert!(error
+        .to_string()
+        .starts_with("line 14: Use of undeclared identifier \"not_declared\""));
+}
+
+const VECTOR_REGISTER_OFFSET: &str = "
+.version 6.5
+.target sm_70
+.address_size 64
+
+.visible .entry vector_offset(
+    .param .u64 output
+)
+{
+    .reg .u64       out_addr;
+    .reg .v2 .u32   pair;
+    .reg .u32       dst;
+
+    ld.param.u64    out_addr, [output];
+    mov.u32         dst, pair+4;
+    st.u32          [out_addr], dst;
+    ret;
+}
+";
+
+#[test]
+fn unsupported_address_names_the_operand() {
+    let error = compile_and_assert(VECTOR_REGISTER_OFFSET).unwrap_err();
+    assert!(
+        matches!(error.inner(), TranslateError::UnsupportedAddress { operand, .. } if operand == "pair+4"),
+        "{error:?}"
+    );
+}
+
+fn compile_matrix_instruction(instruction: &str, emulate_mma: bool) -> Result<(), TranslateError> {
+    let text = format!(
+        "
+.version 7.0
+.target sm_80
+.address_size 64
+
+.visible .entry matrix(
+    .param .u64 output
+)
+{{
+    .reg .u64       out_addr;
+    .reg .b32       %r<8>;
+    .reg .f32       %f<8>;
+
+    ld.param.u64    out_addr, [output];
+    {instruction}
+    ret;
+}}
+"
+    );
+    let ast = ast::parse_module_checked(&text).unwrap();
+    let attributes = pass::Attributes {
+        clock_rate: 2124000,
+    };
+    let options = pass::TranslateOptions {
+        emulate_mma,
+        ..pass::TranslateOptions::default()
+    };
+    crate::to_llvm_module_with_options(ast, attributes, options)?;
+    Ok(())
+}
+
+fn assert_unsupported_matrix_instruction(error: TranslateError, opcode: &str) {
+    assert!(
+        matches!(error.inner(), TranslateError::UnsupportedInstruction { opcode: actual } if actual == opcode),
+        "{error:?}"
+    );
+    assert!(error.to_string().contains(opcode));
+    assert_eq!(error.location().unwrap().line, 15);
+}
+
+#[test]
+fn mma_m16n8k8_is_emulated_only_on_request() {
+    let mma = "mma.sync.aligned.m16n8k8.row.col.f32.f16.f16.f32 {%f0, %f1, %f2, %f3}, {%r0, %r1}, {%r2}, {%f4, %f5, %f6, %f7};";
+    let error = compile_matrix_instruction(mma, false).unwrap_err();
+    assert_unsupported_matrix_instruction(
+        error,
+        "mma.sync.aligned.m16n8k8.row.col.f32.f16.f16.f32",
+    );
+    compile_matrix_instruction(mma, true).unwrap();
+}
+
+#[test]
+fn unsupported_matrix_shapes_are_rejected() {
+    let unsupported = [
+        (
+            "mma.sync.aligned.m16n8k16.row.col.f32.bf16.bf16.f32 {%f0, %f1, %f2, %f3}, {%r0, %r1, %r2, %r3}, {%r4, %r5}, {%f4, %f5, %f6, %f7};",
+            "mma.sync.aligned.m16n8k16.row.col.f32.bf16.bf16.f32",
+        ),
+        (
+            "mma.sync.aligned.m8n8k4.row.col.f32.f16.f16.f32 {%f0, %f1, %f2, %f3, %f4, %f5, %f6, %f7}, {%r0, %r1}, {%r2, %r3}, {%f0, %f1, %f2, %f3, %f4, %f5, %f6, %f7};",
+            "mma.sync.aligned.m8n8k4.row.col.f32.f16.f16.f32",
+        ),
+        (
+            "wmma.load.a.sync.aligned.row.m16n16k16.global.f16 {%r0, %r1, %r2, %r3, %r4, %r5, %r6, %r7}, [out_addr];",
+            "wmma.load.a.sync.aligned.row.m16n16k16.global.f16",
+        ),
+        (
+            "wmma.mma.sync.aligned.row.col.m16n16k16.f32.f16 {%f0, %f1, %f2, %f3, %f4, %f5, %f6, %f7}, {%r0, %r1, %r2, %r3, %r4, %r5, %r6, %r7}, {%r0, %r1, %r2, %r3, %r4, %r5, %r6, %r7}, {%r0, %r1, %r2, %r3};",
+            "wmma.mma.sync.aligned.row.col.m16n16k16.f32.f16",
+        ),
+    ];
+    for (instruction, opcode) in unsupported {
+        // Emulation doesn't change anything for them
+        let error = compile_matrix_instruction(instruction, true).unwrap_err();
+        assert_unsupported_matrix_instruction(error, opcode);
+    }
+}
+
+#[test]
+fn special_registers_are_read_once_per_function() -> Result<(), TranslateError> {
+    let ntid_branch = include_str!("spirv_run/ntid_branch.ptx");
+    let translate = |hoist_special_registers| {
+        let ast = ast::parse_module_checked(ntid_branch).unwrap();
+        let attributes = pass::Attributes {
+            clock_rate: 2124000,
+        };
+        let options = pass::TranslateOptions {
+            hoist_special_registers,
+            ..pass::TranslateOptions::default()
+        };
+        crate::to_llvm_module_with_options(ast, attributes, options)
+    };
+    let count_ntid_calls = |module: &pass::Module| {
+        let ll = module.llvm_ir.print_module_to_string();
+        ll.to_str()
+            .lines()
+            .filter(|line| line.contains("call") && line.contains("@__zluda_ptx_impl_sreg_ntid("))
+            .count()
+    };
+    let repeated = translate(false)?;
+    let hoisted = translate(true)?;
+    assert_eq!(hoisted.statistics.hoisted_special_registers, 1);
+    assert_eq!(count_ntid_calls(&repeated), 2);
+    assert_eq!(count_ntid_calls(&hoisted), 1);
+    Ok(())
+}
+
+#[test]
+fn translation_logs_passes_lowerings_and_emulation() -> Result<(), TranslateError> {
+    let ptx = include_str!("spirv_run/shfl_sync_bfly_b32_pred.ptx");
+    let (ll, messages) = crate::logging::capture::run(|| compile_to_ll(ptx));
+    ll?;
+    let logged = |level: &str, target: &str, text: &str| {
+        let prefix = format!("{level} {target}: ");
+        messages
+            .iter()
+            .any(|message| message.starts_with(&prefix) && message.contains(text))
+    };
+    assert!(logged(
+        "DEBUG",
+        "ptx::pass::trace",
+        "expand_operands: finished"
+    ));
+    assert!(logged(
+        "WARN",
+        "ptx::pass::replace_instructions_with_functions",
+        "shfl.sync.bfly with a predicate is emulated"
+    ));
+    assert!(logged("TRACE", "ptx::pass::llvm::emit", "-> LLVMMul"));
+    Ok(())
+}
+
+// Printing a module and parsing it again must not change how it is compiled
+#[cfg(not(feature = "ci_build"))]
+#[test]
+fn printed_modules_round_trip() {
+    let mut directories = vec![std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src")];
+    let mut round_tripped = 0;
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                directories.push(path);
+                continue;
+            }
+            if path.extension().is_none_or(|extension| extension != "ptx") {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path).unwrap();
+            // spirv_fail has modules which are broken on purpose
+            let module = match ast::parse_module_checked(&text) {
+                Ok(module) => module,
+                Err(_) => continue,
+            };
+            let printed = module.to_string();
+            let reparsed = ast::parse_module_checked(&printed)
+                .unwrap_or_else(|errors| panic!("{}: {:?}\n{}", path.display(), errors, printed));
+            assert_eq!(reparsed.to_string(), printed, "{}", path.display());
+            if let Ok(expected) = compile_to_ll(&text) {
+                assert_eq!(
+                    compile_to_ll(&printed).unwrap(),
+                    expected,
+                    "{}",
+                    path.display()
+                );
+            }
+            round_tripped += 1;
+        }
+    }
+    assert!(round_tripped > 100);
+}
@@ -0,0 +1,333 @@
+// Device resources of the test runners. Everything allocated or loaded is released on drop, so
+// a failing HIP or CUDA call (or a failing assert) in the middle of a launch doesn't leak device
+// memory, modules or contexts into the tests that run after it
+use cuda_types::cuda::{CUcontext, CUdeviceptr_v2, CUfunction, CUmodule};
+use hip_runtime_sys::{hipFunction_t, hipModule_t, hipStream_t};
+use std::error::Error;
+use std::ffi::{c_void, CStr};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ptr;
+
+// Failed HIP or CUDA call, with the arguments as written at the call site
+#[derive(Debug)]
+pub(super) struct ApiError {
+    pub(super) call: &'static str,
+    pub(super) error: String,
+}
+
+impl ApiError {
+    pub(super) fn new(call: &'static str, error: impl Debug) -> Self {
+        Self {
+            call,
+            error: format!("{error:?}"),
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed with {}", self.call, self.error)
+    }
+}
+
+impl Error for ApiError {}
+
+// hip_call!(hipMalloc(&mut ptr, size)) returns Result<(), ApiError>
+#[cfg(not(feature = "dynamic-hip"))]
+macro_rules! hip_call {
+    ($fn_name:ident($($arg:expr),* $(,)?)) => {
+        unsafe { hip_runtime_sys::$fn_name($($arg),*) }.map_err(|err| {
+            $crate::test::spirv_run::device::ApiError::new(
+                concat!(stringify!($fn_name), "(", stringify!($($arg),*), ")"),
+                err,
+            )
+        })
+    };
+}
+
+// With the dynamic-hip feature the functions come from the HIP runtime loaded by the tests
+#[cfg(feature = "dynamic-hip")]
+macro_rules! hip_call {
+    ($fn_name:ident($($arg:expr),* $(,)?)) => {
+        match unsafe { $crate::test::spirv_run::HIP.$fn_name($($arg),*) } {
+            Ok(result) => result.map_err(|err| {
+                $crate::test::spirv_run::device::ApiError::new(
+                    concat!(stringify!($fn_name), "(", stringify!($($arg),*), ")"),
+                    err,
+                )
+            }),
+            // The function is missing from the runtime
+            Err(err) => Err($crate::test::spirv_run::device::ApiError::new(
+                stringify!($fn_name),
+                err,
+            )),
+        }
+    };
+}
+
+// Same as hip_call!, for the functions of the dynamically loaded CUDA driver
+macro_rules! cuda_call {
+    ($fn_name:ident($($arg:expr),* $(,)?)) => {
+        match unsafe { $crate::test::spirv_run::CUDA.$fn_name($($arg),*) } {
+            Ok(result) => result.map_err(|err| {
+                $crate::test::spirv_run::device::ApiError::new(
+                    concat!(stringify!($fn_name), "(", stringify!($($arg),*), ")"),
+                    err,
+                )
+            }),
+            // The function is missing from the driver
+            Err(err) => Err($crate::test::spirv_run::device::ApiError::new(
+                stringify!($fn_name),
+                err,
+            )),
+        }
+    };
+}
+
+pub(super) trait DeviceMemory {
+    unsafe fn alloc(&self, size: usize) -> Result<*mut c_void, ApiError>;
+    unsafe fn copy_to_device(&self, dst: *mut c_void, src: &[u8]) -> Result<(), ApiError>;
+    unsafe fn zero(&self, dst: *mut c_void, size: usize) -> Result<(), ApiError>;
+    unsafe fn copy_to_host(&self, dst: &mut [u8], src: *mut c_void) -> Result<(), ApiError>;
+    // Called on drop, errors are ignored
+    unsafe fn free(&self, ptr: *mut c_void);
+}
+
+pub(super) struct DeviceBuffer<'a, M: DeviceMemory> {
+    memory: &'a M,
+    ptr: *mut c_void,
+    size: usize,
+}
+
+impl<'a, M: DeviceMemory> DeviceBuffer<'a, M> {
+    pub(super) fn new(memory: &'a M, size: usize) -> Result<Self, ApiError> {
+        let ptr = unsafe { memory.alloc(size) }?;
+        Ok(Self { memory, ptr, size })
+    }
+
+    pub(super) fn as_ptr(&self) -> *mut c_void {
+        self.ptr
+    }
+
+    pub(super) fn copy_from(&self, src: &[u8]) -> Result<(), ApiError> {
+        assert!(src.len() <= self.size);
+        unsafe { self.memory.copy_to_device(self.ptr, src) }
+    }
+
+    pub(super) fn zero(&self) -> Result<(), ApiError> {
+        unsafe { self.memory.zero(self.ptr, self.size) }
+    }
+
+    pub(super) fn to_host(&self) -> Result<Vec<u8>, ApiError> {
+        let mut result = vec![0u8; self.size];
+        unsafe { self.memory.copy_to_host(&mut result, self.ptr) }?;
+        Ok(result)
+    }
+}
+
+impl<'a, M: DeviceMemory> Drop for DeviceBuffer<'a, M> {
+    fn drop(&mut self) {
+        unsafe { self.memory.free(self.ptr) };
+    }
+}
+
+pub(super) struct DeviceArgs<'a, M: DeviceMemory> {
+    pub(super) buffers: Vec<DeviceBuffer<'a, M>>,
+    // Indices into `buffers`, in the order of declaration
+    pub(super) out_buffers: Vec<usize>,
+    // Bytes of every argument, as passed to the launch
+    pub(super) values: Vec<Vec<u8>>,
+}
+
+impl<'a, M: DeviceMemory> DeviceArgs<'a, M> {
+    pub(super) fn params(&mut self) -> Vec<*mut c_void> {
+        self.values
+            .iter_mut()
+            .map(|value| value.as_mut_ptr().cast())
+            .collect()
+    }
+
+    // Contents of the out buffers, in the order of declaration
+    pub(super) fn copy_back(&self) -> Result<Vec<Vec<u8>>, ApiError> {
+        self.out_buffers
+            .iter()
+            .map(|&index| self.buffers[index].to_host())
+            .collect()
+    }
+}
+
+// HIP stream, also used for the memory operations of the launch
+pub(super) struct OwnedStream(hipStream_t);
+
+impl OwnedStream {
+    pub(super) fn new() -> Result<Self, ApiError> {
+        let mut stream = hipStream_t(ptr::null_mut());
+        hip_call!(hipStreamCreate(&mut stream))?;
+        Ok(Self(stream))
+    }
+
+    pub(super) fn get(&self) -> hipStream_t {
+        self.0
+    }
+}
+
+impl Drop for OwnedStream {
+    fn drop(&mut self) {
+        hip_call!(hipStreamDestroy(self.0)).ok();
+    }
+}
+
+impl DeviceMemory for OwnedStream {
+    unsafe fn alloc(&self, size: usize) -> Result<*mut c_void, ApiError> {
+        let mut ptr = ptr::null_mut();
+        hip_call!(hipMalloc(&mut ptr, size))?;
+        Ok(ptr)
+    }
+
+    unsafe fn copy_to_device(&self, dst: *mut c_void, src: &[u8]) -> Result<(), ApiError> {
+        use hip_runtime_sys::hipMemcpyKind;
+        hip_call!(hipMemcpyWithStream(
+            dst,
+            src.as_ptr().cast(),
+            src.len(),
+            hipMemcpyKind::hipMemcpyHostToDevice,
+            self.0,
+        ))
+    }
+
+    unsafe fn zero(&self, dst: *mut c_void, size: usize) -> Result<(), ApiError> {
+        hip_call!(hipMemset(dst, 0, size))
+    }
+
+    unsafe fn copy_to_host(&self, dst: &mut [u8], src: *mut c_void) -> Result<(), ApiError> {
+        use hip_runtime_sys::hipMemcpyKind;
+        hip_call!(hipMemcpyWithStream(
+            dst.as_mut_ptr().cast(),
+            src,
+            dst.len(),
+            hipMemcpyKind::hipMemcpyDeviceToHost,
+            self.0,
+        ))
+    }
+
+    unsafe fn free(&self, ptr: *mut c_void) {
+        hip_call!(hipFree(ptr)).ok();
+    }
+}
+
+// CUDA context, current on the thread that created it. Device memory belongs to the context
+pub(super) struct OwnedContext(CUcontext);
+
+impl OwnedContext {
+    pub(super) fn new() -> Result<Self, ApiError> {
+        cuda_call!(cuInit(0))?;
+        let mut context = CUcontext(ptr::null_mut());
+        cuda_call!(cuCtxCreate_v2(&mut context, 0, 0))?;
+        Ok(Self(context))
+    }
+
+    pub(super) fn get(&self) -> CUcontext {
+        self.0
+    }
+}
+
+impl Drop for OwnedContext {
+    fn drop(&mut self) {
+        cuda_call!(cuCtxDestroy_v2(self.0)).ok();
+    }
+}
+
+impl DeviceMemory for OwnedContext {
+    unsafe fn alloc(&self, size: usize) -> Result<*mut c_void, ApiError> {
+        let mut ptr = CUdeviceptr_v2(ptr::null_mut());
+        cuda_call!(cuMemAlloc_v2(&mut ptr, size))?;
+        Ok(ptr.0)
+    }
+
+    unsafe fn copy_to_device(&self, dst: *mut c_void, src: &[u8]) -> Result<(), ApiError> {
+        cuda_call!(cuMemcpyHtoD_v2(
+            CUdeviceptr_v2(dst),
+            src.as_ptr().cast(),
+            src.len()
+        ))
+    }
+
+    unsafe fn zero(&self, dst: *mut c_void, size: usize) -> Result<(), ApiError> {
+        cuda_call!(cuMemsetD8_v2(CUdeviceptr_v2(dst), 0, size))
+    }
+
+    unsafe fn copy_to_host(&self, dst: &mut [u8], src: *mut c_void) -> Result<(), ApiError> {
+        cuda_call!(cuMemcpyDtoH_v2(
+            dst.as_mut_ptr().cast(),
+            CUdeviceptr_v2(src),
+            dst.len()
+        ))
+    }
+
+    unsafe fn free(&self, ptr: *mut c_void) {
+        cuda_call!(cuMemFree_v2(CUdeviceptr_v2(ptr))).ok();
+    }
+}
+
+// Loaded HIP code object or CUDA module
+pub(super) struct OwnedModule<T: Module>(T);
+
+pub(super) trait Module: Copy {
+    fn unload(self) -> Result<(), ApiError>;
+}
+
+impl Module for hipModule_t {
+    fn unload(self) -> Result<(), ApiError> {
+        hip_call!(hipModuleUnload(self))
+    }
+}
+
+impl Module for CUmodule {
+    fn unload(self) -> Result<(), ApiError> {
+        cuda_call!(cuModuleUnload(self))
+    }
+}
+
+impl<T: Module> Drop for OwnedModule<T> {
+    fn drop(&mut self) {
+        self.0.unload().ok();
+    }
+}
+
+impl OwnedModule<hipModule_t> {
+    pub(super) fn load(image: &[u8]) -> Result<Self, ApiError> {
+        let mut module = hipModule_t(ptr::null_mut());
+        hip_call!(hipModuleLoadData(&mut module, image.as_ptr().cast()))?;
+        Ok(Self(module))
+    }
+
+    pub(super) fn function(&self, name: &CStr) -> Result<hipFunction_t, ApiError> {
+        let mut function = hipFunction_t(ptr::null_mut());
+        hip_call!(hipModuleGetFunction(&mut function, self.0, name.as_ptr()))?;
+        Ok(function)
+    }
+}
+
+impl OwnedModule<CUmodule> {
+    // The context must be current
+    pub(super) fn load(image: &CStr) -> Result<Self, ApiError> {
+        let mut module = CUmodule(ptr::null_mut());
+        cuda_call!(cuModuleLoadData(&mut module, image.as_ptr().cast()))?;
+        Ok(Self(module))
+    }
+
+    pub(super) fn function(&self, name: &CStr) -> Result<CUfunction, ApiError> {
+        let mut function = CUfunction(ptr::null_mut());
+        cuda_call!(cuModuleGetFunction(&mut function, self.0, name.as_ptr()))?;
+        Ok(function)
+    }
+}
+
+#[test]
+fn api_error_names_the_call() {
+    let err = ApiError::new("hipMalloc(&mut ptr, size)", std::io::ErrorKind::OutOfMemory);
+    assert_eq!(
+        err.to_string(),
+        "hipMalloc(&mut ptr, size) failed with OutOfMemory"
+    );
+}
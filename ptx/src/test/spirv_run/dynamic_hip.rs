@@ -0,0 +1,124 @@
+// HIP runtime loaded with libloading, used by the tests instead of linking to libamdhip64 when
+// the crate is built with the dynamic-hip feature. Signatures of the functions are taken from
+// hip_runtime_sys, only the functions the test runners call are loaded
+#![allow(non_snake_case)]
+use hip_runtime_sys::*;
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+
+pub(super) struct DynamicHip {
+    lib: libloading::Library,
+}
+
+#[derive(Debug)]
+pub(super) struct LoadError {
+    path: PathBuf,
+    source: libloading::Error,
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} not found — install ROCm or set HIP_PATH ({})",
+            self.path.display(),
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl DynamicHip {
+    #[cfg(not(windows))]
+    const HIP_LIBRARY: &'static str = "libamdhip64.so.6";
+    #[cfg(windows)]
+    const HIP_LIBRARY: &'static str = "amdhip64_6.dll";
+
+    // Same places as the HIP SDK: $HIP_PATH/lib on Linux and $HIP_PATH\bin on Windows. Without
+    // HIP_PATH it's up to the dynamic loader to find the library
+    #[cfg_attr(not(feature = "dynamic-hip"), allow(dead_code))]
+    fn path() -> PathBuf {
+        match env::var_os("HIP_PATH") {
+            Some(hip_path) => {
+                let mut path = PathBuf::from(hip_path);
+                path.push(if cfg!(windows) { "bin" } else { "lib" });
+                path.push(Self::HIP_LIBRARY);
+                path
+            }
+            None => PathBuf::from(Self::HIP_LIBRARY),
+        }
+    }
+
+    #[cfg_attr(not(feature = "dynamic-hip"), allow(dead_code))]
+    pub(super) fn new() -> Result<Self, LoadError> {
+        Self::load(Self::path())
+    }
+
+    pub(super) fn load(path: PathBuf) -> Result<Self, LoadError> {
+        match unsafe { libloading::Library::new(&path) } {
+            Ok(lib) => Ok(Self { lib }),
+            Err(source) => Err(LoadError { path, source }),
+        }
+    }
+}
+
+macro_rules! dynamic_fns {
+    ($($abi:literal fn $fn_name:ident( $($arg_id:ident : $arg_type:ty),* ) -> $ret_type:ty;)*) => {
+        impl DynamicHip {
+        $(
+            #[allow(dead_code)]
+            pub(super) unsafe fn $fn_name(&self, $($arg_id : $arg_type),*) -> Result<$ret_type, libloading::Error> {
+                let func = unsafe { self.lib.get::<unsafe extern $abi fn ($($arg_type),*) -> $ret_type>(concat!(stringify!($fn_name), "\0").as_bytes()) };
+                func.map(|f| f($($arg_id),*) )
+            }
+        )*
+        }
+    };
+}
+
+macro_rules! not_loaded {
+    ($($tokens:tt)*) => {};
+}
+
+cuda_macros::hip_function_declarations!(
+    not_loaded,
+    dynamic_fns
+        <= [
+            hipDeviceGetAttribute,
+            hipDeviceReset,
+            hipFree,
+            hipGetDeviceCount,
+            hipGetDevicePropertiesR0600,
+            hipInit,
+            hipMalloc,
+            hipMemcpyWithStream,
+            hipMemset,
+            hipModuleGetFunction,
+            hipModuleLaunchKernel,
+            hipModuleLoadData,
+            hipModuleUnload,
+            hipSetDevice,
+            hipStreamCreate,
+            hipStreamDestroy,
+            hipStreamSynchronize
+        ]
+);
+
+#[test]
+fn missing_library_is_reported() {
+    let path = PathBuf::from("/nonexistent/libamdhip64.so.6");
+    let err = match DynamicHip::load(path) {
+        Ok(_) => panic!("loaded a library that doesn't exist"),
+        Err(err) => err.to_string(),
+    };
+    assert!(
+        err.starts_with("/nonexistent/libamdhip64.so.6 not found — install ROCm or set HIP_PATH"),
+        "{err}"
+    );
+}
@@ -0,0 +1,561 @@
+// Runs translated kernels on the CPU with ORC, so that changes to the passes can be checked without
+// a GPU. Debug builds of LLVM include the host target for this. Before running, the module is
+// legalized:
+// * the host data layout makes every pointer a 64 bit host pointer, address space casts become
+//   no-ops
+// * special registers (%tid, %ntid, %ctaid, %nctaid) read globals written by the launch loop
+// * shared variables get zero initialized definitions, dynamic shared memory is sized by the
+//   launch
+// * kernels use the C calling convention
+// Threads run one after another. Kernels that rely on threads running together (barriers,
+// atomics, warp-level functions) or call anything specific to AMDGPU are reported as unsupported
+// and the test is skipped. Kernels that are supported, but give different results on the CPU
+// (e.g. flushing denormals) can opt out with a `// no-emulation: <reason>` line
+use super::device::{ApiError, DeviceMemory};
+use super::{KernelArgs, LaunchConfig};
+use crate::pass;
+use llvm_zluda::analysis::{LLVMVerifierFailureAction, LLVMVerifyModule};
+use llvm_zluda::bit_reader::LLVMParseBitcodeInContext2;
+use llvm_zluda::core::*;
+use llvm_zluda::error::*;
+use llvm_zluda::orc2::lljit::*;
+use llvm_zluda::orc2::*;
+use llvm_zluda::prelude::*;
+use llvm_zluda::{LLVMCallConv, LLVMOpcode, LLVMTypeKind, LLVMZludaInitializeNativeTarget};
+use std::alloc::{self, Layout};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr, CString};
+use std::sync::Once;
+use std::{mem, ptr};
+
+const NO_EMULATION_MARKER: &str = "// no-emulation:";
+const SPECIAL_REGISTERS: [&str; 4] = ["tid", "ntid", "ctaid", "nctaid"];
+const SHARED_ADDRESS_SPACE: u32 = 3;
+// Kernels are called through a function pointer type picked by the number of parameters
+const MAX_PARAMS: usize = 8;
+// Generic intrinsics the host backend can lower, possibly to a libm call
+const HOST_INTRINSICS: &[&str] = &[
+    "abs",
+    "bitreverse",
+    "bswap",
+    "ceil",
+    "copysign",
+    "cos",
+    "ctlz",
+    "ctpop",
+    "cttz",
+    "exp2",
+    "fabs",
+    "floor",
+    "fma",
+    "fmuladd",
+    "fshl",
+    "fshr",
+    "log2",
+    "maxnum",
+    "minnum",
+    "rint",
+    "round",
+    "roundeven",
+    "sadd.sat",
+    "sin",
+    "smax",
+    "smin",
+    "sqrt",
+    "ssub.sat",
+    "trunc",
+    "uadd.sat",
+    "umax",
+    "umin",
+    "usub.sat",
+];
+
+#[derive(Debug)]
+pub(super) struct Unsupported(pub(super) String);
+
+// Reason given by the `// no-emulation:` marker of the kernel, if any
+pub(super) fn excluded(ptx_text: &str) -> Option<&str> {
+    ptx_text
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(NO_EMULATION_MARKER))
+        .map(str::trim)
+}
+
+// Runs every thread of the launch and returns the contents of the out buffers
+pub(super) fn run(
+    name: &str,
+    module: &pass::Module,
+    args: &KernelArgs,
+    launch: LaunchConfig,
+) -> Result<Vec<Vec<u8>>, Unsupported> {
+    let memory = HostMemory::default();
+    let mut device_args = args.to_device(&memory).unwrap();
+    let params = device_args.params();
+    let name = CString::new(name).unwrap();
+    let emulator = Emulator::new(module, &name, params.len(), launch.shared_memory)?;
+    for ctaid in indices(launch.grid) {
+        for tid in indices(launch.block) {
+            emulator.set_registers([tid, launch.block, ctaid, launch.grid]);
+            unsafe { call_kernel(emulator.kernel, &params) };
+        }
+    }
+    Ok(device_args.copy_back().unwrap())
+}
+
+fn indices(size: (u32, u32, u32)) -> impl Iterator<Item = (u32, u32, u32)> {
+    (0..size.2)
+        .flat_map(move |z| (0..size.1).flat_map(move |y| (0..size.0).map(move |x| (x, y, z))))
+}
+
+// Kernels take a pointer to each of their parameters
+unsafe fn call_kernel(kernel: LLVMOrcExecutorAddress, params: &[*mut c_void]) {
+    type P = *mut c_void;
+    let kernel = kernel as usize;
+    match *params {
+        [] => mem::transmute::<usize, extern "C" fn()>(kernel)(),
+        [a] => mem::transmute::<usize, extern "C" fn(P)>(kernel)(a),
+        [a, b] => mem::transmute::<usize, extern "C" fn(P, P)>(kernel)(a, b),
+        [a, b, c] => mem::transmute::<usize, extern "C" fn(P, P, P)>(kernel)(a, b, c),
+        [a, b, c, d] => mem::transmute::<usize, extern "C" fn(P, P, P, P)>(kernel)(a, b, c, d),
+        [a, b, c, d, e] => {
+            mem::transmute::<usize, extern "C" fn(P, P, P, P, P)>(kernel)(a, b, c, d, e)
+        }
+        [a, b, c, d, e, f] => {
+            mem::transmute::<usize, extern "C" fn(P, P, P, P, P, P)>(kernel)(a, b, c, d, e, f)
+        }
+        [a, b, c, d, e, f, g] => {
+            mem::transmute::<usize, extern "C" fn(P, P, P, P, P, P, P)>(kernel)(a, b, c, d, e, f, g)
+        }
+        [a, b, c, d, e, f, g, h] => mem::transmute::<usize, extern "C" fn(P, P, P, P, P, P, P, P)>(
+            kernel,
+        )(a, b, c, d, e, f, g, h),
+        _ => unreachable!("more than {MAX_PARAMS} kernel parameters"),
+    }
+}
+
+struct Emulator {
+    jit: LLVMOrcLLJITRef,
+    kernel: LLVMOrcExecutorAddress,
+    // Host addresses of the [3 x i32] globals backing each of SPECIAL_REGISTERS
+    registers: [Option<*mut [u32; 3]>; 4],
+}
+
+impl Emulator {
+    fn new(
+        module: &pass::Module,
+        name: &CStr,
+        params: usize,
+        shared_memory: u32,
+    ) -> Result<Self, Unsupported> {
+        static NATIVE_TARGET: Once = Once::new();
+        NATIVE_TARGET.call_once(|| {
+            assert_eq!(
+                unsafe { LLVMZludaInitializeNativeTarget() },
+                0,
+                "LLVM is built without the host target"
+            )
+        });
+        let mut jit = ptr::null_mut();
+        unsafe {
+            check(
+                LLVMOrcCreateLLJIT(&mut jit, ptr::null_mut()),
+                "create the JIT",
+            )
+        };
+        // Disposes the JIT if the module turns out to be unsupported
+        let mut emulator = Emulator {
+            jit,
+            kernel: 0,
+            registers: [None; 4],
+        };
+        let bitcode = module.llvm_ir.write_bitcode_to_memory();
+        let thread_safe_context = unsafe { LLVMOrcCreateNewThreadSafeContext() };
+        let context = unsafe { LLVMOrcThreadSafeContextGetContext(thread_safe_context) };
+        let buffer = unsafe {
+            LLVMCreateMemoryBufferWithMemoryRangeCopy(
+                bitcode.as_ptr().cast(),
+                bitcode.len(),
+                c"".as_ptr(),
+            )
+        };
+        let mut module = ptr::null_mut();
+        let error = unsafe { LLVMParseBitcodeInContext2(context, buffer, &mut module) };
+        unsafe { LLVMDisposeMemoryBuffer(buffer) };
+        assert_eq!(error, 0, "Failed to parse the translated module");
+        let legalized = unsafe { emulator.legalize(context, module, name, params, shared_memory) };
+        let registers = match legalized {
+            Ok(registers) => registers,
+            Err(err) => {
+                unsafe { LLVMDisposeModule(module) };
+                unsafe { LLVMOrcDisposeThreadSafeContext(thread_safe_context) };
+                return Err(err);
+            }
+        };
+        let mut message = ptr::null_mut();
+        if unsafe {
+            LLVMVerifyModule(
+                module,
+                LLVMVerifierFailureAction::LLVMReturnStatusAction,
+                &mut message,
+            )
+        } != 0
+        {
+            panic!("Legalized module is invalid: {}", unsafe {
+                CStr::from_ptr(message).to_string_lossy()
+            });
+        }
+        // The module keeps the context alive
+        let module = unsafe { LLVMOrcCreateNewThreadSafeModule(module, thread_safe_context) };
+        unsafe { LLVMOrcDisposeThreadSafeContext(thread_safe_context) };
+        unsafe {
+            let dylib = LLVMOrcLLJITGetMainJITDylib(emulator.jit);
+            // libm functions some of the intrinsics are lowered to
+            let mut process_symbols = ptr::null_mut();
+            check(
+                LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess(
+                    &mut process_symbols,
+                    LLVMOrcLLJITGetGlobalPrefix(emulator.jit),
+                    None,
+                    ptr::null_mut(),
+                ),
+                "search the process for symbols",
+            );
+            LLVMOrcJITDylibAddGenerator(dylib, process_symbols);
+            check(
+                LLVMOrcLLJITAddLLVMIRModule(emulator.jit, dylib, module),
+                "add the module",
+            );
+        }
+        emulator.kernel = emulator.lookup(name);
+        emulator.registers =
+            registers.map(|global| global.map(|global| emulator.lookup(&global) as *mut [u32; 3]));
+        Ok(emulator)
+    }
+
+    // Returns the names of the globals backing the special registers the module reads
+    unsafe fn legalize(
+        &self,
+        context: LLVMContextRef,
+        module: LLVMModuleRef,
+        name: &CStr,
+        params: usize,
+        shared_memory: u32,
+    ) -> Result<[Option<CString>; 4], Unsupported> {
+        LLVMSetDataLayout(module, LLVMOrcLLJITGetDataLayoutStr(self.jit));
+        LLVMSetTarget(module, LLVMOrcLLJITGetTripleString(self.jit));
+        let kernel = LLVMGetNamedFunction(module, name.as_ptr());
+        assert!(!kernel.is_null(), "Kernel {name:?} not found");
+        assert_eq!(params, LLVMCountParams(kernel) as usize);
+        if params > MAX_PARAMS {
+            return Err(Unsupported(format!("kernel with {params} parameters")));
+        }
+        for function in functions(module) {
+            if LLVMGetFunctionCallConv(function) == LLVMCallConv::LLVMAMDGPUKERNELCallConv as u32 {
+                LLVMSetFunctionCallConv(function, LLVMCallConv::LLVMCCallConv as u32);
+            }
+        }
+        define_shared_variables(context, module, shared_memory)?;
+        let registers = define_special_registers(context, module);
+        instructions(module)
+            .into_iter()
+            .try_for_each(|instruction| check_supported(instruction))?;
+        Ok(registers)
+    }
+
+    fn lookup(&self, name: &CStr) -> LLVMOrcExecutorAddress {
+        let mut address = 0;
+        unsafe {
+            check(
+                LLVMOrcLLJITLookup(self.jit, &mut address, name.as_ptr()),
+                "look up a symbol",
+            )
+        };
+        address
+    }
+
+    fn set_registers(&self, values: [(u32, u32, u32); 4]) {
+        for (register, (x, y, z)) in self.registers.iter().zip(values) {
+            if let Some(register) = register {
+                unsafe { register.write([x, y, z]) };
+            }
+        }
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        unsafe { check(LLVMOrcDisposeLLJIT(self.jit), "dispose the JIT") };
+    }
+}
+
+unsafe fn check(error: LLVMErrorRef, action: &str) {
+    if error.is_null() {
+        return;
+    }
+    let message = LLVMGetErrorMessage(error);
+    let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeErrorMessage(message);
+    panic!("Failed to {action}: {text}");
+}
+// Shared variables are declared as external globals, static ones get a zero initializer.
+// All dynamic shared variables ([0 x T]) alias the same memory, sized by the launch
+unsafe fn define_shared_variables(
+    context: LLVMContextRef,
+    module: LLVMModuleRef,
+    shared_memory: u32,
+) -> Result<(), Unsupported> {
+    let mut dynamic_shared = None;
+    for global in globals(module) {
+        if LLVMIsDeclaration(global) == 0 {
+            continue;
+        }
+        if LLVMGetPointerAddressSpace(LLVMTypeOf(global)) != SHARED_ADDRESS_SPACE {
+            return Err(Unsupported(format!(
+                "external global {}",
+                value_name(global)
+            )));
+        }
+        let type_ = LLVMGlobalGetValueType(global);
+        if LLVMGetTypeKind(type_) == LLVMTypeKind::LLVMArrayTypeKind
+            && LLVMGetArrayLength2(type_) == 0
+        {
+            let buffer = *dynamic_shared.get_or_insert_with(|| {
+                let type_ = LLVMArrayType2(LLVMInt8TypeInContext(context), shared_memory as u64);
+                let buffer = LLVMAddGlobalInAddressSpace(
+                    module,
+                    type_,
+                    c"__zluda_emulated_dynamic_shared".as_ptr(),
+                    SHARED_ADDRESS_SPACE,
+                );
+                LLVMSetInitializer(buffer, LLVMConstNull(type_));
+                LLVMSetAlignment(buffer, 16);
+                buffer
+            });
+            LLVMReplaceAllUsesWith(global, buffer);
+            LLVMDeleteGlobal(global);
+        } else {
+            LLVMSetInitializer(global, LLVMConstNull(type_));
+        }
+    }
+    Ok(())
+}
+
+// Gives bodies to the special register functions of ptx_impl, they read from a
+// `__zluda_emulated_<register>` [3 x i32] global indexed by the dimension. Returns the names of
+// the globals, for the registers the module reads
+unsafe fn define_special_registers(
+    context: LLVMContextRef,
+    module: LLVMModuleRef,
+) -> [Option<CString>; 4] {
+    let i32_type = LLVMInt32TypeInContext(context);
+    let i64_type = LLVMInt64TypeInContext(context);
+    let array_type = LLVMArrayType2(i32_type, 3);
+    let builder = LLVMCreateBuilderInContext(context);
+    let globals = SPECIAL_REGISTERS.map(|register| {
+        let function_name = CString::new(format!("__zluda_ptx_impl_sreg_{register}")).unwrap();
+        let function = LLVMGetNamedFunction(module, function_name.as_ptr());
+        if function.is_null() || LLVMCountBasicBlocks(function) != 0 {
+            return None;
+        }
+        let global_name = CString::new(format!("__zluda_emulated_{register}")).unwrap();
+        let global = LLVMAddGlobal(module, array_type, global_name.as_ptr());
+        LLVMSetInitializer(global, LLVMConstNull(array_type));
+        let entry = LLVMAppendBasicBlockInContext(context, function, c"".as_ptr());
+        LLVMPositionBuilderAtEnd(builder, entry);
+        let dimension = LLVMBuildZExt(builder, LLVMGetParam(function, 0), i64_type, c"".as_ptr());
+        let mut indices = [LLVMConstInt(i64_type, 0, 0), dimension];
+        let address = LLVMBuildInBoundsGEP2(
+            builder,
+            array_type,
+            global,
+            indices.as_mut_ptr(),
+            indices.len() as u32,
+            c"".as_ptr(),
+        );
+        let value = LLVMBuildLoad2(builder, i32_type, address, c"".as_ptr());
+        LLVMBuildRet(builder, value);
+        Some(global_name)
+    });
+    LLVMDisposeBuilder(builder);
+    globals
+}
+
+unsafe fn check_supported(instruction: LLVMValueRef) -> Result<(), Unsupported> {
+    let opcode = LLVMGetInstructionOpcode(instruction);
+    // Atomics and fences would run, but a thread spinning on a value written by another thread
+    // never finishes
+    let supported = !matches!(
+        opcode,
+        LLVMOpcode::LLVMAtomicRMW
+            | LLVMOpcode::LLVMAtomicCmpXchg
+            | LLVMOpcode::LLVMFence
+            | LLVMOpcode::LLVMIndirectBr
+            | LLVMOpcode::LLVMInvoke
+            | LLVMOpcode::LLVMCallBr
+            | LLVMOpcode::LLVMResume
+            | LLVMOpcode::LLVMLandingPad
+            | LLVMOpcode::LLVMCleanupRet
+            | LLVMOpcode::LLVMCatchRet
+            | LLVMOpcode::LLVMCatchPad
+            | LLVMOpcode::LLVMCleanupPad
+            | LLVMOpcode::LLVMCatchSwitch
+            | LLVMOpcode::LLVMVAArg
+    );
+    if !supported {
+        return Err(Unsupported(format!("{opcode:?} instruction")));
+    }
+    // Converted with compiler-rt functions, which the process doesn't necessarily have
+    let types = (0..LLVMGetNumOperands(instruction) as u32)
+        .map(|index| LLVMTypeOf(LLVMGetOperand(instruction, index)))
+        .chain([LLVMTypeOf(instruction)]);
+    for type_ in types {
+        if is_half(type_) {
+            return Err(Unsupported("half precision value".to_string()));
+        }
+    }
+    // Host pointers don't fit into the 32 bit pointers of shared and local memory
+    let address_type = match opcode {
+        LLVMOpcode::LLVMPtrToInt => LLVMTypeOf(instruction),
+        LLVMOpcode::LLVMIntToPtr => LLVMTypeOf(LLVMGetOperand(instruction, 0)),
+        _ => ptr::null_mut(),
+    };
+    if !address_type.is_null()
+        && (LLVMGetTypeKind(address_type) != LLVMTypeKind::LLVMIntegerTypeKind
+            || LLVMGetIntTypeWidth(address_type) < 64)
+    {
+        return Err(Unsupported("pointer narrower than 64 bits".to_string()));
+    }
+    if opcode == LLVMOpcode::LLVMCall {
+        let callee = LLVMGetCalledValue(instruction);
+        if LLVMIsAFunction(callee).is_null() {
+            return Err(Unsupported("indirect call".to_string()));
+        }
+        if LLVMIsDeclaration(callee) != 0 {
+            let name = value_name(callee);
+            let lowered = name.strip_prefix("llvm.").is_some_and(|intrinsic| {
+                HOST_INTRINSICS.iter().any(|host| {
+                    intrinsic
+                        .strip_prefix(host)
+                        .is_some_and(|suffix| suffix.starts_with('.'))
+                })
+            });
+            if !lowered {
+                return Err(Unsupported(format!("call to {name}")));
+            }
+        }
+    }
+    Ok(())
+}
+
+unsafe fn is_half(type_: LLVMTypeRef) -> bool {
+    match LLVMGetTypeKind(type_) {
+        LLVMTypeKind::LLVMHalfTypeKind | LLVMTypeKind::LLVMBFloatTypeKind => true,
+        LLVMTypeKind::LLVMVectorTypeKind | LLVMTypeKind::LLVMArrayTypeKind => {
+            is_half(LLVMGetElementType(type_))
+        }
+        LLVMTypeKind::LLVMStructTypeKind => (0..LLVMCountStructElementTypes(type_))
+            .any(|index| is_half(LLVMStructGetTypeAtIndex(type_, index))),
+        _ => false,
+    }
+}
+
+unsafe fn value_name(value: LLVMValueRef) -> String {
+    let mut length = 0;
+    let name = LLVMGetValueName2(value, &mut length);
+    String::from_utf8_lossy(std::slice::from_raw_parts(name.cast(), length)).into_owned()
+}
+
+unsafe fn globals(module: LLVMModuleRef) -> Vec<LLVMValueRef> {
+    let mut result = Vec::new();
+    let mut global = LLVMGetFirstGlobal(module);
+    while !global.is_null() {
+        result.push(global);
+        global = LLVMGetNextGlobal(global);
+    }
+    result
+}
+
+unsafe fn functions(module: LLVMModuleRef) -> Vec<LLVMValueRef> {
+    let mut result = Vec::new();
+    let mut function = LLVMGetFirstFunction(module);
+    while !function.is_null() {
+        result.push(function);
+        function = LLVMGetNextFunction(function);
+    }
+    result
+}
+
+unsafe fn instructions(module: LLVMModuleRef) -> Vec<LLVMValueRef> {
+    let mut result = Vec::new();
+    for function in functions(module) {
+        let mut block = LLVMGetFirstBasicBlock(function);
+        while !block.is_null() {
+            let mut instruction = LLVMGetFirstInstruction(block);
+            while !instruction.is_null() {
+                result.push(instruction);
+                instruction = LLVMGetNextInstruction(instruction);
+            }
+            block = LLVMGetNextBasicBlock(block);
+        }
+    }
+    result
+}
+
+// Device memory of the emulator is plain host memory
+#[derive(Default)]
+struct HostMemory {
+    layouts: RefCell<HashMap<*mut c_void, Layout>>,
+}
+
+impl DeviceMemory for HostMemory {
+    unsafe fn alloc(&self, size: usize) -> Result<*mut c_void, ApiError> {
+        let layout = Layout::from_size_align(size.max(1), 16).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) }.cast();
+        self.layouts.borrow_mut().insert(ptr, layout);
+        Ok(ptr)
+    }
+
+    unsafe fn copy_to_device(&self, dst: *mut c_void, src: &[u8]) -> Result<(), ApiError> {
+        unsafe { ptr::copy_nonoverlapping(src.as_ptr(), dst.cast(), src.len()) };
+        Ok(())
+    }
+
+    unsafe fn zero(&self, dst: *mut c_void, size: usize) -> Result<(), ApiError> {
+        unsafe { ptr::write_bytes(dst.cast::<u8>(), 0, size) };
+        Ok(())
+    }
+
+    unsafe fn copy_to_host(&self, dst: &mut [u8], src: *mut c_void) -> Result<(), ApiError> {
+        unsafe { ptr::copy_nonoverlapping(src.cast(), dst.as_mut_ptr(), dst.len()) };
+        Ok(())
+    }
+
+    unsafe fn free(&self, ptr: *mut c_void) {
+        let layout = self.layouts.borrow_mut().remove(&ptr).unwrap();
+        unsafe { alloc::dealloc(ptr.cast(), layout) };
+    }
+}
+
+#[test]
+fn marker_gives_exclusion_reason() {
+    let ptx = ".version 6.5\n// no-emulation: flushes denormals\n.target sm_30";
+    assert_eq!(excluded(ptx), Some("flushes denormals"));
+    assert_eq!(excluded(".version 6.5\n// emulation is fine"), None);
+}
+
+#[test]
+fn atomics_are_unsupported() {
+    let ptx = super::read_test_file!("atom_add.ptx");
+    let ast = ptx_parser::parse_module_checked(&ptx).unwrap();
+    let module = pass::to_llvm_module(
+        ast,
+        pass::Attributes {
+            clock_rate: 2124000,
+        },
+    )
+    .unwrap();
+    let args = KernelArgs::with_input::<u32, u32>(Some(&[2, 4]), 2);
+    let Unsupported(reason) = run("atom_add", &module, &args, LaunchConfig::new(1, 1)).unwrap_err();
+    assert!(reason.contains("AtomicRMW"), "{reason}");
+}
@@ -0,0 +1,697 @@
+// Differential testing of generated kernels: every kernel is run on CUDA and through ZLUDA on
+// HIP and the outputs are compared. Kernels are straight-line code over a single type: the
+// inputs are loaded into registers, each instruction writes a new register and every result
+// is stored to its own output slot.
+//
+// The run is configured with environment variables, because the GPU runners are only
+// available inside the test binary:
+// * ZLUDA_FUZZ_SEED: seed of the run, printed at start, case N uses seed + N
+// * ZLUDA_FUZZ_ITERATIONS: number of generated kernels, 32 by default
+// * ZLUDA_FUZZ_SECONDS: stop after this many seconds even if iterations are left
+// * ZLUDA_FUZZ_REPRO_DIR: where the PTX and inputs of failing cases are written
+// * ZLUDA_VALIDATE=1: also run failing cases with the reference interpreter of the final
+//   statements and report whether the passes or the backend (LLVM, comgr, HIP) are to blame
+//
+//   ZLUDA_FUZZ_ITERATIONS=10000 cargo test -p ptx fuzz -- --ignored --nocapture
+use super::{
+    interpret, run_cuda, skip_cuda, skip_hip, translate_and_run_hip_args, KernelArgs, LaunchConfig,
+};
+use crate::pass::{self, interpret::InterpretError};
+use std::ffi::CString;
+use std::fmt::Write as _;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs};
+
+const INPUT_COUNT: usize = 4;
+const MAX_INSTRUCTIONS: usize = 12;
+// Optional passes in the order they run in the pipeline
+const OPTIONAL_PASSES: [(&str, fn(&mut pass::TranslateOptions) -> &mut bool); 8] = [
+    ("if_convert_predicates", |options| {
+        &mut options.if_convert_predicates
+    }),
+    ("hoist_special_registers", |options| {
+        &mut options.hoist_special_registers
+    }),
+    ("infer_global_state_space", |options| {
+        &mut options.infer_global_state_space
+    }),
+    ("promote_local_variables", |options| {
+        &mut options.promote_local_variables
+    }),
+    ("fold_constants", |options| &mut options.fold_constants),
+    ("deduplicate_values", |options| {
+        &mut options.deduplicate_values
+    }),
+    ("eliminate_dead_code", |options| {
+        &mut options.eliminate_dead_code
+    }),
+    ("scalarize_uniform_loads", |options| {
+        &mut options.scalarize_uniform_loads
+    }),
+];
+
+#[test]
+#[ignore = "requires both an AMD and an NVIDIA GPU"]
+fn fuzz_cuda_against_hip() {
+    if skip_hip() || skip_cuda() {
+        return;
+    }
+    let config = FuzzConfig::from_env();
+    eprintln!("fuzzing with ZLUDA_FUZZ_SEED={}", config.seed);
+    let start = Instant::now();
+    let mut failures = Vec::new();
+    for iteration in 0..config.iterations {
+        if config
+            .time_budget
+            .is_some_and(|budget| start.elapsed() > budget)
+        {
+            break;
+        }
+        let seed = config.seed.wrapping_add(iteration as u64);
+        let case = Case::generate(&mut Rng::new(seed));
+        if divergence(&case).is_none() {
+            continue;
+        }
+        let case = shrink(&case, |case| divergence(case).is_some());
+        let mut report = divergence(&case).unwrap_or_else(|| "divergence is flaky".to_string());
+        if config.validate {
+            report = format!("{report}\n{}", blame(&case));
+        }
+        failures.push(write_reproduction(&config.repro_dir, seed, &case, &report));
+    }
+    assert!(
+        failures.is_empty(),
+        "CUDA and HIP disagree on {} generated kernels, reproductions: {:#?}",
+        failures.len(),
+        failures
+    );
+}
+
+struct FuzzConfig {
+    seed: u64,
+    iterations: usize,
+    time_budget: Option<Duration>,
+    repro_dir: PathBuf,
+    validate: bool,
+}
+
+impl FuzzConfig {
+    fn from_env() -> Self {
+        fn parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+            let value = env::var(name).ok()?;
+            Some(
+                value
+                    .parse()
+                    .unwrap_or_else(|_| panic!("invalid value of {name}: {value}")),
+            )
+        }
+        let seed = parse("ZLUDA_FUZZ_SEED").unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        });
+        FuzzConfig {
+            seed,
+            iterations: parse("ZLUDA_FUZZ_ITERATIONS").unwrap_or(32),
+            time_budget: parse("ZLUDA_FUZZ_SECONDS").map(Duration::from_secs),
+            repro_dir: parse("ZLUDA_FUZZ_REPRO_DIR")
+                .unwrap_or_else(|| env::temp_dir().join("zluda-fuzz")),
+            validate: parse::<u8>("ZLUDA_VALIDATE") == Some(1),
+        }
+    }
+}
+
+// SplitMix64, good enough for picking instructions and reproducible across platforms
+pub(super) struct Rng(u64);
+
+impl Rng {
+    pub(super) fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub(super) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub(super) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub(super) fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    pub(super) fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FuzzType {
+    U32,
+    S32,
+    U64,
+    S64,
+    F32,
+    F64,
+}
+
+impl FuzzType {
+    const ALL: [FuzzType; 6] = [
+        FuzzType::U32,
+        FuzzType::S32,
+        FuzzType::U64,
+        FuzzType::S64,
+        FuzzType::F32,
+        FuzzType::F64,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            FuzzType::U32 => "u32",
+            FuzzType::S32 => "s32",
+            FuzzType::U64 => "u64",
+            FuzzType::S64 => "s64",
+            FuzzType::F32 => "f32",
+            FuzzType::F64 => "f64",
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            FuzzType::U32 | FuzzType::S32 | FuzzType::F32 => 32,
+            FuzzType::U64 | FuzzType::S64 | FuzzType::F64 => 64,
+        }
+    }
+
+    fn size(self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, FuzzType::F32 | FuzzType::F64)
+    }
+
+    fn is_signed(self) -> bool {
+        matches!(self, FuzzType::S32 | FuzzType::S64)
+    }
+
+    fn mask(self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+
+    fn is_nan(self, bits: u64) -> bool {
+        match self {
+            FuzzType::F32 => f32::from_bits(bits as u32).is_nan(),
+            FuzzType::F64 => f64::from_bits(bits).is_nan(),
+            _ => false,
+        }
+    }
+
+    // Edge cases are picked more often than they would be by chance
+    fn random_value(self, rng: &mut Rng) -> u64 {
+        let special: &[u64] = match self {
+            FuzzType::U32 | FuzzType::S32 => &[0, 1, 0xFFFF_FFFF, 0x7FFF_FFFF, 0x8000_0000],
+            FuzzType::U64 | FuzzType::S64 => &[0, 1, u64::MAX, i64::MAX as u64, 1 << 63],
+            FuzzType::F32 => &[
+                0.0f32.to_bits() as u64,
+                (-0.0f32).to_bits() as u64,
+                1.0f32.to_bits() as u64,
+                f32::INFINITY.to_bits() as u64,
+                f32::NEG_INFINITY.to_bits() as u64,
+                f32::NAN.to_bits() as u64,
+                f32::MIN_POSITIVE.to_bits() as u64,
+                // Denormal
+                1,
+                f32::MAX.to_bits() as u64,
+            ],
+            FuzzType::F64 => &[
+                0.0f64.to_bits(),
+                (-0.0f64).to_bits(),
+                1.0f64.to_bits(),
+                f64::INFINITY.to_bits(),
+                f64::NEG_INFINITY.to_bits(),
+                f64::NAN.to_bits(),
+                f64::MIN_POSITIVE.to_bits(),
+                1,
+                f64::MAX.to_bits(),
+            ],
+        };
+        if rng.chance(30) {
+            return *rng.pick(special);
+        }
+        match self {
+            FuzzType::F32 => {
+                (rng.next() as f32 / u64::MAX as f32 * 2000.0 - 1000.0).to_bits() as u64
+            }
+            FuzzType::F64 => (rng.next() as f64 / u64::MAX as f64 * 2000.0 - 1000.0).to_bits(),
+            // Small integers exercise carries and overflow less, but are closer to real code
+            _ if rng.chance(50) => rng.next() % 256,
+            _ => rng.next() & self.mask(),
+        }
+    }
+
+    fn immediate(self, bits: u64) -> String {
+        match self {
+            FuzzType::U32 | FuzzType::U64 => bits.to_string(),
+            FuzzType::S32 => (bits as u32 as i32).to_string(),
+            // The parser reads the magnitude of a negative literal as i64, which can't hold 2^63
+            FuzzType::S64 if bits as i64 == i64::MIN => bits.to_string(),
+            FuzzType::S64 => (bits as i64).to_string(),
+            FuzzType::F32 => format!("0f{:08X}", bits as u32),
+            FuzzType::F64 => format!("0d{:016X}", bits),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand {
+    Reg(usize),
+    Imm(u64),
+    // Shift amounts are always .u32, regardless of the type of the shifted value
+    ShiftAmount(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Instruction {
+    opcode: String,
+    // The first source is always a register, shrinking relies on it
+    sources: Vec<Operand>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Case {
+    type_: FuzzType,
+    inputs: Vec<u64>,
+    instructions: Vec<Instruction>,
+}
+
+impl Case {
+    fn generate(rng: &mut Rng) -> Self {
+        let type_ = *rng.pick(&FuzzType::ALL);
+        let inputs = (0..INPUT_COUNT).map(|_| type_.random_value(rng)).collect();
+        let count = 1 + rng.below(MAX_INSTRUCTIONS);
+        let instructions = (0..count)
+            .map(|index| random_instruction(rng, type_, INPUT_COUNT + index))
+            .collect();
+        Case {
+            type_,
+            inputs,
+            instructions,
+        }
+    }
+
+    fn register_count(&self) -> usize {
+        self.inputs.len() + self.instructions.len()
+    }
+
+    fn to_ptx(&self) -> String {
+        let type_ = self.type_.name();
+        let size = self.type_.size();
+        let mut ptx = String::new();
+        writeln!(ptx, ".version 6.5\n.target sm_30\n.address_size 64\n").unwrap();
+        writeln!(
+            ptx,
+            ".visible .entry fuzz(\n    .param .u64 input,\n    .param .u64 output\n)\n{{"
+        )
+        .unwrap();
+        writeln!(ptx, "    .reg .u64 in_addr;\n    .reg .u64 out_addr;").unwrap();
+        for reg in 0..self.register_count() {
+            writeln!(ptx, "    .reg .{type_} r{reg};").unwrap();
+        }
+        writeln!(ptx, "    ld.param.u64 in_addr, [input];").unwrap();
+        writeln!(ptx, "    ld.param.u64 out_addr, [output];").unwrap();
+        for reg in 0..self.inputs.len() {
+            writeln!(ptx, "    ld.{type_} r{reg}, [in_addr+{}];", reg * size).unwrap();
+        }
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            let dst = self.inputs.len() + index;
+            let sources = instruction
+                .sources
+                .iter()
+                .map(|source| match *source {
+                    Operand::Reg(reg) => format!("r{reg}"),
+                    Operand::Imm(bits) => self.type_.immediate(bits),
+                    Operand::ShiftAmount(amount) => amount.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(ptx, "    {} r{dst}, {sources};", instruction.opcode).unwrap();
+            writeln!(ptx, "    st.{type_} [out_addr+{}], r{dst};", index * size).unwrap();
+        }
+        writeln!(ptx, "    ret;\n}}").unwrap();
+        ptx
+    }
+
+    fn args(&self) -> KernelArgs {
+        KernelArgs::new()
+            .buffer_in(&self.input_bytes())
+            .buffer_out::<u8>(self.instructions.len() * self.type_.size())
+    }
+
+    fn input_bytes(&self) -> Vec<u8> {
+        self.inputs
+            .iter()
+            .flat_map(|value| value.to_le_bytes()[..self.type_.size()].to_vec())
+            .collect()
+    }
+
+    // Uses of the removed result are replaced with its first source
+    fn without_instruction(&self, index: usize) -> Case {
+        let mut result = self.clone();
+        let removed = result.instructions.remove(index);
+        let removed_reg = self.inputs.len() + index;
+        let replacement = removed.sources[0];
+        for instruction in result.instructions[index..].iter_mut() {
+            for source in instruction.sources.iter_mut() {
+                if let Operand::Reg(reg) = *source {
+                    if reg == removed_reg {
+                        *source = replacement;
+                    } else if reg > removed_reg {
+                        *source = Operand::Reg(reg - 1);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+fn random_instruction(rng: &mut Rng, type_: FuzzType, dst: usize) -> Instruction {
+    let t = type_.name();
+    let bits = type_.bits();
+    let (opcode, arity) = if type_.is_float() {
+        let rounding = *rng.pick(&["", ".rn", ".rz", ".rm", ".rp"]);
+        let required_rounding = *rng.pick(&[".rn", ".rz", ".rm", ".rp"]);
+        // .ftz and .sat only exist for f32
+        let ftz = if type_ == FuzzType::F32 && rng.chance(30) {
+            ".ftz"
+        } else {
+            ""
+        };
+        let sat = if type_ == FuzzType::F32 && rng.chance(20) {
+            ".sat"
+        } else {
+            ""
+        };
+        match rng.below(9) {
+            0 => (format!("add{rounding}{ftz}{sat}.{t}"), 2),
+            1 => (format!("sub{rounding}{ftz}{sat}.{t}"), 2),
+            2 => (format!("mul{rounding}{ftz}{sat}.{t}"), 2),
+            3 => (format!("fma{required_rounding}{ftz}{sat}.{t}"), 3),
+            4 => (format!("min{ftz}.{t}"), 2),
+            5 => (format!("max{ftz}.{t}"), 2),
+            6 => (format!("neg{ftz}.{t}"), 1),
+            7 => (format!("abs{ftz}.{t}"), 1),
+            _ => (format!("div{required_rounding}{ftz}.{t}"), 2),
+        }
+    } else {
+        let choices = if type_.is_signed() { 14 } else { 11 };
+        match rng.below(choices) {
+            0 => (format!("add.{t}"), 2),
+            1 => (format!("sub.{t}"), 2),
+            2 => (format!("mul.lo.{t}"), 2),
+            3 => (format!("mul.hi.{t}"), 2),
+            4 => (format!("mad.lo.{t}"), 3),
+            5 => (format!("and.b{bits}"), 2),
+            6 => (format!("or.b{bits}"), 2),
+            7 => (format!("xor.b{bits}"), 2),
+            8 => (format!("not.b{bits}"), 1),
+            9 => {
+                let opcode = if rng.chance(50) {
+                    format!("shl.b{bits}")
+                } else {
+                    format!("shr.{t}")
+                };
+                let amount = rng.below(bits as usize + 1) as u32;
+                return Instruction {
+                    opcode,
+                    sources: vec![random_register(rng, dst), Operand::ShiftAmount(amount)],
+                };
+            }
+            10 => (format!("{}.{t}", rng.pick(&["min", "max"])), 2),
+            11 => (format!("neg.{t}"), 1),
+            12 => (format!("abs.{t}"), 1),
+            _ if type_ == FuzzType::S32 => ("add.sat.s32".to_string(), 2),
+            _ => (format!("sub.{t}"), 2),
+        }
+    };
+    let mut sources = vec![random_register(rng, dst)];
+    for _ in 1..arity {
+        sources.push(if rng.chance(25) {
+            Operand::Imm(type_.random_value(rng))
+        } else {
+            random_register(rng, dst)
+        });
+    }
+    Instruction { opcode, sources }
+}
+
+// Later registers are preferred, so that results feed into each other
+fn random_register(rng: &mut Rng, dst: usize) -> Operand {
+    if rng.chance(50) {
+        Operand::Reg(dst - 1 - rng.below(dst.min(3)))
+    } else {
+        Operand::Reg(rng.below(dst))
+    }
+}
+
+// Removes instructions one by one, as long as the case still fails
+fn shrink(case: &Case, mut fails: impl FnMut(&Case) -> bool) -> Case {
+    let mut current = case.clone();
+    loop {
+        let mut progress = false;
+        for index in (0..current.instructions.len()).rev() {
+            if current.instructions.len() == 1 {
+                break;
+            }
+            let candidate = current.without_instruction(index);
+            if fails(&candidate) {
+                current = candidate;
+                progress = true;
+            }
+        }
+        if !progress {
+            return current;
+        }
+    }
+}
+
+fn divergence(case: &Case) -> Option<String> {
+    let ptx = case.to_ptx();
+    let args = case.args();
+    let launch = LaunchConfig::new(1, 1);
+    let cuda = run_on_cuda(&ptx, &args);
+    let hip = catch_panic(|| {
+        translate_and_run_hip_args("fuzz", &ptx, &args, launch)
+            .map(|mut outputs| outputs.remove(0))
+            .unwrap_or_else(|err| panic!("{err}"))
+    });
+    let (cuda, hip) = match (cuda, hip) {
+        (Ok(cuda), Ok(hip)) => (cuda, hip),
+        (cuda, hip) => {
+            return Some(format!(
+                "a backend failed\nCUDA: {:?}\nHIP: {:?}",
+                cuda.map(|_| ()),
+                hip.map(|_| ())
+            ))
+        }
+    };
+    first_difference(case, &cuda, &hip).map(|(index, cuda, hip)| {
+        format!(
+            "result of `{}` (r{}): CUDA {:#x}, HIP {:#x}",
+            case.instructions[index].opcode,
+            case.inputs.len() + index,
+            cuda,
+            hip
+        )
+    })
+}
+
+fn run_on_cuda(ptx: &str, args: &KernelArgs) -> Result<Vec<u8>, String> {
+    catch_panic(|| {
+        let name = CString::new("fuzz").unwrap();
+        run_cuda(name.as_c_str(), ptx, args, LaunchConfig::new(1, 1))
+            .map(|mut outputs| outputs.remove(0))
+            .unwrap_or_else(|err| panic!("{err}"))
+    })
+}
+
+// Index of the first result that differs and the two results
+fn first_difference(case: &Case, expected: &[u8], actual: &[u8]) -> Option<(usize, u64, u64)> {
+    let size = case.type_.size();
+    let read = |bytes: &[u8], index: usize| {
+        let mut value = [0u8; 8];
+        value[..size].copy_from_slice(&bytes[index * size..][..size]);
+        u64::from_le_bytes(value)
+    };
+    (0..case.instructions.len()).find_map(|index| {
+        let (expected, actual) = (read(expected, index), read(actual, index));
+        // NaN payloads differ between vendors
+        let same = expected == actual || (case.type_.is_nan(expected) && case.type_.is_nan(actual));
+        (!same).then_some((index, expected, actual))
+    })
+}
+
+// Runs the case with the reference interpreter: if the final statements give the CUDA results,
+// the bug is in the backend. Otherwise the optional passes are enabled one by one, in pipeline
+// order, until the results diverge
+fn blame(case: &Case) -> String {
+    let ptx = case.to_ptx();
+    let args = case.args();
+    let cuda = match run_on_cuda(&ptx, &args) {
+        Ok(cuda) => cuda,
+        Err(err) => return format!("validation: CUDA failed: {err}"),
+    };
+    let diverges = |options: &pass::TranslateOptions| {
+        let result =
+            catch_panic(|| interpret::run("fuzz", &ptx, &args, LaunchConfig::new(1, 1), options));
+        match result {
+            Ok(Ok(mut outputs)) => Ok(first_difference(case, &cuda, &outputs.remove(0)).is_some()),
+            Ok(Err(InterpretError::Unsupported(reason))) => Err(format!(
+                "the interpreter does not support the kernel: {reason}"
+            )),
+            Ok(Err(err)) => Err(format!("the interpreter failed: {err}")),
+            Err(err) => Err(format!("the interpreter panicked: {err}")),
+        }
+    };
+    let blame = || -> Result<String, String> {
+        if !diverges(&pass::TranslateOptions::default())? {
+            return Ok(
+                "the passes give the CUDA results, the backend (LLVM, comgr or HIP) is to blame"
+                    .to_string(),
+            );
+        }
+        let mut options = pass::TranslateOptions::default();
+        for (_, enabled) in OPTIONAL_PASSES.iter() {
+            *enabled(&mut options) = false;
+        }
+        if diverges(&options)? {
+            return Ok("the results diverge with every optional pass disabled, a mandatory pass is to blame".to_string());
+        }
+        for (name, enabled) in OPTIONAL_PASSES.iter() {
+            *enabled(&mut options) = true;
+            if diverges(&options)? {
+                return Ok(format!("the results diverge once {name} is enabled"));
+            }
+        }
+        Err("the results diverge only with all passes enabled, which is flaky".to_string())
+    };
+    match blame() {
+        Ok(verdict) | Err(verdict) => format!("validation: {verdict}"),
+    }
+}
+
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "unknown panic".to_string())
+    })
+}
+
+fn write_reproduction(dir: &Path, seed: u64, case: &Case, report: &str) -> PathBuf {
+    fs::create_dir_all(dir).unwrap();
+    let ptx_file = dir.join(format!("case-{seed}.ptx"));
+    fs::write(&ptx_file, case.to_ptx()).unwrap();
+    let inputs = case
+        .inputs
+        .iter()
+        .map(|value| format!("{value:#x}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    fs::write(
+        dir.join(format!("case-{seed}.txt")),
+        format!(
+            "seed: {seed}\ntype: {}\ninputs: [{inputs}]\n{report}\n",
+            case.type_.name()
+        ),
+    )
+    .unwrap();
+    ptx_file
+}
+
+#[test]
+fn generation_is_deterministic() {
+    let first = Case::generate(&mut Rng::new(1234));
+    let second = Case::generate(&mut Rng::new(1234));
+    assert_eq!(first, second);
+    assert_eq!(first.to_ptx(), second.to_ptx());
+}
+
+#[test]
+fn generated_kernels_parse() {
+    for seed in 0..200 {
+        let case = Case::generate(&mut Rng::new(seed));
+        let ptx = case.to_ptx();
+        if let Err(err) = ptx_parser::parse_module_checked(&ptx) {
+            panic!("seed {seed} generated invalid PTX: {err:?}\n{ptx}");
+        }
+        assert_eq!(
+            case.input_bytes().len(),
+            case.inputs.len() * case.type_.size()
+        );
+    }
+}
+
+#[test]
+fn shrinking_keeps_only_failing_instruction() {
+    let is_xor = |instruction: &Instruction| instruction.opcode.starts_with("xor");
+    let case = (0..)
+        .map(|seed| Case::generate(&mut Rng::new(seed)))
+        .find(|case| {
+            case.instructions.len() > 3
+                && case.instructions.iter().filter(|i| is_xor(i)).count() == 1
+        })
+        .unwrap();
+    let shrunk = shrink(&case, |case| case.instructions.iter().any(is_xor));
+    assert_eq!(shrunk.instructions.len(), 1);
+    assert!(is_xor(&shrunk.instructions[0]));
+    for (index, instruction) in shrunk.instructions.iter().enumerate() {
+        for source in instruction.sources.iter() {
+            if let Operand::Reg(reg) = source {
+                assert!(*reg < shrunk.inputs.len() + index);
+            }
+        }
+    }
+}
+
+#[test]
+fn removing_instruction_rewires_uses() {
+    let case = Case {
+        type_: FuzzType::U32,
+        inputs: vec![1, 2],
+        instructions: vec![
+            Instruction {
+                opcode: "add.u32".to_string(),
+                sources: vec![Operand::Reg(0), Operand::Reg(1)],
+            },
+            Instruction {
+                opcode: "mul.lo.u32".to_string(),
+                sources: vec![Operand::Reg(2), Operand::Imm(3)],
+            },
+            Instruction {
+                opcode: "sub.u32".to_string(),
+                sources: vec![Operand::Reg(3), Operand::Reg(2)],
+            },
+        ],
+    };
+    let without_add = case.without_instruction(0);
+    assert_eq!(
+        without_add.instructions[0].sources,
+        [Operand::Reg(0), Operand::Imm(3)]
+    );
+    assert_eq!(
+        without_add.instructions[1].sources,
+        [Operand::Reg(2), Operand::Reg(0)]
+    );
+}
@@ -0,0 +1,164 @@
+// Runs kernels with the reference interpreter of the final statements (pass/interpret.rs), with
+// the same arguments and launches as the GPU tests. Kernels that need threads to run together
+// (shuffles, barriers in blocks with more than one thread) or use approximate math are reported
+// as unsupported and the test is skipped. Kernels that are supported, but are not expected to
+// give the same results, can opt out with a `// no-interpretation: <reason>` line
+use super::device::{ApiError, DeviceMemory};
+use super::{KernelArgs, LaunchConfig};
+use crate::pass::{self, interpret};
+use std::cell::RefCell;
+use std::ffi::c_void;
+
+const NO_INTERPRETATION_MARKER: &str = "// no-interpretation:";
+
+// Reason given by the `// no-interpretation:` marker of the kernel, if any
+pub(super) fn excluded(ptx_text: &str) -> Option<&str> {
+    ptx_text
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(NO_INTERPRETATION_MARKER))
+        .map(str::trim)
+}
+
+// Runs every thread of the launch and returns the contents of the out buffers
+pub(super) fn run(
+    name: &str,
+    ptx_text: &str,
+    args: &KernelArgs,
+    launch: LaunchConfig,
+    options: &pass::TranslateOptions,
+) -> Result<Vec<Vec<u8>>, interpret::InterpretError> {
+    let ast = ptx_parser::parse_module_checked(ptx_text).unwrap();
+    let names = pass::NameInterner::default();
+    let program = interpret::Program::new(ast, options, &names)
+        .unwrap_or_else(|err| panic!("Failed to translate {}: {}", name, err));
+    let memory = InterpreterMemory::default();
+    let device_args = args.to_device(&memory).unwrap();
+    let launch = interpret::Launch {
+        grid: launch.grid,
+        block: launch.block,
+        shared_memory: launch.shared_memory,
+    };
+    program.run(
+        &mut memory.0.borrow_mut(),
+        name,
+        &device_args.values,
+        launch,
+    )?;
+    Ok(device_args.copy_back().unwrap())
+}
+
+// Device memory of the interpreter, pointers are its addresses
+#[derive(Default)]
+struct InterpreterMemory(RefCell<interpret::Memory>);
+
+impl InterpreterMemory {
+    fn error(call: &'static str) -> impl FnOnce(interpret::InterpretError) -> ApiError {
+        move |err| ApiError::new(call, err)
+    }
+}
+
+impl DeviceMemory for InterpreterMemory {
+    unsafe fn alloc(&self, size: usize) -> Result<*mut c_void, ApiError> {
+        let address = self
+            .0
+            .borrow_mut()
+            .alloc(size, 16)
+            .map_err(Self::error("alloc"))?;
+        Ok(address as *mut c_void)
+    }
+
+    unsafe fn copy_to_device(&self, dst: *mut c_void, src: &[u8]) -> Result<(), ApiError> {
+        self.0
+            .borrow_mut()
+            .write(dst as u64, src)
+            .map_err(Self::error("copy_to_device"))
+    }
+
+    unsafe fn zero(&self, dst: *mut c_void, size: usize) -> Result<(), ApiError> {
+        self.0
+            .borrow_mut()
+            .write(dst as u64, &vec![0; size])
+            .map_err(Self::error("zero"))
+    }
+
+    unsafe fn copy_to_host(&self, dst: &mut [u8], src: *mut c_void) -> Result<(), ApiError> {
+        let memory = self.0.borrow();
+        let bytes = memory
+            .read(src as u64, dst.len())
+            .map_err(Self::error("copy_to_host"))?;
+        dst.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    unsafe fn free(&self, ptr: *mut c_void) {
+        self.0.borrow_mut().free(ptr as u64);
+    }
+}
+
+#[test]
+fn marker_gives_exclusion_reason() {
+    let ptx = ".version 6.5\n// no-interpretation: uses rcp.approx\n.target sm_30";
+    assert_eq!(excluded(ptx), Some("uses rcp.approx"));
+    assert_eq!(excluded(".version 6.5\n// interpretation is fine"), None);
+}
+
+#[test]
+fn out_of_bounds_store_faults() {
+    let ptx = "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry out_of_bounds(
+            .param .u64 output
+        )
+        {
+            .reg .u64 out_addr;
+            .reg .u32 value;
+
+            ld.param.u64 out_addr, [output];
+            mov.u32 value, 1;
+            st.u32 [out_addr+4], value;
+            ret;
+        }";
+    let args = KernelArgs::new().buffer_out::<u32>(1);
+    let result = run(
+        "out_of_bounds",
+        ptx,
+        &args,
+        LaunchConfig::new(1, 1),
+        &pass::TranslateOptions::default(),
+    );
+    assert!(
+        matches!(result, Err(interpret::InterpretError::Fault(_))),
+        "{result:?}"
+    );
+}
+
+// Without fp contraction the result is the one of `nvcc --fmad=false`
+#[test]
+fn mul_add_is_rounded_twice_without_fusion() {
+    let ptx = include_str!("mul_add_contract.ptx");
+    let input = [f32::from_bits(0x3F800800), f32::from_bits(0xBF801000)];
+    let args = KernelArgs::new().buffer_in(&input).buffer_out::<f32>(2);
+    let result = |fuse_mul_add| {
+        let options = pass::TranslateOptions {
+            fuse_mul_add,
+            ..pass::TranslateOptions::default()
+        };
+        let outputs = run(
+            "mul_add_contract",
+            ptx,
+            &args,
+            LaunchConfig::new(1, 1),
+            &options,
+        )
+        .unwrap();
+        outputs[0]
+            .chunks(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(result(true), [0x3380_0000, 0]);
+    assert_eq!(result(false), [0, 0]);
+}
@@ -0,0 +1,404 @@
+// Modules translated with TranslateOptions::unique_symbols can be linked together even when they
+// define the same internal functions and variables, kernels keep their names. Functions in
+// TranslateOptions::link_overrides are replaced by their ptx_impl definitions when linking.
+// Modules without kernels keep their functions external, for the modules that call them
+use super::{
+    from_bytes, hip_devices, run_hip_on_device, skip_hip, DeviceSelector, KernelArgs, LaunchConfig,
+    COMGR,
+};
+use crate::pass;
+use llvm_zluda::bit_reader::LLVMParseBitcodeInContext2;
+use llvm_zluda::bit_writer::LLVMWriteBitcodeToMemoryBuffer;
+use llvm_zluda::core::*;
+use llvm_zluda::linker::LLVMLinkModules2;
+use llvm_zluda::prelude::*;
+use std::ffi::{c_void, CString};
+use std::{error, ptr, slice};
+
+// Both modules define `lookup` and `helper`, only the value in `lookup` differs
+fn clashing_module(kernel: &str, value: u32) -> String {
+    format!(
+        "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .global .align 4 .u32 lookup[1] = {{ {value} }};
+
+        .func (.param .u32 result) helper()
+        {{
+            .reg .u32 temp;
+            ld.global.u32 temp, [lookup];
+            st.param.u32 [result], temp;
+            ret;
+        }}
+
+        .visible .entry {kernel}(
+            .param .u64 output
+        )
+        {{
+            .reg .u64 out_addr;
+            .reg .u32 temp;
+            ld.param.u64 out_addr, [output];
+            {{
+                .param .u32 helper_result;
+                call (helper_result), helper;
+                ld.param.u32 temp, [helper_result];
+            }}
+            st.global.u32 [out_addr], temp;
+            ret;
+        }}"
+    )
+}
+
+fn translate(text: &str, unique_symbols: bool) -> pass::Module {
+    translate_with_options(
+        text,
+        pass::TranslateOptions {
+            unique_symbols,
+            ..pass::TranslateOptions::default()
+        },
+    )
+    .unwrap()
+}
+
+fn translate_with_options(
+    text: &str,
+    options: pass::TranslateOptions,
+) -> Result<pass::Module, pass::TranslateError> {
+    let ast = ptx_parser::parse_module_checked(text).unwrap();
+    pass::to_llvm_module_with_options(
+        ast,
+        pass::Attributes {
+            clock_rate: 2124000,
+        },
+        options,
+    )
+}
+
+fn bitcode(module: &pass::Module) -> Vec<u8> {
+    module.llvm_ir.write_bitcode_to_memory().to_vec()
+}
+
+// Without a handler LLVM exits the process on a link error
+extern "C" fn ignore_diagnostic(_: LLVMDiagnosticInfoRef, _: *mut c_void) {}
+
+// Links the bitcode of the modules into one, None if their symbols clash
+fn link(modules: &[&[u8]]) -> Option<Vec<u8>> {
+    let context = unsafe { LLVMContextCreate() };
+    unsafe { LLVMContextSetDiagnosticHandler(context, Some(ignore_diagnostic), ptr::null_mut()) };
+    let parse = |bitcode: &[u8]| {
+        let buffer = unsafe {
+            LLVMCreateMemoryBufferWithMemoryRangeCopy(
+                bitcode.as_ptr().cast(),
+                bitcode.len(),
+                c"".as_ptr(),
+            )
+        };
+        let mut module = ptr::null_mut();
+        let error = unsafe { LLVMParseBitcodeInContext2(context, buffer, &mut module) };
+        unsafe { LLVMDisposeMemoryBuffer(buffer) };
+        assert_eq!(error, 0, "Failed to parse the bitcode");
+        module
+    };
+    let linked = parse(modules[0]);
+    // LLVMLinkModules2 takes ownership of the source module
+    let failed = modules[1..]
+        .iter()
+        .any(|module| unsafe { LLVMLinkModules2(linked, parse(module)) } != 0);
+    let bitcode = (!failed).then(|| unsafe {
+        let buffer = LLVMWriteBitcodeToMemoryBuffer(linked);
+        let bitcode = slice::from_raw_parts(
+            LLVMGetBufferStart(buffer).cast::<u8>(),
+            LLVMGetBufferSize(buffer),
+        )
+        .to_vec();
+        LLVMDisposeMemoryBuffer(buffer);
+        bitcode
+    });
+    unsafe { LLVMDisposeModule(linked) };
+    unsafe { LLVMContextDispose(context) };
+    bitcode
+}
+
+#[test]
+fn internal_symbols_clash_without_unique_symbols() {
+    let first = clashing_module("first", 1);
+    let second = clashing_module("second", 2);
+    let clashing = [translate(&first, false), translate(&second, false)].map(|m| bitcode(&m));
+    assert!(link(&[&clashing[0], &clashing[1]]).is_none());
+    let unique = [translate(&first, true), translate(&second, true)].map(|m| bitcode(&m));
+    assert!(link(&[&unique[0], &unique[1]]).is_some());
+}
+
+#[test]
+fn unique_symbols_keep_kernel_names() {
+    let module = translate(&clashing_module("first", 1), true);
+    let ll = module.llvm_ir.print_module_to_string();
+    let ll = ll.to_str();
+    assert!(ll.contains("@first("));
+    assert!(!ll.contains("@lookup ="));
+    assert!(ll.contains("@lookup."));
+}
+
+#[test]
+fn linked_modules_keep_their_internal_symbols_amdgpu() -> Result<(), Box<dyn error::Error>> {
+    if skip_hip() {
+        return Ok(());
+    }
+    let first = translate(&clashing_module("first", 1), true);
+    let second = translate(&clashing_module("second", 2), true);
+    let linked = link(&[&bitcode(&first), &bitcode(&second)]).unwrap();
+    let devices = hip_devices();
+    for device in DeviceSelector::from_env().select(&devices) {
+        let elf = comgr::compile_bitcode(
+            &*COMGR,
+            &device.arch,
+            &linked,
+            first.linked_bitcode(),
+            &*first.attributes_ir.write_bitcode_to_memory(),
+            first.correctly_rounded_div_sqrt,
+            None,
+        )
+        .unwrap();
+        for (name, expected) in [("first", 1u32), ("second", 2u32)] {
+            let name = CString::new(name)?;
+            let args = KernelArgs::new().buffer_out::<u32>(1);
+            let outputs = run_hip_on_device(&name, device, &elf, &args, LaunchConfig::new(1, 1))?;
+            assert_eq!(from_bytes::<u32>(&outputs[0]), [expected]);
+        }
+    }
+    Ok(())
+}
+
+// The module's own bfe_u32 returns the base unchanged, the one in ptx_impl extracts the bits
+const OVERRIDE_BFE: &str = "
+    .version 6.5
+    .target sm_30
+    .address_size 64
+
+    .func (.reg .u32 result) bfe_u32(
+        .reg .u32 base,
+        .reg .u32 pos,
+        .reg .u32 len
+    )
+    {
+        mov.u32 result, base;
+        ret;
+    }
+
+    .visible .entry override_bfe(
+        .param .u64 input,
+        .param .u64 output
+    )
+    {
+        .reg .u64 in_addr;
+        .reg .u64 out_addr;
+        .reg .u32 base;
+        .reg .u32 pos;
+        .reg .u32 len;
+        .reg .u32 result;
+        ld.param.u64 in_addr, [input];
+        ld.param.u64 out_addr, [output];
+        ld.global.u32 base, [in_addr];
+        mov.u32 pos, 4;
+        mov.u32 len, 8;
+        call (result), bfe_u32, (base, pos, len);
+        st.global.u32 [out_addr], result;
+        ret;
+    }";
+
+fn override_options(name: &str) -> pass::TranslateOptions {
+    let mut options = pass::TranslateOptions::default();
+    options.link_overrides.add(name);
+    options
+}
+
+fn function_definition<'a>(ll: &'a str, name: &str) -> Option<&'a str> {
+    ll.lines()
+        .find(|line| line.starts_with("define") && line.contains(&format!("@{name}(")))
+}
+
+#[test]
+fn overridden_function_is_replaced_by_ptx_impl() {
+    let module = translate_with_options(OVERRIDE_BFE, override_options("bfe_u32")).unwrap();
+    let ll = module.llvm_ir.print_module_to_string();
+    let definition = function_definition(ll.to_str(), "__zluda_ptx_impl_bfe_u32").unwrap();
+    assert!(definition.contains(" weak "));
+    let linked = link(&[&bitcode(&module), module.linked_bitcode()]).unwrap();
+    let linked = String::from_utf8(crate::bitcode_to_ir(linked)).unwrap();
+    let definition = function_definition(&linked, "__zluda_ptx_impl_bfe_u32").unwrap();
+    assert!(!definition.contains(" weak "));
+    assert!(linked.contains("@__ockl_bfe_u32("));
+}
+
+#[test]
+fn link_override_without_ptx_impl_definition_fails() {
+    let err = translate_with_options(
+        &OVERRIDE_BFE.replace("bfe_u32", "bfe_u32_typo"),
+        override_options("bfe_u32_typo"),
+    )
+    .err()
+    .unwrap();
+    assert!(matches!(
+        err,
+        pass::TranslateError::UnresolvedLinkOverride(name)
+            if name == "__zluda_ptx_impl_bfe_u32_typo"
+    ));
+}
+
+#[test]
+fn overridden_function_runs_ptx_impl_amdgpu() -> Result<(), Box<dyn error::Error>> {
+    if skip_hip() {
+        return Ok(());
+    }
+    let module = translate_with_options(OVERRIDE_BFE, override_options("bfe_u32"))?;
+    let devices = hip_devices();
+    for device in DeviceSelector::from_env().select(&devices) {
+        let elf = comgr::compile_bitcode(
+            &*COMGR,
+            &device.arch,
+            &*module.llvm_ir.write_bitcode_to_memory(),
+            module.linked_bitcode(),
+            &*module.attributes_ir.write_bitcode_to_memory(),
+            module.correctly_rounded_div_sqrt,
+            None,
+        )
+        .unwrap();
+        let args = KernelArgs::new()
+            .buffer_in(&[0x1234_5678u32])
+            .buffer_out::<u32>(1);
+        let outputs = run_hip_on_device(
+            c"override_bfe",
+            device,
+            &elf,
+            &args,
+            LaunchConfig::new(1, 1),
+        )?;
+        assert_eq!(from_bytes::<u32>(&outputs[0]), [0x67]);
+    }
+    Ok(())
+}
+
+// Functions and globals only, like the modules cuModuleLoadData gets for device code that other
+// modules link against
+const LIBRARY: &str = "
+    .version 6.5
+    .target sm_30
+    .address_size 64
+
+    .visible .global .align 4 .u32 offset = 100;
+    .visible .global .align 4 .u32 calls;
+
+    .func (.param .b32 result) scale(
+        .param .b32 x
+    )
+    {
+        .reg .u32 temp;
+        ld.param.u32 temp, [x];
+        shl.b32 temp, temp, 1;
+        st.param.u32 [result], temp;
+        ret;
+    }
+
+    .visible .func (.param .b32 result) add_offset(
+        .param .b32 x
+    )
+    {
+        .reg .u32 temp;
+        .reg .u32 off;
+        ld.param.u32 temp, [x];
+        {
+            .param .b32 scale_x;
+            .param .b32 scale_result;
+            st.param.b32 [scale_x], temp;
+            call (scale_result), scale, (scale_x);
+            ld.param.b32 temp, [scale_result];
+        }
+        ld.global.u32 off, [offset];
+        add.u32 temp, temp, off;
+        atom.global.add.u32 off, [calls], 1;
+        st.param.u32 [result], temp;
+        ret;
+    }";
+
+const LIBRARY_USER: &str = "
+    .version 6.5
+    .target sm_30
+    .address_size 64
+
+    .extern .func (.param .b32 result) add_offset(
+        .param .b32 x
+    );
+    .extern .global .align 4 .u32 calls;
+
+    .visible .entry call_library(
+        .param .u64 input,
+        .param .u64 output
+    )
+    {
+        .reg .u64 in_addr;
+        .reg .u64 out_addr;
+        .reg .u32 temp;
+        ld.param.u64 in_addr, [input];
+        ld.param.u64 out_addr, [output];
+        ld.global.u32 temp, [in_addr];
+        {
+            .param .b32 x;
+            .param .b32 result;
+            st.param.b32 [x], temp;
+            call (result), add_offset, (x);
+            ld.param.b32 temp, [result];
+        }
+        st.global.u32 [out_addr], temp;
+        ld.global.u32 temp, [calls];
+        st.global.u32 [out_addr+4], temp;
+        ret;
+    }";
+
+#[test]
+fn library_module_keeps_its_definitions() {
+    let library = translate(LIBRARY, true);
+    assert!(library.kernels.is_empty());
+    let ll = library.llvm_ir.print_module_to_string();
+    let ll = ll.to_str();
+    let definition = function_definition(ll, "add_offset").unwrap();
+    assert!(!definition.contains(" internal "));
+    assert!(ll.contains("@offset = addrspace(1) global i32 100"));
+    assert!(ll.contains("@calls = addrspace(1) global i32 0"));
+    let user = translate(LIBRARY_USER, true);
+    assert!(link(&[&bitcode(&user), &bitcode(&library)]).is_some());
+}
+
+#[test]
+fn kernel_calls_into_library_module_amdgpu() -> Result<(), Box<dyn error::Error>> {
+    if skip_hip() {
+        return Ok(());
+    }
+    let library = translate(LIBRARY, true);
+    let user = translate(LIBRARY_USER, true);
+    let linked = link(&[&bitcode(&user), &bitcode(&library)]).unwrap();
+    let devices = hip_devices();
+    for device in DeviceSelector::from_env().select(&devices) {
+        let elf = comgr::compile_bitcode(
+            &*COMGR,
+            &device.arch,
+            &linked,
+            user.linked_bitcode(),
+            &*user.attributes_ir.write_bitcode_to_memory(),
+            user.correctly_rounded_div_sqrt || library.correctly_rounded_div_sqrt,
+            None,
+        )
+        .unwrap();
+        let args = KernelArgs::new().buffer_in(&[21u32]).buffer_out::<u32>(2);
+        let outputs = run_hip_on_device(
+            c"call_library",
+            device,
+            &elf,
+            &args,
+            LaunchConfig::new(1, 1),
+        )?;
+        assert_eq!(from_bytes::<u32>(&outputs[0]), [142, 1]);
+    }
+    Ok(())
+}
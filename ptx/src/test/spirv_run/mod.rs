@@ -1,12 +1,14 @@
 use super::read_test_file;
 use crate::pass;
 use comgr::Comgr;
-use cuda_types::cuda::CUstream;
-use hip_runtime_sys::hipError_t;
-use pretty_assertions;
+use cuda_types::cuda::{CUdevice_attribute, CUmodule, CUstream};
+use device::{
+    ApiError, DeviceArgs, DeviceBuffer, DeviceMemory, OwnedContext, OwnedModule, OwnedStream,
+};
+use hip_runtime_sys::hipModule_t;
 use std::env;
 use std::error;
-use std::ffi::{CStr, CString};
+use std::ffi::{c_void, CStr, CString};
 use std::fmt::{self, Debug, Display, Formatter};
 use std::fs::{self, File};
 use std::io::Write;
@@ -14,6 +16,19 @@ use std::mem;
 use std::path::Path;
 use std::ptr;
 use std::str;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[macro_use]
+mod device;
+mod dynamic_hip;
+mod emulate;
+mod fuzz;
+mod interpret;
+mod link;
+mod properties;
+mod spirv_target;
 
 macro_rules! test_ptx_llvm {
     ($fn_name:ident) => {
@@ -30,13 +45,62 @@ macro_rules! test_ptx_llvm {
 
 macro_rules! test_ptx {
     ($fn_name:ident, $input:expr, $output:expr) => {
+        test_ptx_gpu!($fn_name, $input, $output);
+        test_ptx_llvm!($fn_name);
+    };
+
+    ($fn_name:ident, $input:expr, $output:expr, launch = ($grid:expr, $block:expr $(, $shared_memory:expr $(, $timeout:expr)?)?)) => {
+        test_ptx_gpu!($fn_name, $input, $output, launch = ($grid, $block $(, $shared_memory $(, $timeout)?)?));
+        test_ptx_llvm!($fn_name);
+    };
+
+    ($fn_name:ident, $input:expr, $output:expr, budget = ($($limit:ident = $value:expr),+)) => {
+        test_ptx_gpu!($fn_name, $input, $output, launch = (1, 1), budget = ($($limit = $value),+));
+        test_ptx_llvm!($fn_name);
+    };
+
+    ($fn_name:ident, $input:expr, $output:expr, tolerance = $tolerance:expr) => {
+        test_ptx_gpu!($fn_name, $input, $output, tolerance = $tolerance);
+        test_ptx_llvm!($fn_name);
+    };
+
+    ($fn_name:ident) => {
+        test_ptx_llvm!($fn_name);
+    };
+}
+
+// Runs the kernel on the GPUs without checking the LLVM IR against a golden file.
+// `launch = (grid, block)` takes either a u32 (x dimension) or a (x, y, z) tuple for each of
+// the dimensions, the expected output must cover every element written by the launch.
+// `launch = (grid, block, bytes)` also sets the size of dynamic shared memory, 0 by default.
+// `launch = (grid, block, bytes, timeout)` overrides the kernel timeout (a Duration) for
+// legitimately long kernels.
+// `budget = (max_vgprs = 32, max_scratch = 0)` fails the HIP test if the compiled kernel uses
+// more resources, see ResourceBudget for the limits.
+// The kernel is also run on the CPU, see emulate.rs for what can be emulated, and by the reference
+// interpreter of the final statements, see interpret.rs for what can be interpreted
+macro_rules! test_ptx_gpu {
+    ($fn_name:ident, $input:expr, $output:expr) => {
+        test_ptx_gpu!($fn_name, $input, $output, launch = (1, 1));
+    };
+
+    ($fn_name:ident, $input:expr, $output:expr, budget = ($($limit:ident = $value:expr),+)) => {
+        test_ptx_gpu!($fn_name, $input, $output, launch = (1, 1), budget = ($($limit = $value),+));
+    };
+
+    ($fn_name:ident, $input:expr, $output:expr, tolerance = $tolerance:expr) => {
+        test_ptx_gpu_tolerance!($fn_name, $input, $output, $tolerance);
+    };
+
+    ($fn_name:ident, $input:expr, $output:expr, launch = ($grid:expr, $block:expr $(, $shared_memory:expr $(, $timeout:expr)?)?) $(, budget = ($($limit:ident = $value:expr),+))?) => {
         paste::item! {
             #[test]
             fn [<$fn_name _amdgpu>]() -> Result<(), Box<dyn std::error::Error>> {
                 let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
                 let input = $input;
                 let output = $output;
-                test_hip_assert(stringify!($fn_name), &ptx, Some(&input), &output, 1)
+                let launch = LaunchConfig::new($grid, $block)$(.shared_memory($shared_memory)$(.timeout($timeout))?)?$(.budget(ResourceBudget::default()$(.$limit($value))+))?;
+                test_hip_assert(stringify!($fn_name), &ptx, Some(&input), &output, launch)
             }
         }
 
@@ -46,15 +110,184 @@ macro_rules! test_ptx {
                 let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
                 let input = $input;
                 let output = $output;
-                test_cuda_assert(stringify!($fn_name), &ptx, Some(&input), &output, 1)
+                let launch = LaunchConfig::new($grid, $block)$(.shared_memory($shared_memory)$(.timeout($timeout))?)?;
+                test_cuda_assert(stringify!($fn_name), &ptx, Some(&input), &output, launch)
             }
         }
 
-        test_ptx_llvm!($fn_name);
+        paste::item! {
+            #[test]
+            fn [<$fn_name _emulated>]() {
+                if let Err(reason) = [<$fn_name _emulate>]() {
+                    eprintln!("skipping emulation of {}: {reason}", stringify!($fn_name));
+                }
+            }
+
+            // Err with the reason if the kernel can't be emulated, see emulation_coverage
+            fn [<$fn_name _emulate>]() -> Result<(), String> {
+                let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
+                let input = $input;
+                let output = $output;
+                let launch = LaunchConfig::new($grid, $block)$(.shared_memory($shared_memory)$(.timeout($timeout))?)?;
+                test_emulate_assert(stringify!($fn_name), &ptx, Some(&input), &output, launch)
+            }
+        }
+
+        paste::item! {
+            #[test]
+            fn [<$fn_name _interp>]() {
+                let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
+                let input = $input;
+                let output = $output;
+                let launch = LaunchConfig::new($grid, $block)$(.shared_memory($shared_memory)$(.timeout($timeout))?)?;
+                test_interpret_assert(stringify!($fn_name), &ptx, Some(&input), &output, launch)
+            }
+        }
     };
+}
 
-    ($fn_name:ident) => {
-        test_ptx_llvm!($fn_name);
+// Same as above, but floating point results only have to be within `tolerance`
+// (`Ulp(n)` or `AbsDiff(x)`) of the expected values
+macro_rules! test_ptx_gpu_tolerance {
+    ($fn_name:ident, $input:expr, $output:expr, $tolerance:expr) => {
+        paste::item! {
+            #[test]
+            fn [<$fn_name _amdgpu>]() -> Result<(), Box<dyn std::error::Error>> {
+                if skip_hip() {
+                    return Ok(());
+                }
+                let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
+                let input = $input;
+                let output = $output;
+                let tolerance = {
+                    use Tolerance::*;
+                    $tolerance
+                };
+                let result = translate_and_run_hip(
+                    stringify!($fn_name),
+                    &ptx,
+                    Some(&input),
+                    &output,
+                    LaunchConfig::new(1, 1),
+                )?;
+                assert_within_tolerance(&result, &output, tolerance);
+                Ok(())
+            }
+        }
+
+        paste::item! {
+            #[test]
+            fn [<$fn_name _cuda>]() -> Result<(), Box<dyn std::error::Error>> {
+                if skip_cuda() {
+                    return Ok(());
+                }
+                let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
+                let input = $input;
+                let output = $output;
+                let tolerance = {
+                    use Tolerance::*;
+                    $tolerance
+                };
+                let name = CString::new(stringify!($fn_name))?;
+                let result = run_cuda_with_input(
+                    name.as_c_str(),
+                    &ptx,
+                    Some(&input),
+                    &output,
+                    LaunchConfig::new(1, 1),
+                )?;
+                assert_within_tolerance(&result, &output, tolerance);
+                Ok(())
+            }
+        }
+    };
+}
+
+// Runs a kernel with an arbitrary signature, `$args` is a KernelArgs with exactly one out
+// buffer, which is compared against `$output`
+macro_rules! test_ptx_args {
+    ($fn_name:ident, $args:expr, $output:expr, launch = ($grid:expr, $block:expr $(, $shared_memory:expr $(, $timeout:expr)?)?)) => {
+        paste::item! {
+            #[test]
+            fn [<$fn_name _amdgpu>]() -> Result<(), Box<dyn std::error::Error>> {
+                if skip_hip() {
+                    return Ok(());
+                }
+                let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
+                let args = $args;
+                let output = $output;
+                let result = translate_and_run_hip_args(
+                    stringify!($fn_name),
+                    &ptx,
+                    &args,
+                    LaunchConfig::new($grid, $block)$(.shared_memory($shared_memory)$(.timeout($timeout))?)?,
+                )?;
+                assert_single_output(result, &output);
+                Ok(())
+            }
+        }
+
+        paste::item! {
+            #[test]
+            fn [<$fn_name _cuda>]() -> Result<(), Box<dyn std::error::Error>> {
+                if skip_cuda() {
+                    return Ok(());
+                }
+                let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
+                let args = $args;
+                let output = $output;
+                let name = CString::new(stringify!($fn_name))?;
+                let result = run_cuda(
+                    name.as_c_str(),
+                    &ptx,
+                    &args,
+                    LaunchConfig::new($grid, $block)$(.shared_memory($shared_memory)$(.timeout($timeout))?)?,
+                )?;
+                assert_single_output(result, &output);
+                Ok(())
+            }
+        }
+
+        paste::item! {
+            #[test]
+            fn [<$fn_name _emulated>]() {
+                if let Err(reason) = [<$fn_name _emulate>]() {
+                    eprintln!("skipping emulation of {}: {reason}", stringify!($fn_name));
+                }
+            }
+
+            // Err with the reason if the kernel can't be emulated, see emulation_coverage
+            fn [<$fn_name _emulate>]() -> Result<(), String> {
+                let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
+                let args = $args;
+                let output = $output;
+                let result = emulate_args(
+                    stringify!($fn_name),
+                    &ptx,
+                    &args,
+                    LaunchConfig::new($grid, $block)$(.shared_memory($shared_memory)$(.timeout($timeout))?)?,
+                )?;
+                assert_single_output(result, &output);
+                Ok(())
+            }
+        }
+
+        paste::item! {
+            #[test]
+            fn [<$fn_name _interp>]() {
+                let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
+                let args = $args;
+                let output = $output;
+                if let Some(result) = interpret_args(
+                    stringify!($fn_name),
+                    &ptx,
+                    &args,
+                    LaunchConfig::new($grid, $block)$(.shared_memory($shared_memory)$(.timeout($timeout))?)?,
+                ) {
+                    assert_single_output(result, &output);
+                }
+            }
+        }
     };
 }
 
@@ -65,7 +298,7 @@ macro_rules! test_ptx_warp {
             fn [<$fn_name _amdgpu>]() -> Result<(), Box<dyn std::error::Error>> {
                 let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
                 let mut output = $output;
-                test_hip_assert(stringify!($fn_name), &ptx, None::<&[u8]>, &mut output, 64)
+                test_hip_assert(stringify!($fn_name), &ptx, None::<&[u8]>, &mut output, LaunchConfig::new(1, 64))
             }
         }
 
@@ -74,7 +307,7 @@ macro_rules! test_ptx_warp {
             fn [<$fn_name _cuda>]() -> Result<(), Box<dyn std::error::Error>> {
                 let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
                 let mut output = $output;
-                test_cuda_assert(stringify!($fn_name), &ptx, None::<&[u8]>, &mut output, 64)
+                test_cuda_assert(stringify!($fn_name), &ptx, None::<&[u8]>, &mut output, LaunchConfig::new(1, 64))
             }
         }
 
@@ -82,12 +315,42 @@ macro_rules! test_ptx_warp {
     };
 }
 
+// Passed by value to the param_struct kernel, fields are only read on the GPU
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct ParamStruct {
+    a: u32,
+    b: u32,
+    c: u64,
+}
+
 test_ptx!(ld_st, [1u64], [1u64]);
 test_ptx!(ld_st_implicit, [0.5f32, 0.25f32], [0.5f32]);
-test_ptx!(mov, [1u64], [1u64]);
-test_ptx!(mul_lo, [1u64], [2u64]);
+test_ptx_gpu!(
+    ld_st_implicit_int,
+    [0x0000_0000_0080_FFFEu64],
+    [0xFFFF_FFFF_FFFF_FFFEu64, 0x0080_FFFE_0000_0080u64]
+);
+test_ptx!(
+    mov,
+    [1u64],
+    [1u64],
+    budget = (max_vgprs = 32, max_scratch = 0)
+);
+test_ptx!(
+    mul_lo,
+    [1u64],
+    [2u64],
+    budget = (max_vgprs = 32, max_scratch = 0)
+);
 test_ptx!(mul_hi, [u64::max_value()], [1u64]);
-test_ptx!(add, [1u64], [2u64]);
+test_ptx!(
+    add,
+    [1u64],
+    [2u64],
+    budget = (max_vgprs = 32, max_scratch = 0)
+);
 test_ptx!(
     mul24_lo_u32,
     [0b01110101_01010101_01010101u32],
@@ -118,17 +381,180 @@ test_ptx!(cvt_sat_s_u, [-1i32], [0i32]);
 test_ptx!(cvta, [3.0f32], [3.0f32]);
 test_ptx!(block, [1u64], [2u64]);
 test_ptx!(local_align, [1u64], [1u64]);
+// Generic pointer to a .local buffer passed to a function, like printf arguments
+test_ptx_gpu!(local_ptr_call, [41u64], [41u64, 42u64]);
 test_ptx!(call, [1u64], [2u64]);
+// Vector and 64-bit .param return values, accessed at +0 like nvcc does
+test_ptx_gpu!(
+    call_multiword,
+    [0x0123_4567_89AB_CDEFu64],
+    [0x89AB_CDEF_0123_4567u64, 0x89AB_CDEF_0123_4567u64]
+);
 test_ptx!(vector, [1u32, 2u32], [3u32, 3u32]);
 test_ptx!(vector4, [1u32, 2u32, 3u32, 4u32], [4u32]);
 test_ptx!(ld_st_offset, [1u32, 2u32], [2u32, 1u32]);
-test_ptx!(ntid, [3u32], [4u32]);
+// `[symbol+imm]` of .shared and .const variables and `mov` of a symbol with an offset
+test_ptx_gpu!(ld_st_symbol_offset, [1u32, 2u32], [2u32, 1u32, 30u32]);
+test_ptx!(ntid, [3u32], [7u32], launch = (1, 4));
+test_ptx_gpu!(
+    ntid_branch,
+    [0u32],
+    [7u32, 15, 16, 17, 18, 19, 20],
+    launch = (1, 7)
+);
 test_ptx!(reg_local, [12u64], [13u64]);
 test_ptx!(mov_address, [0xDEADu64], [0u64]);
+// Addresses taken with mov keep their state space for cvta
+test_ptx_gpu!(
+    mov_address_cvta,
+    [0x0123_4567_89AB_CDEFu64],
+    [0x0123_4567_89AB_CDEFu64, 0x0123_4567_89AB_CDEFu64]
+);
+// Kernel parameters with .ptr .global .align annotations
+test_ptx_gpu!(param_ptr, [1u64], [2u64]);
 test_ptx!(b64tof64, [111u64], [111u64]);
 // This segfaults NV compiler
 // test_ptx!(implicit_param, [34u32], [34u32]);
 test_ptx!(pred_not, [10u64, 11u64], [2u64, 0u64]);
+test_ptx_gpu!(pred_st, [10u64, 11u64], [10u64, 0u64]);
+test_ptx_gpu!(pred_call, [1u64, 2u64], [2u64]);
+test_ptx_gpu!(pred_spill, [5u32, 7u32], [11u32]);
+test_ptx_gpu!(
+    pred_local,
+    [7u32, 7, 5, 7, 7, 5],
+    [6u32, 17, 10],
+    launch = (1, 3)
+);
+test_ptx_gpu!(ret_early, [1u32, 2, 5], [10u32, 20, 30], launch = (1, 3));
+test_ptx_gpu!(ret_label_end, [0u32, 4], [0u32, 5], launch = (1, 2));
+test_ptx_gpu!(loop_pragmas, [1u32, 2, 3, 4, 5, 6, 7, 8], [120u32]);
+test_ptx_gpu!(
+    do_while_setp,
+    [0u32, 10],
+    [0u32, 1, 45, 10],
+    launch = (1, 2)
+);
+test_ptx_gpu!(
+    irreducible_diamond,
+    [4u32, 7],
+    [59u32, 10, 50, 7],
+    launch = (1, 2)
+);
+test_ptx_gpu!(branch_to_self, [5u32, 2], [486u32, 5]);
+test_ptx_gpu!(branch_to_entry, [9u32], [10u32]);
+test_ptx_gpu!(
+    tid_store,
+    [3u32],
+    std::array::from_fn::<u32, 64, _>(|tid| tid as u32 * 3),
+    launch = (1, 64)
+);
+test_ptx_gpu!(
+    block_reduce,
+    std::array::from_fn::<u32, 64, _>(|tid| tid as u32 + 1),
+    [2080u32],
+    launch = (1, 64)
+);
+test_ptx_gpu!(
+    atom_counter_blocks,
+    [1u32],
+    [128u32, 10u32],
+    launch = (4, 32)
+);
+test_ptx_gpu!(
+    bar_arrive,
+    std::array::from_fn::<u32, 64, _>(|i| i as u32 * 3),
+    std::array::from_fn::<u32, 64, _>(|i| i as u32 * 6 + 1),
+    launch = (1, 128)
+);
+test_ptx_args!(
+    bar_sync_count,
+    KernelArgs::new().buffer_out::<u32>(64),
+    std::array::from_fn::<u32, 64, _>(|i| (i as u32 + 32) % 64 * 3),
+    launch = (1, 128)
+);
+test_ptx_gpu!(
+    atom_shared_histogram,
+    std::array::from_fn::<u32, 128, _>(|i| (i as u32 * 37) % 101),
+    [17u32, 16, 16, 16, 16, 16, 16, 15, 100, 127, 47, 0, 128, 6321],
+    launch = (1, 128)
+);
+test_ptx_gpu!(atom_shared_lock, [3u64], [384u64], launch = (1, 128));
+test_ptx_gpu!(
+    shared_reverse,
+    std::array::from_fn::<u64, 1024, _>(|i| i as u64 * 3),
+    std::array::from_fn::<u64, 1024, _>(|i| (1023 - i) as u64 * 3),
+    launch = (1, 256, 8 * 1024)
+);
+test_ptx_gpu!(smem_size, [0u32], [512u32, 768], launch = (1, 1, 512));
+test_ptx_args!(
+    saxpy,
+    KernelArgs::new()
+        .buffer_in(&std::array::from_fn::<f32, 64, _>(|i| i as f32))
+        .buffer_out::<f32>(64)
+        .scalar(50u32),
+    std::array::from_fn::<f32, 64, _>(|i| if i < 50 { 2.0 * i as f32 + 1.0 } else { 0.0 }),
+    launch = (1, 64)
+);
+test_ptx_args!(
+    saxpy_scalar_params,
+    KernelArgs::new()
+        .scalar(50u32)
+        .buffer_in(&std::array::from_fn::<f32, 64, _>(|i| i as f32))
+        .scalar(2.5f32)
+        .buffer_in_out(&std::array::from_fn::<f32, 64, _>(|i| 100.0 + i as f32)),
+    std::array::from_fn::<f32, 64, _>(|i| if i < 50 {
+        3.5 * i as f32 + 100.0
+    } else {
+        100.0 + i as f32
+    }),
+    launch = (1, 64)
+);
+test_ptx_args!(
+    param_struct,
+    KernelArgs::new()
+        .scalar(ParamStruct {
+            a: 6,
+            b: 7,
+            c: 0xFFFF_FFFF_FFFF,
+        })
+        .buffer_out::<u64>(2),
+    [42u64, 0x1_0000_0000_0000],
+    launch = (1, 1)
+);
+test_ptx_args!(
+    param_large,
+    KernelArgs::new()
+        .scalar(std::array::from_fn::<u32, 1024, _>(|i| i as u32 * 3 + 1))
+        .buffer_out::<u32>(1024),
+    std::array::from_fn::<u32, 1024, _>(|i| i as u32 * 3 + 1),
+    launch = (1, 1)
+);
+test_ptx_args!(
+    param_zero_sized,
+    KernelArgs::new()
+        .buffer_in(&[41u64])
+        .scalar(())
+        .buffer_out::<u64>(1),
+    [42u64],
+    launch = (1, 1)
+);
+test_ptx_args!(
+    uniform_loads,
+    KernelArgs::new()
+        .buffer_in(&std::array::from_fn::<u32, 64, _>(|i| i as u32 * 3 + 1))
+        .buffer_out::<u32>(64)
+        .scalar(5u32)
+        .scalar(24u32),
+    std::array::from_fn::<u32, 64, _>(|i| {
+        let (block, thread) = (i as u32 / 32, i as u32 % 32);
+        if thread < 24 {
+            (5 + block) * 3 + 1 + thread * 3 + 1
+        } else {
+            0
+        }
+    }),
+    launch = (2, 32)
+);
 test_ptx!(mad_s32, [2i32, 3i32, 4i32], [10i32]);
 test_ptx!(mad_wide, [-1i32, 3, 4, 5], [21474836481i64]);
 test_ptx!(
@@ -136,7 +562,28 @@ test_ptx!(
     [0x01_00_00_00__01_00_00_00i64],
     [0x1_00_00_00_00_00_00i64]
 );
+test_ptx_gpu!(
+    carry_chain,
+    [0xd2c5ad9e_ffffffffu64, 0xabb14c69_e72812d6u64],
+    [
+        0x059b0480_18d7ed2au64,
+        0x8d5c0490_20dfc846u64,
+        0x32d556e1_18d7ed2bu64,
+        0xe1aab826_39b7b56fu64
+    ]
+);
 test_ptx!(vector_extract, [1u8, 2u8, 3u8, 4u8], [3u8, 4u8, 1u8, 2u8]);
+test_ptx_gpu!(envreg, [0x12345678u32], [0x12345678u32]);
+test_ptx_gpu!(
+    vector_reg_counted,
+    [1f32, 2f32, 3f32, 4f32, 5f32, 6f32, 7f32, 8f32],
+    [3f32, 4f32, 12f32, 3f32, 8f32, 7f32, 6f32, 5f32]
+);
+test_ptx_gpu!(
+    pack_unpack,
+    [0x11111111_22222222u64, 0x33334444_55556666u64],
+    [0x22222222_11111111u64, 0x55556666_44443333u64]
+);
 test_ptx!(shr, [-2i32], [-1i32]);
 test_ptx!(shr_oob, [-32768i16], [-1i16]);
 test_ptx!(or, [1u64, 2u64], [3u64]);
@@ -144,8 +591,15 @@ test_ptx!(sub, [2u64], [1u64]);
 test_ptx!(min, [555i32, 444i32], [444i32]);
 test_ptx!(max, [555i32, 444i32], [555i32]);
 test_ptx!(global_array, [0xDEADu32], [1u32]);
-test_ptx!(extern_shared, [127u64], [127u64]);
-test_ptx!(extern_shared_call, [121u64], [123u64]);
+test_ptx_gpu!(global_pointer_table, [1u64], [43u32]);
+test_ptx_args!(
+    entry_points,
+    KernelArgs::new().buffer_in(&[6u32]).buffer_out::<u32>(1),
+    [30u32],
+    launch = (1, 1)
+);
+test_ptx!(extern_shared, [127u64], [127u64], launch = (1, 1, 8));
+test_ptx!(extern_shared_call, [121u64], [123u64], launch = (1, 1, 8));
 test_ptx!(rcp, [2f32], [0.5f32]);
 // 0b1_00000000_10000000000000000000000u32 is a large denormal
 // 0x3f000000 is 0.5
@@ -166,20 +620,38 @@ test_ptx!(and, [6u32, 3u32], [2u32]);
 test_ptx!(selp, [100u16, 200u16], [200u16]);
 test_ptx!(selp_true, [100u16, 200u16], [100u16]);
 test_ptx!(fma, [2f32, 3f32, 5f32], [11f32]);
+test_ptx_gpu!(
+    mul_add_contract,
+    [f32::from_bits(0x3F800800), f32::from_bits(0xBF801000)],
+    [f32::from_bits(0x33800000), 0f32]
+);
 test_ptx!(shared_variable, [513u64], [513u64]);
 test_ptx!(shared_ptr_32, [513u64], [513u64]);
 test_ptx!(atom_cas, [91u32, 91u32], [91u32, 100u32]);
 test_ptx!(atom_inc, [100u32], [100u32, 101u32, 0u32]);
 test_ptx!(atom_add, [2u32, 4u32], [2u32, 6u32]);
-test_ptx!(div_approx, [1f32, 2f32], [0.5f32]);
+test_ptx!(div_approx, [1f32, 2f32], [0.5f32], tolerance = Ulp(2));
 test_ptx!(sqrt, [0.25f32], [0.5f32]);
 test_ptx!(sqrt_rn_ftz, [0x1u32], [0x0u32]);
 test_ptx!(rsqrt, [0.25f64], [2f64]);
+test_ptx_gpu!(
+    rcp_rn_f64,
+    [3f64, f64::from_bits(0x3ff0000000000001)],
+    [
+        f64::from_bits(0x3fd5555555555555),
+        f64::from_bits(0x3feffffffffffffe)
+    ]
+);
 test_ptx!(neg, [181i32], [-181i32]);
-test_ptx!(sin, [std::f32::consts::PI / 2f32], [1f32]);
-test_ptx!(cos, [std::f32::consts::PI], [-1f32]);
-test_ptx!(lg2, [512f32], [9f32]);
-test_ptx!(ex2, [10f32], [1024f32]);
+test_ptx!(
+    sin,
+    [std::f32::consts::PI / 2f32],
+    [1f32],
+    tolerance = Ulp(2)
+);
+test_ptx!(cos, [std::f32::consts::PI], [-1f32], tolerance = Ulp(2));
+test_ptx!(lg2, [512f32], [9f32], tolerance = Ulp(2));
+test_ptx!(ex2, [10f32], [1024f32], tolerance = Ulp(2));
 test_ptx!(fmax, [0u16, half::f16::NAN.to_bits()], [0u16]);
 test_ptx!(cvt_rni, [9.5f32, 10.5f32], [10f32, 10f32]);
 test_ptx!(cvt_rzi, [-13.8f32, 12.9f32], [-13f32, 12f32]);
@@ -207,14 +679,44 @@ test_ptx!(
     [0b11000001u32]
 );
 test_ptx!(bfi, [0b10u32, 0b101u32, 0u32, 2u32], [0b110u32]);
+test_ptx_gpu!(
+    wide_ops,
+    [0xFFFFFFFF_00000001u64, 0x00000001_FFFFFFFFu64],
+    [
+        0x00000001_FFFFFFFFu64,
+        0xFFFFFFFF_00000001,
+        0xFFFFFFFF_00000001,
+        0x00000001_FFFFFFFF,
+        0x00000001_00000000,
+        31,
+        33,
+        0x80000000_FFFFFFFF,
+        0xF0,
+        0xFFFFFFFF_FFFFFFF0,
+        0xFFFFFFFF,
+        0x0000000A_BFFFFFFF,
+        0x00000010_00000000,
+        0x0FFFFFFF,
+        0xFFFFFFFF_FFFFFFFF,
+        0,
+        0,
+        0xFFFFFFFF_FFFFFFFF
+    ]
+);
 test_ptx!(stateful_ld_st_simple, [121u64], [121u64]);
 test_ptx!(stateful_ld_st_ntid, [123u64], [123u64]);
 test_ptx!(stateful_ld_st_ntid_chain, [12651u64], [12651u64]);
 test_ptx!(stateful_ld_st_ntid_sub, [96311u64], [96311u64]);
-test_ptx!(shared_ptr_take_address, [97815231u64], [97815231u64]);
+test_ptx!(
+    shared_ptr_take_address,
+    [97815231u64],
+    [97815231u64],
+    launch = (1, 1, 8)
+);
 test_ptx!(cvt_s64_s32, [-1i32], [-1i64]);
 test_ptx!(add_tuning, [2u64], [3u64]);
 test_ptx!(add_non_coherent, [3u64], [4u64]);
+test_ptx_gpu!(add_debug, [1u64], [2u64]);
 test_ptx!(sign_extend, [-1i16], [-1i32]);
 test_ptx!(atom_add_float, [1.25f32, 0.5f32], [1.25f32, 1.75f32]);
 test_ptx!(
@@ -250,11 +752,39 @@ test_ptx!(stateful_neg_offset, [1237518u64], [1237518u64]);
 test_ptx!(const, [0u16], [10u16, 20, 30, 40]);
 test_ptx!(cvt_s16_s8, [0x139231C2u32], [0xFFFFFFC2u32]);
 test_ptx!(cvt_f64_f32, [0.125f32], [0.125f64]);
+// Infinities, NaN and values that would round to an infinity (65520 for f16, 3.5e38 for f32)
+test_ptx_args!(
+    cvt_satfinite,
+    KernelArgs::new()
+        .buffer_in(&[0x7F800000u32, 0xFF800000, 0x7FFFFFFF, 0x477FF000])
+        .buffer_in(&[
+            f64::INFINITY.to_bits(),
+            f64::NEG_INFINITY.to_bits(),
+            0x7FFFFFFFFFFFFFFF,
+            3.5e38f64.to_bits(),
+        ])
+        .buffer_out::<u32>(16),
+    [
+        0x7BFFu32, 0xFBFF, 0x7FFF, 0x7BFF, 0x7F7F, 0xFF7F, 0x7FFF, 0x4780, 0x7C00, 0xFC00, 0x7FFF,
+        0x7C00, 0x7F7FFFFF, 0xFF7FFFFF, 0x7FFFFFFF, 0x7F7FFFFF,
+    ],
+    launch = (1, 4)
+);
 test_ptx!(prmt, [0x70c507d6u32, 0x6fbd4b5cu32], [0x6fbdd65cu32]);
 test_ptx!(activemask, [0u32], [1u32]);
 test_ptx!(membar, [152731u32], [152731u32]);
-test_ptx!(shared_unify_extern, [7681u64, 7682u64], [15363u64]);
-test_ptx!(shared_unify_local, [16752u64, 714u64], [17466u64]);
+test_ptx!(
+    shared_unify_extern,
+    [7681u64, 7682u64],
+    [15363u64],
+    launch = (1, 1, 8)
+);
+test_ptx!(
+    shared_unify_local,
+    [16752u64, 714u64],
+    [17466u64],
+    launch = (1, 1, 8)
+);
 // FIXME: This test currently fails for reasons outside of ZLUDA's control.
 // One of the LLVM passes does not understand that setreg instruction changes
 // global floating point state and assumes that both floating point
@@ -279,7 +809,33 @@ test_ptx!(
 test_ptx!(multiple_return, [5u32], [6u32, 123u32]);
 test_ptx!(warp_sz, [0u8], [32u8]);
 test_ptx!(tanh, [f32::INFINITY], [1.0f32]);
+// tanh.approx.f32 has a maximum relative error of 2^-11
+test_ptx_gpu!(
+    tanh_approx,
+    [0.5f32, -1.25f32, 3.0f32],
+    [0.5f32.tanh(), (-1.25f32).tanh(), 3.0f32.tanh()],
+    tolerance = AbsDiff(1e-3)
+);
+test_ptx_gpu!(
+    ex2_f16,
+    [0.5f32, -1.5f32, 3.3f32].map(half::f16::from_f32),
+    [0.5f32, -1.5f32, 3.3f32].map(|x| half::f16::from_f32(x.exp2())),
+    tolerance = Ulp(2)
+);
 test_ptx!(cp_async, [0u32], [1u32, 2u32, 3u32, 0u32]);
+test_ptx_gpu!(
+    cp_async_pipeline,
+    std::array::from_fn::<u32, 256, _>(|i| i as u32 + 1),
+    std::array::from_fn::<u32, 256, _>(|i| {
+        let src = i / 64 * 64 + 63 - i % 64;
+        if src < 250 {
+            src as u32 + 1
+        } else {
+            0
+        }
+    }),
+    launch = (1, 16)
+);
 // Two test below test very important compiler feature, make sure that you
 // understand fully what's going on before you touch it.
 // The problem is that the full-precision division gets legalized by LLVM
@@ -293,6 +849,41 @@ test_ptx!(
     [0x16A2028Du32, 0x5E89F6AE],
     [0x26u32, 900636404u32]
 );
+// Results must be bit-exact with CUDA: 1/3, quotient of neighbours, subnormal quotient and a
+// quotient which rounds up to infinity
+test_ptx_gpu!(
+    div_rn_f32,
+    [
+        0x3f800000u32,
+        0x40400000,
+        0x3f800001,
+        0x3f800002,
+        0x16A2028D,
+        0x5E89F6AE,
+        0x7F7FFFFF,
+        0x3F7FFFFF
+    ],
+    [0x3eaaaaabu32, 0x3f7ffffe, 0x26, 0x7f800000]
+);
+test_ptx_gpu!(
+    div_rn_f64,
+    [
+        0x3ff0000000000000u64,
+        0x4008000000000000,
+        0x3ff0000000000001,
+        0x3ff0000000000002,
+        0x0010000000000000,
+        0x4008000000000000,
+        0x7FEFFFFFFFFFFFFF,
+        0x3FEFFFFFFFFFFFFF
+    ],
+    [
+        0x3fd5555555555555u64,
+        0x3feffffffffffffe,
+        0x0005555555555555,
+        0x7ff0000000000000
+    ]
+);
 
 test_ptx!(nanosleep, [0u64], [0u64]);
 test_ptx!(shf_l, [0x12345678u32, 0x9abcdef0u32, 12], [0xcdef0123u32]);
@@ -324,6 +915,16 @@ test_ptx!(
 );
 
 test_ptx!(assertfail);
+test_ptx_args!(
+    assertfail_not_taken,
+    KernelArgs::new()
+        .buffer_in(&[7u32])
+        .scalar(7u32)
+        .buffer_out::<u32>(1)
+        .scalar(0x12345678u32),
+    [0x1234567fu32],
+    launch = (1, 1)
+);
 // TODO: not yet supported
 //test_ptx!(func_ptr);
 test_ptx!(lanemask_lt);
@@ -350,6 +951,12 @@ test_ptx_warp!(
         2u32, 2u32, 2u32, 2u32,
     ]
 );
+test_ptx_args!(
+    bar_red_popc,
+    KernelArgs::new().buffer_out::<u32>(256),
+    [11245u32; 256],
+    launch = (1, 256)
+);
 test_ptx_warp!(
     shfl_sync_up_b32_pred,
     [
@@ -402,203 +1009,1513 @@ test_ptx_warp!(
     ]
 );
 
-struct DisplayError<T: Debug> {
-    err: T,
+// What a GPU backend needs to run tests, gathered once per test binary
+struct BackendProbe {
+    // Err when the runtime or the compiler libraries can't be loaded
+    libraries: Result<(), String>,
+    device_count: Result<i32, String>,
 }
 
-impl<T: Debug> Display for DisplayError<T> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        Debug::fmt(&self.err, f)
+impl BackendProbe {
+    fn missing_prerequisite(&self) -> Option<String> {
+        if let Err(err) = &self.libraries {
+            return Some(err.clone());
+        }
+        match &self.device_count {
+            Err(err) => Some(format!("failed to initialize the runtime: {err}")),
+            Ok(0) => Some("no devices found".to_string()),
+            Ok(_) => None,
+        }
     }
 }
 
-impl<T: Debug> Debug for DisplayError<T> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        Debug::fmt(&self.err, f)
+fn probe_hip() -> BackendProbe {
+    let libraries = Comgr::new()
+        .map(drop)
+        .map_err(|err| format!("failed to load comgr: {err}"));
+    #[cfg(feature = "dynamic-hip")]
+    if let Err(err) = dynamic_hip::DynamicHip::new() {
+        return BackendProbe {
+            libraries: libraries.and(Err(err.to_string())),
+            device_count: Ok(0),
+        };
+    }
+    let mut device_count = 0;
+    let device_count = hip_call!(hipInit(0))
+        .and_then(|()| hip_call!(hipGetDeviceCount(&mut device_count)))
+        .map(|()| device_count)
+        .map_err(|err| err.error);
+    BackendProbe {
+        libraries,
+        device_count,
     }
 }
 
-impl<T: Debug> error::Error for DisplayError<T> {}
-
-fn test_hip_assert<
-    Input: From<u8> + Debug + Copy + PartialEq,
-    Output: From<u8> + Debug + Copy + PartialEq + Default,
->(
-    name: &str,
-    ptx_text: &str,
-    input: Option<&[Input]>,
-    output: &[Output],
-    block_dim_x: u32,
-) -> Result<(), Box<dyn error::Error>> {
-    let ast = ptx_parser::parse_module_checked(ptx_text).unwrap();
-    let llvm_ir = pass::to_llvm_module(
-        ast,
-        pass::Attributes {
-            clock_rate: 2124000,
-        },
-    )
-    .unwrap();
-    let name = CString::new(name)?;
-    let result = run_hip(name.as_c_str(), llvm_ir, input, output, block_dim_x)
-        .map_err(|err| DisplayError { err })?;
-    assert_eq!(result.as_slice(), output);
-    Ok(())
+fn probe_cuda() -> BackendProbe {
+    let cuda = match DynamicCuda::new() {
+        Ok(cuda) => cuda,
+        Err(err) => {
+            return BackendProbe {
+                libraries: Err(format!("failed to load {}: {err}", DynamicCuda::path())),
+                device_count: Ok(0),
+            }
+        }
+    };
+    let device_count = (|| {
+        unsafe { cuda.cuInit(0) }
+            .map_err(|err| err.to_string())?
+            .map_err(|err| format!("{err:?}"))?;
+        let mut device_count = 0;
+        unsafe { cuda.cuDeviceGetCount(&mut device_count) }
+            .map_err(|err| err.to_string())?
+            .map_err(|err| format!("{err:?}"))?;
+        Ok(device_count)
+    })();
+    BackendProbe {
+        libraries: Ok(()),
+        device_count,
+    }
 }
 
-fn test_llvm_assert(
-    name: &str,
-    ptx_text: &str,
-    expected_ll: &str,
-) -> Result<(), Box<dyn error::Error>> {
-    let ast = ptx_parser::parse_module_checked(ptx_text).unwrap();
-    let llvm_ir = pass::to_llvm_module(
-        ast,
-        pass::Attributes {
-            clock_rate: 2124000,
-        },
-    )
-    .unwrap();
-    let actual_ll = llvm_ir.llvm_ir.print_module_to_string();
-    let actual_ll = actual_ll.to_str();
-    compare_llvm(name, actual_ll, expected_ll);
-
-    let expected_attributes_ll = read_test_file!(concat!("../ll/_attributes.ll"));
-    let actual_attributes_ll = llvm_ir.attributes_ir.print_module_to_string();
-    let actual_attributes_ll = actual_attributes_ll.to_str();
-    compare_llvm("_attributes", actual_attributes_ll, &expected_attributes_ll);
-    Ok(())
-}
+static HIP_MISSING: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| probe_hip().missing_prerequisite());
+static CUDA_MISSING: std::sync::LazyLock<Option<String>> =
+    std::sync::LazyLock::new(|| probe_cuda().missing_prerequisite());
 
-fn compare_llvm(name: &str, actual_ll: &str, expected_ll: &str) {
-    if actual_ll != expected_ll {
-        let output_dir = env::var("TEST_PTX_LLVM_FAIL_DIR");
-        if let Ok(output_dir) = output_dir {
-            let output_dir = Path::new(&output_dir);
-            fs::create_dir_all(&output_dir).unwrap();
-            let output_file = output_dir.join(format!("{}.ll", name));
-            let mut output_file = File::create(output_file).unwrap();
-            output_file.write_all(actual_ll.as_bytes()).unwrap();
+// GPU tests pass without running on machines that can't run them, unless the backend is
+// explicitly required with ZLUDA_TEST_REQUIRE_HIP=1 or ZLUDA_TEST_REQUIRE_CUDA=1
+fn should_skip(backend: &str, missing: Option<&str>, required: bool) -> bool {
+    match missing {
+        None => false,
+        Some(reason) if required => panic!("{backend} tests are required, but {reason}"),
+        Some(reason) => {
+            eprintln!("skipping {backend} test: {reason}");
+            true
         }
-        let comparison = pretty_assertions::StrComparison::new(&expected_ll, &actual_ll);
-        panic!("assertion failed: `(left == right)`\n\n{}", comparison);
     }
 }
 
-fn test_cuda_assert<
-    Input: From<u8> + Debug + Copy + PartialEq,
-    Output: From<u8> + Debug + Copy + PartialEq + Default,
->(
-    name: &str,
-    ptx_text: &str,
-    input: Option<&[Input]>,
-    output: &[Output],
-    block_dim_x: u32,
-) -> Result<(), Box<dyn error::Error>> {
-    let name = CString::new(name)?;
-    let result = run_cuda(name.as_c_str(), ptx_text, input, output, block_dim_x);
-    assert_eq!(result.as_slice(), output);
-    Ok(())
+fn skip_hip() -> bool {
+    should_skip(
+        "HIP",
+        HIP_MISSING.as_deref(),
+        env::var("ZLUDA_TEST_REQUIRE_HIP").as_deref() == Ok("1"),
+    )
 }
 
-fn run_cuda<Input: From<u8> + Copy + Debug, Output: From<u8> + Copy + Debug + Default>(
-    name: &CStr,
-    ptx_module: &str,
-    input: Option<&[Input]>,
-    output: &[Output],
-    block_dim_x: u32,
-) -> Vec<Output> {
-    unsafe { CUDA.cuInit(0) }.unwrap().unwrap();
-    let ptx_module = CString::new(ptx_module).unwrap();
-    let mut result = vec![0u8.into(); output.len()];
-    {
-        let mut ctx = unsafe { mem::zeroed() };
-        unsafe { CUDA.cuCtxCreate_v2(&mut ctx, 0, 0) }
-            .unwrap()
-            .unwrap();
-        let mut module = unsafe { mem::zeroed() };
-        unsafe { CUDA.cuModuleLoadData(&mut module, ptx_module.as_ptr() as _) }
-            .unwrap()
-            .unwrap();
-        let mut kernel = unsafe { mem::zeroed() };
-        unsafe { CUDA.cuModuleGetFunction(&mut kernel, module, name.as_ptr()) }
-            .unwrap()
-            .unwrap();
-        let mut out_b = unsafe { mem::zeroed() };
-        unsafe { CUDA.cuMemAlloc_v2(&mut out_b, output.len() * mem::size_of::<Output>()) }
-            .unwrap()
-            .unwrap();
-        let mut inp_b = unsafe { mem::zeroed() };
-        if let Some(input) = input {
-            unsafe { CUDA.cuMemAlloc_v2(&mut inp_b, input.len() * mem::size_of::<Input>()) }
-                .unwrap()
-                .unwrap();
-            unsafe {
-                CUDA.cuMemcpyHtoD_v2(
-                    inp_b,
-                    input.as_ptr() as _,
-                    input.len() * mem::size_of::<Input>(),
-                )
-            }
-            .unwrap()
-            .unwrap();
-        }
-        unsafe { CUDA.cuMemsetD8_v2(out_b, 0, output.len() * mem::size_of::<Output>()) }
-            .unwrap()
-            .unwrap();
-        let mut args = if input.is_some() {
-            [&inp_b, &out_b]
-        } else {
-            [&out_b, &out_b]
-        };
-        unsafe {
-            CUDA.cuLaunchKernel(
-                kernel,
-                1,
-                1,
-                1,
-                block_dim_x,
-                1,
-                1,
-                1024,
-                CUstream(ptr::null_mut()),
-                args.as_mut_ptr() as _,
-                ptr::null_mut(),
-            )
-        }
-        .unwrap()
-        .unwrap();
-        unsafe {
-            CUDA.cuMemcpyDtoH_v2(
-                result.as_mut_ptr() as _,
-                out_b,
-                output.len() * mem::size_of::<Output>(),
-            )
-        }
-        .unwrap()
-        .unwrap();
-        unsafe { CUDA.cuStreamSynchronize(CUstream(ptr::null_mut())) }
-            .unwrap()
-            .unwrap();
-        unsafe { CUDA.cuMemFree_v2(inp_b) }.unwrap().unwrap();
-        unsafe { CUDA.cuMemFree_v2(out_b) }.unwrap().unwrap();
-        unsafe { CUDA.cuModuleUnload(module) }.unwrap().unwrap();
-        unsafe { CUDA.cuCtxDestroy_v2(ctx) }.unwrap().unwrap();
-    }
-    result
+fn skip_cuda() -> bool {
+    should_skip(
+        "CUDA",
+        CUDA_MISSING.as_deref(),
+        env::var("ZLUDA_TEST_REQUIRE_CUDA").as_deref() == Ok("1"),
+    )
 }
 
-struct DynamicCuda {
-    lib: libloading::Library,
+#[test]
+fn missing_library_is_reported_first() {
+    let probe = BackendProbe {
+        libraries: Err("failed to load comgr".to_string()),
+        device_count: Err("hipErrorNoDevice".to_string()),
+    };
+    assert_eq!(
+        probe.missing_prerequisite().as_deref(),
+        Some("failed to load comgr")
+    );
 }
 
-impl DynamicCuda {
-    #[cfg(not(windows))]
-    const CUDA_PATH: &'static str = "/usr/lib/x86_64-linux-gnu/libcuda.so.1";
-    #[cfg(windows)]
+#[test]
+fn missing_devices_are_reported() {
+    let no_devices = BackendProbe {
+        libraries: Ok(()),
+        device_count: Ok(0),
+    };
+    assert_eq!(
+        no_devices.missing_prerequisite().as_deref(),
+        Some("no devices found")
+    );
+    let init_failed = BackendProbe {
+        libraries: Ok(()),
+        device_count: Err("CUDA_ERROR_NO_DEVICE".to_string()),
+    };
+    assert_eq!(
+        init_failed.missing_prerequisite().as_deref(),
+        Some("failed to initialize the runtime: CUDA_ERROR_NO_DEVICE")
+    );
+    let available = BackendProbe {
+        libraries: Ok(()),
+        device_count: Ok(2),
+    };
+    assert_eq!(available.missing_prerequisite(), None);
+}
+
+#[test]
+fn missing_prerequisite_skips_unless_required() {
+    assert!(!should_skip("HIP", None, true));
+    assert!(!should_skip("HIP", None, false));
+    assert!(should_skip("HIP", Some("no devices found"), false));
+}
+
+#[test]
+#[should_panic(expected = "HIP tests are required, but no devices found")]
+fn required_backend_fails_instead_of_skipping() {
+    should_skip("HIP", Some("no devices found"), true);
+}
+
+#[derive(Clone, Copy)]
+struct LaunchConfig {
+    grid: (u32, u32, u32),
+    block: (u32, u32, u32),
+    // Bytes of dynamic shared memory (`.extern .shared` arrays)
+    shared_memory: u32,
+    // How long to wait for the kernel before failing the test
+    timeout: Duration,
+    // Only checked by the HIP tests
+    budget: ResourceBudget,
+}
+
+impl LaunchConfig {
+    fn new(grid: impl Into<Dim3>, block: impl Into<Dim3>) -> Self {
+        Self {
+            grid: grid.into().0,
+            block: block.into().0,
+            shared_memory: 0,
+            timeout: default_kernel_timeout(),
+            budget: ResourceBudget::default(),
+        }
+    }
+
+    fn budget(mut self, budget: ResourceBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn shared_memory(mut self, bytes: u32) -> Self {
+        self.shared_memory = bytes;
+        self
+    }
+
+    fn assert_shared_memory_fits(&self, name: &CStr, device_limit: usize) {
+        assert!(
+            self.shared_memory as usize <= device_limit,
+            "{} requests {} bytes of dynamic shared memory, the device limit is {} bytes",
+            name.to_string_lossy(),
+            self.shared_memory,
+            device_limit
+        );
+    }
+
+    // Fails the test on a launch that CUDA would reject, with the limit it breaks
+    fn assert_valid(&self, kernel: &pass::KernelInfo, device: &HipDevice) {
+        let limits = crate::DeviceLimits {
+            max_shared_memory_per_block: device.shared_memory_per_block,
+            ..Default::default()
+        };
+        if let Err(err) = crate::validate_launch(
+            kernel,
+            self.grid,
+            self.block,
+            self.shared_memory as usize,
+            &limits,
+        ) {
+            panic!("Invalid launch of {} on {device}: {err}", kernel.name);
+        }
+    }
+}
+
+// Upper limits on the resources of the compiled kernel, catches code generation regressions
+// that hurt occupancy without changing the results
+#[derive(Clone, Copy, Default)]
+struct ResourceBudget {
+    vgprs: Option<u32>,
+    sgprs: Option<u32>,
+    // Bytes of LDS (PTX .shared)
+    lds: Option<u32>,
+    // Bytes of scratch (PTX .local and spills) per work-item
+    scratch: Option<u32>,
+}
+
+impl ResourceBudget {
+    fn max_vgprs(mut self, count: u32) -> Self {
+        self.vgprs = Some(count);
+        self
+    }
+
+    fn max_sgprs(mut self, count: u32) -> Self {
+        self.sgprs = Some(count);
+        self
+    }
+
+    fn max_lds(mut self, bytes: u32) -> Self {
+        self.lds = Some(bytes);
+        self
+    }
+
+    fn max_scratch(mut self, bytes: u32) -> Self {
+        self.scratch = Some(bytes);
+        self
+    }
+
+    // Returns the exceeded limits
+    fn check(&self, resources: &comgr::KernelResources) -> Vec<String> {
+        [
+            ("VGPRs", self.vgprs, resources.vgpr_count),
+            ("SGPRs", self.sgprs, resources.sgpr_count),
+            ("bytes of LDS", self.lds, resources.group_segment_fixed_size),
+            (
+                "bytes of scratch",
+                self.scratch,
+                resources.private_segment_fixed_size,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(resource, limit, used)| match limit {
+            Some(limit) if used > limit => Some(format!("{used} {resource} (limit {limit})")),
+            _ => None,
+        })
+        .collect()
+    }
+}
+
+#[test]
+fn resource_budget_reports_exceeded_limits() {
+    let resources = comgr::KernelResources {
+        name: "add".to_string(),
+        vgpr_count: 40,
+        sgpr_count: 12,
+        private_segment_fixed_size: 16,
+        ..Default::default()
+    };
+    assert!(ResourceBudget::default().check(&resources).is_empty());
+    assert_eq!(
+        ResourceBudget::default()
+            .max_vgprs(32)
+            .max_sgprs(12)
+            .max_lds(0)
+            .max_scratch(0)
+            .check(&resources),
+        vec![
+            "40 VGPRs (limit 32)".to_string(),
+            "16 bytes of scratch (limit 0)".to_string()
+        ]
+    );
+}
+
+const KERNEL_TIMEOUT_VAR: &str = "ZLUDA_TEST_KERNEL_TIMEOUT";
+
+// In seconds
+fn default_kernel_timeout() -> Duration {
+    let seconds = env::var(KERNEL_TIMEOUT_VAR)
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(30);
+    Duration::from_secs(seconds)
+}
+
+struct Dim3((u32, u32, u32));
+
+impl From<u32> for Dim3 {
+    fn from(x: u32) -> Self {
+        Dim3((x, 1, 1))
+    }
+}
+
+impl From<(u32, u32, u32)> for Dim3 {
+    fn from(dim: (u32, u32, u32)) -> Self {
+        Dim3(dim)
+    }
+}
+
+fn test_hip_assert<
+    Input: From<u8> + Debug + Copy + PartialEq,
+    Output: From<u8> + Debug + Copy + PartialEq + Default,
+>(
+    name: &str,
+    ptx_text: &str,
+    input: Option<&[Input]>,
+    output: &[Output],
+    launch: LaunchConfig,
+) -> Result<(), Box<dyn error::Error>> {
+    if skip_hip() {
+        return Ok(());
+    }
+    let result = translate_and_run_hip(name, ptx_text, input, output, launch)?;
+    assert_eq!(result.as_slice(), output);
+    Ok(())
+}
+
+fn test_emulate_assert<Input: Copy, Output: Copy + Debug + PartialEq>(
+    name: &str,
+    ptx_text: &str,
+    input: Option<&[Input]>,
+    output: &[Output],
+    launch: LaunchConfig,
+) -> Result<(), String> {
+    let args = KernelArgs::with_input::<Input, Output>(input, output.len());
+    let result = emulate_args(name, ptx_text, &args, launch)?;
+    assert_single_output(result, output);
+    Ok(())
+}
+
+// Runs the kernel on the CPU, Err with the reason if it can't be emulated
+fn emulate_args(
+    name: &str,
+    ptx_text: &str,
+    args: &KernelArgs,
+    launch: LaunchConfig,
+) -> Result<Vec<Vec<u8>>, String> {
+    if let Some(reason) = emulate::excluded(ptx_text) {
+        return Err(reason.to_string());
+    }
+    crate::init_logger();
+    let ast = ptx_parser::parse_module_checked(ptx_text).unwrap();
+    let module = pass::to_llvm_module(
+        ast,
+        pass::Attributes {
+            clock_rate: 2124000,
+        },
+    )
+    .unwrap_or_else(|err| panic!("Failed to translate {}: {}", name, err));
+    emulate::run(name, &module, args, launch).map_err(|emulate::Unsupported(reason)| reason)
+}
+
+macro_rules! emulate_fns {
+    ($($fn_name:ident),+) => {
+        paste::paste! {
+            [$((stringify!($fn_name), [<$fn_name _emulate>] as fn() -> Result<(), String>)),+]
+        }
+    };
+}
+
+// The _emulated tests pass when the kernel can't be emulated. This makes sure most of the simple
+// kernels really run on the CPU and give the expected output
+#[test]
+fn emulation_coverage() {
+    let kernels = emulate_fns![
+        ld_st,
+        ld_st_implicit,
+        mov,
+        mul_lo,
+        mul_hi,
+        add,
+        setp,
+        setp_gt,
+        setp_leu,
+        bra,
+        not,
+        shl,
+        cvt_sat_s_u,
+        cvta,
+        block,
+        local_align,
+        call,
+        vector,
+        vector4,
+        ld_st_offset,
+        ntid,
+        reg_local,
+        b64tof64,
+        pred_not,
+        mad_s32,
+        mad_wide,
+        mul_wide,
+        shr,
+        or,
+        sub,
+        min,
+        max,
+        and,
+        selp,
+        selp_true,
+        neg,
+        rem,
+        popc,
+        clz,
+        cvt_s64_s32,
+        sign_extend,
+        tid_store,
+        saxpy,
+        saxpy_scalar_params,
+        ret_early,
+        ret_label_end,
+        branch_to_self
+    ];
+    let skipped = kernels
+        .iter()
+        .filter_map(|(name, emulate)| emulate().err().map(|reason| format!("{name}: {reason}")))
+        .collect::<Vec<_>>();
+    assert!(
+        kernels.len() - skipped.len() >= 30,
+        "Too many kernels can't be emulated:\n{}",
+        skipped.join("\n")
+    );
+}
+
+fn test_interpret_assert<Input: Copy, Output: Copy + Debug + PartialEq>(
+    name: &str,
+    ptx_text: &str,
+    input: Option<&[Input]>,
+    output: &[Output],
+    launch: LaunchConfig,
+) {
+    let args = KernelArgs::with_input::<Input, Output>(input, output.len());
+    if let Some(result) = interpret_args(name, ptx_text, &args, launch) {
+        assert_single_output(result, output);
+    }
+}
+
+// Runs the kernel with the reference interpreter, None if it can't be interpreted
+fn interpret_args(
+    name: &str,
+    ptx_text: &str,
+    args: &KernelArgs,
+    launch: LaunchConfig,
+) -> Option<Vec<Vec<u8>>> {
+    if let Some(reason) = interpret::excluded(ptx_text) {
+        eprintln!("skipping interpretation of {name}: {reason}");
+        return None;
+    }
+    crate::init_logger();
+    match interpret::run(
+        name,
+        ptx_text,
+        args,
+        launch,
+        &pass::TranslateOptions::default(),
+    ) {
+        Ok(result) => Some(result),
+        Err(pass::interpret::InterpretError::Unsupported(reason)) => {
+            eprintln!("skipping interpretation of {name}: {reason}");
+            None
+        }
+        Err(err) => panic!("Failed to interpret {name}: {err}"),
+    }
+}
+
+fn translate_and_run_hip<Input: Copy, Output: Copy>(
+    name: &str,
+    ptx_text: &str,
+    input: Option<&[Input]>,
+    output: &[Output],
+    launch: LaunchConfig,
+) -> Result<Vec<Output>, Box<dyn error::Error>> {
+    let args = KernelArgs::with_input::<Input, Output>(input, output.len());
+    let outputs = translate_and_run_hip_args(name, ptx_text, &args, launch)?;
+    Ok(from_bytes(&outputs[0]))
+}
+
+fn translate_and_run_hip_args(
+    name: &str,
+    ptx_text: &str,
+    args: &KernelArgs,
+    launch: LaunchConfig,
+) -> Result<Vec<Vec<u8>>, Box<dyn error::Error>> {
+    crate::init_logger();
+    let ast = ptx_parser::parse_module_checked(ptx_text).unwrap();
+    let llvm_ir = pass::to_llvm_module(
+        ast,
+        pass::Attributes {
+            clock_rate: 2124000,
+        },
+    )
+    .unwrap_or_else(|err| panic!("Failed to translate {}: {}", name, err));
+    let name = CString::new(name)?;
+    Ok(run_hip(name.as_c_str(), llvm_ir, args, launch)?)
+}
+
+// Arguments of a kernel launch, in the order of declaration in the kernel signature
+#[derive(Default)]
+struct KernelArgs {
+    args: Vec<KernelArg>,
+}
+
+enum KernelArg {
+    BufferIn(Vec<u8>),
+    // Zero-initialized and copied back to the host after the launch, size in bytes
+    BufferOut(usize),
+    // Copied to the device before the launch and back to the host after it
+    BufferInOut(Vec<u8>),
+    // Pointer to the buffer of an earlier argument
+    Alias(usize),
+    Scalar(Vec<u8>),
+}
+
+impl KernelArgs {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    // Arguments of the (input, output) kernels used by test_ptx! and test_ptx_warp!.
+    // Kernels without input take either (output) or (unused, output), passing the output
+    // buffer for both arguments works with either signature
+    fn with_input<Input: Copy, Output>(input: Option<&[Input]>, output_len: usize) -> Self {
+        match input {
+            Some(input) => Self::new()
+                .buffer_in(input)
+                .buffer_out::<Output>(output_len),
+            None => Self::new().buffer_out::<Output>(output_len).alias(0),
+        }
+    }
+
+    fn buffer_in<T: Copy>(mut self, data: &[T]) -> Self {
+        self.args.push(KernelArg::BufferIn(as_bytes(data).to_vec()));
+        self
+    }
+
+    fn buffer_out<T>(mut self, len: usize) -> Self {
+        self.args
+            .push(KernelArg::BufferOut(len * mem::size_of::<T>()));
+        self
+    }
+
+    fn buffer_in_out<T: Copy>(mut self, data: &[T]) -> Self {
+        self.args
+            .push(KernelArg::BufferInOut(as_bytes(data).to_vec()));
+        self
+    }
+
+    fn alias(mut self, index: usize) -> Self {
+        assert!(matches!(
+            self.args.get(index),
+            Some(KernelArg::BufferIn(_) | KernelArg::BufferOut(_) | KernelArg::BufferInOut(_))
+        ));
+        self.args.push(KernelArg::Alias(index));
+        self
+    }
+
+    fn scalar<T: Copy>(mut self, value: T) -> Self {
+        self.args.push(KernelArg::Scalar(
+            as_bytes(std::slice::from_ref(&value)).to_vec(),
+        ));
+        self
+    }
+
+    // Device allocations live until the returned value is dropped
+    fn to_device<'a, M: DeviceMemory>(&self, memory: &'a M) -> Result<DeviceArgs<'a, M>, ApiError> {
+        let mut device_args = DeviceArgs {
+            buffers: Vec::new(),
+            out_buffers: Vec::new(),
+            values: Vec::new(),
+        };
+        for arg in self.args.iter() {
+            let value = match arg {
+                KernelArg::BufferIn(data) => {
+                    let buffer = DeviceBuffer::new(memory, data.len())?;
+                    buffer.copy_from(data)?;
+                    let value = as_bytes(&[buffer.as_ptr()]).to_vec();
+                    device_args.buffers.push(buffer);
+                    value
+                }
+                KernelArg::BufferOut(size) => {
+                    let buffer = DeviceBuffer::new(memory, *size)?;
+                    buffer.zero()?;
+                    let value = as_bytes(&[buffer.as_ptr()]).to_vec();
+                    device_args.out_buffers.push(device_args.buffers.len());
+                    device_args.buffers.push(buffer);
+                    value
+                }
+                KernelArg::BufferInOut(data) => {
+                    let buffer = DeviceBuffer::new(memory, data.len())?;
+                    buffer.copy_from(data)?;
+                    let value = as_bytes(&[buffer.as_ptr()]).to_vec();
+                    device_args.out_buffers.push(device_args.buffers.len());
+                    device_args.buffers.push(buffer);
+                    value
+                }
+                KernelArg::Alias(index) => device_args.values[*index].clone(),
+                KernelArg::Scalar(value) => value.clone(),
+            };
+            device_args.values.push(value);
+        }
+        Ok(device_args)
+    }
+}
+
+fn as_bytes<T: Copy>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr().cast(), mem::size_of_val(data)) }
+}
+
+fn from_bytes<T: Copy>(bytes: &[u8]) -> Vec<T> {
+    bytes
+        .chunks_exact(mem::size_of::<T>())
+        .map(|chunk| unsafe { ptr::read_unaligned(chunk.as_ptr().cast()) })
+        .collect()
+}
+
+fn assert_single_output<T: Copy + Debug + PartialEq>(outputs: Vec<Vec<u8>>, expected: &[T]) {
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(from_bytes::<T>(&outputs[0]), expected);
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Tolerance {
+    // Distance between the two values in units in the last place
+    Ulp(u64),
+    AbsDiff(f64),
+}
+
+trait ApproxEq: Copy + Debug {
+    fn is_nan(self) -> bool;
+    fn to_f64(self) -> f64;
+    // Bits as an integer that is monotonic in the float value, +0 and -0 both map to 0
+    fn ordered_bits(self) -> i64;
+}
+
+macro_rules! impl_approx_eq {
+    ($type_:ty, $sign_bit:expr, $is_nan:expr, $to_f64:expr, $to_bits:expr) => {
+        impl ApproxEq for $type_ {
+            fn is_nan(self) -> bool {
+                $is_nan(self)
+            }
+
+            fn to_f64(self) -> f64 {
+                $to_f64(self)
+            }
+
+            fn ordered_bits(self) -> i64 {
+                let bits = $to_bits(self) as u64;
+                let magnitude = (bits & !$sign_bit) as i64;
+                if bits & $sign_bit != 0 {
+                    -magnitude
+                } else {
+                    magnitude
+                }
+            }
+        }
+    };
+}
+
+impl_approx_eq!(
+    half::f16,
+    1 << 15,
+    half::f16::is_nan,
+    f64::from,
+    half::f16::to_bits
+);
+impl_approx_eq!(f32, 1 << 31, f32::is_nan, f64::from, f32::to_bits);
+impl_approx_eq!(
+    f64,
+    1 << 63,
+    f64::is_nan,
+    std::convert::identity,
+    f64::to_bits
+);
+
+// NaNs compare equal to each other, the payload is not checked
+fn assert_within_tolerance<T: ApproxEq>(result: &[T], expected: &[T], tolerance: Tolerance) {
+    assert_eq!(result.len(), expected.len());
+    for (index, (&actual, &expected)) in result.iter().zip(expected).enumerate() {
+        let ulp_distance = actual.ordered_bits().abs_diff(expected.ordered_bits());
+        let within_tolerance = match (actual.is_nan(), expected.is_nan()) {
+            (true, true) => true,
+            (false, false) => match tolerance {
+                Tolerance::Ulp(max_ulp) => ulp_distance <= max_ulp,
+                Tolerance::AbsDiff(max_diff) => {
+                    ulp_distance == 0 || (actual.to_f64() - expected.to_f64()).abs() <= max_diff
+                }
+            },
+            _ => false,
+        };
+        if !within_tolerance {
+            panic!(
+                "mismatch at index {index}: expected {expected:?}, got {actual:?} \
+                 (ULP distance {ulp_distance}, tolerance {tolerance:?})"
+            );
+        }
+    }
+}
+
+fn test_llvm_assert(
+    name: &str,
+    ptx_text: &str,
+    expected_ll: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    // ZLUDA_LOG=ptx::pass=trace shows what the translation did
+    crate::init_logger();
+    let ast = ptx_parser::parse_module_checked(ptx_text).unwrap();
+    // Golden files check the output of the mandatory passes, optimizations would obscure it
+    let llvm_ir = pass::to_llvm_module_with_options(
+        ast,
+        pass::Attributes {
+            clock_rate: 2124000,
+        },
+        pass::TranslateOptions {
+            if_convert_predicates: false,
+            infer_global_state_space: false,
+            promote_local_variables: false,
+            fold_constants: false,
+            deduplicate_values: false,
+            hoist_special_registers: false,
+            eliminate_dead_code: false,
+            scalarize_uniform_loads: false,
+            internalize_functions: false,
+            ..pass::TranslateOptions::default()
+        },
+    )
+    .unwrap_or_else(|err| panic!("Failed to translate {}: {}", name, err));
+    let actual_ll = llvm_ir.llvm_ir.print_module_to_string();
+    let actual_ll = actual_ll.to_str();
+    compare_llvm(name, actual_ll, expected_ll);
+
+    let expected_attributes_ll = read_test_file!(concat!("../ll/_attributes.ll"));
+    let actual_attributes_ll = llvm_ir.attributes_ir.print_module_to_string();
+    let actual_attributes_ll = actual_attributes_ll.to_str();
+    compare_llvm("_attributes", actual_attributes_ll, &expected_attributes_ll);
+    Ok(())
+}
+
+// Value names and ids are renumbered in order of appearance before comparing, so that
+// changes to the id allocation in the passes don't invalidate every golden file.
+// ZLUDA_BLESS_LL=1 overwrites the golden file with the actual output instead of failing,
+// TEST_PTX_LLVM_FAIL_DIR=<dir> writes the actual output of failing tests to <dir>
+fn compare_llvm(name: &str, actual_ll: &str, expected_ll: &str) {
+    let expected = normalize_llvm_names(expected_ll);
+    let actual = normalize_llvm_names(actual_ll);
+    let mismatch = match find_llvm_mismatch(&expected, &actual) {
+        Some(mismatch) => mismatch,
+        None => return,
+    };
+    if env::var("ZLUDA_BLESS_LL").as_deref() == Ok("1") {
+        let golden_file = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/test/ll")
+            .join(format!("{}.ll", name));
+        fs::write(golden_file, actual_ll).unwrap();
+        return;
+    }
+    if let Ok(output_dir) = env::var("TEST_PTX_LLVM_FAIL_DIR") {
+        let output_dir = Path::new(&output_dir);
+        fs::create_dir_all(&output_dir).unwrap();
+        let output_file = output_dir.join(format!("{}.ll", name));
+        let mut output_file = File::create(output_file).unwrap();
+        output_file.write_all(actual_ll.as_bytes()).unwrap();
+    }
+    panic!(
+        "LLVM IR of {} differs from the golden file, {}\n\n--- expected\n+++ actual\n{}",
+        name,
+        mismatch,
+        unified_diff(&expected, &actual)
+    );
+}
+
+fn normalize_llvm_names(module: &str) -> String {
+    let mut names = std::collections::HashMap::new();
+    let mut normalize = |id: &str| {
+        let next = names.len();
+        format!("v{}", names.entry(id.to_string()).or_insert(next))
+    };
+    let mut result = String::with_capacity(module.len());
+    for line in module.lines() {
+        let mut rest = line;
+        // Basic block labels
+        if let Some((id, tail)) = numeric_id(rest) {
+            if tail.starts_with(':') {
+                result.push_str(&normalize(id));
+                rest = tail;
+            }
+        }
+        while let Some(position) = rest.find('%') {
+            result.push_str(&rest[..=position]);
+            rest = &rest[position + 1..];
+            if let Some((id, tail)) = numeric_id(rest) {
+                result.push_str(&normalize(id));
+                rest = tail;
+            }
+        }
+        result.push_str(rest);
+        result.push('\n');
+    }
+    result
+}
+
+// Splits `123` or `"123"` off the start of `text`
+fn numeric_id(text: &str) -> Option<(&str, &str)> {
+    let digits_end = |text: &str| {
+        text.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len())
+    };
+    if let Some(quoted) = text.strip_prefix('"') {
+        let end = digits_end(quoted);
+        (end > 0 && quoted[end..].starts_with('"')).then(|| text.split_at(end + 2))
+    } else {
+        let end = digits_end(text);
+        (end > 0).then(|| text.split_at(end))
+    }
+}
+
+// First difference between two LLVM modules. Lines are counted from 1, blocks and
+// instructions from 0 within the enclosing function
+#[derive(Debug, PartialEq, Eq)]
+struct LlvmMismatch<'a> {
+    line: usize,
+    function: Option<&'a str>,
+    block: usize,
+    instruction: usize,
+    expected: Option<&'a str>,
+    actual: Option<&'a str>,
+}
+
+impl<'a> Display for LlvmMismatch<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "first difference at line {}", self.line)?;
+        if let Some(function) = self.function {
+            write!(
+                f,
+                " (function @{}, block {}, instruction {})",
+                function, self.block, self.instruction
+            )?;
+        }
+        let end = "<end of module>";
+        write!(
+            f,
+            "\n  expected: {}\n    actual: {}",
+            self.expected.unwrap_or(end).trim(),
+            self.actual.unwrap_or(end).trim()
+        )
+    }
+}
+
+fn find_llvm_mismatch<'a>(expected: &'a str, actual: &'a str) -> Option<LlvmMismatch<'a>> {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut function = None;
+    let mut block = 0;
+    let mut instruction = 0;
+    for line in 1.. {
+        let (expected, actual) = (expected_lines.next(), actual_lines.next());
+        if expected != actual {
+            return Some(LlvmMismatch {
+                line,
+                function,
+                block,
+                instruction,
+                expected,
+                actual,
+            });
+        }
+        let text = expected?;
+        if let Some(signature) = text.strip_prefix("define ") {
+            function = signature
+                .split_once('@')
+                .and_then(|(_, name)| name.split_once('('))
+                .map(|(name, _)| name);
+            block = 0;
+            instruction = 0;
+        } else if text == "}" {
+            function = None;
+        } else if function.is_some() {
+            let code = text.split(';').next().unwrap().trim_end();
+            if !text.starts_with(' ') && code.ends_with(':') {
+                block += 1;
+                instruction = 0;
+            } else if !code.is_empty() {
+                instruction += 1;
+            }
+        }
+    }
+    unreachable!()
+}
+
+// Line diff in the unified format with three lines of context, based on the longest common
+// subsequence. Golden files are small enough for the quadratic table
+fn unified_diff(expected: &str, actual: &str) -> String {
+    const CONTEXT: usize = 3;
+    let old = expected.lines().collect::<Vec<_>>();
+    let new = actual.lines().collect::<Vec<_>>();
+    let mut lcs = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    // (prefix, line)
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    let mut result = String::new();
+    let mut start = 0;
+    while let Some(first_change) = lines[start..].iter().position(|(op, _)| *op != ' ') {
+        let first_change = start + first_change;
+        let hunk_start = first_change.saturating_sub(CONTEXT).max(start);
+        // Extend the hunk while the next change is within the context of the previous one
+        let mut hunk_end = first_change + 1;
+        while let Some(next) = lines[hunk_end..]
+            .iter()
+            .take(CONTEXT * 2 + 1)
+            .position(|(op, _)| *op != ' ')
+        {
+            hunk_end += next + 1;
+        }
+        let hunk_end = (hunk_end + CONTEXT).min(lines.len());
+        let count = |range: std::ops::Range<usize>, skip: char| {
+            lines[range].iter().filter(|(op, _)| *op != skip).count()
+        };
+        let (old_start, new_start) = (count(0..hunk_start, '+'), count(0..hunk_start, '-'));
+        let (old_len, new_len) = (
+            count(hunk_start..hunk_end, '+'),
+            count(hunk_start..hunk_end, '-'),
+        );
+        result.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_start + 1,
+            old_len,
+            new_start + 1,
+            new_len
+        ));
+        for (op, line) in lines[hunk_start..hunk_end].iter() {
+            result.push_str(&format!("{}{}\n", op, line));
+        }
+        start = hunk_end;
+    }
+    result
+}
+
+#[test]
+fn llvm_mismatch_reports_location() {
+    let expected = r#"target triple = "amdgcn-amd-amdhsa"
+
+define amdgpu_kernel void @add(ptr addrspace(4) byref(i64) %"32") #0 {
+  %"34" = alloca i64, align 8, addrspace(5)
+  br label %1
+
+1:                                                ; preds = %0
+  %"38" = load i64, ptr addrspace(4) %"32", align 8
+  %"42" = add i64 %"38", 1
+  ret void
+}
+"#;
+    let actual = expected.replace(r#"add i64 %"38", 1"#, r#"add i64 %"38", 2"#);
+    assert_eq!(find_llvm_mismatch(expected, expected), None);
+    let mismatch = find_llvm_mismatch(expected, &actual).unwrap();
+    assert_eq!(
+        mismatch,
+        LlvmMismatch {
+            line: 9,
+            function: Some("add"),
+            block: 1,
+            instruction: 1,
+            expected: Some(r#"  %"42" = add i64 %"38", 1"#),
+            actual: Some(r#"  %"42" = add i64 %"38", 2"#),
+        }
+    );
+    assert_eq!(
+        unified_diff(expected, &actual),
+        r#"@@ -6,6 +6,6 @@
+ 
+ 1:                                                ; preds = %0
+   %"38" = load i64, ptr addrspace(4) %"32", align 8
+-  %"42" = add i64 %"38", 1
++  %"42" = add i64 %"38", 2
+   ret void
+ }
+"#
+    );
+}
+
+#[test]
+fn llvm_mismatch_reports_truncated_module() {
+    let expected = "define void @f() {\n  ret void\n}\n";
+    let mismatch = find_llvm_mismatch(expected, "define void @f() {\n").unwrap();
+    assert_eq!(mismatch.line, 2);
+    assert_eq!(mismatch.function, Some("f"));
+    assert_eq!(mismatch.actual, None);
+}
+
+#[test]
+fn llvm_names_are_normalized() {
+    let before = "define void @f(i64 %\"31\") {\n  br label %1\n\n1:\n  %\"40\" = add i64 %\"31\", 1\n  %2 = add i64 %\"40\", 2\n  ret void\n}\n";
+    let after = "define void @f(i64 %\"7\") {\n  br label %3\n\n3:\n  %\"8\" = add i64 %\"7\", 1\n  %9 = add i64 %\"8\", 2\n  ret void\n}\n";
+    assert_eq!(normalize_llvm_names(before), normalize_llvm_names(after));
+    assert_eq!(
+        normalize_llvm_names(before),
+        "define void @f(i64 %v0) {\n  br label %v1\n\nv1:\n  %v2 = add i64 %v0, 1\n  %v3 = add i64 %v2, 2\n  ret void\n}\n"
+    );
+    let swapped = before.replace("%\"31\", 1", "%\"40\", 1");
+    assert_ne!(normalize_llvm_names(before), normalize_llvm_names(&swapped));
+}
+
+fn test_cuda_assert<
+    Input: From<u8> + Debug + Copy + PartialEq,
+    Output: From<u8> + Debug + Copy + PartialEq + Default,
+>(
+    name: &str,
+    ptx_text: &str,
+    input: Option<&[Input]>,
+    output: &[Output],
+    launch: LaunchConfig,
+) -> Result<(), Box<dyn error::Error>> {
+    if skip_cuda() {
+        return Ok(());
+    }
+    let name = CString::new(name)?;
+    let result = run_cuda_with_input(name.as_c_str(), ptx_text, input, output, launch)?;
+    assert_eq!(result.as_slice(), output);
+    Ok(())
+}
+
+fn run_cuda_with_input<Input: Copy, Output: Copy>(
+    name: &CStr,
+    ptx_module: &str,
+    input: Option<&[Input]>,
+    output: &[Output],
+    launch: LaunchConfig,
+) -> Result<Vec<Output>, ApiError> {
+    let args = KernelArgs::with_input::<Input, Output>(input, output.len());
+    let outputs = run_cuda(name, ptx_module, &args, launch)?;
+    Ok(from_bytes(&outputs[0]))
+}
+
+fn run_cuda(
+    name: &CStr,
+    ptx_module: &str,
+    args: &KernelArgs,
+    launch: LaunchConfig,
+) -> Result<Vec<Vec<u8>>, ApiError> {
+    let ptx_module = CString::new(ptx_module).unwrap();
+    let context = OwnedContext::new()?;
+    let module = OwnedModule::<CUmodule>::load(&ptx_module)?;
+    let kernel = module.function(name)?;
+    let mut max_shared_memory = 0;
+    cuda_call!(cuDeviceGetAttribute(
+        &mut max_shared_memory,
+        CUdevice_attribute::CU_DEVICE_ATTRIBUTE_MAX_SHARED_MEMORY_PER_BLOCK,
+        0,
+    ))?;
+    launch.assert_shared_memory_fits(name, max_shared_memory as usize);
+    let mut device_args = args.to_device(&context)?;
+    let mut params = device_args.params();
+    cuda_call!(cuLaunchKernel(
+        kernel,
+        launch.grid.0,
+        launch.grid.1,
+        launch.grid.2,
+        launch.block.0,
+        launch.block.1,
+        launch.block.2,
+        launch.shared_memory,
+        CUstream(ptr::null_mut()),
+        params.as_mut_ptr(),
+        ptr::null_mut(),
+    ))?;
+    let current = AssertSend(context.get());
+    let finished = synchronize_with_timeout(launch.timeout, move || {
+        // Contexts are current per thread
+        cuda_call!(cuCtxSetCurrent(current.into_inner())).unwrap();
+        cuda_call!(cuStreamSynchronize(CUstream(ptr::null_mut()))).unwrap();
+    });
+    if !finished {
+        // Buffers and the module go away with the context, which is destroyed on unwinding
+        mem::forget(device_args);
+        mem::forget(module);
+        kernel_timed_out(name, launch.timeout);
+    }
+    device_args.copy_back()
+}
+
+// Synchronizes on a helper thread, so that a kernel that never finishes fails its test instead
+// of hanging the whole run. Returns false if `synchronize` didn't return within `timeout`
+fn synchronize_with_timeout(
+    timeout: Duration,
+    synchronize: impl FnOnce() + Send + 'static,
+) -> bool {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        synchronize();
+        sender.send(()).ok();
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(()) => true,
+        Err(mpsc::RecvTimeoutError::Timeout) => false,
+        Err(mpsc::RecvTimeoutError::Disconnected) => panic!("Failed to synchronize"),
+    }
+}
+
+fn kernel_timed_out(name: &CStr, timeout: Duration) -> ! {
+    panic!(
+        "{} did not finish within {:?}, the device was reset. Set {} to change the timeout",
+        name.to_string_lossy(),
+        timeout,
+        KERNEL_TIMEOUT_VAR
+    )
+}
+
+#[test]
+#[ignore = "keeps the GPU busy until the watchdog resets the device"]
+fn infinite_loop_times_out_amdgpu() {
+    if skip_hip() {
+        return;
+    }
+    let ptx = read_test_file!("infinite_loop.ptx");
+    let launch = LaunchConfig::new(1, 1).timeout(Duration::from_secs(2));
+    let err = std::panic::catch_unwind(|| {
+        translate_and_run_hip("infinite_loop", &ptx, Some(&[0u32]), &[0u32], launch)
+    })
+    .unwrap_err();
+    assert_timed_out(err);
+}
+
+#[test]
+#[ignore = "keeps the GPU busy until the watchdog resets the device"]
+fn infinite_loop_times_out_cuda() {
+    if skip_cuda() {
+        return;
+    }
+    let ptx = read_test_file!("infinite_loop.ptx");
+    let launch = LaunchConfig::new(1, 1).timeout(Duration::from_secs(2));
+    let name = CString::new("infinite_loop").unwrap();
+    let err = std::panic::catch_unwind(|| {
+        run_cuda_with_input(name.as_c_str(), &ptx, Some(&[0u32]), &[0u32], launch)
+    })
+    .unwrap_err();
+    assert_timed_out(err);
+}
+
+// Failed launches must release everything they allocated, otherwise a long fuzzing session
+// runs out of device memory. Leaking every iteration adds up to more memory than GPUs have
+const LEAK_TEST_ITERATIONS: usize = 64;
+const LEAK_TEST_BUFFER: usize = 512 << 20;
+
+fn leak_test_args() -> KernelArgs {
+    KernelArgs::new()
+        .buffer_in(&[1u64])
+        .buffer_out::<u8>(LEAK_TEST_BUFFER)
+}
+
+#[test]
+fn failed_launches_release_resources_amdgpu() {
+    if skip_hip() {
+        return;
+    }
+    let devices = hip_devices();
+    let device = DeviceSelector::from_env().select(&devices)[0];
+    let ptx = read_test_file!("add.ptx");
+    let ast = ptx_parser::parse_module_checked(&ptx).unwrap();
+    let module = pass::to_llvm_module(
+        ast,
+        pass::Attributes {
+            clock_rate: 2124000,
+        },
+    )
+    .unwrap();
+    let binary = compile_hip(&module, &device.arch);
+    let args = leak_test_args();
+    // More threads than a block can have
+    let invalid_launch = LaunchConfig::new(1, 4096);
+    for _ in 0..LEAK_TEST_ITERATIONS {
+        let err = run_hip_on_device(c"add", device, b"not a code object", &args, invalid_launch)
+            .unwrap_err();
+        assert!(err.call.starts_with("hipModuleLoadData"), "{err}");
+        let err = run_hip_on_device(c"add", device, &binary, &args, invalid_launch).unwrap_err();
+        assert!(err.call.starts_with("hipModuleLaunchKernel"), "{err}");
+    }
+    let result =
+        translate_and_run_hip("add", &ptx, Some(&[1u64]), &[0u64], LaunchConfig::new(1, 1));
+    assert_eq!(result.unwrap(), [2u64]);
+}
+
+fn entry_points_options() -> pass::TranslateOptions {
+    pass::TranslateOptions {
+        entry_points: Some(vec!["entry_points".to_string()]),
+        ..pass::TranslateOptions::default()
+    }
+}
+
+// The kernel translated without the rest of its module gives the same result as the whole module
+// in entry_points_cuda
+#[test]
+fn entry_points_selected_amdgpu() -> Result<(), Box<dyn error::Error>> {
+    if skip_hip() {
+        return Ok(());
+    }
+    let ptx = read_test_file!("entry_points.ptx");
+    let ast = ptx_parser::parse_module_checked(&ptx).unwrap();
+    let module = pass::to_llvm_module_with_options(
+        ast,
+        pass::Attributes {
+            clock_rate: 2124000,
+        },
+        entry_points_options(),
+    )?;
+    let args = KernelArgs::new().buffer_in(&[6u32]).buffer_out::<u32>(1);
+    let result = run_hip(c"entry_points", module, &args, LaunchConfig::new(1, 1))?;
+    assert_single_output(result, &[30u32]);
+    Ok(())
+}
+
+#[test]
+fn entry_points_selected_interp() {
+    let ptx = read_test_file!("entry_points.ptx");
+    let args = KernelArgs::new().buffer_in(&[6u32]).buffer_out::<u32>(1);
+    let result = interpret::run(
+        "entry_points",
+        &ptx,
+        &args,
+        LaunchConfig::new(1, 1),
+        &entry_points_options(),
+    )
+    .unwrap();
+    assert_single_output(result, &[30u32]);
+}
+
+// Small integers, every product and sum is exact in .f16 and .f32, so the emulation has to give
+// the same result as the tensor cores. Returns A (16x8, row-major), B (8x8, column-major),
+// C (16x8, row-major) and the expected D
+fn mma_m16n8k8_tile() -> (Vec<half::f16>, Vec<half::f16>, Vec<f32>, Vec<f32>) {
+    let a = |row: usize, k: usize| ((row + 2 * k) % 5) as f32 - 2.0;
+    let b = |k: usize, column: usize| ((3 * k + column) % 4) as f32 - 1.0;
+    let c = |row: usize, column: usize| row as f32 - column as f32;
+    let input_a = (0..16)
+        .flat_map(|row| (0..8).map(move |k| half::f16::from_f32(a(row, k))))
+        .collect();
+    let input_b = (0..8)
+        .flat_map(|column| (0..8).map(move |k| half::f16::from_f32(b(k, column))))
+        .collect();
+    let input_c = (0..16)
+        .flat_map(|row| (0..8).map(move |column| c(row, column)))
+        .collect();
+    let output = (0..16)
+        .flat_map(|row| {
+            (0..8).map(move |column| {
+                c(row, column) + (0..8).map(|k| a(row, k) * b(k, column)).sum::<f32>()
+            })
+        })
+        .collect();
+    (input_a, input_b, input_c, output)
+}
+
+fn mma_m16n8k8_args() -> (KernelArgs, Vec<f32>) {
+    let (input_a, input_b, input_c, output) = mma_m16n8k8_tile();
+    let args = KernelArgs::new()
+        .buffer_in(&input_a)
+        .buffer_in(&input_b)
+        .buffer_in(&input_c)
+        .buffer_out::<f32>(output.len());
+    (args, output)
+}
+
+#[test]
+fn mma_m16n8k8_cuda() -> Result<(), Box<dyn error::Error>> {
+    if skip_cuda() {
+        return Ok(());
+    }
+    let ptx = read_test_file!("mma_m16n8k8.ptx");
+    let (args, output) = mma_m16n8k8_args();
+    let result = run_cuda(c"mma_m16n8k8", &ptx, &args, LaunchConfig::new(1, 32))?;
+    assert_single_output(result, &output);
+    Ok(())
+}
+
+// The same tile as mma_m16n8k8_cuda, computed by the emulation
+#[test]
+fn mma_m16n8k8_emulated_amdgpu() -> Result<(), Box<dyn error::Error>> {
+    if skip_hip() {
+        return Ok(());
+    }
+    let ptx = read_test_file!("mma_m16n8k8.ptx");
+    let ast = ptx_parser::parse_module_checked(&ptx).unwrap();
+    let module = pass::to_llvm_module_with_options(
+        ast,
+        pass::Attributes {
+            clock_rate: 2124000,
+        },
+        pass::TranslateOptions {
+            emulate_mma: true,
+            ..pass::TranslateOptions::default()
+        },
+    )?;
+    let (args, output) = mma_m16n8k8_args();
+    let result = run_hip(c"mma_m16n8k8", module, &args, LaunchConfig::new(1, 32))?;
+    assert_single_output(result, &output);
+    Ok(())
+}
+
+// The kernel tells the host it runs with a volatile store, then spins on a volatile load until
+// the host sets a flag from another stream and copies the value written before the flag. The
+// copies have to run while the kernel does, devices without an asynchronous copy engine are
+// skipped
+#[test]
+fn volatile_flag_polling_amdgpu() -> Result<(), Box<dyn error::Error>> {
+    use hip_runtime_sys::*;
+    if skip_hip() {
+        return Ok(());
+    }
+    let devices = hip_devices();
+    let device = DeviceSelector::from_env().select(&devices)[0];
+    hip_call!(hipSetDevice(device.index))?;
+    let mut copy_engines = 0;
+    hip_call!(hipDeviceGetAttribute(
+        &mut copy_engines,
+        hipDeviceAttribute_t::hipDeviceAttributeAsyncEngineCount,
+        device.index,
+    ))?;
+    if copy_engines == 0 {
+        eprintln!("skipping volatile_flag_polling on {device}: no asynchronous copy engine");
+        return Ok(());
+    }
+    let ptx = read_test_file!("volatile_flag_polling.ptx");
+    let ast = ptx_parser::parse_module_checked(&ptx).unwrap();
+    let module = pass::to_llvm_module_with_options(
+        ast,
+        pass::Attributes {
+            clock_rate: 2124000,
+        },
+        pass::TranslateOptions {
+            volatile_coherence: pass::VolatileCoherence::for_arch(&device.arch),
+            ..pass::TranslateOptions::default()
+        },
+    )?;
+    let binary = compile_hip(&module, &device.arch);
+    let name = c"volatile_flag_polling";
+    let kernel_stream = OwnedStream::new()?;
+    let copy_stream = OwnedStream::new()?;
+    let hip_module = OwnedModule::<hipModule_t>::load(&binary)?;
+    let kernel = hip_module.function(name)?;
+    let mailbox = DeviceBuffer::new(&copy_stream, 12)?;
+    mailbox.zero()?;
+    let output = DeviceBuffer::new(&copy_stream, 4)?;
+    output.zero()?;
+    let mut values = [mailbox.as_ptr() as u64, output.as_ptr() as u64];
+    let mut params = values
+        .iter_mut()
+        .map(|value| (value as *mut u64).cast::<c_void>())
+        .collect::<Vec<_>>();
+    hip_call!(hipModuleLaunchKernel(
+        kernel,
+        1,
+        1,
+        1,
+        1,
+        1,
+        1,
+        0,
+        kernel_stream.get(),
+        params.as_mut_ptr(),
+        ptr::null_mut(),
+    ))?;
+    let timeout = default_kernel_timeout();
+    let start = Instant::now();
+    let running = loop {
+        if mailbox.to_host()?[8..12] == 1u32.to_ne_bytes() {
+            break true;
+        }
+        if start.elapsed() > timeout {
+            break false;
+        }
+    };
+    let expected = 0x1234_5678u32;
+    let finished = running && {
+        unsafe {
+            copy_stream.copy_to_device(mailbox.as_ptr().byte_add(4), &expected.to_ne_bytes())?;
+            copy_stream.copy_to_device(mailbox.as_ptr(), &1u32.to_ne_bytes())?;
+        }
+        let raw_stream = AssertSend(kernel_stream.get());
+        synchronize_with_timeout(timeout, move || {
+            hip_call!(hipStreamSynchronize(raw_stream.into_inner())).unwrap();
+        })
+    };
+    if !finished {
+        // The kernel still spins, everything is gone after the reset
+        mem::forget(mailbox);
+        mem::forget(output);
+        mem::forget(hip_module);
+        mem::forget(kernel_stream);
+        mem::forget(copy_stream);
+        hip_call!(hipDeviceReset()).ok();
+        if !running {
+            panic!("The host never saw the volatile store of {device}");
+        }
+        kernel_timed_out(name, timeout);
+    }
+    assert_eq!(output.to_host()?, expected.to_ne_bytes());
+    Ok(())
+}
+
+#[test]
+fn failed_launches_release_resources_cuda() {
+    if skip_cuda() {
+        return;
+    }
+    let ptx = read_test_file!("add.ptx");
+    let args = leak_test_args();
+    let invalid_launch = LaunchConfig::new(1, 4096);
+    for _ in 0..LEAK_TEST_ITERATIONS {
+        let err = run_cuda(c"add", "not a PTX module", &args, invalid_launch).unwrap_err();
+        assert!(err.call.starts_with("cuModuleLoadData"), "{err}");
+        let err = run_cuda(c"add", &ptx, &args, invalid_launch).unwrap_err();
+        assert!(err.call.starts_with("cuLaunchKernel"), "{err}");
+    }
+    let result = run_cuda_with_input(
+        c"add",
+        &ptx,
+        Some(&[1u64]),
+        &[0u64],
+        LaunchConfig::new(1, 1),
+    );
+    assert_eq!(result.unwrap(), [2u64]);
+}
+
+fn assert_timed_out(panic: Box<dyn std::any::Any + Send>) {
+    let message = panic
+        .downcast_ref::<String>()
+        .expect("expected a formatted panic message");
+    assert!(
+        message.starts_with("infinite_loop did not finish within 2s"),
+        "{}",
+        message
+    );
+}
+
+// Raw handles are not Send, but the runtimes allow using them from any thread
+struct AssertSend<T>(T);
+
+unsafe impl<T> Send for AssertSend<T> {}
+
+impl<T> AssertSend<T> {
+    // Closures must call this instead of accessing the field, which would capture just the
+    // field and not the Send wrapper
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+struct DynamicCuda {
+    lib: libloading::Library,
+}
+
+impl DynamicCuda {
+    #[cfg(not(windows))]
+    const CUDA_PATH: &'static str = "/usr/lib/x86_64-linux-gnu/libcuda.so.1";
+    #[cfg(windows)]
     const CUDA_PATH: &'static str = "C:\\Windows\\System32\\nvcuda.dll";
 
+    // Systems which keep NVIDIA's driver elsewhere (e.g. /usr/lib64) can point the tests at it
+    // with ZLUDA_TEST_CUDA_LIBRARY. We don't leave the search to the dynamic loader, with ZLUDA on
+    // the library path it would find ZLUDA's own libcuda and compare it against itself
+    fn path() -> String {
+        env::var(CUDA_LIBRARY_VAR).unwrap_or_else(|_| Self::CUDA_PATH.to_string())
+    }
+
     pub fn new() -> Result<Self, libloading::Error> {
-        let lib = unsafe { libloading::Library::new(Self::CUDA_PATH) }?;
+        let lib = unsafe { libloading::Library::new(Self::path()) }?;
         Ok(Self { lib })
     }
 }
@@ -622,97 +2539,331 @@ cuda_macros::cuda_function_declarations!(dynamic_fns);
 static COMGR: std::sync::LazyLock<Comgr> = std::sync::LazyLock::new(|| Comgr::new().unwrap());
 static CUDA: std::sync::LazyLock<DynamicCuda> =
     std::sync::LazyLock::new(|| DynamicCuda::new().unwrap());
+#[cfg(feature = "dynamic-hip")]
+static HIP: std::sync::LazyLock<dynamic_hip::DynamicHip> =
+    std::sync::LazyLock::new(|| dynamic_hip::DynamicHip::new().unwrap());
 
-fn run_hip<Input: From<u8> + Copy + Debug, Output: From<u8> + Copy + Debug + Default>(
-    name: &CStr,
-    module: pass::Module,
-    input: Option<&[Input]>,
-    output: &[Output],
-    block_dim_x: u32,
-) -> Result<Vec<Output>, hipError_t> {
-    use hip_runtime_sys::*;
-    unsafe { hipInit(0) }.unwrap();
-    let comgr = &*COMGR;
-    let mut result = vec![0u8.into(); output.len()];
-    {
-        let dev = 0;
-        let mut stream = unsafe { mem::zeroed() };
-        unsafe { hipStreamCreate(&mut stream) }.unwrap();
-        let mut dev_props = unsafe { mem::zeroed() };
-        unsafe { hipGetDevicePropertiesR0600(&mut dev_props, dev) }.unwrap();
-        let elf_module = comgr::compile_bitcode(
-            &comgr,
-            unsafe { CStr::from_ptr(dev_props.gcnArchName.as_ptr()) }
-                .to_str()
-                .unwrap(),
-            &*module.llvm_ir.write_bitcode_to_memory(),
-            module.linked_bitcode(),
-            &*module.attributes_ir.write_bitcode_to_memory(),
-            None,
+const CUDA_LIBRARY_VAR: &str = "ZLUDA_TEST_CUDA_LIBRARY";
+const TEST_DEVICE_VAR: &str = "ZLUDA_TEST_DEVICE";
+const ALL_DEVICES_VAR: &str = "ZLUDA_TEST_ALL_DEVICES";
+
+// Which HIP devices a test runs on. An index selects exactly that device,
+// anything else is matched as a substring of the gfx arch name
+#[derive(Debug, PartialEq)]
+enum DeviceSelector {
+    Default,
+    All,
+    Index(i32),
+    Arch(String),
+}
+
+impl DeviceSelector {
+    fn from_env() -> Self {
+        Self::parse(
+            env::var(TEST_DEVICE_VAR).ok().as_deref(),
+            env::var(ALL_DEVICES_VAR).as_deref() == Ok("1"),
         )
-        .unwrap();
-        // TODO: Re-enable when we are able to privatize function-scoped
-        // globals and constants
-        // let fns = comgr::get_symbols(&comgr, &elf_module).unwrap();
-        // verify_symbols(fns);
-        let mut module = unsafe { mem::zeroed() };
-        unsafe { hipModuleLoadData(&mut module, elf_module.as_ptr() as _) }.unwrap();
-        let mut kernel = unsafe { mem::zeroed() };
-        unsafe { hipModuleGetFunction(&mut kernel, module, name.as_ptr()) }.unwrap();
-        let mut out_b = ptr::null_mut();
-        unsafe { hipMalloc(&mut out_b, output.len() * mem::size_of::<Output>()) }.unwrap();
-        let mut inp_b = ptr::null_mut();
-        if let Some(input) = input {
-            unsafe { hipMalloc(&mut inp_b, input.len() * mem::size_of::<Input>()) }.unwrap();
-            unsafe {
-                hipMemcpyWithStream(
-                    inp_b,
-                    input.as_ptr() as _,
-                    input.len() * mem::size_of::<Input>(),
-                    hipMemcpyKind::hipMemcpyHostToDevice,
-                    stream,
-                )
-            }
-            .unwrap();
+    }
+
+    fn parse(device: Option<&str>, all_devices: bool) -> Self {
+        if all_devices {
+            return DeviceSelector::All;
         }
-        unsafe { hipMemset(out_b, 0, output.len() * mem::size_of::<Output>()) }.unwrap();
-        let mut args = if input.is_some() {
-            [&inp_b, &out_b]
-        } else {
-            [&out_b, &out_b]
+        match device.map(str::trim) {
+            None | Some("") => DeviceSelector::Default,
+            Some(device) => match device.parse::<i32>() {
+                Ok(index) => DeviceSelector::Index(index),
+                Err(_) => DeviceSelector::Arch(device.to_string()),
+            },
+        }
+    }
+
+    fn select<'a>(&self, devices: &'a [HipDevice]) -> Vec<&'a HipDevice> {
+        let selected = match self {
+            DeviceSelector::Default => devices.iter().take(1).collect::<Vec<_>>(),
+            DeviceSelector::All => devices.iter().collect(),
+            DeviceSelector::Index(index) => {
+                devices.iter().filter(|dev| dev.index == *index).collect()
+            }
+            DeviceSelector::Arch(arch) => devices
+                .iter()
+                .filter(|dev| dev.arch.contains(arch.as_str()))
+                .take(1)
+                .collect(),
         };
-        unsafe {
-            hipModuleLaunchKernel(
-                kernel,
-                1,
-                1,
-                1,
-                block_dim_x,
-                1,
-                1,
-                1024,
-                stream,
-                args.as_mut_ptr() as _,
-                ptr::null_mut(),
-            )
-        }
-        .unwrap();
-        unsafe {
-            hipMemcpyAsync(
-                result.as_mut_ptr() as _,
-                out_b,
-                output.len() * mem::size_of::<Output>(),
-                hipMemcpyKind::hipMemcpyDeviceToHost,
-                stream,
-            )
-        }
-        .unwrap();
-        unsafe { hipStreamSynchronize(stream) }.unwrap();
-        unsafe { hipFree(inp_b) }.unwrap();
-        unsafe { hipFree(out_b) }.unwrap();
-        unsafe { hipModuleUnload(module) }.unwrap();
-    }
-    Ok(result)
+        if selected.is_empty() {
+            let available = devices
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            panic!("No HIP device matches {self:?}, available devices: [{available}]");
+        }
+        selected
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HipDevice {
+    index: i32,
+    name: String,
+    arch: String,
+    shared_memory_per_block: usize,
+    multiprocessor_count: i32,
+}
+
+impl Display for HipDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {} ({})", self.index, self.name, self.arch)
+    }
+}
+
+fn hip_devices() -> Vec<HipDevice> {
+    use hip_runtime_sys::*;
+    hip_call!(hipInit(0)).unwrap();
+    let mut count = 0;
+    hip_call!(hipGetDeviceCount(&mut count)).unwrap();
+    (0..count)
+        .map(|index| {
+            let mut props = unsafe { mem::zeroed::<hipDeviceProp_tR0600>() };
+            hip_call!(hipGetDevicePropertiesR0600(&mut props, index)).unwrap();
+            let to_string = |chars: &[std::ffi::c_char]| {
+                unsafe { CStr::from_ptr(chars.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            HipDevice {
+                index,
+                name: to_string(&props.name),
+                arch: to_string(&props.gcnArchName),
+                shared_memory_per_block: props.sharedMemPerBlock,
+                multiprocessor_count: props.multiProcessorCount,
+            }
+        })
+        .collect()
+}
+
+fn test_devices() -> Vec<HipDevice> {
+    [
+        (0, "AMD Radeon Graphics", "gfx1103"),
+        (1, "AMD Radeon RX 7900 XTX", "gfx1100"),
+    ]
+    .into_iter()
+    .map(|(index, name, arch)| HipDevice {
+        index,
+        name: name.to_string(),
+        arch: format!("{arch}:sramecc-:xnack-"),
+        shared_memory_per_block: 64 * 1024,
+        multiprocessor_count: 48,
+    })
+    .collect()
+}
+
+#[test]
+fn device_selector_parses_index_and_arch() {
+    assert_eq!(DeviceSelector::parse(None, false), DeviceSelector::Default);
+    assert_eq!(
+        DeviceSelector::parse(Some(" "), false),
+        DeviceSelector::Default
+    );
+    assert_eq!(
+        DeviceSelector::parse(Some("1"), false),
+        DeviceSelector::Index(1)
+    );
+    assert_eq!(
+        DeviceSelector::parse(Some("gfx1100"), false),
+        DeviceSelector::Arch("gfx1100".to_string())
+    );
+    assert_eq!(DeviceSelector::parse(Some("1"), true), DeviceSelector::All);
+}
+
+#[test]
+fn device_selector_picks_matching_devices() {
+    let devices = test_devices();
+    let indices = |selector: DeviceSelector| {
+        selector
+            .select(&devices)
+            .into_iter()
+            .map(|dev| dev.index)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(indices(DeviceSelector::Default), [0]);
+    assert_eq!(indices(DeviceSelector::All), [0, 1]);
+    assert_eq!(indices(DeviceSelector::Index(1)), [1]);
+    assert_eq!(indices(DeviceSelector::Arch("gfx1100".to_string())), [1]);
+    assert_eq!(indices(DeviceSelector::Arch("gfx11".to_string())), [0]);
+}
+
+#[test]
+#[should_panic(expected = "available devices: [#0 AMD Radeon Graphics")]
+fn device_selector_rejects_unknown_device() {
+    DeviceSelector::Index(2).select(&test_devices());
+}
+
+#[test]
+fn selected_hip_devices() {
+    if skip_hip() {
+        return;
+    }
+    let devices = hip_devices();
+    for device in DeviceSelector::from_env().select(&devices) {
+        eprintln!("{device:#?}");
+        assert!(device.arch.starts_with("gfx"), "{}", device.arch);
+        assert!(device.multiprocessor_count > 0);
+    }
+}
+
+fn run_hip(
+    name: &CStr,
+    module: pass::Module,
+    args: &KernelArgs,
+    launch: LaunchConfig,
+) -> Result<Vec<Vec<u8>>, ApiError> {
+    let devices = hip_devices();
+    let selected = DeviceSelector::from_env().select(&devices);
+    let kernel = module
+        .kernels
+        .iter()
+        .find(|kernel| kernel.name.as_bytes() == name.to_bytes());
+    let validate = |device: &HipDevice| {
+        if let Some(kernel) = kernel {
+            launch.assert_valid(kernel, device);
+        }
+    };
+    let compile = |arch: &str| {
+        let binary = compile_hip(&module, arch);
+        check_resources(name, arch, &binary, &launch.budget);
+        binary
+    };
+    if let [device] = &*selected {
+        validate(device);
+        return run_hip_on_device(name, device, &compile(&device.arch), args, launch);
+    }
+    // Compile once per gfx arch, identical devices share the binary
+    let mut binaries = std::collections::HashMap::<&str, Vec<u8>>::new();
+    let mut results = Vec::new();
+    for device in selected {
+        let binary = binaries
+            .entry(device.arch.as_str())
+            .or_insert_with(|| compile(&device.arch));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            validate(device);
+            run_hip_on_device(name, device, binary, args, launch)
+        }));
+        results.push((device, result));
+    }
+    let expected = match &results[0].1 {
+        Ok(Ok(result)) => Some(result.clone()),
+        _ => None,
+    };
+    let failures = results
+        .iter()
+        .filter_map(|(device, result)| {
+            let failure = match result {
+                Ok(Ok(result)) if Some(result) == expected.as_ref() => return None,
+                Ok(Ok(_)) => format!("output differs from {}", results[0].0),
+                Ok(Err(err)) => err.to_string(),
+                Err(panic) => panic
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| panic.downcast_ref::<&str>().map(ToString::to_string))
+                    .unwrap_or_else(|| "panicked".to_string()),
+            };
+            Some(format!("{device}: {failure}"))
+        })
+        .collect::<Vec<_>>();
+    if !failures.is_empty() {
+        panic!(
+            "{} failed on some devices:\n{}",
+            name.to_string_lossy(),
+            failures.join("\n")
+        );
+    }
+    Ok(expected.unwrap())
+}
+
+fn compile_hip(module: &pass::Module, arch: &str) -> Vec<u8> {
+    comgr::compile_bitcode(
+        &*COMGR,
+        arch,
+        &*module.llvm_ir.write_bitcode_to_memory(),
+        module.linked_bitcode(),
+        &*module.attributes_ir.write_bitcode_to_memory(),
+        module.correctly_rounded_div_sqrt,
+        None,
+    )
+    .unwrap()
+}
+
+// Prints the resource usage with ZLUDA_TEST_VERBOSE=1
+fn check_resources(name: &CStr, arch: &str, binary: &[u8], budget: &ResourceBudget) {
+    let name = name.to_string_lossy();
+    let kernels = comgr::get_kernel_resources(binary).unwrap();
+    let resources = kernels
+        .iter()
+        .find(|kernel| kernel.name == name)
+        .unwrap_or_else(|| panic!("no resource usage of {name} in the code object"));
+    if env::var("ZLUDA_TEST_VERBOSE").as_deref() == Ok("1") {
+        eprintln!(
+            "{name} on {arch}: {} VGPRs, {} SGPRs, {} bytes of LDS, {} bytes of scratch",
+            resources.vgpr_count,
+            resources.sgpr_count,
+            resources.group_segment_fixed_size,
+            resources.private_segment_fixed_size
+        );
+    }
+    let exceeded = budget.check(resources);
+    assert!(
+        exceeded.is_empty(),
+        "{name} on {arch} is over its resource budget: {}",
+        exceeded.join(", ")
+    );
+}
+
+fn run_hip_on_device(
+    name: &CStr,
+    device: &HipDevice,
+    elf_module: &[u8],
+    args: &KernelArgs,
+    launch: LaunchConfig,
+) -> Result<Vec<Vec<u8>>, ApiError> {
+    hip_call!(hipSetDevice(device.index))?;
+    let stream = OwnedStream::new()?;
+    // TODO: Re-enable when we are able to privatize function-scoped
+    // globals and constants
+    // let fns = comgr::get_symbols(&comgr, &elf_module).unwrap();
+    // verify_symbols(fns);
+    let module = OwnedModule::<hipModule_t>::load(elf_module)?;
+    let kernel = module.function(name)?;
+    launch.assert_shared_memory_fits(name, device.shared_memory_per_block);
+    let mut device_args = args.to_device(&stream)?;
+    let mut params = device_args.params();
+    hip_call!(hipModuleLaunchKernel(
+        kernel,
+        launch.grid.0,
+        launch.grid.1,
+        launch.grid.2,
+        launch.block.0,
+        launch.block.1,
+        launch.block.2,
+        launch.shared_memory,
+        stream.get(),
+        params.as_mut_ptr(),
+        ptr::null_mut(),
+    ))?;
+    let raw_stream = AssertSend(stream.get());
+    let finished = synchronize_with_timeout(launch.timeout, move || {
+        hip_call!(hipStreamSynchronize(raw_stream.into_inner())).unwrap();
+    });
+    if !finished {
+        // Everything is gone after the reset, releasing it would fail
+        mem::forget(device_args);
+        mem::forget(module);
+        mem::forget(stream);
+        hip_call!(hipDeviceReset()).ok();
+        kernel_timed_out(name, launch.timeout);
+    }
+    device_args.copy_back()
 }
 
 // TODO: Re-enable when we are able to privatize function-scoped
@@ -0,0 +1,566 @@
+// Randomized tests of integer instruction semantics. Every instruction gets a kernel where each
+// thread loads its operands, executes the instruction once and stores the result, so a single
+// launch checks hundreds of random cases against a Rust implementation of the PTX semantics.
+// Operands are biased towards boundary values: 0, -1, INT_MIN, INT_MAX, shift counts around the
+// type width and so on.
+//
+// ZLUDA_PROPERTY_SEED reproduces a run, the seed is printed on failure
+use super::fuzz::Rng;
+use super::{
+    emulate_args, run_cuda, skip_cuda, skip_hip, translate_and_run_hip_args, KernelArgs,
+    LaunchConfig,
+};
+use std::env;
+use std::ffi::CString;
+use std::fmt::Write as _;
+use std::time::SystemTime;
+
+const CASES: usize = 512;
+const BLOCK_SIZE: u32 = 256;
+const MAX_REPORTED_CASES: usize = 8;
+
+#[derive(Clone, Copy)]
+enum Operand {
+    B32,
+    B64,
+    // Shift amounts, bit positions and lengths, mostly small
+    Count,
+    // Immediate, for instructions that only accept constants
+    Imm(u32),
+}
+
+use Operand::{Count, Imm, B32, B64};
+
+impl Operand {
+    fn bits(self) -> u32 {
+        match self {
+            Operand::B64 => 64,
+            Operand::B32 | Operand::Count | Operand::Imm(_) => 32,
+        }
+    }
+}
+
+struct Property {
+    instruction: &'static str,
+    operands: &'static [Operand],
+    result: Operand,
+    // None if the result is undefined for these operands, they are generated again
+    reference: fn(&[u64]) -> Option<u64>,
+}
+
+const PROPERTIES: &[Property] = &[
+    property("add.u32", &[B32, B32], B32, |o| {
+        Some(u32_(o[0]).wrapping_add(u32_(o[1])) as u64)
+    }),
+    property("add.u64", &[B64, B64], B64, |o| {
+        Some(o[0].wrapping_add(o[1]))
+    }),
+    property("sub.u32", &[B32, B32], B32, |o| {
+        Some(u32_(o[0]).wrapping_sub(u32_(o[1])) as u64)
+    }),
+    property("sub.u64", &[B64, B64], B64, |o| {
+        Some(o[0].wrapping_sub(o[1]))
+    }),
+    property("mul.lo.u32", &[B32, B32], B32, |o| {
+        Some(u32_(o[0]).wrapping_mul(u32_(o[1])) as u64)
+    }),
+    property("mul.lo.u64", &[B64, B64], B64, |o| {
+        Some(o[0].wrapping_mul(o[1]))
+    }),
+    property("mul.hi.u32", &[B32, B32], B32, |o| {
+        Some((o[0] * o[1]) >> 32)
+    }),
+    property("mul.hi.s32", &[B32, B32], B32, |o| {
+        Some(((s32(o[0]) as i64 * s32(o[1]) as i64) >> 32) as u64)
+    }),
+    property("mul.hi.u64", &[B64, B64], B64, |o| {
+        Some(((o[0] as u128 * o[1] as u128) >> 64) as u64)
+    }),
+    property("mul.hi.s64", &[B64, B64], B64, |o| {
+        Some(((o[0] as i64 as i128 * o[1] as i64 as i128) >> 64) as u64)
+    }),
+    property("mul.wide.u32", &[B32, B32], B64, |o| Some(o[0] * o[1])),
+    property("mul.wide.s32", &[B32, B32], B64, |o| {
+        Some((s32(o[0]) as i64 * s32(o[1]) as i64) as u64)
+    }),
+    property("shl.b32", &[B32, Count], B32, |o| {
+        Some(shift_left(o[0], o[1], 32))
+    }),
+    property("shl.b64", &[B64, Count], B64, |o| {
+        Some(shift_left(o[0], o[1], 64))
+    }),
+    property("shr.u32", &[B32, Count], B32, |o| {
+        Some(shift_right(o[0], o[1], 32, false))
+    }),
+    property("shr.s32", &[B32, Count], B32, |o| {
+        Some(shift_right(o[0], o[1], 32, true))
+    }),
+    property("shr.u64", &[B64, Count], B64, |o| {
+        Some(shift_right(o[0], o[1], 64, false))
+    }),
+    property("shr.s64", &[B64, Count], B64, |o| {
+        Some(shift_right(o[0], o[1], 64, true))
+    }),
+    property("bfe.u32", &[B32, Count, Count], B32, |o| {
+        Some(bfe(o[0], o[1], o[2], 32, false))
+    }),
+    property("bfe.s32", &[B32, Count, Count], B32, |o| {
+        Some(bfe(o[0], o[1], o[2], 32, true))
+    }),
+    property("bfe.u64", &[B64, Count, Count], B64, |o| {
+        Some(bfe(o[0], o[1], o[2], 64, false))
+    }),
+    property("bfe.s64", &[B64, Count, Count], B64, |o| {
+        Some(bfe(o[0], o[1], o[2], 64, true))
+    }),
+    property("bfi.b32", &[B32, B32, Count, Count], B32, |o| {
+        Some(bfi(o[0], o[1], o[2], o[3], 32))
+    }),
+    property("bfi.b64", &[B64, B64, Count, Count], B64, |o| {
+        Some(bfi(o[0], o[1], o[2], o[3], 64))
+    }),
+    property("clz.b32", &[B32], B32, |o| {
+        Some(u32_(o[0]).leading_zeros() as u64)
+    }),
+    property(
+        "clz.b64",
+        &[B64],
+        B32,
+        |o| Some(o[0].leading_zeros() as u64),
+    ),
+    property("popc.b32", &[B32], B32, |o| Some(o[0].count_ones() as u64)),
+    property("popc.b64", &[B64], B32, |o| Some(o[0].count_ones() as u64)),
+    property("brev.b32", &[B32], B32, |o| {
+        Some(u32_(o[0]).reverse_bits() as u64)
+    }),
+    property("brev.b64", &[B64], B64, |o| Some(o[0].reverse_bits())),
+    property("min.u32", &[B32, B32], B32, |o| Some(o[0].min(o[1]))),
+    property("min.s32", &[B32, B32], B32, |o| {
+        Some(s32(o[0]).min(s32(o[1])) as u64)
+    }),
+    property("min.u64", &[B64, B64], B64, |o| Some(o[0].min(o[1]))),
+    property("min.s64", &[B64, B64], B64, |o| {
+        Some((o[0] as i64).min(o[1] as i64) as u64)
+    }),
+    property("max.u32", &[B32, B32], B32, |o| Some(o[0].max(o[1]))),
+    property("max.s32", &[B32, B32], B32, |o| {
+        Some(s32(o[0]).max(s32(o[1])) as u64)
+    }),
+    property("max.u64", &[B64, B64], B64, |o| Some(o[0].max(o[1]))),
+    property("max.s64", &[B64, B64], B64, |o| {
+        Some((o[0] as i64).max(o[1] as i64) as u64)
+    }),
+    // Division by zero and overflowing signed division are undefined
+    property("div.u32", &[B32, B32], B32, |o| o[0].checked_div(o[1])),
+    property("div.s32", &[B32, B32], B32, |o| {
+        s32(o[0]).checked_div(s32(o[1])).map(|x| x as u64)
+    }),
+    property("div.u64", &[B64, B64], B64, |o| o[0].checked_div(o[1])),
+    property("div.s64", &[B64, B64], B64, |o| {
+        (o[0] as i64).checked_div(o[1] as i64).map(|x| x as u64)
+    }),
+    property("rem.u32", &[B32, B32], B32, |o| o[0].checked_rem(o[1])),
+    property("rem.s32", &[B32, B32], B32, |o| {
+        s32(o[0]).checked_rem(s32(o[1])).map(|x| x as u64)
+    }),
+    property("rem.u64", &[B64, B64], B64, |o| o[0].checked_rem(o[1])),
+    property("rem.s64", &[B64, B64], B64, |o| {
+        (o[0] as i64).checked_rem(o[1] as i64).map(|x| x as u64)
+    }),
+    // Only immediate selectors are supported, the last two replicate sign bits
+    property("prmt.b32", &[B32, B32, Imm(0x3210)], B32, prmt_reference),
+    property("prmt.b32", &[B32, B32, Imm(0x7604)], B32, prmt_reference),
+    property("prmt.b32", &[B32, B32, Imm(0x0123)], B32, prmt_reference),
+    property("prmt.b32", &[B32, B32, Imm(0x4567)], B32, prmt_reference),
+    property("prmt.b32", &[B32, B32, Imm(0x8888)], B32, prmt_reference),
+    property("prmt.b32", &[B32, B32, Imm(0xb1d8)], B32, prmt_reference),
+];
+
+const fn property(
+    instruction: &'static str,
+    operands: &'static [Operand],
+    result: Operand,
+    reference: fn(&[u64]) -> Option<u64>,
+) -> Property {
+    Property {
+        instruction,
+        operands,
+        result,
+        reference,
+    }
+}
+
+fn u32_(x: u64) -> u32 {
+    x as u32
+}
+
+fn s32(x: u64) -> i32 {
+    x as u32 as i32
+}
+
+fn mask(bits: u32) -> u64 {
+    u64::MAX >> (64 - bits)
+}
+
+// Sign extends the low `bits` of x
+fn sign_extend(x: u64, bits: u32) -> i64 {
+    ((x << (64 - bits)) as i64) >> (64 - bits)
+}
+
+// Shift amounts are not masked, shifting by the width or more clears the value
+fn shift_left(a: u64, shift: u64, bits: u32) -> u64 {
+    let shift = shift as u32;
+    if shift >= bits {
+        0
+    } else {
+        (a << shift) & mask(bits)
+    }
+}
+
+// Arithmetic shifts by the width or more fill the value with the sign bit
+fn shift_right(a: u64, shift: u64, bits: u32, signed: bool) -> u64 {
+    let shift = shift as u32;
+    if signed {
+        (sign_extend(a, bits) >> shift.min(bits - 1)) as u64 & mask(bits)
+    } else if shift >= bits {
+        0
+    } else {
+        (a & mask(bits)) >> shift
+    }
+}
+
+fn bfe(a: u64, pos: u64, len: u64, bits: u32, signed: bool) -> u64 {
+    let msb = bits as u64 - 1;
+    let (pos, len) = (pos & 0xff, len & 0xff);
+    let bit = |i: u64| (a >> i) & 1;
+    let sign = if signed && len != 0 {
+        bit((pos + len - 1).min(msb))
+    } else {
+        0
+    };
+    (0..bits as u64).fold(0, |result, i| {
+        let value = if i < len && pos + i <= msb {
+            bit(pos + i)
+        } else {
+            sign
+        };
+        result | (value << i)
+    })
+}
+
+fn bfi(a: u64, b: u64, pos: u64, len: u64, bits: u32) -> u64 {
+    let msb = bits as u64 - 1;
+    let (pos, len) = (pos & 0xff, len & 0xff);
+    let mut result = b & mask(bits);
+    let mut i = 0;
+    while i < len && pos + i <= msb {
+        result = (result & !(1 << (pos + i))) | (((a >> i) & 1) << (pos + i));
+        i += 1;
+    }
+    result
+}
+
+// Default mode: every selector nibble picks one of the 8 bytes of {b, a}, selectors with the
+// high bit set replicate the sign bit of the picked byte
+fn prmt_reference(o: &[u64]) -> Option<u64> {
+    let source = (o[1] << 32) | (o[0] & mask(32));
+    Some((0..4).fold(0, |result, i| {
+        let selector = (o[2] >> (i * 4)) & 0xf;
+        let byte = (source >> ((selector & 7) * 8)) & 0xff;
+        let byte = match (selector & 8 != 0, byte & 0x80 != 0) {
+            (false, _) => byte,
+            (true, true) => 0xff,
+            (true, false) => 0,
+        };
+        result | (byte << (i * 8))
+    }))
+}
+
+impl Property {
+    fn name(&self) -> String {
+        let immediates = self
+            .operands
+            .iter()
+            .filter_map(|operand| match operand {
+                Imm(value) => Some(format!(" {value:#x}")),
+                _ => None,
+            })
+            .collect::<String>();
+        format!("{}{}", self.instruction, immediates)
+    }
+
+    fn to_ptx(&self) -> String {
+        let loaded = self
+            .operands
+            .iter()
+            .filter(|operand| !matches!(operand, Imm(_)))
+            .count();
+        let mut ptx = String::new();
+        writeln!(ptx, ".version 6.5\n.target sm_30\n.address_size 64\n").unwrap();
+        writeln!(
+            ptx,
+            ".visible .entry property(\n    .param .u64 input,\n    .param .u64 output,\n    .param .u32 count\n)\n{{"
+        )
+        .unwrap();
+        for register in [
+            ".u64        in_addr",
+            ".u64        out_addr",
+            ".u64        offset",
+            ".u32        index",
+            ".u32        block_id",
+            ".u32        block_size",
+            ".u32        len",
+            ".pred       in_bounds",
+        ] {
+            writeln!(ptx, "    .reg {register};").unwrap();
+        }
+        for (index, operand) in self.operands.iter().enumerate() {
+            if !matches!(operand, Imm(_)) {
+                writeln!(ptx, "    .reg .b{}        a{index};", operand.bits()).unwrap();
+            }
+        }
+        writeln!(ptx, "    .reg .b{}        result;\n", self.result.bits()).unwrap();
+        ptx.push_str(
+            "    ld.param.u64     in_addr, [input];
+    ld.param.u64     out_addr, [output];
+    ld.param.u32     len, [count];
+    mov.u32          index, %tid.x;
+    mov.u32          block_id, %ctaid.x;
+    mov.u32          block_size, %ntid.x;
+    mad.lo.u32       index, block_id, block_size, index;
+    setp.lt.u32      in_bounds, index, len;
+    @!in_bounds bra  end;
+",
+        );
+        // Every operand and the result take a 64 bit slot, 32 bit values are in the low half
+        writeln!(ptx, "    mul.wide.u32     offset, index, {};", loaded * 8).unwrap();
+        writeln!(ptx, "    add.u64          in_addr, in_addr, offset;").unwrap();
+        let mut arguments = Vec::new();
+        let mut slot = 0;
+        for (index, operand) in self.operands.iter().enumerate() {
+            match operand {
+                Imm(value) => arguments.push(value.to_string()),
+                operand => {
+                    writeln!(
+                        ptx,
+                        "    ld.global.b{}    a{index}, [in_addr+{}];",
+                        operand.bits(),
+                        slot * 8
+                    )
+                    .unwrap();
+                    arguments.push(format!("a{index}"));
+                    slot += 1;
+                }
+            }
+        }
+        writeln!(
+            ptx,
+            "    {:<16} result, {};",
+            self.instruction,
+            arguments.join(", ")
+        )
+        .unwrap();
+        writeln!(ptx, "    mul.wide.u32     offset, index, 8;").unwrap();
+        writeln!(ptx, "    add.u64          out_addr, out_addr, offset;").unwrap();
+        writeln!(
+            ptx,
+            "    st.global.b{}    [out_addr], result;",
+            self.result.bits()
+        )
+        .unwrap();
+        ptx.push_str("end:\n    ret;\n}\n");
+        ptx
+    }
+
+    // Operands of every case, immediates included, and the expected results
+    fn generate(&self, rng: &mut Rng) -> (Vec<Vec<u64>>, Vec<u64>) {
+        (0..CASES)
+            .map(|_| loop {
+                let operands = self
+                    .operands
+                    .iter()
+                    .map(|&operand| random_operand(rng, operand))
+                    .collect::<Vec<_>>();
+                if let Some(expected) = (self.reference)(&operands) {
+                    break (operands, expected & mask(self.result.bits()));
+                }
+            })
+            .unzip()
+    }
+}
+
+fn random_operand(rng: &mut Rng, operand: Operand) -> u64 {
+    let bits = match operand {
+        Imm(value) => return value as u64,
+        Count if rng.chance(80) => return rng.below(72) as u64,
+        operand => operand.bits(),
+    };
+    let max = mask(bits);
+    let value = if rng.chance(30) {
+        // 0, 1, 2, -1, -2, INT_MAX, INT_MIN, INT_MIN + 1
+        *rng.pick(&[
+            0,
+            1,
+            2,
+            max,
+            max - 1,
+            max >> 1,
+            (max >> 1) + 1,
+            (max >> 1) + 2,
+        ])
+    } else if rng.chance(20) {
+        rng.below(256) as u64
+    } else {
+        rng.next()
+    };
+    value & max
+}
+
+fn seed() -> u64 {
+    match env::var("ZLUDA_PROPERTY_SEED") {
+        Ok(seed) => seed
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid value of ZLUDA_PROPERTY_SEED: {seed}")),
+        Err(_) => SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    }
+}
+
+// Runs every property on `backend`, which returns None if it can't run the kernel
+fn check_properties(
+    backend: &str,
+    run: impl Fn(&str, &KernelArgs, LaunchConfig) -> Option<Vec<Vec<u8>>>,
+) {
+    let seed = seed();
+    let mut rng = Rng::new(seed);
+    let mut failures = String::new();
+    for property in PROPERTIES {
+        let (operands, expected) = property.generate(&mut rng);
+        let ptx = property.to_ptx();
+        let loaded = operands
+            .iter()
+            .flat_map(|case| {
+                case.iter()
+                    .zip(property.operands)
+                    .filter(|(_, operand)| !matches!(operand, Imm(_)))
+                    .map(|(value, _)| *value)
+            })
+            .collect::<Vec<_>>();
+        let args = KernelArgs::new()
+            .buffer_in(&loaded)
+            .buffer_out::<u64>(CASES)
+            .scalar(CASES as u32);
+        let launch = LaunchConfig::new((CASES as u32).div_ceil(BLOCK_SIZE), BLOCK_SIZE);
+        let Some(outputs) = run(&ptx, &args, launch) else {
+            continue;
+        };
+        let results = super::from_bytes::<u64>(&outputs[0]);
+        let mismatches = (0..CASES)
+            .filter(|&case| results[case] != expected[case])
+            .collect::<Vec<_>>();
+        if mismatches.is_empty() {
+            continue;
+        }
+        writeln!(
+            failures,
+            "{} differs from the reference on {backend} in {} of {CASES} cases:",
+            property.name(),
+            mismatches.len()
+        )
+        .unwrap();
+        for &case in mismatches.iter().take(MAX_REPORTED_CASES) {
+            let operands = operands[case]
+                .iter()
+                .map(|value| format!("{value:#x}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(
+                failures,
+                "  operands [{operands}]: expected {:#x}, got {:#x}",
+                expected[case], results[case]
+            )
+            .unwrap();
+        }
+        writeln!(failures, "{ptx}").unwrap();
+    }
+    assert!(
+        failures.is_empty(),
+        "ZLUDA_PROPERTY_SEED={seed}\n{failures}"
+    );
+}
+
+#[test]
+fn integer_properties_amdgpu() {
+    if skip_hip() {
+        return;
+    }
+    check_properties("HIP", |ptx, args, launch| {
+        Some(translate_and_run_hip_args("property", ptx, args, launch).unwrap())
+    });
+}
+
+#[test]
+fn integer_properties_cuda() {
+    if skip_cuda() {
+        return;
+    }
+    let name = CString::new("property").unwrap();
+    check_properties("CUDA", |ptx, args, launch| {
+        Some(run_cuda(name.as_c_str(), ptx, args, launch).unwrap())
+    });
+}
+
+#[test]
+fn integer_properties_emulated() {
+    check_properties("emulation", |ptx, args, launch| {
+        emulate_args("property", ptx, args, launch)
+            .inspect_err(|reason| eprintln!("skipping emulation of property: {reason}"))
+            .ok()
+    });
+}
+
+#[test]
+fn references_match_known_results() {
+    let reference = |instruction: &str, operands: &[u64]| {
+        let property = PROPERTIES
+            .iter()
+            .find(|property| property.name() == instruction)
+            .unwrap();
+        (property.reference)(operands).map(|result| result & mask(property.result.bits()))
+    };
+    // Same values as the single-point tests
+    assert_eq!(
+        reference("bfe.u32", &[0b11111000_11000001_00100010_10100000, 16, 8]),
+        Some(0b11000001)
+    );
+    assert_eq!(reference("bfi.b32", &[0b10, 0b101, 0, 2]), Some(0b110));
+    assert_eq!(
+        reference("prmt.b32 0x7604", &[0x70c507d6, 0x6fbd4b5c, 0x7604]),
+        Some(0x6fbdd65c)
+    );
+    assert_eq!(reference("bfe.s32", &[0x80, 4, 4]), Some(0xffff_fff8));
+    assert_eq!(reference("bfe.s32", &[0x80, 4, 0]), Some(0));
+    assert_eq!(reference("shr.s32", &[0x8000_0000, 40]), Some(0xffff_ffff));
+    assert_eq!(reference("shr.u64", &[u64::MAX, 64]), Some(0));
+    assert_eq!(reference("shl.b32", &[1, 32]), Some(0));
+    assert_eq!(
+        reference("mul.hi.s32", &[0x8000_0000, 0x8000_0000]),
+        Some(0x4000_0000)
+    );
+    assert_eq!(reference("div.s32", &[0x8000_0000, 0xffff_ffff]), None);
+    assert_eq!(
+        reference("rem.s32", &[(-7i32) as u32 as u64, 2]),
+        Some(0xffff_ffff)
+    );
+    assert_eq!(reference("clz.b32", &[0]), Some(32));
+}
+
+#[test]
+fn generated_kernels_parse() {
+    for property in PROPERTIES {
+        ptx_parser::parse_module_checked(&property.to_ptx())
+            .unwrap_or_else(|err| panic!("{}: {err:?}", property.name()));
+    }
+}
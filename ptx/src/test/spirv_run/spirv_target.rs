@@ -0,0 +1,106 @@
+// SPIR-V output of the pass pipeline, see TranslateOptions and pass::to_spirv_module_with_options.
+// Golden files in ../spvtxt hold the disassembly after the mandatory passes,
+// ZLUDA_BLESS_SPVTXT=1 overwrites them with the actual output. Every module, with and without the
+// optional passes, must also pass the validator for the OpenCL environment Level Zero consumes
+use super::{read_test_file, unified_diff};
+use crate::pass;
+use rspirv::binary::Disassemble;
+use spirv_tools::val::Validator;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+macro_rules! test_ptx_spirv {
+    ($fn_name:ident) => {
+        paste::item! {
+            #[test]
+            fn [<$fn_name _spirv>]() {
+                let ptx = read_test_file!(concat!(stringify!($fn_name), ".ptx"));
+                let spvtxt = read_test_file!(concat!("../spvtxt/", stringify!($fn_name), ".spvtxt"));
+                test_spirv_assert(stringify!($fn_name), &ptx, &spvtxt);
+            }
+        }
+    };
+}
+
+test_ptx_spirv!(ld_st);
+test_ptx_spirv!(add);
+test_ptx_spirv!(bra);
+
+fn translate(ptx_text: &str, options: pass::TranslateOptions) -> Vec<u32> {
+    let ast = ptx_parser::parse_module_checked(ptx_text).unwrap();
+    let module = pass::to_spirv_module_with_options(ast, options)
+        .unwrap_or_else(|err| panic!("Failed to translate: {}", err));
+    let validator = spirv_tools::val::create(Some(spirv_tools::TargetEnv::OpenCL_2_2));
+    if let Err(err) = validator.validate(&module.words, None) {
+        let disassembly = rspirv::dr::load_words(&module.words).unwrap().disassemble();
+        panic!("{}\n\n{}", err, disassembly);
+    }
+    module.words
+}
+
+fn test_spirv_assert(name: &str, ptx_text: &str, expected_spvtxt: &str) {
+    // Same options as the LLVM golden files
+    let words = translate(
+        ptx_text,
+        pass::TranslateOptions {
+            if_convert_predicates: false,
+            infer_global_state_space: false,
+            promote_local_variables: false,
+            fold_constants: false,
+            deduplicate_values: false,
+            hoist_special_registers: false,
+            eliminate_dead_code: false,
+            scalarize_uniform_loads: false,
+            internalize_functions: false,
+            ..pass::TranslateOptions::default()
+        },
+    );
+    translate(ptx_text, pass::TranslateOptions::default());
+    let actual_spvtxt = rspirv::dr::load_words(&words).unwrap().disassemble();
+    if actual_spvtxt.trim() == expected_spvtxt.trim() {
+        return;
+    }
+    if env::var("ZLUDA_BLESS_SPVTXT").as_deref() == Ok("1") {
+        let golden_file = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/test/spvtxt")
+            .join(format!("{}.spvtxt", name));
+        fs::write(golden_file, actual_spvtxt + "\n").unwrap();
+        return;
+    }
+    panic!(
+        "SPIR-V of {} differs from the golden file\n\n--- expected\n+++ actual\n{}",
+        name,
+        unified_diff(expected_spvtxt.trim(), actual_spvtxt.trim())
+    );
+}
+
+#[test]
+fn unsupported_instruction_is_reported() {
+    let ptx = "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .entry mul(
+            .param .u64 output
+        )
+        {
+            .reg .u64 out_addr;
+            .reg .u32 temp;
+            ld.param.u64 out_addr, [output];
+            ld.u32 temp, [out_addr];
+            mul.lo.u32 temp, temp, temp;
+            st.u32 [out_addr], temp;
+            ret;
+        }";
+    let ast = ptx_parser::parse_module_checked(ptx).unwrap();
+    let err = match pass::to_spirv_module_with_options(ast, pass::TranslateOptions::default()) {
+        Ok(_) => panic!("mul translated to SPIR-V"),
+        Err(err) => err,
+    };
+    assert!(
+        matches!(err.inner(), pass::TranslateError::UnsupportedSpirv { .. }),
+        "{err}"
+    );
+}
@@ -0,0 +1,262 @@
+use ptx::{CompileError, Compiler, KernelInfo, OptLevel, ParameterInfo, Target};
+
+const ADD: &str = include_str!("../src/test/spirv_run/add.ptx");
+
+fn add_metadata() -> Vec<KernelInfo> {
+    let pointer = ParameterInfo {
+        size: 8,
+        align: 8,
+        is_pointer: true,
+        pointee_align: None,
+    };
+    vec![KernelInfo {
+        name: "add".to_string(),
+        parameters: vec![pointer, pointer],
+        static_shared_memory: 0,
+        dynamic_shared_memory: false,
+        local_memory: 0,
+        constant_memory: 0,
+        tuning: Vec::new(),
+    }]
+}
+
+#[test]
+fn add_to_llvm_bitcode() -> Result<(), CompileError> {
+    let artifact = Compiler::new(Target::LlvmBitcode).compile(ADD)?;
+    assert_eq!(artifact.target(), Target::LlvmBitcode);
+    assert!(artifact.bytes().starts_with(b"BC\xC0\xDE"));
+    assert!(artifact.attributes_bitcode().starts_with(b"BC\xC0\xDE"));
+    assert!(!artifact.linked_bitcode().is_empty());
+    assert_eq!(artifact.kernels(), add_metadata());
+    Ok(())
+}
+
+#[test]
+fn add_to_llvm_ir() -> Result<(), CompileError> {
+    let artifact = Compiler::new(Target::LlvmIr)
+        .opt_level(OptLevel::None)
+        .compile(ADD)?;
+    let ir = std::str::from_utf8(artifact.bytes()).unwrap();
+    assert!(ir.contains("define amdgpu_kernel void @add("));
+    assert!(!artifact.correctly_rounded_div_sqrt());
+    assert_eq!(artifact.kernels(), add_metadata());
+    Ok(())
+}
+
+#[test]
+#[ignore = "requires ROCm comgr"]
+fn add_to_code_object() -> Result<(), Box<dyn std::error::Error>> {
+    let artifact = Compiler::new(Target::LlvmBitcode).compile(ADD)?;
+    let comgr = comgr::Comgr::new()?;
+    let elf = comgr::compile_bitcode(
+        &comgr,
+        "gfx1030",
+        artifact.bytes(),
+        artifact.linked_bitcode(),
+        artifact.attributes_bitcode(),
+        artifact.correctly_rounded_div_sqrt(),
+        None,
+    )?;
+    assert!(elf.starts_with(b"\x7FELF"));
+    Ok(())
+}
+
+#[test]
+fn shared_memory_is_reported_per_kernel() -> Result<(), CompileError> {
+    let ptx = "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .shared .align 4 .b8 module_shared[64];
+        .extern .shared .align 4 .b8 dynamic_shared[];
+
+        .visible .entry with_shared(.param .u64 out)
+        {
+            .shared .align 8 .u64 local_shared[4];
+            .reg .u64 r;
+            ret;
+        }
+
+        .visible .entry with_dynamic(.param .u32 n, .param .align 8 .b8 blob[24])
+        {
+            ret;
+        }
+    ";
+    let artifact = Compiler::new(Target::LlvmIr).compile(ptx)?;
+    let kernels = artifact.kernels();
+    assert_eq!(kernels.len(), 2);
+    assert_eq!(kernels[0].name, "with_shared");
+    assert_eq!(kernels[0].static_shared_memory, 96);
+    assert!(kernels[0].dynamic_shared_memory);
+    assert_eq!(kernels[1].name, "with_dynamic");
+    assert_eq!(
+        kernels[1].parameters,
+        [
+            ParameterInfo {
+                size: 4,
+                align: 4,
+                is_pointer: false,
+                pointee_align: None,
+            },
+            ParameterInfo {
+                size: 24,
+                align: 8,
+                is_pointer: false,
+                pointee_align: None,
+            },
+        ]
+    );
+    assert_eq!(kernels[1].static_shared_memory, 64);
+    assert!(kernels[1].dynamic_shared_memory);
+    Ok(())
+}
+
+// The HIP runtime places kernel arguments by the alignment of the byref types, CUDA by the natural
+// alignment of the parameters, for this kernel both give 4 bytes of padding before each pointer
+#[test]
+fn scalar_parameters_keep_natural_alignment() -> Result<(), CompileError> {
+    let saxpy = include_str!("../src/test/spirv_run/saxpy_scalar_params.ptx");
+    let artifact = Compiler::new(Target::LlvmIr).compile(saxpy)?;
+    let kernel = &artifact.kernels()[0];
+    let layout = kernel
+        .parameters
+        .iter()
+        .map(|param| (param.size, param.align))
+        .collect::<Vec<_>>();
+    assert_eq!(layout, [(4, 4), (8, 8), (4, 4), (8, 8)]);
+    assert_eq!(kernel.parameters_size(), 32);
+    let ir = std::str::from_utf8(artifact.bytes()).unwrap();
+    let signature = ir
+        .lines()
+        .find(|line| line.contains("@saxpy_scalar_params("))
+        .unwrap();
+    let mut rest = signature;
+    for byref in ["byref(i32)", "byref(i64)", "byref(float)", "byref(i64)"] {
+        rest = rest.split_once(byref).unwrap().1;
+    }
+    Ok(())
+}
+
+#[test]
+fn module_without_kernels_reports_none() -> Result<(), CompileError> {
+    let ptx = "
+        .version 6.5
+        .target sm_30
+        .address_size 64
+
+        .visible .global .align 4 .u32 counter;
+
+        .visible .func (.reg .u32 result) next()
+        {
+            atom.global.add.u32 result, [counter], 1;
+            ret;
+        }
+    ";
+    let artifact = Compiler::new(Target::LlvmIr).compile(ptx)?;
+    assert!(artifact.kernels().is_empty());
+    let ir = std::str::from_utf8(artifact.bytes()).unwrap();
+    assert!(ir.contains("define hidden i32 @next("));
+    assert!(ir.contains("@counter = addrspace(1) global i32 0"));
+    Ok(())
+}
+
+#[test]
+fn fp_contract_fuses_mul_add_without_rounding_modifiers() -> Result<(), CompileError> {
+    let mul_add_contract = include_str!("../src/test/spirv_run/mul_add_contract.ptx");
+    let fma_calls = |fp_contract| -> Result<usize, CompileError> {
+        let artifact = Compiler::new(Target::LlvmIr)
+            .fp_contract(fp_contract)
+            .compile(mul_add_contract)?;
+        let ir = std::str::from_utf8(artifact.bytes()).unwrap();
+        Ok(ir.matches("call float @llvm.fma.f32").count())
+    };
+    // mul.rn and add.rn are never fused
+    assert_eq!(fma_calls(true)?, 1);
+    assert_eq!(fma_calls(false)?, 0);
+    Ok(())
+}
+
+#[test]
+fn invalid_ptx_is_a_parse_error() {
+    let err = Compiler::new(Target::LlvmBitcode)
+        .compile(&ADD.replace("add.u64", "frobnicate.u64"))
+        .unwrap_err();
+    assert!(matches!(err, CompileError::Parse(_)));
+    assert!(err.to_string().contains("frobnicate"));
+}
+
+// Cached binaries are keyed by the PTX text, so the same text must always give the same bitcode.
+// call_rnd has several functions with different rounding modes, which goes through every pass
+// that collects functions and modes into maps
+#[test]
+fn same_text_gives_identical_bitcode() -> Result<(), CompileError> {
+    let call_rnd = include_str!("../src/test/spirv_run/call_rnd.ptx");
+    let compiler = Compiler::new(Target::LlvmBitcode);
+    let first = compiler.compile(call_rnd)?;
+    for _ in 1..20 {
+        let artifact = compiler.compile(call_rnd)?;
+        assert!(artifact.bytes() == first.bytes());
+        assert!(artifact.attributes_bitcode() == first.attributes_bitcode());
+    }
+    Ok(())
+}
+
+#[test]
+fn batch_gives_the_same_artifacts_as_single_modules() -> Result<(), CompileError> {
+    let call_rnd = include_str!("../src/test/spirv_run/call_rnd.ptx");
+    let compiler = Compiler::new(Target::LlvmBitcode);
+    let batch = compiler.compile_batch([ADD, "not ptx", call_rnd]);
+    assert_eq!(batch.len(), 3);
+    assert!(batch[0].as_ref().unwrap() == &compiler.compile(ADD)?);
+    assert!(matches!(batch[1], Err(CompileError::Parse(_))));
+    assert!(batch[2].as_ref().unwrap() == &compiler.compile(call_rnd)?);
+    Ok(())
+}
+
+#[test]
+fn entry_point_leaves_out_other_kernels() -> Result<(), CompileError> {
+    let entry_points = include_str!("../src/test/spirv_run/entry_points.ptx");
+    let artifact = Compiler::new(Target::LlvmIr)
+        .entry_point("entry_points")
+        .compile(entry_points)?;
+    let ir = std::str::from_utf8(artifact.bytes()).unwrap();
+    assert!(
+        ir.contains("define amdgpu_kernel void @entry_points("),
+        "{ir}"
+    );
+    assert!(ir.contains(" @scale("), "{ir}");
+    assert!(ir.contains("@factors_address = "), "{ir}");
+    assert!(ir.contains("@factors = "), "{ir}");
+    for unused in ["@entry_points_other(", "@other_helper(", "@other_table"] {
+        assert!(!ir.contains(unused), "{ir}");
+    }
+    // Metadata still describes every kernel, so the others can be compiled later
+    let kernels = artifact
+        .kernels()
+        .iter()
+        .map(|kernel| kernel.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(kernels, ["entry_points", "entry_points_other"]);
+    Ok(())
+}
+
+#[test]
+fn unknown_entry_point_lists_kernels() {
+    let entry_points = include_str!("../src/test/spirv_run/entry_points.ptx");
+    let err = Compiler::new(Target::LlvmIr)
+        .entry_point("entry_points")
+        .entry_point("missing")
+        .compile(entry_points)
+        .unwrap_err();
+    assert!(
+        matches!(&err, CompileError::Translate(err) if err.inner().as_ref() == "KernelNotFound"),
+        "{err}"
+    );
+    assert!(
+        err.to_string().contains(
+            "Kernel \"missing\" not found, the module has \"entry_points\", \"entry_points_other\""
+        ),
+        "{err}"
+    );
+}
@@ -0,0 +1,127 @@
+use ptx::{validate_launch, DeviceLimits, KernelInfo, LaunchError, TuningDirective};
+
+fn kernel(tuning: Vec<TuningDirective>) -> KernelInfo {
+    KernelInfo {
+        name: "kernel".to_string(),
+        parameters: Vec::new(),
+        static_shared_memory: 1024,
+        dynamic_shared_memory: true,
+        local_memory: 0,
+        constant_memory: 0,
+        tuning,
+    }
+}
+
+#[test]
+fn launch_within_limits_is_valid() {
+    let kernel = kernel(vec![
+        TuningDirective::MaxNtid(256, 1, 1),
+        TuningDirective::MinNCtaPerSm(4),
+    ]);
+    let limits = DeviceLimits::default();
+    // Only the total of .maxntid is a limit, not its shape
+    assert_eq!(
+        validate_launch(&kernel, (1000, 2, 1), (16, 16, 1), 4096, &limits),
+        Ok(())
+    );
+}
+
+#[test]
+fn empty_grid_or_block_is_invalid() {
+    let limits = DeviceLimits::default();
+    assert_eq!(
+        validate_launch(&kernel(Vec::new()), (1, 0, 1), (32, 1, 1), 0, &limits),
+        Err(LaunchError::EmptyLaunch {
+            grid: (1, 0, 1),
+            block: (32, 1, 1)
+        })
+    );
+}
+
+#[test]
+fn grid_over_device_limit_is_invalid() {
+    let limits = DeviceLimits::default();
+    assert_eq!(
+        validate_launch(&kernel(Vec::new()), (1, 65536, 1), (32, 1, 1), 0, &limits),
+        Err(LaunchError::GridTooLarge {
+            grid: (1, 65536, 1),
+            max_grid_dim: limits.max_grid_dim
+        })
+    );
+}
+
+#[test]
+fn block_over_device_limit_is_invalid() {
+    let limits = DeviceLimits::default();
+    let too_large = LaunchError::BlockTooLarge {
+        block: (1, 1, 128),
+        max_block_dim: limits.max_block_dim,
+        max_threads_per_block: limits.max_threads_per_block,
+    };
+    assert_eq!(
+        validate_launch(&kernel(Vec::new()), (1, 1, 1), (1, 1, 128), 0, &limits),
+        Err(too_large)
+    );
+    assert!(matches!(
+        validate_launch(&kernel(Vec::new()), (1, 1, 1), (64, 32, 1), 0, &limits),
+        Err(LaunchError::BlockTooLarge { .. })
+    ));
+}
+
+#[test]
+fn block_over_maxntid_is_invalid() {
+    let err = validate_launch(
+        &kernel(vec![TuningDirective::MaxNtid(16, 16, 1)]),
+        (1, 1, 1),
+        (512, 1, 1),
+        0,
+        &DeviceLimits::default(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        LaunchError::MaxNtidExceeded {
+            block: (512, 1, 1),
+            maxntid: (16, 16, 1)
+        }
+    );
+    assert!(err.to_string().contains("allows 256"), "{err}");
+}
+
+#[test]
+fn block_other_than_reqntid_is_invalid() {
+    let kernel = kernel(vec![TuningDirective::ReqNtid(32, 4, 1)]);
+    let limits = DeviceLimits::default();
+    assert_eq!(
+        validate_launch(&kernel, (1, 1, 1), (32, 4, 1), 0, &limits),
+        Ok(())
+    );
+    assert_eq!(
+        validate_launch(&kernel, (1, 1, 1), (4, 32, 1), 0, &limits),
+        Err(LaunchError::ReqNtidMismatch {
+            block: (4, 32, 1),
+            reqntid: (32, 4, 1)
+        })
+    );
+}
+
+#[test]
+fn shared_memory_over_device_limit_is_invalid() {
+    let limits = DeviceLimits {
+        max_shared_memory_per_block: 64 * 1024,
+        ..Default::default()
+    };
+    let kernel = kernel(Vec::new());
+    assert_eq!(
+        validate_launch(&kernel, (1, 1, 1), (32, 1, 1), 63 * 1024, &limits),
+        Ok(())
+    );
+    assert_eq!(
+        validate_launch(&kernel, (1, 1, 1), (32, 1, 1), 63 * 1024 + 1, &limits),
+        Err(LaunchError::SharedMemoryTooLarge {
+            static_shared_memory: 1024,
+            dynamic_shared_memory: 63 * 1024 + 1,
+            max_shared_memory_per_block: 64 * 1024
+        })
+    );
+}
@@ -0,0 +1,7 @@
+fn main() {
+    // cc needs the target triple when invoked from the C API integration test
+    println!(
+        "cargo:rustc-env=PTX_CAPI_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+}
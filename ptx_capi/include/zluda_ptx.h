@@ -0,0 +1,83 @@
+/* C interface to the ZLUDA PTX compiler, implemented by the ptx_capi crate.
+ *
+ * Ownership rules:
+ *  - Every pointer returned by this library is owned by the library.
+ *  - An artifact is freed with zluda_artifact_destroy. Buffers and strings returned by artifact
+ *    accessors stay valid until their artifact is destroyed.
+ *  - The string returned by zluda_get_last_error_string stays valid until the next call into
+ *    this library on the same thread.
+ *  - Pointers passed into the library are only read during the call, the caller keeps owning
+ *    them.
+ */
+
+#ifndef ZLUDA_PTX_H
+#define ZLUDA_PTX_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum zluda_result {
+  ZLUDA_PTX_SUCCESS = 0,
+  ZLUDA_PTX_ERROR_INVALID_VALUE = 1,
+  ZLUDA_PTX_ERROR_PARSE = 2,
+  ZLUDA_PTX_ERROR_TRANSLATE = 3,
+  ZLUDA_PTX_ERROR_TOOLCHAIN = 4,
+  ZLUDA_PTX_ERROR_INTERNAL = 5,
+} zluda_result;
+
+typedef enum zluda_opt_level {
+  ZLUDA_PTX_OPT_NONE = 0,
+  ZLUDA_PTX_OPT_DEFAULT = 1,
+} zluda_opt_level;
+
+typedef struct zluda_artifact zluda_artifact;
+
+typedef struct zluda_compile_options {
+  zluda_opt_level opt_level;
+  /**
+   * Clock frequency in kHz of the target device
+   */
+  uint32_t clock_rate;
+} zluda_compile_options;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Compile PTX text. Without `arch` the artifact holds LLVM bitcode of the module, with `arch`
+ * (e.g. "gfx1030") it holds an AMDGPU code object and requires comgr at runtime.
+ */
+zluda_result zluda_ptx_compile(const char *ptx,
+                               const char *arch,
+                               const zluda_compile_options *options,
+                               zluda_artifact **out);
+
+zluda_result zluda_artifact_bytes(const zluda_artifact *artifact,
+                                  const uint8_t **data,
+                                  size_t *size);
+
+zluda_result zluda_artifact_kernel_count(const zluda_artifact *artifact, size_t *count);
+
+zluda_result zluda_artifact_kernel_name(const zluda_artifact *artifact,
+                                        size_t index,
+                                        const char **name);
+
+zluda_result zluda_artifact_kernel_metadata(const zluda_artifact *artifact,
+                                            size_t index,
+                                            const size_t **parameter_sizes,
+                                            size_t *parameter_count,
+                                            size_t *static_shared_memory);
+
+void zluda_artifact_destroy(zluda_artifact *artifact);
+
+/**
+ * Message of the last failed call on this thread, empty after a successful one.
+ */
+const char *zluda_get_last_error_string(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* ZLUDA_PTX_H */
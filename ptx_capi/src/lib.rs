@@ -0,0 +1,303 @@
+//! C interface to the PTX compiler, declared in `include/zluda_ptx.h`.
+//!
+//! Ownership rules: every pointer returned by this library is owned by it. Artifacts are freed
+//! with `zluda_artifact_destroy`, strings and buffers returned by artifact accessors live as long
+//! as their artifact and the last error string lives until the next call on the same thread.
+//! Pointers passed in are only read during the call.
+#![allow(non_camel_case_types)]
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum zluda_result {
+    ZLUDA_PTX_SUCCESS = 0,
+    ZLUDA_PTX_ERROR_INVALID_VALUE = 1,
+    ZLUDA_PTX_ERROR_PARSE = 2,
+    ZLUDA_PTX_ERROR_TRANSLATE = 3,
+    ZLUDA_PTX_ERROR_TOOLCHAIN = 4,
+    ZLUDA_PTX_ERROR_INTERNAL = 5,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum zluda_opt_level {
+    ZLUDA_PTX_OPT_NONE = 0,
+    ZLUDA_PTX_OPT_DEFAULT = 1,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct zluda_compile_options {
+    pub opt_level: zluda_opt_level,
+    /// Clock frequency in kHz of the target device
+    pub clock_rate: u32,
+}
+
+pub struct zluda_artifact {
+    bytes: Vec<u8>,
+    kernels: Vec<Kernel>,
+}
+
+struct Kernel {
+    name: CString,
+    parameter_sizes: Vec<usize>,
+    static_shared_memory: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: String) {
+    // Interior NULs would truncate the message on the C side anyway
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
+
+fn fail(result: zluda_result, message: impl Into<String>) -> zluda_result {
+    set_last_error(message.into());
+    result
+}
+
+fn guard(f: impl FnOnce() -> zluda_result) -> zluda_result {
+    set_last_error(String::new());
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            fail(
+                zluda_result::ZLUDA_PTX_ERROR_INTERNAL,
+                format!("panic: {message}"),
+            )
+        }
+    }
+}
+
+/// Compile PTX text. Without `arch` the artifact holds LLVM bitcode of the module, with `arch`
+/// (e.g. "gfx1030") it holds an AMDGPU code object and requires comgr at runtime.
+///
+/// # Safety
+/// `ptx` and `arch` must be NUL-terminated strings or NULL, `options` must be NULL or point to
+/// a valid `zluda_compile_options`, `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn zluda_ptx_compile(
+    ptx: *const c_char,
+    arch: *const c_char,
+    options: *const zluda_compile_options,
+    out: *mut *mut zluda_artifact,
+) -> zluda_result {
+    guard(|| {
+        if ptx.is_null() || out.is_null() {
+            return fail(
+                zluda_result::ZLUDA_PTX_ERROR_INVALID_VALUE,
+                "ptx and out must not be NULL",
+            );
+        }
+        *out = ptr::null_mut();
+        let text = match CStr::from_ptr(ptx).to_str() {
+            Ok(text) => text,
+            Err(err) => return fail(zluda_result::ZLUDA_PTX_ERROR_INVALID_VALUE, err.to_string()),
+        };
+        let arch = if arch.is_null() {
+            None
+        } else {
+            match CStr::from_ptr(arch).to_str() {
+                Ok(arch) => Some(arch),
+                Err(err) => {
+                    return fail(zluda_result::ZLUDA_PTX_ERROR_INVALID_VALUE, err.to_string())
+                }
+            }
+        };
+        let mut compiler = ptx::Compiler::new(ptx::Target::LlvmBitcode);
+        if let Some(options) = options.as_ref() {
+            compiler = compiler
+                .opt_level(match options.opt_level {
+                    zluda_opt_level::ZLUDA_PTX_OPT_NONE => ptx::OptLevel::None,
+                    zluda_opt_level::ZLUDA_PTX_OPT_DEFAULT => ptx::OptLevel::Default,
+                })
+                .clock_rate(options.clock_rate);
+        }
+        let artifact = match compiler.compile(text) {
+            Ok(artifact) => artifact,
+            Err(err @ ptx::CompileError::Parse(_)) => {
+                return fail(zluda_result::ZLUDA_PTX_ERROR_PARSE, err.to_string())
+            }
+            Err(err) => return fail(zluda_result::ZLUDA_PTX_ERROR_TRANSLATE, err.to_string()),
+        };
+        let bytes = match arch {
+            None => artifact.bytes().to_vec(),
+            Some(arch) => match compile_code_object(&artifact, arch) {
+                Ok(bytes) => bytes,
+                Err(err) => return fail(zluda_result::ZLUDA_PTX_ERROR_TOOLCHAIN, err.to_string()),
+            },
+        };
+        let kernels = artifact
+            .kernels()
+            .iter()
+            .map(|kernel| Kernel {
+                // PTX identifiers never contain NUL
+                name: CString::new(kernel.name.as_str()).unwrap(),
+                parameter_sizes: kernel.parameters.iter().map(|param| param.size).collect(),
+                static_shared_memory: kernel.static_shared_memory,
+            })
+            .collect();
+        *out = Box::into_raw(Box::new(zluda_artifact { bytes, kernels }));
+        zluda_result::ZLUDA_PTX_SUCCESS
+    })
+}
+
+fn compile_code_object(artifact: &ptx::Artifact, arch: &str) -> Result<Vec<u8>, comgr::Error> {
+    let comgr = comgr::Comgr::new()?;
+    comgr::compile_bitcode(
+        &comgr,
+        arch,
+        artifact.bytes(),
+        artifact.linked_bitcode(),
+        artifact.attributes_bitcode(),
+        artifact.correctly_rounded_div_sqrt(),
+        None,
+    )
+}
+
+/// # Safety
+/// `artifact` must come from `zluda_ptx_compile`, `data` and `size` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn zluda_artifact_bytes(
+    artifact: *const zluda_artifact,
+    data: *mut *const u8,
+    size: *mut usize,
+) -> zluda_result {
+    guard(|| {
+        let (Some(artifact), false, false) = (artifact.as_ref(), data.is_null(), size.is_null())
+        else {
+            return fail(
+                zluda_result::ZLUDA_PTX_ERROR_INVALID_VALUE,
+                "artifact, data and size must not be NULL",
+            );
+        };
+        *data = artifact.bytes.as_ptr();
+        *size = artifact.bytes.len();
+        zluda_result::ZLUDA_PTX_SUCCESS
+    })
+}
+
+/// # Safety
+/// `artifact` must come from `zluda_ptx_compile`, `count` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn zluda_artifact_kernel_count(
+    artifact: *const zluda_artifact,
+    count: *mut usize,
+) -> zluda_result {
+    guard(|| {
+        let (Some(artifact), false) = (artifact.as_ref(), count.is_null()) else {
+            return fail(
+                zluda_result::ZLUDA_PTX_ERROR_INVALID_VALUE,
+                "artifact and count must not be NULL",
+            );
+        };
+        *count = artifact.kernels.len();
+        zluda_result::ZLUDA_PTX_SUCCESS
+    })
+}
+
+unsafe fn get_kernel<'a>(
+    artifact: *const zluda_artifact,
+    index: usize,
+) -> Result<&'a Kernel, zluda_result> {
+    let Some(artifact) = artifact.as_ref() else {
+        return Err(fail(
+            zluda_result::ZLUDA_PTX_ERROR_INVALID_VALUE,
+            "artifact must not be NULL",
+        ));
+    };
+    artifact.kernels.get(index).ok_or_else(|| {
+        fail(
+            zluda_result::ZLUDA_PTX_ERROR_INVALID_VALUE,
+            format!(
+                "kernel index {index} out of range, artifact has {} kernels",
+                artifact.kernels.len()
+            ),
+        )
+    })
+}
+
+/// # Safety
+/// `artifact` must come from `zluda_ptx_compile`, `name` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn zluda_artifact_kernel_name(
+    artifact: *const zluda_artifact,
+    index: usize,
+    name: *mut *const c_char,
+) -> zluda_result {
+    guard(|| {
+        if name.is_null() {
+            return fail(
+                zluda_result::ZLUDA_PTX_ERROR_INVALID_VALUE,
+                "name must not be NULL",
+            );
+        }
+        match get_kernel(artifact, index) {
+            Ok(kernel) => {
+                *name = kernel.name.as_ptr();
+                zluda_result::ZLUDA_PTX_SUCCESS
+            }
+            Err(err) => err,
+        }
+    })
+}
+
+/// # Safety
+/// `artifact` must come from `zluda_ptx_compile`, `parameter_sizes`, `parameter_count` and
+/// `static_shared_memory` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn zluda_artifact_kernel_metadata(
+    artifact: *const zluda_artifact,
+    index: usize,
+    parameter_sizes: *mut *const usize,
+    parameter_count: *mut usize,
+    static_shared_memory: *mut usize,
+) -> zluda_result {
+    guard(|| {
+        if parameter_sizes.is_null() || parameter_count.is_null() || static_shared_memory.is_null()
+        {
+            return fail(
+                zluda_result::ZLUDA_PTX_ERROR_INVALID_VALUE,
+                "parameter_sizes, parameter_count and static_shared_memory must not be NULL",
+            );
+        }
+        match get_kernel(artifact, index) {
+            Ok(kernel) => {
+                *parameter_sizes = kernel.parameter_sizes.as_ptr();
+                *parameter_count = kernel.parameter_sizes.len();
+                *static_shared_memory = kernel.static_shared_memory;
+                zluda_result::ZLUDA_PTX_SUCCESS
+            }
+            Err(err) => err,
+        }
+    })
+}
+
+/// # Safety
+/// `artifact` must be NULL or come from `zluda_ptx_compile` and not be destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn zluda_artifact_destroy(artifact: *mut zluda_artifact) {
+    if !artifact.is_null() {
+        drop(Box::from_raw(artifact));
+    }
+}
+
+/// Message of the last failed call on this thread, empty after a successful one.
+#[no_mangle]
+pub extern "C" fn zluda_get_last_error_string() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
+}
@@ -0,0 +1,65 @@
+#include <stdio.h>
+#include <string.h>
+#include "zluda_ptx.h"
+
+#define CHECK(cond)                                                                   \
+  do {                                                                                \
+    if (!(cond)) {                                                                    \
+      fprintf(stderr, "%s:%d: %s (%s)\n", __FILE__, __LINE__, #cond,                 \
+              zluda_get_last_error_string());                                         \
+      return 1;                                                                       \
+    }                                                                                 \
+  } while (0)
+
+static const char *ADD =
+    ".version 6.5\n"
+    ".target sm_30\n"
+    ".address_size 64\n"
+    ".visible .entry add(.param .u64 input, .param .u64 output)\n"
+    "{\n"
+    "  .reg .u64 in_addr;\n"
+    "  .reg .u64 out_addr;\n"
+    "  .reg .u64 temp;\n"
+    "  ld.param.u64 in_addr, [input];\n"
+    "  ld.param.u64 out_addr, [output];\n"
+    "  ld.u64 temp, [in_addr];\n"
+    "  add.u64 temp, temp, 1;\n"
+    "  st.u64 [out_addr], temp;\n"
+    "  ret;\n"
+    "}\n";
+
+int main(void) {
+  zluda_compile_options options = {ZLUDA_PTX_OPT_DEFAULT, 1000000};
+  zluda_artifact *artifact = NULL;
+  CHECK(zluda_ptx_compile(ADD, NULL, &options, &artifact) == ZLUDA_PTX_SUCCESS);
+  CHECK(artifact != NULL);
+
+  const uint8_t *data;
+  size_t size;
+  CHECK(zluda_artifact_bytes(artifact, &data, &size) == ZLUDA_PTX_SUCCESS);
+  CHECK(size > 4 && memcmp(data, "BC\xC0\xDE", 4) == 0);
+
+  size_t count;
+  CHECK(zluda_artifact_kernel_count(artifact, &count) == ZLUDA_PTX_SUCCESS);
+  CHECK(count == 1);
+  const char *name;
+  CHECK(zluda_artifact_kernel_name(artifact, 0, &name) == ZLUDA_PTX_SUCCESS);
+  CHECK(strcmp(name, "add") == 0);
+  const size_t *parameter_sizes;
+  size_t parameter_count, shared;
+  CHECK(zluda_artifact_kernel_metadata(artifact, 0, &parameter_sizes, &parameter_count,
+                                       &shared) == ZLUDA_PTX_SUCCESS);
+  CHECK(parameter_count == 2 && parameter_sizes[0] == 8 && parameter_sizes[1] == 8);
+  CHECK(shared == 0);
+  CHECK(zluda_artifact_kernel_name(artifact, 1, &name) == ZLUDA_PTX_ERROR_INVALID_VALUE);
+  CHECK(strstr(zluda_get_last_error_string(), "out of range") != NULL);
+  zluda_artifact_destroy(artifact);
+
+  artifact = NULL;
+  CHECK(zluda_ptx_compile(".version 6.5\n.target sm_30\nfrobnicate;\n", NULL, NULL, &artifact) ==
+        ZLUDA_PTX_ERROR_PARSE);
+  CHECK(artifact == NULL);
+  CHECK(strlen(zluda_get_last_error_string()) > 0);
+  CHECK(zluda_ptx_compile(NULL, NULL, NULL, &artifact) == ZLUDA_PTX_ERROR_INVALID_VALUE);
+  return 0;
+}
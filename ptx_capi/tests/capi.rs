@@ -0,0 +1,37 @@
+#![cfg(unix)]
+use std::{env, path::PathBuf, process::Command};
+
+#[test]
+fn c_program_compiles_add() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // Integration tests live next to the cdylib in target/<profile>/deps
+    let deps_dir = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let out_dir = tempfile::tempdir().unwrap();
+    let exe = out_dir.path().join("capi");
+    let compiler = cc::Build::new()
+        .target(env!("PTX_CAPI_TARGET"))
+        .host(env!("PTX_CAPI_TARGET"))
+        .opt_level(0)
+        .cargo_metadata(false)
+        .get_compiler();
+    let status = compiler
+        .to_command()
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(manifest_dir.join("tests").join("capi.c"))
+        .arg("-o")
+        .arg(&exe)
+        .arg("-L")
+        .arg(&deps_dir)
+        .arg("-lzluda_ptx")
+        .arg(format!("-Wl,-rpath,{}", deps_dir.display()))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = Command::new(&exe).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -3,16 +3,74 @@ use super::{
     StateSpace, VectorPrefix,
 };
 use crate::{
-    FunnelShiftMode, Mul24Control, PtxError, PtxParserState, Reduction, ShiftDirection, ShuffleMode,
+    FunnelShiftMode, MatrixFragment, MatrixLayout, MatrixType, MmaShape, Mul24Control, PtxError,
+    PtxParserState, Reduction, ShiftDirection, ShuffleMode,
 };
 use bitflags::bitflags;
 use std::{alloc::Layout, cmp::Ordering, fmt::Write, num::NonZeroU8};
 
 pub enum Statement<P: Operand> {
-    Label(P::Ident),
-    Variable(MultiVariable<P::Ident>),
-    Instruction(Option<PredAt<P::Ident>>, Instruction<P>),
+    Label(P::Ident, Span),
+    Variable(MultiVariable<P::Ident>, Span),
+    Instruction(Option<PredAt<P::Ident>>, Instruction<P>, Span),
     Block(Vec<Statement<P>>),
+    Pragma(LoopPragma, Span),
+}
+
+/// Statement-level `.pragma` controlling the unrolling of the loop whose header is the basic
+/// block containing it. Other pragmas are ignored by the parser
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoopPragma {
+    /// `.pragma "nounroll";`
+    NoUnroll,
+    /// `.pragma "unroll N";`, not in the PTX ISA, but accepted for hand-written kernels
+    Unroll(u32),
+}
+
+/// Byte range of a statement or a directive in the module text
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn location(self, text: &str) -> SourceLocation {
+        let start = self.start.min(text.len());
+        let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+        SourceLocation {
+            line: text[..line_start].matches('\n').count() + 1,
+            column: text[line_start..start].chars().count() + 1,
+            snippet: text[line_start..line_end].trim_end().to_string(),
+        }
+    }
+}
+
+/// Position of a span resolved against the module text, line and column are 1-based
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SourceLocation {
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String,
+}
+
+impl SourceLocation {
+    /// Formats `message` as "line N: message", followed by the offending line and a caret under
+    /// the start of the span
+    pub fn annotate(&self, message: &dyn std::fmt::Display) -> String {
+        // PTX emitted by NVCC is indented with tabs, we copy them so the caret stays aligned
+        let padding = self
+            .snippet
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        format!(
+            "line {}: {}\n    {}\n    {}^",
+            self.line, message, self.snippet, padding
+        )
+    }
 }
 
 // We define the instruction enum through the macro instead of normally, because we have some of how
@@ -55,6 +113,15 @@ ptx_parser_macros::generate_instruction_type!(
                 src2: T,
             }
         },
+        AddExtended {
+            type: { Type::Scalar(data.type_) },
+            data: CarryDetails,
+            arguments<T>: {
+                dst: T,
+                src1: T,
+                src2: T,
+            }
+        },
         And {
             data: ScalarType,
             type: { Type::Scalar(data.clone()) },
@@ -104,6 +171,22 @@ ptx_parser_macros::generate_instruction_type!(
                 src2: Option<T>,
             }
         },
+        BarArrive {
+            type: Type::Scalar(ScalarType::U32),
+            data: BarData,
+            arguments<T>: {
+                src1: T,
+                src2: T,
+            }
+        },
+        BarrierCluster {
+            data: BarrierClusterData,
+            display: write!(
+                f,
+                "barrier.cluster.{}",
+                if data.wait { "wait" } else { "arrive" }
+            )?
+        },
         BarRed {
             type: Type::Scalar(ScalarType::U32),
             data: BarRedData,
@@ -124,6 +207,23 @@ ptx_parser_macros::generate_instruction_type!(
                 },
             }
         },
+        BarRedPopc {
+            type: Type::Scalar(ScalarType::U32),
+            data: BarData,
+            arguments<T>: {
+                dst: T,
+                src_barrier: T,
+                src_threadcount: Option<T>,
+                src_predicate: {
+                    repr: T,
+                    type: Type::from(ScalarType::Pred)
+                },
+                src_negate_predicate: {
+                    repr: T,
+                    type: Type::from(ScalarType::Pred)
+                },
+            }
+        },
         Bfe {
             type: Type::Scalar(data.clone()),
             data: ScalarType,
@@ -208,7 +308,9 @@ ptx_parser_macros::generate_instruction_type!(
                 src_from: {
                     repr: T,
                     space: StateSpace::Global
-                }
+                },
+                // src-size given in a register, immediate src-size is in CpAsyncDetails
+                src_size: Option<T>,
             }
         },
         CpAsyncCommitGroup { },
@@ -274,7 +376,7 @@ ptx_parser_macros::generate_instruction_type!(
             }
         },
         Ex2 {
-            type: Type::Scalar(ScalarType::F32),
+            type: { Type::Scalar(data.type_) },
             data: TypeFtz,
             arguments<T>: {
                 dst: T,
@@ -330,6 +432,16 @@ ptx_parser_macros::generate_instruction_type!(
                 }
             }
         },
+        MadExtended {
+            type: { Type::Scalar(data.carry.type_) },
+            data: MadCarryDetails,
+            arguments<T>: {
+                dst: T,
+                src1: T,
+                src2: T,
+                src3: T,
+            }
+        },
         Max {
             type: { Type::from(data.type_()) },
             data: MinMaxDetails,
@@ -351,6 +463,30 @@ ptx_parser_macros::generate_instruction_type!(
                 src2: T,
             }
         },
+        // Fragments are vector packs of any length, the type is the type of a single register of
+        // the fragment
+        Mma {
+            data: MmaDetails,
+            arguments<T>: {
+                dst: {
+                    repr: T,
+                    type: { Type::Scalar(data.dtype.register_type()) },
+                },
+                src_a: {
+                    repr: T,
+                    type: { Type::Scalar(data.atype.register_type()) },
+                },
+                src_b: {
+                    repr: T,
+                    type: { Type::Scalar(data.btype.register_type()) },
+                },
+                src_c: {
+                    repr: T,
+                    type: { Type::Scalar(data.ctype.register_type()) },
+                }
+            },
+            display: write!(f, "{}", data)?
+        },
         Mov {
             type: { &data.typ },
             data: MovDetails,
@@ -655,6 +791,15 @@ ptx_parser_macros::generate_instruction_type!(
                 src2: T,
             }
         },
+        SubExtended {
+            type: { Type::Scalar(data.type_) },
+            data: CarryDetails,
+            arguments<T>: {
+                dst: T,
+                src1: T,
+                src2: T,
+            }
+        },
         Trap { },
         Xor {
             type: Type::Scalar(data.clone()),
@@ -673,6 +818,60 @@ ptx_parser_macros::generate_instruction_type!(
                 src: T
             }
         },
+        WmmaLoad {
+            type: { Type::Scalar(data.type_.register_type()) },
+            data: WmmaLoadDetails,
+            arguments<T>: {
+                dst: T,
+                src: {
+                    repr: T,
+                    space: { data.state_space },
+                },
+                src_stride: {
+                    repr: Option<T>,
+                    type: Type::Scalar(ScalarType::U32),
+                }
+            },
+            display: write!(f, "{}", data)?
+        },
+        WmmaStore {
+            type: { Type::Scalar(data.type_.register_type()) },
+            data: WmmaStoreDetails,
+            arguments<T>: {
+                src_ptr: {
+                    repr: T,
+                    space: { data.state_space },
+                },
+                src: T,
+                src_stride: {
+                    repr: Option<T>,
+                    type: Type::Scalar(ScalarType::U32),
+                }
+            },
+            display: write!(f, "{}", data)?
+        },
+        WmmaMma {
+            data: WmmaMmaDetails,
+            arguments<T>: {
+                dst: {
+                    repr: T,
+                    type: { Type::Scalar(data.dtype.register_type()) },
+                },
+                src_a: {
+                    repr: T,
+                    type: Type::Scalar(ScalarType::F16x2),
+                },
+                src_b: {
+                    repr: T,
+                    type: Type::Scalar(ScalarType::F16x2),
+                },
+                src_c: {
+                    repr: T,
+                    type: { Type::Scalar(data.ctype.register_type()) },
+                }
+            },
+            display: write!(f, "{}", data)?
+        },
     }
 );
 
@@ -926,7 +1125,79 @@ pub struct Variable<ID> {
     pub v_type: Type,
     pub state_space: StateSpace,
     pub name: ID,
-    pub array_init: Vec<u8>,
+    pub array_init: Initializer<ID>,
+    // Only kernel parameters can have it
+    pub pointer: Option<PointerAttributes>,
+}
+
+// Initializers are stored as little-endian bytes, arrays are already padded with zeros. Addresses
+// of variables and functions are not known before the module is linked, their bytes are zero and
+// they are given by `relocations`
+#[derive(Clone)]
+pub struct Initializer<ID> {
+    pub bytes: Vec<u8>,
+    pub relocations: Vec<Relocation<ID>>,
+}
+
+impl<ID> Initializer<ID> {
+    pub fn new() -> Self {
+        Initializer {
+            bytes: Vec::new(),
+            relocations: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn map<To, Err>(
+        self,
+        mut fn_: impl FnMut(ID) -> Result<To, Err>,
+    ) -> Result<Initializer<To>, Err> {
+        Ok(Initializer {
+            bytes: self.bytes,
+            relocations: self
+                .relocations
+                .into_iter()
+                .map(|relocation| {
+                    Ok(Relocation {
+                        offset: relocation.offset,
+                        symbol: fn_(relocation.symbol)?,
+                        generic: relocation.generic,
+                        addend: relocation.addend,
+                        byte: relocation.byte,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+}
+
+impl<ID> Default for Initializer<ID> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// `generic(table)+8` stored in the element starting at byte `offset` of the initializer. Without
+// `generic(...)` the address is in the state space of the symbol. Mask operators
+// (`0xff00(table)`) set `byte`, only this byte of the address is stored
+#[derive(Clone)]
+pub struct Relocation<ID> {
+    pub offset: u32,
+    pub symbol: ID,
+    pub generic: bool,
+    pub addend: i64,
+    pub byte: Option<u8>,
+}
+
+// `.param .u64 .ptr .global .align 8 out`: the kernel parameter holds an address in `space`
+// (generic if not given) of memory aligned to `align` bytes (4 if not given)
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PointerAttributes {
+    pub space: StateSpace,
+    pub align: Option<u32>,
 }
 
 impl<ID: std::fmt::Display> std::fmt::Display for Variable<ID> {
@@ -949,20 +1220,112 @@ impl<ID: std::fmt::Display> std::fmt::Display for Variable<ID> {
             write!(f, " .v{}", size)?;
         }
 
-        write!(f, " {} {}", scalar_type, self.name)?;
+        write!(f, " {}", scalar_type)?;
+
+        if let Some(pointer) = self.pointer {
+            write!(f, " .ptr")?;
+            if pointer.space != StateSpace::Generic {
+                write!(f, " {}", pointer.space)?;
+            }
+            if let Some(align) = pointer.align {
+                write!(f, " .align {}", align)?;
+            }
+        }
+
+        write!(f, " {}", self.name)?;
 
         for dim in array_dims {
-            write!(f, "[{}]", dim)?;
+            // Size of an .extern array is given by the linker, a .param array of size 0 is an
+            // empty struct
+            if *dim == 0 && self.state_space != StateSpace::Param {
+                write!(f, "[]")?;
+            } else {
+                write!(f, "[{}]", dim)?;
+            }
         }
 
-        if self.array_init.len() > 0 {
-            todo!("Need to interpret the array initializer data as the appropriate type");
+        if !self.array_init.is_empty() {
+            let is_array = matches!(self.v_type, Type::Array(..));
+            write!(f, " = ")?;
+            if is_array {
+                f.write_char('{')?;
+            }
+            let element_size = scalar_type.size_of() as usize;
+            for (idx, element) in self.array_init.bytes.chunks(element_size).enumerate() {
+                if idx != 0 {
+                    write!(f, ", ")?;
+                }
+                let offset = (idx * element_size) as u32;
+                match self
+                    .array_init
+                    .relocations
+                    .iter()
+                    .find(|relocation| relocation.offset == offset)
+                {
+                    Some(relocation) => write!(f, "{}", relocation)?,
+                    None => write_initializer_element(f, scalar_type, element)?,
+                }
+            }
+            if is_array {
+                f.write_char('}')?;
+            }
         }
 
         Ok(())
     }
 }
 
+impl<ID: std::fmt::Display> std::fmt::Display for Relocation<ID> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(byte) = self.byte {
+            write!(f, "{:#x}(", 0xffu64 << (byte * 8))?;
+        }
+        if self.generic {
+            write!(f, "generic({})", self.symbol)?;
+        } else {
+            write!(f, "{}", self.symbol)?;
+        }
+        if self.addend != 0 {
+            write!(f, "{:+}", self.addend)?;
+        }
+        if self.byte.is_some() {
+            f.write_char(')')?;
+        }
+        Ok(())
+    }
+}
+
+fn write_initializer_element(
+    f: &mut std::fmt::Formatter<'_>,
+    type_: ScalarType,
+    bytes: &[u8],
+) -> std::fmt::Result {
+    let mut buffer = [0u8; 8];
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    let value = u64::from_le_bytes(buffer);
+    let unused_bits = 64 - bytes.len() as u32 * 8;
+    match type_.kind() {
+        ScalarKind::Signed => write!(
+            f,
+            "{}",
+            crate::print::Immediate(ImmediateValue::S64(
+                ((value << unused_bits) as i64) >> unused_bits
+            ))
+        ),
+        ScalarKind::Float if bytes.len() == 4 => write!(
+            f,
+            "{}",
+            crate::print::Immediate(ImmediateValue::F32(f32::from_bits(value as u32)))
+        ),
+        ScalarKind::Float if bytes.len() == 8 => write!(
+            f,
+            "{}",
+            crate::print::Immediate(ImmediateValue::F64(f64::from_bits(value)))
+        ),
+        _ => write!(f, "{}", crate::print::Immediate(ImmediateValue::U64(value))),
+    }
+}
+
 pub struct PredAt<ID> {
     pub not: bool,
     pub label: ID,
@@ -982,7 +1345,17 @@ impl std::fmt::Display for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Type::Scalar(scalar_type) => write!(f, "{}", scalar_type),
-            _ => todo!(),
+            Type::Vector(size, scalar_type) => write!(f, ".v{}{}", size, scalar_type),
+            Type::Array(vector_size, scalar_type, dimensions) => {
+                if let Some(size) = vector_size {
+                    write!(f, ".v{}", size)?;
+                }
+                write!(f, "{}", scalar_type)?;
+                for dimension in dimensions {
+                    write!(f, "[{}]", dimension)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -1179,6 +1552,62 @@ pub struct CpAsyncDetails {
     pub src_size: Option<u64>,
 }
 
+pub struct MmaDetails {
+    pub shape: MmaShape,
+    pub alayout: MatrixLayout,
+    pub blayout: MatrixLayout,
+    pub dtype: MatrixType,
+    pub atype: MatrixType,
+    pub btype: MatrixType,
+    pub ctype: MatrixType,
+}
+
+pub struct WmmaLoadDetails {
+    pub fragment: MatrixFragment,
+    pub layout: MatrixLayout,
+    pub shape: MmaShape,
+    pub state_space: StateSpace,
+    pub type_: MatrixType,
+}
+
+pub struct WmmaStoreDetails {
+    pub layout: MatrixLayout,
+    pub shape: MmaShape,
+    pub state_space: StateSpace,
+    pub type_: MatrixType,
+}
+
+// Inputs of this form are always .f16
+pub struct WmmaMmaDetails {
+    pub alayout: MatrixLayout,
+    pub blayout: MatrixLayout,
+    pub shape: MmaShape,
+    pub dtype: MatrixType,
+    pub ctype: MatrixType,
+}
+
+impl MatrixType {
+    /// Type of the registers holding a fragment of this type: half precision elements are packed
+    /// in pairs, other elements narrower than 32 bits are packed in .b32 registers
+    pub fn register_type(self) -> ScalarType {
+        match self {
+            MatrixType::F16 => ScalarType::F16x2,
+            MatrixType::F32 => ScalarType::F32,
+            MatrixType::F64 => ScalarType::F64,
+            MatrixType::S32 => ScalarType::S32,
+            MatrixType::BF16
+            | MatrixType::Tf32
+            | MatrixType::S8
+            | MatrixType::U8
+            | MatrixType::S4
+            | MatrixType::U4
+            | MatrixType::B1
+            | MatrixType::E4m3
+            | MatrixType::E5m2 => ScalarType::B32,
+        }
+    }
+}
+
 pub struct ShfDetails {
     pub direction: ShiftDirection,
     pub mode: FunnelShiftMode,
@@ -1376,7 +1805,7 @@ pub struct RetData {
     pub uniform: bool,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum TuningDirective {
     MaxNReg(u32),
     MaxNtid(u32, u32, u32),
@@ -1438,9 +1867,11 @@ impl std::fmt::Display for LinkingDirective {
         let mut directives = vec![];
         if self.contains(LinkingDirective::EXTERN) {
             directives.push(".extern");
-        } else if self.contains(LinkingDirective::VISIBLE) {
+        }
+        if self.contains(LinkingDirective::VISIBLE) {
             directives.push(".visible");
-        } else if self.contains(LinkingDirective::WEAK) {
+        }
+        if self.contains(LinkingDirective::WEAK) {
             directives.push(".weak");
         }
         write!(f, "{}", directives.join(" "))
@@ -1451,10 +1882,11 @@ pub struct Function<'a, ID, S> {
     pub func_directive: MethodDeclaration<'a, ID>,
     pub tuning: Vec<TuningDirective>,
     pub body: Option<Vec<S>>,
+    pub span: Span,
 }
 
 pub enum Directive<'input, O: Operand> {
-    Variable(LinkingDirective, Variable<O::Ident>),
+    Variable(LinkingDirective, Variable<O::Ident>, Span),
     Method(
         LinkingDirective,
         Function<'input, &'input str, Statement<O>>,
@@ -1462,8 +1894,33 @@ pub enum Directive<'input, O: Operand> {
 }
 
 pub struct Module<'input> {
+    // Source of the module, used to resolve spans in error messages
+    pub text: &'input str,
     pub version: (u8, u8),
+    // Shader model and its variant letter, e.g. (90, Some('a')) for sm_90a
+    pub target: (u32, Option<char>),
     pub directives: Vec<Directive<'input, ParsedOperand<&'input str>>>,
+    // Debug builds (nvcc -G) put DWARF data in sections, we keep it, but don't translate it
+    pub sections: Vec<Section<'input>>,
+}
+
+// `.section .debug_info { ... }`
+pub struct Section<'input> {
+    pub name: &'input str,
+    pub entries: Vec<SectionEntry<'input>>,
+    pub span: Span,
+}
+
+pub enum SectionEntry<'input> {
+    Label(&'input str),
+    // `.b8 1, 2` or `.b64 $L__func_end0-$L__func_begin0`, every value is a sum of its terms
+    Data(ScalarType, Vec<Vec<SectionTerm<'input>>>),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SectionTerm<'input> {
+    Integer(u64),
+    Label { name: &'input str, negative: bool },
 }
 
 #[derive(Copy, Clone)]
@@ -1507,6 +1964,22 @@ pub enum MulIntControl {
     Wide,
 }
 
+// add.cc, addc, sub.cc, subc: the carry flag (CC.CF) is read and written implicitly, it is not
+// an operand. After a subtraction it holds the borrow
+#[derive(Copy, Clone)]
+pub struct CarryDetails {
+    pub type_: ScalarType,
+    pub carry_in: bool,
+    pub carry_out: bool,
+}
+
+// mad.cc and madc add the low or high half of the product
+#[derive(Copy, Clone)]
+pub struct MadCarryDetails {
+    pub carry: CarryDetails,
+    pub is_hi: bool,
+}
+
 #[derive(Copy, Clone)]
 pub struct Mul24Details {
     pub type_: ScalarType,
@@ -1805,6 +2278,9 @@ pub enum CvtMode {
         is_integer_rounding: bool,
         flush_to_zero: Option<bool>,
         saturate: bool,
+        // .satfinite: values above the largest finite value of the destination type (infinities
+        // included) become that value with the same sign, NaN stays NaN
+        satfinite: bool,
     },
     FPRound {
         integer_rounding: Option<RoundingMode>,
@@ -1837,6 +2313,7 @@ impl CvtDetails {
         rnd: Option<RawRoundingMode>,
         ftz: bool,
         saturate: bool,
+        satfinite: bool,
         dst: ScalarType,
         src: ScalarType,
     ) -> Self {
@@ -1876,6 +2353,7 @@ impl CvtDetails {
                         is_integer_rounding,
                         flush_to_zero,
                         saturate,
+                        satfinite,
                     }
                 }
                 Ordering::Equal => CvtMode::FPRound {
@@ -1954,6 +2432,11 @@ impl CvtDetails {
                 CvtMode::Bitcast
             }
         };
+        if satfinite && !matches!(mode, CvtMode::FPTruncate { .. }) {
+            errors.push(PtxError::SyntaxError(
+                ".satfinite is only allowed when cvt narrows a float".to_string(),
+            ));
+        }
         CvtDetails {
             mode,
             to: dst,
@@ -2085,6 +2568,14 @@ pub struct BarRedData {
     pub pred_reduction: Reduction,
 }
 
+// barrier.cluster.arrive and barrier.cluster.wait, parsed only to report them as unsupported
+#[derive(Copy, Clone)]
+pub struct BarrierClusterData {
+    pub wait: bool,
+    pub semantics: Option<AtomSemantics>,
+    pub aligned: bool,
+}
+
 pub struct AtomDetails {
     pub type_: Type,
     pub semantics: AtomSemantics,
@@ -18,7 +18,10 @@ use winnow::{
 use winnow::{prelude::*, Stateful};
 
 mod ast;
+mod print;
 pub use ast::*;
+// winnow::combinator::* also brings a Span into scope, which would make ours ambiguous
+pub use ast::Span;
 
 impl From<RawMulIntControl> for ast::MulIntControl {
     fn from(value: RawMulIntControl) -> Self {
@@ -167,6 +170,21 @@ fn ident<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<&'input str>
     .parse_next(stream)
 }
 
+// Contents of a string literal, without the quotes
+fn string<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<&'input str> {
+    trace(
+        "string",
+        any.verify_map(|(t, _)| {
+            if let Token::String(text) = t {
+                Some(text)
+            } else {
+                None
+            }
+        }),
+    )
+    .parse_next(stream)
+}
+
 fn dot_ident<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<&'input str> {
     trace(
         "dot_ident",
@@ -328,6 +346,9 @@ fn immediate_value<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<as
 
 pub fn parse_for_errors<'input>(text: &'input str) -> Vec<PtxError<'input>> {
     let (tokens, mut errors) = lex_with_span_unchecked(text);
+    if errors.len() > MAX_ERRORS {
+        return errors;
+    }
     let parse_result = {
         let state = PtxParserState::new(text, &mut errors);
         let parser = PtxParser {
@@ -336,8 +357,10 @@ pub fn parse_for_errors<'input>(text: &'input str) -> Vec<PtxError<'input>> {
         };
         module
             .parse(parser)
-            .map_err(|err| PtxError::Parser(err.into_inner()))
+            .map_err(|err| (err.offset(), err.into_inner()))
     };
+    let parse_result =
+        parse_result.map_err(|(offset, err)| parse_failure(&tokens, &errors, offset, err));
     match parse_result {
         Ok(_) => {}
         Err(err) => {
@@ -347,25 +370,73 @@ pub fn parse_for_errors<'input>(text: &'input str) -> Vec<PtxError<'input>> {
     errors
 }
 
+// Parser errors report the index of the token where parsing failed
+fn token_span(tokens: &[(Token, logos::Span)], index: usize) -> Option<ast::Span> {
+    tokens.get(index).map(|(_, span)| ast::Span {
+        start: span.start,
+        end: span.end,
+    })
+}
+
+// Parsing stops after this many errors, a module that is not PTX at all should fail fast instead
+// of reporting an error for every statement
+const MAX_ERRORS: usize = 100;
+
+const BYTE_ORDER_MARK: char = '\u{FEFF}';
+
+// PTX saved by Windows applications may start with a byte order mark and buffers passed to
+// cuModuleLoadData often end with NULs. Both are skipped without changing the offsets of the
+// tokens, so spans still point into `text`. \r of \r\n line endings is whitespace for the lexer
+fn lexer<'input>(text: &'input str) -> logos::Lexer<'input, Token<'input>> {
+    let mut lexer = Token::lexer(text.trim_end_matches('\0'));
+    if text.starts_with(BYTE_ORDER_MARK) {
+        lexer.bump(BYTE_ORDER_MARK.len_utf8());
+    }
+    lexer
+}
+
 fn lex_with_span_unchecked<'input>(
     text: &'input str,
 ) -> (Vec<(Token<'input>, logos::Span)>, Vec<PtxError<'input>>) {
-    let lexer = Token::lexer(text);
+    let lexer = lexer(text);
     let mut result = Vec::new();
     let mut errors = Vec::new();
     for (token, span) in lexer.spanned() {
         match token {
             Ok(t) => result.push((t, span)),
-            Err(err) => errors.push(PtxError::Lexer { source: err }),
+            Err(err) => {
+                errors.push(PtxError::Lexer { source: err });
+                if errors.len() == MAX_ERRORS {
+                    errors.push(PtxError::TooManyErrors(MAX_ERRORS));
+                    break;
+                }
+            }
         }
     }
     (result, errors)
 }
 
+// Parser error which ended the parse, unless it was stopped by with_recovery after MAX_ERRORS
+fn parse_failure<'input>(
+    tokens: &[(Token<'input>, logos::Span)],
+    errors: &[PtxError<'input>],
+    offset: usize,
+    error: ContextError,
+) -> PtxError<'input> {
+    if errors.len() >= MAX_ERRORS {
+        PtxError::TooManyErrors(MAX_ERRORS)
+    } else {
+        PtxError::Parser {
+            span: token_span(tokens, offset),
+            error,
+        }
+    }
+}
+
 pub fn parse_module_checked<'input>(
     text: &'input str,
 ) -> Result<ast::Module<'input>, Vec<PtxError<'input>>> {
-    let mut lexer = Token::lexer(text);
+    let mut lexer = lexer(text);
     let mut errors = Vec::new();
     let mut tokens = Vec::new();
     loop {
@@ -377,7 +448,11 @@ pub fn parse_module_checked<'input>(
             Ok(token) => tokens.push((token, lexer.span())),
             Err(mut err) => {
                 err.0 = lexer.span();
-                errors.push(PtxError::from(err))
+                errors.push(PtxError::from(err));
+                if errors.len() == MAX_ERRORS {
+                    errors.push(PtxError::TooManyErrors(MAX_ERRORS));
+                    break;
+                }
             }
         }
     }
@@ -392,8 +467,10 @@ pub fn parse_module_checked<'input>(
         };
         module
             .parse(parser)
-            .map_err(|err| PtxError::Parser(err.into_inner()))
+            .map_err(|err| (err.offset(), err.into_inner()))
     };
+    let parse_result =
+        parse_result.map_err(|(offset, err)| parse_failure(&tokens, &errors, offset, err));
     match parse_result {
         Ok(result) if errors.is_empty() => Ok(result),
         Ok(_) => Err(errors),
@@ -405,6 +482,7 @@ pub fn parse_module_checked<'input>(
 }
 
 fn module<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<ast::Module<'input>> {
+    let text = stream.state.text;
     trace(
         "module",
         (
@@ -414,9 +492,22 @@ fn module<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<ast::Module
             repeat_without_none(directive),
             eof,
         )
-            .map(|(version, _, _, directives, _)| ast::Module {
-                version,
-                directives,
+            .map(|(version, target, _, module_directives, _)| {
+                let mut directives = Vec::new();
+                let mut sections = Vec::new();
+                for directive in module_directives {
+                    match directive {
+                        ModuleDirective::Directive(directive) => directives.push(directive),
+                        ModuleDirective::Section(section) => sections.push(section),
+                    }
+                }
+                ast::Module {
+                    text,
+                    version,
+                    target,
+                    directives,
+                    sections,
+                }
             }),
     )
     .parse_next(stream)
@@ -434,8 +525,14 @@ fn version<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<(u8, u8)>
         .parse_next(stream)
 }
 
+// Options after the shader model (debug, texmode_unified, map_f64_to_f32...) are ignored
 fn target<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<(u32, Option<char>)> {
-    preceded(Token::DotTarget, ident.and_then(shader_model)).parse_next(stream)
+    delimited(
+        Token::DotTarget,
+        ident.and_then(shader_model),
+        repeat::<_, _, (), _, _>(0.., (Token::Comma, ident)),
+    )
+    .parse_next(stream)
 }
 
 fn shader_model<'a>(stream: &mut &str) -> PResult<(u32, Option<char>)> {
@@ -449,19 +546,32 @@ fn shader_model<'a>(stream: &mut &str) -> PResult<(u32, Option<char>)> {
         .parse_next(stream)
 }
 
+// Top level directives that the AST keeps, .file is dropped
+enum ModuleDirective<'input> {
+    Directive(ast::Directive<'input, ast::ParsedOperand<&'input str>>),
+    Section(ast::Section<'input>),
+}
+
 fn directive<'a, 'input>(
     stream: &mut PtxParser<'a, 'input>,
-) -> PResult<Option<ast::Directive<'input, ast::ParsedOperand<&'input str>>>> {
+) -> PResult<Option<ModuleDirective<'input>>> {
     trace(
         "directive",
         with_recovery(
             alt((
                 // When adding a new variant here remember to add its first token into recovery parser down below
-                function.map(|(linking, func)| Some(ast::Directive::Method(linking, func))),
+                function.map(|(linking, func)| {
+                    Some(ModuleDirective::Directive(ast::Directive::Method(
+                        linking, func,
+                    )))
+                }),
                 file.map(|_| None),
-                section.map(|_| None),
-                (module_variable, Token::Semicolon)
-                    .map(|((linking, var), _)| Some(ast::Directive::Variable(linking, var))),
+                section.map(|section| Some(ModuleDirective::Section(section))),
+                spanned((module_variable, Token::Semicolon)).map(|(((linking, var), _), span)| {
+                    Some(ModuleDirective::Directive(ast::Directive::Variable(
+                        linking, var, span,
+                    )))
+                }),
             )),
             (
                 any,
@@ -503,7 +613,7 @@ fn file<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<()> {
         (
             Token::DotFile,
             u32,
-            Token::String,
+            string,
             opt((Token::Comma, u32, Token::Comma, u32)),
         )
             .void(),
@@ -511,43 +621,84 @@ fn file<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<()> {
     .parse_next(stream)
 }
 
-fn section<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<()> {
+fn section<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<ast::Section<'input>> {
     trace(
         "section",
-        (
-            Token::DotSection.void(),
-            dot_ident.void(),
-            Token::LBrace.void(),
-            repeat::<_, _, (), _, _>(0.., section_dwarf_line),
-            Token::RBrace.void(),
-        )
-            .void(),
+        spanned((
+            preceded(Token::DotSection, dot_ident),
+            delimited(Token::LBrace, repeat(0.., section_entry), Token::RBrace),
+        ))
+        .map(|((name, entries), span)| ast::Section {
+            name,
+            entries,
+            span,
+        }),
     )
     .parse_next(stream)
 }
 
-fn section_dwarf_line<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<()> {
+fn section_entry<'a, 'input>(
+    stream: &mut PtxParser<'a, 'input>,
+) -> PResult<ast::SectionEntry<'input>> {
     alt((
-        (section_label, Token::Colon).void(),
-        (Token::DotB32, section_label, opt((Token::Add, u32))).void(),
-        (Token::DotB64, section_label, opt((Token::Add, u32))).void(),
-        (
-            any_bit_type,
-            separated::<_, _, (), _, _, _, _>(1.., u32, Token::Comma),
-        )
-            .void(),
+        terminated(section_label, Token::Colon).map(ast::SectionEntry::Label),
+        (bit_type, separated(1.., section_value, Token::Comma))
+            .map(|(type_, values)| ast::SectionEntry::Data(type_, values)),
     ))
     .parse_next(stream)
 }
 
-fn any_bit_type<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<()> {
-    alt((Token::DotB8, Token::DotB16, Token::DotB32, Token::DotB64))
-        .void()
-        .parse_next(stream)
+fn bit_type<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<ScalarType> {
+    alt((
+        Token::DotB8.value(ScalarType::B8),
+        Token::DotB16.value(ScalarType::B16),
+        Token::DotB32.value(ScalarType::B32),
+        Token::DotB64.value(ScalarType::B64),
+    ))
+    .parse_next(stream)
+}
+
+// Label arithmetic, e.g. `$L__func_end0-$L__func_begin0` or `.debug_abbrev+4`
+fn section_value<'a, 'input>(
+    stream: &mut PtxParser<'a, 'input>,
+) -> PResult<Vec<ast::SectionTerm<'input>>> {
+    let (first, rest): (_, Vec<_>) = (
+        section_term,
+        repeat(
+            0..,
+            alt((
+                preceded(Token::Plus, section_term),
+                preceded(Token::Minus, section_term).map(|term| match term {
+                    ast::SectionTerm::Integer(x) => ast::SectionTerm::Integer(x.wrapping_neg()),
+                    ast::SectionTerm::Label { name, negative } => ast::SectionTerm::Label {
+                        name,
+                        negative: !negative,
+                    },
+                }),
+            )),
+        ),
+    )
+        .parse_next(stream)?;
+    let mut terms = rest;
+    terms.insert(0, first);
+    Ok(terms)
 }
 
-fn section_label<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<()> {
-    alt((ident, dot_ident)).void().parse_next(stream)
+fn section_term<'a, 'input>(
+    stream: &mut PtxParser<'a, 'input>,
+) -> PResult<ast::SectionTerm<'input>> {
+    alt((
+        int_immediate.verify_map(|x| x.as_u64().map(ast::SectionTerm::Integer)),
+        section_label.map(|name| ast::SectionTerm::Label {
+            name,
+            negative: false,
+        }),
+    ))
+    .parse_next(stream)
+}
+
+fn section_label<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<&'input str> {
+    alt((ident, dot_ident)).parse_next(stream)
 }
 
 fn function<'a, 'input>(
@@ -559,18 +710,18 @@ fn function<'a, 'input>(
     let (linking, function) = trace(
         "function",
         (
-            linking_directives,
-            method_declaration,
+            spanned((linking_directives, method_declaration)),
             repeat(0.., tuning_directive),
             function_body,
         )
-            .map(|(linking, func_directive, tuning, body)| {
+            .map(|(((linking, func_directive), span), tuning, body)| {
                 (
                     linking,
                     ast::Function {
                         func_directive,
                         tuning,
                         body,
+                        span,
                     },
                 )
             }),
@@ -654,7 +805,90 @@ fn kernel_arguments<'a, 'input>(
 fn kernel_input<'a, 'input>(
     stream: &mut PtxParser<'a, 'input>,
 ) -> PResult<ast::Variable<&'input str>> {
-    preceded(Token::DotParam, method_parameter(StateSpace::Param)).parse_next(stream)
+    preceded(Token::DotParam, kernel_parameter).parse_next(stream)
+}
+
+fn kernel_parameter<'a, 'input>(
+    stream: &mut PtxParser<'a, 'input>,
+) -> PResult<ast::Variable<&'input str>> {
+    trace("kernel_parameter", |stream: &mut PtxParser<'a, 'input>| {
+        let (align, vector, type_, pointer, name) = (
+            opt(align.verify(|x| x.count_ones() == 1)),
+            vector_prefix,
+            scalar_type,
+            opt(pointer_attributes),
+            ident,
+        )
+            .parse_next(stream)?;
+        let array_dimensions = param_array_dimensions.parse_next(stream)?;
+        // Address is 64-bit, .ptr on anything else contradicts the type
+        if let Some(pointer) = pointer {
+            let is_address = vector.is_none()
+                && array_dimensions.is_none()
+                && matches!(type_, ScalarType::U64 | ScalarType::S64 | ScalarType::B64);
+            let align_ok = pointer.align.is_none_or(|x| x.count_ones() == 1);
+            if !is_address || !align_ok {
+                stream
+                    .state
+                    .errors
+                    .push(PtxError::InvalidPointerParameter(name));
+            }
+        }
+        Ok(Variable {
+            align,
+            v_type: Type::maybe_array(vector, type_, array_dimensions),
+            state_space: StateSpace::Param,
+            name,
+            array_init: Initializer::new(),
+            pointer,
+        })
+    })
+    .parse_next(stream)
+}
+
+// Empty structs passed by value are declared as `.param .align 1 .b8 empty[0]`, other
+// parameters must have a size
+fn param_array_dimensions<'a, 'input>(
+    stream: &mut PtxParser<'a, 'input>,
+) -> PResult<Option<Vec<u32>>> {
+    let zero_sized = opt(delimited(
+        Token::LBracket,
+        u32.verify(|dim| *dim == 0),
+        Token::RBracket,
+    ))
+    .parse_next(stream)?;
+    if zero_sized.is_some() {
+        return Ok(Some(vec![0]));
+    }
+    let array_dimensions = opt(array_dimensions).parse_next(stream)?;
+    // TODO: push this check into array_dimensions(...)
+    if let Some(ref dims) = array_dimensions {
+        if dims[0] == 0 {
+            return Err(ErrMode::from_error_kind(stream, ErrorKind::Verify));
+        }
+    }
+    Ok(array_dimensions)
+}
+
+// .ptr [.global|.shared|.const|.local] [.align N]
+fn pointer_attributes<'a, 'input>(
+    stream: &mut PtxParser<'a, 'input>,
+) -> PResult<ast::PointerAttributes> {
+    preceded(
+        Token::DotPtr,
+        (
+            opt(alt((
+                global_space,
+                Token::DotLocal.value(StateSpace::Local),
+            ))),
+            opt(align),
+        ),
+    )
+    .map(|(space, align)| ast::PointerAttributes {
+        space: space.unwrap_or(StateSpace::Generic),
+        align,
+    })
+    .parse_next(stream)
 }
 
 fn fn_input<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<ast::Variable<&'input str>> {
@@ -722,14 +956,13 @@ fn statement<'a, 'input>(
         alt((
             label.map(Some),
             debug_directive.map(|_| None),
-            terminated(
-                method_space
-                    .flat_map(|space| multi_variable(false, space))
-                    .map(|var| Some(Statement::Variable(var))),
+            spanned(terminated(
+                method_space.flat_map(|space| multi_variable(false, space)),
                 Token::Semicolon,
-            ),
+            ))
+            .map(|(var, span)| Some(Statement::Variable(var, span))),
             predicated_instruction.map(Some),
-            pragma.map(|_| None),
+            pragma,
             block_statement.map(Some),
         )),
         take_till_end_of_statement(),
@@ -791,6 +1024,8 @@ fn with_recovery<'a, 'input: 'a, T>(
             let stream_start = stream.checkpoint();
             match parser.parse_next(stream) {
                 Ok(value) => Ok(Some(value)),
+                // Parsing was stopped, see below
+                Err(err) if stream.state.errors.len() >= MAX_ERRORS => Err(err),
                 Err(_) => {
                     stream.reset(&stream_start);
                     let tokens = recovery.parse_next(stream)?;
@@ -803,6 +1038,9 @@ fn with_recovery<'a, 'input: 'a, T>(
                         _ => None,
                     };
                     stream.state.errors.push(error(range));
+                    if stream.state.errors.len() >= MAX_ERRORS {
+                        return Err(ErrMode::Cut(ContextError::new()));
+                    }
                     Ok(None)
                 }
             }
@@ -810,14 +1048,47 @@ fn with_recovery<'a, 'input: 'a, T>(
     )
 }
 
-fn pragma<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<()> {
+// Byte range covered by the tokens consumed by the parser
+fn spanned<'a, 'input: 'a, T>(
+    mut parser: impl Parser<PtxParser<'a, 'input>, T, ContextError>,
+) -> impl Parser<PtxParser<'a, 'input>, (T, ast::Span), ContextError> {
+    trace("spanned", move |stream: &mut PtxParser<'a, 'input>| {
+        let input = stream.input;
+        let value = parser.parse_next(stream)?;
+        let consumed = &input[..input.len() - stream.input.len()];
+        let span = match (consumed.first(), consumed.last()) {
+            (Some((_, first)), Some((_, last))) => ast::Span {
+                start: first.start,
+                end: last.end,
+            },
+            _ => ast::Span::default(),
+        };
+        Ok((value, span))
+    })
+}
+
+fn pragma<'a, 'input>(
+    stream: &mut PtxParser<'a, 'input>,
+) -> PResult<Option<ast::Statement<ParsedOperandStr<'input>>>> {
     trace(
         "pragma",
-        (Token::DotPragma, Token::String, Token::Semicolon).void(),
+        spanned(delimited(Token::DotPragma, string, Token::Semicolon)).map(|(text, span)| {
+            loop_pragma(text).map(|pragma| ast::Statement::Pragma(pragma, span))
+        }),
     )
     .parse_next(stream)
 }
 
+fn loop_pragma(text: &str) -> Option<ast::LoopPragma> {
+    let mut words = text.split_whitespace();
+    let pragma = match (words.next()?, words.next()) {
+        ("nounroll", None) => ast::LoopPragma::NoUnroll,
+        ("unroll", Some(count)) => ast::LoopPragma::Unroll(count.parse().ok()?),
+        _ => return None,
+    };
+    words.next().is_none().then_some(pragma)
+}
+
 fn method_parameter<'a, 'input: 'a>(
     state_space: StateSpace,
 ) -> impl Parser<PtxParser<'a, 'input>, Variable<&'input str>, ContextError> {
@@ -826,22 +1097,17 @@ fn method_parameter<'a, 'input: 'a>(
         move |stream: &mut PtxParser<'a, 'input>| {
             let (align, vector, type_, name) = variable_declaration.parse_next(stream)?;
             let array_dimensions = if state_space != StateSpace::Reg {
-                opt(array_dimensions).parse_next(stream)?
+                param_array_dimensions.parse_next(stream)?
             } else {
                 None
             };
-            // TODO: push this check into array_dimensions(...)
-            if let Some(ref dims) = array_dimensions {
-                if dims[0] == 0 {
-                    return Err(ErrMode::from_error_kind(stream, ErrorKind::Verify));
-                }
-            }
             Ok(Variable {
                 align,
                 v_type: Type::maybe_array(vector, type_, array_dimensions),
                 state_space,
                 name,
-                array_init: Vec::new(),
+                array_init: Initializer::new(),
+                pointer: None,
             })
         },
     )
@@ -883,7 +1149,8 @@ fn multi_variable<'a, 'input: 'a>(
                         v_type: Type::maybe_vector_parsed(vector, type_),
                         state_space,
                         name,
-                        array_init: Vec::new(),
+                        array_init: Initializer::new(),
+                        pointer: None,
                     },
                     count,
                 });
@@ -913,7 +1180,8 @@ fn multi_variable<'a, 'input: 'a>(
                     v_type: Type::maybe_array(vector, type_, array_dimensions),
                     state_space,
                     name,
-                    array_init: initializer.unwrap_or(Vec::new()),
+                    array_init: initializer.unwrap_or(Initializer::new()),
+                    pointer: None,
                 },
                 count,
             })
@@ -925,12 +1193,12 @@ fn array_initializer<'b, 'a: 'b, 'input: 'a>(
     vector: Option<NonZeroU8>,
     type_: ScalarType,
     array_dimensions: &'b mut Vec<u32>,
-) -> impl Parser<PtxParser<'a, 'input>, Vec<u8>, ContextError> + 'b {
+) -> impl Parser<PtxParser<'a, 'input>, Initializer<&'input str>, ContextError> + 'b {
     trace(
         "array_initializer",
         move |stream: &mut PtxParser<'a, 'input>| {
             Token::Eq.parse_next(stream)?;
-            let mut result = Vec::new();
+            let mut result = Initializer::new();
             // TODO: vector constants and multi dim arrays
             if vector.is_some() || array_dimensions[0] == 0 || array_dimensions.len() > 1 {
                 return Err(ErrMode::from_error_kind(stream, ErrorKind::Verify));
@@ -947,7 +1215,9 @@ fn array_initializer<'b, 'a: 'b, 'input: 'a>(
             .parse_next(stream)?;
             // pad with zeros
             let result_size = type_.size_of() as usize * array_dimensions[0] as usize;
-            result.extend(iter::repeat(0u8).take(result_size - result.len()));
+            result
+                .bytes
+                .extend(iter::repeat_n(0u8, result_size - result.bytes.len()));
             Ok(result)
         },
     )
@@ -956,12 +1226,12 @@ fn array_initializer<'b, 'a: 'b, 'input: 'a>(
 fn value_initializer<'a, 'input: 'a>(
     vector: Option<NonZeroU8>,
     type_: ScalarType,
-) -> impl Parser<PtxParser<'a, 'input>, Vec<u8>, ContextError> {
+) -> impl Parser<PtxParser<'a, 'input>, Initializer<&'input str>, ContextError> {
     trace(
         "value_initializer",
         move |stream: &mut PtxParser<'a, 'input>| {
             Token::Eq.parse_next(stream)?;
-            let mut result = Vec::new();
+            let mut result = Initializer::new();
             // TODO: vector constants
             if vector.is_some() {
                 return Err(ErrMode::from_error_kind(stream, ErrorKind::Verify));
@@ -973,13 +1243,34 @@ fn value_initializer<'a, 'input: 'a>(
 }
 
 fn single_value_append<'b, 'a: 'b, 'input: 'a>(
-    accumulator: &'b mut Vec<u8>,
+    initializer: &'b mut Initializer<&'input str>,
     type_: ScalarType,
 ) -> impl Parser<PtxParser<'a, 'input>, (), ContextError> + 'b {
     trace(
         "single_value_append",
         move |stream: &mut PtxParser<'a, 'input>| {
-            let value = immediate_value.parse_next(stream)?;
+            let value = match opt(relocation).parse_next(stream)? {
+                Some((symbol, generic, addend, byte)) => {
+                    // Addresses are stored in .u32 or .u64 variables, a single byte of an address
+                    // can also be stored in a .u8 or .u16 one
+                    let size = type_.size_of();
+                    let is_integer = matches!(type_.kind(), ScalarKind::Unsigned | ScalarKind::Bit);
+                    if !is_integer || (byte.is_none() && size != 4 && size != 8) {
+                        return Err(ErrMode::from_error_kind(stream, ErrorKind::Verify));
+                    }
+                    initializer.relocations.push(Relocation {
+                        offset: initializer.bytes.len() as u32,
+                        symbol,
+                        generic,
+                        addend,
+                        byte,
+                    });
+                    initializer.bytes.extend(iter::repeat_n(0u8, size as usize));
+                    return Ok(());
+                }
+                None => immediate_value.parse_next(stream)?,
+            };
+            let accumulator = &mut initializer.bytes;
             match (type_, value) {
                 (ScalarType::U8 | ScalarType::B8, ImmediateValue::U64(x)) => {
                     accumulator.extend_from_slice(&(x as u8).to_le_bytes())
@@ -1042,6 +1333,46 @@ fn single_value_append<'b, 'a: 'b, 'input: 'a>(
     )
 }
 
+// Address of a variable or function in an initializer: `table`, `generic(table)+8`, or a single
+// byte of it selected by a mask operator: `0xff00(generic(table)+8)`
+fn relocation<'a, 'input>(
+    stream: &mut PtxParser<'a, 'input>,
+) -> PResult<(&'input str, bool, i64, Option<u8>)> {
+    fn address<'a, 'input>(
+        stream: &mut PtxParser<'a, 'input>,
+    ) -> PResult<(&'input str, bool, i64)> {
+        let (symbol, generic) = alt((
+            delimited(
+                (ident_literal("generic"), Token::LParen),
+                ident,
+                Token::RParen,
+            )
+            .map(|symbol| (symbol, true)),
+            ident.map(|symbol| (symbol, false)),
+        ))
+        .parse_next(stream)?;
+        let addend = opt(alt((
+            preceded(Token::Plus, s32),
+            preceded(Token::Minus, s32).map(|x| -x),
+        )))
+        .parse_next(stream)?;
+        Ok((symbol, generic, addend.unwrap_or(0) as i64))
+    }
+    let mask = num.verify_map(|(text, radix, _)| {
+        let mask = u64::from_str_radix(text, radix).ok()?;
+        (0..8u8).find(|byte| mask == 0xff << (byte * 8))
+    });
+    trace(
+        "relocation",
+        alt((
+            (mask, delimited(Token::LParen, address, Token::RParen))
+                .map(|(byte, (symbol, generic, addend))| (symbol, generic, addend, Some(byte))),
+            address.map(|(symbol, generic, addend)| (symbol, generic, addend, None)),
+        )),
+    )
+    .parse_next(stream)
+}
+
 fn array_dimensions<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<Vec<u32>> {
     let dimension = delimited(
         Token::LBracket,
@@ -1168,8 +1499,8 @@ fn predicated_instruction<'a, 'input>(
 ) -> PResult<ast::Statement<ParsedOperandStr<'input>>> {
     trace(
         "predicated_instruction",
-        (opt(pred_at), parse_instruction, Token::Semicolon)
-            .map(|(p, i, _)| ast::Statement::Instruction(p, i)),
+        spanned((opt(pred_at), parse_instruction, Token::Semicolon))
+            .map(|((p, i, _), span)| ast::Statement::Instruction(p, i, span)),
     )
     .parse_next(stream)
 }
@@ -1188,8 +1519,8 @@ fn pred_at<'a, 'input>(stream: &mut PtxParser<'a, 'input>) -> PResult<ast::PredA
 fn label<'a, 'input>(
     stream: &mut PtxParser<'a, 'input>,
 ) -> PResult<ast::Statement<ParsedOperandStr<'input>>> {
-    terminated(ident, Token::Colon)
-        .map(|l| ast::Statement::Label(l))
+    spanned(terminated(ident, Token::Colon))
+        .map(|(l, span)| ast::Statement::Label(l, span))
         .parse_next(stream)
 }
 
@@ -1264,7 +1595,6 @@ impl<Ident> ast::ParsedOperand<Ident> {
         stream: &mut PtxParser<'a, 'input>,
     ) -> PResult<ast::ParsedOperand<&'input str>> {
         use winnow::combinator::*;
-        use winnow::token::any;
         fn vector_index<'input>(inp: &'input str) -> Result<u8, PtxError<'input>> {
             match inp {
                 ".x" | ".r" => Ok(0),
@@ -1281,11 +1611,21 @@ impl<Ident> ast::ParsedOperand<Ident> {
             alt((
                 preceded(Token::Plus, s32)
                     .map(move |offset| ast::ParsedOperand::RegOffset(main_ident, offset)),
-                take_error(dot_ident.map(move |suffix| {
-                    let vector_index = vector_index(suffix)
-                        .map_err(move |e| (ast::ParsedOperand::VecMember(main_ident, 0), e))?;
-                    Ok(ast::ParsedOperand::VecMember(main_ident, vector_index))
-                })),
+                // `.r` is also a modifier of shf and `.a`, `.b` are fragments of wmma.load, so
+                // they are not lexed as identifiers
+                take_error(
+                    alt((
+                        dot_ident,
+                        Token::DotR.value(".r"),
+                        Token::DotA.value(".a"),
+                        Token::DotB.value(".b"),
+                    ))
+                    .map(move |suffix| {
+                        let vector_index = vector_index(suffix)
+                            .map_err(move |e| (ast::ParsedOperand::VecMember(main_ident, 0), e))?;
+                        Ok(ast::ParsedOperand::VecMember(main_ident, vector_index))
+                    }),
+                ),
                 empty.value(ast::ParsedOperand::Reg(main_ident)),
             ))
             .parse_next(stream)
@@ -1293,13 +1633,12 @@ impl<Ident> ast::ParsedOperand<Ident> {
         fn vector_operand<'a, 'input>(
             stream: &mut PtxParser<'a, 'input>,
         ) -> PResult<Vec<&'input str>> {
-            let (_, r1, _, r2) = (Token::LBrace, ident, Token::Comma, ident).parse_next(stream)?;
-            // TODO: parse .v8 literals
-            dispatch! {any;
-                (Token::RBrace, _) => empty.map(|_| vec![r1, r2]),
-                (Token::Comma, _) => (ident, Token::Comma, ident, Token::RBrace).map(|(r3, _, r4, _)| vec![r1, r2, r3, r4]),
-                _ => fail
-            }
+            // Fragments of matrix instructions take from one to eight registers
+            delimited(
+                Token::LBrace,
+                separated(1..=8, ident, Token::Comma),
+                Token::RBrace,
+            )
             .parse_next(stream)
         }
         trace(
@@ -1334,8 +1673,11 @@ pub enum PtxError<'input> {
         #[from]
         source: TokenError,
     },
-    #[error("{0}")]
-    Parser(ContextError),
+    #[error("{error}")]
+    Parser {
+        error: ContextError,
+        span: Option<ast::Span>,
+    },
     #[error("")]
     Todo,
     #[error("Syntax error: {0}")]
@@ -1366,6 +1708,41 @@ pub enum PtxError<'input> {
     UnrecognizedStatement(&'input str),
     #[error("Unrecognized directive {0:?}")]
     UnrecognizedDirective(&'input str),
+    #[error("Kernel parameter {0:?} has a .ptr annotation that contradicts its type or alignment")]
+    InvalidPointerParameter(&'input str),
+    #[error("Too many errors, parsing stopped after the first {0}")]
+    TooManyErrors(usize),
+}
+
+impl<'input> PtxError<'input> {
+    /// Part of `text` (which must be the text passed to the parser) the error refers to, if known
+    pub fn span(&self, text: &'input str) -> Option<ast::Span> {
+        match self {
+            PtxError::Lexer { source } => Some(ast::Span {
+                start: source.0.start,
+                end: source.0.end,
+            }),
+            PtxError::Parser { span, .. } => *span,
+            PtxError::UnrecognizedStatement(fragment)
+            | PtxError::UnrecognizedDirective(fragment)
+            | PtxError::InvalidPointerParameter(fragment) => {
+                // All hold a slice of the original text, unless recovery found nothing to skip
+                let start = (fragment.as_ptr() as usize).checked_sub(text.as_ptr() as usize)?;
+                if start + fragment.len() > text.len() || fragment.is_empty() {
+                    return None;
+                }
+                Some(ast::Span {
+                    start,
+                    end: start + fragment.len(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn location(&self, text: &'input str) -> Option<ast::SourceLocation> {
+        self.span(text).map(|span| span.location(text))
+    }
 }
 
 #[derive(Debug)]
@@ -1683,8 +2060,8 @@ derive_parser!(
         Ident(&'input str),
         #[regex(r"\.[a-zA-Z][a-zA-Z0-9_$]*|\.[_$%][a-zA-Z0-9_$]+", |lex| lex.slice(), priority = 0)]
         DotIdent(&'input str),
-        #[regex(r#""[^"]*""#)]
-        String,
+        #[regex(r#""[^"]*""#, |lex| { let text = lex.slice(); &text[1..text.len() - 1] })]
+        String(&'input str),
         #[token("|")]
         Pipe,
         #[token("!")]
@@ -1729,6 +2106,8 @@ derive_parser!(
         DotReg,
         #[token(".align")]
         DotAlign,
+        #[token(".ptr")]
+        DotPtr,
         #[token(".pragma")]
         DotPragma,
         #[token(".maxnreg")]
@@ -1751,7 +2130,7 @@ derive_parser!(
         DotTarget,
         #[token(".address_size")]
         DotAddressSize,
-        #[token(".action")]
+        #[token(".section")]
         DotSection,
         #[token(".file")]
         DotFile
@@ -1792,6 +2171,18 @@ derive_parser!(
     #[derive(Copy, Clone, Display, PartialEq, Eq, Hash)]
     pub enum FunnelShiftMode { }
 
+    #[derive(Copy, Clone, Display, PartialEq, Eq, Hash)]
+    pub enum MmaShape { }
+
+    #[derive(Copy, Clone, Display, PartialEq, Eq, Hash)]
+    pub enum MatrixLayout { }
+
+    #[derive(Copy, Clone, Display, PartialEq, Eq, Hash)]
+    pub enum MatrixType { }
+
+    #[derive(Copy, Clone, Display, PartialEq, Eq, Hash)]
+    pub enum MatrixFragment { }
+
     // https://docs.nvidia.com/cuda/parallel-thread-execution/index.html#data-movement-and-conversion-instructions-mov
     mov{.vec}.type  d, a => {
         Instruction::Mov {
@@ -2042,6 +2433,36 @@ derive_parser!(
                           .u16x2, .s16x2 };
     ScalarType =        { .s32 };
 
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/index.html#extended-precision-arithmetic-instructions-add-cc
+    add.cc.type     d, a, b => {
+        Instruction::AddExtended {
+            data: ast::CarryDetails {
+                type_,
+                carry_in: false,
+                carry_out: true
+            },
+            arguments: AddExtendedArgs {
+                dst: d, src1: a, src2: b
+            }
+        }
+    }
+    .type: ScalarType = { .u32, .s32, .u64, .s64 };
+
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/index.html#extended-precision-arithmetic-instructions-addc
+    addc{.cc}.type  d, a, b => {
+        Instruction::AddExtended {
+            data: ast::CarryDetails {
+                type_,
+                carry_in: true,
+                carry_out: cc
+            },
+            arguments: AddExtendedArgs {
+                dst: d, src1: a, src2: b
+            }
+        }
+    }
+    .type: ScalarType = { .u32, .s32, .u64, .s64 };
+
     // https://docs.nvidia.com/cuda/parallel-thread-execution/#floating-point-instructions-add
     add{.rnd}{.ftz}{.sat}.f32  d, a, b => {
         Instruction::Add {
@@ -2368,8 +2789,8 @@ derive_parser!(
     call <= { call(stream) }
 
     // https://docs.nvidia.com/cuda/parallel-thread-execution/index.html#data-movement-and-conversion-instructions-cvt
-    cvt{.ifrnd}{.ftz}{.sat}.dtype.atype         d, a => {
-        let data = ast::CvtDetails::new(&mut state.errors, ifrnd, ftz, sat, dtype, atype);
+    cvt{.ifrnd}{.ftz}{.sat}{.satfinite}.dtype.atype         d, a => {
+        let data = ast::CvtDetails::new(&mut state.errors, ifrnd, ftz, sat, satfinite, dtype, atype);
         let arguments = ast::CvtArgs { dst: d, src: a };
         ast::Instruction::Cvt {
             data, arguments
@@ -2559,6 +2980,40 @@ derive_parser!(
                           .s16, .s32 };
     RawMulIntControl =  { .wide };
 
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/index.html#extended-precision-arithmetic-instructions-mad-cc
+    mad.mode.cc.type  d, a, b, c => {
+        ast::Instruction::MadExtended {
+            data: ast::MadCarryDetails {
+                carry: ast::CarryDetails {
+                    type_,
+                    carry_in: false,
+                    carry_out: true
+                },
+                is_hi: mode == RawMulIntControl::Hi
+            },
+            arguments: MadExtendedArgs { dst: d, src1: a, src2: b, src3: c }
+        }
+    }
+    .type: ScalarType =         { .u32, .s32, .u64, .s64 };
+    .mode: RawMulIntControl =   { .hi, .lo };
+
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/index.html#extended-precision-arithmetic-instructions-madc
+    madc.mode{.cc}.type  d, a, b, c => {
+        ast::Instruction::MadExtended {
+            data: ast::MadCarryDetails {
+                carry: ast::CarryDetails {
+                    type_,
+                    carry_in: true,
+                    carry_out: cc
+                },
+                is_hi: mode == RawMulIntControl::Hi
+            },
+            arguments: MadExtendedArgs { dst: d, src1: a, src2: b, src3: c }
+        }
+    }
+    .type: ScalarType =         { .u32, .s32, .u64, .s64 };
+    .mode: RawMulIntControl =   { .hi, .lo };
+
     mad.hi.sat.s32 d, a, b, c => {
         ast::Instruction::Mad {
             data: ast::MadDetails::Integer {
@@ -2696,6 +3151,32 @@ derive_parser!(
                           .s16, .s32, .s64 };
     ScalarType = { .s32 };
 
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/index.html#extended-precision-arithmetic-instructions-sub-cc
+    sub.cc.type     d, a, b => {
+        ast::Instruction::SubExtended {
+            data: ast::CarryDetails {
+                type_,
+                carry_in: false,
+                carry_out: true
+            },
+            arguments: SubExtendedArgs { dst: d, src1: a, src2: b }
+        }
+    }
+    .type: ScalarType = { .u32, .s32, .u64, .s64 };
+
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/index.html#extended-precision-arithmetic-instructions-subc
+    subc{.cc}.type  d, a, b => {
+        ast::Instruction::SubExtended {
+            data: ast::CarryDetails {
+                type_,
+                carry_in: true,
+                carry_out: cc
+            },
+            arguments: SubExtendedArgs { dst: d, src1: a, src2: b }
+        }
+    }
+    .type: ScalarType = { .u32, .s32, .u64, .s64 };
+
     sub{.rnd}{.ftz}{.sat}.f32  d, a, b => {
         ast::Instruction::Sub {
             data: ast::ArithDetails::Float(
@@ -3119,9 +3600,70 @@ derive_parser!(
             arguments: BarArgs { src1: a, src2: b }
         }
     }
-    //barrier{.cta}.arrive{.aligned}    a, b;
-    //barrier{.cta}.red.popc{.aligned}.u32  d, a{, b}, {!}c;
-    //barrier{.cta}.red.op{.aligned}.pred   p, a{, b}, {!}c;
+    barrier{.cta}.arrive{.aligned}  a, b => {
+        let _ = cta;
+        ast::Instruction::BarArrive {
+            data: ast::BarData {
+                aligned,
+            },
+            arguments: BarArriveArgs { src1: a, src2: b }
+        }
+    }
+    barrier{.cta}.red.popc{.aligned}.u32  d, a{, b}, {!}c => {
+        let _ = cta;
+        let (negate_src3, c) = c;
+        ast::Instruction::BarRedPopc {
+            data: ast::BarData {
+                aligned,
+            },
+            arguments: BarRedPopcArgs {
+                dst: d,
+                src_barrier: a,
+                src_threadcount: b,
+                src_predicate: c,
+                src_negate_predicate: ParsedOperand::Imm(ImmediateValue::U64(negate_src3 as u64))
+            }
+        }
+    }
+    barrier{.cta}.red.op{.aligned}.pred   p, a{, b}, {!}c => {
+        let _ = cta;
+        let (negate_src3, c) = c;
+        ast::Instruction::BarRed {
+            data: ast::BarRedData {
+                aligned,
+                pred_reduction: op,
+            },
+            arguments: BarRedArgs {
+                dst1: p,
+                src_barrier: a,
+                src_threadcount: b,
+                src_predicate: c,
+                src_negate_predicate: ParsedOperand::Imm(ImmediateValue::U64(negate_src3 as u64))
+            }
+        }
+    }
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/index.html#parallel-synchronization-and-communication-instructions-barrier-cluster
+    barrier.cluster.arrive{.sem}{.aligned} => {
+        ast::Instruction::BarrierCluster {
+            data: ast::BarrierClusterData {
+                wait: false,
+                semantics: sem.map(Into::into),
+                aligned,
+            }
+        }
+    }
+    barrier.cluster.wait{.acquire}{.aligned} => {
+        ast::Instruction::BarrierCluster {
+            data: ast::BarrierClusterData {
+                wait: true,
+                semantics: acquire.then_some(AtomSemantics::Acquire),
+                aligned,
+            }
+        }
+    }
+    .op: Reduction = { .and, .or };
+    .sem: AtomSemantics = { .release, .relaxed };
+
     bar{.cta}.sync                  a{, b} => {
         let _ = cta;
         ast::Instruction::Bar {
@@ -3131,8 +3673,31 @@ derive_parser!(
             arguments: BarArgs { src1: a, src2: b }
         }
     }
-    //bar{.cta}.arrive    a, b;
-    //bar{.cta}.red.popc.u32  d, a{, b}, {!}c;
+    bar{.cta}.arrive                a, b => {
+        let _ = cta;
+        ast::Instruction::BarArrive {
+            data: ast::BarData {
+                aligned: true,
+            },
+            arguments: BarArriveArgs { src1: a, src2: b }
+        }
+    }
+    bar{.cta}.red.popc.u32  d, a{, b}, {!}c => {
+        let _ = cta;
+        let (negate_src3, c) = c;
+        ast::Instruction::BarRedPopc {
+            data: ast::BarData {
+                aligned: true,
+            },
+            arguments: BarRedPopcArgs {
+                dst: d,
+                src_barrier: a,
+                src_threadcount: b,
+                src_predicate: c,
+                src_negate_predicate: ParsedOperand::Imm(ImmediateValue::U64(negate_src3 as u64))
+            }
+        }
+    }
     bar{.cta}.red.op.pred   p, a{, b}, {!}c => {
         let _ = cta;
         let (negate_src3, c) = c;
@@ -3644,9 +4209,10 @@ derive_parser!(
                 CpAsyncCpSize::Bytes4
             });
 
-        let src_size = src_size
-            .and_then(|op| op.as_immediate())
-            .and_then(|imm| imm.as_u64());
+        let (src_size, src_size_reg) = match src_size {
+            Some(ParsedOperand::Imm(imm)) => (imm.as_u64(), None),
+            src_size => (None, src_size),
+        };
 
         Instruction::CpAsync {
             data: CpAsyncDetails {
@@ -3658,9 +4224,12 @@ derive_parser!(
             arguments: CpAsyncArgs {
                 src_to: dst,
                 src_from: src,
+                src_size: src_size_reg,
             }
         }
     }
+    // The forms with a predicate ignore-src operand can't be told apart from the src-size forms
+    // while parsing, a predicate register is reported as an operand of the wrong type:
     // cp.async.ca.shared{::cta}.global{.level::cache_hint}{.level::prefetch_size}
     //                          [dst], [src], cp-size{, ignore-src}{, cache-policy} ;
     // cp.async.cg.shared{::cta}.global{.level::cache_hint}{.level::prefetch_size}
@@ -3717,6 +4286,68 @@ derive_parser!(
 
     .atype: ScalarType = { .u32, .s32 };
     .btype: ScalarType = { .u32, .s32 };
+
+    // Tensor core instructions are parsed only to be rejected with their shape and types, or
+    // emulated for a few shapes. Forms with .satfinite, .kind, block scaling or sparsity are not
+    // parsed, neither are the wmma.mma forms with integer, .bf16 or .tf32 inputs
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/#warp-level-matrix-instructions-mma
+    mma.sync.aligned.shape.alayout.blayout.dtype.atype.btype.ctype  d, a, b, c => {
+        Instruction::Mma {
+            data: MmaDetails { shape, alayout, blayout, dtype, atype, btype, ctype },
+            arguments: MmaArgs { dst: d, src_a: a, src_b: b, src_c: c }
+        }
+    }
+    .shape: MmaShape = { .m8n8k4, .m8n8k16, .m8n8k32, .m8n8k128, .m16n8k4, .m16n8k8,
+                         .m16n8k16, .m16n8k32, .m16n8k64, .m16n8k128, .m16n8k256 };
+    .alayout: MatrixLayout = { .row, .col };
+    .blayout: MatrixLayout = { .row, .col };
+    .dtype: MatrixType = { .f16, .f32, .f64, .s32 };
+    .atype: MatrixType = { .f16, .bf16, .tf32, .f64, .s8, .u8, .s4, .u4, .b1, .e4m3, .e5m2 };
+    .btype: MatrixType = { .f16, .bf16, .tf32, .f64, .s8, .u8, .s4, .u4, .b1, .e4m3, .e5m2 };
+    .ctype: MatrixType = { .f16, .f32, .f64, .s32 };
+
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/#warp-level-matrix-instructions-wmma-ld
+    wmma.load.frag.sync.aligned.layout.shape{.ss}.type  r, [p]{, stride} => {
+        Instruction::WmmaLoad {
+            data: WmmaLoadDetails {
+                fragment: frag,
+                layout,
+                shape,
+                state_space: ss.unwrap_or(StateSpace::Generic),
+                type_
+            },
+            arguments: WmmaLoadArgs { dst: r, src: p, src_stride: stride }
+        }
+    }
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/#warp-level-matrix-instructions-wmma-st
+    wmma.store.d.sync.aligned.layout.shape{.ss}.type  [p], r{, stride} => {
+        Instruction::WmmaStore {
+            data: WmmaStoreDetails {
+                layout,
+                shape,
+                state_space: ss.unwrap_or(StateSpace::Generic),
+                type_
+            },
+            arguments: WmmaStoreArgs { src_ptr: p, src: r, src_stride: stride }
+        }
+    }
+    // https://docs.nvidia.com/cuda/parallel-thread-execution/#warp-level-matrix-instructions-wmma-mma
+    wmma.mma.sync.aligned.alayout.blayout.shape.dtype.ctype  d, a, b, c => {
+        Instruction::WmmaMma {
+            data: WmmaMmaDetails { alayout, blayout, shape, dtype, ctype },
+            arguments: WmmaMmaArgs { dst: d, src_a: a, src_b: b, src_c: c }
+        }
+    }
+    .frag: MatrixFragment = { .a, .b, .c };
+    .layout: MatrixLayout = { .row, .col };
+    .alayout: MatrixLayout = { .row, .col };
+    .blayout: MatrixLayout = { .row, .col };
+    .shape: MmaShape = { .m16n16k16, .m32n8k16, .m8n32k16, .m16n16k8, .m8n8k4, .m8n8k32,
+                         .m8n8k128 };
+    .ss: StateSpace = { .global, .shared{::cta} };
+    .type: MatrixType = { .f16, .bf16, .tf32, .f32, .f64, .s8, .u8, .s4, .u4, .b1, .s32 };
+    .dtype: MatrixType = { .f16, .f32 };
+    .ctype: MatrixType = { .f16, .f32 };
 );
 
 #[cfg(test)]
@@ -3945,4 +4576,496 @@ mod tests {
             PtxError::UnrecognizedDirective(".global .bad_type foo;")
         ));
     }
+
+    #[test]
+    fn report_error_location() {
+        let text = ".version 6.5
+.target sm_60
+.address_size 64
+
+.visible .entry foo()
+{
+\tret;
+\tunknown_op1 %r1;
+\tret;
+}";
+        let errors = parse_module_checked(text).err().unwrap();
+        assert_eq!(errors.len(), 1);
+        let location = errors[0].location(text).unwrap();
+        assert_eq!(location.line, 8);
+        assert_eq!(location.column, 2);
+        assert_eq!(location.snippet, "\tunknown_op1 %r1;");
+        assert_eq!(
+            location.annotate(&errors[0]),
+            "line 8: Unrecognized statement \"unknown_op1 %r1;\"\n    \tunknown_op1 %r1;\n    \t^"
+        );
+    }
+
+    #[test]
+    fn byte_order_mark_keeps_error_location() {
+        let text = "\u{FEFF}.version 6.5\r
+.target sm_60\r
+.address_size 64\r
+\r
+.visible .entry foo()\r
+{\r
+\tunknown_op1 %r1;\r
+\tret;\r
+}\0\0";
+        let errors = parse_module_checked(text).err().unwrap();
+        assert_eq!(errors.len(), 1);
+        let location = errors[0].location(text).unwrap();
+        assert_eq!(location.line, 7);
+        assert_eq!(location.column, 2);
+        assert_eq!(location.snippet, "\tunknown_op1 %r1;");
+    }
+
+    #[test]
+    fn broken_module_fails_fast() {
+        let mut text =
+            ".version 6.5\n.target sm_60\n.address_size 64\n\n.visible .entry foo()\n{\n"
+                .to_string();
+        while text.len() < 10 << 20 {
+            text.push_str("\tunknown_op1 %r1, [%rd2+4];\n");
+        }
+        text.push('}');
+        let start = std::time::Instant::now();
+        let errors = parse_module_checked(&text).err().unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        assert_eq!(errors.len(), super::MAX_ERRORS + 1);
+        assert!(matches!(errors.last(), Some(PtxError::TooManyErrors(_))));
+    }
+
+    #[test]
+    fn statement_spans() {
+        let text = ".version 6.5
+.target sm_60
+.address_size 64
+
+.visible .entry foo()
+{
+    .reg .u32 a;
+    mov.u32 a, 1;
+}";
+        let module = parse_module_checked(text).unwrap();
+        let function = match &module.directives[..] {
+            [crate::Directive::Method(_, function)] => function,
+            _ => panic!(),
+        };
+        assert_eq!(function.span.location(text).line, 5);
+        let body = function.body.as_ref().unwrap();
+        let spans =
+            body.iter()
+                .map(|statement| match statement {
+                    crate::Statement::Variable(_, span)
+                    | crate::Statement::Instruction(_, _, span) => &text[span.start..span.end],
+                    _ => panic!(),
+                })
+                .collect::<Vec<_>>();
+        assert_eq!(spans, vec![".reg .u32 a;", "mov.u32 a, 1;"]);
+    }
+
+    #[test]
+    fn kernel_parameter_pointer_attributes() {
+        let text = ".version 6.5
+.target sm_60
+.address_size 64
+
+.visible .entry foo(
+    .param .u64 .ptr .global .align 8 a,
+    .param .u64 .ptr .align 16 b,
+    .param .u64 .ptr .shared c,
+    .param .u64 d
+)
+{
+    ret;
+}";
+        let module = parse_module_checked(text).unwrap();
+        let function = match &module.directives[..] {
+            [crate::Directive::Method(_, function)] => function,
+            _ => panic!(),
+        };
+        let params = &function.func_directive.input_arguments;
+        let pointers = params.iter().map(|p| p.pointer).collect::<Vec<_>>();
+        assert!(
+            pointers
+                == [
+                    Some(crate::ast::PointerAttributes {
+                        space: crate::StateSpace::Global,
+                        align: Some(8)
+                    }),
+                    Some(crate::ast::PointerAttributes {
+                        space: crate::StateSpace::Generic,
+                        align: Some(16)
+                    }),
+                    Some(crate::ast::PointerAttributes {
+                        space: crate::StateSpace::Shared,
+                        align: None
+                    }),
+                    None,
+                ]
+        );
+        assert_eq!(params[0].to_string(), ".param .u64 .ptr .global .align 8 a");
+        assert_eq!(params[1].to_string(), ".param .u64 .ptr .align 16 b");
+    }
+
+    #[test]
+    fn zero_sized_kernel_parameter() {
+        let text = ".version 8.1
+.target sm_70
+.address_size 64
+
+.visible .entry foo(
+    .param .u64 a,
+    .param .align 1 .b8 empty[0],
+    .param .u64 b
+)
+{
+    ret;
+}";
+        let module = parse_module_checked(text).unwrap();
+        let function = match &module.directives[..] {
+            [crate::Directive::Method(_, function)] => function,
+            _ => panic!(),
+        };
+        let params = &function.func_directive.input_arguments;
+        assert_eq!(params.len(), 3);
+        assert!(params[1].v_type == crate::Type::Array(None, crate::ScalarType::B8, vec![0]));
+        assert!(params[1].to_string().ends_with(".b8 empty[0]"));
+    }
+
+    #[test]
+    fn report_contradictory_pointer_attributes() {
+        let text = ".version 6.5
+.target sm_60
+.address_size 64
+
+.visible .entry foo(
+    .param .u32 .ptr .global a,
+    .param .u64 .ptr .align 3 b
+)
+{
+    ret;
+}";
+        let errors = parse_module_checked(text).err().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], PtxError::InvalidPointerParameter("a")));
+        assert!(matches!(errors[1], PtxError::InvalidPointerParameter("b")));
+        assert_eq!(errors[1].location(text).unwrap().line, 7);
+    }
+
+    #[test]
+    fn address_initializers() {
+        let text = ".version 6.5
+.target sm_30
+.address_size 64
+
+.global .u64 table[3] = {generic(second)+4, first};
+.global .u8 bytes[2] = {0xff(first), 0xff00(generic(first)-8)};
+.global .u32 first;
+.global .u32 second[2];";
+        let module = parse_module_checked(text).unwrap();
+        let variables = module
+            .directives
+            .iter()
+            .filter_map(|directive| match directive {
+                crate::Directive::Variable(_, var, _) => Some(var.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            variables,
+            [
+                ".global .u64 table[3] = {generic(second)+4, first, 0U}",
+                ".global .u8 bytes[2] = {0xff(first), 0xff00(generic(first)-8)}",
+                ".global .u32 first",
+                ".global .u32 second[2]",
+            ]
+        );
+        let float_address = ".version 6.5
+.target sm_30
+.address_size 64
+
+.global .f32 address = first;
+.global .u32 first;";
+        assert!(parse_module_checked(float_address).is_err());
+    }
+
+    #[test]
+    fn debug_sections() {
+        let text = ".version 8.5
+.target sm_52, debug
+.address_size 64
+
+.visible .entry foo()
+{
+$L__func_begin0:
+    .loc 1 1 0
+    ret;
+$L__func_end0:
+}
+.file 1 \"/tmp/foo.cu\"
+.section .debug_loc { }
+.section .debug_info
+{
+.b32 $L__debug_info_end0-$L__debug_info_start0 // Length of Unit
+$L__debug_info_start0:
+.b8 2
+.b32 .debug_abbrev
+.b8 102,111,111
+.b64 $L__func_begin0+4
+$L__debug_info_end0:
+}";
+        let module = parse_module_checked(text).unwrap();
+        assert_eq!(module.target, (52, None));
+        assert_eq!(module.directives.len(), 1);
+        let sections = module
+            .sections
+            .iter()
+            .map(|section| (section.name, section.entries.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(sections, [(".debug_loc", 0), (".debug_info", 7)]);
+        let data = module.sections[1]
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                crate::SectionEntry::Data(_, values) => Some(values.clone()),
+                crate::SectionEntry::Label(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let label = |name, negative| crate::SectionTerm::Label { name, negative };
+        assert_eq!(
+            data[0],
+            [vec![
+                label("$L__debug_info_end0", false),
+                label("$L__debug_info_start0", true)
+            ]]
+        );
+        assert_eq!(
+            data[3],
+            [102, 111, 111].map(|x| vec![crate::SectionTerm::Integer(x)])
+        );
+        assert_eq!(
+            data[4],
+            [vec![
+                label("$L__func_begin0", false),
+                crate::SectionTerm::Integer(4)
+            ]]
+        );
+    }
+
+    #[test]
+    fn print_module() {
+        let text = ".version 7.0
+.target sm_90a
+.address_size 64
+
+.global .align 4 .s32 table[3] = {-1, 2};
+.extern .shared .align 16 .b8 shmem[];
+
+.visible .entry foo(
+    .param .u64 input
+)
+.maxntid 32, 1, 1
+{
+    .reg .f32 f<2>;
+    .reg .pred p;
+    .reg .u32 b;
+    .reg .u64 a;
+    ld.param.u64 a, [input];
+    add.f32 f0, f1, 0f3F800000;
+    add.rn.ftz.f32 f0, f0, f1;
+    setp.lt.f32 p, f0, f1;
+    @!p bra END;
+    atom.global.add.u32 b, [a], 1;
+    cvt.rzi.u32.f32 b, f0;
+END:
+    ret;
+}";
+        let module = parse_module_checked(text).unwrap();
+        assert_eq!(
+            module.to_string(),
+            ".version 7.0
+.target sm_90a
+.address_size 64
+
+.global .align 4 .s32 table[3] = {-1, 2, 0};
+
+.extern .shared .align 16 .b8 shmem[];
+
+.visible .entry foo(
+    .param .u64 input
+)
+.maxntid 32, 1, 1
+{
+    .reg .f32 f<2>;
+    .reg .pred p;
+    .reg .u32 b;
+    .reg .u64 a;
+    ld.param.u64 a, [input];
+    add.f32 f0, f1, 0f3F800000;
+    add.rn.ftz.f32 f0, f0, f1;
+    setp.lt.f32 p, f0, f1;
+    @!p bra END;
+    atom.relaxed.gpu.global.add.u32 b, [a], 1;
+    cvt.rzi.u32.f32 b, f0;
+END:
+    ret;
+}
+"
+        );
+    }
+
+    #[test]
+    fn print_carry_chain() {
+        let text = ".version 6.5
+.target sm_30
+.address_size 64
+
+.visible .entry foo()
+{
+    .reg .u32 a;
+    .reg .u64 b;
+    add.cc.u32 a, a, 1;
+    addc.cc.u32 a, a, 2;
+    addc.u32 a, a, 3;
+    sub.cc.u64 b, b, 4;
+    subc.cc.s64 b, b, 5;
+    subc.u64 b, b, 6;
+    mad.lo.cc.u32 a, a, a, 7;
+    madc.hi.cc.s32 a, a, a, 8;
+    madc.lo.u32 a, a, a, 9;
+    ret;
+}
+";
+        let module = parse_module_checked(text).unwrap();
+        assert_eq!(module.to_string(), text);
+    }
+
+    #[test]
+    fn print_loop_pragmas() {
+        let text = ".version 6.5
+.target sm_30
+.address_size 64
+
+.visible .entry foo()
+{
+    .reg .u32 a;
+    .reg .pred p;
+    mov.u32 a, 0;
+outer:
+    .pragma \"unroll 4\";
+inner:
+    .pragma \"nounroll\";
+    add.u32 a, a, 1;
+    setp.lt.u32 p, a, 16;
+    @p bra inner;
+    setp.lt.u32 p, a, 64;
+    @p bra outer;
+    ret;
+}
+";
+        // Pragmas other than the loop pragmas are dropped
+        let with_ignored = text.replace(
+            "    add.u32",
+            "    .pragma \"used_bytes_mask 0xf\";\n    add.u32",
+        );
+        let module = parse_module_checked(&with_ignored).unwrap();
+        assert_eq!(module.to_string(), text);
+    }
+
+    #[test]
+    fn print_cvt_satfinite() {
+        let text = ".version 7.8
+.target sm_90
+.address_size 64
+
+.visible .entry foo()
+{
+    .reg .f32 a;
+    .reg .f64 b;
+    .reg .b16 h;
+    cvt.rn.satfinite.f16.f32 h, a;
+    cvt.rz.satfinite.bf16.f32 h, a;
+    cvt.rn.satfinite.f32.f64 a, b;
+    cvt.rn.sat.f16.f32 h, a;
+    ret;
+}
+";
+        let module = parse_module_checked(text).unwrap();
+        assert_eq!(module.to_string(), text);
+    }
+
+    #[test]
+    fn report_satfinite_widening_cvt() {
+        let text = ".version 7.8
+.target sm_90
+.address_size 64
+
+.visible .entry foo()
+{
+    .reg .f32 a;
+    .reg .f64 b;
+    cvt.satfinite.f64.f32 b, a;
+    ret;
+}
+";
+        let errors = parse_module_checked(text).err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(
+            matches!(&errors[0], PtxError::SyntaxError(message) if message.contains(".satfinite"))
+        );
+    }
+
+    #[test]
+    fn print_vector_components() {
+        let text = ".version 6.5
+.target sm_30
+.address_size 64
+
+.visible .entry foo()
+{
+    .reg .v4 .f32 %v<2>;
+    mov.f32 %v0.x, %v1.w;
+    mov.f32 %v0.y, %v1.z;
+    mov.f32 %v0.z, %v1.y;
+    mov.f32 %v0.w, %v1.x;
+    ret;
+}
+";
+        // Color suffixes are the same components as the coordinate ones
+        let with_colors = text
+            .replace("%v1.w", "%v1.a")
+            .replace("%v1.z", "%v1.b")
+            .replace("%v1.y", "%v1.g")
+            .replace("%v1.x", "%v1.r");
+        let module = parse_module_checked(&with_colors).unwrap();
+        assert_eq!(module.to_string(), text);
+    }
+
+    #[test]
+    fn print_matrix_instructions() {
+        let text = ".version 7.0
+.target sm_80
+.address_size 64
+
+.visible .entry foo(
+    .param .u64 p
+)
+{
+    .reg .b64 %rd;
+    .reg .b32 %r<8>;
+    .reg .f32 %f<8>;
+    mma.sync.aligned.m16n8k8.row.col.f32.f16.f16.f32 {%f0, %f1, %f2, %f3}, {%r0, %r1}, {%r2}, {%f4, %f5, %f6, %f7};
+    mma.sync.aligned.m16n8k16.row.col.f16.bf16.bf16.f16 {%r0, %r1}, {%r0, %r1, %r2, %r3}, {%r4, %r5}, {%r6, %r7};
+    wmma.load.a.sync.aligned.row.m16n16k16.global.f16 {%r0, %r1, %r2, %r3, %r4, %r5, %r6, %r7}, [%rd], 16;
+    wmma.load.c.sync.aligned.col.m16n16k16.f32 {%f0, %f1, %f2, %f3, %f4, %f5, %f6, %f7}, [%rd];
+    wmma.mma.sync.aligned.row.col.m16n16k16.f32.f16 {%f0, %f1, %f2, %f3, %f4, %f5, %f6, %f7}, {%r0, %r1, %r2, %r3, %r4, %r5, %r6, %r7}, {%r0, %r1, %r2, %r3, %r4, %r5, %r6, %r7}, {%r0, %r1, %r2, %r3};
+    wmma.store.d.sync.aligned.row.m16n16k16.shared.f32 [%rd], {%f0, %f1, %f2, %f3, %f4, %f5, %f6, %f7}, %r0;
+    ret;
+}
+";
+        let module = parse_module_checked(text).unwrap();
+        assert_eq!(module.to_string(), text);
+    }
 }
@@ -0,0 +1,1119 @@
+// Prints the parsed module back as PTX text that parse_module_checked(...) accepts.
+// Printing is lossy only in what the AST doesn't keep: comments, debug directives, formatting of
+// numeric literals and modifiers that have no effect on the parsed instruction. Modifiers which
+// the parser fills with defaults (e.g. .relaxed.gpu in atom) are printed explicitly and floats
+// use the hex notation, so parsing the text again gives the same AST
+use crate::ast::*;
+use crate::{MemScope, ScalarType};
+use std::fmt::{self, Display, Formatter, Write};
+
+const INDENT: &str = "    ";
+
+impl<'input> Display for Module<'input> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (major, minor) = self.version;
+        writeln!(f, ".version {}.{}", major, minor)?;
+        let (shader_model, variant) = self.target;
+        write!(f, ".target sm_{}", shader_model)?;
+        if let Some(variant) = variant {
+            f.write_char(variant)?;
+        }
+        writeln!(f, "\n.address_size 64")?;
+        for directive in self.directives.iter() {
+            writeln!(f)?;
+            write!(f, "{}", directive)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'input, ID: Copy + Display> Display for Directive<'input, ParsedOperand<ID>> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Directive::Variable(linking, variable, _) => {
+                write_linking(f, *linking)?;
+                writeln!(f, "{};", variable)
+            }
+            Directive::Method(linking, function) => {
+                write_linking(f, *linking)?;
+                write_function(f, function)
+            }
+        }
+    }
+}
+
+impl<ID: Copy + Display> Display for Statement<ParsedOperand<ID>> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_statement(f, self, 0)
+    }
+}
+
+fn write_linking(f: &mut Formatter<'_>, linking: LinkingDirective) -> fmt::Result {
+    if !linking.is_empty() {
+        write!(f, "{} ", linking)?;
+    }
+    Ok(())
+}
+
+fn write_function<'input, ID: Copy + Display>(
+    f: &mut Formatter<'_>,
+    function: &Function<'input, &'input str, Statement<ParsedOperand<ID>>>,
+) -> fmt::Result {
+    let declaration = &function.func_directive;
+    match declaration.name {
+        MethodName::Kernel(name) => write!(f, ".entry {}", name)?,
+        MethodName::Func(name) => {
+            f.write_str(".func ")?;
+            if !declaration.return_arguments.is_empty() {
+                write_parameters(f, &declaration.return_arguments)?;
+                f.write_char(' ')?;
+            }
+            f.write_str(name)?;
+        }
+    }
+    write_parameters(f, &declaration.input_arguments)?;
+    writeln!(f)?;
+    for tuning in function.tuning.iter() {
+        match tuning {
+            TuningDirective::MaxNReg(count) => writeln!(f, ".maxnreg {}", count)?,
+            TuningDirective::MaxNtid(x, y, z) => writeln!(f, ".maxntid {}, {}, {}", x, y, z)?,
+            TuningDirective::ReqNtid(x, y, z) => writeln!(f, ".reqntid {}, {}, {}", x, y, z)?,
+            TuningDirective::MinNCtaPerSm(count) => writeln!(f, ".minnctapersm {}", count)?,
+        }
+    }
+    match &function.body {
+        Some(statements) => {
+            writeln!(f, "{{")?;
+            for statement in statements.iter() {
+                write_statement(f, statement, 1)?;
+            }
+            writeln!(f, "}}")
+        }
+        None => writeln!(f, ";"),
+    }
+}
+
+fn write_parameters<ID: Display>(
+    f: &mut Formatter<'_>,
+    parameters: &[Variable<ID>],
+) -> fmt::Result {
+    f.write_char('(')?;
+    for (idx, parameter) in parameters.iter().enumerate() {
+        f.write_str(if idx == 0 { "\n" } else { ",\n" })?;
+        write!(f, "{}{}", INDENT, parameter)?;
+    }
+    if !parameters.is_empty() {
+        writeln!(f)?;
+    }
+    f.write_char(')')
+}
+
+fn write_statement<ID: Copy + Display>(
+    f: &mut Formatter<'_>,
+    statement: &Statement<ParsedOperand<ID>>,
+    depth: usize,
+) -> fmt::Result {
+    let indent = INDENT.repeat(depth);
+    match statement {
+        Statement::Label(label, _) => writeln!(f, "{}:", label),
+        Statement::Variable(MultiVariable { var, count }, _) => {
+            write!(f, "{}{}", indent, var)?;
+            if let Some(count) = count {
+                write!(f, "<{}>", count)?;
+            }
+            writeln!(f, ";")
+        }
+        Statement::Instruction(predicate, instruction, _) => {
+            f.write_str(&indent)?;
+            if let Some(PredAt { not, label }) = predicate {
+                write!(f, "@{}{} ", if *not { "!" } else { "" }, label)?;
+            }
+            write_instruction(f, instruction)?;
+            writeln!(f, ";")
+        }
+        Statement::Block(statements) => {
+            writeln!(f, "{}{{", indent)?;
+            for statement in statements.iter() {
+                write_statement(f, statement, depth + 1)?;
+            }
+            writeln!(f, "{}}}", indent)
+        }
+        Statement::Pragma(pragma, _) => match pragma {
+            LoopPragma::NoUnroll => writeln!(f, "{}.pragma \"nounroll\";", indent),
+            LoopPragma::Unroll(count) => writeln!(f, "{}.pragma \"unroll {}\";", indent, count),
+        },
+    }
+}
+
+/// Immediate value in the syntax of PTX literals: floats as exact hex bit patterns and
+/// unsigned 64-bit values with the U suffix
+pub(crate) struct Immediate(pub(crate) ImmediateValue);
+
+impl Display for Immediate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ImmediateValue::U64(value) => write!(f, "{}U", value),
+            ImmediateValue::S64(value) => write!(f, "{}", value),
+            ImmediateValue::F32(value) => write!(f, "0f{:08X}", value.to_bits()),
+            ImmediateValue::F64(value) => write!(f, "0d{:016X}", value.to_bits()),
+        }
+    }
+}
+
+struct Op<'a, ID>(&'a ParsedOperand<ID>);
+
+impl<'a, ID: Display> Display for Op<'a, ID> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ParsedOperand::Imm(value) => write!(f, "{}", Immediate(*value)),
+            operand => write!(f, "{}", operand),
+        }
+    }
+}
+
+// Optional operand together with its separator, e.g. the second destination in `setp p|q, a, b`
+struct OptOp<'a, ID>(&'static str, Option<&'a ParsedOperand<ID>>);
+
+impl<'a, ID: Display> Display for OptOp<'a, ID> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            Some(operand) => write!(f, "{}{}", self.0, Op(operand)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn flag(value: bool, modifier: &'static str) -> &'static str {
+    if value {
+        modifier
+    } else {
+        ""
+    }
+}
+
+fn ftz(flush_to_zero: Option<bool>) -> &'static str {
+    flag(flush_to_zero == Some(true), ".ftz")
+}
+
+fn rounding(mode: RoundingMode) -> &'static str {
+    match mode {
+        RoundingMode::NearestEven => ".rn",
+        RoundingMode::Zero => ".rz",
+        RoundingMode::NegativeInf => ".rm",
+        RoundingMode::PositiveInf => ".rp",
+    }
+}
+
+fn integer_rounding(mode: RoundingMode) -> &'static str {
+    match mode {
+        RoundingMode::NearestEven => ".rni",
+        RoundingMode::Zero => ".rzi",
+        RoundingMode::NegativeInf => ".rmi",
+        RoundingMode::PositiveInf => ".rpi",
+    }
+}
+
+// Instructions without an explicit rounding mode are fusable, that's the only way to tell them apart
+fn write_arith_float(f: &mut Formatter<'_>, arith: &ArithFloat, always_round: bool) -> fmt::Result {
+    write!(
+        f,
+        "{}{}{}{}",
+        flag(always_round || !arith.is_fusable, rounding(arith.rounding)),
+        ftz(arith.flush_to_zero),
+        flag(arith.saturate, ".sat"),
+        arith.type_
+    )
+}
+
+fn write_arith(f: &mut Formatter<'_>, arith: &ArithDetails) -> fmt::Result {
+    match arith {
+        ArithDetails::Integer(integer) => {
+            write!(f, "{}{}", flag(integer.saturate, ".sat"), integer.type_)
+        }
+        ArithDetails::Float(float) => write_arith_float(f, float, false),
+    }
+}
+
+fn mul_control(control: MulIntControl) -> &'static str {
+    match control {
+        MulIntControl::Low => ".lo",
+        MulIntControl::High => ".hi",
+        MulIntControl::Wide => ".wide",
+    }
+}
+
+fn write_min_max(f: &mut Formatter<'_>, details: &MinMaxDetails) -> fmt::Result {
+    match details {
+        MinMaxDetails::Signed(type_) | MinMaxDetails::Unsigned(type_) => write!(f, "{}", type_),
+        MinMaxDetails::Float(float) => write!(
+            f,
+            "{}{}{}",
+            ftz(float.flush_to_zero),
+            flag(float.nan, ".NaN"),
+            float.type_
+        ),
+    }
+}
+
+fn write_rcp(f: &mut Formatter<'_>, data: &RcpData) -> fmt::Result {
+    let kind = match data.kind {
+        RcpKind::Approx => ".approx",
+        RcpKind::Compliant(mode) => rounding(mode),
+    };
+    write!(f, "{}{}{}", kind, ftz(data.flush_to_zero), data.type_)
+}
+
+fn compare_op(op: SetpCompareOp) -> &'static str {
+    match op {
+        SetpCompareOp::Integer(op) => match op {
+            SetpCompareInt::Eq => ".eq",
+            SetpCompareInt::NotEq => ".ne",
+            SetpCompareInt::UnsignedLess | SetpCompareInt::SignedLess => ".lt",
+            SetpCompareInt::UnsignedLessOrEq | SetpCompareInt::SignedLessOrEq => ".le",
+            SetpCompareInt::UnsignedGreater | SetpCompareInt::SignedGreater => ".gt",
+            SetpCompareInt::UnsignedGreaterOrEq | SetpCompareInt::SignedGreaterOrEq => ".ge",
+        },
+        SetpCompareOp::Float(op) => match op {
+            SetpCompareFloat::Eq => ".eq",
+            SetpCompareFloat::NotEq => ".ne",
+            SetpCompareFloat::Less => ".lt",
+            SetpCompareFloat::LessOrEq => ".le",
+            SetpCompareFloat::Greater => ".gt",
+            SetpCompareFloat::GreaterOrEq => ".ge",
+            SetpCompareFloat::NanEq => ".equ",
+            SetpCompareFloat::NanNotEq => ".neu",
+            SetpCompareFloat::NanLess => ".ltu",
+            SetpCompareFloat::NanLessOrEq => ".leu",
+            SetpCompareFloat::NanGreater => ".gtu",
+            SetpCompareFloat::NanGreaterOrEq => ".geu",
+            SetpCompareFloat::IsNotNan => ".num",
+            SetpCompareFloat::IsAnyNan => ".nan",
+        },
+    }
+}
+
+fn ld_st_qualifier(qualifier: LdStQualifier) -> String {
+    match qualifier {
+        LdStQualifier::Weak => String::new(),
+        LdStQualifier::Volatile => ".volatile".to_string(),
+        LdStQualifier::Relaxed(scope) => format!(".relaxed{}", scope),
+        LdStQualifier::Acquire(scope) => format!(".acquire{}", scope),
+        LdStQualifier::Release(scope) => format!(".release{}", scope),
+    }
+}
+
+fn atomic_op(op: AtomicOp) -> &'static str {
+    match op {
+        AtomicOp::And => ".and",
+        AtomicOp::Or => ".or",
+        AtomicOp::Xor => ".xor",
+        AtomicOp::Exchange => ".exch",
+        AtomicOp::Add | AtomicOp::FloatAdd => ".add",
+        AtomicOp::IncrementWrap => ".inc",
+        AtomicOp::DecrementWrap => ".dec",
+        AtomicOp::SignedMin | AtomicOp::UnsignedMin | AtomicOp::FloatMin => ".min",
+        AtomicOp::SignedMax | AtomicOp::UnsignedMax | AtomicOp::FloatMax => ".max",
+    }
+}
+
+/// Opcode with all the modifiers, matrix instructions are reported with it when they can't be
+/// translated
+impl Display for MmaDetails {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mma.sync.aligned{}{}{}{}{}{}{}",
+            self.shape, self.alayout, self.blayout, self.dtype, self.atype, self.btype, self.ctype
+        )
+    }
+}
+
+impl Display for WmmaLoadDetails {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "wmma.load{}.sync.aligned{}{}{}{}",
+            self.fragment, self.layout, self.shape, self.state_space, self.type_
+        )
+    }
+}
+
+impl Display for WmmaStoreDetails {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "wmma.store.d.sync.aligned{}{}{}{}",
+            self.layout, self.shape, self.state_space, self.type_
+        )
+    }
+}
+
+impl Display for WmmaMmaDetails {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "wmma.mma.sync.aligned{}{}{}{}{}",
+            self.alayout, self.blayout, self.shape, self.dtype, self.ctype
+        )
+    }
+}
+
+fn write_cvt(f: &mut Formatter<'_>, data: &CvtDetails) -> fmt::Result {
+    let satfinite = matches!(
+        data.mode,
+        CvtMode::FPTruncate {
+            satfinite: true,
+            ..
+        }
+    );
+    let (rnd, flush_to_zero, saturate) = match data.mode {
+        CvtMode::ZeroExtend | CvtMode::SignExtend | CvtMode::Truncate | CvtMode::Bitcast => {
+            ("", None, false)
+        }
+        CvtMode::IntSaturateToSigned | CvtMode::IntSaturateToUnsigned => ("", None, true),
+        CvtMode::FPExtend {
+            flush_to_zero,
+            saturate,
+        } => ("", flush_to_zero, saturate),
+        CvtMode::FPTruncate {
+            rounding: mode,
+            is_integer_rounding,
+            flush_to_zero,
+            saturate,
+            satfinite: _,
+        } => {
+            let rnd = if is_integer_rounding {
+                integer_rounding(mode)
+            } else {
+                rounding(mode)
+            };
+            (rnd, flush_to_zero, saturate)
+        }
+        CvtMode::FPRound {
+            integer_rounding: mode,
+            flush_to_zero,
+            saturate,
+        } => (mode.map_or("", integer_rounding), flush_to_zero, saturate),
+        CvtMode::SignedFromFP {
+            rounding: mode,
+            flush_to_zero,
+        }
+        | CvtMode::UnsignedFromFP {
+            rounding: mode,
+            flush_to_zero,
+        } => (integer_rounding(mode), flush_to_zero, false),
+        CvtMode::FPFromSigned {
+            rounding: mode,
+            saturate,
+        }
+        | CvtMode::FPFromUnsigned {
+            rounding: mode,
+            saturate,
+        } => (rounding(mode), None, saturate),
+    };
+    write!(
+        f,
+        "cvt{}{}{}{}{}{}",
+        rnd,
+        ftz(flush_to_zero),
+        flag(saturate, ".sat"),
+        flag(satfinite, ".satfinite"),
+        data.to,
+        data.from
+    )
+}
+
+fn write_instruction<ID: Copy + Display>(
+    f: &mut Formatter<'_>,
+    instruction: &Instruction<ParsedOperand<ID>>,
+) -> fmt::Result {
+    match instruction {
+        Instruction::Abs { data, arguments } => write!(
+            f,
+            "abs{}{} {}, {}",
+            ftz(data.flush_to_zero),
+            data.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Activemask { arguments } => {
+            write!(f, "activemask.b32 {}", Op(&arguments.dst))
+        }
+        Instruction::Add { data, arguments } => {
+            f.write_str("add")?;
+            write_arith(f, data)?;
+            write!(
+                f,
+                " {}, {}, {}",
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2)
+            )
+        }
+        Instruction::AddExtended { data, arguments } => write!(
+            f,
+            "{}{}{} {}, {}, {}",
+            if data.carry_in { "addc" } else { "add" },
+            flag(data.carry_out, ".cc"),
+            data.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::And { data, arguments } => write!(
+            f,
+            "and{} {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::Atom { data, arguments } => {
+            let noftz = matches!(
+                data.type_,
+                Type::Scalar(type_) | Type::Vector(_, type_)
+                    if matches!(type_, ScalarType::F16 | ScalarType::BF16 | ScalarType::F16x2 | ScalarType::BF16x2)
+            );
+            write!(
+                f,
+                "atom{}{}{}{}{}{} {}, [{}], {}",
+                data.semantics,
+                data.scope,
+                data.space,
+                atomic_op(data.op),
+                flag(noftz, ".noftz"),
+                data.type_,
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2)
+            )
+        }
+        Instruction::AtomCas { data, arguments } => write!(
+            f,
+            "atom{}{}{}.cas{} {}, [{}], {}, {}",
+            data.semantics,
+            data.scope,
+            data.space,
+            data.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2),
+            Op(&arguments.src3)
+        ),
+        Instruction::BarWarp { arguments, .. } => {
+            write!(f, "bar.warp.sync {}", Op(&arguments.src))
+        }
+        Instruction::Bar { data, arguments } => write!(
+            f,
+            "{}.sync {}{}",
+            if data.aligned { "bar" } else { "barrier" },
+            Op(&arguments.src1),
+            OptOp(", ", arguments.src2.as_ref())
+        ),
+        Instruction::BarArrive { data, arguments } => write!(
+            f,
+            "{}.arrive {}, {}",
+            if data.aligned { "bar" } else { "barrier" },
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::BarrierCluster { data } => {
+            write!(
+                f,
+                "barrier.cluster.{}",
+                if data.wait { "wait" } else { "arrive" }
+            )?;
+            if let Some(semantics) = data.semantics {
+                write!(f, "{}", semantics)?;
+            }
+            f.write_str(flag(data.aligned, ".aligned"))
+        }
+        Instruction::BarRed { data, arguments } => {
+            let negate = matches!(
+                arguments.src_negate_predicate,
+                ParsedOperand::Imm(ImmediateValue::U64(1))
+            );
+            write!(
+                f,
+                "{}.red{}.pred {}, {}{}, {}{}",
+                if data.aligned { "bar" } else { "barrier" },
+                data.pred_reduction,
+                Op(&arguments.dst1),
+                Op(&arguments.src_barrier),
+                OptOp(", ", arguments.src_threadcount.as_ref()),
+                flag(negate, "!"),
+                Op(&arguments.src_predicate)
+            )
+        }
+        Instruction::BarRedPopc { data, arguments } => {
+            let negate = matches!(
+                arguments.src_negate_predicate,
+                ParsedOperand::Imm(ImmediateValue::U64(1))
+            );
+            write!(
+                f,
+                "{}.red.popc.u32 {}, {}{}, {}{}",
+                if data.aligned { "bar" } else { "barrier" },
+                Op(&arguments.dst),
+                Op(&arguments.src_barrier),
+                OptOp(", ", arguments.src_threadcount.as_ref()),
+                flag(negate, "!"),
+                Op(&arguments.src_predicate)
+            )
+        }
+        Instruction::Bfe { data, arguments } => write!(
+            f,
+            "bfe{} {}, {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2),
+            Op(&arguments.src3)
+        ),
+        Instruction::Bfi { data, arguments } => write!(
+            f,
+            "bfi{} {}, {}, {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2),
+            Op(&arguments.src3),
+            Op(&arguments.src4)
+        ),
+        Instruction::Bra { arguments } => write!(f, "bra {}", arguments.src),
+        Instruction::Brev { data, arguments } => write!(
+            f,
+            "brev{} {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Call { data, arguments } => {
+            write!(f, "call{} ", flag(data.uniform, ".uni"))?;
+            if !arguments.return_arguments.is_empty() {
+                f.write_char('(')?;
+                for (idx, argument) in arguments.return_arguments.iter().enumerate() {
+                    if idx != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                f.write_str("), ")?;
+            }
+            write!(f, "{}", arguments.func)?;
+            if !arguments.input_arguments.is_empty() {
+                f.write_str(", (")?;
+                for (idx, argument) in arguments.input_arguments.iter().enumerate() {
+                    if idx != 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", Op(argument))?;
+                }
+                f.write_char(')')?;
+            }
+            Ok(())
+        }
+        Instruction::Clz { data, arguments } => write!(
+            f,
+            "clz{} {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Cos { data, arguments } => write!(
+            f,
+            "cos.approx{}.f32 {}, {}",
+            flag(data.flush_to_zero, ".ftz"),
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::CpAsync { data, arguments } => {
+            let caching = match data.caching {
+                CpAsyncCacheOperator::Cached => ".ca",
+                CpAsyncCacheOperator::L2Only => ".cg",
+            };
+            write!(
+                f,
+                "cp.async{}{}.global [{}], [{}], {}",
+                caching,
+                data.space,
+                Op(&arguments.src_to),
+                Op(&arguments.src_from),
+                data.cp_size.as_u64()
+            )?;
+            if let Some(src_size) = data.src_size {
+                write!(f, ", {}", src_size)?;
+            }
+            write!(f, "{}", OptOp(", ", arguments.src_size.as_ref()))
+        }
+        Instruction::CpAsyncCommitGroup {} => f.write_str("cp.async.commit_group"),
+        Instruction::CpAsyncWaitGroup { arguments } => {
+            write!(f, "cp.async.wait_group {}", Op(&arguments.src_group))
+        }
+        Instruction::CpAsyncWaitAll {} => f.write_str("cp.async.wait_all"),
+        Instruction::Cvt { data, arguments } => {
+            write_cvt(f, data)?;
+            write!(f, " {}, {}", Op(&arguments.dst), Op(&arguments.src))
+        }
+        Instruction::Cvta { data, arguments } => write!(
+            f,
+            "cvta{}{}.u64 {}, {}",
+            flag(
+                matches!(data.direction, CvtaDirection::GenericToExplicit),
+                ".to"
+            ),
+            data.state_space,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Div { data, arguments } => {
+            match data {
+                DivDetails::Unsigned(type_) | DivDetails::Signed(type_) => {
+                    write!(f, "div{}", type_)?
+                }
+                DivDetails::Float(float) => {
+                    let kind = match float.kind {
+                        DivFloatKind::Approx => ".approx",
+                        DivFloatKind::ApproxFull => ".full",
+                        DivFloatKind::Rounding(mode) => rounding(mode),
+                    };
+                    write!(f, "div{}{}{}", kind, ftz(float.flush_to_zero), float.type_)?
+                }
+            }
+            write!(
+                f,
+                " {}, {}, {}",
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2)
+            )
+        }
+        Instruction::Dp4a { data, arguments } => write!(
+            f,
+            "dp4a{}{} {}, {}, {}, {}",
+            data.atype,
+            data.btype,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2),
+            Op(&arguments.src3)
+        ),
+        Instruction::Ex2 { data, arguments } => write!(
+            f,
+            "ex2.approx{}{} {}, {}",
+            ftz(data.flush_to_zero),
+            data.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Fma { data, arguments } => {
+            f.write_str("fma")?;
+            write_arith_float(f, data, true)?;
+            write!(
+                f,
+                " {}, {}, {}, {}",
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2),
+                Op(&arguments.src3)
+            )
+        }
+        Instruction::Ld { data, arguments } => {
+            let caching = match data.caching {
+                LdCacheOperator::Cached => "",
+                LdCacheOperator::L2Only => ".cg",
+                LdCacheOperator::Streaming => ".cs",
+                LdCacheOperator::LastUse => ".lu",
+                LdCacheOperator::Uncached => ".cv",
+            };
+            write!(
+                f,
+                "ld{}{}{}{}{} {}, [{}]",
+                ld_st_qualifier(data.qualifier),
+                data.state_space,
+                caching,
+                flag(data.non_coherent, ".nc"),
+                data.typ,
+                Op(&arguments.dst),
+                Op(&arguments.src)
+            )
+        }
+        Instruction::Lg2 { data, arguments } => write!(
+            f,
+            "lg2.approx{}.f32 {}, {}",
+            flag(data.flush_to_zero, ".ftz"),
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Mad { data, arguments } => {
+            match data {
+                MadDetails::Integer {
+                    control,
+                    saturate,
+                    type_,
+                } => write!(
+                    f,
+                    "mad{}{}{}",
+                    mul_control(*control),
+                    flag(*saturate, ".sat"),
+                    type_
+                )?,
+                MadDetails::Float(float) => {
+                    f.write_str("mad")?;
+                    write_arith_float(f, float, true)?;
+                }
+            }
+            write!(
+                f,
+                " {}, {}, {}, {}",
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2),
+                Op(&arguments.src3)
+            )
+        }
+        Instruction::MadExtended { data, arguments } => {
+            let hi_lo = if data.is_hi { ".hi" } else { ".lo" };
+            write!(
+                f,
+                "{}{}{}{} {}, {}, {}, {}",
+                if data.carry.carry_in { "madc" } else { "mad" },
+                hi_lo,
+                flag(data.carry.carry_out, ".cc"),
+                data.carry.type_,
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2),
+                Op(&arguments.src3)
+            )
+        }
+        Instruction::Max { data, arguments } => {
+            f.write_str("max")?;
+            write_min_max(f, data)?;
+            write!(
+                f,
+                " {}, {}, {}",
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2)
+            )
+        }
+        Instruction::Membar { data } => match data {
+            MemScope::Gpu => f.write_str("membar.gl"),
+            scope => write!(f, "membar{}", scope),
+        },
+        Instruction::Min { data, arguments } => {
+            f.write_str("min")?;
+            write_min_max(f, data)?;
+            write!(
+                f,
+                " {}, {}, {}",
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2)
+            )
+        }
+        Instruction::Mma { data, arguments } => write!(
+            f,
+            "{} {}, {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src_a),
+            Op(&arguments.src_b),
+            Op(&arguments.src_c)
+        ),
+        Instruction::Mov { data, arguments } => write!(
+            f,
+            "mov{} {}, {}",
+            data.typ,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Mul { data, arguments } => {
+            match data {
+                MulDetails::Integer { type_, control } => {
+                    write!(f, "mul{}{}", mul_control(*control), type_)?
+                }
+                MulDetails::Float(float) => {
+                    f.write_str("mul")?;
+                    write_arith_float(f, float, false)?;
+                }
+            }
+            write!(
+                f,
+                " {}, {}, {}",
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2)
+            )
+        }
+        Instruction::Mul24 { data, arguments } => write!(
+            f,
+            "mul24{}{} {}, {}, {}",
+            data.control,
+            data.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::Nanosleep { arguments } => {
+            write!(f, "nanosleep.u32 {}", Op(&arguments.src))
+        }
+        Instruction::Neg { data, arguments } => write!(
+            f,
+            "neg{}{} {}, {}",
+            ftz(data.flush_to_zero),
+            data.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Not { data, arguments } => write!(
+            f,
+            "not{} {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Or { data, arguments } => write!(
+            f,
+            "or{} {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::Popc { data, arguments } => write!(
+            f,
+            "popc{} {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Prmt { data, arguments } => write!(
+            f,
+            "prmt.b32 {}, {}, {}, {}",
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2),
+            data
+        ),
+        Instruction::PrmtSlow { arguments } => write!(
+            f,
+            "prmt.b32 {}, {}, {}, {}",
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2),
+            Op(&arguments.src3)
+        ),
+        Instruction::Rcp { data, arguments } => {
+            f.write_str("rcp")?;
+            write_rcp(f, data)?;
+            write!(f, " {}, {}", Op(&arguments.dst), Op(&arguments.src))
+        }
+        Instruction::Rem { data, arguments } => write!(
+            f,
+            "rem{} {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::Ret { data } => write!(f, "ret{}", flag(data.uniform, ".uni")),
+        Instruction::Rsqrt { data, arguments } => write!(
+            f,
+            "rsqrt.approx{}{} {}, {}",
+            ftz(data.flush_to_zero),
+            data.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Selp { data, arguments } => write!(
+            f,
+            "selp{} {}, {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2),
+            Op(&arguments.src3)
+        ),
+        Instruction::Set { data, arguments } => write!(
+            f,
+            "set{}{}{}{} {}, {}, {}",
+            compare_op(data.base.cmp_op),
+            ftz(data.base.flush_to_zero),
+            data.dtype,
+            data.base.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::SetBool { data, arguments } => write!(
+            f,
+            "set{}{}{}{}{} {}, {}, {}, {}{}",
+            compare_op(data.base.base.cmp_op),
+            data.base.bool_op,
+            ftz(data.base.base.flush_to_zero),
+            data.dtype,
+            data.base.base.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2),
+            flag(data.base.negate_src3, "!"),
+            Op(&arguments.src3)
+        ),
+        Instruction::Setp { data, arguments } => write!(
+            f,
+            "setp{}{}{} {}{}, {}, {}",
+            compare_op(data.cmp_op),
+            ftz(data.flush_to_zero),
+            data.type_,
+            Op(&arguments.dst1),
+            OptOp("|", arguments.dst2.as_ref()),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::SetpBool { data, arguments } => write!(
+            f,
+            "setp{}{}{}{} {}{}, {}, {}, {}{}",
+            compare_op(data.base.cmp_op),
+            data.bool_op,
+            ftz(data.base.flush_to_zero),
+            data.base.type_,
+            Op(&arguments.dst1),
+            OptOp("|", arguments.dst2.as_ref()),
+            Op(&arguments.src1),
+            Op(&arguments.src2),
+            flag(data.negate_src3, "!"),
+            Op(&arguments.src3)
+        ),
+        Instruction::ShflSync { data, arguments } => write!(
+            f,
+            "shfl.sync{}.b32 {}{}, {}, {}, {}, {}",
+            data.mode,
+            Op(&arguments.dst),
+            OptOp("|", arguments.dst_pred.as_ref()),
+            Op(&arguments.src),
+            Op(&arguments.src_lane),
+            Op(&arguments.src_opts),
+            Op(&arguments.src_membermask)
+        ),
+        Instruction::Shf { data, arguments } => write!(
+            f,
+            "shf{}{}.b32 {}, {}, {}, {}",
+            data.direction,
+            data.mode,
+            Op(&arguments.dst),
+            Op(&arguments.src_a),
+            Op(&arguments.src_b),
+            Op(&arguments.src_c)
+        ),
+        Instruction::Shl { data, arguments } => write!(
+            f,
+            "shl{} {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::Shr { data, arguments } => write!(
+            f,
+            "shr{} {}, {}, {}",
+            data.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::Sin { data, arguments } => write!(
+            f,
+            "sin.approx{}.f32 {}, {}",
+            flag(data.flush_to_zero, ".ftz"),
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::Sqrt { data, arguments } => {
+            f.write_str("sqrt")?;
+            write_rcp(f, data)?;
+            write!(f, " {}, {}", Op(&arguments.dst), Op(&arguments.src))
+        }
+        Instruction::St { data, arguments } => {
+            let caching = match data.caching {
+                StCacheOperator::Writeback => "",
+                StCacheOperator::L2Only => ".cg",
+                StCacheOperator::Streaming => ".cs",
+                StCacheOperator::Writethrough => ".wt",
+            };
+            write!(
+                f,
+                "st{}{}{}{} [{}], {}",
+                ld_st_qualifier(data.qualifier),
+                data.state_space,
+                caching,
+                data.typ,
+                Op(&arguments.src1),
+                Op(&arguments.src2)
+            )
+        }
+        Instruction::Sub { data, arguments } => {
+            f.write_str("sub")?;
+            write_arith(f, data)?;
+            write!(
+                f,
+                " {}, {}, {}",
+                Op(&arguments.dst),
+                Op(&arguments.src1),
+                Op(&arguments.src2)
+            )
+        }
+        Instruction::SubExtended { data, arguments } => write!(
+            f,
+            "{}{}{} {}, {}, {}",
+            if data.carry_in { "subc" } else { "sub" },
+            flag(data.carry_out, ".cc"),
+            data.type_,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::Trap {} => f.write_str("trap"),
+        Instruction::Xor { data, arguments } => write!(
+            f,
+            "xor{} {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src1),
+            Op(&arguments.src2)
+        ),
+        Instruction::Tanh { data, arguments } => write!(
+            f,
+            "tanh.approx{} {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src)
+        ),
+        Instruction::WmmaLoad { data, arguments } => write!(
+            f,
+            "{} {}, [{}]{}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src),
+            OptOp(", ", arguments.src_stride.as_ref())
+        ),
+        Instruction::WmmaStore { data, arguments } => write!(
+            f,
+            "{} [{}], {}{}",
+            data,
+            Op(&arguments.src_ptr),
+            Op(&arguments.src),
+            OptOp(", ", arguments.src_stride.as_ref())
+        ),
+        Instruction::WmmaMma { data, arguments } => write!(
+            f,
+            "{} {}, {}, {}, {}",
+            data,
+            Op(&arguments.dst),
+            Op(&arguments.src_a),
+            Op(&arguments.src_b),
+            Op(&arguments.src_c)
+        ),
+    }
+}
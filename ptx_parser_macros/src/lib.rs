@@ -25,7 +25,15 @@ static POSTFIX_MODIFIERS: &[&str] = &[
     ".bf16", ".bf16x2", ".e4m3", ".e5m2", ".tf32",
 ];
 
-static POSTFIX_TYPES: &[&str] = &["ScalarType", "VectorPrefix"];
+// Matrix instructions take two layouts and up to four types from the same enum, they can only be
+// told apart by their position
+static POSTFIX_TYPES: &[&str] = &[
+    "ScalarType",
+    "VectorPrefix",
+    "MmaShape",
+    "MatrixLayout",
+    "MatrixType",
+];
 
 struct OpcodeDefinitions {
     definitions: Vec<SingleOpcodeDefinition>,
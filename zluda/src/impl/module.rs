@@ -1,7 +1,7 @@
 use super::driver;
 use cuda_types::{
     cuda::*,
-    dark_api::{FatbinFileHeader, FatbincWrapper},
+    dark_api::{FatbinFileHeader, FatbinHeader, FatbincWrapper},
 };
 use dark_api::fatbin::Fatbin;
 use hip_runtime_sys::*;
@@ -41,17 +41,30 @@ fn get_ptx_from_wrapped_fatbin(image: *const ::core::ffi::c_void) -> Result<Vec<
     Err(CUerror::NO_BINARY_FOR_GPU)
 }
 
-/// get_ptx takes an `image` that can be either a fatbin or a NULL-terminated ptx, and returns a String containing a ptx extracted from `image`.
+/// get_ptx takes an `image` that can be either a wrapped fatbin, a bare fatbin or a NULL-terminated ptx, and returns a String containing a ptx extracted from `image`.
 fn get_ptx(image: *const ::core::ffi::c_void) -> Result<String, CUerror> {
     if image.is_null() {
         return Err(CUerror::INVALID_VALUE);
     }
 
-    let ptx = if unsafe { *(image as *const u32) } == FatbincWrapper::MAGIC {
+    let magic = unsafe { *(image as *const u32) };
+    let ptx = if magic == FatbincWrapper::MAGIC {
         let ptx_bytes = get_ptx_from_wrapped_fatbin(image)?;
         std::str::from_utf8(&ptx_bytes)
             .map_err(|_| CUerror::UNKNOWN)?
             .to_owned()
+    } else if magic == FatbinHeader::MAGIC {
+        let header = unsafe { &*image.cast::<FatbinHeader>() };
+        let fatbin = unsafe {
+            std::slice::from_raw_parts(
+                image.cast::<u8>(),
+                header.header_size as usize + header.files_size as usize,
+            )
+        };
+        ptx::fatbin::extract_ptx(fatbin).map_err(|err| match err {
+            ptx::fatbin::FatbinError::NoPtxAvailable => CUerror::NO_BINARY_FOR_GPU,
+            _ => CUerror::INVALID_IMAGE,
+        })?
     } else {
         unsafe { CStr::from_ptr(image.cast()) }
             .to_str()
@@ -117,7 +130,7 @@ fn get_cache_key<'a, 'b>(
     Some(zluda_cache::ModuleKey {
         hash: blake3::hash(text.as_bytes()).to_hex(),
         compiler_version: &*global_state.comgr_clang_version,
-        zluda_version: env!("VERGEN_GIT_SHA"),
+        zluda_version: ptx::PIPELINE_VERSION,
         device: isa,
         backend_key: serialized_attributes,
         last_access: zluda_cache::ModuleCache::time_now(),
@@ -140,13 +153,19 @@ fn compile_from_ptx_and_cache(
     cache_with_key: &mut Option<(zluda_cache::ModuleCache, zluda_cache::ModuleKey)>,
 ) -> Result<Vec<u8>, CUerror> {
     let ast = ptx_parser::parse_module_checked(text).map_err(|_| CUerror::NO_BINARY_FOR_GPU)?;
-    let llvm_module = ptx::to_llvm_module(ast, attributes).map_err(|_| CUerror::UNKNOWN)?;
+    let options = ptx::TranslateOptions {
+        volatile_coherence: ptx::VolatileCoherence::for_arch(gcn_arch),
+        ..ptx::TranslateOptions::default()
+    };
+    let llvm_module =
+        ptx::to_llvm_module_with_options(ast, attributes, options).map_err(|_| CUerror::UNKNOWN)?;
     let elf_module = comgr::compile_bitcode(
         comgr,
         gcn_arch,
         &*llvm_module.llvm_ir.write_bitcode_to_memory(),
         llvm_module.linked_bitcode(),
         &*llvm_module.attributes_ir.write_bitcode_to_memory(),
+        llvm_module.correctly_rounded_div_sqrt,
         None,
     )
     .map_err(|_| CUerror::UNKNOWN)?;
@@ -7,6 +7,16 @@ use std::{
     ptr,
 };
 
+/// Compute capability reported for every device. Both the CUDA driver (`zluda`) and NVML
+/// (`zluda_ml`) read it from here, so the two libraries can't disagree.
+pub const COMPUTE_CAPABILITY_MAJOR: i32 = 8;
+pub const COMPUTE_CAPABILITY_MINOR: i32 = 8;
+
+/// Version of the NVIDIA driver reported by NVML (`zluda_ml`). It must be recent enough to
+/// support `cuda_types::cuda::CUDA_VERSION` reported by the CUDA driver (`zluda`), otherwise
+/// applications comparing the two (e.g. PyTorch) will refuse to run. CUDA 12.8 requires r570
+pub const DRIVER_VERSION: &str = "570.124.06";
+
 pub trait CudaErrorType {
     const INVALID_VALUE: Self;
     const NOT_SUPPORTED: Self;
@@ -154,9 +164,32 @@ from_cuda_nop!(
     cublasStatus_t,
     CUlaunchConfig,
     cublasMath_t,
+    nvmlAccountingStats_t,
+    nvmlBAR1Memory_t,
+    nvmlBrandType_t,
+    nvmlClockType_t,
+    nvmlComputeMode_t,
     nvmlDevice_t,
+    nvmlEccCounterType_t,
+    nvmlEccErrorCounts_t,
+    nvmlEnableState_t,
+    nvmlEventData_t,
+    nvmlEventSet_t,
     nvmlFieldValue_t,
     nvmlGpuFabricInfo_t,
+    nvmlGpuP2PCapsIndex_t,
+    nvmlGpuP2PStatus_t,
+    nvmlGpuTopologyLevel_t,
+    nvmlHwbcEntry_t,
+    nvmlMemory_t,
+    nvmlMemoryErrorType_t,
+    nvmlPciInfo_t,
+    nvmlPcieUtilCounter_t,
+    nvmlProcessInfo_t,
+    nvmlPstates_t,
+    nvmlReturn_t,
+    nvmlTemperatureSensors_t,
+    nvmlUtilization_t,
     cublasLtHandle_t
 );
 from_cuda_transmute!(
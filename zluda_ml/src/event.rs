@@ -0,0 +1,158 @@
+use cuda_types::nvml::*;
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+use zluda_common::{FromCuda, ZludaObject};
+
+// We never generate any events, but some applications refuse to start unless they can register
+// for them and then block in nvmlEventSetWait, so event sets are real objects that behave like
+// the ones of an idle GPU
+pub(crate) struct EventSet {
+    shared: Arc<EventSetShared>,
+}
+
+pub(crate) struct EventSetShared {
+    state: Mutex<EventSetState>,
+    freed: Condvar,
+}
+
+struct EventSetState {
+    freed: bool,
+    // Recorded, but never acted upon since no event is ever generated
+    #[allow(dead_code)]
+    registered_events: ::core::ffi::c_ulonglong,
+}
+
+impl ZludaObject for EventSet {
+    const COOKIE: usize = 0x2b8f1a7c94d3e605;
+
+    type Error = nvmlError_t;
+    type CudaHandle = nvmlEventSet_t;
+
+    fn drop_checked(&mut self) -> nvmlReturn_t {
+        let mut state = self.shared.state.lock().map_err(|_| nvmlError_t::UNKNOWN)?;
+        state.freed = true;
+        self.shared.freed.notify_all();
+        Ok(())
+    }
+}
+
+/// Owning reference to the state of an event set. Entry points receive this instead of
+/// `&EventSet` so that a thread blocked in `nvmlEventSetWait` keeps the state alive even if
+/// another thread frees the set in the meantime
+pub(crate) struct EventSetRef(Arc<EventSetShared>);
+
+impl<'a> FromCuda<'a, nvmlEventSet_t, nvmlError_t> for EventSetRef {
+    fn from_cuda(handle: &'a nvmlEventSet_t) -> Result<Self, nvmlError_t> {
+        let set = zluda_common::as_ref::<EventSet>(handle).as_result()?;
+        Ok(EventSetRef(set.shared.clone()))
+    }
+}
+
+impl EventSetRef {
+    pub(crate) fn register(&self, event_types: ::core::ffi::c_ulonglong) -> nvmlReturn_t {
+        let mut state = self.0.state.lock().map_err(|_| nvmlError_t::UNKNOWN)?;
+        state.registered_events |= event_types;
+        Ok(())
+    }
+}
+
+pub(crate) fn event_set_create(set: &mut nvmlEventSet_t) -> nvmlReturn_t {
+    *set = EventSet {
+        shared: Arc::new(EventSetShared {
+            state: Mutex::new(EventSetState {
+                freed: false,
+                registered_events: 0,
+            }),
+            freed: Condvar::new(),
+        }),
+    }
+    .wrap();
+    Ok(())
+}
+
+pub(crate) fn event_set_wait_v2(
+    set: EventSetRef,
+    _data: &mut nvmlEventData_t,
+    timeoutms: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    let state = set.0.state.lock().map_err(|_| nvmlError_t::UNKNOWN)?;
+    // No event is ever going to arrive, we wait until the timeout expires or the set is freed
+    let _state = set
+        .0
+        .freed
+        .wait_timeout_while(state, Duration::from_millis(timeoutms as u64), |state| {
+            !state.freed
+        })
+        .map_err(|_| nvmlError_t::UNKNOWN)?;
+    nvmlReturn_t::ERROR_TIMEOUT
+}
+
+pub(crate) fn event_set_wait(
+    set: EventSetRef,
+    data: &mut nvmlEventData_t,
+    timeoutms: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    event_set_wait_v2(set, data, timeoutms)
+}
+
+pub(crate) fn event_set_free(set: nvmlEventSet_t) -> nvmlReturn_t {
+    zluda_common::drop_checked::<EventSet>(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use cuda_types::nvml::*;
+    use std::{
+        mem, thread,
+        time::{Duration, Instant},
+    };
+
+    fn create() -> nvmlEventSet_t {
+        let mut set = unsafe { mem::zeroed() };
+        assert_eq!(
+            unsafe { crate::nvmlEventSetCreate(&mut set) },
+            nvmlReturn_t::SUCCESS
+        );
+        set
+    }
+
+    #[test]
+    fn wait_times_out() {
+        let set = create();
+        let mut data = unsafe { mem::zeroed() };
+        let start = Instant::now();
+        assert_eq!(
+            unsafe { crate::nvmlEventSetWait_v2(set, &mut data, 50) },
+            nvmlReturn_t::ERROR_TIMEOUT
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            unsafe { crate::nvmlEventSetFree(set) },
+            nvmlReturn_t::SUCCESS
+        );
+    }
+
+    #[test]
+    fn free_while_waiting() {
+        let set = create();
+        let set_address = set as usize;
+        let waiter = thread::spawn(move || {
+            let mut data = unsafe { mem::zeroed() };
+            let start = Instant::now();
+            let result = unsafe {
+                crate::nvmlEventSetWait_v2(set_address as nvmlEventSet_t, &mut data, 60_000)
+            };
+            (result, start.elapsed())
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            unsafe { crate::nvmlEventSetFree(set) },
+            nvmlReturn_t::SUCCESS
+        );
+        let (result, elapsed) = waiter.join().unwrap();
+        assert_eq!(result, nvmlReturn_t::ERROR_TIMEOUT);
+        assert!(elapsed < Duration::from_secs(60));
+    }
+}
@@ -1,7 +1,22 @@
 use cuda_types::nvml::*;
-use std::{ffi::CStr, ptr};
+use std::{
+    env,
+    ffi::{CStr, CString, OsString},
+    ptr,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
-const VERSION: &'static CStr = c"550.77";
+const DRIVER_VERSION_ENV: &str = "ZLUDA_NVML_DRIVER_VERSION";
+// Set to 1 to let nvmlDeviceSetComputeMode succeed. The mode is only remembered, AMD GPUs
+// have no compute modes to enforce it
+pub(crate) const SETTABLE_COMPUTE_MODE_ENV: &str = "ZLUDA_NVML_SETTABLE_COMPUTE_MODE";
+// Set through zluda_nvml_set_driver_version(...), takes priority over the environment variable
+static DRIVER_VERSION_OVERRIDE: Mutex<Option<CString>> = Mutex::new(None);
+
+// Tests observing the global initialization state must not run concurrently with each other
+#[cfg(test)]
+pub(crate) static GLOBAL_STATE_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
 
 #[cfg(debug_assertions)]
 pub(crate) fn unimplemented() -> nvmlReturn_t {
@@ -13,23 +28,381 @@ pub(crate) fn unimplemented() -> nvmlReturn_t {
     nvmlReturn_t::ERROR_NOT_SUPPORTED
 }
 
+fn is_valid_driver_version(version: &[u8]) -> bool {
+    !version.is_empty() && version.len() < NVML_SYSTEM_DRIVER_VERSION_BUFFER_SIZE as usize
+}
+
+fn driver_version() -> CString {
+    driver_version_with(|name| env::var_os(name))
+}
+
+// `var` looks up environment variables, tests pass their own environment
+fn driver_version_with(var: impl FnOnce(&str) -> Option<OsString>) -> CString {
+    if let Some(version) = &*DRIVER_VERSION_OVERRIDE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+    {
+        return version.clone();
+    }
+    if let Some(version) = var(DRIVER_VERSION_ENV)
+        .and_then(|version| version.into_string().ok())
+        .filter(|version| is_valid_driver_version(version.as_bytes()))
+        .and_then(|version| CString::new(version).ok())
+    {
+        return version;
+    }
+    CString::new(zluda_common::DRIVER_VERSION).unwrap()
+}
+
+// `var` looks up environment variables, tests pass their own environment
+pub(crate) fn is_compute_mode_settable(var: impl FnOnce(&str) -> Option<OsString>) -> bool {
+    var(SETTABLE_COMPUTE_MODE_ENV).is_some_and(|value| value == "1")
+}
+
+/// Overrides the reported driver version, `None` restores the default
+pub(crate) fn set_driver_version(version: Option<&CStr>) -> nvmlReturn_t {
+    if let Some(version) = version {
+        if !is_valid_driver_version(version.to_bytes()) {
+            return nvmlReturn_t::ERROR_INVALID_ARGUMENT;
+        }
+    }
+    *DRIVER_VERSION_OVERRIDE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner()) = version.map(CStr::to_owned);
+    nvmlReturn_t::SUCCESS
+}
+
 pub(crate) fn system_get_driver_version(
     result: *mut ::core::ffi::c_char,
     length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    copy_string(&driver_version(), result, length)
+}
+
+pub(crate) fn system_get_n_v_m_l_version(
+    result: *mut ::core::ffi::c_char,
+    length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    // NVML reports its own version as "<NVML_API_VERSION>.<driver version>"
+    let mut version = format!("{}.", NVML_API_VERSION).into_bytes();
+    version.extend_from_slice(driver_version().as_bytes());
+    copy_string(
+        &CString::new(version).map_err(|_| nvmlError_t::UNKNOWN)?,
+        result,
+        length,
+    )
+}
+
+// Host Interface Cards only exist in NVIDIA's S-class systems
+pub(crate) fn system_get_hic_version(
+    hwbc_count: &mut ::core::ffi::c_uint,
+    _hwbc_entries: Option<&mut nvmlHwbcEntry_t>,
+) -> nvmlReturn_t {
+    *hwbc_count = 0;
+    nvmlReturn_t::SUCCESS
+}
+
+pub(crate) fn system_get_cuda_driver_version(
+    cuda_driver_version: &mut ::core::ffi::c_int,
+) -> nvmlReturn_t {
+    *cuda_driver_version = cuda_types::cuda::CUDA_VERSION as i32;
+    nvmlReturn_t::SUCCESS
+}
+
+pub(crate) fn system_get_cuda_driver_version_v2(
+    cuda_driver_version: &mut ::core::ffi::c_int,
+) -> nvmlReturn_t {
+    system_get_cuda_driver_version(cuda_driver_version)
+}
+
+// Same rules as NVML: strings are never truncated, if the buffer can't fit the string together
+// with its null terminator we return ERROR_INSUFFICIENT_SIZE and leave the buffer untouched
+pub(crate) fn copy_string(
+    string: &CStr,
+    result: *mut ::core::ffi::c_char,
+    length: ::core::ffi::c_uint,
 ) -> nvmlReturn_t {
     if result == ptr::null_mut() {
         return nvmlReturn_t::ERROR_INVALID_ARGUMENT;
     }
-    let version = VERSION.to_bytes_with_nul();
-    let copy_length = usize::min(length as usize, version.len());
-    let slice = unsafe { std::slice::from_raw_parts_mut(result.cast(), copy_length) };
-    slice.copy_from_slice(&version[..copy_length]);
-    if let Some(null) = slice.last_mut() {
-        *null = 0;
+    let string = string.to_bytes_with_nul();
+    if (length as usize) < string.len() {
+        return nvmlReturn_t::ERROR_INSUFFICIENT_SIZE;
     }
+    let slice = unsafe { std::slice::from_raw_parts_mut(result.cast(), string.len()) };
+    slice.copy_from_slice(string);
     nvmlReturn_t::SUCCESS
 }
 
+/// Fills every slot of an `nvmlDeviceGetFieldValues` batch using `get_value`, which returns
+/// `None` for field ids we don't know about. Failures are reported per slot, the batch as a
+/// whole always succeeds
+pub(crate) fn fill_field_values(
+    values: &mut [nvmlFieldValue_t],
+    mut get_value: impl FnMut(
+        ::core::ffi::c_uint,
+        ::core::ffi::c_uint,
+    ) -> Option<Result<(nvmlValueType_t, nvmlValue_t), nvmlError_t>>,
+) {
+    for field in values {
+        let start = Instant::now();
+        let result = get_value(field.fieldId, field.scopeId);
+        field.latencyUsec = start.elapsed().as_micros() as i64;
+        field.timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_micros() as i64)
+            .unwrap_or(0);
+        field.value = nvmlValue_t { ullVal: 0 };
+        match result {
+            Some(Ok((value_type, value))) => {
+                field.valueType = value_type;
+                field.value = value;
+                field.nvmlReturn = nvmlReturn_t::SUCCESS;
+            }
+            Some(Err(err)) => {
+                field.valueType = nvmlValueType_t::NVML_VALUE_TYPE_UNSIGNED_LONG_LONG;
+                field.nvmlReturn = Err(err);
+            }
+            None => {
+                field.valueType = nvmlValueType_t::NVML_VALUE_TYPE_UNSIGNED_LONG_LONG;
+                field.nvmlReturn = nvmlReturn_t::ERROR_NOT_SUPPORTED;
+            }
+        }
+    }
+}
+
 pub(crate) fn error_string(_result: nvmlReturn_t) -> *const ::core::ffi::c_char {
     c"".as_ptr()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::GLOBAL_STATE_TEST_LOCK;
+    use cuda_types::nvml::*;
+    use std::ffi::{CStr, OsString};
+
+    fn get_string(
+        f: unsafe extern "system" fn(*mut ::core::ffi::c_char, ::core::ffi::c_uint) -> nvmlReturn_t,
+    ) -> String {
+        let mut buffer = [0 as ::core::ffi::c_char; NVML_SYSTEM_NVML_VERSION_BUFFER_SIZE as usize];
+        assert_eq!(
+            unsafe { f(buffer.as_mut_ptr(), buffer.len() as u32) },
+            nvmlReturn_t::SUCCESS
+        );
+        unsafe { CStr::from_ptr(buffer.as_ptr()) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn nvml_version_matches_driver_version() {
+        let _lock = GLOBAL_STATE_TEST_LOCK
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let driver = get_string(crate::nvmlSystemGetDriverVersion);
+        let nvml = get_string(crate::nvmlSystemGetNVMLVersion);
+        assert_eq!(nvml, format!("{}.{}", NVML_API_VERSION, driver));
+    }
+
+    #[test]
+    fn cuda_driver_version_matches_cuda_shim() {
+        let mut v1 = 0;
+        let mut v2 = 0;
+        assert_eq!(
+            unsafe { crate::nvmlSystemGetCudaDriverVersion(&mut v1) },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(
+            unsafe { crate::nvmlSystemGetCudaDriverVersion_v2(&mut v2) },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(v1, v2);
+        assert_eq!(v1, cuda_types::cuda::CUDA_VERSION as i32);
+    }
+
+    #[test]
+    fn driver_version_supports_cuda_version() {
+        // Minimum Linux driver for each CUDA version we may report
+        let minimum_driver = match cuda_types::cuda::CUDA_VERSION {
+            12080 => (570, 26),
+            version => panic!("unknown CUDA version {version}"),
+        };
+        let mut version = zluda_common::DRIVER_VERSION
+            .split('.')
+            .map(|part| part.parse::<u32>().unwrap());
+        let driver = (version.next().unwrap(), version.next().unwrap());
+        assert!(driver >= minimum_driver);
+    }
+
+    #[test]
+    fn driver_version_overrides() {
+        let _lock = GLOBAL_STATE_TEST_LOCK
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let env = |version: &'static str| {
+            move |name: &str| (name == super::DRIVER_VERSION_ENV).then(|| OsString::from(version))
+        };
+        let no_env = |_: &str| None;
+        assert_eq!(
+            super::driver_version_with(no_env).to_str().unwrap(),
+            zluda_common::DRIVER_VERSION
+        );
+        assert_eq!(
+            super::driver_version_with(env("535.183.01"))
+                .to_str()
+                .unwrap(),
+            "535.183.01"
+        );
+        // Invalid versions in the environment are ignored
+        assert_eq!(
+            super::driver_version_with(env("")).to_str().unwrap(),
+            zluda_common::DRIVER_VERSION
+        );
+        assert_eq!(
+            unsafe { crate::zluda_nvml_set_driver_version(c"560.35.03".as_ptr()) },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(get_string(crate::nvmlSystemGetDriverVersion), "560.35.03");
+        assert_eq!(get_string(crate::nvmlSystemGetNVMLVersion), "12.560.35.03");
+        assert_eq!(
+            super::driver_version_with(env("535.183.01"))
+                .to_str()
+                .unwrap(),
+            "560.35.03"
+        );
+        assert_eq!(
+            unsafe { crate::zluda_nvml_set_driver_version(c"".as_ptr()) },
+            nvmlReturn_t::ERROR_INVALID_ARGUMENT
+        );
+        assert_eq!(get_string(crate::nvmlSystemGetDriverVersion), "560.35.03");
+        assert_eq!(
+            unsafe { crate::zluda_nvml_set_driver_version(std::ptr::null()) },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(
+            super::driver_version_with(env("535.183.01"))
+                .to_str()
+                .unwrap(),
+            "535.183.01"
+        );
+    }
+
+    #[test]
+    fn no_host_interface_cards() {
+        let mut count = u32::MAX;
+        assert_eq!(
+            unsafe { crate::nvmlSystemGetHicVersion(&mut count, std::ptr::null_mut()) },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn short_buffer_is_rejected() {
+        let mut buffer = [1 as ::core::ffi::c_char; 4];
+        assert_eq!(
+            unsafe { crate::nvmlSystemGetNVMLVersion(buffer.as_mut_ptr(), buffer.len() as u32) },
+            nvmlReturn_t::ERROR_INSUFFICIENT_SIZE
+        );
+        assert_eq!(buffer, [1; 4]);
+    }
+
+    #[test]
+    fn copy_string_fits_exactly() {
+        let mut buffer = [1 as ::core::ffi::c_char; 4];
+        assert_eq!(
+            super::copy_string(c"abc", buffer.as_mut_ptr(), 4),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(buffer, [b'a' as _, b'b' as _, b'c' as _, 0]);
+        assert_eq!(
+            super::copy_string(c"abcd", buffer.as_mut_ptr(), 4),
+            nvmlReturn_t::ERROR_INSUFFICIENT_SIZE
+        );
+    }
+
+    #[test]
+    fn field_values_are_reported_per_slot() {
+        let mut values = [
+            NVML_FI_DEV_POWER_INSTANT,
+            0xdead,
+            NVML_FI_DEV_ECC_CURRENT,
+            NVML_FI_DEV_MEMORY_TEMP,
+        ]
+        .map(|field_id| {
+            let mut value: nvmlFieldValue_t = unsafe { std::mem::zeroed() };
+            value.fieldId = field_id;
+            value.scopeId = 7;
+            value
+        });
+        super::fill_field_values(&mut values, |field_id, scope_id| {
+            assert_eq!(scope_id, 7);
+            match field_id {
+                NVML_FI_DEV_POWER_INSTANT => Some(Ok((
+                    nvmlValueType_t::NVML_VALUE_TYPE_UNSIGNED_INT,
+                    nvmlValue_t { uiVal: 150_000 },
+                ))),
+                NVML_FI_DEV_ECC_CURRENT => Some(Err(nvmlError_t::NO_PERMISSION)),
+                NVML_FI_DEV_MEMORY_TEMP => Some(Ok((
+                    nvmlValueType_t::NVML_VALUE_TYPE_UNSIGNED_INT,
+                    nvmlValue_t { uiVal: 55 },
+                ))),
+                _ => None,
+            }
+        });
+        assert_eq!(values[0].nvmlReturn, nvmlReturn_t::SUCCESS);
+        assert_eq!(
+            values[0].valueType,
+            nvmlValueType_t::NVML_VALUE_TYPE_UNSIGNED_INT
+        );
+        assert_eq!(unsafe { values[0].value.uiVal }, 150_000);
+        assert_eq!(values[1].fieldId, 0xdead);
+        assert_eq!(values[1].nvmlReturn, nvmlReturn_t::ERROR_NOT_SUPPORTED);
+        assert_eq!(
+            values[1].valueType,
+            nvmlValueType_t::NVML_VALUE_TYPE_UNSIGNED_LONG_LONG
+        );
+        assert_eq!(values[2].nvmlReturn, nvmlReturn_t::ERROR_NO_PERMISSION);
+        assert_eq!(values[3].nvmlReturn, nvmlReturn_t::SUCCESS);
+        assert_eq!(unsafe { values[3].value.uiVal }, 55);
+        for value in values {
+            assert_ne!(value.timestamp, 0);
+            assert!(value.latencyUsec >= 0);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn compute_capability_matches_cuda_driver() {
+        let _lock = super::GLOBAL_STATE_TEST_LOCK
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        assert_eq!(
+            unsafe { crate::nvmlInitWithFlags(NVML_INIT_FLAG_NO_GPUS) },
+            nvmlReturn_t::SUCCESS
+        );
+        let mut count = 0;
+        assert_eq!(
+            unsafe { crate::nvmlDeviceGetCount_v2(&mut count) },
+            nvmlReturn_t::SUCCESS
+        );
+        for index in 0..count {
+            let mut device = unsafe { std::mem::zeroed() };
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetHandleByIndex_v2(index, &mut device) },
+                nvmlReturn_t::SUCCESS
+            );
+            let (mut major, mut minor) = (0, 0);
+            assert_eq!(
+                unsafe {
+                    crate::nvmlDeviceGetCudaComputeCapability(device, &mut major, &mut minor)
+                },
+                nvmlReturn_t::SUCCESS
+            );
+            assert_eq!(major, zluda_common::COMPUTE_CAPABILITY_MAJOR);
+            assert_eq!(minor, zluda_common::COMPUTE_CAPABILITY_MINOR);
+        }
+        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+    }
+}
@@ -1,67 +1,991 @@
+use crate::event::EventSetRef;
+use crate::sysfs;
+use crate::telemetry::{
+    GpuTelemetry, PeerLink, ProcessMemory, RocmSmiTelemetry, TemperatureSensor,
+};
+use crate::topology::{self, Link};
 use cuda_types::nvml::*;
 use rocm_smi_sys::*;
-use std::mem;
-use zluda_common::{from_cuda_object, ZludaObject};
+use std::{
+    env,
+    ffi::{CStr, CString, OsString},
+    mem,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
+use zluda_common::FromCuda;
 
+pub(crate) use crate::event::{
+    event_set_create, event_set_free, event_set_wait, event_set_wait_v2,
+};
 pub(crate) use crate::impl_common::error_string;
+pub(crate) use crate::impl_common::system_get_cuda_driver_version;
+pub(crate) use crate::impl_common::system_get_cuda_driver_version_v2;
 pub(crate) use crate::impl_common::system_get_driver_version;
+pub(crate) use crate::impl_common::system_get_hic_version;
+pub(crate) use crate::impl_common::system_get_n_v_m_l_version;
 
 pub(crate) struct Device {
-    _index: u32,
+    // ROCm SMI device index, our own (NVML) index is the position in the device table
+    index: u32,
+    nvml_index: u32,
+    uuid: Option<String>,
+    telemetry: Box<dyn GpuTelemetry>,
+    settings: DeviceSettings,
 }
 
-impl ZludaObject for Device {
-    const COOKIE: usize = 0x79443851e7cee0d9;
+// Settings which clients can change, but which have no effect on AMD GPUs. We remember them,
+// so that clients reading them back see what they have set
+#[derive(Default)]
+struct DeviceSettings {
+    compute_mode: AtomicU32,
+    accounting_mode: AtomicBool,
+}
+
+impl Device {
+    fn kfd_properties(&self) -> Result<sysfs::KfdProperties, nvmlError_t> {
+        let node_id = self.telemetry.kfd_node_id()?;
+        sysfs::kfd_properties(&sysfs::kfd_node_properties_path(node_id))
+    }
+}
+
+#[cfg(test)]
+impl Device {
+    pub(crate) fn mock(
+        nvml_index: u32,
+        uuid: &str,
+        telemetry: impl GpuTelemetry + 'static,
+    ) -> Self {
+        Device {
+            index: nvml_index,
+            nvml_index,
+            uuid: Some(uuid.to_string()),
+            telemetry: Box::new(telemetry),
+            settings: DeviceSettings::default(),
+        }
+    }
+}
+
+// What entry points taking a device handle get. Devices never change after initialization
+// (settings are atomics), so a getter works on its own reference to the device without holding
+// the GLOBAL_STATE lock. If nvmlShutdown runs in the meantime, the device is freed when the
+// getter is done with it
+#[derive(Clone)]
+pub(crate) struct DeviceRef(Arc<Device>);
+
+impl Deref for DeviceRef {
+    type Target = Device;
+
+    fn deref(&self) -> &Device {
+        &self.0
+    }
+}
+
+struct GlobalState {
+    devices: Vec<DeviceRef>,
+    // Number of nvmlInit* calls not yet matched by nvmlShutdown
+    init_count: usize,
+    // Handles encode the generation of the device table they were obtained from, see
+    // device_handle
+    generation: usize,
+    // False if we were initialized with NVML_INIT_FLAG_NO_GPUS and ROCm SMI failed to initialize
+    smi_initialized: bool,
+}
+
+impl GlobalState {
+    fn with_devices(devices: Vec<Device>, smi_initialized: bool) -> Self {
+        static GENERATION: AtomicUsize = AtomicUsize::new(0);
+        GlobalState {
+            devices: devices
+                .into_iter()
+                .map(|device| DeviceRef(Arc::new(device)))
+                .collect(),
+            init_count: 0,
+            generation: GENERATION.fetch_add(1, Ordering::Relaxed) & HANDLE_GENERATION_MASK,
+            smi_initialized,
+        }
+    }
+
+    unsafe fn new(flags: ::core::ffi::c_uint) -> Result<Self, nvmlError_t> {
+        if let Err(err) = rsmi_init(0) {
+            return if flags & NVML_INIT_FLAG_NO_GPUS != 0 {
+                Ok(GlobalState::with_devices(Vec::new(), false))
+            } else {
+                Err(err.into())
+            };
+        }
+        let mut device_count = 0;
+        if let Err(err) = rsmi_num_monitor_devices(&mut device_count) {
+            rsmi_shut_down().ok();
+            return Err(err.into());
+        }
+        let mut devices = (0..device_count)
+            .map(|index| {
+                let mut bus_id = u64::MAX;
+                rsmi_dev_pci_id_get(index, &mut bus_id).ok();
+                let mut unique_id = 0;
+                let uuid = rsmi_dev_unique_id_get(index, &mut unique_id)
+                    .ok()
+                    .map(|_| uuid_from_unique_id(unique_id));
+                (bus_id, index, uuid)
+            })
+            .collect::<Vec<_>>();
+        devices.sort_by_key(|(bus_id, index, _)| (*bus_id, *index));
+        let devices = devices
+            .into_iter()
+            .map(|(_, index, uuid)| Device {
+                index,
+                // Set by order_devices
+                nvml_index: 0,
+                uuid,
+                telemetry: Box::new(RocmSmiTelemetry::new(index)),
+                settings: DeviceSettings::default(),
+            })
+            .collect();
+        let devices = order_devices(devices, |name| env::var_os(name));
+        Ok(GlobalState::with_devices(devices, true))
+    }
+
+    fn device_handle(&self, nvml_index: usize) -> nvmlDevice_t {
+        ((self.generation << HANDLE_INDEX_BITS) | (nvml_index + 1)) as nvmlDevice_t
+    }
 
-    type Error = nvmlError_t;
-    type CudaHandle = nvmlDevice_t;
+    fn device(&self, handle: nvmlDevice_t) -> Result<DeviceRef, nvmlError_t> {
+        let handle = handle as usize;
+        let nvml_index = (handle & ((1 << HANDLE_INDEX_BITS) - 1))
+            .checked_sub(1)
+            .ok_or(nvmlError_t::INVALID_ARGUMENT)?;
+        if handle >> HANDLE_INDEX_BITS != self.generation {
+            return Err(nvmlError_t::INVALID_ARGUMENT);
+        }
+        self.devices
+            .get(nvml_index)
+            .cloned()
+            .ok_or(nvmlError_t::INVALID_ARGUMENT)
+    }
+}
+
+// ZLUDA's CUDA ordinals are HIP ordinals, and HIP only exposes the devices listed in
+// HIP_VISIBLE_DEVICES (or in CUDA_VISIBLE_DEVICES, if the former is not set), in the listed
+// order. Like NVIDIA's NVML we still enumerate every device, so nvmlDeviceGetCount can be larger
+// than cuDeviceGetCount, but the visible devices come first: the NVML index of a visible device
+// is its CUDA ordinal. The hidden devices follow in PCI bus order. `devices` are in PCI bus order,
+// which is also what the indices in the variables refer to
+fn order_devices(devices: Vec<Device>, var: impl Fn(&str) -> Option<OsString>) -> Vec<Device> {
+    let visible = match var("HIP_VISIBLE_DEVICES").or_else(|| var("CUDA_VISIBLE_DEVICES")) {
+        Some(list) => visible_devices(&list.to_string_lossy(), devices.len()),
+        None => (0..devices.len()).collect(),
+    };
+    let hidden = (0..devices.len())
+        .filter(|index| !visible.contains(index))
+        .collect::<Vec<_>>();
+    let mut devices = devices.into_iter().map(Some).collect::<Vec<_>>();
+    visible
+        .into_iter()
+        .chain(hidden)
+        .enumerate()
+        .map(|(nvml_index, index)| {
+            let mut device = devices[index].take().unwrap();
+            device.nvml_index = nvml_index as u32;
+            device
+        })
+        .collect()
+}
 
-    fn drop_checked(&mut self) -> nvmlReturn_t {
-        Ok(())
+// Like in HIP, the list ends at the first entry that is not the index of a device or repeats an
+// earlier one, so an empty variable hides every device
+fn visible_devices(list: &str, device_count: usize) -> Vec<usize> {
+    let mut visible = Vec::new();
+    for entry in list.split(',') {
+        match entry.trim().parse::<usize>() {
+            Ok(index) if index < device_count && !visible.contains(&index) => visible.push(index),
+            _ => break,
+        }
     }
+    visible
 }
 
-from_cuda_object!(Device);
+// Device handles are not pointers, but the position in the device table plus one (so that no
+// handle is NULL) in the low bits and the generation of the device table in the high bits. Every
+// initialization after a full shutdown creates a new generation, so a handle that outlived its
+// nvmlShutdown is rejected instead of pointing at a freed device, even if the library has been
+// initialized again since
+const HANDLE_INDEX_BITS: u32 = 16;
+const HANDLE_GENERATION_MASK: usize = usize::MAX >> HANDLE_INDEX_BITS;
 
-pub(crate) unsafe fn init() -> rsmi_status_t {
-    rsmi_init(0)
+// HIP's device UUID (and thus what we return from cuDeviceGetUuid) is the ASCII text of the
+// 16-digit hex unique id. NVML formats the same 16 bytes the way CUDA does, so that UUIDs
+// obtained from both libraries match
+fn uuid_from_unique_id(unique_id: u64) -> String {
+    let bytes = format!("{:016x}", unique_id);
+    let hex = bytes
+        .as_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>();
+    format!(
+        "GPU-{}-{}-{}-{}-{}",
+        hex[0..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..16].concat()
+    )
+}
+
+// Getters only take the read lock, and only for as long as it takes to find their device, so
+// monitoring threads polling concurrently don't wait for each other
+static GLOBAL_STATE: RwLock<Option<GlobalState>> = RwLock::new(None);
+
+fn with_state<T>(f: impl FnOnce(&GlobalState) -> Result<T, nvmlError_t>) -> Result<T, nvmlError_t> {
+    let state = GLOBAL_STATE.read().map_err(|_| nvmlError_t::UNKNOWN)?;
+    match &*state {
+        Some(state) => f(state),
+        None => Err(nvmlError_t::UNINITIALIZED),
+    }
+}
+
+// Every entry point taking a device handle goes through this conversion, so this is where we
+// reject calls made before nvmlInit or after the final nvmlShutdown
+impl<'a> FromCuda<'a, nvmlDevice_t, nvmlError_t> for DeviceRef {
+    fn from_cuda(handle: &'a nvmlDevice_t) -> Result<DeviceRef, nvmlError_t> {
+        with_state(|state| state.device(*handle))
+    }
+}
+
+pub(crate) unsafe fn init() -> nvmlReturn_t {
+    init_with_flags(0)
+}
+
+pub(crate) unsafe fn init_v2() -> nvmlReturn_t {
+    init_with_flags(0)
+}
+
+pub(crate) unsafe fn init_with_flags(flags: ::core::ffi::c_uint) -> nvmlReturn_t {
+    let mut state = GLOBAL_STATE.write().map_err(|_| nvmlError_t::UNKNOWN)?;
+    let state = match &mut *state {
+        Some(state) => state,
+        None => state.insert(GlobalState::new(flags)?),
+    };
+    state.init_count += 1;
+    Ok(())
 }
 
-pub(crate) unsafe fn init_v2() -> rsmi_status_t {
-    rsmi_init(0)
+// Initializes the library with the given devices instead of the ones found by ROCm SMI. Like
+// with nvmlInit, it must be matched by a call to nvmlShutdown. Calls to nvmlInit made while
+// initialized keep these devices
+#[cfg(test)]
+pub(crate) fn init_with_devices(devices: Vec<Device>) -> nvmlReturn_t {
+    let mut state = GLOBAL_STATE.write().map_err(|_| nvmlError_t::UNKNOWN)?;
+    if state.is_some() {
+        return nvmlReturn_t::ERROR_ALREADY_INITIALIZED;
+    }
+    state
+        .insert(GlobalState::with_devices(devices, false))
+        .init_count = 1;
+    Ok(())
 }
 
-pub(crate) unsafe fn init_with_flags(_flags: ::core::ffi::c_uint) -> rsmi_status_t {
-    rsmi_init(0)
+pub(crate) unsafe fn shutdown() -> nvmlReturn_t {
+    let mut state = GLOBAL_STATE.write().map_err(|_| nvmlError_t::UNKNOWN)?;
+    let global_state = state.as_mut().ok_or(nvmlError_t::UNINITIALIZED)?;
+    global_state.init_count -= 1;
+    if global_state.init_count > 0 {
+        return Ok(());
+    }
+    match state.take() {
+        Some(GlobalState {
+            smi_initialized: true,
+            ..
+        }) => Ok(rsmi_shut_down()?),
+        _ => Ok(()),
+    }
 }
 
-pub(crate) unsafe fn shutdown() -> rsmi_status_t {
-    rsmi_shut_down()
+pub(crate) fn system_get_process_name(
+    pid: ::core::ffi::c_uint,
+    name: *mut ::core::ffi::c_char,
+    length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    let process_name = std::fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map_err(|_| nvmlError_t::NOT_FOUND)?;
+    let process_name = CString::new(process_name.trim_end()).map_err(|_| nvmlError_t::UNKNOWN)?;
+    crate::impl_common::copy_string(&process_name, name, length)
 }
 
-pub(crate) unsafe fn device_get_count_v2(device_count: &mut ::core::ffi::c_uint) -> rsmi_status_t {
-    rsmi_num_monitor_devices(device_count)
+pub(crate) fn device_get_count_v2(device_count: &mut ::core::ffi::c_uint) -> nvmlReturn_t {
+    *device_count = with_state(|state| Ok(state.devices.len() as u32))?;
+    Ok(())
 }
 
 pub(crate) unsafe fn device_get_field_values(
-    _device: &Device,
+    device: DeviceRef,
     values_count: ::core::ffi::c_int,
     values: &mut cuda_types::nvml::nvmlFieldValue_t,
 ) -> nvmlReturn_t {
-    for field in std::slice::from_raw_parts_mut(values, values_count as usize) {
-        get_field_value(field)?;
+    let values_count = usize::try_from(values_count).map_err(|_| nvmlError_t::INVALID_ARGUMENT)?;
+    let values = std::slice::from_raw_parts_mut(values, values_count);
+    crate::impl_common::fill_field_values(values, |field_id, _| get_field_value(&device, field_id));
+    Ok(())
+}
+
+// Sensor fields read the device telemetry, the same source as the dedicated getters. NVML has
+// no field ids for clocks, utilization or memory usage, clients query them with
+// nvmlDeviceGetClockInfo, nvmlDeviceGetUtilizationRates and nvmlDeviceGetMemoryInfo
+unsafe fn get_field_value(
+    device: &DeviceRef,
+    field_id: ::core::ffi::c_uint,
+) -> Option<Result<(nvmlValueType_t, nvmlValue_t), nvmlError_t>> {
+    fn unsigned_int(value: u32) -> (nvmlValueType_t, nvmlValue_t) {
+        (
+            nvmlValueType_t::NVML_VALUE_TYPE_UNSIGNED_INT,
+            nvmlValue_t { uiVal: value },
+        )
+    }
+    fn unsigned_long_long(value: u64) -> (nvmlValueType_t, nvmlValue_t) {
+        (
+            nvmlValueType_t::NVML_VALUE_TYPE_UNSIGNED_LONG_LONG,
+            nvmlValue_t { ullVal: value },
+        )
+    }
+    unsafe fn total_ecc(
+        device: &DeviceRef,
+        error_type: nvmlMemoryErrorType_t,
+        counter_type: nvmlEccCounterType_t,
+    ) -> Result<(nvmlValueType_t, nvmlValue_t), nvmlError_t> {
+        let mut count = 0;
+        device_get_total_ecc_errors(device.clone(), error_type, counter_type, &mut count)?;
+        Ok(unsigned_long_long(count))
+    }
+    unsafe fn detailed_ecc(
+        device: &DeviceRef,
+        error_type: nvmlMemoryErrorType_t,
+        counter_type: nvmlEccCounterType_t,
+        location: fn(&nvmlEccErrorCounts_t) -> u64,
+    ) -> Result<(nvmlValueType_t, nvmlValue_t), nvmlError_t> {
+        let mut counts = mem::zeroed();
+        device_get_detailed_ecc_errors(device.clone(), error_type, counter_type, &mut counts)?;
+        Ok(unsigned_long_long(location(&counts)))
+    }
+    // NVML's single bit errors are corrected, double bit errors are uncorrected
+    const SBE: nvmlMemoryErrorType_t = nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_CORRECTED;
+    const DBE: nvmlMemoryErrorType_t = nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_UNCORRECTED;
+    const VOL: nvmlEccCounterType_t = nvmlEccCounterType_t::NVML_VOLATILE_ECC;
+    const AGG: nvmlEccCounterType_t = nvmlEccCounterType_t::NVML_AGGREGATE_ECC;
+    Some(match field_id {
+        NVML_FI_DEV_ECC_CURRENT | NVML_FI_DEV_ECC_PENDING => {
+            let (mut current, mut pending) = (mem::zeroed(), mem::zeroed());
+            device_get_ecc_mode(device.clone(), &mut current, &mut pending).map(|_| {
+                let mode = if field_id == NVML_FI_DEV_ECC_CURRENT {
+                    current
+                } else {
+                    pending
+                };
+                unsigned_int(mode.0)
+            })
+        }
+        NVML_FI_DEV_ECC_SBE_VOL_TOTAL => total_ecc(device, SBE, VOL),
+        NVML_FI_DEV_ECC_DBE_VOL_TOTAL => total_ecc(device, DBE, VOL),
+        NVML_FI_DEV_ECC_SBE_AGG_TOTAL => total_ecc(device, SBE, AGG),
+        NVML_FI_DEV_ECC_DBE_AGG_TOTAL => total_ecc(device, DBE, AGG),
+        NVML_FI_DEV_ECC_SBE_VOL_L1 => detailed_ecc(device, SBE, VOL, |c| c.l1Cache),
+        NVML_FI_DEV_ECC_DBE_VOL_L1 => detailed_ecc(device, DBE, VOL, |c| c.l1Cache),
+        NVML_FI_DEV_ECC_SBE_VOL_L2 => detailed_ecc(device, SBE, VOL, |c| c.l2Cache),
+        NVML_FI_DEV_ECC_DBE_VOL_L2 => detailed_ecc(device, DBE, VOL, |c| c.l2Cache),
+        NVML_FI_DEV_ECC_SBE_VOL_DEV => detailed_ecc(device, SBE, VOL, |c| c.deviceMemory),
+        NVML_FI_DEV_ECC_DBE_VOL_DEV => detailed_ecc(device, DBE, VOL, |c| c.deviceMemory),
+        NVML_FI_DEV_ECC_SBE_VOL_REG => detailed_ecc(device, SBE, VOL, |c| c.registerFile),
+        NVML_FI_DEV_ECC_DBE_VOL_REG => detailed_ecc(device, DBE, VOL, |c| c.registerFile),
+        NVML_FI_DEV_ECC_SBE_AGG_L1 => detailed_ecc(device, SBE, AGG, |c| c.l1Cache),
+        NVML_FI_DEV_ECC_DBE_AGG_L1 => detailed_ecc(device, DBE, AGG, |c| c.l1Cache),
+        NVML_FI_DEV_ECC_SBE_AGG_L2 => detailed_ecc(device, SBE, AGG, |c| c.l2Cache),
+        NVML_FI_DEV_ECC_DBE_AGG_L2 => detailed_ecc(device, DBE, AGG, |c| c.l2Cache),
+        NVML_FI_DEV_ECC_SBE_AGG_DEV => detailed_ecc(device, SBE, AGG, |c| c.deviceMemory),
+        NVML_FI_DEV_ECC_DBE_AGG_DEV => detailed_ecc(device, DBE, AGG, |c| c.deviceMemory),
+        NVML_FI_DEV_ECC_SBE_AGG_REG => detailed_ecc(device, SBE, AGG, |c| c.registerFile),
+        NVML_FI_DEV_ECC_DBE_AGG_REG => detailed_ecc(device, DBE, AGG, |c| c.registerFile),
+        NVML_FI_DEV_MEMORY_TEMP => device
+            .telemetry
+            .temperature(TemperatureSensor::Memory)
+            .map(unsigned_int),
+        NVML_FI_DEV_POWER_AVERAGE | NVML_FI_DEV_POWER_INSTANT => {
+            device.telemetry.power_usage().map(unsigned_int)
+        }
+        NVML_FI_DEV_PCIE_REPLAY_COUNTER => match device.telemetry.pcie_replay_counter() {
+            Ok(counter) => Ok(unsigned_int(counter as u32)),
+            Err(nvmlError_t::NOT_SUPPORTED) => Ok(unsigned_int(0)),
+            Err(err) => Err(err),
+        },
+        _ => return None,
+    })
+}
+
+pub(crate) fn device_get_handle_by_u_u_i_d(
+    uuid: &CStr,
+    device: &mut cuda_types::nvml::nvmlDevice_t,
+) -> nvmlReturn_t {
+    let uuid = uuid.to_str().map_err(|_| nvmlError_t::NOT_FOUND)?;
+    *device = with_state(|state| {
+        state
+            .devices
+            .iter()
+            .position(|device| device.uuid.as_deref() == Some(uuid))
+            .map(|nvml_index| state.device_handle(nvml_index))
+            .ok_or(nvmlError_t::NOT_FOUND)
+    })?;
+    Ok(())
+}
+
+pub(crate) fn device_get_u_u_i_d(
+    device: DeviceRef,
+    uuid: *mut ::core::ffi::c_char,
+    length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    let device_uuid = device.uuid.as_deref().ok_or(nvmlError_t::NOT_SUPPORTED)?;
+    let device_uuid = CString::new(device_uuid).map_err(|_| nvmlError_t::UNKNOWN)?;
+    crate::impl_common::copy_string(&device_uuid, uuid, length)
+}
+
+pub(crate) fn device_get_index(device: DeviceRef, index: &mut ::core::ffi::c_uint) -> nvmlReturn_t {
+    *index = device.nvml_index;
+    Ok(())
+}
+
+// Same suffix as the device name reported by the CUDA driver
+const PROJECT_SUFFIX: &str = " [ZLUDA]";
+
+pub(crate) fn device_get_name(
+    device: DeviceRef,
+    name: *mut ::core::ffi::c_char,
+    length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    let device_name = device.telemetry.name()?;
+    let device_name = CString::new(format!("{}{}", device_name, PROJECT_SUFFIX))
+        .map_err(|_| nvmlError_t::UNKNOWN)?;
+    crate::impl_common::copy_string(&device_name, name, length)
+}
+
+// Every AMD compute unit since GCN has 64 lanes (4x SIMD16 on GCN/CDNA, 2x SIMD32 on RDNA).
+// RDNA3 can dual-issue, but AMD's own stream processor counts do not double count it, so we
+// don't either
+fn cores_per_compute_unit(_gfx_version: (u64, u64, u64)) -> u32 {
+    64
+}
+
+pub(crate) unsafe fn device_get_num_gpu_cores(
+    device: DeviceRef,
+    num_cores: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    let properties = device.kfd_properties()?;
+    *num_cores =
+        properties.compute_units() as u32 * cores_per_compute_unit(properties.gfx_version());
+    Ok(())
+}
+
+// We map each gfx generation to the NVIDIA architecture of its era with the closest feature set:
+// * gfx8 (Polaris) -> Pascal
+// * gfx900/gfx906 (Vega) -> Volta
+// * gfx908/gfx90a (CDNA1/CDNA2) -> Ampere
+// * gfx94x (CDNA3) -> Hopper
+// * gfx95x (CDNA4) -> Blackwell
+// * gfx101x (RDNA1) -> Turing
+// * gfx103x (RDNA2) -> Ampere
+// * gfx11xx (RDNA3) -> Ada
+// * gfx12xx (RDNA4) -> Blackwell
+fn gfx_version_to_architecture(
+    (major, minor, stepping): (u64, u64, u64),
+) -> nvmlDeviceArchitecture_t {
+    match (major, minor, stepping) {
+        (8, _, _) => NVML_DEVICE_ARCH_PASCAL,
+        (9, 0, 0..=7) => NVML_DEVICE_ARCH_VOLTA,
+        (9, 0, 8..) => NVML_DEVICE_ARCH_AMPERE,
+        (9, 4, _) => NVML_DEVICE_ARCH_HOPPER,
+        (9, 5, _) => NVML_DEVICE_ARCH_BLACKWELL,
+        (10, 0 | 1, _) => NVML_DEVICE_ARCH_TURING,
+        (10, 3, _) => NVML_DEVICE_ARCH_AMPERE,
+        (11, _, _) => NVML_DEVICE_ARCH_ADA,
+        (12, _, _) => NVML_DEVICE_ARCH_BLACKWELL,
+        _ => NVML_DEVICE_ARCH_UNKNOWN,
+    }
+}
+
+pub(crate) unsafe fn device_get_architecture(
+    device: DeviceRef,
+    arch: &mut nvmlDeviceArchitecture_t,
+) -> nvmlReturn_t {
+    *arch = gfx_version_to_architecture(device.kfd_properties()?.gfx_version());
+    Ok(())
+}
+
+pub(crate) fn device_get_brand(_device: DeviceRef, type_: &mut nvmlBrandType_t) -> nvmlReturn_t {
+    *type_ = nvmlBrandType_t::NVML_BRAND_UNKNOWN;
+    Ok(())
+}
+
+pub(crate) fn device_get_vbios_version(
+    device: DeviceRef,
+    version: *mut ::core::ffi::c_char,
+    length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    let vbios_version =
+        CString::new(device.telemetry.vbios_version()?).map_err(|_| nvmlError_t::UNKNOWN)?;
+    crate::impl_common::copy_string(&vbios_version, version, length)
+}
+
+pub(crate) fn device_get_board_part_number(
+    device: DeviceRef,
+    part_number: *mut ::core::ffi::c_char,
+    length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    let product_number = sysfs::product_number(device.telemetry.device_dir()?)?;
+    let product_number = CString::new(product_number).map_err(|_| nvmlError_t::UNKNOWN)?;
+    crate::impl_common::copy_string(&product_number, part_number, length)
+}
+
+pub(crate) fn device_get_cuda_compute_capability(
+    _device: DeviceRef,
+    major: &mut ::core::ffi::c_int,
+    minor: &mut ::core::ffi::c_int,
+) -> nvmlReturn_t {
+    *major = zluda_common::COMPUTE_CAPABILITY_MAJOR;
+    *minor = zluda_common::COMPUTE_CAPABILITY_MINOR;
+    nvmlReturn_t::SUCCESS
+}
+
+pub(crate) fn device_get_performance_state(
+    device: DeviceRef,
+    p_state: &mut nvmlPstates_t,
+) -> nvmlReturn_t {
+    *p_state = device.telemetry.performance_state()?;
+    Ok(())
+}
+
+// Deprecated alias of nvmlDeviceGetPerformanceState
+pub(crate) fn device_get_power_state(
+    device: DeviceRef,
+    p_state: &mut nvmlPstates_t,
+) -> nvmlReturn_t {
+    device_get_performance_state(device, p_state)
+}
+
+// There's no persistence mode on amdgpu, the driver always keeps the device initialized
+pub(crate) fn device_get_persistence_mode(
+    _device: DeviceRef,
+    mode: &mut nvmlEnableState_t,
+) -> nvmlReturn_t {
+    *mode = nvmlEnableState_t::NVML_FEATURE_ENABLED;
+    Ok(())
+}
+
+pub(crate) fn device_set_persistence_mode(
+    _device: DeviceRef,
+    _mode: nvmlEnableState_t,
+) -> nvmlReturn_t {
+    Ok(())
+}
+
+// Compute modes are an NVIDIA driver feature, every process can always use an AMD GPU. Like on
+// NVIDIA GPUs, only privileged users can change the mode, here that is opted into with
+// ZLUDA_NVML_SETTABLE_COMPUTE_MODE
+pub(crate) fn device_get_compute_mode(
+    device: DeviceRef,
+    mode: &mut nvmlComputeMode_t,
+) -> nvmlReturn_t {
+    *mode = nvmlComputeMode_t(device.settings.compute_mode.load(Ordering::Relaxed));
+    Ok(())
+}
+
+pub(crate) fn device_set_compute_mode(device: DeviceRef, mode: nvmlComputeMode_t) -> nvmlReturn_t {
+    set_compute_mode(&device, mode, |name| env::var_os(name))
+}
+
+fn set_compute_mode(
+    device: &Device,
+    mode: nvmlComputeMode_t,
+    var: impl FnOnce(&str) -> Option<OsString>,
+) -> nvmlReturn_t {
+    if mode.0 >= nvmlComputeMode_t::NVML_COMPUTEMODE_COUNT.0 {
+        return nvmlReturn_t::ERROR_INVALID_ARGUMENT;
+    }
+    if !crate::impl_common::is_compute_mode_settable(var) {
+        return nvmlReturn_t::ERROR_NO_PERMISSION;
+    }
+    device
+        .settings
+        .compute_mode
+        .store(mode.0, Ordering::Relaxed);
+    Ok(())
+}
+
+// We don't collect per-process accounting statistics: accounting mode can be switched on and
+// off, but there is never a process to report on. This is what NVIDIA GPUs report before the
+// first process with accounting enabled terminates
+pub(crate) fn device_get_accounting_mode(
+    device: DeviceRef,
+    mode: &mut nvmlEnableState_t,
+) -> nvmlReturn_t {
+    *mode = if device.settings.accounting_mode.load(Ordering::Relaxed) {
+        nvmlEnableState_t::NVML_FEATURE_ENABLED
+    } else {
+        nvmlEnableState_t::NVML_FEATURE_DISABLED
+    };
+    Ok(())
+}
+
+pub(crate) fn device_set_accounting_mode(
+    device: DeviceRef,
+    mode: nvmlEnableState_t,
+) -> nvmlReturn_t {
+    let enabled = match mode {
+        nvmlEnableState_t::NVML_FEATURE_ENABLED => true,
+        nvmlEnableState_t::NVML_FEATURE_DISABLED => false,
+        _ => return nvmlReturn_t::ERROR_INVALID_ARGUMENT,
+    };
+    device
+        .settings
+        .accounting_mode
+        .store(enabled, Ordering::Relaxed);
+    Ok(())
+}
+
+pub(crate) fn device_get_accounting_pids(
+    _device: DeviceRef,
+    count: &mut ::core::ffi::c_uint,
+    _pids: Option<&mut ::core::ffi::c_uint>,
+) -> nvmlReturn_t {
+    *count = 0;
+    Ok(())
+}
+
+pub(crate) fn device_get_accounting_stats(
+    _device: DeviceRef,
+    _pid: ::core::ffi::c_uint,
+    _stats: &mut nvmlAccountingStats_t,
+) -> nvmlReturn_t {
+    nvmlReturn_t::ERROR_NOT_FOUND
+}
+
+pub(crate) fn device_clear_accounting_pids(_device: DeviceRef) -> nvmlReturn_t {
+    Ok(())
+}
+
+// MIG is reported as supported, but disabled: this is what consumer NVIDIA GPUs do and what
+// client code (e.g. Kubernetes GPU operator) expects. Returning ERROR_NOT_SUPPORTED from
+// nvmlDeviceGetMigMode is treated as a hard error by some of them
+pub(crate) fn device_get_mig_mode(
+    _device: DeviceRef,
+    current_mode: &mut ::core::ffi::c_uint,
+    pending_mode: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *current_mode = NVML_DEVICE_MIG_DISABLE;
+    *pending_mode = NVML_DEVICE_MIG_DISABLE;
+    Ok(())
+}
+
+pub(crate) fn device_set_mig_mode(
+    _device: DeviceRef,
+    _mode: ::core::ffi::c_uint,
+    activation_status: &mut nvmlReturn_t,
+) -> nvmlReturn_t {
+    *activation_status = nvmlReturn_t::ERROR_NOT_SUPPORTED;
+    nvmlReturn_t::ERROR_NOT_SUPPORTED
+}
+
+pub(crate) fn device_get_max_mig_device_count(
+    _device: DeviceRef,
+    count: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *count = 0;
+    Ok(())
+}
+
+pub(crate) fn device_get_mig_device_handle_by_index(
+    _device: DeviceRef,
+    _index: ::core::ffi::c_uint,
+    _mig_device: &mut nvmlDevice_t,
+) -> nvmlReturn_t {
+    nvmlReturn_t::ERROR_NOT_FOUND
+}
+
+pub(crate) fn device_is_mig_device_handle(
+    _device: DeviceRef,
+    is_mig_device: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *is_mig_device = 0;
+    Ok(())
+}
+
+pub(crate) fn device_get_temperature(
+    device: DeviceRef,
+    sensor_type: nvmlTemperatureSensors_t,
+    temp: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    if sensor_type != nvmlTemperatureSensors_t::NVML_TEMPERATURE_GPU {
+        return nvmlReturn_t::ERROR_INVALID_ARGUMENT;
+    }
+    *temp = device.telemetry.temperature(TemperatureSensor::Gpu)?;
+    Ok(())
+}
+
+pub(crate) fn device_get_power_usage(
+    device: DeviceRef,
+    power: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *power = device.telemetry.power_usage()?;
+    Ok(())
+}
+
+pub(crate) fn device_get_clock_info(
+    device: DeviceRef,
+    type_: nvmlClockType_t,
+    clock: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *clock = device.telemetry.clock(type_)?;
+    Ok(())
+}
+
+pub(crate) fn device_get_utilization_rates(
+    device: DeviceRef,
+    utilization: &mut nvmlUtilization_t,
+) -> nvmlReturn_t {
+    *utilization = device.telemetry.utilization()?;
+    Ok(())
+}
+
+pub(crate) fn device_get_memory_info(device: DeviceRef, memory: &mut nvmlMemory_t) -> nvmlReturn_t {
+    *memory = device.telemetry.vram()?;
+    Ok(())
+}
+
+pub(crate) fn device_get_pci_info_v3(device: DeviceRef, pci: &mut nvmlPciInfo_t) -> nvmlReturn_t {
+    fn write_c_string<const N: usize>(target: &mut [::core::ffi::c_char; N], value: &str) {
+        *target = [0; N];
+        for (target, byte) in target[..N - 1].iter_mut().zip(value.bytes()) {
+            *target = byte as ::core::ffi::c_char;
+        }
     }
+    let info = device.telemetry.pci_info()?;
+    *pci = unsafe { mem::zeroed() };
+    pci.domain = info.domain;
+    pci.bus = info.bus;
+    pci.device = info.device;
+    pci.pciDeviceId = ((info.device_id as u32) << 16) | info.vendor_id as u32;
+    pci.pciSubSystemId = ((info.subsystem_id as u32) << 16) | info.subsystem_vendor_id as u32;
+    write_c_string(
+        &mut pci.busIdLegacy,
+        &format!(
+            "{:04X}:{:02X}:{:02X}.{}",
+            info.domain, info.bus, info.device, info.function
+        ),
+    );
+    write_c_string(
+        &mut pci.busId,
+        &format!(
+            "{:08X}:{:02X}:{:02X}.{}",
+            info.domain, info.bus, info.device, info.function
+        ),
+    );
+    Ok(())
+}
+
+pub(crate) unsafe fn device_get_compute_running_processes_v3(
+    device: DeviceRef,
+    info_count: &mut ::core::ffi::c_uint,
+    infos: Option<&mut nvmlProcessInfo_t>,
+) -> nvmlReturn_t {
+    let processes = device.telemetry.process_memory()?;
+    let capacity = *info_count as usize;
+    *info_count = processes.len() as u32;
+    if processes.is_empty() {
+        return Ok(());
+    }
+    let infos = match infos {
+        Some(infos) if capacity >= processes.len() => {
+            std::slice::from_raw_parts_mut(infos as *mut nvmlProcessInfo_t, processes.len())
+        }
+        _ => return nvmlReturn_t::ERROR_INSUFFICIENT_SIZE,
+    };
+    for (info, ProcessMemory { pid, used_memory }) in infos.iter_mut().zip(processes) {
+        *info = nvmlProcessInfo_t {
+            pid,
+            usedGpuMemory: used_memory,
+            // Reported when MIG is disabled
+            gpuInstanceId: u32::MAX,
+            computeInstanceId: u32::MAX,
+        };
+    }
+    Ok(())
+}
+
+pub(crate) fn device_get_b_a_r1_memory_info(
+    device: DeviceRef,
+    bar1_memory: &mut nvmlBAR1Memory_t,
+) -> nvmlReturn_t {
+    *bar1_memory = sysfs::bar1_memory_info(device.telemetry.device_dir()?)?;
+    Ok(())
+}
+
+pub(crate) fn device_get_memory_bus_width(
+    device: DeviceRef,
+    bus_width: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    let node_id = device.telemetry.kfd_node_id()?;
+    *bus_width = sysfs::memory_bus_width(&sysfs::kfd_mem_bank_properties_path(node_id))?;
+    Ok(())
+}
+
+pub(crate) fn device_get_curr_pcie_link_generation(
+    device: DeviceRef,
+    curr_link_gen: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *curr_link_gen =
+        sysfs::pcie_link_generation(device.telemetry.device_dir()?, sysfs::PcieLink::Current)?;
+    Ok(())
+}
+
+pub(crate) fn device_get_max_pcie_link_generation(
+    device: DeviceRef,
+    max_link_gen: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *max_link_gen =
+        sysfs::pcie_link_generation(device.telemetry.device_dir()?, sysfs::PcieLink::Max)?;
+    Ok(())
+}
+
+pub(crate) fn device_get_curr_pcie_link_width(
+    device: DeviceRef,
+    curr_link_width: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *curr_link_width =
+        sysfs::pcie_link_width(device.telemetry.device_dir()?, sysfs::PcieLink::Current)?;
+    Ok(())
+}
+
+pub(crate) fn device_get_max_pcie_link_width(
+    device: DeviceRef,
+    max_link_width: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *max_link_width = sysfs::pcie_link_width(device.telemetry.device_dir()?, sysfs::PcieLink::Max)?;
+    Ok(())
+}
+
+pub(crate) fn device_get_pcie_throughput(
+    device: DeviceRef,
+    counter: nvmlPcieUtilCounter_t,
+    value: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *value = sysfs::pcie_throughput(device.telemetry.device_dir()?, counter)?;
+    Ok(())
+}
+
+// Not every GPU counts replays, the ones that don't never had one as far as clients are concerned
+pub(crate) fn device_get_pcie_replay_counter(
+    device: DeviceRef,
+    value: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    *value = match device.telemetry.pcie_replay_counter() {
+        Ok(counter) => counter as u32,
+        Err(nvmlError_t::NOT_SUPPORTED) => 0,
+        Err(err) => return Err(err),
+    };
     Ok(())
 }
 
-unsafe fn get_field_value(field: &mut nvmlFieldValue_st) -> Result<(), nvmlError_t> {
-    *field = mem::zeroed();
-    field.nvmlReturn = nvmlReturn_t::ERROR_NOT_SUPPORTED;
+pub(crate) fn device_get_ecc_mode(
+    device: DeviceRef,
+    current: &mut nvmlEnableState_t,
+    pending: &mut nvmlEnableState_t,
+) -> nvmlReturn_t {
+    let mode = if sysfs::ras_counters(device.telemetry.device_dir()?)?.enabled {
+        nvmlEnableState_t::NVML_FEATURE_ENABLED
+    } else {
+        nvmlEnableState_t::NVML_FEATURE_DISABLED
+    };
+    *current = mode;
+    *pending = mode;
+    Ok(())
+}
+
+pub(crate) fn device_get_total_ecc_errors(
+    device: DeviceRef,
+    error_type: nvmlMemoryErrorType_t,
+    counter_type: nvmlEccCounterType_t,
+    ecc_counts: &mut ::core::ffi::c_ulonglong,
+) -> nvmlReturn_t {
+    *ecc_counts = sysfs::ras_counters(device.telemetry.device_dir()?)?
+        .total()
+        .get(error_type, counter_type)?;
+    Ok(())
+}
+
+pub(crate) fn device_get_detailed_ecc_errors(
+    device: DeviceRef,
+    error_type: nvmlMemoryErrorType_t,
+    counter_type: nvmlEccCounterType_t,
+    ecc_counts: &mut nvmlEccErrorCounts_t,
+) -> nvmlReturn_t {
+    *ecc_counts =
+        sysfs::ras_counters(device.telemetry.device_dir()?)?.detailed(error_type, counter_type)?;
+    Ok(())
+}
+
+fn query_link(device1: &Device, device2: &Device) -> Result<Option<Link>, nvmlError_t> {
+    if device1.index == device2.index {
+        return Ok(None);
+    }
+    let PeerLink {
+        kind,
+        hops,
+        p2p_accessible,
+    } = device1.telemetry.peer_link(device2.index)?;
+    Ok(Some(Link {
+        kind,
+        hops,
+        same_numa_node: device1.telemetry.numa_node()? == device2.telemetry.numa_node()?,
+        p2p_accessible,
+    }))
+}
+
+pub(crate) fn device_get_topology_common_ancestor(
+    device1: DeviceRef,
+    device2: DeviceRef,
+    path_info: &mut nvmlGpuTopologyLevel_t,
+) -> nvmlReturn_t {
+    *path_info = topology::common_ancestor(query_link(&device1, &device2)?);
+    Ok(())
+}
+
+pub(crate) fn device_get_p2_p_status(
+    device1: DeviceRef,
+    device2: DeviceRef,
+    p2p_index: nvmlGpuP2PCapsIndex_t,
+    p2p_status: &mut nvmlGpuP2PStatus_t,
+) -> nvmlReturn_t {
+    *p2p_status = topology::p2p_status(query_link(&device1, &device2)?, p2p_index)?;
+    Ok(())
+}
+
+pub(crate) fn device_register_events(
+    _device: DeviceRef,
+    event_types: ::core::ffi::c_ulonglong,
+    set: EventSetRef,
+) -> nvmlReturn_t {
+    set.register(event_types)
+}
+
+pub(crate) fn device_get_supported_event_types(
+    _device: DeviceRef,
+    event_types: &mut ::core::ffi::c_ulonglong,
+) -> nvmlReturn_t {
+    *event_types = 0;
     Ok(())
 }
 
 pub(crate) unsafe fn device_get_gpu_fabric_info(
-    _device: &Device,
+    _device: DeviceRef,
     gpu_fabric_info: &mut cuda_types::nvml::nvmlGpuFabricInfo_t,
 ) -> nvmlReturn_t {
     *gpu_fabric_info = mem::zeroed();
@@ -72,6 +996,1015 @@ pub(crate) fn device_get_handle_by_index_v2(
     index: ::core::ffi::c_uint,
     device: &mut cuda_types::nvml::nvmlDevice_t,
 ) -> nvmlReturn_t {
-    *device = Device { _index: index }.wrap();
+    *device = with_state(|state| {
+        let index = index as usize;
+        if index < state.devices.len() {
+            Ok(state.device_handle(index))
+        } else {
+            Err(nvmlError_t::INVALID_ARGUMENT)
+        }
+    })?;
     nvmlReturn_t::SUCCESS
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Device, DeviceRef, DeviceSettings};
+    use crate::impl_common::GLOBAL_STATE_TEST_LOCK;
+    use crate::telemetry::{MockTelemetry, PciInfo, PeerLink, ProcessMemory};
+    use crate::topology::LinkKind;
+    use cuda_types::nvml::*;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, MutexGuard,
+        },
+        thread,
+    };
+
+    fn lock() -> MutexGuard<'static, ()> {
+        GLOBAL_STATE_TEST_LOCK
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn device_count() -> Result<u32, nvmlError_t> {
+        let mut count = 0;
+        unsafe { crate::nvmlDeviceGetCount_v2(&mut count) }?;
+        Ok(count)
+    }
+
+    #[test]
+    fn calls_before_init_fail() {
+        let _lock = lock();
+        assert_eq!(device_count(), Err(nvmlError_t::UNINITIALIZED));
+        let mut device = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            unsafe { crate::nvmlDeviceGetHandleByIndex_v2(0, &mut device) },
+            nvmlReturn_t::ERROR_UNINITIALIZED
+        );
+        assert_eq!(
+            unsafe { crate::nvmlShutdown() },
+            nvmlReturn_t::ERROR_UNINITIALIZED
+        );
+    }
+
+    #[test]
+    fn init_is_reference_counted() {
+        let _lock = lock();
+        assert_eq!(
+            unsafe { crate::nvmlInitWithFlags(NVML_INIT_FLAG_NO_GPUS) },
+            nvmlReturn_t::SUCCESS
+        );
+        let count = device_count().unwrap();
+        assert_eq!(
+            unsafe { crate::nvmlInitWithFlags(NVML_INIT_FLAG_NO_GPUS) },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+        assert_eq!(device_count(), Ok(count));
+        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+        assert_eq!(device_count(), Err(nvmlError_t::UNINITIALIZED));
+    }
+
+    #[test]
+    fn device_handles_are_rejected_after_shutdown() {
+        let _lock = lock();
+        assert_eq!(
+            unsafe { crate::nvmlInitWithFlags(NVML_INIT_FLAG_NO_GPUS) },
+            nvmlReturn_t::SUCCESS
+        );
+        if device_count().unwrap() == 0 {
+            assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+            return;
+        }
+        let mut device = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            unsafe { crate::nvmlDeviceGetHandleByIndex_v2(0, &mut device) },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+        let (mut major, mut minor) = (0, 0);
+        assert_eq!(
+            unsafe { crate::nvmlDeviceGetCudaComputeCapability(device, &mut major, &mut minor) },
+            nvmlReturn_t::ERROR_UNINITIALIZED
+        );
+    }
+
+    #[test]
+    fn mig_is_disabled() {
+        let _lock = lock();
+        assert_eq!(
+            unsafe { crate::nvmlInitWithFlags(NVML_INIT_FLAG_NO_GPUS) },
+            nvmlReturn_t::SUCCESS
+        );
+        for index in 0..device_count().unwrap() {
+            let mut device = unsafe { std::mem::zeroed() };
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetHandleByIndex_v2(index, &mut device) },
+                nvmlReturn_t::SUCCESS
+            );
+            let (mut current_mode, mut pending_mode) = (u32::MAX, u32::MAX);
+            assert_eq!(
+                unsafe {
+                    crate::nvmlDeviceGetMigMode(device, &mut current_mode, &mut pending_mode)
+                },
+                nvmlReturn_t::SUCCESS
+            );
+            assert_eq!(current_mode, NVML_DEVICE_MIG_DISABLE);
+            assert_eq!(pending_mode, NVML_DEVICE_MIG_DISABLE);
+            let mut count = u32::MAX;
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetMaxMigDeviceCount(device, &mut count) },
+                nvmlReturn_t::SUCCESS
+            );
+            assert_eq!(count, 0);
+            let mut mig_device = unsafe { std::mem::zeroed() };
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetMigDeviceHandleByIndex(device, 0, &mut mig_device) },
+                nvmlReturn_t::ERROR_NOT_FOUND
+            );
+            let mut is_mig_device = u32::MAX;
+            assert_eq!(
+                unsafe { crate::nvmlDeviceIsMigDeviceHandle(device, &mut is_mig_device) },
+                nvmlReturn_t::SUCCESS
+            );
+            assert_eq!(is_mig_device, 0);
+            let mut activation_status = nvmlReturn_t::SUCCESS;
+            assert_eq!(
+                unsafe {
+                    crate::nvmlDeviceSetMigMode(
+                        device,
+                        NVML_DEVICE_MIG_ENABLE,
+                        &mut activation_status,
+                    )
+                },
+                nvmlReturn_t::ERROR_NOT_SUPPORTED
+            );
+        }
+        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+    }
+
+    #[test]
+    fn process_name() {
+        let mut name = [0; NVML_DEVICE_NAME_V2_BUFFER_SIZE as usize];
+        assert_eq!(
+            unsafe {
+                crate::nvmlSystemGetProcessName(
+                    std::process::id(),
+                    name.as_mut_ptr(),
+                    name.len() as u32,
+                )
+            },
+            nvmlReturn_t::SUCCESS
+        );
+        let name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+        assert!(!name.is_empty());
+        let mut short = [0; 1];
+        assert_eq!(
+            unsafe { crate::nvmlSystemGetProcessName(std::process::id(), short.as_mut_ptr(), 1) },
+            nvmlReturn_t::ERROR_INSUFFICIENT_SIZE
+        );
+    }
+
+    fn mock_device(telemetry: MockTelemetry) -> DeviceRef {
+        DeviceRef(Arc::new(Device {
+            index: 0,
+            nvml_index: 0,
+            uuid: None,
+            telemetry: Box::new(telemetry),
+            settings: DeviceSettings::default(),
+        }))
+    }
+
+    #[test]
+    fn telemetry_not_supported() {
+        let device = mock_device(MockTelemetry::default());
+        let mut value = 0;
+        assert_eq!(
+            super::device_get_temperature(
+                device.clone(),
+                nvmlTemperatureSensors_t::NVML_TEMPERATURE_GPU,
+                &mut value
+            ),
+            nvmlReturn_t::ERROR_NOT_SUPPORTED
+        );
+        assert_eq!(
+            super::device_get_power_usage(device.clone(), &mut value),
+            nvmlReturn_t::ERROR_NOT_SUPPORTED
+        );
+        let mut memory = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            super::device_get_memory_info(device.clone(), &mut memory),
+            nvmlReturn_t::ERROR_NOT_SUPPORTED
+        );
+    }
+
+    #[test]
+    fn telemetry_values() {
+        let device = mock_device(MockTelemetry {
+            gpu_temperature: Some(65),
+            power_usage: Some(123_456),
+            graphics_clock: Some(2100),
+            ..Default::default()
+        });
+        let mut value = 0;
+        assert_eq!(
+            super::device_get_temperature(
+                device.clone(),
+                nvmlTemperatureSensors_t::NVML_TEMPERATURE_GPU,
+                &mut value
+            ),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(value, 65);
+        assert_eq!(
+            super::device_get_temperature(
+                device.clone(),
+                nvmlTemperatureSensors_t::NVML_TEMPERATURE_COUNT,
+                &mut value
+            ),
+            nvmlReturn_t::ERROR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            super::device_get_power_usage(device.clone(), &mut value),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(value, 123_456);
+        assert_eq!(
+            super::device_get_clock_info(
+                device.clone(),
+                nvmlClockType_t::NVML_CLOCK_SM,
+                &mut value
+            ),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(value, 2100);
+    }
+
+    #[test]
+    fn pci_info() {
+        let device = mock_device(MockTelemetry {
+            pci_info: Some(PciInfo {
+                domain: 0,
+                bus: 0x3b,
+                device: 0,
+                function: 0,
+                vendor_id: 0x1002,
+                device_id: 0x744c,
+                subsystem_vendor_id: 0x1002,
+                subsystem_id: 0x0e3b,
+            }),
+            ..Default::default()
+        });
+        let mut pci = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            super::device_get_pci_info_v3(device.clone(), &mut pci),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(pci.bus, 0x3b);
+        assert_eq!(pci.pciDeviceId, 0x744c1002);
+        assert_eq!(pci.pciSubSystemId, 0x0e3b1002);
+        let bus_id = unsafe { std::ffi::CStr::from_ptr(pci.busId.as_ptr()) };
+        assert_eq!(bus_id, c"00000000:3B:00.0");
+        let bus_id_legacy = unsafe { std::ffi::CStr::from_ptr(pci.busIdLegacy.as_ptr()) };
+        assert_eq!(bus_id_legacy, c"0000:3B:00.0");
+    }
+
+    #[test]
+    fn vbios_version_and_performance_state() {
+        let device = mock_device(MockTelemetry {
+            vbios_version: Some("113-D7090100-102".to_string()),
+            performance_state: Some(nvmlPstates_t::NVML_PSTATE_2),
+            ..Default::default()
+        });
+        let mut version = [0; NVML_DEVICE_VBIOS_VERSION_BUFFER_SIZE as usize];
+        assert_eq!(
+            super::device_get_vbios_version(
+                device.clone(),
+                version.as_mut_ptr(),
+                version.len() as u32
+            ),
+            nvmlReturn_t::SUCCESS
+        );
+        let version = unsafe { std::ffi::CStr::from_ptr(version.as_ptr()) };
+        assert_eq!(version, c"113-D7090100-102");
+        let mut p_state = nvmlPstates_t::NVML_PSTATE_UNKNOWN;
+        assert_eq!(
+            super::device_get_power_state(device.clone(), &mut p_state),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(p_state, nvmlPstates_t::NVML_PSTATE_2);
+        let unsupported = mock_device(MockTelemetry::default());
+        assert_eq!(
+            super::device_get_performance_state(unsupported, &mut p_state),
+            nvmlReturn_t::ERROR_NOT_SUPPORTED
+        );
+    }
+
+    #[test]
+    fn field_values_from_telemetry() {
+        let device = mock_device(MockTelemetry {
+            memory_temperature: Some(71),
+            power_usage: Some(210_000),
+            pcie_replay_counter: Some(4),
+            ..Default::default()
+        });
+        let mut values = [
+            NVML_FI_DEV_MEMORY_TEMP,
+            NVML_FI_DEV_POWER_INSTANT,
+            NVML_FI_DEV_PCIE_REPLAY_COUNTER,
+            NVML_FI_DEV_ECC_CURRENT,
+            NVML_FI_DEV_NVSWITCH_CONNECTED_LINK_COUNT,
+        ]
+        .map(|field_id| {
+            let mut value: nvmlFieldValue_t = unsafe { std::mem::zeroed() };
+            value.fieldId = field_id;
+            value
+        });
+        assert_eq!(
+            unsafe { super::device_get_field_values(device, values.len() as i32, &mut values[0]) },
+            nvmlReturn_t::SUCCESS
+        );
+        let results = values.map(|value| value.nvmlReturn);
+        assert_eq!(
+            results,
+            [
+                nvmlReturn_t::SUCCESS,
+                nvmlReturn_t::SUCCESS,
+                nvmlReturn_t::SUCCESS,
+                nvmlReturn_t::ERROR_NOT_SUPPORTED,
+                nvmlReturn_t::ERROR_NOT_SUPPORTED,
+            ]
+        );
+        assert_eq!(unsafe { values[0].value.uiVal }, 71);
+        assert_eq!(unsafe { values[1].value.uiVal }, 210_000);
+        assert_eq!(unsafe { values[2].value.uiVal }, 4);
+    }
+
+    #[test]
+    fn pcie_replay_counter() {
+        let device = mock_device(MockTelemetry {
+            pcie_replay_counter: Some(3),
+            ..Default::default()
+        });
+        let mut value = 0;
+        assert_eq!(
+            super::device_get_pcie_replay_counter(device.clone(), &mut value),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(value, 3);
+        let uncounted = mock_device(MockTelemetry::default());
+        assert_eq!(
+            super::device_get_pcie_replay_counter(uncounted, &mut value),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(value, 0);
+    }
+
+    #[test]
+    fn sysfs_queries_read_the_device_dir() {
+        let device = mock_device(MockTelemetry {
+            device_dir: Some(
+                std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("src/test/sysfs/ras_enabled/device"),
+            ),
+            ..Default::default()
+        });
+        let mut part_number = [0; NVML_DEVICE_PART_NUMBER_BUFFER_SIZE as usize];
+        assert_eq!(
+            super::device_get_board_part_number(
+                device.clone(),
+                part_number.as_mut_ptr(),
+                part_number.len() as u32
+            ),
+            nvmlReturn_t::SUCCESS
+        );
+        let part_number = unsafe { std::ffi::CStr::from_ptr(part_number.as_ptr()) };
+        assert_eq!(part_number, c"113-D7090100-XTX");
+        let mut bar1 = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            super::device_get_b_a_r1_memory_info(device.clone(), &mut bar1),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(bar1.bar1Total, 34359738368);
+        let (mut current, mut pending) = unsafe { (std::mem::zeroed(), std::mem::zeroed()) };
+        assert_eq!(
+            super::device_get_ecc_mode(device.clone(), &mut current, &mut pending),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(current, nvmlEnableState_t::NVML_FEATURE_ENABLED);
+        let without_dir = mock_device(MockTelemetry::default());
+        assert_eq!(
+            super::device_get_b_a_r1_memory_info(without_dir, &mut bar1),
+            nvmlReturn_t::ERROR_NOT_SUPPORTED
+        );
+    }
+
+    // GPUs 0 and 1 share an XGMI hive, GPU 2 is a PCIe card on the other socket
+    #[test]
+    fn topology_from_peer_links() {
+        let xgmi = PeerLink {
+            kind: LinkKind::Xgmi,
+            hops: 1,
+            p2p_accessible: true,
+        };
+        let pcie = PeerLink {
+            kind: LinkKind::Pcie,
+            hops: 5,
+            p2p_accessible: false,
+        };
+        let devices = [
+            (0, vec![(1, xgmi), (2, pcie)]),
+            (0, vec![(0, xgmi), (2, pcie)]),
+            (1, vec![(0, pcie), (1, pcie)]),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(index, (numa_node, peer_links))| {
+            DeviceRef(Arc::new(Device::mock(
+                index as u32,
+                &format!("GPU-{index:08x}-0000-0000-0000-000000000000"),
+                MockTelemetry {
+                    numa_node: Some(numa_node),
+                    peer_links,
+                    ..Default::default()
+                },
+            )))
+        })
+        .collect::<Vec<_>>();
+        let common_ancestor = |device1: usize, device2: usize| {
+            let mut level = nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_INTERNAL;
+            assert_eq!(
+                super::device_get_topology_common_ancestor(
+                    devices[device1].clone(),
+                    devices[device2].clone(),
+                    &mut level
+                ),
+                nvmlReturn_t::SUCCESS
+            );
+            level
+        };
+        assert_eq!(
+            common_ancestor(0, 0),
+            nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_INTERNAL
+        );
+        assert_eq!(
+            common_ancestor(0, 1),
+            nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_SINGLE
+        );
+        assert_eq!(
+            common_ancestor(0, 2),
+            nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_SYSTEM
+        );
+        let p2p_status = |device1: usize, device2: usize, index| {
+            let mut status = nvmlGpuP2PStatus_t::NVML_P2P_STATUS_UNKNOWN;
+            assert_eq!(
+                super::device_get_p2_p_status(
+                    devices[device1].clone(),
+                    devices[device2].clone(),
+                    index,
+                    &mut status
+                ),
+                nvmlReturn_t::SUCCESS
+            );
+            status
+        };
+        assert_eq!(
+            p2p_status(1, 0, nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_NVLINK),
+            nvmlGpuP2PStatus_t::NVML_P2P_STATUS_OK
+        );
+        assert_eq!(
+            p2p_status(2, 0, nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_READ),
+            nvmlGpuP2PStatus_t::NVML_P2P_STATUS_CHIPSET_NOT_SUPPORTED
+        );
+        let mut level = nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_INTERNAL;
+        assert_eq!(
+            super::device_get_topology_common_ancestor(
+                mock_device(MockTelemetry::default()),
+                devices[1].clone(),
+                &mut level
+            ),
+            nvmlReturn_t::ERROR_NOT_SUPPORTED
+        );
+    }
+
+    #[test]
+    fn compute_running_processes() {
+        let device = mock_device(MockTelemetry {
+            processes: Some(vec![
+                ProcessMemory {
+                    pid: 100,
+                    used_memory: 1 << 30,
+                },
+                ProcessMemory {
+                    pid: 200,
+                    used_memory: 1 << 20,
+                },
+            ]),
+            ..Default::default()
+        });
+        let mut count = 0;
+        assert_eq!(
+            unsafe {
+                super::device_get_compute_running_processes_v3(device.clone(), &mut count, None)
+            },
+            nvmlReturn_t::ERROR_INSUFFICIENT_SIZE
+        );
+        assert_eq!(count, 2);
+        let mut infos = [unsafe { std::mem::zeroed::<nvmlProcessInfo_t>() }; 2];
+        assert_eq!(
+            unsafe {
+                super::device_get_compute_running_processes_v3(
+                    device.clone(),
+                    &mut count,
+                    Some(&mut infos[0]),
+                )
+            },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(count, 2);
+        assert_eq!((infos[0].pid, infos[0].usedGpuMemory), (100, 1 << 30));
+        assert_eq!((infos[1].pid, infos[1].usedGpuMemory), (200, 1 << 20));
+        let idle = mock_device(MockTelemetry {
+            processes: Some(Vec::new()),
+            ..Default::default()
+        });
+        let mut count = 0;
+        assert_eq!(
+            unsafe {
+                super::device_get_compute_running_processes_v3(idle.clone(), &mut count, None)
+            },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(count, 0);
+    }
+
+    // What Slurm's NVML GPU plugin does when it sets up a node for a job and when the job ends
+    #[test]
+    fn slurm_node_setup() {
+        let _lock = lock();
+        let device = mock_device(MockTelemetry::default());
+        let mut mode = nvmlComputeMode_t::NVML_COMPUTEMODE_PROHIBITED;
+        assert_eq!(
+            super::device_get_compute_mode(device.clone(), &mut mode),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(mode, nvmlComputeMode_t::NVML_COMPUTEMODE_DEFAULT);
+        assert_eq!(
+            super::set_compute_mode(
+                &device,
+                nvmlComputeMode_t::NVML_COMPUTEMODE_EXCLUSIVE_PROCESS,
+                |_| None
+            ),
+            nvmlReturn_t::ERROR_NO_PERMISSION
+        );
+        let settable = |name: &str| {
+            (name == crate::impl_common::SETTABLE_COMPUTE_MODE_ENV).then(|| "1".into())
+        };
+        assert_eq!(
+            super::set_compute_mode(
+                &device,
+                nvmlComputeMode_t::NVML_COMPUTEMODE_EXCLUSIVE_PROCESS,
+                settable
+            ),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(
+            super::set_compute_mode(&device, nvmlComputeMode_t::NVML_COMPUTEMODE_COUNT, settable),
+            nvmlReturn_t::ERROR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            super::device_get_compute_mode(device.clone(), &mut mode),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(mode, nvmlComputeMode_t::NVML_COMPUTEMODE_EXCLUSIVE_PROCESS);
+        let mut accounting = nvmlEnableState_t::NVML_FEATURE_ENABLED;
+        assert_eq!(
+            super::device_get_accounting_mode(device.clone(), &mut accounting),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(accounting, nvmlEnableState_t::NVML_FEATURE_DISABLED);
+        assert_eq!(
+            super::device_set_accounting_mode(
+                device.clone(),
+                nvmlEnableState_t::NVML_FEATURE_ENABLED
+            ),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(
+            super::device_get_accounting_mode(device.clone(), &mut accounting),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(accounting, nvmlEnableState_t::NVML_FEATURE_ENABLED);
+        assert_eq!(
+            super::device_clear_accounting_pids(device.clone()),
+            nvmlReturn_t::SUCCESS
+        );
+        // Job ends: collect the statistics of its processes
+        let mut pids = [0; 16];
+        let mut count = pids.len() as u32;
+        assert_eq!(
+            super::device_get_accounting_pids(device.clone(), &mut count, Some(&mut pids[0])),
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(count, 0);
+        let mut stats = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            super::device_get_accounting_stats(device.clone(), std::process::id(), &mut stats),
+            nvmlReturn_t::ERROR_NOT_FOUND
+        );
+        assert_eq!(
+            super::device_set_accounting_mode(
+                device.clone(),
+                nvmlEnableState_t::NVML_FEATURE_DISABLED
+            ),
+            nvmlReturn_t::SUCCESS
+        );
+    }
+
+    #[test]
+    fn uuid_from_unique_id() {
+        // "0123456789abcdef" in ASCII
+        assert_eq!(
+            super::uuid_from_unique_id(0x0123456789abcdef),
+            "GPU-30313233-3435-3637-3839-616263646566"
+        );
+    }
+
+    #[test]
+    fn handle_by_uuid() {
+        let _lock = lock();
+        assert_eq!(
+            unsafe { crate::nvmlInitWithFlags(NVML_INIT_FLAG_NO_GPUS) },
+            nvmlReturn_t::SUCCESS
+        );
+        for index in 0..device_count().unwrap() {
+            let mut device = unsafe { std::mem::zeroed() };
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetHandleByIndex_v2(index, &mut device) },
+                nvmlReturn_t::SUCCESS
+            );
+            let mut uuid = [0; NVML_DEVICE_UUID_V2_BUFFER_SIZE as usize];
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetUUID(device, uuid.as_mut_ptr(), uuid.len() as u32) },
+                nvmlReturn_t::SUCCESS
+            );
+            let mut device_by_uuid = unsafe { std::mem::zeroed() };
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetHandleByUUID(uuid.as_ptr(), &mut device_by_uuid) },
+                nvmlReturn_t::SUCCESS
+            );
+            assert_eq!(device, device_by_uuid);
+            let mut nvml_index = u32::MAX;
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetIndex(device_by_uuid, &mut nvml_index) },
+                nvmlReturn_t::SUCCESS
+            );
+            assert_eq!(nvml_index, index);
+        }
+        for bogus in [
+            c"GPU-00000000-0000-0000-0000-000000000000",
+            c"not a uuid",
+            c"",
+        ] {
+            let mut device = unsafe { std::mem::zeroed() };
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetHandleByUUID(bogus.as_ptr(), &mut device) },
+                nvmlReturn_t::ERROR_NOT_FOUND
+            );
+        }
+        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+    }
+
+    #[test]
+    fn visible_devices() {
+        use super::visible_devices;
+        assert_eq!(visible_devices("2,0", 3), [2, 0]);
+        assert_eq!(visible_devices(" 1 ", 3), [1]);
+        // The first invalid entry ends the list
+        assert_eq!(visible_devices("0,3,1", 3), [0]);
+        assert_eq!(visible_devices("1,1,0", 3), [1]);
+        assert_eq!(visible_devices("GPU-00000000,0", 3), []);
+        assert_eq!(visible_devices("", 3), []);
+    }
+
+    // Visible devices get the NVML index of their CUDA ordinal, but every device is enumerated
+    #[test]
+    fn device_order_follows_visible_devices() {
+        let _lock = lock();
+        let uuid = |index: u32| format!("GPU-{index:08x}-0000-0000-0000-000000000000");
+        let devices = (0..3)
+            .map(|index| Device::mock(index, &uuid(index), MockTelemetry::default()))
+            .collect();
+        let devices = super::order_devices(devices, |name| match name {
+            "HIP_VISIBLE_DEVICES" => Some("2,0".into()),
+            "CUDA_VISIBLE_DEVICES" => Some("1".into()),
+            _ => None,
+        });
+        assert_eq!(super::init_with_devices(devices), nvmlReturn_t::SUCCESS);
+        // cuDeviceGetCount would return 2
+        assert_eq!(device_count(), Ok(3));
+        for (index, pci_index) in [2, 0, 1].into_iter().enumerate() {
+            let mut device = unsafe { std::mem::zeroed() };
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetHandleByIndex_v2(index as u32, &mut device) },
+                nvmlReturn_t::SUCCESS
+            );
+            let mut nvml_index = u32::MAX;
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetIndex(device, &mut nvml_index) },
+                nvmlReturn_t::SUCCESS
+            );
+            assert_eq!(nvml_index, index as u32);
+            let uuid = std::ffi::CString::new(uuid(pci_index)).unwrap();
+            let mut device_by_uuid = unsafe { std::mem::zeroed() };
+            assert_eq!(
+                unsafe { crate::nvmlDeviceGetHandleByUUID(uuid.as_ptr(), &mut device_by_uuid) },
+                nvmlReturn_t::SUCCESS
+            );
+            assert_eq!(device, device_by_uuid);
+        }
+        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+    }
+
+    #[test]
+    fn hidden_devices_keep_pci_bus_order() {
+        let order = |list: &str| {
+            let devices = (0..4)
+                .map(|index| Device::mock(index, "", MockTelemetry::default()))
+                .collect();
+            super::order_devices(devices, |name| {
+                (name == "CUDA_VISIBLE_DEVICES").then(|| list.into())
+            })
+            .iter()
+            .enumerate()
+            .map(|(nvml_index, device)| {
+                assert_eq!(device.nvml_index, nvml_index as u32);
+                device.index
+            })
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(order("3,1"), [3, 1, 0, 2]);
+        assert_eq!(order(""), [0, 1, 2, 3]);
+        let devices = (0..2)
+            .map(|index| Device::mock(index, "", MockTelemetry::default()))
+            .collect();
+        let devices = super::order_devices(devices, |_| None);
+        assert_eq!(
+            devices
+                .iter()
+                .map(|device| device.index)
+                .collect::<Vec<_>>(),
+            [0, 1]
+        );
+    }
+
+    #[test]
+    fn gfx_version_to_architecture() {
+        use super::gfx_version_to_architecture;
+        let cases = [
+            ((8, 0, 3), NVML_DEVICE_ARCH_PASCAL),
+            ((9, 0, 0), NVML_DEVICE_ARCH_VOLTA),
+            ((9, 0, 6), NVML_DEVICE_ARCH_VOLTA),
+            ((9, 0, 8), NVML_DEVICE_ARCH_AMPERE),
+            ((9, 0, 10), NVML_DEVICE_ARCH_AMPERE),
+            ((9, 4, 2), NVML_DEVICE_ARCH_HOPPER),
+            ((9, 5, 0), NVML_DEVICE_ARCH_BLACKWELL),
+            ((10, 1, 0), NVML_DEVICE_ARCH_TURING),
+            ((10, 3, 0), NVML_DEVICE_ARCH_AMPERE),
+            ((11, 0, 0), NVML_DEVICE_ARCH_ADA),
+            ((11, 5, 1), NVML_DEVICE_ARCH_ADA),
+            ((12, 0, 1), NVML_DEVICE_ARCH_BLACKWELL),
+            ((7, 0, 0), NVML_DEVICE_ARCH_UNKNOWN),
+        ];
+        for (gfx_version, arch) in cases {
+            assert_eq!(
+                gfx_version_to_architecture(gfx_version),
+                arch,
+                "{gfx_version:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn perf_level_to_pstate() {
+        use crate::telemetry::perf_level_to_pstate;
+        use rocm_smi_sys::rsmi_dev_perf_level_t;
+        let cases = [
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_AUTO,
+                0,
+                nvmlPstates_t::NVML_PSTATE_8,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_AUTO,
+                10,
+                nvmlPstates_t::NVML_PSTATE_2,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_AUTO,
+                100,
+                nvmlPstates_t::NVML_PSTATE_0,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_LOW,
+                100,
+                nvmlPstates_t::NVML_PSTATE_8,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_HIGH,
+                0,
+                nvmlPstates_t::NVML_PSTATE_0,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_MANUAL,
+                0,
+                nvmlPstates_t::NVML_PSTATE_2,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_STABLE_STD,
+                0,
+                nvmlPstates_t::NVML_PSTATE_2,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_STABLE_PEAK,
+                0,
+                nvmlPstates_t::NVML_PSTATE_0,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_STABLE_MIN_MCLK,
+                0,
+                nvmlPstates_t::NVML_PSTATE_8,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_STABLE_MIN_SCLK,
+                0,
+                nvmlPstates_t::NVML_PSTATE_8,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_DETERMINISM,
+                0,
+                nvmlPstates_t::NVML_PSTATE_0,
+            ),
+            (
+                rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_UNKNOWN,
+                0,
+                nvmlPstates_t::NVML_PSTATE_UNKNOWN,
+            ),
+        ];
+        for (perf_level, busy_percent, pstate) in cases {
+            assert_eq!(
+                perf_level_to_pstate(perf_level, busy_percent),
+                pstate,
+                "{perf_level:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn concurrent_init_and_shutdown() {
+        let _lock = lock();
+        let threads = (0..8)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..100 {
+                        assert_eq!(
+                            unsafe { crate::nvmlInitWithFlags(NVML_INIT_FLAG_NO_GPUS) },
+                            nvmlReturn_t::SUCCESS
+                        );
+                        device_count().unwrap();
+                        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(device_count(), Err(nvmlError_t::UNINITIALIZED));
+    }
+
+    fn mock_devices() -> Vec<Device> {
+        (0..2)
+            .map(|index| {
+                let telemetry = MockTelemetry {
+                    name: Some("AMD Radeon RX 7900 XTX".to_string()),
+                    gpu_temperature: Some(50),
+                    ..Default::default()
+                };
+                Device::mock(
+                    index,
+                    &format!("GPU-{index:08x}-0000-0000-0000-000000000000"),
+                    telemetry,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn handles_are_rejected_after_reinitialization() {
+        let _lock = lock();
+        assert_eq!(
+            super::init_with_devices(mock_devices()),
+            nvmlReturn_t::SUCCESS
+        );
+        let mut stale = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            unsafe { crate::nvmlDeviceGetHandleByIndex_v2(1, &mut stale) },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+        assert_eq!(
+            super::init_with_devices(mock_devices()),
+            nvmlReturn_t::SUCCESS
+        );
+        let mut device = unsafe { std::mem::zeroed() };
+        assert_eq!(
+            unsafe { crate::nvmlDeviceGetHandleByIndex_v2(1, &mut device) },
+            nvmlReturn_t::SUCCESS
+        );
+        let mut index = u32::MAX;
+        assert_eq!(
+            unsafe { crate::nvmlDeviceGetIndex(stale, &mut index) },
+            nvmlReturn_t::ERROR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            unsafe { crate::nvmlDeviceGetIndex(std::ptr::null_mut(), &mut index) },
+            nvmlReturn_t::ERROR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            unsafe { crate::nvmlDeviceGetIndex(device, &mut index) },
+            nvmlReturn_t::SUCCESS
+        );
+        assert_eq!(index, 1);
+        assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+    }
+
+    // Monitoring threads keep polling while another thread initializes and shuts down the library.
+    // Every call must either succeed or fail with one of the errors NVML documents for a library
+    // that is not initialized or a handle that is no longer valid
+    #[test]
+    fn getters_race_with_shutdown() {
+        fn check(result: nvmlReturn_t) {
+            assert!(
+                matches!(
+                    result,
+                    nvmlReturn_t::SUCCESS
+                        | nvmlReturn_t::ERROR_UNINITIALIZED
+                        | nvmlReturn_t::ERROR_INVALID_ARGUMENT
+                ),
+                "{result:?}"
+            );
+        }
+        let _lock = lock();
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for thread_index in 0..8 {
+                let done = &done;
+                scope.spawn(move || {
+                    let mut device: nvmlDevice_t = std::ptr::null_mut();
+                    while !done.load(Ordering::Relaxed) {
+                        let mut count = 0;
+                        check(unsafe { crate::nvmlDeviceGetCount_v2(&mut count) });
+                        // Half of the threads keep using their first handle, so that most of
+                        // their calls are made with a handle from an earlier initialization
+                        if thread_index % 2 == 0 || device.is_null() {
+                            check(unsafe {
+                                crate::nvmlDeviceGetHandleByIndex_v2(thread_index % 2, &mut device)
+                            });
+                        }
+                        let mut name = [0; NVML_DEVICE_NAME_V2_BUFFER_SIZE as usize];
+                        check(unsafe {
+                            crate::nvmlDeviceGetName(device, name.as_mut_ptr(), name.len() as u32)
+                        });
+                        let mut uuid = [0; NVML_DEVICE_UUID_V2_BUFFER_SIZE as usize];
+                        check(unsafe {
+                            crate::nvmlDeviceGetUUID(device, uuid.as_mut_ptr(), uuid.len() as u32)
+                        });
+                        let mut temperature = 0;
+                        check(unsafe {
+                            crate::nvmlDeviceGetTemperature(
+                                device,
+                                nvmlTemperatureSensors_t::NVML_TEMPERATURE_GPU,
+                                &mut temperature,
+                            )
+                        });
+                        let mut mode = unsafe { std::mem::zeroed() };
+                        check(unsafe { crate::nvmlDeviceGetComputeMode(device, &mut mode) });
+                    }
+                });
+            }
+            for _ in 0..1000 {
+                assert_eq!(
+                    super::init_with_devices(mock_devices()),
+                    nvmlReturn_t::SUCCESS
+                );
+                thread::yield_now();
+                assert_eq!(unsafe { crate::nvmlShutdown() }, nvmlReturn_t::SUCCESS);
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(device_count(), Err(nvmlError_t::UNINITIALIZED));
+    }
+}
@@ -1,7 +1,14 @@
 use cuda_types::nvml::*;
 
+pub(crate) use crate::event::{
+    event_set_create, event_set_free, event_set_wait, event_set_wait_v2,
+};
 pub(crate) use crate::impl_common::error_string;
+pub(crate) use crate::impl_common::system_get_cuda_driver_version;
+pub(crate) use crate::impl_common::system_get_cuda_driver_version_v2;
 pub(crate) use crate::impl_common::system_get_driver_version;
+pub(crate) use crate::impl_common::system_get_hic_version;
+pub(crate) use crate::impl_common::system_get_n_v_m_l_version;
 
 pub(crate) unsafe fn init() -> nvmlReturn_t {
     crate::impl_common::unimplemented()
@@ -19,6 +26,14 @@ pub(crate) unsafe fn shutdown() -> nvmlReturn_t {
     crate::impl_common::unimplemented()
 }
 
+pub(crate) fn system_get_process_name(
+    _pid: ::core::ffi::c_uint,
+    _name: *mut ::core::ffi::c_char,
+    _length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
 pub(crate) unsafe fn device_get_count_v2(_device_count: &mut ::core::ffi::c_uint) -> nvmlReturn_t {
     crate::impl_common::unimplemented()
 }
@@ -35,6 +50,342 @@ unsafe fn get_field_value(_field: &mut nvmlFieldValue_st) -> Result<(), nvmlErro
     crate::impl_common::unimplemented()
 }
 
+pub(crate) fn device_get_name(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _name: *mut ::core::ffi::c_char,
+    _length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_num_gpu_cores(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _num_cores: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_architecture(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _arch: &mut nvmlDeviceArchitecture_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_brand(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _type_: &mut nvmlBrandType_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_vbios_version(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _version: *mut ::core::ffi::c_char,
+    _length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_board_part_number(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _part_number: *mut ::core::ffi::c_char,
+    _length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_cuda_compute_capability(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _major: &mut ::core::ffi::c_int,
+    _minor: &mut ::core::ffi::c_int,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) unsafe fn device_get_performance_state(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _p_state: &mut nvmlPstates_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) unsafe fn device_get_power_state(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _p_state: &mut nvmlPstates_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_memory_bus_width(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _bus_width: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_curr_pcie_link_generation(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _curr_link_gen: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_max_pcie_link_generation(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _max_link_gen: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_curr_pcie_link_width(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _curr_link_width: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_max_pcie_link_width(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _max_link_width: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_pcie_throughput(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _counter: nvmlPcieUtilCounter_t,
+    _value: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_pcie_replay_counter(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _value: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_compute_mode(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _mode: &mut nvmlComputeMode_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_set_compute_mode(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _mode: nvmlComputeMode_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_accounting_mode(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _mode: &mut nvmlEnableState_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_set_accounting_mode(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _mode: nvmlEnableState_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_accounting_pids(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _count: &mut ::core::ffi::c_uint,
+    _pids: Option<&mut ::core::ffi::c_uint>,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_accounting_stats(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _pid: ::core::ffi::c_uint,
+    _stats: &mut nvmlAccountingStats_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_clear_accounting_pids(
+    _device: cuda_types::nvml::nvmlDevice_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_persistence_mode(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _mode: &mut nvmlEnableState_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_set_persistence_mode(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _mode: nvmlEnableState_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_mig_mode(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _current_mode: &mut ::core::ffi::c_uint,
+    _pending_mode: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_set_mig_mode(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _mode: ::core::ffi::c_uint,
+    _activation_status: &mut nvmlReturn_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_max_mig_device_count(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _count: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_mig_device_handle_by_index(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _index: ::core::ffi::c_uint,
+    _mig_device: &mut cuda_types::nvml::nvmlDevice_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_is_mig_device_handle(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _is_mig_device: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_temperature(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _sensor_type: nvmlTemperatureSensors_t,
+    _temp: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_power_usage(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _power: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_clock_info(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _type_: nvmlClockType_t,
+    _clock: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_utilization_rates(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _utilization: &mut nvmlUtilization_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_memory_info(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _memory: &mut nvmlMemory_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_pci_info_v3(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _pci: &mut nvmlPciInfo_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) unsafe fn device_get_compute_running_processes_v3(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _info_count: &mut ::core::ffi::c_uint,
+    _infos: Option<&mut nvmlProcessInfo_t>,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) unsafe fn device_get_b_a_r1_memory_info(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _bar1_memory: &mut nvmlBAR1Memory_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) unsafe fn device_get_ecc_mode(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _current: &mut nvmlEnableState_t,
+    _pending: &mut nvmlEnableState_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) unsafe fn device_get_total_ecc_errors(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _error_type: nvmlMemoryErrorType_t,
+    _counter_type: nvmlEccCounterType_t,
+    _ecc_counts: &mut ::core::ffi::c_ulonglong,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) unsafe fn device_get_detailed_ecc_errors(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _error_type: nvmlMemoryErrorType_t,
+    _counter_type: nvmlEccCounterType_t,
+    _ecc_counts: &mut nvmlEccErrorCounts_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) unsafe fn device_get_topology_common_ancestor(
+    _device1: cuda_types::nvml::nvmlDevice_t,
+    _device2: cuda_types::nvml::nvmlDevice_t,
+    _path_info: &mut nvmlGpuTopologyLevel_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) unsafe fn device_get_p2_p_status(
+    _device1: cuda_types::nvml::nvmlDevice_t,
+    _device2: cuda_types::nvml::nvmlDevice_t,
+    _p2p_index: nvmlGpuP2PCapsIndex_t,
+    _p2p_status: &mut nvmlGpuP2PStatus_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_register_events(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _event_types: ::core::ffi::c_ulonglong,
+    _set: cuda_types::nvml::nvmlEventSet_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_supported_event_types(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _event_types: &mut ::core::ffi::c_ulonglong,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
 pub(crate) unsafe fn device_get_gpu_fabric_info(
     _device: cuda_types::nvml::nvmlDevice_t,
     _gpu_fabric_info: &mut cuda_types::nvml::nvmlGpuFabricInfo_t,
@@ -42,6 +393,28 @@ pub(crate) unsafe fn device_get_gpu_fabric_info(
     crate::impl_common::unimplemented()
 }
 
+pub(crate) fn device_get_handle_by_u_u_i_d(
+    _uuid: &std::ffi::CStr,
+    _device: &mut cuda_types::nvml::nvmlDevice_t,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_u_u_i_d(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _uuid: *mut ::core::ffi::c_char,
+    _length: ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
+pub(crate) fn device_get_index(
+    _device: cuda_types::nvml::nvmlDevice_t,
+    _index: &mut ::core::ffi::c_uint,
+) -> nvmlReturn_t {
+    crate::impl_common::unimplemented()
+}
+
 pub(crate) fn device_get_handle_by_index_v2(
     _index: ::core::ffi::c_uint,
     _device: &mut cuda_types::nvml::nvmlDevice_t,
@@ -1,7 +1,16 @@
+mod event;
 #[cfg_attr(windows, path = "impl_win.rs")]
 #[cfg_attr(unix, path = "impl_unix.rs")]
 mod r#impl;
 mod impl_common;
+#[cfg(all(test, unix))]
+mod replay;
+#[cfg(unix)]
+mod sysfs;
+#[cfg(unix)]
+mod telemetry;
+#[cfg(unix)]
+mod topology;
 
 macro_rules! unimplemented_fn {
     ($($abi:literal fn $fn_name:ident( $($arg_id:ident : $arg_type:ty),* ) -> $ret_type:ty;)*) => {
@@ -44,15 +53,86 @@ cuda_macros::nvml_function_declarations!(
     unimplemented_fn,
     implemented_fn
         <= [
+            nvmlDeviceClearAccountingPids,
+            nvmlDeviceGetAccountingMode,
+            nvmlDeviceGetAccountingPids,
+            nvmlDeviceGetAccountingStats,
+            nvmlDeviceGetArchitecture,
+            nvmlDeviceGetBAR1MemoryInfo,
+            nvmlDeviceGetBoardPartNumber,
+            nvmlDeviceGetBrand,
+            nvmlDeviceGetClockInfo,
+            nvmlDeviceGetComputeMode,
+            nvmlDeviceGetComputeRunningProcesses_v3,
             nvmlDeviceGetCount_v2,
+            nvmlDeviceGetCudaComputeCapability,
+            nvmlDeviceGetCurrPcieLinkGeneration,
+            nvmlDeviceGetCurrPcieLinkWidth,
+            nvmlDeviceGetDetailedEccErrors,
+            nvmlDeviceGetEccMode,
             nvmlDeviceGetFieldValues,
             nvmlDeviceGetGpuFabricInfo,
             nvmlDeviceGetHandleByIndex_v2,
+            nvmlDeviceGetHandleByUUID,
+            nvmlDeviceGetIndex,
+            nvmlDeviceGetMaxMigDeviceCount,
+            nvmlDeviceGetMaxPcieLinkGeneration,
+            nvmlDeviceGetMaxPcieLinkWidth,
+            nvmlDeviceGetMemoryBusWidth,
+            nvmlDeviceGetMemoryInfo,
+            nvmlDeviceGetMigDeviceHandleByIndex,
+            nvmlDeviceGetMigMode,
+            nvmlDeviceGetName,
+            nvmlDeviceGetNumGpuCores,
+            nvmlDeviceGetP2PStatus,
+            nvmlDeviceGetPciInfo_v3,
+            nvmlDeviceGetPcieReplayCounter,
+            nvmlDeviceGetPcieThroughput,
+            nvmlDeviceGetPerformanceState,
+            nvmlDeviceGetPersistenceMode,
+            nvmlDeviceGetPowerState,
+            nvmlDeviceGetPowerUsage,
+            nvmlDeviceGetSupportedEventTypes,
+            nvmlDeviceGetTemperature,
+            nvmlDeviceGetTopologyCommonAncestor,
+            nvmlDeviceGetTotalEccErrors,
+            nvmlDeviceGetUUID,
+            nvmlDeviceGetUtilizationRates,
+            nvmlDeviceGetVbiosVersion,
+            nvmlDeviceIsMigDeviceHandle,
+            nvmlDeviceRegisterEvents,
+            nvmlDeviceSetAccountingMode,
+            nvmlDeviceSetComputeMode,
+            nvmlDeviceSetMigMode,
+            nvmlDeviceSetPersistenceMode,
+            nvmlEventSetCreate,
+            nvmlEventSetFree,
+            nvmlEventSetWait,
+            nvmlEventSetWait_v2,
             nvmlInit,
             nvmlInitWithFlags,
             nvmlInit_v2,
             nvmlShutdown,
+            nvmlSystemGetCudaDriverVersion,
+            nvmlSystemGetCudaDriverVersion_v2,
             nvmlSystemGetDriverVersion,
+            nvmlSystemGetHicVersion,
+            nvmlSystemGetNVMLVersion,
+            nvmlSystemGetProcessName,
         ],
     implemented_unnormalized <= [nvmlErrorString,]
 );
+
+/// ZLUDA extension for applications embedding ZLUDA: overrides the driver version reported by
+/// `nvmlSystemGetDriverVersion` and `nvmlSystemGetNVMLVersion`, including the one set through
+/// the `ZLUDA_NVML_DRIVER_VERSION` environment variable. Passing NULL restores the default
+#[no_mangle]
+pub unsafe extern "system" fn zluda_nvml_set_driver_version(
+    version: *const ::core::ffi::c_char,
+) -> cuda_types::nvml::nvmlReturn_t {
+    impl_common::set_driver_version(
+        version
+            .as_ref()
+            .map(|version| std::ffi::CStr::from_ptr(version)),
+    )
+}
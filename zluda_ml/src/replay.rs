@@ -0,0 +1,546 @@
+// Replays NVML call sequences of real applications against mock devices. Traces are in
+// src/test/traces, one call per line:
+//   <entry point> <arguments> -> <expected result>[|<expected result>...]
+// Devices are referred to by the index they were obtained with through
+// nvmlDeviceGetHandleByIndex_v2. `repeat <n>` ... `end` repeats the calls in between and an
+// optional `threads <n>` before the first call replays the whole trace on that many threads at
+// once. Besides the result of every call we check that the strings and structs returned by
+// successful calls are well-formed
+use crate::impl_common::GLOBAL_STATE_TEST_LOCK;
+use crate::r#impl::{init_with_devices, Device};
+use crate::telemetry::{MockTelemetry, PciInfo, ProcessMemory};
+use cuda_types::nvml::*;
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    mem, ptr, thread,
+};
+
+#[test]
+fn pytorch() {
+    replay("pytorch", include_str!("test/traces/pytorch.trace"));
+}
+
+#[test]
+fn nvidia_smi() {
+    replay("nvidia_smi", include_str!("test/traces/nvidia_smi.trace"));
+}
+
+#[test]
+fn dcgm() {
+    replay("dcgm", include_str!("test/traces/dcgm.trace"));
+}
+
+// A discrete GPU with every sensor and an APU without a power sensor
+fn mock_devices() -> Vec<Device> {
+    let discrete = MockTelemetry {
+        name: Some("AMD Radeon RX 7900 XTX".to_string()),
+        gpu_temperature: Some(54),
+        memory_temperature: Some(62),
+        power_usage: Some(187_000),
+        graphics_clock: Some(2482),
+        memory_clock: Some(1249),
+        utilization: Some(nvmlUtilization_t {
+            gpu: 97,
+            memory: 41,
+        }),
+        vram: Some(nvmlMemory_t {
+            total: 24 << 30,
+            free: 4 << 30,
+            used: 20 << 30,
+        }),
+        pci_info: Some(PciInfo {
+            domain: 0,
+            bus: 0x03,
+            device: 0,
+            function: 0,
+            vendor_id: 0x1002,
+            device_id: 0x744c,
+            subsystem_vendor_id: 0x1002,
+            subsystem_id: 0x0e3b,
+        }),
+        processes: Some(vec![ProcessMemory {
+            pid: std::process::id(),
+            used_memory: 19 << 30,
+        }]),
+        ..Default::default()
+    };
+    let apu = MockTelemetry {
+        name: Some("AMD Radeon 780M".to_string()),
+        gpu_temperature: Some(48),
+        graphics_clock: Some(2700),
+        utilization: Some(nvmlUtilization_t { gpu: 3, memory: 0 }),
+        vram: Some(nvmlMemory_t {
+            total: 512 << 20,
+            free: 300 << 20,
+            used: 212 << 20,
+        }),
+        pci_info: Some(PciInfo {
+            domain: 0,
+            bus: 0xc5,
+            device: 0,
+            function: 0,
+            vendor_id: 0x1002,
+            device_id: 0x15bf,
+            subsystem_vendor_id: 0x1002,
+            subsystem_id: 0x0123,
+        }),
+        processes: Some(Vec::new()),
+        ..Default::default()
+    };
+    vec![
+        Device::mock(0, "GPU-30313233-3435-3637-3839-616263646566", discrete),
+        Device::mock(1, "GPU-66656463-6261-3938-3736-353433323130", apu),
+    ]
+}
+
+fn replay(name: &str, text: &str) {
+    let trace = Trace::parse(name, text);
+    let _lock = GLOBAL_STATE_TEST_LOCK
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    assert_eq!(init_with_devices(mock_devices()), nvmlReturn_t::SUCCESS);
+    let _shutdown = ShutdownOnDrop;
+    thread::scope(|scope| {
+        for _ in 0..trace.threads {
+            scope.spawn(|| Replay::default().run(&trace, &trace.calls));
+        }
+    });
+}
+
+// Leaves the library uninitialized for the other tests, also when a call of the trace failed
+struct ShutdownOnDrop;
+
+impl Drop for ShutdownOnDrop {
+    fn drop(&mut self) {
+        while unsafe { crate::nvmlShutdown() } == nvmlReturn_t::SUCCESS {}
+    }
+}
+
+struct Trace<'a> {
+    name: &'a str,
+    threads: usize,
+    calls: Vec<Line<'a>>,
+}
+
+enum Line<'a> {
+    Call(Call<'a>),
+    Repeat(usize, Vec<Line<'a>>),
+}
+
+struct Call<'a> {
+    line: usize,
+    entry_point: &'a str,
+    arguments: Vec<&'a str>,
+    expected: Vec<nvmlReturn_t>,
+}
+
+impl<'a> Trace<'a> {
+    fn parse(name: &'a str, text: &'a str) -> Self {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .peekable();
+        let threads = match lines.peek() {
+            Some((_, line)) if line.starts_with("threads ") => {
+                let (line_number, line) = lines.next().unwrap();
+                parse_count(name, line_number, &line["threads ".len()..])
+            }
+            _ => 1,
+        };
+        let calls = Self::parse_block(name, &mut lines, false);
+        Trace {
+            name,
+            threads,
+            calls,
+        }
+    }
+
+    fn parse_block(
+        name: &str,
+        lines: &mut impl Iterator<Item = (usize, &'a str)>,
+        is_nested: bool,
+    ) -> Vec<Line<'a>> {
+        let mut block = Vec::new();
+        while let Some((line_number, line)) = lines.next() {
+            if line == "end" {
+                assert!(is_nested, "{name}:{line_number}: `end` without `repeat`");
+                return block;
+            }
+            if let Some(count) = line.strip_prefix("repeat ") {
+                let count = parse_count(name, line_number, count);
+                block.push(Line::Repeat(count, Self::parse_block(name, lines, true)));
+                continue;
+            }
+            let (call, expected) = line
+                .split_once("->")
+                .unwrap_or_else(|| panic!("{name}:{line_number}: missing expected result"));
+            let mut call = call.split_whitespace();
+            block.push(Line::Call(Call {
+                line: line_number,
+                entry_point: call.next().unwrap(),
+                arguments: call.collect(),
+                expected: expected
+                    .split('|')
+                    .map(|result| {
+                        parse_result(result.trim()).unwrap_or_else(|| {
+                            panic!("{name}:{line_number}: unknown result {result}")
+                        })
+                    })
+                    .collect(),
+            }));
+        }
+        assert!(!is_nested, "{name}: `repeat` without `end`");
+        block
+    }
+}
+
+fn parse_count(name: &str, line_number: usize, count: &str) -> usize {
+    count
+        .trim()
+        .parse()
+        .unwrap_or_else(|_| panic!("{name}:{line_number}: invalid count {count}"))
+}
+
+fn parse_result(result: &str) -> Option<nvmlReturn_t> {
+    Some(match result {
+        "SUCCESS" => nvmlReturn_t::SUCCESS,
+        "ERROR_UNINITIALIZED" => nvmlReturn_t::ERROR_UNINITIALIZED,
+        "ERROR_INVALID_ARGUMENT" => nvmlReturn_t::ERROR_INVALID_ARGUMENT,
+        "ERROR_NOT_SUPPORTED" => nvmlReturn_t::ERROR_NOT_SUPPORTED,
+        "ERROR_NO_PERMISSION" => nvmlReturn_t::ERROR_NO_PERMISSION,
+        "ERROR_NOT_FOUND" => nvmlReturn_t::ERROR_NOT_FOUND,
+        "ERROR_INSUFFICIENT_SIZE" => nvmlReturn_t::ERROR_INSUFFICIENT_SIZE,
+        "ERROR_NO_DATA" => nvmlReturn_t::ERROR_NO_DATA,
+        "ERROR_UNKNOWN" => nvmlReturn_t::ERROR_UNKNOWN,
+        _ => return None,
+    })
+}
+
+// State of a single replaying thread
+#[derive(Default)]
+struct Replay {
+    device_count: Option<u32>,
+    devices: HashMap<u32, nvmlDevice_t>,
+    uuids: HashMap<u32, CString>,
+}
+
+impl Replay {
+    fn run(&mut self, trace: &Trace, lines: &[Line]) {
+        for line in lines {
+            match line {
+                Line::Call(call) => self.call(trace, call),
+                Line::Repeat(count, lines) => {
+                    for _ in 0..*count {
+                        self.run(trace, lines);
+                    }
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, trace: &Trace, call: &Call) {
+        let location = format!("{}:{} {}", trace.name, call.line, call.entry_point);
+        let result = unsafe { self.dispatch(&location, call) };
+        // These two are never right for a well-behaved application, so they get their own message
+        if matches!(
+            result,
+            nvmlReturn_t::ERROR_UNINITIALIZED | nvmlReturn_t::ERROR_INVALID_ARGUMENT
+        ) {
+            assert!(
+                call.expected.contains(&result),
+                "{location}: unexpected {result:?}"
+            );
+        }
+        assert!(
+            call.expected.contains(&result),
+            "{location}: returned {result:?}, expected {:?}",
+            call.expected
+        );
+    }
+
+    fn device(&self, location: &str, call: &Call) -> nvmlDevice_t {
+        let index = device_index(location, call);
+        *self
+            .devices
+            .get(&index)
+            .unwrap_or_else(|| panic!("{location}: no handle of device {index}"))
+    }
+
+    unsafe fn dispatch(&mut self, location: &str, call: &Call) -> nvmlReturn_t {
+        match call.entry_point {
+            "nvmlInit_v2" => crate::nvmlInit_v2(),
+            "nvmlShutdown" => crate::nvmlShutdown(),
+            "nvmlSystemGetDriverVersion" => {
+                let mut version = [0; NVML_SYSTEM_DRIVER_VERSION_BUFFER_SIZE as usize];
+                crate::nvmlSystemGetDriverVersion(version.as_mut_ptr(), version.len() as u32)?;
+                let version = c_string(location, &version);
+                assert!(
+                    version.split('.').all(|part| part.parse::<u32>().is_ok()),
+                    "{location}: {version}"
+                );
+                Ok(())
+            }
+            "nvmlSystemGetNVMLVersion" => {
+                let mut version = [0; NVML_SYSTEM_NVML_VERSION_BUFFER_SIZE as usize];
+                crate::nvmlSystemGetNVMLVersion(version.as_mut_ptr(), version.len() as u32)?;
+                let version = c_string(location, &version);
+                assert!(
+                    version.starts_with(&format!("{}.", NVML_API_VERSION)),
+                    "{location}: {version}"
+                );
+                Ok(())
+            }
+            "nvmlSystemGetCudaDriverVersion" | "nvmlSystemGetCudaDriverVersion_v2" => {
+                let mut version = 0;
+                if call.entry_point == "nvmlSystemGetCudaDriverVersion" {
+                    crate::nvmlSystemGetCudaDriverVersion(&mut version)?;
+                } else {
+                    crate::nvmlSystemGetCudaDriverVersion_v2(&mut version)?;
+                }
+                assert!(version >= 10000, "{location}: {version}");
+                Ok(())
+            }
+            "nvmlSystemGetProcessName" => {
+                let mut name = [0; NVML_DEVICE_NAME_V2_BUFFER_SIZE as usize];
+                crate::nvmlSystemGetProcessName(
+                    std::process::id(),
+                    name.as_mut_ptr(),
+                    name.len() as u32,
+                )?;
+                c_string(location, &name);
+                Ok(())
+            }
+            "nvmlDeviceGetCount_v2" => {
+                let mut count = 0;
+                crate::nvmlDeviceGetCount_v2(&mut count)?;
+                self.device_count = Some(count);
+                Ok(())
+            }
+            "nvmlDeviceGetHandleByIndex_v2" => {
+                let index = device_index(location, call);
+                let count = self
+                    .device_count
+                    .unwrap_or_else(|| panic!("{location}: device count was not queried"));
+                assert!(index < count, "{location}: only {count} devices");
+                let mut device = ptr::null_mut();
+                crate::nvmlDeviceGetHandleByIndex_v2(index, &mut device)?;
+                assert!(!device.is_null(), "{location}");
+                self.devices.insert(index, device);
+                Ok(())
+            }
+            "nvmlDeviceGetHandleByUUID" => {
+                let index = device_index(location, call);
+                let uuid = self
+                    .uuids
+                    .get(&index)
+                    .unwrap_or_else(|| panic!("{location}: no UUID of device {index}"));
+                let mut device = ptr::null_mut();
+                crate::nvmlDeviceGetHandleByUUID(uuid.as_ptr(), &mut device)?;
+                assert_eq!(device, self.device(location, call), "{location}");
+                Ok(())
+            }
+            "nvmlDeviceGetIndex" => {
+                let mut index = u32::MAX;
+                crate::nvmlDeviceGetIndex(self.device(location, call), &mut index)?;
+                assert_eq!(index, device_index(location, call), "{location}");
+                Ok(())
+            }
+            "nvmlDeviceGetName" => {
+                let mut name = [0; NVML_DEVICE_NAME_V2_BUFFER_SIZE as usize];
+                crate::nvmlDeviceGetName(
+                    self.device(location, call),
+                    name.as_mut_ptr(),
+                    name.len() as u32,
+                )?;
+                let name = c_string(location, &name);
+                assert!(name.ends_with(" [ZLUDA]"), "{location}: {name}");
+                Ok(())
+            }
+            "nvmlDeviceGetUUID" => {
+                let mut uuid = [0; NVML_DEVICE_UUID_V2_BUFFER_SIZE as usize];
+                crate::nvmlDeviceGetUUID(
+                    self.device(location, call),
+                    uuid.as_mut_ptr(),
+                    uuid.len() as u32,
+                )?;
+                let uuid = c_string(location, &uuid);
+                let groups = uuid
+                    .strip_prefix("GPU-")
+                    .map(|uuid| uuid.split('-').map(str::len).collect::<Vec<_>>());
+                assert_eq!(groups, Some(vec![8, 4, 4, 4, 12]), "{location}: {uuid}");
+                self.uuids
+                    .insert(device_index(location, call), CString::new(uuid).unwrap());
+                Ok(())
+            }
+            "nvmlDeviceGetPciInfo_v3" => {
+                let mut pci = mem::zeroed::<nvmlPciInfo_t>();
+                crate::nvmlDeviceGetPciInfo_v3(self.device(location, call), &mut pci)?;
+                let bus_id = c_string(location, &pci.busId);
+                assert_eq!(
+                    bus_id,
+                    format!("{:08X}:{:02X}:{:02X}.0", pci.domain, pci.bus, pci.device),
+                    "{location}"
+                );
+                c_string(location, &pci.busIdLegacy);
+                assert_ne!(pci.pciDeviceId, 0, "{location}");
+                Ok(())
+            }
+            "nvmlDeviceGetCudaComputeCapability" => {
+                let (mut major, mut minor) = (0, -1);
+                crate::nvmlDeviceGetCudaComputeCapability(
+                    self.device(location, call),
+                    &mut major,
+                    &mut minor,
+                )?;
+                assert!(major > 0 && minor >= 0, "{location}: {major}.{minor}");
+                Ok(())
+            }
+            "nvmlDeviceGetMemoryInfo" => {
+                let mut memory = mem::zeroed::<nvmlMemory_t>();
+                crate::nvmlDeviceGetMemoryInfo(self.device(location, call), &mut memory)?;
+                assert!(
+                    memory.total > 0 && memory.used + memory.free == memory.total,
+                    "{location}: total {}, used {}, free {}",
+                    memory.total,
+                    memory.used,
+                    memory.free
+                );
+                Ok(())
+            }
+            "nvmlDeviceGetUtilizationRates" => {
+                let mut utilization = nvmlUtilization_t {
+                    gpu: u32::MAX,
+                    memory: u32::MAX,
+                };
+                crate::nvmlDeviceGetUtilizationRates(
+                    self.device(location, call),
+                    &mut utilization,
+                )?;
+                assert!(
+                    utilization.gpu <= 100 && utilization.memory <= 100,
+                    "{location}: gpu {}%, memory {}%",
+                    utilization.gpu,
+                    utilization.memory
+                );
+                Ok(())
+            }
+            "nvmlDeviceGetTemperature" => {
+                let sensor = match call.arguments.get(1) {
+                    Some(&"gpu") => nvmlTemperatureSensors_t::NVML_TEMPERATURE_GPU,
+                    sensor => panic!("{location}: unknown sensor {sensor:?}"),
+                };
+                let mut temperature = 0;
+                crate::nvmlDeviceGetTemperature(
+                    self.device(location, call),
+                    sensor,
+                    &mut temperature,
+                )?;
+                assert!(temperature < 150, "{location}: {temperature}C");
+                Ok(())
+            }
+            "nvmlDeviceGetPowerUsage" => {
+                let mut power = 0;
+                crate::nvmlDeviceGetPowerUsage(self.device(location, call), &mut power)?;
+                assert!(power > 0, "{location}");
+                Ok(())
+            }
+            "nvmlDeviceGetClockInfo" => {
+                let clock_type = match call.arguments.get(1) {
+                    Some(&"graphics") => nvmlClockType_t::NVML_CLOCK_GRAPHICS,
+                    Some(&"sm") => nvmlClockType_t::NVML_CLOCK_SM,
+                    Some(&"mem") => nvmlClockType_t::NVML_CLOCK_MEM,
+                    Some(&"video") => nvmlClockType_t::NVML_CLOCK_VIDEO,
+                    clock => panic!("{location}: unknown clock {clock:?}"),
+                };
+                let mut clock = 0;
+                crate::nvmlDeviceGetClockInfo(self.device(location, call), clock_type, &mut clock)?;
+                assert!(clock > 0, "{location}");
+                Ok(())
+            }
+            // Count query first, then the list, like every client does
+            "nvmlDeviceGetComputeRunningProcesses_v3" => {
+                let device = self.device(location, call);
+                let mut count = 0;
+                match crate::nvmlDeviceGetComputeRunningProcesses_v3(
+                    device,
+                    &mut count,
+                    ptr::null_mut(),
+                ) {
+                    nvmlReturn_t::ERROR_INSUFFICIENT_SIZE => {}
+                    result => {
+                        assert_eq!(count, 0, "{location}");
+                        return result;
+                    }
+                }
+                let mut infos = vec![mem::zeroed::<nvmlProcessInfo_t>(); count as usize];
+                crate::nvmlDeviceGetComputeRunningProcesses_v3(
+                    device,
+                    &mut count,
+                    infos.as_mut_ptr(),
+                )?;
+                assert_eq!(count as usize, infos.len(), "{location}");
+                assert!(
+                    infos.iter().all(|info| info.pid != 0),
+                    "{location}: process without a pid"
+                );
+                Ok(())
+            }
+            "nvmlDeviceGetFieldValues" => {
+                let mut values = call.arguments[1..]
+                    .iter()
+                    .map(|field| {
+                        let mut value = mem::zeroed::<nvmlFieldValue_t>();
+                        value.fieldId = match *field {
+                            "POWER_INSTANT" => NVML_FI_DEV_POWER_INSTANT,
+                            "POWER_AVERAGE" => NVML_FI_DEV_POWER_AVERAGE,
+                            "MEMORY_TEMP" => NVML_FI_DEV_MEMORY_TEMP,
+                            field => panic!("{location}: unknown field {field}"),
+                        };
+                        value
+                    })
+                    .collect::<Vec<_>>();
+                crate::nvmlDeviceGetFieldValues(
+                    self.device(location, call),
+                    values.len() as i32,
+                    values.as_mut_ptr(),
+                )?;
+                for (field, value) in call.arguments[1..].iter().zip(values) {
+                    assert!(
+                        matches!(
+                            value.nvmlReturn,
+                            nvmlReturn_t::SUCCESS | nvmlReturn_t::ERROR_NOT_SUPPORTED
+                        ),
+                        "{location}: {field} returned {:?}",
+                        value.nvmlReturn
+                    );
+                }
+                Ok(())
+            }
+            entry_point => panic!("{location}: replay of {entry_point} is not implemented"),
+        }
+    }
+}
+
+fn device_index(location: &str, call: &Call) -> u32 {
+    call.arguments
+        .first()
+        .and_then(|index| index.parse().ok())
+        .unwrap_or_else(|| panic!("{location}: missing device index"))
+}
+
+// Strings must be null-terminated inside the buffer, non-empty and printable
+fn c_string(location: &str, buffer: &[::core::ffi::c_char]) -> String {
+    let bytes = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), buffer.len()) };
+    let string = CStr::from_bytes_until_nul(bytes)
+        .unwrap_or_else(|_| panic!("{location}: string is not null-terminated"))
+        .to_str()
+        .unwrap_or_else(|_| panic!("{location}: string is not UTF-8"));
+    assert!(
+        !string.is_empty() && !string.chars().any(char::is_control),
+        "{location}: {string:?}"
+    );
+    string.to_string()
+}
@@ -0,0 +1,690 @@
+use cuda_types::nvml::*;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub(crate) fn device_dir(render_minor: u32) -> PathBuf {
+    PathBuf::from(format!("/sys/class/drm/renderD{}/device", render_minor))
+}
+
+pub(crate) fn kfd_node_properties_path(node_id: u32) -> PathBuf {
+    PathBuf::from(format!(
+        "/sys/class/kfd/kfd/topology/nodes/{}/properties",
+        node_id
+    ))
+}
+
+// Every node has at least one memory bank, the first one is the VRAM of dGPUs
+pub(crate) fn kfd_mem_bank_properties_path(node_id: u32) -> PathBuf {
+    PathBuf::from(format!(
+        "/sys/class/kfd/kfd/topology/nodes/{}/mem_banks/0/properties",
+        node_id
+    ))
+}
+
+fn read_u64(path: &Path) -> Result<u64, nvmlError_t> {
+    fs::read_to_string(path)
+        .map_err(|_| nvmlError_t::NOT_SUPPORTED)?
+        .trim()
+        .parse()
+        .map_err(|_| nvmlError_t::NOT_SUPPORTED)
+}
+
+// BAR1 is the CPU-visible part of the VRAM, amdgpu reports it as "visible VRAM"
+pub(crate) fn bar1_memory_info(device_dir: &Path) -> Result<nvmlBAR1Memory_t, nvmlError_t> {
+    let total = read_u64(&device_dir.join("mem_info_vis_vram_total"))?;
+    let used = read_u64(&device_dir.join("mem_info_vis_vram_used"))?;
+    Ok(nvmlBAR1Memory_t {
+        bar1Total: total,
+        bar1Free: total.saturating_sub(used),
+        bar1Used: used,
+    })
+}
+
+// amdgpu exposes its sensors through a single hwmon device, e.g. device/hwmon/hwmon3
+pub(crate) fn hwmon_dir(device_dir: &Path) -> Result<PathBuf, nvmlError_t> {
+    fs::read_dir(device_dir.join("hwmon"))
+        .map_err(|_| nvmlError_t::NOT_SUPPORTED)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("hwmon"))
+        })
+        .ok_or(nvmlError_t::NOT_SUPPORTED)
+}
+
+/// Temperature in degrees Celsius of the sensor with the given label ("edge", "junction" or
+/// "mem"). Older kernels expose a single unlabeled sensor, which is the edge sensor
+pub(crate) fn hwmon_temperature(hwmon_dir: &Path, label: &str) -> Result<u32, nvmlError_t> {
+    let mut has_labels = false;
+    for sensor in 1.. {
+        let input = hwmon_dir.join(format!("temp{}_input", sensor));
+        if !input.exists() {
+            break;
+        }
+        if let Ok(sensor_label) =
+            fs::read_to_string(hwmon_dir.join(format!("temp{}_label", sensor)))
+        {
+            has_labels = true;
+            if sensor_label.trim() == label {
+                return Ok((read_u64(&input)? / 1000) as u32);
+            }
+        }
+    }
+    if !has_labels && label == "edge" {
+        return Ok((read_u64(&hwmon_dir.join("temp1_input"))? / 1000) as u32);
+    }
+    Err(nvmlError_t::NOT_SUPPORTED)
+}
+
+/// Power draw in milliwatts. Older kernels only report power1_average, newer ones report
+/// power1_input instead on GPUs whose firmware does not compute the average
+pub(crate) fn hwmon_power(hwmon_dir: &Path) -> Result<u32, nvmlError_t> {
+    let microwatts = read_u64(&hwmon_dir.join("power1_average"))
+        .or_else(|_| read_u64(&hwmon_dir.join("power1_input")))?;
+    Ok((microwatts / 1000) as u32)
+}
+
+// mem_busy_percent is missing on older kernels, we report idle memory then
+pub(crate) fn utilization(device_dir: &Path) -> Result<nvmlUtilization_t, nvmlError_t> {
+    Ok(nvmlUtilization_t {
+        gpu: read_u64(&device_dir.join("gpu_busy_percent"))? as u32,
+        memory: read_u64(&device_dir.join("mem_busy_percent")).unwrap_or(0) as u32,
+    })
+}
+
+pub(crate) fn vram_info(device_dir: &Path) -> Result<nvmlMemory_t, nvmlError_t> {
+    let total = read_u64(&device_dir.join("mem_info_vram_total"))?;
+    let used = read_u64(&device_dir.join("mem_info_vram_used"))?;
+    Ok(nvmlMemory_t {
+        total,
+        free: total.saturating_sub(used),
+        used,
+    })
+}
+
+// Board part number, only present on some boards
+pub(crate) fn product_number(device_dir: &Path) -> Result<String, nvmlError_t> {
+    let product_number = fs::read_to_string(device_dir.join("product_number"))
+        .map_err(|_| nvmlError_t::NOT_SUPPORTED)?;
+    let product_number = product_number.trim();
+    if product_number.is_empty() {
+        Err(nvmlError_t::NOT_SUPPORTED)
+    } else {
+        Ok(product_number.to_string())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PcieLink {
+    Current,
+    Max,
+}
+
+impl PcieLink {
+    fn prefix(self) -> &'static str {
+        match self {
+            PcieLink::Current => "current",
+            PcieLink::Max => "max",
+        }
+    }
+}
+
+// Link speed as the PCI core formats it: "16.0 GT/s PCIe" or "8 GT/s" on older kernels. Links
+// which are powered down or behind some laptop bridges report "Unknown" ("Unknown speed" on
+// older kernels)
+fn pcie_generation(speed: &str) -> Option<u32> {
+    let gigatransfers = speed.split_whitespace().next()?.parse::<f64>().ok()?;
+    Some(match (gigatransfers * 10.0).round() as u32 {
+        25 => 1,
+        50 => 2,
+        80 => 3,
+        160 => 4,
+        320 => 5,
+        640 => 6,
+        _ => return None,
+    })
+}
+
+pub(crate) fn pcie_link_generation(device_dir: &Path, link: PcieLink) -> Result<u32, nvmlError_t> {
+    let speed = fs::read_to_string(device_dir.join(format!("{}_link_speed", link.prefix())))
+        .map_err(|_| nvmlError_t::NOT_SUPPORTED)?;
+    pcie_generation(&speed).ok_or(nvmlError_t::NOT_SUPPORTED)
+}
+
+// Unknown width is reported either as "Unknown" or as 0
+pub(crate) fn pcie_link_width(device_dir: &Path, link: PcieLink) -> Result<u32, nvmlError_t> {
+    match read_u64(&device_dir.join(format!("{}_link_width", link.prefix())))? {
+        0 => Err(nvmlError_t::NOT_SUPPORTED),
+        width => Ok(width as u32),
+    }
+}
+
+/// PCIe throughput in KB/s. amdgpu counts the messages received and sent during one second
+/// (the read takes that long) together with the maximum payload size:
+/// "<received> <sent> <max payload size>". Newer GPUs don't have the counters
+pub(crate) fn pcie_throughput(
+    device_dir: &Path,
+    counter: nvmlPcieUtilCounter_t,
+) -> Result<u32, nvmlError_t> {
+    let counters =
+        fs::read_to_string(device_dir.join("pcie_bw")).map_err(|_| nvmlError_t::NOT_SUPPORTED)?;
+    let counters = counters
+        .split_whitespace()
+        .map(str::parse::<u64>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| nvmlError_t::NOT_SUPPORTED)?;
+    let (received, sent, max_payload_size) = match counters[..] {
+        [received, sent, max_payload_size] => (received, sent, max_payload_size),
+        _ => return Err(nvmlError_t::NOT_SUPPORTED),
+    };
+    // TX and RX are from the point of view of the GPU
+    let messages = match counter {
+        nvmlPcieUtilCounter_t::NVML_PCIE_UTIL_TX_BYTES => sent,
+        nvmlPcieUtilCounter_t::NVML_PCIE_UTIL_RX_BYTES => received,
+        _ => return Err(nvmlError_t::INVALID_ARGUMENT),
+    };
+    Ok(u32::try_from(messages.saturating_mul(max_payload_size) / 1024).unwrap_or(u32::MAX))
+}
+
+/// Width in bits of the memory bus. This is also where HIP takes hipDeviceProp_t::memoryBusWidth
+/// from. APUs have no VRAM bank and report 0
+pub(crate) fn memory_bus_width(mem_bank_properties: &Path) -> Result<u32, nvmlError_t> {
+    let properties =
+        fs::read_to_string(mem_bank_properties).map_err(|_| nvmlError_t::NOT_SUPPORTED)?;
+    let width = properties
+        .lines()
+        .find_map(|line| line.strip_prefix("width "))
+        .and_then(|width| width.trim().parse::<u32>().ok())
+        .ok_or(nvmlError_t::NOT_SUPPORTED)?;
+    match width {
+        0 => Err(nvmlError_t::NOT_SUPPORTED),
+        width => Ok(width),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct KfdProperties {
+    pub(crate) simd_count: u64,
+    pub(crate) simd_per_cu: u64,
+    /// major * 10000 + minor * 100 + stepping, e.g. 90010 for gfx90a
+    pub(crate) gfx_target_version: u64,
+}
+
+impl KfdProperties {
+    pub(crate) fn compute_units(&self) -> u64 {
+        self.simd_count / self.simd_per_cu
+    }
+
+    pub(crate) fn gfx_version(&self) -> (u64, u64, u64) {
+        (
+            self.gfx_target_version / 10000,
+            (self.gfx_target_version / 100) % 100,
+            self.gfx_target_version % 100,
+        )
+    }
+}
+
+pub(crate) fn kfd_properties(path: &Path) -> Result<KfdProperties, nvmlError_t> {
+    parse_kfd_properties(&fs::read_to_string(path).map_err(|_| nvmlError_t::NOT_SUPPORTED)?)
+}
+
+// Format: one "name value" pair per line
+fn parse_kfd_properties(content: &str) -> Result<KfdProperties, nvmlError_t> {
+    let mut simd_count = None;
+    let mut simd_per_cu = None;
+    let mut gfx_target_version = None;
+    for line in content.lines() {
+        let (key, value) = match line.split_once(' ') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let field = match key {
+            "simd_count" => &mut simd_count,
+            "simd_per_cu" => &mut simd_per_cu,
+            "gfx_target_version" => &mut gfx_target_version,
+            _ => continue,
+        };
+        *field = Some(
+            value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| nvmlError_t::NOT_SUPPORTED)?,
+        );
+    }
+    match (simd_count, simd_per_cu, gfx_target_version) {
+        (Some(simd_count), Some(simd_per_cu), Some(gfx_target_version)) if simd_per_cu != 0 => {
+            Ok(KfdProperties {
+                simd_count,
+                simd_per_cu,
+                gfx_target_version,
+            })
+        }
+        _ => Err(nvmlError_t::NOT_SUPPORTED),
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub(crate) struct RasErrorCount {
+    pub(crate) uncorrected: u64,
+    pub(crate) corrected: u64,
+}
+
+impl RasErrorCount {
+    // amdgpu counts errors since the driver was loaded, there is no persistent counter we could
+    // report as the aggregate one, so both counter types return the same value
+    pub(crate) fn get(
+        &self,
+        error_type: nvmlMemoryErrorType_t,
+        counter_type: nvmlEccCounterType_t,
+    ) -> Result<u64, nvmlError_t> {
+        Ok(match (error_type, counter_type) {
+            (
+                nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_CORRECTED,
+                nvmlEccCounterType_t::NVML_VOLATILE_ECC,
+            )
+            | (
+                nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_CORRECTED,
+                nvmlEccCounterType_t::NVML_AGGREGATE_ECC,
+            ) => self.corrected,
+            (
+                nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_UNCORRECTED,
+                nvmlEccCounterType_t::NVML_VOLATILE_ECC,
+            )
+            | (
+                nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_UNCORRECTED,
+                nvmlEccCounterType_t::NVML_AGGREGATE_ECC,
+            ) => self.uncorrected,
+            _ => return Err(nvmlError_t::INVALID_ARGUMENT),
+        })
+    }
+
+    fn add(&mut self, other: RasErrorCount) {
+        self.uncorrected += other.uncorrected;
+        self.corrected += other.corrected;
+    }
+}
+
+/// Error counters of a RAS-capable device. Consumer GPUs have no `ras` directory at all, in
+/// that case every counter is zero and ECC is reported as disabled
+#[derive(Default, Debug)]
+pub(crate) struct RasCounters {
+    pub(crate) enabled: bool,
+    // Counters keyed by the amdgpu block name, e.g. "umc" or "gfx"
+    pub(crate) blocks: Vec<(String, RasErrorCount)>,
+}
+
+impl RasCounters {
+    pub(crate) fn total(&self) -> RasErrorCount {
+        let mut total = RasErrorCount::default();
+        for (_, count) in self.blocks.iter() {
+            total.add(*count);
+        }
+        total
+    }
+
+    // NVML's deprecated detailed counters only know four locations. We map memory controller
+    // (UMC) errors to device memory and graphics block errors to the register file, errors
+    // from other blocks are only included in the totals
+    pub(crate) fn detailed(
+        &self,
+        error_type: nvmlMemoryErrorType_t,
+        counter_type: nvmlEccCounterType_t,
+    ) -> Result<nvmlEccErrorCounts_t, nvmlError_t> {
+        let mut result = nvmlEccErrorCounts_t {
+            l1Cache: 0,
+            l2Cache: 0,
+            deviceMemory: 0,
+            registerFile: 0,
+        };
+        for (block, count) in self.blocks.iter() {
+            let count = count.get(error_type, counter_type)?;
+            match &**block {
+                "umc" => result.deviceMemory += count,
+                "gfx" => result.registerFile += count,
+                _ => {}
+            }
+        }
+        // Reject invalid enum values even if the device has no counters
+        RasErrorCount::default().get(error_type, counter_type)?;
+        Ok(result)
+    }
+}
+
+pub(crate) fn ras_counters(device_dir: &Path) -> Result<RasCounters, nvmlError_t> {
+    let ras_dir = device_dir.join("ras");
+    let features = match fs::read_to_string(ras_dir.join("features")) {
+        Ok(features) => features,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(RasCounters::default()),
+        Err(_) => return Err(nvmlError_t::NOT_SUPPORTED),
+    };
+    let mut blocks = Vec::new();
+    for entry in fs::read_dir(&ras_dir).map_err(|_| nvmlError_t::NOT_SUPPORTED)? {
+        let entry = entry.map_err(|_| nvmlError_t::NOT_SUPPORTED)?;
+        let file_name = entry.file_name();
+        let block = match file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix("_err_count"))
+        {
+            Some(block) => block.to_string(),
+            None => continue,
+        };
+        let content = fs::read_to_string(entry.path()).map_err(|_| nvmlError_t::NOT_SUPPORTED)?;
+        blocks.push((block, parse_err_count(&content)?));
+    }
+    blocks.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(RasCounters {
+        enabled: parse_feature_mask(&features)? != 0,
+        blocks,
+    })
+}
+
+// Format: "feature mask: 0x3fff"
+fn parse_feature_mask(content: &str) -> Result<u64, nvmlError_t> {
+    content
+        .trim()
+        .strip_prefix("feature mask:")
+        .map(str::trim)
+        .and_then(|mask| mask.strip_prefix("0x"))
+        .and_then(|mask| u64::from_str_radix(mask, 16).ok())
+        .ok_or(nvmlError_t::NOT_SUPPORTED)
+}
+
+// Format: "ue: 0\nce: 0\n"
+fn parse_err_count(content: &str) -> Result<RasErrorCount, nvmlError_t> {
+    let mut uncorrected = None;
+    let mut corrected = None;
+    for line in content.lines() {
+        let (key, value) = line.split_once(':').ok_or(nvmlError_t::NOT_SUPPORTED)?;
+        let value = value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| nvmlError_t::NOT_SUPPORTED)?;
+        match key.trim() {
+            "ue" => uncorrected = Some(value),
+            "ce" => corrected = Some(value),
+            _ => {}
+        }
+    }
+    match (uncorrected, corrected) {
+        (Some(uncorrected), Some(corrected)) => Ok(RasErrorCount {
+            uncorrected,
+            corrected,
+        }),
+        _ => Err(nvmlError_t::NOT_SUPPORTED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/test/sysfs")
+            .join(name)
+            .join("device")
+    }
+
+    const ALL_COUNTERS: [(nvmlMemoryErrorType_t, nvmlEccCounterType_t); 4] = [
+        (
+            nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_CORRECTED,
+            nvmlEccCounterType_t::NVML_VOLATILE_ECC,
+        ),
+        (
+            nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_CORRECTED,
+            nvmlEccCounterType_t::NVML_AGGREGATE_ECC,
+        ),
+        (
+            nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_UNCORRECTED,
+            nvmlEccCounterType_t::NVML_VOLATILE_ECC,
+        ),
+        (
+            nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_UNCORRECTED,
+            nvmlEccCounterType_t::NVML_AGGREGATE_ECC,
+        ),
+    ];
+
+    #[test]
+    fn sensors_linux_5_4() {
+        let device_dir = fixture("linux_5_4");
+        let hwmon_dir = hwmon_dir(&device_dir).unwrap();
+        assert_eq!(hwmon_temperature(&hwmon_dir, "edge"), Ok(45));
+        assert_eq!(
+            hwmon_temperature(&hwmon_dir, "mem"),
+            Err(nvmlError_t::NOT_SUPPORTED)
+        );
+        assert_eq!(hwmon_power(&hwmon_dir), Ok(35123));
+        assert_eq!(
+            utilization(&device_dir),
+            Ok(nvmlUtilization_t { gpu: 37, memory: 0 })
+        );
+        assert_eq!(
+            vram_info(&device_dir),
+            Ok(nvmlMemory_t {
+                total: 8573157376,
+                free: 7524581376,
+                used: 1048576000,
+            })
+        );
+    }
+
+    #[test]
+    fn sensors_linux_6_8() {
+        let device_dir = fixture("linux_6_8");
+        let hwmon_dir = hwmon_dir(&device_dir).unwrap();
+        assert_eq!(hwmon_temperature(&hwmon_dir, "edge"), Ok(52));
+        assert_eq!(hwmon_temperature(&hwmon_dir, "junction"), Ok(61));
+        assert_eq!(hwmon_temperature(&hwmon_dir, "mem"), Ok(58));
+        assert_eq!(hwmon_power(&hwmon_dir), Ok(212000));
+        assert_eq!(
+            utilization(&device_dir),
+            Ok(nvmlUtilization_t {
+                gpu: 99,
+                memory: 12
+            })
+        );
+        assert_eq!(
+            vram_info(&device_dir),
+            Ok(nvmlMemory_t {
+                total: 25753026560,
+                free: 21458059264,
+                used: 4294967296,
+            })
+        );
+    }
+
+    #[test]
+    fn sensors_missing() {
+        assert_eq!(
+            hwmon_dir(&fixture("ras_less")),
+            Err(nvmlError_t::NOT_SUPPORTED)
+        );
+        assert_eq!(
+            utilization(&fixture("ras_less")),
+            Err(nvmlError_t::NOT_SUPPORTED)
+        );
+    }
+
+    #[test]
+    fn pcie_speed_strings() {
+        assert_eq!(pcie_generation("2.5 GT/s PCIe\n"), Some(1));
+        assert_eq!(pcie_generation("5.0 GT/s PCIe"), Some(2));
+        assert_eq!(pcie_generation("8 GT/s"), Some(3));
+        assert_eq!(pcie_generation("16.0 GT/s PCIe"), Some(4));
+        assert_eq!(pcie_generation("32.0 GT/s PCIe"), Some(5));
+        assert_eq!(pcie_generation("64.0 GT/s PCIe"), Some(6));
+        assert_eq!(pcie_generation("Unknown"), None);
+        assert_eq!(pcie_generation("Unknown speed\n"), None);
+        assert_eq!(pcie_generation("2.7 GT/s"), None);
+        assert_eq!(pcie_generation(""), None);
+    }
+
+    #[test]
+    fn pcie_link_linux_5_4() {
+        let device_dir = fixture("linux_5_4");
+        assert_eq!(pcie_link_generation(&device_dir, PcieLink::Current), Ok(3));
+        assert_eq!(pcie_link_generation(&device_dir, PcieLink::Max), Ok(4));
+        assert_eq!(pcie_link_width(&device_dir, PcieLink::Current), Ok(8));
+        assert_eq!(pcie_link_width(&device_dir, PcieLink::Max), Ok(16));
+        // 5000 messages of 256 bytes received, 12000 sent
+        assert_eq!(
+            pcie_throughput(&device_dir, nvmlPcieUtilCounter_t::NVML_PCIE_UTIL_RX_BYTES),
+            Ok(1250)
+        );
+        assert_eq!(
+            pcie_throughput(&device_dir, nvmlPcieUtilCounter_t::NVML_PCIE_UTIL_TX_BYTES),
+            Ok(3000)
+        );
+        assert_eq!(
+            pcie_throughput(&device_dir, nvmlPcieUtilCounter_t::NVML_PCIE_UTIL_COUNT),
+            Err(nvmlError_t::INVALID_ARGUMENT)
+        );
+    }
+
+    #[test]
+    fn pcie_link_unknown() {
+        // Laptop with the dGPU link powered down
+        let device_dir = fixture("linux_6_8");
+        assert_eq!(
+            pcie_link_generation(&device_dir, PcieLink::Current),
+            Err(nvmlError_t::NOT_SUPPORTED)
+        );
+        assert_eq!(pcie_link_generation(&device_dir, PcieLink::Max), Ok(4));
+        assert_eq!(
+            pcie_link_width(&device_dir, PcieLink::Current),
+            Err(nvmlError_t::NOT_SUPPORTED)
+        );
+        assert_eq!(pcie_link_width(&device_dir, PcieLink::Max), Ok(16));
+        assert_eq!(
+            pcie_throughput(&device_dir, nvmlPcieUtilCounter_t::NVML_PCIE_UTIL_RX_BYTES),
+            Err(nvmlError_t::NOT_SUPPORTED)
+        );
+        assert_eq!(
+            pcie_link_generation(&fixture("ras_less"), PcieLink::Current),
+            Err(nvmlError_t::NOT_SUPPORTED)
+        );
+    }
+
+    #[test]
+    fn memory_bus_width_gfx1100() {
+        let kfd = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/test/sysfs/kfd");
+        assert_eq!(
+            memory_bus_width(&kfd.join("gfx1100_mem_bank_properties")),
+            Ok(384)
+        );
+        assert_eq!(
+            memory_bus_width(&kfd.join("gfx1103_mem_bank_properties")),
+            Err(nvmlError_t::NOT_SUPPORTED)
+        );
+        assert_eq!(
+            memory_bus_width(&kfd.join("missing")),
+            Err(nvmlError_t::NOT_SUPPORTED)
+        );
+    }
+
+    #[test]
+    fn kfd_properties_gfx1100() {
+        let properties = kfd_properties(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src/test/sysfs/kfd/gfx1100_properties"),
+        )
+        .unwrap();
+        assert_eq!(properties.compute_units(), 96);
+        assert_eq!(properties.gfx_version(), (11, 0, 0));
+    }
+
+    #[test]
+    fn product_number() {
+        assert_eq!(
+            super::product_number(&fixture("ras_enabled")).unwrap(),
+            "113-D7090100-XTX"
+        );
+        assert_eq!(
+            super::product_number(&fixture("ras_less")).unwrap_err(),
+            nvmlError_t::NOT_SUPPORTED
+        );
+    }
+
+    #[test]
+    fn bar1_ras_enabled() {
+        let bar1 = bar1_memory_info(&fixture("ras_enabled")).unwrap();
+        assert_eq!(bar1.bar1Total, 34359738368);
+        assert_eq!(bar1.bar1Used, 1073741824);
+        assert_eq!(bar1.bar1Free, 34359738368 - 1073741824);
+    }
+
+    #[test]
+    fn bar1_malformed() {
+        assert_eq!(
+            bar1_memory_info(&fixture("ras_malformed")).unwrap_err(),
+            nvmlError_t::NOT_SUPPORTED
+        );
+    }
+
+    #[test]
+    fn ras_enabled_counters() {
+        let counters = ras_counters(&fixture("ras_enabled")).unwrap();
+        assert!(counters.enabled);
+        assert_eq!(
+            counters.total(),
+            RasErrorCount {
+                uncorrected: 3,
+                corrected: 10
+            }
+        );
+        let (error_type, counter_type) = ALL_COUNTERS[0];
+        let detailed = counters.detailed(error_type, counter_type).unwrap();
+        assert_eq!(detailed.deviceMemory, 7);
+        assert_eq!(detailed.registerFile, 3);
+        assert_eq!(detailed.l1Cache, 0);
+        assert_eq!(detailed.l2Cache, 0);
+        let (error_type, counter_type) = ALL_COUNTERS[2];
+        let detailed = counters.detailed(error_type, counter_type).unwrap();
+        assert_eq!(detailed.deviceMemory, 1);
+        assert_eq!(detailed.registerFile, 0);
+    }
+
+    #[test]
+    fn ras_less_counters_are_zero() {
+        let counters = ras_counters(&fixture("ras_less")).unwrap();
+        assert!(!counters.enabled);
+        for (error_type, counter_type) in ALL_COUNTERS {
+            assert_eq!(counters.total().get(error_type, counter_type), Ok(0));
+            let detailed = counters.detailed(error_type, counter_type).unwrap();
+            assert_eq!(detailed.deviceMemory, 0);
+            assert_eq!(detailed.registerFile, 0);
+        }
+    }
+
+    #[test]
+    fn ras_malformed_is_not_supported() {
+        assert_eq!(
+            ras_counters(&fixture("ras_malformed")).unwrap_err(),
+            nvmlError_t::NOT_SUPPORTED
+        );
+    }
+
+    #[test]
+    fn invalid_counter_type_is_rejected() {
+        let counters = ras_counters(&fixture("ras_less")).unwrap();
+        assert_eq!(
+            counters.total().get(
+                nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_COUNT,
+                nvmlEccCounterType_t::NVML_VOLATILE_ECC
+            ),
+            Err(nvmlError_t::INVALID_ARGUMENT)
+        );
+        assert_eq!(
+            counters
+                .detailed(
+                    nvmlMemoryErrorType_t::NVML_MEMORY_ERROR_TYPE_CORRECTED,
+                    nvmlEccCounterType_t::NVML_ECC_COUNTER_TYPE_COUNT
+                )
+                .unwrap_err(),
+            nvmlError_t::INVALID_ARGUMENT
+        );
+    }
+}
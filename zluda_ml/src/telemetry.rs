@@ -0,0 +1,386 @@
+use crate::sysfs;
+use crate::topology::LinkKind;
+use cuda_types::nvml::*;
+use rocm_smi_sys::*;
+use std::{
+    ffi::CStr,
+    mem,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TemperatureSensor {
+    Gpu,
+    Memory,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct PciInfo {
+    pub(crate) domain: u32,
+    pub(crate) bus: u32,
+    pub(crate) device: u32,
+    pub(crate) function: u32,
+    pub(crate) vendor_id: u16,
+    pub(crate) device_id: u16,
+    pub(crate) subsystem_vendor_id: u16,
+    pub(crate) subsystem_id: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ProcessMemory {
+    pub(crate) pid: u32,
+    pub(crate) used_memory: u64,
+}
+
+/// How a device is connected to one of its peers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PeerLink {
+    pub(crate) kind: LinkKind,
+    pub(crate) hops: u64,
+    pub(crate) p2p_accessible: bool,
+}
+
+/// Source of the live device state (sensors, memory usage, processes) behind the NVML entry
+/// points. Every device carries its own instance, so the entry points can be tested against
+/// `MockTelemetry` on machines without an AMD GPU.
+/// Values use NVML units: degrees Celsius, milliwatts, megahertz and bytes
+pub(crate) trait GpuTelemetry: Send + Sync {
+    /// Marketing name, without the ZLUDA suffix
+    fn name(&self) -> Result<String, nvmlError_t>;
+    fn temperature(&self, sensor: TemperatureSensor) -> Result<u32, nvmlError_t>;
+    fn power_usage(&self) -> Result<u32, nvmlError_t>;
+    fn clock(&self, clock_type: nvmlClockType_t) -> Result<u32, nvmlError_t>;
+    fn utilization(&self) -> Result<nvmlUtilization_t, nvmlError_t>;
+    fn vram(&self) -> Result<nvmlMemory_t, nvmlError_t>;
+    fn pci_info(&self) -> Result<PciInfo, nvmlError_t>;
+    fn process_memory(&self) -> Result<Vec<ProcessMemory>, nvmlError_t>;
+    fn vbios_version(&self) -> Result<String, nvmlError_t>;
+    fn performance_state(&self) -> Result<nvmlPstates_t, nvmlError_t>;
+    fn pcie_replay_counter(&self) -> Result<u64, nvmlError_t>;
+    /// amdgpu sysfs directory of the device, the PCIe, BAR1, ECC and board queries parse it
+    fn device_dir(&self) -> Result<&Path, nvmlError_t>;
+    /// Node of the device in the KFD topology (/sys/class/kfd/kfd/topology/nodes)
+    fn kfd_node_id(&self) -> Result<u32, nvmlError_t>;
+    fn numa_node(&self) -> Result<u32, nvmlError_t>;
+    /// `peer` is the ROCm SMI index of the other device
+    fn peer_link(&self, peer: u32) -> Result<PeerLink, nvmlError_t>;
+}
+
+/// Sensors are read from the amdgpu sysfs directory, everything else comes from ROCm SMI.
+/// If we fail to locate the sysfs directory we don't fail device enumeration, the queries which
+/// need it return ERROR_NOT_SUPPORTED
+pub(crate) struct RocmSmiTelemetry {
+    // ROCm SMI device index
+    index: u32,
+    device_dir: Option<PathBuf>,
+}
+
+impl RocmSmiTelemetry {
+    pub(crate) unsafe fn new(index: u32) -> Self {
+        let mut render_minor = 0;
+        let device_dir = rsmi_dev_drm_render_minor_get(index, &mut render_minor)
+            .ok()
+            .map(|_| sysfs::device_dir(render_minor));
+        RocmSmiTelemetry { index, device_dir }
+    }
+
+    unsafe fn is_used_by(&self, pid: u32) -> bool {
+        let mut device_count = 0;
+        if rsmi_compute_process_gpus_get(pid, ptr::null_mut(), &mut device_count).is_err() {
+            return false;
+        }
+        let mut devices = vec![0u32; device_count as usize];
+        if rsmi_compute_process_gpus_get(pid, devices.as_mut_ptr(), &mut device_count).is_err() {
+            return false;
+        }
+        devices.truncate(device_count as usize);
+        devices.contains(&self.index)
+    }
+}
+
+impl GpuTelemetry for RocmSmiTelemetry {
+    fn name(&self) -> Result<String, nvmlError_t> {
+        let mut buffer = [0 as ::core::ffi::c_char; NVML_DEVICE_NAME_V2_BUFFER_SIZE as usize];
+        unsafe {
+            rsmi_dev_name_get(self.index, buffer.as_mut_ptr(), buffer.len())?;
+            Ok(CStr::from_ptr(buffer.as_ptr())
+                .to_string_lossy()
+                .into_owned())
+        }
+    }
+
+    fn temperature(&self, sensor: TemperatureSensor) -> Result<u32, nvmlError_t> {
+        let label = match sensor {
+            TemperatureSensor::Gpu => "edge",
+            TemperatureSensor::Memory => "mem",
+        };
+        sysfs::hwmon_temperature(&sysfs::hwmon_dir(self.device_dir()?)?, label)
+    }
+
+    fn power_usage(&self) -> Result<u32, nvmlError_t> {
+        sysfs::hwmon_power(&sysfs::hwmon_dir(self.device_dir()?)?)
+    }
+
+    // SM and graphics clocks are the same clock on AMD GPUs, there is no video clock
+    fn clock(&self, clock_type: nvmlClockType_t) -> Result<u32, nvmlError_t> {
+        let clock_type = match clock_type {
+            nvmlClockType_t::NVML_CLOCK_GRAPHICS | nvmlClockType_t::NVML_CLOCK_SM => {
+                rsmi_clk_type_t::RSMI_CLK_TYPE_SYS
+            }
+            nvmlClockType_t::NVML_CLOCK_MEM => rsmi_clk_type_t::RSMI_CLK_TYPE_MEM,
+            nvmlClockType_t::NVML_CLOCK_VIDEO => return Err(nvmlError_t::NOT_SUPPORTED),
+            _ => return Err(nvmlError_t::INVALID_ARGUMENT),
+        };
+        let mut frequencies = unsafe { mem::zeroed::<rsmi_frequencies_t>() };
+        unsafe { rsmi_dev_gpu_clk_freq_get(self.index, clock_type, &mut frequencies) }?;
+        let hertz = frequencies
+            .frequency
+            .get(frequencies.current as usize)
+            .ok_or(nvmlError_t::UNKNOWN)?;
+        Ok((hertz / 1_000_000) as u32)
+    }
+
+    fn utilization(&self) -> Result<nvmlUtilization_t, nvmlError_t> {
+        sysfs::utilization(self.device_dir()?)
+    }
+
+    fn vram(&self) -> Result<nvmlMemory_t, nvmlError_t> {
+        sysfs::vram_info(self.device_dir()?)
+    }
+
+    fn pci_info(&self) -> Result<PciInfo, nvmlError_t> {
+        let mut info = PciInfo::default();
+        let mut bdf_id = 0;
+        unsafe {
+            rsmi_dev_pci_id_get(self.index, &mut bdf_id)?;
+            rsmi_dev_vendor_id_get(self.index, &mut info.vendor_id)?;
+            rsmi_dev_id_get(self.index, &mut info.device_id)?;
+            rsmi_dev_subsystem_vendor_id_get(self.index, &mut info.subsystem_vendor_id)?;
+            rsmi_dev_subsystem_id_get(self.index, &mut info.subsystem_id)?;
+        }
+        // BDFID = (DOMAIN << 32) | (BUS << 8) | (DEVICE << 3) | FUNCTION
+        info.domain = ((bdf_id >> 32) & 0xffffffff) as u32;
+        info.bus = ((bdf_id >> 8) & 0xff) as u32;
+        info.device = ((bdf_id >> 3) & 0x1f) as u32;
+        info.function = (bdf_id & 0x7) as u32;
+        Ok(info)
+    }
+
+    // ROCm SMI only knows the total VRAM usage of a process across all devices
+    fn process_memory(&self) -> Result<Vec<ProcessMemory>, nvmlError_t> {
+        unsafe {
+            let mut process_count = 0;
+            rsmi_compute_process_info_get(ptr::null_mut(), &mut process_count)?;
+            let mut processes = vec![mem::zeroed::<rsmi_process_info_t>(); process_count as usize];
+            rsmi_compute_process_info_get(processes.as_mut_ptr(), &mut process_count)?;
+            processes.truncate(process_count as usize);
+            Ok(processes
+                .into_iter()
+                .filter(|process| self.is_used_by(process.process_id))
+                .filter_map(|process| {
+                    let mut info = mem::zeroed();
+                    rsmi_compute_process_info_by_pid_get(process.process_id, &mut info).ok()?;
+                    Some(ProcessMemory {
+                        pid: process.process_id,
+                        used_memory: info.vram_usage,
+                    })
+                })
+                .collect())
+        }
+    }
+
+    fn vbios_version(&self) -> Result<String, nvmlError_t> {
+        let mut buffer = [0 as ::core::ffi::c_char; NVML_DEVICE_VBIOS_VERSION_BUFFER_SIZE as usize];
+        unsafe {
+            rsmi_dev_vbios_version_get(self.index, buffer.as_mut_ptr(), buffer.len() as u32)?;
+            Ok(CStr::from_ptr(buffer.as_ptr())
+                .to_string_lossy()
+                .into_owned())
+        }
+    }
+
+    fn performance_state(&self) -> Result<nvmlPstates_t, nvmlError_t> {
+        let mut perf_level = unsafe { mem::zeroed() };
+        unsafe { rsmi_dev_perf_level_get(self.index, &mut perf_level) }?;
+        let busy_percent = if perf_level == rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_AUTO {
+            let mut busy_percent = 0;
+            unsafe { rsmi_dev_busy_percent_get(self.index, &mut busy_percent) }?;
+            busy_percent
+        } else {
+            0
+        };
+        Ok(perf_level_to_pstate(perf_level, busy_percent))
+    }
+
+    fn pcie_replay_counter(&self) -> Result<u64, nvmlError_t> {
+        let mut counter = 0;
+        unsafe { rsmi_dev_pci_replay_counter_get(self.index, &mut counter) }?;
+        Ok(counter)
+    }
+
+    fn device_dir(&self) -> Result<&Path, nvmlError_t> {
+        self.device_dir.as_deref().ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn kfd_node_id(&self) -> Result<u32, nvmlError_t> {
+        let mut node_id = 0;
+        unsafe { rsmi_dev_node_id_get(self.index, &mut node_id) }?;
+        Ok(node_id)
+    }
+
+    fn numa_node(&self) -> Result<u32, nvmlError_t> {
+        let mut numa_node = 0;
+        unsafe { rsmi_topo_get_numa_node_number(self.index, &mut numa_node) }?;
+        Ok(numa_node)
+    }
+
+    fn peer_link(&self, peer: u32) -> Result<PeerLink, nvmlError_t> {
+        let mut hops = 0;
+        let mut link_type = unsafe { mem::zeroed() };
+        unsafe { rsmi_topo_get_link_type(self.index, peer, &mut hops, &mut link_type) }?;
+        let kind = match link_type {
+            RSMI_IO_LINK_TYPE::RSMI_IOLINK_TYPE_PCIEXPRESS => LinkKind::Pcie,
+            RSMI_IO_LINK_TYPE::RSMI_IOLINK_TYPE_XGMI => LinkKind::Xgmi,
+            _ => return Err(nvmlError_t::UNKNOWN),
+        };
+        let mut p2p_accessible = false;
+        unsafe { rsmi_is_P2P_accessible(self.index, peer, &mut p2p_accessible) }?;
+        Ok(PeerLink {
+            kind,
+            hops,
+            p2p_accessible,
+        })
+    }
+}
+
+// amdgpu has no P-states, we derive one from the performance level:
+// * "high", "stable peak" and "determinism" pin the clocks high -> P0
+// * "auto" follows the load: P0 when mostly busy, P2 when partially busy, P8 when idle
+// * "manual" and "stable std" run fixed, intermediate clocks -> P2
+// * "low" and "stable min" pin the clocks low -> P8
+pub(crate) fn perf_level_to_pstate(
+    perf_level: rsmi_dev_perf_level_t,
+    busy_percent: u32,
+) -> nvmlPstates_t {
+    match perf_level {
+        rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_HIGH
+        | rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_STABLE_PEAK
+        | rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_DETERMINISM => nvmlPstates_t::NVML_PSTATE_0,
+        rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_AUTO => match busy_percent {
+            0 => nvmlPstates_t::NVML_PSTATE_8,
+            1..50 => nvmlPstates_t::NVML_PSTATE_2,
+            _ => nvmlPstates_t::NVML_PSTATE_0,
+        },
+        rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_MANUAL
+        | rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_STABLE_STD => nvmlPstates_t::NVML_PSTATE_2,
+        rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_LOW
+        | rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_STABLE_MIN_MCLK
+        | rsmi_dev_perf_level_t::RSMI_DEV_PERF_LEVEL_STABLE_MIN_SCLK => {
+            nvmlPstates_t::NVML_PSTATE_8
+        }
+        _ => nvmlPstates_t::NVML_PSTATE_UNKNOWN,
+    }
+}
+
+/// Every value left as `None` is reported as ERROR_NOT_SUPPORTED
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MockTelemetry {
+    pub(crate) name: Option<String>,
+    pub(crate) gpu_temperature: Option<u32>,
+    pub(crate) memory_temperature: Option<u32>,
+    pub(crate) power_usage: Option<u32>,
+    pub(crate) graphics_clock: Option<u32>,
+    pub(crate) memory_clock: Option<u32>,
+    pub(crate) utilization: Option<nvmlUtilization_t>,
+    pub(crate) vram: Option<nvmlMemory_t>,
+    pub(crate) pci_info: Option<PciInfo>,
+    pub(crate) processes: Option<Vec<ProcessMemory>>,
+    pub(crate) vbios_version: Option<String>,
+    pub(crate) performance_state: Option<nvmlPstates_t>,
+    pub(crate) pcie_replay_counter: Option<u64>,
+    pub(crate) device_dir: Option<PathBuf>,
+    pub(crate) kfd_node_id: Option<u32>,
+    pub(crate) numa_node: Option<u32>,
+    // Keyed by the ROCm SMI index of the peer
+    pub(crate) peer_links: Vec<(u32, PeerLink)>,
+}
+
+#[cfg(test)]
+impl GpuTelemetry for MockTelemetry {
+    fn name(&self) -> Result<String, nvmlError_t> {
+        self.name.clone().ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn temperature(&self, sensor: TemperatureSensor) -> Result<u32, nvmlError_t> {
+        match sensor {
+            TemperatureSensor::Gpu => self.gpu_temperature,
+            TemperatureSensor::Memory => self.memory_temperature,
+        }
+        .ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn power_usage(&self) -> Result<u32, nvmlError_t> {
+        self.power_usage.ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn clock(&self, clock_type: nvmlClockType_t) -> Result<u32, nvmlError_t> {
+        match clock_type {
+            nvmlClockType_t::NVML_CLOCK_GRAPHICS | nvmlClockType_t::NVML_CLOCK_SM => {
+                self.graphics_clock
+            }
+            nvmlClockType_t::NVML_CLOCK_MEM => self.memory_clock,
+            _ => None,
+        }
+        .ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn utilization(&self) -> Result<nvmlUtilization_t, nvmlError_t> {
+        self.utilization.ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn vram(&self) -> Result<nvmlMemory_t, nvmlError_t> {
+        self.vram.ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn pci_info(&self) -> Result<PciInfo, nvmlError_t> {
+        self.pci_info.ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn process_memory(&self) -> Result<Vec<ProcessMemory>, nvmlError_t> {
+        self.processes.clone().ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn vbios_version(&self) -> Result<String, nvmlError_t> {
+        self.vbios_version.clone().ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn performance_state(&self) -> Result<nvmlPstates_t, nvmlError_t> {
+        self.performance_state.ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn pcie_replay_counter(&self) -> Result<u64, nvmlError_t> {
+        self.pcie_replay_counter.ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn device_dir(&self) -> Result<&Path, nvmlError_t> {
+        self.device_dir.as_deref().ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn kfd_node_id(&self) -> Result<u32, nvmlError_t> {
+        self.kfd_node_id.ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn numa_node(&self) -> Result<u32, nvmlError_t> {
+        self.numa_node.ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+
+    fn peer_link(&self, peer: u32) -> Result<PeerLink, nvmlError_t> {
+        self.peer_links
+            .iter()
+            .find(|(index, _)| *index == peer)
+            .map(|(_, link)| *link)
+            .ok_or(nvmlError_t::NOT_SUPPORTED)
+    }
+}
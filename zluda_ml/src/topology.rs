@@ -0,0 +1,191 @@
+use cuda_types::nvml::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LinkKind {
+    Pcie,
+    Xgmi,
+}
+
+/// Connection between two distinct GPUs, as reported by ROCm SMI
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Link {
+    pub(crate) kind: LinkKind,
+    pub(crate) hops: u64,
+    pub(crate) same_numa_node: bool,
+    pub(crate) p2p_accessible: bool,
+}
+
+// XGMI is AMD's NVLink equivalent, we report it as the closest NVML level. For PCIe we only know
+// the number of hops and the NUMA node of each device:
+// * 1 hop  -> both devices behind the same PCIe switch: SINGLE
+// * 2 hops -> behind a hierarchy of switches: MULTIPLE
+// * 3 hops -> behind the same host bridge: HOSTBRIDGE
+// * more hops, but the same NUMA node: NODE
+// * different NUMA nodes: SYSTEM
+pub(crate) fn common_ancestor(link: Option<Link>) -> nvmlGpuTopologyLevel_t {
+    let link = match link {
+        Some(link) => link,
+        None => return nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_INTERNAL,
+    };
+    match link.kind {
+        LinkKind::Xgmi => nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_SINGLE,
+        LinkKind::Pcie => match link.hops {
+            0 | 1 => nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_SINGLE,
+            2 => nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_MULTIPLE,
+            3 => nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_HOSTBRIDGE,
+            _ if link.same_numa_node => nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_NODE,
+            _ => nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_SYSTEM,
+        },
+    }
+}
+
+// Reads and writes work over any peer-accessible link, NVLink capability (and atomics, which
+// PCIe peer access does not guarantee) is reported only for XGMI
+pub(crate) fn p2p_status(
+    link: Option<Link>,
+    index: nvmlGpuP2PCapsIndex_t,
+) -> Result<nvmlGpuP2PStatus_t, nvmlError_t> {
+    let link = match link {
+        Some(link) => link,
+        None => return Ok(nvmlGpuP2PStatus_t::NVML_P2P_STATUS_OK),
+    };
+    Ok(match index {
+        nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_READ
+        | nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_WRITE => {
+            if link.p2p_accessible {
+                nvmlGpuP2PStatus_t::NVML_P2P_STATUS_OK
+            } else {
+                nvmlGpuP2PStatus_t::NVML_P2P_STATUS_CHIPSET_NOT_SUPPORTED
+            }
+        }
+        nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_NVLINK
+        | nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_ATOMICS => {
+            if link.kind == LinkKind::Xgmi && link.p2p_accessible {
+                nvmlGpuP2PStatus_t::NVML_P2P_STATUS_OK
+            } else {
+                nvmlGpuP2PStatus_t::NVML_P2P_STATUS_NOT_SUPPORTED
+            }
+        }
+        nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_PROP => {
+            nvmlGpuP2PStatus_t::NVML_P2P_STATUS_NOT_SUPPORTED
+        }
+        _ => return Err(nvmlError_t::INVALID_ARGUMENT),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // GPUs 0 and 1 are linked with XGMI, GPUs 2 and 3 are plain PCIe cards on the other socket
+    fn mock_topology(a: usize, b: usize) -> Option<Link> {
+        if a == b {
+            return None;
+        }
+        let xgmi = |i| i < 2;
+        let numa = |i| i / 2;
+        Some(if xgmi(a) && xgmi(b) {
+            Link {
+                kind: LinkKind::Xgmi,
+                hops: 1,
+                same_numa_node: true,
+                p2p_accessible: true,
+            }
+        } else if numa(a) == numa(b) {
+            Link {
+                kind: LinkKind::Pcie,
+                hops: 2,
+                same_numa_node: true,
+                p2p_accessible: true,
+            }
+        } else {
+            Link {
+                kind: LinkKind::Pcie,
+                hops: 5,
+                same_numa_node: false,
+                p2p_accessible: false,
+            }
+        })
+    }
+
+    #[test]
+    fn same_device_is_internal() {
+        for i in 0..4 {
+            assert_eq!(
+                common_ancestor(mock_topology(i, i)),
+                nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_INTERNAL
+            );
+            for index in 0..5 {
+                assert_eq!(
+                    p2p_status(mock_topology(i, i), nvmlGpuP2PCapsIndex_t(index)),
+                    Ok(nvmlGpuP2PStatus_t::NVML_P2P_STATUS_OK)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn xgmi_pair() {
+        let link = mock_topology(0, 1);
+        assert_eq!(
+            common_ancestor(link),
+            nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_SINGLE
+        );
+        for index in [
+            nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_READ,
+            nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_WRITE,
+            nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_NVLINK,
+            nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_ATOMICS,
+        ] {
+            assert_eq!(
+                p2p_status(link, index),
+                Ok(nvmlGpuP2PStatus_t::NVML_P2P_STATUS_OK)
+            );
+        }
+    }
+
+    #[test]
+    fn pcie_pair() {
+        let link = mock_topology(2, 3);
+        assert_eq!(
+            common_ancestor(link),
+            nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_MULTIPLE
+        );
+        assert_eq!(
+            p2p_status(link, nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_READ),
+            Ok(nvmlGpuP2PStatus_t::NVML_P2P_STATUS_OK)
+        );
+        assert_eq!(
+            p2p_status(link, nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_WRITE),
+            Ok(nvmlGpuP2PStatus_t::NVML_P2P_STATUS_OK)
+        );
+        assert_eq!(
+            p2p_status(link, nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_NVLINK),
+            Ok(nvmlGpuP2PStatus_t::NVML_P2P_STATUS_NOT_SUPPORTED)
+        );
+    }
+
+    #[test]
+    fn cross_socket_pair() {
+        let link = mock_topology(1, 3);
+        assert_eq!(
+            common_ancestor(link),
+            nvmlGpuTopologyLevel_t::NVML_TOPOLOGY_SYSTEM
+        );
+        assert_eq!(
+            p2p_status(link, nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_READ),
+            Ok(nvmlGpuP2PStatus_t::NVML_P2P_STATUS_CHIPSET_NOT_SUPPORTED)
+        );
+    }
+
+    #[test]
+    fn unknown_caps_index_is_rejected() {
+        assert_eq!(
+            p2p_status(
+                mock_topology(0, 1),
+                nvmlGpuP2PCapsIndex_t::NVML_P2P_CAPS_INDEX_UNKNOWN
+            ),
+            Err(nvmlError_t::INVALID_ARGUMENT)
+        );
+    }
+}
@@ -192,6 +192,7 @@ impl StateTracker {
             fn_logger.log_io_error(self.writer.save_module_error_log(
                 module_index,
                 submodule_index,
+                module_text,
                 &*errors,
             ));
         }
@@ -229,6 +230,7 @@ impl DumpWriter {
         &self,
         module_index: usize,
         submodule_index: Option<usize>,
+        module_text: &'input str,
         errors: &[ptx_parser::PtxError<'input>],
     ) -> io::Result<()> {
         let mut log_file = match &self.dump_dir {
@@ -238,7 +240,10 @@ impl DumpWriter {
         log_file.push(Self::get_file_name(module_index, submodule_index, "log"));
         let mut file = File::create(log_file)?;
         for error in errors {
-            writeln!(file, "{}", error)?;
+            match error.location(module_text) {
+                Some(location) => writeln!(file, "{}", location.annotate(error))?,
+                None => writeln!(file, "{}", error)?,
+            }
         }
         Ok(())
     }